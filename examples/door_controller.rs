@@ -0,0 +1,60 @@
+//! Application logic written against `FingerprintReader` rather than `R502`, so that it can be
+//! tested against `FakeReader` without a module. On a device, the same `Door` is given the
+//! driver itself, for example `Door::new(R502::from_serialport(port, 0xffffffff))`.
+//!
+//! Run with `cargo run --features mock --example door_controller`.
+use hzgrow_r502::{FakeReader, FingerprintReader, IdentifyConfig, IdentifyError};
+
+/// How many unknown fingers in a row lock the door until an administrator resets it.
+const MAX_FAILURES: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DoorState {
+    Locked { failures: u8 },
+    Open { user: u16 },
+    LockedOut,
+}
+
+struct Door<R> {
+    reader: R,
+    state: DoorState,
+}
+
+impl<R> Door<R>
+where
+    R: FingerprintReader,
+{
+    fn new(reader: R) -> Self {
+        return Self { reader, state: DoorState::Locked { failures: 0 } };
+    }
+
+    /// Looks at the sensor once, and moves the door on accordingly.
+    fn poll(&mut self) -> DoorState {
+        let failures = match self.state {
+            DoorState::Locked { failures } => failures,
+            state => return state,
+        };
+        self.state = match self.reader.identify(&IdentifyConfig::default()) {
+            Ok((user, _)) => DoorState::Open { user },
+            Err(IdentifyError::NoFinger) => DoorState::Locked { failures },
+            Err(error) if error.is_user_recoverable() && failures + 1 < MAX_FAILURES => {
+                DoorState::Locked { failures: failures + 1 }
+            }
+            Err(_) => DoorState::LockedOut,
+        };
+        return self.state;
+    }
+}
+
+fn main() {
+    let mut reader = FakeReader::new();
+    reader.identifications.push_back(Err(IdentifyError::NoMatch));
+    reader.identifications.push_back(Ok((4, 150)));
+
+    let mut door = Door::new(reader);
+    for _ in 0..3 {
+        println!("{:?}", door.poll());
+    }
+    assert_eq!(door.state, DoorState::Open { user: 4 });
+    println!("Calls to the reader: {:?}", door.reader.calls);
+}
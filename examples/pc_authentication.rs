@@ -1,9 +1,6 @@
 use hzgrow_r502::{Command, R502};
 use serialport::{available_ports, open};
-use std::{cell::RefCell, env, time::Duration};
-
-mod pc_utils;
-use pc_utils::{SerialReader, SerialWriter};
+use std::{env, time::Duration};
 
 const DEFAULT_BAUD_RATE: u32 = 57600;
 
@@ -29,11 +26,7 @@ fn run_test(port_name: &str) {
     port.set_baud_rate(DEFAULT_BAUD_RATE).unwrap();
     port.set_timeout(Duration::from_secs(5)).unwrap();
 
-    let port_cell = RefCell::new(port);
-
-    let reader = SerialReader(&port_cell);
-    let writer = SerialWriter(&port_cell);
-    let mut r502 = R502::new(writer, reader, 0xffffffff);
+    let mut r502 = R502::from_serialport(port, 0xffffffff);
 
     println!("1. Checking status");
 
@@ -1,14 +1,10 @@
-use hzgrow_r502::{Command, Reply, R502};
+use hzgrow_r502::{CombinedSerial, Command, Reply, SerialPortAdapter, R502};
 use serialport::{available_ports, open, SerialPort};
 use std::{
-    cell::RefCell,
     env,
     time::Duration,
 };
 
-mod pc_utils;
-use pc_utils::{SerialReader, SerialWriter};
-
 const DEFAULT_BAUD_RATE: u32 = 57600;
 
 fn main() {
@@ -29,11 +25,7 @@ fn print_ports() {
 
 fn delete_id(port_name: &str, index: u16) {
     let port = get_configured_serial_port(port_name).unwrap();
-    let port_cell = RefCell::new(port);
-
-    let reader = SerialReader(&port_cell);
-    let writer = SerialWriter(&port_cell);
-    let mut r502 = R502::new(writer, reader, 0xffffffff);
+    let mut r502 = R502::from_serialport(port, 0xffffffff);
 
     verify_pwd(&mut r502, 0x00000000).unwrap();
 
@@ -53,10 +45,13 @@ fn get_configured_serial_port(port_name: &str) -> serialport::Result<Box<dyn Ser
     });
 }
 
-fn verify_pwd(r502: &mut R502<SerialWriter, SerialReader>, password: u32) -> Result<(), String> {
+fn verify_pwd(
+    r502: &mut R502<CombinedSerial<SerialPortAdapter>>,
+    password: u32,
+) -> Result<(), String> {
     println!("1. Verifying password");
 
-    let cmd = Command::VfyPwd { password: password };
+    let cmd = Command::VfyPwd { password };
     println!("Command: {:#?}", cmd);
     match r502.send_command(cmd) {
         Ok(Reply::VfyPwd(result)) => println!("Reply: {:#?}", result.confirmation_code),
@@ -1,14 +1,13 @@
-use hzgrow_r502::{Command, GenImgStatus, Reply, R502};
+use hzgrow_r502::{CombinedSerial, Command, GenImgStatus, Reply, SerialPortAdapter, R502};
 use serialport::{available_ports, open, SerialPort};
 use std::{
-    cell::RefCell,
     env,
     io::{Read, Write},
     time::Duration,
 };
 
 mod pc_utils;
-use pc_utils::{SerialReader, SerialWriter};
+use pc_utils::StdDelay;
 
 const DEFAULT_BAUD_RATE: u32 = 57600;
 
@@ -16,7 +15,7 @@ fn main() {
     let args: Vec<String> = env::args().collect();
     match args.len() {
         1 => print_ports(),
-        2 => print_next_template_number(args[1].as_str()),
+        2 => print_next_free_slot(args[1].as_str()),
         3 => enroll_to_id(args[1].as_str(), args[2].parse::<u16>().unwrap()),
         _ => panic!("Usage: pc_enrollment [port_name] [num_char]"),
     };
@@ -29,37 +28,32 @@ fn print_ports() {
     }
 }
 
-fn print_next_template_number(port_name: &str) {
+fn print_next_free_slot(port_name: &str) {
     let port = get_configured_serial_port(port_name).unwrap();
-    let port_cell = RefCell::new(port);
-
-    let reader = SerialReader(&port_cell);
-    let writer = SerialWriter(&port_cell);
-    let mut r502 = R502::new(writer, reader, 0xffffffff);
+    let mut r502 = R502::from_serialport(port, 0xffffffff);
 
     verify_pwd(&mut r502, 0x00000000).unwrap();
 
-    println!("3. Checking next valid template id");
+    println!("3. Counting enrolled templates");
+    match r502.template_count() {
+        Ok(count) => println!("Templates in the library: {}", count),
+        Err(e) => panic!("Error: {:#?}", e),
+    };
 
-    println!("Command: {:#?}", Command::TemplateNum);
-    match r502.send_command(Command::TemplateNum) {
-        Ok(Reply::TemplateNum(result)) => println!(
-            "[{:#?}] Next valid template number: {}",
-            result.confirmation_code, result.template_num,
-        ),
+    // The template count is not an index: after a deletion it points at an occupied slot.
+    // The index table tells us where the gaps are.
+    println!("4. Checking next free slot");
+    match r502.next_free_slot() {
+        Ok(Some(index)) => println!("Next free slot: {}", index),
+        Ok(None) => println!("The library is full"),
         Err(e) => panic!("Error: {:#?}", e),
-        msg => panic!("Unexpected msg: {:#?}", msg),
     };
 }
 
 fn enroll_to_id(port_name: &str, index: u16) {
     println!("Will enroll a new fingerprint to index {}", index);
     let port = get_configured_serial_port(port_name).unwrap();
-    let port_cell = RefCell::new(port);
-
-    let reader = SerialReader(&port_cell);
-    let writer = SerialWriter(&port_cell);
-    let mut r502 = R502::new(writer, reader, 0xffffffff);
+    let mut r502 = R502::from_serialport(port, 0xffffffff);
 
     verify_pwd(&mut r502, 0x00000000).unwrap();
 
@@ -72,7 +66,7 @@ fn enroll_to_id(port_name: &str, index: u16) {
     print!("Now lift your finger and press any key...");
     std::io::stdout().flush().unwrap();
     let mut buf = [0u8];
-    std::io::stdin().read(&mut buf).unwrap();
+    std::io::stdin().read_exact(&mut buf).unwrap();
     println!();
 
     println!("[2/2] Place finger on reader");
@@ -89,7 +83,7 @@ fn enroll_to_id(port_name: &str, index: u16) {
     };
 
     println!("Saving the template");
-    match r502.send_command(Command::Store { index: index, buffer: 1 }) {
+    match r502.send_command(Command::Store { index, buffer: 1 }) {
         Ok(Reply::Store(result)) => println!("Reply: {:#?}", result),
         Err(e) => panic!("Error: {:#?}", e),
         msg => panic!("Unexpected msg: {:#?}", msg),
@@ -105,10 +99,13 @@ fn get_configured_serial_port(port_name: &str) -> serialport::Result<Box<dyn Ser
     });
 }
 
-fn verify_pwd(r502: &mut R502<SerialWriter, SerialReader>, password: u32) -> Result<(), String> {
+fn verify_pwd(
+    r502: &mut R502<CombinedSerial<SerialPortAdapter>>,
+    password: u32,
+) -> Result<(), String> {
     println!("1. Verifying password");
 
-    let cmd = Command::VfyPwd { password: password };
+    let cmd = Command::VfyPwd { password };
     println!("Command: {:#?}", cmd);
     match r502.send_command(cmd) {
         Ok(Reply::VfyPwd(result)) => println!("Reply: {:#?}", result.confirmation_code),
@@ -132,27 +129,20 @@ fn verify_pwd(r502: &mut R502<SerialWriter, SerialReader>, password: u32) -> Res
     };
 }
 
-fn get_image(r502: &mut R502<SerialWriter, SerialReader>) -> Result<(), String> {
-    print!("Command: {:#?}", Command::GenImg);
-    loop {
-        match r502.send_command(Command::GenImg) {
-            Ok(Reply::GenImg(result)) => match result.confirmation_code {
-                GenImgStatus::Success => break,
-                GenImgStatus::FingerNotDetected => print!("."),
-                GenImgStatus::ImageNotCaptured => print!("!"),
-                _ => {}
-            },
-            Err(e) => return Err(format!("Error: {:#?}", e)),
-            msg => return Err(format!("Unexpected msg: {:#?}", msg)),
-        };
-        std::io::stdout().flush().unwrap();
-    }
-    println!();
-    return Ok(());
+fn get_image(r502: &mut R502<CombinedSerial<SerialPortAdapter>>) -> Result<(), String> {
+    println!("Command: {:#?} (every 100 ms)", Command::GenImg);
+    return match r502.wait_for_finger(&mut StdDelay, 100, 300) {
+        Ok(GenImgStatus::Success) => Ok(()),
+        Ok(status) => Err(format!("No finger captured: {:#?}", status)),
+        Err(e) => Err(format!("Error: {:#?}", e)),
+    };
 }
 
-fn process_image(r502: &mut R502<SerialWriter, SerialReader>, buffer: u8) -> Result<(), String> {
-    let cmd = Command::Img2Tz { buffer: buffer };
+fn process_image(
+    r502: &mut R502<CombinedSerial<SerialPortAdapter>>,
+    buffer: u8,
+) -> Result<(), String> {
+    let cmd = Command::Img2Tz { buffer };
     println!("Command: {:#?}", cmd);
     match r502.send_command(cmd) {
         Ok(Reply::Img2Tz(result)) => println!("Reply: {:#?}", result),
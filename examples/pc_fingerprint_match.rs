@@ -1,9 +1,9 @@
 use hzgrow_r502::{Command, GenImgStatus, MatchStatus, LoadCharResult, LoadCharStatus, Reply, R502};
 use serialport::{available_ports, open};
-use std::{cell::RefCell, env, time::Duration};
+use std::{env, time::Duration};
 
 mod pc_utils;
-use pc_utils::{SerialReader, SerialWriter};
+use pc_utils::StdDelay;
 
 const DEFAULT_BAUD_RATE: u32 = 57600;
 
@@ -30,11 +30,7 @@ fn run_test(port_name: &str, library_index: u16) {
     port.set_baud_rate(DEFAULT_BAUD_RATE).unwrap();
     port.set_timeout(Duration::from_secs(5)).unwrap();
 
-    let port_cell = RefCell::new(port);
-
-    let reader = SerialReader(&port_cell);
-    let writer = SerialWriter(&port_cell);
-    let mut r502 = R502::new(writer, reader, 0xffffffff);
+    let mut r502 = R502::from_serialport(port, 0xffffffff);
 
     println!("1. Verifying password");
 
@@ -61,20 +57,12 @@ fn run_test(port_name: &str, library_index: u16) {
     };
 
     println!("3. Acquiring image");
-    print!("Command: {:#?}", Command::GenImg);
-    loop {
-        match r502.send_command(Command::GenImg) {
-            Ok(Reply::GenImg(result)) => match result.confirmation_code {
-                GenImgStatus::Success => break,
-                GenImgStatus::FingerNotDetected => print!("."),
-                GenImgStatus::ImageNotCaptured => print!("!"),
-                _ => {}
-            },
-            Err(e) => panic!("Error: {:#?}", e),
-            msg => panic!("Unexpected msg: {:#?}", msg),
-        };
-    }
-    println!();
+    println!("Command: {:#?} (every 100 ms)", Command::GenImg);
+    match r502.wait_for_finger(&mut StdDelay, 100, 300) {
+        Ok(GenImgStatus::Success) => {}
+        Ok(status) => panic!("No finger captured: {:#?}", status),
+        Err(e) => panic!("Error: {:#?}", e),
+    };
 
     println!("4. Checking status - image should be ok");
 
@@ -1,60 +1,13 @@
-use embedded_hal::serial::{Read, Write};
-use serialport::prelude::*;
-use std::cell::RefCell;
+use embedded_hal::blocking::delay::DelayMs;
 
-// We're cheating here and will use the host OS's serial port
-// as our UART, and for that we have to implement the read/write
-// interfaces from embedded-hal.
+// The serial port itself is taken care of by `R502::from_serialport`, with the `serialport`
+// feature. This is what else the PC-based examples share.
 
-pub struct SerialReader<'a>(pub &'a RefCell<Box<dyn SerialPort>>);
-pub struct SerialWriter<'a>(pub &'a RefCell<Box<dyn SerialPort>>);
+pub struct StdDelay;
 
-impl Read<u8> for SerialReader<'_> {
-    type Error = std::io::Error;
-
-    fn read(&mut self) -> nb::Result<u8, Self::Error> {
-        let mut buf: [u8; 1] = [0u8];
-        loop {
-            match self.0.borrow_mut().read(&mut buf) {
-                Ok(n) => {
-                    if n == 1 {
-                        println!("read: {:02x}", buf[0]);
-                        return Ok(buf[0]);
-                    }
-                }
-                Err(e) => {
-                    return Err(nb::Error::from(e));
-                }
-            };
-        }
-    }
-}
-
-impl Write<u8> for SerialWriter<'_> {
-    type Error = std::io::Error;
-
-    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
-        let buf: [u8; 1] = [word];
-        loop {
-            match self.0.borrow_mut().write(&buf) {
-                Ok(n) => {
-                    if n == 1 {
-                        println!("write: {:02x}", word);
-                        return Ok(());
-                    }
-                }
-                Err(e) => {
-                    return Err(nb::Error::from(e));
-                }
-            }
-        }
-    }
-
-    fn flush(&mut self) -> nb::Result<(), Self::Error> {
-        return match self.0.borrow_mut().flush() {
-            Ok(_) => Ok(()),
-            Err(e) => Err(nb::Error::from(e)),
-        };
+impl DelayMs<u16> for StdDelay {
+    fn delay_ms(&mut self, ms: u16) {
+        std::thread::sleep(std::time::Duration::from_millis(ms as u64));
     }
 }
 
@@ -0,0 +1,11 @@
+#![no_main]
+
+use hzgrow_r502::Command;
+use libfuzzer_sys::fuzz_target;
+
+// Encodes random commands and decodes them again. Run it with
+// `cargo +nightly fuzz run commands` from the root of the repository.
+fuzz_target!(|input: (Command, u32)| {
+    let (command, address) = input;
+    hzgrow_r502::fuzz_commands(&command, address);
+});
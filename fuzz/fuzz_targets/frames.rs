@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// Feeds random bytes through the frame parser and the reply decoding. Run it with
+// `cargo +nightly fuzz run frames fuzz/corpus/frames` from the root of the repository.
+fuzz_target!(|data: &[u8]| {
+    hzgrow_r502::fuzz_frames(data);
+});
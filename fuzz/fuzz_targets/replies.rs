@@ -0,0 +1,10 @@
+#![no_main]
+
+use hzgrow_r502::Reply;
+use libfuzzer_sys::fuzz_target;
+
+// Formats replies with random fields. Run it with `cargo +nightly fuzz run replies` from the
+// root of the repository.
+fuzz_target!(|reply: Reply| {
+    hzgrow_r502::fuzz_replies(&reply);
+});
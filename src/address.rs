@@ -0,0 +1,133 @@
+use crate::driver::R502;
+use crate::responses::ReadSysParaResult;
+use crate::transport::Transport;
+
+/// The module says its address is not the one the driver sends commands to, see
+/// [`R502::address_disagreement`](struct.R502.html#method.address_disagreement). This happens
+/// when the address was mistyped but the module answers anyway, as some clones do when
+/// addressed with `0xffffffff`, or after the module was given a new address elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AddressDisagreement {
+    /// The address the driver was sending commands to.
+    pub configured: u32,
+
+    /// The address in the module's `SystemParameters`.
+    pub reported: u32,
+}
+
+impl<T, const RX_BUF: usize, const CMD_BUF: usize> R502<T, RX_BUF, CMD_BUF>
+where
+    T: Transport,
+{
+    /// The latest disagreement between the driver's address and the one the module reports,
+    /// if any. Every `ReadSysPara` which goes through `send_command` or `poll` is checked, so
+    /// this is set by helpers such as `authenticate` too. It is cleared by a `ReadSysPara`
+    /// which agrees, and by [`set_address`](#method.set_address).
+    ///
+    /// Nothing else happens unless
+    /// [`adopt_reported_address`](#method.adopt_reported_address) is on.
+    pub fn address_disagreement(&self) -> Option<AddressDisagreement> {
+        return self.address_disagreement;
+    }
+
+    /// With `adopt` set, the driver switches to the address the module reports whenever the
+    /// two disagree, and sends the commands which follow there. The disagreement is still
+    /// reported by [`address_disagreement`](#method.address_disagreement). Off by default, as
+    /// a module at the wrong address may well be the wrong module.
+    pub fn adopt_reported_address(&mut self, adopt: bool) {
+        self.adopt_address = adopt;
+    }
+
+    /// Compares the address the module reports in `result` with the driver's own.
+    pub(crate) fn check_reported_address(&mut self, result: &ReadSysParaResult) {
+        if result.confirmation_code != 0x00 {
+            return;
+        }
+        let reported = result.system_parameters.device_address;
+        if reported == self.address {
+            self.address_disagreement = None;
+            return;
+        }
+
+        let disagreement = AddressDisagreement { configured: self.address, reported };
+        if self.adopt_address {
+            self.set_address(reported);
+        }
+        self.address_disagreement = Some(disagreement);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::commands::Command;
+    use crate::emulator::{Emulator, EmulatorRx, EmulatorTx};
+
+    /// A module which answers at 0xffffffff, but reports `reported` as its address.
+    fn misreporting(reported: u32) -> (Emulator, R502<(EmulatorTx, EmulatorRx)>) {
+        let emulator = Emulator::new();
+        emulator.state().reported_address = Some(reported);
+        let (tx, rx) = emulator.serial();
+        return (emulator, R502::new(tx, rx, 0xffffffff));
+    }
+
+    #[test]
+    fn test_address_disagreement_detected() {
+        // given: a module which reports another address
+        let (_emulator, mut r502) = misreporting(0x0000_0042);
+
+        // when: reading its parameters
+        r502.send_command(Command::ReadSysPara).unwrap();
+
+        // then: the disagreement is reported, and the driver keeps its address
+        let disagreement = AddressDisagreement { configured: 0xffffffff, reported: 0x42 };
+        assert_eq!(r502.address_disagreement(), Some(disagreement));
+        assert_eq!(r502.address(), 0xffffffff);
+    }
+
+    #[test]
+    fn test_address_disagreement_adopted() {
+        // given: a module which reports another address, and a driver set to adopt it
+        let (_emulator, mut r502) = misreporting(0x0000_0042);
+        r502.adopt_reported_address(true);
+
+        // when: reading its parameters
+        r502.send_command(Command::ReadSysPara).unwrap();
+
+        // then: the driver switched over, and says so
+        let disagreement = AddressDisagreement { configured: 0xffffffff, reported: 0x42 };
+        assert_eq!(r502.address_disagreement(), Some(disagreement));
+        assert_eq!(r502.address(), 0x42);
+    }
+
+    #[test]
+    fn test_address_agreement_clears_disagreement() {
+        // given: a disagreement seen earlier
+        let (emulator, mut r502) = misreporting(0x0000_0042);
+        r502.send_command(Command::ReadSysPara).unwrap();
+
+        // when: the module reports the right address again
+        emulator.state().reported_address = None;
+        r502.send_command(Command::ReadSysPara).unwrap();
+
+        // then: the disagreement is gone
+        assert_eq!(r502.address_disagreement(), None);
+    }
+
+    #[test]
+    fn test_address_agreement() {
+        // given: a module which reports the address the driver uses
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: reading its parameters
+        r502.send_command(Command::ReadSysPara).unwrap();
+
+        // then: there is nothing to report
+        assert_eq!(r502.address_disagreement(), None);
+    }
+}
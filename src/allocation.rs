@@ -0,0 +1,237 @@
+
+use crate::driver::R502;
+use crate::library::IndexTable;
+use crate::notepad::NotepadError;
+#[cfg(feature = "cmd-notepad")]
+use crate::notepad::{NotepadPage, NOTEPAD_CHECKED_SIZE};
+use crate::transport::Transport;
+
+/// How [`R502::next_free_slot`](struct.R502.html#method.next_free_slot) and `EnrollmentBatch`
+/// pick the slot for a new template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocationStrategy {
+    /// The lowest free slot. New templates pile up at the start of the library, so the flash
+    /// there is rewritten far more often than the rest.
+    LowestFree,
+
+    /// The first free slot at or after a cursor, wrapping around at the end of the library.
+    /// Each allocation moves the cursor past the slot it picked, which spreads enrolments
+    /// across the whole library.
+    ///
+    /// The cursor is kept in notepad page `page`, so it survives restarts. Nothing else may
+    /// use that page. Needs the `cmd-notepad` feature.
+    #[cfg(feature = "cmd-notepad")]
+    Rotating { page: NotepadPage },
+}
+
+/// Settings for picking the slot for a new template, see
+/// [`R502::set_slot_allocation`](struct.R502.html#method.set_slot_allocation).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotAllocation {
+    /// Where to look for a free slot. Default: `AllocationStrategy::LowestFree`
+    pub strategy: AllocationStrategy,
+
+    /// First of a run of slots which are never picked, for example for administrator fingers
+    /// or the scratch slot of `diagnose`.
+    pub reserved_start: u16,
+
+    /// Length of the reserved run. Default: 0
+    pub reserved_count: u16,
+}
+
+impl Default for SlotAllocation {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl SlotAllocation {
+    /// The defaults, for `const` contexts where `Default` cannot be used.
+    pub const fn new() -> Self {
+        return Self {
+            strategy: AllocationStrategy::LowestFree,
+            reserved_start: 0,
+            reserved_count: 0,
+        };
+    }
+
+    /// True if slot `index` is never picked.
+    pub fn is_reserved(&self, index: u16) -> bool {
+        return index >= self.reserved_start && index - self.reserved_start < self.reserved_count;
+    }
+
+    /// The slot to use for a new template in `table`, or `None` if every slot is taken or
+    /// reserved. The search starts at `cursor`, which `AllocationStrategy::LowestFree` ignores.
+    #[cfg_attr(not(feature = "cmd-notepad"), allow(unused_variables))]
+    pub fn pick(&self, table: &IndexTable, cursor: u16) -> Option<u16> {
+        let capacity = table.capacity();
+        if capacity == 0 {
+            return None;
+        }
+        let start = match self.strategy {
+            AllocationStrategy::LowestFree => 0,
+            #[cfg(feature = "cmd-notepad")]
+            AllocationStrategy::Rotating { .. } => cursor % capacity,
+        };
+        return (start..capacity)
+            .chain(0..start)
+            .find(|index| !table.is_occupied(*index) && !self.is_reserved(*index));
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Changes how the slot for a new template is picked. Takes effect straight away; with
+    /// `AllocationStrategy::Rotating`, the cursor carries on from wherever it was saved.
+    pub fn set_slot_allocation(&mut self, allocation: SlotAllocation) {
+        self.allocation = allocation;
+    }
+
+    /// How the slot for a new template is picked.
+    pub fn slot_allocation(&self) -> SlotAllocation {
+        return self.allocation;
+    }
+
+    /// Where the search for a free slot starts: the saved cursor with
+    /// `AllocationStrategy::Rotating`, 0 otherwise. A cursor page which has never been written
+    /// reads as 0.
+    pub fn allocation_cursor(&mut self) -> Result<u16, NotepadError<T::WriteError, T::ReadError>> {
+        return match self.allocation.strategy {
+            AllocationStrategy::LowestFree => Ok(0),
+            #[cfg(feature = "cmd-notepad")]
+            AllocationStrategy::Rotating { page } => match self.read_notepad_page_checked(page) {
+                Ok(data) => Ok(u16::from_be_bytes([data[0], data[1]])),
+                Err(NotepadError::NeverWritten(_)) => Ok(0),
+                Err(error) => Err(error),
+            },
+        };
+    }
+
+    /// Moves the cursor of `AllocationStrategy::Rotating` past slot `index` and saves it.
+    /// Call this when enrolling into a slot found with
+    /// [`next_free_slot`](#method.next_free_slot), which only looks; `EnrollmentBatch` does it
+    /// by itself. Does nothing with `AllocationStrategy::LowestFree`.
+    #[cfg_attr(not(feature = "cmd-notepad"), allow(unused_variables))]
+    pub fn advance_allocation_cursor(
+        &mut self,
+        index: u16,
+    ) -> Result<(), NotepadError<T::WriteError, T::ReadError>> {
+        return match self.allocation.strategy {
+            AllocationStrategy::LowestFree => Ok(()),
+            #[cfg(feature = "cmd-notepad")]
+            AllocationStrategy::Rotating { page } => {
+                let mut data = [0u8; NOTEPAD_CHECKED_SIZE];
+                data[..2].copy_from_slice(&index.wrapping_add(1).to_be_bytes());
+                self.write_notepad_page_checked(page, &data)
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::emulator::Emulator;
+    #[cfg(feature = "cmd-notepad")]
+    use std::vec::Vec;
+
+    #[cfg(feature = "cmd-notepad")]
+    fn rotating() -> SlotAllocation {
+        return SlotAllocation {
+            strategy: AllocationStrategy::Rotating { page: NotepadPage::new(15).unwrap() },
+            ..SlotAllocation::default()
+        };
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-notepad")]
+    fn test_rotating_allocation() {
+        // given: a four-slot module using the rotating strategy
+        let emulator = Emulator::with_geometry(4, 2);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.set_slot_allocation(rotating());
+
+        // when: allocating six times, leaving the slots free
+        let mut picked = Vec::new();
+        for _ in 0..6 {
+            let index = r502.next_free_slot().unwrap().unwrap();
+            r502.advance_allocation_cursor(index).unwrap();
+            picked.push(index);
+        }
+
+        // then: every slot got its turn, wrapping around at the end
+        assert_eq!(picked, [0, 1, 2, 3, 0, 1]);
+        assert_eq!(r502.allocation_cursor().unwrap(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-notepad")]
+    fn test_rotating_allocation_skips_taken_and_reserved() {
+        // given: a module with slots 3 and 4 reserved and slot 5 taken, the cursor at 3
+        let emulator = Emulator::with_geometry(8, 2);
+        emulator.enroll(5, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.set_slot_allocation(SlotAllocation {
+            reserved_start: 3,
+            reserved_count: 2,
+            ..rotating()
+        });
+        r502.advance_allocation_cursor(2).unwrap();
+
+        // then: the next free slot is the first one past all of them
+        assert_eq!(r502.next_free_slot().unwrap(), Some(6));
+
+        // when: every other slot is taken
+        for index in [0, 1, 2, 6, 7].iter() {
+            emulator.enroll(*index, 8);
+        }
+
+        // then: the library is reported full, even though the reserved slots are free
+        assert_eq!(r502.next_free_slot().unwrap(), None);
+    }
+
+    #[test]
+    fn test_lowest_free_allocation_skips_reserved() {
+        // given: a module with its first two slots reserved
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.set_slot_allocation(SlotAllocation {
+            reserved_start: 0,
+            reserved_count: 2,
+            ..SlotAllocation::default()
+        });
+
+        // then: the lowest free slot past them is picked, without touching the notepad
+        assert_eq!(r502.next_free_slot().unwrap(), Some(2));
+        r502.advance_allocation_cursor(2).unwrap();
+        assert_eq!(emulator.instructions().contains(&0x18), false);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-notepad")]
+    fn test_allocation_cursor_persists() {
+        // given: a module whose cursor was moved by one driver
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.set_slot_allocation(rotating());
+        r502.advance_allocation_cursor(41).unwrap();
+
+        // when: another driver picks up after a restart
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.set_slot_allocation(rotating());
+
+        // then: it carries on from the saved cursor
+        assert_eq!(r502.allocation_cursor().unwrap(), 42);
+        assert_eq!(r502.next_free_slot().unwrap(), Some(42));
+        assert_eq!(&emulator.state().notepad[15][..2], &[0x00, 0x2a]);
+    }
+}
@@ -0,0 +1,564 @@
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::task::Poll;
+use embedded_hal_async::delay::DelayNs;
+use embedded_io_async::{Error as _, ErrorKind, Read, ReadExactError, Write};
+
+use crate::codec::{self, CommandBuffer};
+use crate::consts::{PacketSize, DEFAULT_PACKET_SIZE, FRAME_HEADER_LENGTH, MAX_PACKET_LENGTH};
+use crate::commands::Command;
+use crate::power::{ReadyError, ReadyScanner};
+use crate::profile::TimingProfile;
+use crate::responses::*;
+#[cfg(feature = "cmd-transfer")]
+use crate::template::{Template, TransferError};
+use crate::utils::{Error, ProtocolCommand};
+
+/// Represents a R502 device connected to an async U(S)ART, such as one of embassy's. The
+/// counterpart of [`R502`](struct.R502.html) for async firmware, sharing its packet handling.
+///
+/// This covers sending commands, transferring templates and waiting for the module to be
+/// ready. The higher-level helpers of `R502` are not available here yet; they can be built on
+/// [`send_command`](#method.send_command).
+///
+/// On a PC running tokio, a `tokio_serial::SerialStream` can be used with the `tokio` feature,
+/// through [`from_tokio`](#method.from_tokio).
+///
+/// A read error of kind `ErrorKind::TimedOut`, such as a `TokioSerial` gives when its read
+/// timeout runs out, is returned as `Error::Timeout`.
+#[derive(Debug)]
+pub struct R502Async<TX, RX> {
+    address: u32,
+    tx: TX,
+    rx: RX,
+    received: [u8; MAX_PACKET_LENGTH],
+    cmd_buffer: CommandBuffer,
+    data_packet_size: u16,
+    profile: TimingProfile,
+}
+
+impl<TX, RX> R502Async<TX, RX>
+where
+    TX: Write,
+    RX: Read,
+{
+    /// Creates an instance of the R502. `tx` and `rx` are the transmit and receive halves of a
+    /// USART, and `address` is the R502 address. By default this should be `0xffffffff`.
+    pub fn new(tx: TX, rx: RX, address: u32) -> Self {
+        Self {
+            address,
+            tx,
+            rx,
+            received: [0u8; MAX_PACKET_LENGTH],
+            cmd_buffer: CommandBuffer::new(),
+            data_packet_size: DEFAULT_PACKET_SIZE.bytes(),
+            profile: TimingProfile::datasheet(),
+        }
+    }
+
+    /// The address commands are sent to.
+    pub fn address(&self) -> u32 {
+        return self.address;
+    }
+
+    /// Changes the address commands are sent to, for example after the module has been given
+    /// a new one with `SetAdder`.
+    pub fn set_address(&mut self, address: u32) {
+        self.address = address;
+    }
+
+    /// Sets the size of the data packets the host sends when transferring templates to the
+    /// R502. This must agree with the packet size setting of the module (see
+    /// `SystemParameters::packet_size`), which is 128 bytes by default.
+    pub fn set_data_packet_size(&mut self, size: PacketSize) {
+        self.data_packet_size = size.bytes();
+    }
+
+    /// Sends a command `cmd` to the R502 and waits for the reply, as
+    /// [`R502::send_command`](struct.R502.html#method.send_command) does.
+    ///
+    /// The module always replies, so this waits as long as it takes; see
+    /// [`send_command_timeout`](#method.send_command_timeout) to give up sooner.
+    pub async fn send_command(
+        &mut self,
+        cmd: Command,
+    ) -> Result<Reply, Error<TX::Error, RX::Error>> {
+        if let Some(buffer) = cmd.invalid_buffer() {
+            return Err(Error::InvalidBuffer(buffer));
+        }
+        codec::write_builtin_command(&mut self.cmd_buffer, self.address, &cmd)?;
+        self.tx.write_all(&self.cmd_buffer).await.map_err(Error::WriteError)?;
+        self.tx.flush().await.map_err(Error::WriteError)?;
+
+        let length = self.receive_packet().await?;
+        return Ok(cmd.decode_reply(codec::check_reply(&self.received[..length])?)?);
+    }
+
+    /// Sends `cmd`, which may be a command the driver does not know of, such as a vendor
+    /// instruction defined in another crate, and waits for the reply, as
+    /// [`R502::send`](struct.R502.html#method.send) does.
+    pub async fn send<C>(&mut self, cmd: &C) -> Result<C::Reply, Error<TX::Error, RX::Error>>
+    where
+        C: ProtocolCommand,
+    {
+        codec::write_command(&mut self.cmd_buffer, self.address, cmd)?;
+        self.tx.write_all(&self.cmd_buffer).await.map_err(Error::WriteError)?;
+        self.tx.flush().await.map_err(Error::WriteError)?;
+
+        let length = self.receive_packet().await?;
+        return Ok(cmd.decode_reply(codec::check_reply(&self.received[..length])?)?);
+    }
+
+    /// Sends a command `cmd` to the R502 as [`send_command`](#method.send_command) does, but
+    /// gives up with `Error::Timeout` if the reply is not in within `timeout_ms`.
+    ///
+    /// **Note:** A reply which arrives after the timeout is not read, and would be taken for
+    /// the reply to the next command. Wait for the module with [`wait_ready`](#method.wait_ready)
+    /// or drain the receiver before carrying on.
+    pub async fn send_command_timeout<D>(
+        &mut self,
+        cmd: Command,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<Reply, Error<TX::Error, RX::Error>>
+    where
+        D: DelayNs,
+    {
+        return match select(self.send_command(cmd), delay.delay_ms(timeout_ms)).await {
+            Either::First(reply) => reply,
+            Either::Second(()) => Err(Error::Timeout),
+        };
+    }
+
+    /// The timeouts and gaps used by
+    /// [`send_command_with_timeout`](#method.send_command_with_timeout), as for
+    /// [`R502::timing_profile`](struct.R502.html#method.timing_profile).
+    pub fn timing_profile(&self) -> &TimingProfile {
+        return &self.profile;
+    }
+
+    /// The timeouts and gaps used by
+    /// [`send_command_with_timeout`](#method.send_command_with_timeout), to change them.
+    pub fn timing_profile_mut(&mut self) -> &mut TimingProfile {
+        return &mut self.profile;
+    }
+
+    /// Sends `cmd` as [`send_command_timeout`](#method.send_command_timeout) does, with the
+    /// timeout the [`timing_profile`](#method.timing_profile) has for it, and then waits out
+    /// the gap the profile has after it.
+    pub async fn send_command_with_timeout<D>(
+        &mut self,
+        cmd: Command,
+        delay: &mut D,
+    ) -> Result<Reply, Error<TX::Error, RX::Error>>
+    where
+        D: DelayNs,
+    {
+        let times = self.profile.times(cmd.kind());
+        let reply = self.send_command_timeout(cmd, delay, times.timeout_ms).await?;
+        if times.gap_ms > 0 {
+            delay.delay_ms(times.gap_ms as u32).await;
+        }
+        return Ok(reply);
+    }
+
+    /// Waits for the ready byte the R502 sends after power-up or a reset, as
+    /// [`R502::wait_ready`](struct.R502.html#method.wait_ready) does. Gives up with
+    /// `ReadyError::Timeout` after `timeout_ms`.
+    pub async fn wait_ready<D>(
+        &mut self,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<(), ReadyError<TX::Error, RX::Error>>
+    where
+        D: DelayNs,
+    {
+        return match select(self.scan_ready(), delay.delay_ms(timeout_ms)).await {
+            Either::First(result) => result,
+            Either::Second(()) => Err(ReadyError::Timeout),
+        };
+    }
+
+    /// Uploads the contents of _character buffer_ `buffer` to the host using `UpChar`.
+    #[cfg(feature = "cmd-transfer")]
+    pub async fn upload_template(
+        &mut self,
+        buffer: u8,
+    ) -> Result<Template, TransferError<TX::Error, RX::Error>> {
+        let reply = self.send_command(Command::UpChar { buffer }).await;
+        let result = expect_reply!(reply, Reply::UpChar)?;
+        match result.confirmation_code {
+            UpCharStatus::Success => {}
+            status => return Err(TransferError::UploadRejected(status)),
+        }
+
+        let mut template = Template::new();
+        loop {
+            let length = self.receive_packet().await?;
+            let (payload, last) =
+                codec::data_payload(&self.received[..length]).map_err(Error::from)?;
+            if !template.append(payload) {
+                return Err(TransferError::TooLarge);
+            }
+            if last {
+                return Ok(template);
+            }
+        }
+    }
+
+    /// Uploads the contents of _character buffer_ `buffer` into `out`, as
+    /// `R502::up_char_into` does.
+    #[cfg(feature = "cmd-transfer")]
+    pub async fn up_char_into(
+        &mut self,
+        buffer: u8,
+        out: &mut [u8],
+    ) -> Result<usize, TransferError<TX::Error, RX::Error>> {
+        let reply = self.send_command(Command::UpChar { buffer }).await;
+        let result = expect_reply!(reply, Reply::UpChar)?;
+        match result.confirmation_code {
+            UpCharStatus::Success => {}
+            status => return Err(TransferError::UploadRejected(status)),
+        }
+
+        let mut written = 0;
+        loop {
+            let length = self.receive_packet().await?;
+            let (payload, last) =
+                codec::data_payload(&self.received[..length]).map_err(Error::from)?;
+            if let Some(space) = out.get_mut(written..written + payload.len()) {
+                space.copy_from_slice(payload);
+            }
+            written += payload.len();
+            if last {
+                break;
+            }
+        }
+
+        if written > out.len() {
+            return Err(TransferError::BufferTooSmall { needed: written });
+        }
+        return Ok(written);
+    }
+
+    /// Downloads `template` into _character buffer_ `buffer` using `DownChar`.
+    #[cfg(feature = "cmd-transfer")]
+    pub async fn download_template(
+        &mut self,
+        buffer: u8,
+        template: &Template,
+    ) -> Result<(), TransferError<TX::Error, RX::Error>> {
+        return self.down_char_from(buffer, template.as_bytes()).await;
+    }
+
+    /// Downloads `data` into _character buffer_ `buffer` using `DownChar`, straight from the
+    /// caller's slice.
+    #[cfg(feature = "cmd-transfer")]
+    pub async fn down_char_from(
+        &mut self,
+        buffer: u8,
+        data: &[u8],
+    ) -> Result<(), TransferError<TX::Error, RX::Error>> {
+        let reply = self.send_command(Command::DownChar { buffer }).await;
+        let result = expect_reply!(reply, Reply::DownChar)?;
+        match result.confirmation_code {
+            DownCharStatus::Success => {}
+            status => return Err(TransferError::DownloadRejected(status)),
+        }
+
+        let mut chunks = data.chunks(self.data_packet_size as usize).peekable();
+        while let Some(chunk) = chunks.next() {
+            let last = chunks.peek().is_none();
+            let chk = codec::encode_data_header(&mut self.cmd_buffer, self.address, chunk, last);
+            self.tx.write_all(&self.cmd_buffer).await.map_err(Error::WriteError)?;
+            self.tx.write_all(chunk).await.map_err(Error::WriteError)?;
+            self.tx.write_all(&chk).await.map_err(Error::WriteError)?;
+        }
+        self.tx.flush().await.map_err(Error::WriteError)?;
+        return Ok(());
+    }
+
+    /// Reads a whole packet into `received`, and returns its length.
+    async fn receive_packet(&mut self) -> Result<usize, Error<TX::Error, RX::Error>> {
+        read_exact(&mut self.rx, &mut self.received[..FRAME_HEADER_LENGTH]).await?;
+
+        let length = codec::frame_length(&self.received)?;
+        if length > MAX_PACKET_LENGTH {
+            return Err(Error::RecvWrongReplyType);
+        }
+        read_exact(&mut self.rx, &mut self.received[FRAME_HEADER_LENGTH..length]).await?;
+        return Ok(length);
+    }
+
+    /// Reads bytes until the ready byte, without a timeout.
+    async fn scan_ready(&mut self) -> Result<(), ReadyError<TX::Error, RX::Error>> {
+        let mut scanner = ReadyScanner::default();
+        loop {
+            let mut byte = [0u8];
+            read_exact(&mut self.rx, &mut byte).await?;
+            if scanner.feed(byte[0])? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Fills `buffer` from `rx`. The stream ending early counts as a packet cut short.
+async fn read_exact<RX, TXE>(rx: &mut RX, buffer: &mut [u8]) -> Result<(), Error<TXE, RX::Error>>
+where
+    RX: Read,
+{
+    return match rx.read_exact(buffer).await {
+        Ok(()) => Ok(()),
+        Err(ReadExactError::UnexpectedEof) => Err(Error::RecvPacketTooShort),
+        Err(ReadExactError::Other(error)) if error.kind() == ErrorKind::TimedOut => {
+            Err(Error::Timeout)
+        }
+        Err(ReadExactError::Other(error)) => Err(Error::RecvReadError(error)),
+    };
+}
+
+enum Either<A, B> {
+    First(A),
+    Second(B),
+}
+
+/// Waits for whichever of `first` and `second` finishes first, and drops the other.
+async fn select<A, B>(first: A, second: B) -> Either<A::Output, B::Output>
+where
+    A: Future,
+    B: Future,
+{
+    let mut first = pin!(first);
+    let mut second = pin!(second);
+    return poll_fn(|cx| {
+        if let Poll::Ready(output) = first.as_mut().poll(cx) {
+            return Poll::Ready(Either::First(output));
+        }
+        if let Poll::Ready(output) = second.as_mut().poll(cx) {
+            return Poll::Ready(Either::Second(output));
+        }
+        return Poll::Pending;
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    #[cfg(all(feature = "cmd-enroll", feature = "cmd-transfer"))]
+    use crate::emulator::char_file;
+    use crate::emulator::{Emulator, EmulatorError, EmulatorRx, EmulatorTx};
+    use core::task::{Context, Waker};
+    use embedded_hal::serial::{Read as _, Write as _};
+    use embedded_io_async::{ErrorKind, ErrorType};
+    use std::vec;
+    use std::vec::Vec;
+
+    impl embedded_io_async::Error for EmulatorError {
+        fn kind(&self) -> ErrorKind {
+            return ErrorKind::TimedOut;
+        }
+    }
+
+    /// The emulated module behind an async stream, which takes a byte at a time and makes
+    /// every other read wait, so replies come in over several polls.
+    struct AsyncTx(EmulatorTx);
+    struct AsyncRx(EmulatorRx, bool);
+
+    impl ErrorType for AsyncTx {
+        type Error = EmulatorError;
+    }
+
+    impl ErrorType for AsyncRx {
+        type Error = EmulatorError;
+    }
+
+    impl Write for AsyncTx {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            return match self.0.write(buf[0]) {
+                Ok(()) => Ok(1),
+                Err(nb::Error::Other(error)) => Err(error),
+                Err(nb::Error::WouldBlock) => unreachable!(),
+            };
+        }
+    }
+
+    impl Read for AsyncRx {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            return poll_fn(|cx| {
+                self.1 = !self.1;
+                if self.1 {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                return match self.0.read() {
+                    Ok(word) => {
+                        buf[0] = word;
+                        Poll::Ready(Ok(1))
+                    }
+                    Err(nb::Error::Other(error)) => Poll::Ready(Err(error)),
+                    Err(nb::Error::WouldBlock) => {
+                        cx.waker().wake_by_ref();
+                        Poll::Pending
+                    }
+                };
+            })
+            .await;
+        }
+    }
+
+    /// A timer which has always run out.
+    struct Expired;
+
+    impl DelayNs for Expired {
+        async fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    /// A timer which never runs out.
+    struct Endless;
+
+    impl DelayNs for Endless {
+        async fn delay_ns(&mut self, _ns: u32) {
+            core::future::pending::<()>().await;
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    fn r502(emulator: &Emulator) -> R502Async<AsyncTx, AsyncRx> {
+        let (tx, rx) = emulator.serial();
+        return R502Async::new(AsyncTx(tx), AsyncRx(rx, false), 0xffffffff);
+    }
+
+    #[test]
+    fn test_async_send_command() {
+        // given: a module with two templates
+        let emulator = Emulator::new();
+        emulator.enroll(3, 7);
+        emulator.enroll(8, 9);
+        let mut r502 = r502(&emulator);
+
+        // when: counting them
+        let reply = block_on(r502.send_command(Command::TemplateNum));
+
+        // then: the reply is the same as the blocking driver gets
+        match reply {
+            Ok(Reply::TemplateNum(result)) => assert_eq!(result.template_num, 2),
+            other => panic!("Expected Reply::TemplateNum, got {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "cmd-enroll", feature = "cmd-transfer"))]
+    fn test_async_template_round_trip() {
+        // given: a module with a template in slot 3, and a small data packet size
+        let emulator = Emulator::new();
+        emulator.enroll(3, 7);
+        let mut r502 = r502(&emulator);
+        r502.set_data_packet_size(PacketSize::Bytes64);
+
+        // when: exporting the template and storing it again in slot 5
+        let template = block_on(async {
+            r502.send_command(Command::LoadChar { buffer: 2, index: 3 }).await.unwrap();
+            let template = r502.upload_template(2).await.unwrap();
+            r502.download_template(1, &template).await.unwrap();
+            r502.send_command(Command::Store { buffer: 1, index: 5 }).await.unwrap();
+            return template;
+        });
+
+        // then: both slots hold the same template
+        assert_eq!(template.as_bytes(), &char_file(7)[..]);
+        assert_eq!(emulator.slot(5), emulator.slot(3));
+    }
+
+    /// A timer which notes how long each wait was meant to be. Waits of 100 ms or more, long
+    /// enough to be timeouts, never run out; shorter ones end straight away.
+    #[derive(Default)]
+    struct Recording(Vec<u32>);
+
+    impl DelayNs for Recording {
+        async fn delay_ns(&mut self, ns: u32) {
+            self.delay_ms(ns / 1_000_000).await;
+        }
+
+        async fn delay_ms(&mut self, ms: u32) {
+            self.0.push(ms);
+            if ms >= 100 {
+                core::future::pending::<()>().await;
+            }
+        }
+    }
+
+    #[test]
+    fn test_async_send_command_with_timeout() {
+        // given: a module which answers straight away
+        let emulator = Emulator::new();
+        let mut r502 = r502(&emulator);
+
+        // when: changing a setting, then asking for the template count
+        let mut delay = Recording::default();
+        let set = Command::SetSysPara { parameter: 5, value: 3 };
+        let set = block_on(r502.send_command_with_timeout(set, &mut delay));
+        let count = block_on(r502.send_command_with_timeout(Command::TemplateNum, &mut delay));
+
+        // then: both went through within the profile's timeouts, and only the setting was
+        // followed by a gap
+        assert_eq!(matches!(set, Ok(Reply::SetSysPara(_))), true);
+        assert_eq!(matches!(count, Ok(Reply::TemplateNum(_))), true);
+        assert_eq!(delay.0, vec![500, 50, 200]);
+    }
+
+    #[test]
+    fn test_async_send_command_timeout() {
+        // given: a module which loses its reply to the first `TemplateNum`
+        let emulator = Emulator::new();
+        emulator.state().would_block = true;
+        emulator.lose_next_reply(0x1d);
+        let mut r502 = r502(&emulator);
+
+        // when: sending it with a timer which has already run out
+        // then: the command times out
+        match block_on(r502.send_command_timeout(Command::TemplateNum, &mut Expired, 100)) {
+            Err(Error::Timeout) => {}
+            other => panic!("Expected Error::Timeout, got {:?}", other),
+        }
+
+        // when: sending it again, with plenty of time
+        let reply = block_on(r502.send_command_timeout(Command::TemplateNum, &mut Endless, 100));
+
+        // then: the reply comes in
+        assert_eq!(matches!(reply, Ok(Reply::TemplateNum(_))), true);
+    }
+
+    #[test]
+    fn test_async_wait_ready() {
+        // given: a module which sends a packet containing 0x55 before the ready byte
+        let emulator = Emulator::new();
+        emulator.state().would_block = true;
+        emulator.send_raw(&[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x02, 0x55, 0x5d]);
+        emulator.send_raw(&[0x55]);
+        let mut r502 = r502(&emulator);
+
+        // when: waiting for it
+        // then: it is ready once the ready byte is in
+        block_on(r502.wait_ready(&mut Endless, 100)).unwrap();
+
+        // when: waiting again, with nothing more to come
+        // then: it times out
+        match block_on(r502.wait_ready(&mut Expired, 100)) {
+            Err(ReadyError::Timeout) => {}
+            other => panic!("Expected ReadyError::Timeout, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,620 @@
+use arrayvec::ArrayVec;
+#[cfg(feature = "cmd-notepad")]
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::driver::R502;
+#[cfg(feature = "cmd-notepad")]
+use crate::notepad::{crc16, NOTEPAD_CHECKED_SIZE};
+use crate::consts::NOTEPAD_PAGES;
+use crate::notepad::{NotepadError, NotepadPage};
+use crate::transport::Transport;
+
+/// Number of records an `AuditLog` keeps per notepad page.
+pub const AUDIT_RECORDS_PER_PAGE: usize = 2;
+
+/// Most records an `AuditLog` can keep, with the whole notepad given over to it.
+pub const MAX_AUDIT_RECORDS: usize = NOTEPAD_PAGES as usize * AUDIT_RECORDS_PER_PAGE;
+
+/// The records in an audit log, oldest first, as returned by
+/// [`R502::read_audit_log`](struct.R502.html#method.read_audit_log).
+pub type AuditEntries = ArrayVec<AuditRecord, MAX_AUDIT_RECORDS>;
+
+/// Version of the page layout written by this driver.
+#[cfg(feature = "cmd-notepad")]
+const AUDIT_VERSION: u8 = 1;
+
+#[cfg(feature = "cmd-notepad")]
+const RECORD_SIZE: usize = 12;
+
+/// Chain of the very first record, which has none before it: the CRC of nothing.
+#[cfg(feature = "cmd-notepad")]
+const FIRST_CHAIN: u16 = 0xffff;
+
+#[cfg(feature = "cmd-notepad")]
+type AuditPage = [u8; NOTEPAD_CHECKED_SIZE];
+
+#[cfg(feature = "cmd-notepad")]
+type AuditPages = ArrayVec<(NotepadPage, AuditPage), { NOTEPAD_PAGES as usize }>;
+
+/// A record as stored, with the checksum of the record before it.
+#[cfg(feature = "cmd-notepad")]
+type StoredRecords = ArrayVec<(AuditRecord, u16), MAX_AUDIT_RECORDS>;
+
+/// What an `AuditRecord` records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AuditOperation {
+    /// A template was stored by an enrolment helper.
+    Enrolled,
+
+    /// A run of templates was deleted by `delete_indices`, or a helper built on it, or a
+    /// template was deleted from the old slot of a move by `defragment_library`.
+    Deleted,
+
+    /// A template was stored by `import_template`, or moved into the slot by
+    /// `defragment_library`.
+    Stored,
+}
+
+/// One entry of an `AuditLog`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuditRecord {
+    /// Number of the record, counting up from 0 over the life of the log, so that records
+    /// which have gone can be counted even after the ring has wrapped around.
+    pub counter: u32,
+
+    /// What happened.
+    pub operation: AuditOperation,
+
+    /// The first slot affected.
+    pub slot: u16,
+
+    /// How many slots were affected, starting from `slot`. 1 for an enrolment.
+    pub count: u16,
+
+    /// The tag set with [`R502::set_audit_tag`](struct.R502.html#method.set_audit_tag) at the
+    /// time, such as the operator's ID. 0 if none was set.
+    pub tag: u8,
+}
+
+/// Error type for the audit log.
+#[derive(Debug)]
+pub enum AuditError<TXE, RXE> {
+    /// A page of the log could not be read or written. `NotepadError::CrcMismatch` means the
+    /// page was damaged, or changed by something other than the driver.
+    Notepad(NotepadError<TXE, RXE>),
+
+    /// The page was written by a newer version of the log, and is left alone.
+    UnknownVersion(NotepadPage, u8),
+
+    /// The record numbered `counter` does not follow on from the one before it: records in
+    /// between are missing, or the one before was changed after it was written.
+    Broken { counter: u32 },
+
+    /// The log ends before the record numbered `expected`, which the caller knows was
+    /// written: the newest records were wiped or rolled back, or the whole log was. `newest`
+    /// is the counter of the newest record left, `None` if there are none.
+    Truncated { expected: u32, newest: Option<u32> },
+}
+
+impl<TXE, RXE> From<NotepadError<TXE, RXE>> for AuditError<TXE, RXE> {
+    fn from(error: NotepadError<TXE, RXE>) -> Self {
+        return Self::Notepad(error);
+    }
+}
+
+/// A record of the templates added to and removed from the library, kept on the module in a
+/// ring of notepad pages, so it survives the host being reflashed. Once enabled with
+/// [`R502::set_audit_log`](struct.R502.html#method.set_audit_log), the enrolment helpers,
+/// `import_template`, `defragment_library` and `delete_indices` add a record for each template
+/// stored and each run of templates deleted; a move is recorded as a store into the new slot
+/// followed by a delete of the old one. When the ring is full, the oldest records are written
+/// over.
+///
+/// Each record carries a CRC of the one before it, and each page a CRC of its contents, so a
+/// page which was damaged or changed, or a record missing from between two others, shows up
+/// when the log is read with [`R502::read_audit_log`](struct.R502.html#method.read_audit_log).
+/// The CRCs are not keyed: this catches accidents and casual tampering, not someone who
+/// rewrites the whole log.
+///
+/// The log alone cannot tell that records are missing from its end: with the newest records
+/// wiped or rolled back, or the whole log wiped, what is left still reads as a whole log. To
+/// catch that, keep the `counter` of the newest record somewhere the module cannot change,
+/// such as the host's own storage, and read the log with
+/// [`R502::read_audit_log_since`](struct.R502.html#method.read_audit_log_since).
+///
+/// # Storage layout
+///
+/// Every page is written with
+/// [`R502::write_notepad_page_checked`](struct.R502.html#method.write_notepad_page_checked),
+/// so its last two bytes are a CRC of the first 30:
+///
+/// | Bytes  | Contents                                                              |
+/// |--------|-----------------------------------------------------------------------|
+/// | 0      | Layout version, currently 1                                           |
+/// | 1..25  | 2 records of 12 bytes, as below                                       |
+/// | 25..30 | Reserved, written as zero                                             |
+///
+/// A record is the operation (1 enrolled, 2 deleted, 3 stored, 0 for an unused record), the
+/// tag, then
+/// big-endian the slot, the count, the counter, and the CRC of the 12 bytes of the record
+/// before it (`0xffff` for record 0). Record `n` is kept at place `n` modulo the capacity.
+///
+/// **Note:** Labels stored with [`R502::set_label`](struct.R502.html#method.set_label) take
+/// up the whole notepad, so they cannot be used together with an audit log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditLog {
+    first: u8,
+    pages: u8,
+    tag: u8,
+}
+
+impl AuditLog {
+    /// A log kept in `pages` notepad pages starting from `first`. Returns `None` if `pages` is
+    /// zero or the range runs past the end of the notepad.
+    pub fn new(first: NotepadPage, pages: u8) -> Option<Self> {
+        if pages == 0 || first.number() as usize + pages as usize > NOTEPAD_PAGES as usize {
+            return None;
+        }
+        return Some(Self { first: first.number(), pages, tag: 0 });
+    }
+
+    /// How many records the log keeps before the oldest are written over.
+    pub fn capacity(&self) -> usize {
+        return self.pages as usize * AUDIT_RECORDS_PER_PAGE;
+    }
+}
+
+#[cfg(feature = "cmd-notepad")]
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Starts keeping an `AuditLog` of templates added and removed by the helpers, in the
+    /// pages given by `log`, carrying on from the records already there. `None`, the default,
+    /// stops it. Nothing else may use those pages.
+    ///
+    /// Each record takes a read of every page of the log and a write of one, after the
+    /// template has been stored or deleted.
+    pub fn set_audit_log(&mut self, log: Option<AuditLog>) {
+        let tag = self.audit.map(|log| log.tag).unwrap_or(0);
+        self.audit = log.map(|log| AuditLog { tag, ..log });
+    }
+
+    /// The audit log being kept, if there is one.
+    pub fn audit_log(&self) -> Option<AuditLog> {
+        return self.audit;
+    }
+
+    /// Sets the tag put in the records from now on, such as the ID of the operator at the
+    /// device. 0, the default, means none. Does nothing if no log is being kept.
+    pub fn set_audit_tag(&mut self, tag: u8) {
+        if let Some(log) = self.audit.as_mut() {
+            log.tag = tag;
+        }
+    }
+
+    /// Reads the audit log, oldest record first, checking that each record follows on from
+    /// the one before. Empty if no log is being kept, or nothing has been recorded.
+    ///
+    /// # Errors
+    ///
+    /// ## `AuditError::Notepad(NotepadError::CrcMismatch(page))`
+    /// Returned if a page was changed by something other than the driver.
+    ///
+    /// ## `AuditError::Broken { counter }`
+    /// Returned if records are missing from before `counter`, or the one before it was
+    /// changed, even with the page CRC made good.
+    pub fn read_audit_log(
+        &mut self,
+    ) -> Result<AuditEntries, AuditError<T::WriteError, T::ReadError>> {
+        let log = match self.audit {
+            Some(log) => log,
+            None => return Ok(AuditEntries::new()),
+        };
+        let pages = self.load_audit_pages(&log)?;
+        let records = stored_records(&log, &pages)?;
+        for pair in records.windows(2) {
+            let ((before, before_chain), (record, chain)) = (pair[0], pair[1]);
+            if record.counter != before.counter.wrapping_add(1)
+                || chain != crc16(&encode_record(&before, before_chain))
+            {
+                return Err(AuditError::Broken { counter: record.counter });
+            }
+        }
+        return Ok(records.iter().map(|(record, _)| *record).collect());
+    }
+
+    /// Reads the audit log as [`read_audit_log`](#method.read_audit_log) does, and checks that
+    /// it still reaches the record numbered `expected`, the newest the caller saw written,
+    /// which is the only way to catch records wiped from the end of the log.
+    ///
+    /// # Errors
+    ///
+    /// As for `read_audit_log`, and:
+    ///
+    /// ## `AuditError::Truncated { expected, newest }`
+    /// Returned if the newest record left is older than `expected`, or there are none.
+    pub fn read_audit_log_since(
+        &mut self,
+        expected: u32,
+    ) -> Result<AuditEntries, AuditError<T::WriteError, T::ReadError>> {
+        let records = self.read_audit_log()?;
+        let newest = records.last().map(|record| record.counter);
+        return match newest {
+            Some(counter) if counter >= expected => Ok(records),
+            _ => Err(AuditError::Truncated { expected, newest }),
+        };
+    }
+
+    /// Adds a record to the audit log, if one is being kept.
+    pub(crate) fn record_audit(
+        &mut self,
+        operation: AuditOperation,
+        slot: u16,
+        count: u16,
+    ) -> Result<(), AuditError<T::WriteError, T::ReadError>> {
+        let log = match self.audit {
+            Some(log) => log,
+            None => return Ok(()),
+        };
+        let mut pages = self.load_audit_pages(&log)?;
+        let records = stored_records(&log, &pages)?;
+        let (counter, chain) = match records.last() {
+            Some((last, last_chain)) => {
+                (last.counter.wrapping_add(1), crc16(&encode_record(last, *last_chain)))
+            }
+            None => (0, FIRST_CHAIN),
+        };
+
+        let record = AuditRecord { counter, operation, slot, count, tag: log.tag };
+        let place = counter as usize % log.capacity();
+        let (page, data) = &mut pages[place / AUDIT_RECORDS_PER_PAGE];
+        let offset = 1 + place % AUDIT_RECORDS_PER_PAGE * RECORD_SIZE;
+        data[offset..offset + RECORD_SIZE].copy_from_slice(&encode_record(&record, chain));
+        self.write_notepad_page_checked(*page, data)?;
+        return Ok(());
+    }
+
+    /// Reads every page of the log. Blank pages come back with no records.
+    fn load_audit_pages(
+        &mut self,
+        log: &AuditLog,
+    ) -> Result<AuditPages, AuditError<T::WriteError, T::ReadError>> {
+        let mut pages = AuditPages::new();
+        for page in NotepadPage::all().skip(log.first as usize).take(log.pages as usize) {
+            let data = match self.read_notepad_page_checked(page) {
+                Ok(data) => data,
+                Err(NotepadError::NeverWritten(_)) => {
+                    let mut data = [0u8; NOTEPAD_CHECKED_SIZE];
+                    data[0] = AUDIT_VERSION;
+                    data
+                }
+                Err(error) => return Err(AuditError::Notepad(error)),
+            };
+            if data[0] != AUDIT_VERSION {
+                return Err(AuditError::UnknownVersion(page, data[0]));
+            }
+            pages.push((page, data));
+        }
+        return Ok(pages);
+    }
+}
+
+/// Adds a record to the audit log; without the notepad, there is none to add to.
+#[cfg(not(feature = "cmd-notepad"))]
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    #[allow(clippy::unnecessary_wraps)]
+    pub(crate) fn record_audit(
+        &mut self,
+        _operation: AuditOperation,
+        _slot: u16,
+        _count: u16,
+    ) -> Result<(), AuditError<T::WriteError, T::ReadError>> {
+        return Ok(());
+    }
+}
+
+/// Every record in `pages`, with its chain, in order of counter. A record found in a place
+/// other than its counter's has been moved, so the log is broken there.
+#[cfg(feature = "cmd-notepad")]
+fn stored_records<TXE, RXE>(
+    log: &AuditLog,
+    pages: &AuditPages,
+) -> Result<StoredRecords, AuditError<TXE, RXE>> {
+    let mut records = StoredRecords::new();
+    for (page_index, (_, data)) in pages.iter().enumerate() {
+        for within in 0..AUDIT_RECORDS_PER_PAGE {
+            let offset = 1 + within * RECORD_SIZE;
+            let (record, chain) = match decode_record(&data[offset..offset + RECORD_SIZE]) {
+                Some(stored) => stored,
+                None => continue,
+            };
+            let place = page_index * AUDIT_RECORDS_PER_PAGE + within;
+            if record.counter as usize % log.capacity() != place {
+                return Err(AuditError::Broken { counter: record.counter });
+            }
+            records.push((record, chain));
+        }
+    }
+    records.sort_unstable_by_key(|(record, _)| record.counter);
+    return Ok(records);
+}
+
+#[cfg(feature = "cmd-notepad")]
+fn encode_record(record: &AuditRecord, chain: u16) -> [u8; RECORD_SIZE] {
+    let mut bytes = [0u8; RECORD_SIZE];
+    bytes[0] = match record.operation {
+        AuditOperation::Enrolled => 1,
+        AuditOperation::Deleted => 2,
+        AuditOperation::Stored => 3,
+    };
+    bytes[1] = record.tag;
+    BigEndian::write_u16(&mut bytes[2..4], record.slot);
+    BigEndian::write_u16(&mut bytes[4..6], record.count);
+    BigEndian::write_u32(&mut bytes[6..10], record.counter);
+    BigEndian::write_u16(&mut bytes[10..12], chain);
+    return bytes;
+}
+
+/// The record in `bytes`, with its chain, or `None` if the place is unused. An operation this
+/// driver does not know is taken as unused.
+#[cfg(feature = "cmd-notepad")]
+fn decode_record(bytes: &[u8]) -> Option<(AuditRecord, u16)> {
+    let operation = match bytes[0] {
+        1 => AuditOperation::Enrolled,
+        2 => AuditOperation::Deleted,
+        3 => AuditOperation::Stored,
+        _ => return None,
+    };
+    let record = AuditRecord {
+        counter: BigEndian::read_u32(&bytes[6..10]),
+        operation,
+        slot: BigEndian::read_u16(&bytes[2..4]),
+        count: BigEndian::read_u16(&bytes[4..6]),
+        tag: bytes[1],
+    };
+    return Some((record, BigEndian::read_u16(&bytes[10..12])));
+}
+
+#[cfg(all(test, feature = "cmd-notepad"))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::emulator::{Emulator, EmulatorRx, EmulatorTx};
+    #[cfg(feature = "cmd-enroll")]
+    use crate::emulator::NoDelay;
+    #[cfg(feature = "cmd-enroll")]
+    use crate::enroll::EnrollConfig;
+    #[cfg(all(feature = "cmd-enroll", feature = "cmd-transfer"))]
+    use crate::emulator::char_file;
+    #[cfg(all(feature = "cmd-enroll", feature = "cmd-transfer"))]
+    use crate::template::Template;
+    use std::vec::Vec;
+
+    fn setup(first: u8, pages: u8) -> (Emulator, R502<(EmulatorTx, EmulatorRx)>) {
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.set_audit_log(AuditLog::new(NotepadPage::new(first).unwrap(), pages));
+        return (emulator, r502);
+    }
+
+    #[test]
+    fn test_audit_log_bounds() {
+        // then: only logs which fit in the notepad are allowed
+        assert_eq!(AuditLog::new(NotepadPage::new(0).unwrap(), 16).is_some(), true);
+        assert_eq!(AuditLog::new(NotepadPage::new(15).unwrap(), 2), None);
+        assert_eq!(AuditLog::new(NotepadPage::new(3).unwrap(), 0), None);
+        assert_eq!(AuditLog::new(NotepadPage::new(2).unwrap(), 3).unwrap().capacity(), 6);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_helpers_write_audit_log() {
+        // given: a module keeping a log in pages 10 and 11, and an operator at the device
+        let (emulator, mut r502) = setup(10, 2);
+        emulator.script_captures(7, 2);
+        emulator.enroll(4, 8);
+        emulator.enroll(5, 9);
+        r502.set_audit_tag(42);
+
+        // when: enrolling a finger, then deleting two templates in one run
+        r502.enroll(3, &EnrollConfig::default(), &mut NoDelay, |_| {}).unwrap();
+        r502.delete_indices(&[5, 4]).unwrap();
+
+        // then: each was recorded in order, the deletion as a single record
+        let records = r502.read_audit_log().unwrap();
+        let enrolled = AuditRecord {
+            counter: 0,
+            operation: AuditOperation::Enrolled,
+            slot: 3,
+            count: 1,
+            tag: 42,
+        };
+        let deleted = AuditRecord {
+            counter: 1,
+            operation: AuditOperation::Deleted,
+            slot: 4,
+            count: 2,
+            tag: 42,
+        };
+        assert_eq!(&records[..], &[enrolled, deleted]);
+
+        // and: only the log pages were written
+        assert_eq!(emulator.state().notepad[10][0], AUDIT_VERSION);
+        assert_eq!(emulator.state().notepad[11], [0x00; 32]);
+        assert_eq!(emulator.state().notepad[9], [0x00; 32]);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_defragment_writes_audit_log() {
+        // given: a module keeping a log, with templates at 2 and 5
+        let (emulator, mut r502) = setup(0, 2);
+        emulator.enroll(2, 6);
+        emulator.enroll(5, 7);
+
+        // when: compacting the library
+        let moves = r502.defragment_library(|_| {}).unwrap();
+
+        // then: each move was recorded as a store into the new slot, then a delete of the old
+        let records = r502.read_audit_log().unwrap();
+        let operations: Vec<_> =
+            records.iter().map(|record| (record.operation, record.slot, record.count)).collect();
+        assert_eq!(moves, 2);
+        assert_eq!(
+            operations,
+            [
+                (AuditOperation::Stored, 0, 1),
+                (AuditOperation::Deleted, 5, 1),
+                (AuditOperation::Stored, 1, 1),
+                (AuditOperation::Deleted, 2, 1),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "cmd-enroll", feature = "cmd-transfer"))]
+    fn test_import_writes_audit_log() {
+        // given: a module keeping a log, and a template to import
+        let (emulator, mut r502) = setup(0, 2);
+        let template = Template::from_bytes(&char_file(5)).unwrap();
+
+        // when: importing it into slot 9
+        r502.import_template(9, &template, false).unwrap();
+
+        // then: the store was recorded
+        let records = r502.read_audit_log().unwrap();
+        let stored = AuditRecord {
+            counter: 0,
+            operation: AuditOperation::Stored,
+            slot: 9,
+            count: 1,
+            tag: 0,
+        };
+        assert_eq!(&records[..], &[stored]);
+        assert_eq!(emulator.slot(9), Some(char_file(5)));
+    }
+
+    #[test]
+    fn test_audit_log_wraps_around() {
+        // given: a log with room for four records, and seven templates to delete
+        let (emulator, mut r502) = setup(0, 2);
+        for index in 0..7 {
+            emulator.enroll(index * 2, 7);
+        }
+
+        // when: deleting them one at a time
+        for index in 0..7u16 {
+            r502.delete_indices(&[index * 2]).unwrap();
+        }
+
+        // then: the last four are kept, in order, and still chain together
+        let records = r502.read_audit_log().unwrap();
+        let counters: Vec<u32> = records.iter().map(|record| record.counter).collect();
+        let slots: Vec<u16> = records.iter().map(|record| record.slot).collect();
+        assert_eq!(counters, [3, 4, 5, 6]);
+        assert_eq!(slots, [6, 8, 10, 12]);
+
+        // and: a driver started afresh carries on counting from them
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.set_audit_log(AuditLog::new(NotepadPage::new(0).unwrap(), 2));
+        emulator.enroll(20, 7);
+        r502.delete_indices(&[20]).unwrap();
+        let records = r502.read_audit_log().unwrap();
+        assert_eq!(records.last().map(|record| record.counter), Some(7));
+        assert_eq!(records.len(), 4);
+    }
+
+    #[test]
+    fn test_audit_log_tampering() {
+        // given: a log of three deletions
+        let (emulator, mut r502) = setup(0, 2);
+        for index in 0..3u16 {
+            emulator.enroll(index as usize, 7);
+            r502.delete_indices(&[index]).unwrap();
+        }
+
+        // when: the slot of the first record is changed on the module
+        emulator.state().notepad[0][3] ^= 0x01;
+
+        // then: the page fails its CRC
+        match r502.read_audit_log() {
+            Err(AuditError::Notepad(NotepadError::CrcMismatch(page))) => {
+                assert_eq!(page.number(), 0)
+            }
+            other => panic!("Expected NotepadError::CrcMismatch, got {:?}", other),
+        };
+
+        // when: the CRC of the page is made good again
+        let mut data = [0u8; NOTEPAD_CHECKED_SIZE];
+        data.copy_from_slice(&emulator.state().notepad[0][..NOTEPAD_CHECKED_SIZE]);
+        r502.write_notepad_page_checked(NotepadPage::new(0).unwrap(), &data).unwrap();
+
+        // then: the record after it no longer follows on
+        match r502.read_audit_log() {
+            Err(AuditError::Broken { counter: 1 }) => {}
+            other => panic!("Expected AuditError::Broken, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn test_audit_log_rolled_back() {
+        // given: a log of three deletions, and a copy of its second page after the first two
+        let (emulator, mut r502) = setup(0, 2);
+        emulator.enroll(0, 7);
+        r502.delete_indices(&[0]).unwrap();
+        let old_page = emulator.state().notepad[0];
+        for index in 1..3u16 {
+            emulator.enroll(index as usize, 7);
+            r502.delete_indices(&[index]).unwrap();
+        }
+
+        // when: the first page is put back as it was after the first deletion
+        emulator.state().notepad[0] = old_page;
+
+        // then: the second record has gone missing, which the third gives away
+        match r502.read_audit_log() {
+            Err(AuditError::Broken { counter: 2 }) => {}
+            other => panic!("Expected AuditError::Broken, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn test_audit_log_truncated() {
+        // given: a log of three deletions, the newest of which the host kept the counter of
+        let (emulator, mut r502) = setup(0, 2);
+        for index in 0..3u16 {
+            emulator.enroll(index as usize, 7);
+            r502.delete_indices(&[index]).unwrap();
+        }
+        let newest = r502.read_audit_log().unwrap().last().unwrap().counter;
+        assert_eq!(r502.read_audit_log_since(newest).unwrap().len(), 3);
+
+        // when: the page with the newest record is wiped
+        emulator.state().notepad[1] = [0x00; 32];
+
+        // then: the log still reads, but falls short of the counter the host kept
+        assert_eq!(r502.read_audit_log().unwrap().len(), 2);
+        match r502.read_audit_log_since(newest) {
+            Err(AuditError::Truncated { expected: 2, newest: Some(1) }) => {}
+            other => panic!("Expected AuditError::Truncated, got {:?}", other),
+        };
+
+        // when: the whole log is wiped
+        emulator.state().notepad[0] = [0x00; 32];
+
+        // then: that is caught too
+        match r502.read_audit_log_since(newest) {
+            Err(AuditError::Truncated { expected: 2, newest: None }) => {}
+            other => panic!("Expected AuditError::Truncated, got {:?}", other),
+        };
+    }
+}
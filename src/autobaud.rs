@@ -0,0 +1,225 @@
+use arrayvec::ArrayVec;
+use embedded_hal::blocking::delay::DelayMs;
+
+use crate::commands::Command;
+use crate::driver::R502;
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// Baud rate settings, in units of 9600, for
+/// [`R502::detect_baud`](struct.R502.html#method.detect_baud) to try, most likely first: the
+/// factory default of 57600, then 115200, 19200, 38400 and 9600.
+pub const BAUD_CANDIDATES: [u8; 5] = [6, 12, 2, 4, 1];
+
+/// Most rates `detect_baud` tries in one go, one for each setting the module has.
+pub const MAX_BAUD_ATTEMPTS: usize = 12;
+
+/// A rate `detect_baud` tried, and how it failed.
+#[derive(Debug)]
+pub struct BaudAttempt<TXE, RXE> {
+    /// The baud rate setting, in units of 9600.
+    pub baud_setting: u8,
+
+    /// `Error::Timeout` if nothing came back, or what was wrong with what did.
+    pub error: Error<TXE, RXE>,
+}
+
+/// Every rate `detect_baud` tried, in order.
+pub type BaudAttempts<TXE, RXE> = ArrayVec<BaudAttempt<TXE, RXE>, MAX_BAUD_ATTEMPTS>;
+
+/// Error type for `detect_baud`.
+#[derive(Debug)]
+pub enum DetectBaudError<TXE, RXE, E> {
+    /// A candidate is not a baud rate setting [1-12], or there are more than
+    /// `MAX_BAUD_ATTEMPTS` of them. Nothing was tried.
+    InvalidCandidates,
+
+    /// Switching the host UART to `baud_setting` failed with `error`.
+    Reconfigure { baud_setting: u8, error: E },
+
+    /// No rate got a good reply.
+    NoAnswer(BaudAttempts<TXE, RXE>),
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Finds the baud rate of a module whose settings are unknown, trying each of
+    /// `candidates` in turn, such as `BAUD_CANDIDATES`. Returns the first setting, in units of
+    /// 9600, at which a `HandShake` gets a reply with a good checksum back; the host UART is
+    /// left at that rate.
+    ///
+    /// `reconfigure` switches the host UART to the rate it is given, in bits per second,
+    /// through the transport. Whatever is waiting to be read is thrown away after that, up to
+    /// `MAX_READY_NOISE` bytes, and the reply then has `timeout_ms` to arrive, counted in 1 ms
+    /// steps on `delay`. This needs a transport which returns `WouldBlock` while there is
+    /// nothing to read.
+    ///
+    /// ```ignore
+    /// let setting = r502.detect_baud(&BAUD_CANDIDATES, &mut delay, 100, |serial, bps| {
+    ///     serial.0.set_baud_rate(bps)
+    /// })?;
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// `DetectBaudError::NoAnswer` with every attempt and how it failed if no rate worked,
+    /// and `DetectBaudError::Reconfigure` as soon as `reconfigure` fails.
+    pub fn detect_baud<D, F, E>(
+        &mut self,
+        candidates: &[u8],
+        delay: &mut D,
+        timeout_ms: u32,
+        mut reconfigure: F,
+    ) -> Result<u8, DetectBaudError<T::WriteError, T::ReadError, E>>
+    where
+        D: DelayMs<u16>,
+        F: FnMut(&mut T, u32) -> Result<(), E>,
+    {
+        let valid = candidates.iter().all(|setting| (1..=12).contains(setting));
+        if !valid || candidates.len() > MAX_BAUD_ATTEMPTS {
+            return Err(DetectBaudError::InvalidCandidates);
+        }
+
+        let mut attempts = BaudAttempts::new();
+        for &baud_setting in candidates {
+            reconfigure(self.transport_mut(), baud_setting as u32 * 9600)
+                .map_err(|error| DetectBaudError::Reconfigure { baud_setting, error })?;
+            self.drain_input();
+
+            match self.try_handshake(delay, timeout_ms) {
+                Ok(()) => return Ok(baud_setting),
+                Err(error) => attempts.push(BaudAttempt { baud_setting, error }),
+            }
+        }
+        return Err(DetectBaudError::NoAnswer(attempts));
+    }
+
+    /// Sends a `HandShake` and waits up to `timeout_ms` for any good reply.
+    pub(crate) fn try_handshake<D>(
+        &mut self,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<(), Error<T::WriteError, T::ReadError>>
+    where
+        D: DelayMs<u16>,
+    {
+        self.start_command(Command::HandShake)?;
+        self.await_reply(delay, timeout_ms)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::emulator::{Emulator, EmulatorRx, EmulatorTx, NoDelay};
+    use std::vec;
+    use std::vec::Vec;
+
+    /// A module at `baud_setting` behind a host UART which starts out at 57600 baud.
+    fn module_at(baud_setting: u16) -> (Emulator, R502<(EmulatorTx, EmulatorRx)>) {
+        let emulator = Emulator::new();
+        {
+            let mut state = emulator.state();
+            state.baud_setting = baud_setting;
+            state.link_baud = Some(6);
+            state.would_block = true;
+        }
+        let (tx, rx) = emulator.serial();
+        return (emulator, R502::new(tx, rx, 0xffffffff));
+    }
+
+    #[test]
+    fn test_detect_baud() {
+        // given: a module at 19200 baud, the third candidate
+        let (emulator, mut r502) = module_at(2);
+
+        // when: detecting its rate
+        let mut rates = Vec::new();
+        let result = r502.detect_baud(&BAUD_CANDIDATES, &mut NoDelay, 50, |_, bps| {
+            rates.push(bps);
+            emulator.state().link_baud = Some((bps / 9600) as u16);
+            return Ok::<(), ()>(());
+        });
+
+        // then: it is found on the third attempt, and the UART is left at that rate
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(rates, vec![57600, 115200, 19200]);
+        assert_eq!(emulator.instructions(), vec![0x40]);
+        assert_eq!(r502.send_command(Command::TemplateNum).is_ok(), true);
+    }
+
+    #[test]
+    fn test_detect_baud_no_answer() {
+        // given: a module at 9600 baud, which is not among the candidates
+        let (emulator, mut r502) = module_at(1);
+
+        // when: detecting its rate
+        let result = r502.detect_baud(&[6, 12], &mut NoDelay, 50, |_, bps| {
+            emulator.state().link_baud = Some((bps / 9600) as u16);
+            return Ok::<(), ()>(());
+        });
+
+        // then: every attempt timed out, and the driver is free for the next command
+        let attempts = match result {
+            Err(DetectBaudError::NoAnswer(attempts)) => attempts,
+            other => panic!("Expected DetectBaudError::NoAnswer, got {:?}", other),
+        };
+        let tried: Vec<u8> = attempts.iter().map(|attempt| attempt.baud_setting).collect();
+        assert_eq!(tried, vec![6, 12]);
+        let timeouts = attempts.iter().all(|attempt| matches!(attempt.error, Error::Timeout));
+        assert_eq!(timeouts, true);
+        emulator.state().link_baud = Some(1);
+        assert_eq!(r502.send_command(Command::TemplateNum).is_ok(), true);
+    }
+
+    #[test]
+    fn test_detect_baud_discards_noise() {
+        // given: a module at 57600 baud, with junk waiting on the line
+        let (emulator, mut r502) = module_at(6);
+        emulator.send_raw(&[0x00, 0xef, 0x13, 0xfe]);
+
+        // when: detecting its rate
+        let result = r502.detect_baud(&BAUD_CANDIDATES, &mut NoDelay, 50, |_, _| Ok::<(), ()>(()));
+
+        // then: the junk did not get in the way of the reply
+        assert_eq!(result.unwrap(), 6);
+    }
+
+    #[test]
+    fn test_detect_baud_reconfigure_failure() {
+        // given: a module at 57600 baud, and a UART which cannot be switched
+        let (emulator, mut r502) = module_at(6);
+
+        // when: detecting its rate
+        let result = r502.detect_baud(&BAUD_CANDIDATES, &mut NoDelay, 50, |_, _| Err("busy"));
+
+        // then: it gives up straight away
+        let failed = matches!(
+            result,
+            Err(DetectBaudError::Reconfigure { baud_setting: 6, error: "busy" })
+        );
+        assert_eq!(failed, true);
+        assert_eq!(emulator.instructions().is_empty(), true);
+    }
+
+    #[test]
+    fn test_detect_baud_invalid_candidates() {
+        // given: a module at 57600 baud
+        let (emulator, mut r502) = module_at(6);
+
+        // when: asking for a setting the module does not have, or for too many attempts
+        let reconfigure = |_: &mut (EmulatorTx, EmulatorRx), _| Ok::<(), ()>(());
+        let invalid = r502.detect_baud(&[6, 13], &mut NoDelay, 50, reconfigure);
+        let too_many = r502.detect_baud(&[6; 13], &mut NoDelay, 50, reconfigure);
+
+        // then: nothing is tried
+        assert_eq!(matches!(invalid, Err(DetectBaudError::InvalidCandidates)), true);
+        assert_eq!(matches!(too_many, Err(DetectBaudError::InvalidCandidates)), true);
+        assert_eq!(emulator.instructions().is_empty(), true);
+    }
+}
@@ -0,0 +1,389 @@
+use nb::block;
+
+use crate::codec::{decode_command, decode_reply, DecodeError};
+use crate::commands::{Command, CommandKind};
+use crate::parser::{FrameError, ReplyParser};
+use crate::responses::Reply;
+use crate::transport::{Direction, Transport};
+
+/// What a `Bridge` saw pass through it. Every frame is forwarded as it was, whatever is
+/// reported about it.
+#[derive(Debug)]
+pub enum BridgeEvent<'a> {
+    /// The host sent a command.
+    Command { frame: &'a [u8], command: Command },
+
+    /// The module answered the last command.
+    Reply { frame: &'a [u8], reply: Reply },
+
+    /// A data packet of a template or image transfer went through.
+    Data { direction: Direction, frame: &'a [u8] },
+
+    /// A frame went through which is whole, with a good checksum, but could not be decoded:
+    /// a command the driver does not know, or a reply with a code the command is not known to
+    /// return.
+    Undecoded {
+        direction: Direction,
+        frame: &'a [u8],
+        error: DecodeError,
+    },
+
+    /// The module sent a reply with no command to answer, so it could not be decoded.
+    Unsolicited { frame: &'a [u8] },
+
+    /// Bytes went through which turned out not to be a good frame, for example after noise on
+    /// the line. They were forwarded regardless.
+    Damaged { direction: Direction, error: FrameError },
+}
+
+/// Is shown what passes through a `Bridge`. Implemented for closures taking a `BridgeEvent`.
+pub trait BridgeObserver {
+    /// Something passed through the bridge. The frame is only kept until the next byte.
+    fn on_event(&mut self, event: BridgeEvent<'_>);
+}
+
+impl<F> BridgeObserver for F
+where
+    F: FnMut(BridgeEvent<'_>),
+{
+    fn on_event(&mut self, event: BridgeEvent<'_>) {
+        self(event);
+    }
+}
+
+/// Error type for `Bridge::poll`, saying which side failed.
+#[derive(Debug)]
+pub enum BridgeError<HTXE, HRXE, MTXE, MRXE> {
+    /// Reading from the host failed.
+    HostRead(HRXE),
+
+    /// Writing to the host failed.
+    HostWrite(HTXE),
+
+    /// Reading from the module failed.
+    ModuleRead(MRXE),
+
+    /// Writing to the module failed.
+    ModuleWrite(MTXE),
+}
+
+type PollResult<H, M> = Result<
+    usize,
+    BridgeError<
+        <H as Transport>::WriteError,
+        <H as Transport>::ReadError,
+        <M as Transport>::WriteError,
+        <M as Transport>::ReadError,
+    >,
+>;
+
+/// Sits between a host and a module which talk to each other, forwarding the bytes each way
+/// and showing a `BridgeObserver` what they say, for debugging a product the driver is not
+/// part of. The host is wired to one `Transport`, the module to the other.
+///
+/// Bytes are passed on one at a time, as they arrive and untouched, so the host and module
+/// see each other as they would without the bridge, apart from the delay. Frames are put
+/// together on the way to decode them: commands with `decode_command`, and replies according
+/// to the last command. Bytes which do not make a good frame, such as the `0x55` a module
+/// sends when it is ready, are forwarded all the same.
+///
+/// Putting the frames together takes a frame-sized buffer in each direction (about 2 KiB in
+/// all), and nothing else, so a bridge can run on an MCU wired between the two as well as on
+/// a PC with two serial ports.
+#[derive(Debug)]
+pub struct Bridge<H, M, O> {
+    host: H,
+    module: M,
+    observer: O,
+    from_host: ReplyParser,
+    from_module: ReplyParser,
+    pending: Option<CommandKind>,
+}
+
+impl<H, M, O> Bridge<H, M, O>
+where
+    H: Transport,
+    M: Transport,
+    O: BridgeObserver,
+{
+    /// Bridges `host` and `module`, showing what passes between them to `observer`.
+    pub fn new(host: H, module: M, observer: O) -> Self {
+        return Self {
+            host,
+            module,
+            observer,
+            from_host: ReplyParser::new(),
+            from_module: ReplyParser::new(),
+            pending: None,
+        };
+    }
+
+    /// The observer.
+    pub fn observer(&self) -> &O {
+        return &self.observer;
+    }
+
+    /// The observer, for example to change how much it logs.
+    pub fn observer_mut(&mut self) -> &mut O {
+        return &mut self.observer;
+    }
+
+    /// Gives back the host and module transports, and the observer.
+    pub fn release(self) -> (H, M, O) {
+        return (self.host, self.module, self.observer);
+    }
+
+    /// Forwards every byte waiting on either side to the other, and returns how many there
+    /// were. Call it in a loop, or whenever either side has something to read. Writes block
+    /// until the other side takes the byte; reads never block.
+    pub fn poll(&mut self) -> PollResult<H, M> {
+        let mut forwarded = 0;
+        loop {
+            let before = forwarded;
+            match self.host.read_byte() {
+                Ok(byte) => {
+                    block!(self.module.write_byte(byte)).map_err(BridgeError::ModuleWrite)?;
+                    self.took_from_host(byte);
+                    forwarded += 1;
+                }
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(error)) => return Err(BridgeError::HostRead(error)),
+            }
+            match self.module.read_byte() {
+                Ok(byte) => {
+                    block!(self.host.write_byte(byte)).map_err(BridgeError::HostWrite)?;
+                    self.took_from_module(byte);
+                    forwarded += 1;
+                }
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(error)) => return Err(BridgeError::ModuleRead(error)),
+            }
+            if forwarded == before {
+                return Ok(forwarded);
+            }
+        }
+    }
+
+    fn took_from_host(&mut self, byte: u8) {
+        let direction = Direction::Tx;
+        let frame = match self.from_host.push(byte) {
+            Some(Ok(frame)) => frame,
+            Some(Err(error)) => {
+                self.observer.on_event(BridgeEvent::Damaged { direction, error });
+                return;
+            }
+            None => return,
+        };
+        let event = match frame.packet_id() {
+            0x01 => match decode_command(frame.as_bytes()) {
+                Ok(command) => {
+                    self.pending = Some(command.kind());
+                    BridgeEvent::Command { frame: frame.as_bytes(), command }
+                }
+                Err(error) => {
+                    self.pending = None;
+                    BridgeEvent::Undecoded { direction, frame: frame.as_bytes(), error }
+                }
+            },
+            _ => BridgeEvent::Data { direction, frame: frame.as_bytes() },
+        };
+        self.observer.on_event(event);
+    }
+
+    fn took_from_module(&mut self, byte: u8) {
+        let direction = Direction::Rx;
+        let frame = match self.from_module.push(byte) {
+            Some(Ok(frame)) => frame,
+            Some(Err(error)) => {
+                self.observer.on_event(BridgeEvent::Damaged { direction, error });
+                return;
+            }
+            None => return,
+        };
+        let event = match (frame.packet_id(), self.pending.take()) {
+            (0x07, Some(kind)) => match decode_reply(kind, frame.as_bytes()) {
+                Ok(reply) => BridgeEvent::Reply { frame: frame.as_bytes(), reply },
+                Err(error) => {
+                    BridgeEvent::Undecoded { direction, frame: frame.as_bytes(), error }
+                }
+            },
+            (0x07, None) => BridgeEvent::Unsolicited { frame: frame.as_bytes() },
+            (_, pending) => {
+                self.pending = pending;
+                BridgeEvent::Data { direction, frame: frame.as_bytes() }
+            }
+        };
+        self.observer.on_event(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::codec::encode_command;
+    use crate::consts::{DEFAULT_PACKET_SIZE, MAX_COMMAND_LENGTH, R502_TEMPLATE_LEN};
+    use crate::emulator::{Emulator, EmulatorRx, EmulatorTx};
+    use crate::responses::TemplateNumStatus;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::format;
+    use std::rc::Rc;
+    use std::string::String;
+    use std::vec::Vec;
+
+    /// The host side of the bridge, scripted with the bytes the host sends, and keeping what
+    /// the bridge sends back.
+    #[derive(Clone, Default)]
+    struct ScriptedHost {
+        sends: Rc<RefCell<VecDeque<u8>>>,
+        received: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl ScriptedHost {
+        fn send(&self, bytes: &[u8]) {
+            self.sends.borrow_mut().extend(bytes.iter());
+        }
+
+        fn send_command(&self, command: Command) {
+            let mut frame = [0u8; MAX_COMMAND_LENGTH];
+            let length = encode_command(&command, 0xffffffff, &mut frame).unwrap();
+            self.send(&frame[..length]);
+        }
+    }
+
+    impl Transport for ScriptedHost {
+        type WriteError = ();
+        type ReadError = ();
+
+        fn write_byte(&mut self, byte: u8) -> nb::Result<(), ()> {
+            self.received.borrow_mut().push(byte);
+            return Ok(());
+        }
+
+        fn flush(&mut self) -> nb::Result<(), ()> {
+            return Ok(());
+        }
+
+        fn read_byte(&mut self) -> nb::Result<u8, ()> {
+            return self.sends.borrow_mut().pop_front().ok_or(nb::Error::WouldBlock);
+        }
+    }
+
+    /// An emulated module to bridge to, which does not time out when it has nothing to say.
+    fn module(emulator: &Emulator) -> (EmulatorTx, EmulatorRx) {
+        emulator.state().would_block = true;
+        return emulator.serial();
+    }
+
+    fn describe(event: &BridgeEvent<'_>) -> String {
+        return match event {
+            BridgeEvent::Command { command, .. } => format!("command {:?}", command.kind()),
+            BridgeEvent::Reply { reply, .. } => format!("reply {:?}", reply),
+            BridgeEvent::Data { direction, .. } => format!("data {:?}", direction),
+            BridgeEvent::Undecoded { direction, error, .. } => {
+                format!("undecoded {:?} {:?}", direction, error)
+            }
+            BridgeEvent::Unsolicited { .. } => String::from("unsolicited"),
+            BridgeEvent::Damaged { direction, error } => {
+                format!("damaged {:?} {:?}", direction, error)
+            }
+        };
+    }
+
+    #[test]
+    fn test_bridge_decodes_both_ways() {
+        // given: a module with two templates, and a host which asks how many there are
+        let emulator = Emulator::new();
+        emulator.enroll(0, 7);
+        emulator.enroll(3, 8);
+        let host = ScriptedHost::default();
+        host.send_command(Command::TemplateNum);
+        let mut seen = Vec::new();
+
+        // when: bridging them
+        let mut bridge = Bridge::new(host.clone(), module(&emulator), |event: BridgeEvent<'_>| {
+            seen.push(describe(&event));
+        });
+        let forwarded = bridge.poll().unwrap();
+        drop(bridge);
+
+        // then: the command and the decoded reply were shown
+        assert_eq!(seen.len(), 2);
+        assert_eq!(seen[0], "command TemplateNum");
+        assert_eq!(seen[1].starts_with("reply TemplateNum"), true);
+
+        // and: the host got the reply, byte for byte
+        let received = host.received.borrow();
+        let reply = decode_reply(CommandKind::TemplateNum, &received).unwrap();
+        match reply {
+            Reply::TemplateNum(result) => {
+                assert_eq!(matches!(result.confirmation_code, TemplateNumStatus::Success), true);
+                assert_eq!(result.template_num, 2);
+            }
+            other => panic!("Expected Reply::TemplateNum, got {:?}", other),
+        };
+        assert_eq!(forwarded, 12 + received.len());
+        assert_eq!(emulator.instructions(), [0x1d]);
+    }
+
+    #[test]
+    fn test_bridge_forwards_data_packets() {
+        // given: a host which uploads the template in slot 0
+        let emulator = Emulator::new();
+        emulator.enroll(0, 7);
+        let host = ScriptedHost::default();
+        host.send_command(Command::LoadChar { buffer: 1, index: 0 });
+        host.send_command(Command::UpChar { buffer: 1 });
+        let mut seen = Vec::new();
+
+        // when: bridging it to the module
+        let mut bridge = Bridge::new(host.clone(), module(&emulator), |event: BridgeEvent<'_>| {
+            seen.push(describe(&event));
+        });
+        bridge.poll().unwrap();
+        drop(bridge);
+
+        // then: both replies were decoded, and the template came through as data packets
+        assert_eq!(seen[0], "command LoadChar");
+        assert_eq!(seen[1].starts_with("reply LoadChar"), true);
+        assert_eq!(seen[2], "command UpChar");
+        assert_eq!(seen[3].starts_with("reply UpChar"), true);
+        assert_eq!(seen[4..].iter().all(|event| event == "data Rx"), true);
+        assert_eq!(seen.len(), 4 + R502_TEMPLATE_LEN / DEFAULT_PACKET_SIZE.bytes() as usize);
+    }
+
+    #[test]
+    fn test_bridge_flags_what_it_cannot_decode() {
+        // given: a host which sends a command nobody knows, a damaged command, one which is
+        // fine, and then noise
+        let emulator = Emulator::new();
+        let host = ScriptedHost::default();
+        host.send(&[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x99, 0x00, 0x9d]);
+        host.send(&[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x1d, 0x00, 0x22]);
+        host.send_command(Command::HandShake);
+        host.send(&[0x55, 0x00]);
+        let mut seen = Vec::new();
+
+        // when: bridging it to the module
+        let mut bridge = Bridge::new(host.clone(), module(&emulator), |event: BridgeEvent<'_>| {
+            seen.push(describe(&event));
+        });
+        let forwarded = bridge.poll().unwrap();
+        drop(bridge);
+
+        // then: what could not be decoded was flagged, and the good command still decoded
+        assert_eq!(seen.contains(&String::from("undecoded Tx UnknownInstruction(153)")), true);
+        assert_eq!(seen.contains(&String::from("damaged Tx BadChecksum")), true);
+        assert_eq!(seen.contains(&String::from("unsolicited")), true);
+        assert_eq!(seen[seen.len() - 2], "command HandShake");
+        assert_eq!(seen[seen.len() - 1].starts_with("reply HandShake"), true);
+
+        // and: everything was forwarded, noise and all, so the module saw both good frames, and
+        // answered all three
+        assert_eq!(forwarded, 38 + host.received.borrow().len());
+        assert_eq!(emulator.instructions(), [0x99, 0x40]);
+        assert_eq!(seen.iter().filter(|event| *event == "unsolicited").count(), 2);
+    }
+}
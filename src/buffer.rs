@@ -0,0 +1,92 @@
+use core::fmt;
+use core::ops::Deref;
+
+use crate::utils::CommandWriter;
+
+/// A byte buffer holding up to `N` bytes, for packets being put together or received. Unlike
+/// `ArrayVec`, writing past the end is not an error but is counted, so the length a packet
+/// would have needed can be reported, and it can be made in a `const fn`.
+pub(crate) struct ByteBuffer<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+    /// Bytes written which did not fit.
+    overflow: usize,
+}
+
+impl<const N: usize> ByteBuffer<N> {
+    pub(crate) const fn new() -> Self {
+        return Self { bytes: [0u8; N], len: 0, overflow: 0 };
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.len = 0;
+        self.overflow = 0;
+    }
+
+    /// Appends `byte`, if there is room for it.
+    pub(crate) fn push(&mut self, byte: u8) {
+        self.write_cmd_bytes(&[byte]);
+    }
+
+    /// Appends `bytes`, failing with the length needed if they do not fit.
+    #[cfg(test)]
+    pub(crate) fn try_extend_from_slice(&mut self, bytes: &[u8]) -> Result<(), usize> {
+        self.write_cmd_bytes(bytes);
+        return if self.overflow > 0 { Err(self.needed()) } else { Ok(()) };
+    }
+
+    /// How long the contents would be if everything written had fit.
+    pub(crate) fn needed(&self) -> usize {
+        return self.len + self.overflow;
+    }
+}
+
+impl<const N: usize> Deref for ByteBuffer<N> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        return &self.bytes[..self.len];
+    }
+}
+
+impl<const N: usize> CommandWriter for ByteBuffer<N> {
+    fn write_cmd_bytes(&mut self, bytes: &[u8]) {
+        if self.overflow > 0 || bytes.len() > N - self.len {
+            self.overflow += bytes.len();
+            return;
+        }
+        self.bytes[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+}
+
+impl<const N: usize> fmt::Debug for ByteBuffer<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.debug_list().entries(self.iter()).finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overflow() {
+        // given: a buffer with room for four bytes
+        let mut buffer = ByteBuffer::<4>::new();
+
+        // when: writing six
+        buffer.write_cmd_bytes(&[1, 2, 3]);
+        buffer.write_cmd_bytes(&[4, 5]);
+        buffer.push(6);
+
+        // then: what fits is kept, and the rest is counted
+        assert_eq!(&buffer[..], &[1, 2, 3]);
+        assert_eq!(buffer.needed(), 6);
+
+        // and: clearing makes room again
+        buffer.clear();
+        buffer.push(7);
+        assert_eq!((&buffer[..], buffer.needed()), (&[7][..], 1));
+    }
+}
@@ -0,0 +1,350 @@
+use arrayvec::ArrayVec;
+use embedded_hal::serial::{Read, Write};
+
+use crate::commands::Command;
+use crate::driver::R502;
+use crate::responses::*;
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// Most modules one `R502Bus` keeps track of.
+pub const MAX_BUS_DEVICES: usize = 8;
+
+/// One of the modules on an `R502Bus`, as returned by
+/// [`R502Bus::add_device`](struct.R502Bus.html#method.add_device). It only means something
+/// to the bus which handed it out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceHandle(usize);
+
+/// Error type for `R502Bus`.
+#[derive(Debug)]
+pub enum BusError<TXE, RXE> {
+    /// Communication with the module failed.
+    Comms(Error<TXE, RXE>),
+
+    /// The bus already has `MAX_BUS_DEVICES` modules.
+    Full,
+
+    /// Another module on the bus already has this address.
+    DuplicateAddress(u32),
+
+    /// The handle was not handed out by this bus.
+    UnknownDevice(DeviceHandle),
+
+    /// The module refused its password, so the command was not sent.
+    WrongPassword { address: u32 },
+
+    /// The reply came from `address` rather than the module the command was sent to: the
+    /// module which has that address on this bus, if any. The reply is passed on as it is.
+    Misaddressed {
+        address: u32,
+        device: Option<DeviceHandle>,
+        reply: Reply,
+    },
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for BusError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+/// What the bus knows of one module.
+#[derive(Debug, Clone, Copy)]
+struct BusDevice {
+    address: u32,
+    password: u32,
+    verified: bool,
+}
+
+/// Several modules sharing one serial line, told apart by their addresses, as the packet
+/// format allows for. The bus owns the transport and talks to one module at a time, so there
+/// is never more than one command in flight; each module gets a handle to send commands
+/// through.
+///
+/// Each module's password is verified with `VfyPwd` before the first command sent to it, and
+/// again after it has been reset. Every reply is checked to come from the module the command
+/// went to, see `BusError::Misaddressed`.
+///
+/// ```ignore
+/// let mut bus = R502Bus::new(tx, rx);
+/// let door = bus.add_device(0x00000001, 0)?;
+/// let gate = bus.add_device(0x00000002, 0x1234)?;
+/// let reply = bus.send_command(gate, Command::GenImg)?;
+/// ```
+#[derive(Debug)]
+pub struct R502Bus<T> {
+    pub(crate) r502: R502<T>,
+    devices: ArrayVec<BusDevice, MAX_BUS_DEVICES>,
+}
+
+impl<TX, RX> R502Bus<(TX, RX)>
+where
+    TX: Write<u8>,
+    RX: Read<u8>,
+{
+    /// Creates a bus on the serial line `tx` and `rx`, with no modules on it yet.
+    pub fn new(tx: TX, rx: RX) -> Self {
+        return Self::with_transport((tx, rx));
+    }
+}
+
+impl<T> R502Bus<T>
+where
+    T: Transport,
+{
+    /// Creates a bus on `transport`, with no modules on it yet.
+    pub fn with_transport(transport: T) -> Self {
+        return Self { r502: R502::with_transport(transport, 0xffffffff), devices: ArrayVec::new() };
+    }
+
+    /// Adds the module at `address`, which takes `password`, and returns its handle. Nothing
+    /// is sent yet.
+    pub fn add_device(
+        &mut self,
+        address: u32,
+        password: u32,
+    ) -> Result<DeviceHandle, BusError<T::WriteError, T::ReadError>> {
+        if self.find(address).is_some() {
+            return Err(BusError::DuplicateAddress(address));
+        }
+        let device = BusDevice { address, password, verified: false };
+        if self.devices.try_push(device).is_err() {
+            return Err(BusError::Full);
+        }
+        return Ok(DeviceHandle(self.devices.len() - 1));
+    }
+
+    /// The address of the module behind `device`.
+    pub fn address(&self, device: DeviceHandle) -> Option<u32> {
+        return self.devices.get(device.0).map(|device| device.address);
+    }
+
+    /// The handle of the module at `address`, if it is on the bus.
+    pub fn find(&self, address: u32) -> Option<DeviceHandle> {
+        return self.devices.iter().position(|device| device.address == address).map(DeviceHandle);
+    }
+
+    /// Sends `cmd` to the module behind `device` and blocks waiting for its reply, verifying
+    /// the module's password first if that has not been done yet.
+    ///
+    /// # Errors
+    ///
+    /// `BusError::Comms` as for [`R502::send_command`](struct.R502.html#method.send_command),
+    /// `BusError::WrongPassword` if the module did not take its password, and
+    /// `BusError::Misaddressed` if the reply came from another address.
+    pub fn send_command(
+        &mut self,
+        device: DeviceHandle,
+        cmd: Command,
+    ) -> Result<Reply, BusError<T::WriteError, T::ReadError>> {
+        let BusDevice { address, password, verified } = match self.devices.get(device.0) {
+            Some(found) => *found,
+            None => return Err(BusError::UnknownDevice(device)),
+        };
+        if !verified {
+            match self.transact(address, Command::VfyPwd { password })? {
+                Reply::VfyPwd(VfyPwdResult {
+                    confirmation_code: PasswordVerificationState::Correct,
+                    ..
+                }) => {}
+                Reply::VfyPwd(_) => return Err(BusError::WrongPassword { address }),
+                _ => return Err(Error::RecvWrongReplyType.into()),
+            }
+            self.devices[device.0].verified = true;
+        }
+
+        let reply = self.transact(address, cmd)?;
+        match &reply {
+            Reply::VfyPwd(result) => {
+                let verified = &mut self.devices[device.0].verified;
+                *verified = matches!(result.confirmation_code, PasswordVerificationState::Correct);
+            }
+            Reply::SoftRst(SoftRstResult { confirmation_code: SoftRstStatus::Success, .. }) => {
+                self.devices[device.0].verified = false;
+            }
+            _ => {}
+        }
+        return Ok(reply);
+    }
+
+    /// Gives back the transport.
+    pub fn release(self) -> T {
+        return self.r502.transport;
+    }
+
+    /// Sends `cmd` to `address`, and checks that the reply came from there.
+    fn transact(
+        &mut self,
+        address: u32,
+        cmd: Command,
+    ) -> Result<Reply, BusError<T::WriteError, T::ReadError>> {
+        self.r502.set_address(address);
+        let view = self.r502.send_command_view(cmd)?;
+        let from = view.address();
+        let reply = view.decode().map_err(Error::from)?;
+        if from != address {
+            return Err(BusError::Misaddressed { address: from, device: self.find(from), reply });
+        }
+        return Ok(reply);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::emulator::{Emulator, EmulatorError, EmulatorRx, EmulatorTx};
+    use std::vec;
+    use std::vec::Vec;
+
+    /// One serial line with several emulated modules on it: every module hears every byte,
+    /// and the host reads whatever any of them sends.
+    struct SharedLine(Vec<(EmulatorTx, EmulatorRx)>);
+
+    impl Transport for SharedLine {
+        type WriteError = EmulatorError;
+        type ReadError = EmulatorError;
+
+        fn write_byte(&mut self, byte: u8) -> nb::Result<(), Self::WriteError> {
+            for serial in self.0.iter_mut() {
+                serial.write_byte(byte)?;
+            }
+            return Ok(());
+        }
+
+        fn flush(&mut self) -> nb::Result<(), Self::WriteError> {
+            return Ok(());
+        }
+
+        fn read_byte(&mut self) -> nb::Result<u8, Self::ReadError> {
+            for serial in self.0.iter_mut() {
+                if let Ok(byte) = serial.read_byte() {
+                    return Ok(byte);
+                }
+            }
+            return Err(nb::Error::Other(EmulatorError::Timeout));
+        }
+    }
+
+    /// Two modules, at addresses 1 and 2, with passwords 0x11 and 0x22, on one line.
+    fn two_modules() -> (Emulator, Emulator, R502Bus<SharedLine>) {
+        let first = Emulator::new();
+        let second = Emulator::new();
+        first.state().address = 1;
+        first.state().password = 0x11;
+        second.state().address = 2;
+        second.state().password = 0x22;
+        let line = SharedLine(vec![first.serial(), second.serial()]);
+        return (first, second, R502Bus::with_transport(line));
+    }
+
+    #[test]
+    fn test_bus_routes_commands() {
+        // given: two modules on one line, with a template stored on the second
+        let (first, second, mut bus) = two_modules();
+        second.enroll(0, 7);
+        let door = bus.add_device(1, 0x11).unwrap();
+        let gate = bus.add_device(2, 0x22).unwrap();
+
+        // when: asking each for its template count
+        let door_count = bus.send_command(door, Command::TemplateNum).unwrap();
+        let gate_count = bus.send_command(gate, Command::TemplateNum).unwrap();
+
+        // then: each module verified its password, then answered for itself
+        assert_eq!(matches!(door_count, Reply::TemplateNum(ref r) if r.template_num == 0), true);
+        assert_eq!(matches!(gate_count, Reply::TemplateNum(ref r) if r.template_num == 1), true);
+        assert_eq!(first.instructions(), vec![0x13, 0x1d]);
+        assert_eq!(second.instructions(), vec![0x13, 0x1d]);
+        assert_eq!(first.state().authenticated, true);
+        assert_eq!(second.state().authenticated, true);
+
+        // when: talking to the first again
+        bus.send_command(door, Command::TemplateNum).unwrap();
+
+        // then: its password is not verified again
+        assert_eq!(first.instructions(), vec![0x13, 0x1d, 0x1d]);
+    }
+
+    #[test]
+    fn test_bus_wrong_password() {
+        // given: a module whose password the bus has wrong
+        let (first, _second, mut bus) = two_modules();
+        let door = bus.add_device(1, 0x99).unwrap();
+
+        // when: sending it a command
+        let result = bus.send_command(door, Command::GenImg);
+
+        // then: the command is not sent
+        assert_eq!(matches!(result, Err(BusError::WrongPassword { address: 1 })), true);
+        assert_eq!(first.instructions(), vec![0x13]);
+    }
+
+    #[test]
+    fn test_bus_reports_misaddressed_reply() {
+        // given: two modules on the bus
+        let (first, _second, mut bus) = two_modules();
+        let door = bus.add_device(1, 0x11).unwrap();
+        let gate = bus.add_device(2, 0x22).unwrap();
+
+        // when: moving the first module onto the second's address, so that it replies from
+        // there
+        let result = bus.send_command(door, Command::SetAdder { address: 2 });
+
+        // then: the reply is handed over, saying which module it came from
+        match result {
+            Err(BusError::Misaddressed { address: 2, device, reply: Reply::SetAdder(_) }) => {
+                assert_eq!(device, Some(gate));
+            }
+            other => panic!("Expected BusError::Misaddressed, got {:?}", other),
+        }
+        assert_eq!(first.state().address, 2);
+    }
+
+    #[test]
+    fn test_bus_reverifies_after_reset() {
+        // given: a module on the bus which has been talked to, and says nothing on restart
+        let (first, _second, mut bus) = two_modules();
+        first.state().boot_output.clear();
+        let door = bus.add_device(1, 0x11).unwrap();
+        bus.send_command(door, Command::TemplateNum).unwrap();
+
+        // when: resetting it, and talking to it again
+        bus.send_command(door, Command::SoftRst).unwrap();
+        bus.send_command(door, Command::TemplateNum).unwrap();
+
+        // then: its password was verified again
+        assert_eq!(first.instructions(), vec![0x13, 0x1d, 0x3d, 0x13, 0x1d]);
+    }
+
+    #[test]
+    fn test_bus_devices() {
+        // given: a bus with one module
+        let (_first, _second, mut bus) = two_modules();
+        let door = bus.add_device(1, 0x11).unwrap();
+
+        // then: it can be found by address, and no other module can take that address
+        assert_eq!(bus.find(1), Some(door));
+        assert_eq!(bus.address(door), Some(1));
+        assert_eq!(bus.find(2), None);
+        assert_eq!(matches!(bus.add_device(1, 0), Err(BusError::DuplicateAddress(1))), true);
+
+        // and: handles from elsewhere are refused
+        let stranger = DeviceHandle(5);
+        let result = bus.send_command(stranger, Command::TemplateNum);
+        assert_eq!(matches!(result, Err(BusError::UnknownDevice(DeviceHandle(5)))), true);
+    }
+
+    #[test]
+    fn test_bus_full() {
+        // given: a bus with as many modules as it holds
+        let (_first, _second, mut bus) = two_modules();
+        for address in 0..MAX_BUS_DEVICES as u32 {
+            bus.add_device(address, 0).unwrap();
+        }
+
+        // then: no more can be added
+        assert_eq!(matches!(bus.add_device(100, 0), Err(BusError::Full)), true);
+    }
+}
@@ -0,0 +1,28 @@
+/// Lets the application abort a long-running helper, for example on a button press, an RTOS
+/// notification or a watchdog budget running out. Helpers poll it between protocol steps, so
+/// it should return quickly.
+///
+/// Any `FnMut() -> bool` closure is a `CancelToken`.
+pub trait CancelToken {
+    /// True if the helper should stop as soon as it can.
+    fn is_cancelled(&mut self) -> bool;
+}
+
+impl<F> CancelToken for F
+where
+    F: FnMut() -> bool,
+{
+    fn is_cancelled(&mut self) -> bool {
+        return self();
+    }
+}
+
+/// A `CancelToken` which never cancels, used by the helpers that do not take one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NeverCancel;
+
+impl CancelToken for NeverCancel {
+    fn is_cancelled(&mut self) -> bool {
+        return false;
+    }
+}
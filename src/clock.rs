@@ -0,0 +1,18 @@
+/// A monotonic millisecond time source, such as a SysTick counter or an RTOS tick count,
+/// for the helpers which need to know how much time has passed. It may wrap around; the
+/// helpers only ever look at differences between readings.
+///
+/// Any `FnMut() -> u32` closure is a `Clock`.
+pub trait Clock {
+    /// Milliseconds since some fixed point in the past.
+    fn now_ms(&mut self) -> u32;
+}
+
+impl<F> Clock for F
+where
+    F: FnMut() -> u32,
+{
+    fn now_ms(&mut self) -> u32 {
+        return self();
+    }
+}
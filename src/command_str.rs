@@ -0,0 +1,470 @@
+use core::convert::TryFrom;
+use core::fmt;
+use core::str::{FromStr, SplitAsciiWhitespace};
+
+use crate::commands::{Command, CommandKind};
+#[cfg(feature = "cmd-notepad")]
+use crate::consts::NOTEPAD_PAGE_SIZE;
+
+/// Every kind of command with its name, and the names of its arguments in the order they are
+/// given. The names are the datasheet's, in lower case, and the arguments are named after the
+/// fields of `Command`.
+const COMMAND_TABLE: &[(CommandKind, &str, &[&str])] = &[
+    (CommandKind::ReadSysPara, "readsyspara", &[]),
+    (CommandKind::VfyPwd, "vfypwd", &["password"]),
+    (CommandKind::GenImg, "genimg", &[]),
+    (CommandKind::Img2Tz, "img2tz", &["buffer"]),
+    (CommandKind::Search, "search", &["buffer", "start_index", "end_index"]),
+    (CommandKind::LoadChar, "loadchar", &["buffer", "index"]),
+    (CommandKind::Match, "match", &[]),
+    (CommandKind::TemplateNum, "templatenum", &[]),
+    (CommandKind::ReadIndexTable, "readindextable", &["page"]),
+    #[cfg(feature = "cmd-enroll")]
+    (CommandKind::RegModel, "regmodel", &[]),
+    #[cfg(feature = "cmd-enroll")]
+    (CommandKind::Store, "store", &["buffer", "index"]),
+    #[cfg(feature = "cmd-transfer")]
+    (CommandKind::UpChar, "upchar", &["buffer"]),
+    #[cfg(feature = "cmd-transfer")]
+    (CommandKind::DownChar, "downchar", &["buffer"]),
+    #[cfg(feature = "cmd-transfer")]
+    (CommandKind::DownImage, "downimage", &[]),
+    (CommandKind::SetSysPara, "setsyspara", &["parameter", "value"]),
+    (CommandKind::SetPwd, "setpwd", &["password"]),
+    (CommandKind::SetAdder, "setadder", &["address"]),
+    (CommandKind::GetChipSN, "getchipsn", &[]),
+    (CommandKind::GetRandomCode, "getrandomcode", &[]),
+    #[cfg(feature = "cmd-notepad")]
+    (CommandKind::WriteNotepad, "writenotepad", &["page", "data"]),
+    #[cfg(feature = "cmd-notepad")]
+    (CommandKind::ReadNotepad, "readnotepad", &["page"]),
+    (CommandKind::GetFwVer, "getfwver", &[]),
+    (CommandKind::GetAlgVer, "getalgver", &[]),
+    (CommandKind::HandShake, "handshake", &[]),
+    (CommandKind::CheckSensor, "checksensor", &[]),
+    (CommandKind::SoftRst, "softrst", &[]),
+    (CommandKind::Sleep, "sleep", &[]),
+    (CommandKind::PortControl, "portcontrol", &["enable"]),
+    #[cfg(feature = "cmd-led")]
+    (CommandKind::AuraLedConfig, "auraledconfig", &["control", "speed", "color", "times"]),
+    (CommandKind::DeletChar, "deletchar", &["start_index", "num_to_delete"]),
+    (CommandKind::Empty, "empty", &[]),
+];
+
+/// Why a line could not be parsed as a `Command`. The arguments are named as in
+/// `CommandKind::arguments`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandParseError {
+    /// The line is empty, or only whitespace.
+    Empty,
+
+    /// The first word is not the name of a command.
+    UnknownCommand,
+
+    /// The line ends before this argument.
+    MissingArgument { argument: &'static str },
+
+    /// There are words left after the last argument.
+    TooManyArguments,
+
+    /// This argument is not a number, a flag or hex data, whichever it should be.
+    BadArgument { argument: &'static str },
+
+    /// This argument is a number, but too large for its field.
+    OutOfRange { argument: &'static str },
+}
+
+/// A message for the user of a console, such as ``missing argument `index` ``.
+impl fmt::Display for CommandParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Self::Empty => f.write_str("no command given"),
+            Self::UnknownCommand => f.write_str("unknown command"),
+            Self::MissingArgument { argument } => write!(f, "missing argument `{}`", argument),
+            Self::TooManyArguments => f.write_str("too many arguments"),
+            Self::BadArgument { argument } => write!(f, "`{}` is not valid", argument),
+            Self::OutOfRange { argument } => write!(f, "`{}` is out of range", argument),
+        };
+    }
+}
+
+impl CommandKind {
+    /// The name of the command, as `Command::from_str` takes it: the datasheet's, in lower
+    /// case, such as `vfypwd` or `readindextable`. Names do not change between releases.
+    pub fn name(self) -> &'static str {
+        return entry(self).1;
+    }
+
+    /// The names of the command's arguments, in the order `Command::from_str` takes them, for
+    /// help text. They are the names of the fields of the `Command`.
+    pub fn arguments(self) -> &'static [&'static str] {
+        return entry(self).2;
+    }
+
+    /// The kind of command called `name`, ignoring case, or `None` if there is none.
+    pub fn from_name(name: &str) -> Option<Self> {
+        return COMMAND_TABLE
+            .iter()
+            .find(|(_, known, _)| known.eq_ignore_ascii_case(name))
+            .map(|(kind, _, _)| *kind);
+    }
+}
+
+/// The row of `COMMAND_TABLE` for `kind`. Every kind has one.
+fn entry(kind: CommandKind) -> &'static (CommandKind, &'static str, &'static [&'static str]) {
+    return COMMAND_TABLE.iter().find(|(known, _, _)| *known == kind).unwrap();
+}
+
+/// Parses a line such as `search 1 0 200` or `vfypwd 0x00000000`, for consoles and other
+/// tools driven by text: a command name, as `CommandKind::name` gives it, then its arguments,
+/// as `CommandKind::arguments` lists them, separated by whitespace.
+///
+/// * Numbers are decimal, or hexadecimal with a `0x` prefix, and must fit their field.
+/// * `enable` of `portcontrol` is `on`, `off`, `true`, `false`, `1` or `0`.
+/// * `data` of `writenotepad` is up to 32 bytes as hex digits, two to a byte with an optional
+///   `0x` prefix, padded with zeroes to the whole page.
+///
+/// Names and `0x` prefixes are not case sensitive. The command is only parsed, not checked
+/// any further: a `buffer` the module does not have is still refused when the command is sent.
+///
+/// ```
+/// use hzgrow_r502::Command;
+///
+/// let command: Command = "search 1 0 0xffff".parse().unwrap();
+/// match command {
+///     Command::Search { buffer: 1, start_index: 0, end_index: 0xffff } => {}
+///     other => panic!("Expected Command::Search, got {:?}", other),
+/// }
+/// ```
+impl FromStr for Command {
+    type Err = CommandParseError;
+
+    fn from_str(line: &str) -> Result<Self, CommandParseError> {
+        let mut words = line.split_ascii_whitespace();
+        let name = words.next().ok_or(CommandParseError::Empty)?;
+        let kind = CommandKind::from_name(name).ok_or(CommandParseError::UnknownCommand)?;
+        let mut arguments = Arguments { names: kind.arguments().iter(), words };
+
+        let command = match kind {
+            CommandKind::ReadSysPara => Command::ReadSysPara,
+            CommandKind::VfyPwd => Command::VfyPwd { password: arguments.number()? },
+            CommandKind::GenImg => Command::GenImg,
+            CommandKind::Img2Tz => Command::Img2Tz { buffer: arguments.number()? },
+            CommandKind::Search => Command::Search {
+                buffer: arguments.number()?,
+                start_index: arguments.number()?,
+                end_index: arguments.number()?,
+            },
+            CommandKind::LoadChar => Command::LoadChar {
+                buffer: arguments.number()?,
+                index: arguments.number()?,
+            },
+            CommandKind::Match => Command::Match,
+            CommandKind::TemplateNum => Command::TemplateNum,
+            CommandKind::ReadIndexTable => Command::ReadIndexTable { page: arguments.number()? },
+            #[cfg(feature = "cmd-enroll")]
+            CommandKind::RegModel => Command::RegModel,
+            #[cfg(feature = "cmd-enroll")]
+            CommandKind::Store => Command::Store {
+                buffer: arguments.number()?,
+                index: arguments.number()?,
+            },
+            #[cfg(feature = "cmd-transfer")]
+            CommandKind::UpChar => Command::UpChar { buffer: arguments.number()? },
+            #[cfg(feature = "cmd-transfer")]
+            CommandKind::DownChar => Command::DownChar { buffer: arguments.number()? },
+            #[cfg(feature = "cmd-transfer")]
+            CommandKind::DownImage => Command::DownImage,
+            CommandKind::SetSysPara => Command::SetSysPara {
+                parameter: arguments.number()?,
+                value: arguments.number()?,
+            },
+            CommandKind::SetPwd => Command::SetPwd { password: arguments.number()? },
+            CommandKind::SetAdder => Command::SetAdder { address: arguments.number()? },
+            CommandKind::GetChipSN => Command::GetChipSN,
+            CommandKind::GetRandomCode => Command::GetRandomCode,
+            #[cfg(feature = "cmd-notepad")]
+            CommandKind::WriteNotepad => Command::WriteNotepad {
+                page: arguments.number()?,
+                data: arguments.data()?,
+            },
+            #[cfg(feature = "cmd-notepad")]
+            CommandKind::ReadNotepad => Command::ReadNotepad { page: arguments.number()? },
+            CommandKind::GetFwVer => Command::GetFwVer,
+            CommandKind::GetAlgVer => Command::GetAlgVer,
+            CommandKind::HandShake => Command::HandShake,
+            CommandKind::CheckSensor => Command::CheckSensor,
+            CommandKind::SoftRst => Command::SoftRst,
+            CommandKind::Sleep => Command::Sleep,
+            CommandKind::PortControl => Command::PortControl { enable: arguments.flag()? },
+            #[cfg(feature = "cmd-led")]
+            CommandKind::AuraLedConfig => Command::AuraLedConfig {
+                control: arguments.number()?,
+                speed: arguments.number()?,
+                color: arguments.number()?,
+                times: arguments.number()?,
+            },
+            CommandKind::DeletChar => Command::DeletChar {
+                start_index: arguments.number()?,
+                num_to_delete: arguments.number()?,
+            },
+            CommandKind::Empty => Command::Empty,
+        };
+
+        if arguments.words.next().is_some() {
+            return Err(CommandParseError::TooManyArguments);
+        }
+        return Ok(command);
+    }
+}
+
+/// The words after the command name, taken in turn along with the names of the arguments
+/// they should be.
+struct Arguments<'a> {
+    names: core::slice::Iter<'static, &'static str>,
+    words: SplitAsciiWhitespace<'a>,
+}
+
+impl<'a> Arguments<'a> {
+    /// The next argument's name and word.
+    fn next(&mut self) -> Result<(&'static str, &'a str), CommandParseError> {
+        let argument = self.names.next().copied().unwrap_or("");
+        let word = self.words.next().ok_or(CommandParseError::MissingArgument { argument })?;
+        return Ok((argument, word));
+    }
+
+    /// The next argument, as a number which fits `N`.
+    fn number<N>(&mut self) -> Result<N, CommandParseError>
+    where
+        N: TryFrom<u32>,
+    {
+        let (argument, word) = self.next()?;
+        let (digits, radix) = match strip_hex_prefix(word) {
+            Some(digits) => (digits, 16),
+            None => (word, 10),
+        };
+        // `from_str_radix` takes a leading `+`, which is not a number here.
+        if digits.is_empty() || !digits.bytes().all(|b| (b as char).is_digit(radix)) {
+            return Err(CommandParseError::BadArgument { argument });
+        }
+        let value = u32::from_str_radix(digits, radix)
+            .map_err(|_| CommandParseError::OutOfRange { argument })?;
+        return N::try_from(value).map_err(|_| CommandParseError::OutOfRange { argument });
+    }
+
+    /// The next argument, as a flag.
+    fn flag(&mut self) -> Result<bool, CommandParseError> {
+        let (argument, word) = self.next()?;
+        let names = [("on", true), ("off", false), ("true", true), ("false", false)];
+        for (name, value) in names.iter() {
+            if word.eq_ignore_ascii_case(name) {
+                return Ok(*value);
+            }
+        }
+        return match word {
+            "1" => Ok(true),
+            "0" => Ok(false),
+            _ => Err(CommandParseError::BadArgument { argument }),
+        };
+    }
+
+    /// The next argument, as up to 32 bytes of hex, padded with zeroes.
+    #[cfg(feature = "cmd-notepad")]
+    fn data(&mut self) -> Result<[u8; NOTEPAD_PAGE_SIZE], CommandParseError> {
+        let (argument, word) = self.next()?;
+        let digits = strip_hex_prefix(word).unwrap_or(word).as_bytes();
+        if digits.len() > 2 * NOTEPAD_PAGE_SIZE {
+            return Err(CommandParseError::OutOfRange { argument });
+        }
+        if digits.is_empty() || !digits.len().is_multiple_of(2) {
+            return Err(CommandParseError::BadArgument { argument });
+        }
+        let mut data = [0u8; NOTEPAD_PAGE_SIZE];
+        for (byte, pair) in data.iter_mut().zip(digits.chunks(2)) {
+            let high = (pair[0] as char).to_digit(16);
+            let low = (pair[1] as char).to_digit(16);
+            *byte = match (high, low) {
+                (Some(high), Some(low)) => (high << 4 | low) as u8,
+                _ => return Err(CommandParseError::BadArgument { argument }),
+            };
+        }
+        return Ok(data);
+    }
+}
+
+/// `word` without its `0x` or `0X` prefix, or `None` if it has none.
+fn strip_hex_prefix(word: &str) -> Option<&str> {
+    return word.strip_prefix("0x").or_else(|| word.strip_prefix("0X"));
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::codec::encode_command;
+    use crate::commands::COMMAND_KINDS;
+    use std::string::ToString;
+    use std::vec::Vec;
+
+    /// The frame `command` is sent as, to compare commands, which are not `PartialEq`.
+    fn frame(command: &Command) -> Vec<u8> {
+        let mut bytes = [0u8; 64];
+        let length = encode_command(command, 0xffffffff, &mut bytes).unwrap();
+        return bytes[..length].to_vec();
+    }
+
+    fn parse(line: &str) -> Result<Vec<u8>, CommandParseError> {
+        return line.parse::<Command>().map(|command| frame(&command));
+    }
+
+    #[test]
+    fn test_names_cover_every_kind() {
+        // given: every kind of command
+        for kind in COMMAND_KINDS.iter() {
+            // then: each has a lower-case name which leads back to it
+            let name = kind.name();
+            assert_eq!(name, name.to_ascii_lowercase());
+            assert_eq!(CommandKind::from_name(name), Some(*kind));
+            assert_eq!(CommandKind::from_name(&name.to_ascii_uppercase()), Some(*kind));
+        }
+
+        // and: no two kinds share a row
+        assert_eq!(COMMAND_TABLE.len(), COMMAND_KINDS.len());
+        assert_eq!(CommandKind::from_name("frobnicate"), None);
+    }
+
+    #[test]
+    fn test_parse_every_command() {
+        // given: a line for every command, and the command it should be
+        let cases: &[(&str, Command)] = &[
+            ("readsyspara", Command::ReadSysPara),
+            ("vfypwd 0x00000000", Command::VfyPwd { password: 0 }),
+            ("genimg", Command::GenImg),
+            ("img2tz 2", Command::Img2Tz { buffer: 2 }),
+            ("search 1 0 200", Command::Search { buffer: 1, start_index: 0, end_index: 200 }),
+            ("loadchar 2 0x11", Command::LoadChar { buffer: 2, index: 17 }),
+            ("match", Command::Match),
+            ("templatenum", Command::TemplateNum),
+            ("readindextable 5", Command::ReadIndexTable { page: 5 }),
+            #[cfg(feature = "cmd-enroll")]
+            ("regmodel", Command::RegModel),
+            #[cfg(feature = "cmd-enroll")]
+            ("store 1 17", Command::Store { buffer: 1, index: 17 }),
+            #[cfg(feature = "cmd-transfer")]
+            ("upchar 1", Command::UpChar { buffer: 1 }),
+            #[cfg(feature = "cmd-transfer")]
+            ("downchar 2", Command::DownChar { buffer: 2 }),
+            #[cfg(feature = "cmd-transfer")]
+            ("downimage", Command::DownImage),
+            ("setsyspara 4 12", Command::SetSysPara { parameter: 4, value: 12 }),
+            ("setpwd 0xDEADBEEF", Command::SetPwd { password: 0xdeadbeef }),
+            ("setadder 4294967295", Command::SetAdder { address: 0xffffffff }),
+            ("getchipsn", Command::GetChipSN),
+            ("getrandomcode", Command::GetRandomCode),
+            #[cfg(feature = "cmd-notepad")]
+            ("writenotepad 15 0x0102", Command::WriteNotepad { page: 15, data: notepad() }),
+            #[cfg(feature = "cmd-notepad")]
+            ("readnotepad 0", Command::ReadNotepad { page: 0 }),
+            ("getfwver", Command::GetFwVer),
+            ("getalgver", Command::GetAlgVer),
+            ("handshake", Command::HandShake),
+            ("checksensor", Command::CheckSensor),
+            ("softrst", Command::SoftRst),
+            ("sleep", Command::Sleep),
+            ("portcontrol off", Command::PortControl { enable: false }),
+            #[cfg(feature = "cmd-led")]
+            (
+                "auraledconfig 1 0x80 2 0",
+                Command::AuraLedConfig { control: 1, speed: 128, color: 2, times: 0 },
+            ),
+            ("deletchar 10 5", Command::DeletChar { start_index: 10, num_to_delete: 5 }),
+            ("empty", Command::Empty),
+        ];
+
+        for (line, expected) in cases.iter() {
+            // then: each parses to its command, and is named after it
+            assert_eq!(parse(line), Ok(frame(expected)), "{}", line);
+            assert_eq!(line.split(' ').next(), Some(expected.kind().name()));
+            assert_eq!(
+                line.split(' ').count() - 1,
+                expected.kind().arguments().len(),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[cfg(feature = "cmd-notepad")]
+    fn notepad() -> [u8; 32] {
+        let mut data = [0u8; 32];
+        data[0] = 0x01;
+        data[1] = 0x02;
+        return data;
+    }
+
+    #[test]
+    fn test_parse_spelling() {
+        // then: case, extra whitespace and either spelling of a flag are all fine
+        let search = frame(&Command::Search { buffer: 1, start_index: 0, end_index: 0xffff });
+        assert_eq!(parse("  Search\t1   0 0XFFFF \n"), Ok(search));
+        let on = frame(&Command::PortControl { enable: true });
+        for line in ["portcontrol on", "portcontrol TRUE", "portcontrol 1"].iter() {
+            assert_eq!(parse(line), Ok(on.clone()));
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        // given: malformed lines, and how each should be refused
+        let cases: &[(&str, CommandParseError)] = &[
+            ("", CommandParseError::Empty),
+            ("   ", CommandParseError::Empty),
+            ("serch 1 0 200", CommandParseError::UnknownCommand),
+            ("0x01", CommandParseError::UnknownCommand),
+            ("search 1 0", CommandParseError::MissingArgument { argument: "end_index" }),
+            ("vfypwd", CommandParseError::MissingArgument { argument: "password" }),
+            ("genimg 1", CommandParseError::TooManyArguments),
+            #[cfg(feature = "cmd-enroll")]
+            ("store 1 17 3", CommandParseError::TooManyArguments),
+            ("img2tz one", CommandParseError::BadArgument { argument: "buffer" }),
+            ("img2tz -1", CommandParseError::BadArgument { argument: "buffer" }),
+            ("img2tz +1", CommandParseError::BadArgument { argument: "buffer" }),
+            ("loadchar 1 0x", CommandParseError::BadArgument { argument: "index" }),
+            ("loadchar 1 0xg1", CommandParseError::BadArgument { argument: "index" }),
+            ("img2tz 256", CommandParseError::OutOfRange { argument: "buffer" }),
+            ("deletchar 0 65536", CommandParseError::OutOfRange { argument: "num_to_delete" }),
+            ("setpwd 0x100000000", CommandParseError::OutOfRange { argument: "password" }),
+            ("portcontrol maybe", CommandParseError::BadArgument { argument: "enable" }),
+        ];
+
+        for (line, expected) in cases.iter() {
+            // then: each is refused as expected
+            assert_eq!(parse(line), Err(*expected), "{:?}", line);
+        }
+
+        // and: notepad data must be whole bytes of hex, at most a page of them
+        #[cfg(feature = "cmd-notepad")]
+        {
+            let too_long = ["writenotepad 0 ", &"00".repeat(33)].concat();
+            assert_eq!(parse(&too_long), Err(CommandParseError::OutOfRange { argument: "data" }));
+            assert_eq!(
+                parse("writenotepad 0 0x123"),
+                Err(CommandParseError::BadArgument { argument: "data" })
+            );
+            assert_eq!(
+                parse("writenotepad 0 zz"),
+                Err(CommandParseError::BadArgument { argument: "data" })
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_error_display() {
+        assert_eq!(
+            CommandParseError::MissingArgument { argument: "index" }.to_string(),
+            "missing argument `index`"
+        );
+        assert_eq!(CommandParseError::UnknownCommand.to_string(), "unknown command");
+    }
+}
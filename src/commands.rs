@@ -1,10 +1,25 @@
-use crate::utils::{CommandWriter, ToPayload};
+use crate::codec;
+#[cfg(feature = "cmd-notepad")]
+use crate::consts::NOTEPAD_PAGE_SIZE;
+use crate::responses::Reply;
+use crate::utils::{CommandWriter, ProtocolCommand, ToPayload};
+
+/// How many _character buffers_ the module has, numbered from 1. The R502 has 2; with the
+/// `r503` feature, this is the 6 of the R503.
+#[cfg(not(feature = "r503"))]
+pub const CHAR_BUFFERS: u8 = 2;
+
+/// How many _character buffers_ the module has, numbered from 1. The R502 has 2; with the
+/// `r503` feature, this is the 6 of the R503.
+#[cfg(feature = "r503")]
+pub const CHAR_BUFFERS: u8 = 6;
 
 /// Commands that one can send to the R502.
 ///
 /// Command naming and some field names are taken from the R502 datasheet: [Datasheet link](https://www.dropbox.com/sh/epucei8lmoz7xpp/AAAmon04b1DiSOeh1q4nAhzAa?dl=0&preview=R502+fingerprint+module+user+manual-V1.2.pdf) -
 /// yes, it actually is hosted on Dropbox.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub enum Command {
     /// Reads system status and configuration
     ReadSysPara,
@@ -19,22 +34,25 @@ pub enum Command {
     /// Captures an image of the fingerprint into the _image buffer_.
     GenImg,
 
-    /// Processes the image from the R502's _image buffer_ into one of the two
+    /// Processes the image from the R502's _image buffer_ into one of the
     /// available _character buffers_. This command actually runs the image recognition
     /// and builds a feature vector-like representation of the fingerprint captured.
     Img2Tz {
-        /// Which buffer to store the processed fingerprint data into (there are 2).
+        /// Which buffer to store the processed fingerprint data into (see `CHAR_BUFFERS`).
         ///
-        /// **Note:** The buffers are named **1** and **2**. Any other value defaults to 2.
+        /// **Note:** The buffers are numbered from **1** to `CHAR_BUFFERS`, which is 2 on the
+        /// R502. The driver refuses any other value with `Error::InvalidBuffer`.
         buffer: u8,
     },
 
     /// Matches the captured fingerprint against a number of stored templates. You can set the
-    /// `start_index` and `end_index` to `0` and `0xff` respectively to search the entire library.
+    /// `start_index` and `end_index` to `0` and `0xffff` respectively to search the entire
+    /// library, however large; `0xff` would leave out every slot above 255.
     Search {
-        /// Which buffer to store the processed fingerprint data into (there are 2).
+        /// Which buffer to store the processed fingerprint data into (see `CHAR_BUFFERS`).
         ///
-        /// **Note:** The buffers are named **1** and **2**. Any other value defaults to 2.
+        /// **Note:** The buffers are numbered from **1** to `CHAR_BUFFERS`, which is 2 on the
+        /// R502. The driver refuses any other value with `Error::InvalidBuffer`.
         buffer: u8,
 
         /// The start index. Where the search should start from. 0-based.
@@ -45,11 +63,12 @@ pub enum Command {
         end_index: u16,
     },
 
-    /// Loads a fingerprint _character file_ into one of the two _character buffers_.
+    /// Loads a fingerprint _character file_ into one of the _character buffers_.
     LoadChar {
-        /// Which buffer to store the processed fingerprint data into (there are 2).
+        /// Which buffer to store the processed fingerprint data into (see `CHAR_BUFFERS`).
         ///
-        /// **Note:** The buffers are named **1** and **2**. Any other value defaults to 2.
+        /// **Note:** The buffers are numbered from **1** to `CHAR_BUFFERS`, which is 2 on the
+        /// R502. The driver refuses any other value with `Error::InvalidBuffer`.
         buffer: u8,
 
         /// Which fingerprint to load from the library (0-based index).
@@ -61,22 +80,42 @@ pub enum Command {
     /// finger is placed on the reader.
     Match,
 
-    /// Returns the next valid index at which a new fingerprint can be enrolled.
+    /// Returns the number of templates stored in the library.
+    ///
+    /// **Note:** This is a count, not the next free index. Use
+    /// [`R502::next_free_slot`](struct.R502.html#method.next_free_slot) to find out where to
+    /// enrol a new fingerprint.
     TemplateNum,
 
+    /// Reads one page of the _index table_, a bitmap of which library slots hold a template.
+    /// Each page covers 256 slots.
+    ReadIndexTable {
+        /// Which page to read. Page 0 covers slots 0-255, page 1 slots 256-511 and so on, so a
+        /// 200-slot R502 has only page 0 and a 1500-slot module pages 0 to 5.
+        page: u8,
+    },
+
     /// Combines fingerprint data stored in two _character buffers_ into a new _template_,
     /// which is returned into _both_ character buffers. This is part of the enrollment
     /// process. For this to work, both buffers need to contain data from the same finger.
+    ///
+    /// The R503 combines every one of its buffers holding a capture, which the `r503` feature
+    /// lets the driver fill.
+    #[cfg(feature = "cmd-enroll")]
     RegModel,
 
     /// Stores a fingerprint template from the given buffer into the library.
     /// 
     /// **Note:** This will allow you to overwrite an existing fingerprint template.
-    /// Use with caution, and invoke `TemplateNum` first to get the next free index.
+    /// Use with caution, and invoke
+    /// [`R502::next_free_slot`](struct.R502.html#method.next_free_slot) first to get the next
+    /// free index.
+    #[cfg(feature = "cmd-enroll")]
     Store {
-        /// Which _character buffer_ to read the fingerprint template from (there are 2).
+        /// Which _character buffer_ to read the fingerprint template from (see `CHAR_BUFFERS`).
         ///
-        /// **Note:** The buffers are named **1** and **2**. Any other value defaults to 2.
+        /// **Note:** The buffers are numbered from **1** to `CHAR_BUFFERS`, which is 2 on the
+        /// R502. The driver refuses any other value with `Error::InvalidBuffer`.
         /// Also note that it shouldn't really matter which buffer you use when enrolling, since
         /// `RegModel` will return its result into both buffers.
         buffer: u8,
@@ -85,6 +124,151 @@ pub enum Command {
         index: u16,
     },
 
+    /// Uploads the contents of a _character buffer_ to the host. The R502 replies with an
+    /// acknowledgement followed by a series of data packets.
+    ///
+    /// Use [`R502::upload_template`](struct.R502.html#method.upload_template) rather than
+    /// sending this directly, as it takes care of collecting the data packets.
+    #[cfg(feature = "cmd-transfer")]
+    UpChar {
+        /// Which _character buffer_ to upload.
+        ///
+        /// **Note:** The buffers are numbered from **1** to `CHAR_BUFFERS`, which is 2 on the
+        /// R502. The driver refuses any other value with `Error::InvalidBuffer`.
+        buffer: u8,
+    },
+
+    /// Downloads a _character file_ or template from the host into a _character buffer_. The
+    /// R502 replies with an acknowledgement and then expects the data packets to follow.
+    ///
+    /// Use [`R502::download_template`](struct.R502.html#method.download_template) rather than
+    /// sending this directly, as it takes care of sending the data packets.
+    #[cfg(feature = "cmd-transfer")]
+    DownChar {
+        /// Which _character buffer_ to download into.
+        ///
+        /// **Note:** The buffers are numbered from **1** to `CHAR_BUFFERS`, which is 2 on the
+        /// R502. The driver refuses any other value with `Error::InvalidBuffer`.
+        buffer: u8,
+    },
+
+    /// Downloads a fingerprint image from the host into the _image buffer_, in place of one
+    /// captured with `GenImg`. The R502 replies with an acknowledgement and then expects the
+    /// data packets to follow.
+    ///
+    /// Use [`R502::download_image`](struct.R502.html#method.download_image) rather than
+    /// sending this directly, as it takes care of sending the data packets.
+    #[cfg(feature = "cmd-transfer")]
+    DownImage,
+
+    /// Writes one of the system parameters, as read back by `ReadSysPara`.
+    ///
+    /// **Note:** A new baud rate takes effect as soon as the R502 has replied, so the host
+    /// UART has to be switched over before the next command.
+    /// [`R502::change_baud`](struct.R502.html#method.change_baud) takes care of the order.
+    SetSysPara {
+        /// Which parameter to write: 4 for the baud rate, 5 for the security level,
+        /// 6 for the data packet size.
+        parameter: u8,
+
+        /// The new value: a multiple of 9600 baud [1-12], a security level [1-5],
+        /// or a packet size code [0-3].
+        value: u8,
+    },
+
+    /// Sets a new module password, which has to be given with `VfyPwd` from then on.
+    ///
+    /// **Note:** A module whose password is lost cannot be used any more. Prefer
+    /// [`R502::change_password`](struct.R502.html#method.change_password), which confirms
+    /// that the new password works.
+    SetPwd {
+        /// The new password.
+        password: u32,
+    },
+
+    /// Sets a new module address. The R502 replies from the new address, and only listens
+    /// to it from then on.
+    SetAdder {
+        /// The new address.
+        address: u32,
+    },
+
+    /// Reads the unique serial number of the module's chip.
+    GetChipSN,
+
+    /// Reads a 32-bit random number from the module's random number generator. See
+    /// [`R502::random_code`](struct.R502.html#method.random_code).
+    GetRandomCode,
+
+    /// Writes a page of the 512-byte notepad, which the module keeps in flash for the host
+    /// to use as it sees fit.
+    #[cfg(feature = "cmd-notepad")]
+    WriteNotepad {
+        /// Page to write, 0 to 15.
+        page: u8,
+
+        /// Contents of the page.
+        data: [u8; NOTEPAD_PAGE_SIZE],
+    },
+
+    /// Reads a page of the notepad.
+    #[cfg(feature = "cmd-notepad")]
+    ReadNotepad {
+        /// Page to read, 0 to 15.
+        page: u8,
+    },
+
+    /// Reads the firmware version string. Not supported by older firmware, which reports a
+    /// `PacketError` instead.
+    GetFwVer,
+
+    /// Reads the version string of the fingerprint algorithm library. Not supported by older
+    /// firmware, which reports a `PacketError` instead.
+    GetAlgVer,
+
+    /// Checks that the module is alive and ready to accept commands. Not supported by older
+    /// firmware, which reports a `PacketError` instead.
+    HandShake,
+
+    /// Checks that the fingerprint sensor itself is working. Not supported by older firmware,
+    /// which reports a `PacketError` instead.
+    CheckSensor,
+
+    /// Resets the module. Once it has restarted, it sends a single `0x55` byte to say it is
+    /// ready; see [`R502::soft_reset`](struct.R502.html#method.soft_reset).
+    SoftRst,
+
+    /// Puts the module to sleep to save power. It stops answering until it is powered up again
+    /// or woken by a touch, after which it sends the `0x55` ready byte; see
+    /// [`R502::standby`](struct.R502.html#method.standby).
+    Sleep,
+
+    /// Turns the USB port of the module on or off. Turning it off saves power when the module
+    /// is only used over the UART.
+    PortControl {
+        /// Whether the port should be on.
+        enable: bool,
+    },
+
+    /// Controls the ring LED found on the R503 and on later R502 revisions. Modules without
+    /// the LED report a `PacketError`.
+    #[cfg(feature = "cmd-led")]
+    AuraLedConfig {
+        /// Control code: 1 breathing, 2 flashing, 3 always on, 4 always off,
+        /// 5 gradually on, 6 gradually off.
+        control: u8,
+
+        /// Speed of the breathing or flashing effect. Lower is faster.
+        speed: u8,
+
+        /// Colour index: 1 red, 2 blue, 3 purple. Some firmware also knows 4 green,
+        /// 5 yellow, 6 cyan and 7 white.
+        color: u8,
+
+        /// How many times to flash or breathe. 0 means forever.
+        times: u8,
+    },
+
     /// Deletes enrolled fingerprint templates starting from the given index.
     DeletChar {
         /// Index of the fingerprint template in the library to delete.
@@ -93,6 +277,259 @@ pub enum Command {
         /// Number of templates to delete starting from the given index
         num_to_delete: u16,
     },
+
+    /// Deletes every fingerprint template in the library.
+    Empty,
+}
+
+/// Which command a `Command` is, without its fields. A reply is decoded according to the kind
+/// of command it answers, see `decode_reply`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum CommandKind {
+    ReadSysPara,
+    VfyPwd,
+    GenImg,
+    Img2Tz,
+    Search,
+    LoadChar,
+    Match,
+    TemplateNum,
+    ReadIndexTable,
+    #[cfg(feature = "cmd-enroll")]
+    RegModel,
+    #[cfg(feature = "cmd-enroll")]
+    Store,
+    #[cfg(feature = "cmd-transfer")]
+    UpChar,
+    #[cfg(feature = "cmd-transfer")]
+    DownChar,
+    #[cfg(feature = "cmd-transfer")]
+    DownImage,
+    SetSysPara,
+    SetPwd,
+    SetAdder,
+    GetChipSN,
+    GetRandomCode,
+    #[cfg(feature = "cmd-notepad")]
+    WriteNotepad,
+    #[cfg(feature = "cmd-notepad")]
+    ReadNotepad,
+    GetFwVer,
+    GetAlgVer,
+    HandShake,
+    CheckSensor,
+    SoftRst,
+    Sleep,
+    PortControl,
+    #[cfg(feature = "cmd-led")]
+    AuraLedConfig,
+    DeletChar,
+    Empty,
+}
+
+/// Every kind of command compiled in, for tests which go through them all.
+#[cfg(any(test, feature = "fuzzing"))]
+pub(crate) const COMMAND_KINDS: &[CommandKind] = &[
+    CommandKind::ReadSysPara,
+    CommandKind::VfyPwd,
+    CommandKind::GenImg,
+    CommandKind::Img2Tz,
+    CommandKind::Search,
+    CommandKind::LoadChar,
+    CommandKind::Match,
+    CommandKind::TemplateNum,
+    CommandKind::ReadIndexTable,
+    #[cfg(feature = "cmd-enroll")]
+    CommandKind::RegModel,
+    #[cfg(feature = "cmd-enroll")]
+    CommandKind::Store,
+    #[cfg(feature = "cmd-transfer")]
+    CommandKind::UpChar,
+    #[cfg(feature = "cmd-transfer")]
+    CommandKind::DownChar,
+    #[cfg(feature = "cmd-transfer")]
+    CommandKind::DownImage,
+    CommandKind::SetSysPara,
+    CommandKind::SetPwd,
+    CommandKind::SetAdder,
+    CommandKind::GetChipSN,
+    CommandKind::GetRandomCode,
+    #[cfg(feature = "cmd-notepad")]
+    CommandKind::WriteNotepad,
+    #[cfg(feature = "cmd-notepad")]
+    CommandKind::ReadNotepad,
+    CommandKind::GetFwVer,
+    CommandKind::GetAlgVer,
+    CommandKind::HandShake,
+    CommandKind::CheckSensor,
+    CommandKind::SoftRst,
+    CommandKind::Sleep,
+    CommandKind::PortControl,
+    #[cfg(feature = "cmd-led")]
+    CommandKind::AuraLedConfig,
+    CommandKind::DeletChar,
+    CommandKind::Empty,
+];
+
+impl Command {
+    /// Which command this is.
+    pub fn kind(&self) -> CommandKind {
+        return match self {
+            Self::ReadSysPara => CommandKind::ReadSysPara,
+            Self::VfyPwd { .. } => CommandKind::VfyPwd,
+            Self::GenImg => CommandKind::GenImg,
+            Self::Img2Tz { .. } => CommandKind::Img2Tz,
+            Self::Search { .. } => CommandKind::Search,
+            Self::LoadChar { .. } => CommandKind::LoadChar,
+            Self::Match => CommandKind::Match,
+            Self::TemplateNum => CommandKind::TemplateNum,
+            Self::ReadIndexTable { .. } => CommandKind::ReadIndexTable,
+            #[cfg(feature = "cmd-enroll")]
+            Self::RegModel => CommandKind::RegModel,
+            #[cfg(feature = "cmd-enroll")]
+            Self::Store { .. } => CommandKind::Store,
+            #[cfg(feature = "cmd-transfer")]
+            Self::UpChar { .. } => CommandKind::UpChar,
+            #[cfg(feature = "cmd-transfer")]
+            Self::DownChar { .. } => CommandKind::DownChar,
+            #[cfg(feature = "cmd-transfer")]
+            Self::DownImage => CommandKind::DownImage,
+            Self::SetSysPara { .. } => CommandKind::SetSysPara,
+            Self::SetPwd { .. } => CommandKind::SetPwd,
+            Self::SetAdder { .. } => CommandKind::SetAdder,
+            Self::GetChipSN => CommandKind::GetChipSN,
+            Self::GetRandomCode => CommandKind::GetRandomCode,
+            #[cfg(feature = "cmd-notepad")]
+            Self::WriteNotepad { .. } => CommandKind::WriteNotepad,
+            #[cfg(feature = "cmd-notepad")]
+            Self::ReadNotepad { .. } => CommandKind::ReadNotepad,
+            Self::GetFwVer => CommandKind::GetFwVer,
+            Self::GetAlgVer => CommandKind::GetAlgVer,
+            Self::HandShake => CommandKind::HandShake,
+            Self::CheckSensor => CommandKind::CheckSensor,
+            Self::SoftRst => CommandKind::SoftRst,
+            Self::Sleep => CommandKind::Sleep,
+            Self::PortControl { .. } => CommandKind::PortControl,
+            #[cfg(feature = "cmd-led")]
+            Self::AuraLedConfig { .. } => CommandKind::AuraLedConfig,
+            Self::DeletChar { .. } => CommandKind::DeletChar,
+            Self::Empty => CommandKind::Empty,
+        };
+    }
+
+    /// The _character buffer_ this command names, if any.
+    pub fn char_buffer(&self) -> Option<u8> {
+        return match self {
+            Self::Img2Tz { buffer }
+            | Self::Search { buffer, .. }
+            | Self::LoadChar { buffer, .. } => Some(*buffer),
+            #[cfg(feature = "cmd-enroll")]
+            Self::Store { buffer, .. } => Some(*buffer),
+            #[cfg(feature = "cmd-transfer")]
+            Self::UpChar { buffer } | Self::DownChar { buffer } => Some(*buffer),
+            _ => None,
+        };
+    }
+
+    /// The _character buffer_ this command names, if the module does not have it.
+    pub(crate) fn invalid_buffer(&self) -> Option<u8> {
+        return self.char_buffer().filter(|buffer| !(1..=CHAR_BUFFERS).contains(buffer));
+    }
+}
+
+/// Written out as `Debug` would, except that the password of `VfyPwd` and `SetPwd` is left out,
+/// so that logs from the field do not give it away.
+#[cfg(feature = "defmt")]
+impl defmt::Format for Command {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        match self {
+            Self::ReadSysPara => defmt::write!(f, "ReadSysPara"),
+            Self::VfyPwd { .. } => defmt::write!(f, "VfyPwd {{ password: <redacted> }}"),
+            Self::GenImg => defmt::write!(f, "GenImg"),
+            Self::Img2Tz { buffer } => defmt::write!(f, "Img2Tz {{ buffer: {} }}", buffer),
+            Self::Search { buffer, start_index, end_index } => defmt::write!(
+                f,
+                "Search {{ buffer: {}, start_index: {}, end_index: {} }}",
+                buffer,
+                start_index,
+                end_index
+            ),
+            Self::LoadChar { buffer, index } => {
+                defmt::write!(f, "LoadChar {{ buffer: {}, index: {} }}", buffer, index)
+            }
+            Self::Match => defmt::write!(f, "Match"),
+            Self::TemplateNum => defmt::write!(f, "TemplateNum"),
+            Self::ReadIndexTable { page } => {
+                defmt::write!(f, "ReadIndexTable {{ page: {} }}", page)
+            }
+            #[cfg(feature = "cmd-enroll")]
+            Self::RegModel => defmt::write!(f, "RegModel"),
+            #[cfg(feature = "cmd-enroll")]
+            Self::Store { buffer, index } => {
+                defmt::write!(f, "Store {{ buffer: {}, index: {} }}", buffer, index)
+            }
+            #[cfg(feature = "cmd-transfer")]
+            Self::UpChar { buffer } => defmt::write!(f, "UpChar {{ buffer: {} }}", buffer),
+            #[cfg(feature = "cmd-transfer")]
+            Self::DownChar { buffer } => defmt::write!(f, "DownChar {{ buffer: {} }}", buffer),
+            #[cfg(feature = "cmd-transfer")]
+            Self::DownImage => defmt::write!(f, "DownImage"),
+            Self::SetSysPara { parameter, value } => defmt::write!(
+                f,
+                "SetSysPara {{ parameter: {}, value: {} }}",
+                parameter,
+                value
+            ),
+            Self::SetPwd { .. } => defmt::write!(f, "SetPwd {{ password: <redacted> }}"),
+            Self::SetAdder { address } => {
+                defmt::write!(f, "SetAdder {{ address: {=u32:#010x} }}", address)
+            }
+            Self::GetChipSN => defmt::write!(f, "GetChipSN"),
+            Self::GetRandomCode => defmt::write!(f, "GetRandomCode"),
+            #[cfg(feature = "cmd-notepad")]
+            Self::WriteNotepad { page, data } => {
+                let data = &data[..];
+                defmt::write!(f, "WriteNotepad {{ page: {}, data: {=[u8]:02x} }}", page, data)
+            }
+            #[cfg(feature = "cmd-notepad")]
+            Self::ReadNotepad { page } => defmt::write!(f, "ReadNotepad {{ page: {} }}", page),
+            Self::GetFwVer => defmt::write!(f, "GetFwVer"),
+            Self::GetAlgVer => defmt::write!(f, "GetAlgVer"),
+            Self::HandShake => defmt::write!(f, "HandShake"),
+            Self::CheckSensor => defmt::write!(f, "CheckSensor"),
+            Self::SoftRst => defmt::write!(f, "SoftRst"),
+            Self::Sleep => defmt::write!(f, "Sleep"),
+            Self::PortControl { enable } => {
+                defmt::write!(f, "PortControl {{ enable: {} }}", enable)
+            }
+            #[cfg(feature = "cmd-led")]
+            Self::AuraLedConfig { control, speed, color, times } => defmt::write!(
+                f,
+                "AuraLedConfig {{ control: {}, speed: {}, color: {}, times: {} }}",
+                control,
+                speed,
+                color,
+                times
+            ),
+            Self::DeletChar { start_index, num_to_delete } => defmt::write!(
+                f,
+                "DeletChar {{ start_index: {}, num_to_delete: {} }}",
+                start_index,
+                num_to_delete
+            ),
+            Self::Empty => defmt::write!(f, "Empty"),
+        }
+    }
+}
+
+impl ProtocolCommand for Command {
+    type Reply = Reply;
+
+    fn decode_reply(&self, packet: &[u8]) -> Result<Reply, codec::DecodeError> {
+        return codec::reply_from_packet(self.kind(), packet);
+    }
 }
 
 impl ToPayload for Command {
@@ -220,6 +657,21 @@ impl ToPayload for Command {
                 writer.write_cmd_bytes(&[0x1D]);
             }
 
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x04 [2]
+            // instr  | 0x1F [1]
+            // page   | page [1]
+            // chksum | checksum [2]
+            Self::ReadIndexTable { page } => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x04]);
+                writer.write_cmd_bytes(&[0x1F]);
+                writer.write_cmd_bytes(&[*page]);
+            }
+
             // Required packet:
             // headr  | 0xEF 0x01 [2]
             // addr   | cmd.address [4]
@@ -227,6 +679,7 @@ impl ToPayload for Command {
             // length | 0x00 0x03 [2]
             // instr  | 0x05 [1]
             // chksum | checksum [2]
+            #[cfg(feature = "cmd-enroll")]
             Self::RegModel => {
                 writer.write_cmd_bytes(&[0x01]);
                 writer.write_cmd_bytes(&[0x00, 0x03]);
@@ -242,6 +695,7 @@ impl ToPayload for Command {
             // bufid  | buffer [1]
             // index  | index [2]
             // chksum | checksum [2]
+            #[cfg(feature = "cmd-enroll")]
             Self::Store { buffer, index } => {
                 writer.write_cmd_bytes(&[0x01]);
                 writer.write_cmd_bytes(&[0x00, 0x06]);
@@ -250,6 +704,272 @@ impl ToPayload for Command {
                 writer.write_cmd_bytes(&index.to_be_bytes()[..]);
             }
 
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x04 [2]
+            // instr  | 0x08 [1]
+            // bufid  | buffer [1]
+            // chksum | checksum [2]
+            #[cfg(feature = "cmd-transfer")]
+            Self::UpChar { buffer } => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x04]);
+                writer.write_cmd_bytes(&[0x08]);
+                writer.write_cmd_bytes(&[*buffer]);
+            }
+
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x04 [2]
+            // instr  | 0x09 [1]
+            // bufid  | buffer [1]
+            // chksum | checksum [2]
+            #[cfg(feature = "cmd-transfer")]
+            Self::DownChar { buffer } => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x04]);
+                writer.write_cmd_bytes(&[0x09]);
+                writer.write_cmd_bytes(&[*buffer]);
+            }
+
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x03 [2]
+            // instr  | 0x0b [1]
+            // chksum | checksum [2]
+            #[cfg(feature = "cmd-transfer")]
+            Self::DownImage => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x03]);
+                writer.write_cmd_bytes(&[0x0b]);
+            }
+
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x05 [2]
+            // instr  | 0x0e [1]
+            // param  | parameter [1]
+            // value  | value [1]
+            // chksum | checksum [2]
+            Self::SetSysPara { parameter, value } => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x05]);
+                writer.write_cmd_bytes(&[0x0e]);
+                writer.write_cmd_bytes(&[*parameter, *value]);
+            }
+
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x07 [2]
+            // instr  | 0x12 [1]
+            // passwd | password [4]
+            // chksum | checksum [2]
+            Self::SetPwd { password } => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x07]);
+                writer.write_cmd_bytes(&[0x12]);
+                writer.write_cmd_bytes(&password.to_be_bytes());
+            }
+
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x07 [2]
+            // instr  | 0x15 [1]
+            // newadr | address [4]
+            // chksum | checksum [2]
+            Self::SetAdder { address } => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x07]);
+                writer.write_cmd_bytes(&[0x15]);
+                writer.write_cmd_bytes(&address.to_be_bytes());
+            }
+
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x04 [2]
+            // instr  | 0x34 [1]
+            // rsrvd  | 0x00 [1]
+            // chksum | checksum [2]
+            Self::GetChipSN => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x04]);
+                writer.write_cmd_bytes(&[0x34]);
+                writer.write_cmd_bytes(&[0x00]);
+            }
+
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x03 [2]
+            // instr  | 0x14 [1]
+            // chksum | checksum [2]
+            Self::GetRandomCode => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x03]);
+                writer.write_cmd_bytes(&[0x14]);
+            }
+
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x03 [2]
+            // instr  | 0x3a [1]
+            // chksum | checksum [2]
+            Self::GetFwVer => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x03]);
+                writer.write_cmd_bytes(&[0x3a]);
+            }
+
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x03 [2]
+            // instr  | 0x39 [1]
+            // chksum | checksum [2]
+            Self::GetAlgVer => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x03]);
+                writer.write_cmd_bytes(&[0x39]);
+            }
+
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x24 [2]
+            // instr  | 0x18 [1]
+            // page   | page [1]
+            // data   | data [32]
+            // chksum | checksum [2]
+            #[cfg(feature = "cmd-notepad")]
+            Self::WriteNotepad { page, data } => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x24]);
+                writer.write_cmd_bytes(&[0x18]);
+                writer.write_cmd_bytes(&[*page]);
+                writer.write_cmd_bytes(&data[..]);
+            }
+
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x04 [2]
+            // instr  | 0x19 [1]
+            // page   | page [1]
+            // chksum | checksum [2]
+            #[cfg(feature = "cmd-notepad")]
+            Self::ReadNotepad { page } => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x04]);
+                writer.write_cmd_bytes(&[0x19]);
+                writer.write_cmd_bytes(&[*page]);
+            }
+
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x03 [2]
+            // instr  | 0x40 [1]
+            // chksum | checksum [2]
+            Self::HandShake => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x03]);
+                writer.write_cmd_bytes(&[0x40]);
+            }
+
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x03 [2]
+            // instr  | 0x36 [1]
+            // chksum | checksum [2]
+            Self::CheckSensor => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x03]);
+                writer.write_cmd_bytes(&[0x36]);
+            }
+
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x03 [2]
+            // instr  | 0x3d [1]
+            // chksum | checksum [2]
+            Self::SoftRst => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x03]);
+                writer.write_cmd_bytes(&[0x3d]);
+            }
+
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x03 [2]
+            // instr  | 0x33 [1]
+            // chksum | checksum [2]
+            Self::Sleep => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x03]);
+                writer.write_cmd_bytes(&[0x33]);
+            }
+
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x04 [2]
+            // instr  | 0x17 [1]
+            // ctrl   | 0x00 off / 0x01 on [1]
+            // chksum | checksum [2]
+            Self::PortControl { enable } => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x04]);
+                writer.write_cmd_bytes(&[0x17]);
+                writer.write_cmd_bytes(&[*enable as u8]);
+            }
+
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x07 [2]
+            // instr  | 0x35 [1]
+            // ctrl   | control [1]
+            // speed  | speed [1]
+            // color  | color [1]
+            // times  | times [1]
+            // chksum | checksum [2]
+            #[cfg(feature = "cmd-led")]
+            Self::AuraLedConfig { control, speed, color, times } => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x07]);
+                writer.write_cmd_bytes(&[0x35]);
+                writer.write_cmd_bytes(&[*control, *speed, *color, *times]);
+            }
+
             // Required packet:
             // headr  | 0xEF 0x01 [2]
             // addr   | cmd.address [4]
@@ -266,6 +986,19 @@ impl ToPayload for Command {
                 writer.write_cmd_bytes(&start_index.to_be_bytes()[..]);
                 writer.write_cmd_bytes(&num_to_delete.to_be_bytes()[..]);
             }
+
+            // Required packet:
+            // headr  | 0xEF 0x01 [2]
+            // addr   | cmd.address [4]
+            // ident  | 0x01 [1]
+            // length | 0x00 0x03 [2]
+            // instr  | 0x0d [1]
+            // chksum | checksum [2]
+            Self::Empty => {
+                writer.write_cmd_bytes(&[0x01]);
+                writer.write_cmd_bytes(&[0x00, 0x03]);
+                writer.write_cmd_bytes(&[0x0d]);
+            }
         }
     }
 }
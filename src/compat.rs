@@ -0,0 +1,194 @@
+use crate::commands::Command;
+use crate::consts::{
+    R307_IMAGE_HEIGHT, R307_IMAGE_LEN, R307_IMAGE_WIDTH, R502_IMAGE_HEIGHT, R502_IMAGE_LEN,
+    R502_IMAGE_WIDTH,
+};
+use crate::driver::R502;
+use crate::library::IndexTableSource;
+use crate::responses::*;
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// The largest library in the R307 family. Capacities reported above this, or of zero, are
+/// taken to be this in `ModuleFamily::R307` mode.
+pub const R307_MAX_LIBRARY_SIZE: u16 = 1000;
+
+/// Which family of module the driver is talking to, see
+/// [`R502::set_module_family`](struct.R502.html#method.set_module_family).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ModuleFamily {
+    /// The R502, R503 and other modules with the full instruction set. The default.
+    R502,
+
+    /// The R307, ZFM-20 and their clones, which speak the same protocol with fewer
+    /// instructions. In this mode, helpers degrade as follows:
+    ///
+    /// * `set_led` returns `LedError::Unsupported` without sending anything, and the LED
+    ///   feedback of the enrolment and identification helpers is skipped, as there is no
+    ///   `AuraLedConfig`.
+    /// * The _index table_ is always built by probing every slot with `LoadChar`
+    ///   (`IndexTableSource::Probing`), as older firmware has no `ReadIndexTable`. This
+    ///   takes a round trip per slot, and overwrites _character buffer_ 2.
+    /// * A library capacity of zero or above `R307_MAX_LIBRARY_SIZE`, as some clones report,
+    ///   is taken to be `R307_MAX_LIBRARY_SIZE`.
+    R307,
+}
+
+impl ModuleFamily {
+    /// Length of the images the family's sensors take, and `DownImage` expects.
+    pub const fn image_len(self) -> usize {
+        return match self {
+            Self::R502 => R502_IMAGE_LEN,
+            Self::R307 => R307_IMAGE_LEN,
+        };
+    }
+
+    /// Width in pixels of the images the family's sensors take.
+    pub const fn image_width(self) -> u32 {
+        return match self {
+            Self::R502 => R502_IMAGE_WIDTH,
+            Self::R307 => R307_IMAGE_WIDTH,
+        };
+    }
+
+    /// Height in pixels of the images the family's sensors take.
+    pub const fn image_height(self) -> u32 {
+        return match self {
+            Self::R502 => R502_IMAGE_HEIGHT,
+            Self::R307 => R307_IMAGE_HEIGHT,
+        };
+    }
+}
+
+impl Default for ModuleFamily {
+    fn default() -> Self {
+        return Self::R502;
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Which family of module the driver is talking to.
+    pub fn module_family(&self) -> ModuleFamily {
+        return self.family;
+    }
+
+    /// Tells the driver which family of module it is talking to, so the helpers stick to the
+    /// instructions it has. See [`ModuleFamily`](enum.ModuleFamily.html) for what changes.
+    pub fn set_module_family(&mut self, family: ModuleFamily) {
+        self.family = family;
+        self.index_cache.source = match family {
+            ModuleFamily::R502 => None,
+            ModuleFamily::R307 => Some(IndexTableSource::Probing),
+        };
+        self.index_cache.invalidate();
+    }
+
+    /// Works out which family of module the driver is talking to with `GetFwVer`, which the
+    /// R307 family does not know, and switches to it as
+    /// [`set_module_family`](#method.set_module_family) does.
+    pub fn detect_module_family(
+        &mut self,
+    ) -> Result<ModuleFamily, Error<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(self.send_command(Command::GetFwVer), Reply::GetFwVer)?;
+        let family = match result.confirmation_code {
+            GetFwVerStatus::Success => ModuleFamily::R502,
+            GetFwVerStatus::PacketError => ModuleFamily::R307,
+        };
+        self.set_module_family(family);
+        return Ok(family);
+    }
+
+    /// The library capacity to go by, given the one `ReadSysPara` reported.
+    pub(crate) fn usable_library_size(&self, reported: u16) -> u16 {
+        return match self.family {
+            ModuleFamily::R307 if reported == 0 || reported > R307_MAX_LIBRARY_SIZE => {
+                R307_MAX_LIBRARY_SIZE
+            }
+            _ => reported,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::emulator::Emulator;
+    #[cfg(all(feature = "cmd-enroll", feature = "cmd-led"))]
+    use crate::emulator::NoDelay;
+    #[cfg(all(feature = "cmd-enroll", feature = "cmd-led"))]
+    use crate::enroll::EnrollConfig;
+    #[cfg(all(feature = "cmd-enroll", feature = "cmd-led"))]
+    use crate::led::{LedColor, LedError, LedFeedback, LedPattern};
+    use std::vec;
+
+    #[test]
+    fn test_detect_module_family() {
+        // given: an R502 and an R307
+        let r502_emulator = Emulator::new();
+        let (tx, rx) = r502_emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        let r307_emulator = Emulator::r307();
+        let (tx, rx) = r307_emulator.serial();
+        let mut r307 = R502::new(tx, rx, 0xffffffff);
+
+        // when: detecting their families
+        let r502_family = r502.detect_module_family().unwrap();
+        let r307_family = r307.detect_module_family().unwrap();
+
+        // then: each is told apart by whether it knows `GetFwVer`
+        assert_eq!(r502_family, ModuleFamily::R502);
+        assert_eq!(r502.index_table_source(), None);
+        assert_eq!(r307_family, ModuleFamily::R307);
+        assert_eq!(r307.module_family(), ModuleFamily::R307);
+        assert_eq!(r307.index_table_source(), Some(IndexTableSource::Probing));
+    }
+
+    #[test]
+    #[cfg(all(feature = "cmd-enroll", feature = "cmd-led"))]
+    fn test_r307_led() {
+        // given: a driver in R307 mode
+        let emulator = Emulator::r307();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.set_module_family(ModuleFamily::R307);
+
+        // when: setting the LED, and enrolling with LED feedback
+        let led = r502.set_led(LedPattern::On, LedColor::Green);
+        emulator.script_captures(7, 2);
+        let config = EnrollConfig { led: Some(LedFeedback::default()), ..Default::default() };
+        let enrolled = r502.enroll(3, &config, &mut NoDelay, |_| {});
+
+        // then: the LED is unsupported, and enrolment goes ahead without touching it
+        assert_eq!(matches!(led, Err(LedError::Unsupported)), true);
+        assert_eq!(enrolled.is_ok(), true);
+        assert_eq!(emulator.instructions().contains(&0x35), false);
+        assert_eq!(emulator.slot(3).unwrap()[0], 7);
+    }
+
+    #[test]
+    fn test_r307_bogus_capacity() {
+        // given: an R307 clone which reports a library of 0xffff slots, with slot 5 in use
+        let emulator = Emulator::r307();
+        emulator.state().reported_library_size = Some(0xffff);
+        emulator.enroll(5, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.set_module_family(ModuleFamily::R307);
+
+        // when: reading the library capacity and the index table
+        let capacity = r502.library_capacity().unwrap();
+        let table = r502.read_index_table().unwrap();
+
+        // then: the capacity is capped, and the table was probed rather than read
+        assert_eq!(capacity, R307_MAX_LIBRARY_SIZE);
+        assert_eq!(table.capacity(), R307_MAX_LIBRARY_SIZE);
+        assert_eq!(table.occupied().collect::<std::vec::Vec<_>>(), vec![5]);
+        assert_eq!(emulator.instructions().contains(&0x1f), false);
+    }
+}
@@ -0,0 +1,552 @@
+
+use embedded_hal::serial::{Read, Write};
+
+use crate::allocation::SlotAllocation;
+use crate::audit::AuditLog;
+use crate::commands::Command;
+use crate::compat::ModuleFamily;
+use crate::consts::PacketSize;
+use crate::driver::R502;
+use crate::library::IndexCache;
+use crate::profile::TimingProfile;
+use crate::stats::DriverStats;
+use crate::responses::*;
+use crate::transport::Transport;
+use crate::utils::Error;
+use crate::workflow::WorkflowTracker;
+
+/// `SetSysPara` parameter number of the baud rate.
+const BAUD_SETTING: u8 = 4;
+
+/// `SetSysPara` parameter number of the security level.
+const SECURITY_LEVEL: u8 = 5;
+
+/// `SetSysPara` parameter number of the data packet size.
+const PACKET_SIZE: u8 = 6;
+
+/// The system parameters `apply_config` should make the R502 match. Fields left as `None`
+/// are not touched. The values use the same codes as `SystemParameters`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeviceConfigTarget {
+    /// Security level [1-5].
+    pub security_level: Option<u8>,
+
+    /// Data packet size code [0-3]: 32, 64, 128 or 256 bytes.
+    pub packet_size: Option<u8>,
+
+    /// Baud rate as a multiple of 9600 [1-12].
+    pub baud_setting: Option<u8>,
+}
+
+/// What `apply_config` changed.
+#[derive(Debug, Clone, Copy)]
+pub struct ConfigReport {
+    /// The security level was written.
+    pub security_level_changed: bool,
+
+    /// The data packet size was written. The driver has been switched over to it.
+    pub packet_size_changed: bool,
+
+    /// The baud rate was written. The host UART has to be switched to the new rate
+    /// before talking to the R502 again.
+    pub baud_changed: bool,
+
+    /// The system parameters as read back after the other settings were written. If the
+    /// baud rate was changed, `baud_setting` here is still the old one.
+    pub parameters: SystemParameters,
+}
+
+impl ConfigReport {
+    /// True if the R502 already matched the target and nothing was written.
+    pub fn is_noop(&self) -> bool {
+        return !self.security_level_changed && !self.packet_size_changed && !self.baud_changed;
+    }
+}
+
+/// Error type for `apply_config`.
+#[derive(Debug)]
+pub enum ConfigError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// A value in the target is out of range for its parameter. Nothing was written.
+    InvalidTarget { parameter: u8, value: u8 },
+
+    /// `ReadSysPara` failed with the given confirmation code.
+    ReadSysPara(u8),
+
+    /// The R502 refused to write a parameter.
+    Rejected {
+        parameter: u8,
+        status: SetSysParaStatus,
+    },
+
+    /// The R502 accepted a write, but reading the parameters back shows the old value.
+    NotApplied { parameter: u8, value: u8 },
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for ConfigError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Makes the R502's system parameters match `target`, writing only the ones which differ.
+    ///
+    /// The parameters are read with `ReadSysPara`, the security level and packet size are
+    /// written with `SetSysPara` if needed, and the parameters are read again to confirm.
+    /// The baud rate is written last, as the link stops working at the old rate as soon as
+    /// the R502 has acknowledged it; `ConfigReport::baud_changed` says if that happened.
+    pub fn apply_config(
+        &mut self,
+        target: &DeviceConfigTarget,
+    ) -> Result<ConfigReport, ConfigError<T::WriteError, T::ReadError>> {
+        let checks = [
+            (SECURITY_LEVEL, target.security_level, 1..=5),
+            (PACKET_SIZE, target.packet_size, 0..=3),
+            (BAUD_SETTING, target.baud_setting, 1..=12),
+        ];
+        for (parameter, value, range) in checks.iter() {
+            if let Some(value) = value {
+                if !range.contains(value) {
+                    return Err(ConfigError::InvalidTarget {
+                        parameter: *parameter,
+                        value: *value,
+                    });
+                }
+            }
+        }
+
+        let current = self.read_system_parameters()?;
+        let security_level = differs(target.security_level, current.security_level);
+        let packet_size = differs(target.packet_size, current.packet_size);
+        let baud_setting = differs(target.baud_setting, current.baud_setting);
+
+        if let Some(value) = security_level {
+            self.set_sys_para(SECURITY_LEVEL, value)?;
+        }
+        if let Some(value) = packet_size {
+            self.set_sys_para(PACKET_SIZE, value)?;
+        }
+
+        let mut parameters = current;
+        if security_level.is_some() || packet_size.is_some() {
+            parameters = self.read_system_parameters()?;
+            if let Some(value) = security_level {
+                if parameters.security_level != value as u16 {
+                    return Err(ConfigError::NotApplied {
+                        parameter: SECURITY_LEVEL,
+                        value,
+                    });
+                }
+            }
+            if let Some(value) = packet_size {
+                if parameters.packet_size != value as u16 {
+                    return Err(ConfigError::NotApplied {
+                        parameter: PACKET_SIZE,
+                        value,
+                    });
+                }
+                if let Some(size) = PacketSize::from_code(value as u16) {
+                    self.set_data_packet_size(size);
+                }
+            }
+        }
+
+        if let Some(value) = baud_setting {
+            self.set_sys_para(BAUD_SETTING, value)?;
+        }
+
+        return Ok(ConfigReport {
+            security_level_changed: security_level.is_some(),
+            packet_size_changed: packet_size.is_some(),
+            baud_changed: baud_setting.is_some(),
+            parameters,
+        });
+    }
+
+    fn read_system_parameters(
+        &mut self,
+    ) -> Result<SystemParameters, ConfigError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(self.send_command(Command::ReadSysPara), Reply::ReadSysPara)?;
+        if result.confirmation_code != 0x00 {
+            return Err(ConfigError::ReadSysPara(result.confirmation_code));
+        }
+        return Ok(result.system_parameters);
+    }
+
+    fn set_sys_para(
+        &mut self,
+        parameter: u8,
+        value: u8,
+    ) -> Result<(), ConfigError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(
+            self.send_command(Command::SetSysPara { parameter, value }),
+            Reply::SetSysPara
+        )?;
+        return match result.confirmation_code {
+            SetSysParaStatus::Success => Ok(()),
+            status => Err(ConfigError::Rejected { parameter, status }),
+        };
+    }
+}
+
+/// What is kept of a driver while the host UART is switched to a new baud rate, see
+/// [`R502::change_baud`](struct.R502.html#method.change_baud): the driver's settings, and the
+/// rate to switch to.
+#[derive(Debug, Clone)]
+pub struct BaudChange {
+    address: u32,
+    baud_setting: u8,
+    data_packet_size: u16,
+    index_cache: IndexCache,
+    allocation: SlotAllocation,
+    family: ModuleFamily,
+    profile: TimingProfile,
+    stats: DriverStats,
+    workflow: WorkflowTracker,
+    adopt_address: bool,
+    audit: Option<AuditLog>,
+}
+
+/// Error type for `BaudChange::resume`: the module did not answer at the new rate. The serial
+/// port halves are handed back, to reconfigure and try again.
+#[derive(Debug)]
+pub struct ResumeError<TX, RX>
+where
+    TX: Write<u8>,
+    RX: Read<u8>,
+{
+    /// The transmitting half of the serial port.
+    pub tx: TX,
+
+    /// The receiving half of the serial port.
+    pub rx: RX,
+
+    /// Why the `HandShake` failed.
+    pub error: Error<TX::Error, RX::Error>,
+}
+
+/// The result of `R502::change_baud`: the serial port halves and what is needed to resume, or
+/// the driver as it was and why the module did not change its rate.
+pub type BaudChangeResult<TX, RX> = Result<
+    (TX, RX, BaudChange),
+    (
+        R502<(TX, RX)>,
+        ConfigError<<TX as Write<u8>>::Error, <RX as Read<u8>>::Error>,
+    ),
+>;
+
+impl<TX, RX> R502<(TX, RX)>
+where
+    TX: Write<u8>,
+    RX: Read<u8>,
+{
+    /// Switches the module to a baud rate of `baud_setting` times 9600 [1-12], and takes the
+    /// driver apart so that the host UART can follow.
+    ///
+    /// The module acknowledges `SetSysPara` at the old rate and only listens at the new one
+    /// after that, so nothing more can be said to it until the host has switched too. This
+    /// returns the serial port halves, to reconfigure or rebuild at
+    /// [`BaudChange::bps`](struct.BaudChange.html#method.bps), and a `BaudChange` whose
+    /// [`resume`](struct.BaudChange.html#method.resume) puts the driver back together and
+    /// checks the module answers.
+    ///
+    /// ```ignore
+    /// let (tx, rx, change) = r502.change_baud(12).map_err(|(_, error)| error)?;
+    /// let (tx, rx) = reconfigure_uart(tx, rx, change.bps());
+    /// let mut r502 = change.resume(tx, rx).map_err(|error| error.error)?;
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If the module did not take the new rate, the driver is handed back untouched, still at
+    /// the old rate, with `ConfigError::InvalidTarget` for a setting out of range, in which
+    /// case nothing was sent, `ConfigError::Rejected` if the module refused it, or
+    /// `ConfigError::Comms`.
+    #[allow(clippy::result_large_err)]
+    pub fn change_baud(mut self, baud_setting: u8) -> BaudChangeResult<TX, RX> {
+        if !(1..=12).contains(&baud_setting) {
+            let error = ConfigError::InvalidTarget { parameter: BAUD_SETTING, value: baud_setting };
+            return Err((self, error));
+        }
+        if let Err(error) = self.set_sys_para(BAUD_SETTING, baud_setting) {
+            return Err((self, error));
+        }
+        let change = BaudChange {
+            address: self.address,
+            baud_setting,
+            data_packet_size: self.data_packet_size,
+            index_cache: self.index_cache,
+            allocation: self.allocation,
+            family: self.family,
+            profile: self.profile,
+            stats: self.stats,
+            workflow: self.workflow,
+            adopt_address: self.adopt_address,
+            audit: self.audit,
+        };
+        let (tx, rx) = self.transport;
+        return Ok((tx, rx, change));
+    }
+}
+
+impl BaudChange {
+    /// The new baud rate setting, in units of 9600.
+    pub fn baud_setting(&self) -> u8 {
+        return self.baud_setting;
+    }
+
+    /// The new baud rate, in bits per second, to set the host UART to.
+    pub fn bps(&self) -> u32 {
+        return self.baud_setting as u32 * 9600;
+    }
+
+    /// Puts the driver back together on `tx` and `rx`, which should now run at
+    /// [`bps`](#method.bps), with the settings it had, and checks with a `HandShake` that the
+    /// module answers. Any reply will do, so this works with firmware which refuses
+    /// `HandShake` too.
+    ///
+    /// On failure the halves are handed back in the `ResumeError`, and this can be called
+    /// again, for example once the UART has been set up properly.
+    #[allow(clippy::result_large_err)]
+    pub fn resume<TX, RX>(&self, tx: TX, rx: RX) -> Result<R502<(TX, RX)>, ResumeError<TX, RX>>
+    where
+        TX: Write<u8>,
+        RX: Read<u8>,
+    {
+        let mut r502 = R502::new(tx, rx, self.address);
+        r502.data_packet_size = self.data_packet_size;
+        r502.index_cache = self.index_cache.clone();
+        r502.allocation = self.allocation;
+        r502.family = self.family;
+        r502.profile = self.profile.clone();
+        r502.stats = self.stats.clone();
+        r502.workflow = self.workflow;
+        r502.adopt_address = self.adopt_address;
+        r502.audit = self.audit;
+        if let Err(error) = expect_reply!(r502.send_command(Command::HandShake), Reply::HandShake) {
+            let (tx, rx) = r502.transport;
+            return Err(ResumeError { tx, rx, error });
+        }
+        return Ok(r502);
+    }
+}
+
+/// The target value, if there is one and it differs from the current value.
+fn differs(target: Option<u8>, current: u16) -> Option<u8> {
+    return target.filter(|value| *value as u16 != current);
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::emulator::Emulator;
+    use std::vec;
+
+    #[test]
+    fn test_apply_config_noop() {
+        // given: a module with the default settings
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: applying the settings it already has
+        let target = DeviceConfigTarget {
+            security_level: Some(3),
+            packet_size: Some(2),
+            baud_setting: Some(6),
+        };
+        let report = r502.apply_config(&target).unwrap();
+
+        // then: nothing is written
+        assert_eq!(report.is_noop(), true);
+        assert_eq!(emulator.instructions(), vec![0x0f]);
+    }
+
+    #[test]
+    fn test_apply_config_single_field() {
+        // given: a module with the default settings
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: raising the security level
+        let target = DeviceConfigTarget {
+            security_level: Some(5),
+            ..DeviceConfigTarget::default()
+        };
+        let report = r502.apply_config(&target).unwrap();
+
+        // then: only the security level is written, and then confirmed
+        assert_eq!(report.security_level_changed, true);
+        assert_eq!(report.is_noop(), false);
+        assert_eq!(report.parameters.security_level, 5);
+        assert_eq!(emulator.state().sys_para_writes, vec![(5, 5)]);
+        assert_eq!(emulator.instructions(), vec![0x0f, 0x0e, 0x0f]);
+    }
+
+    #[test]
+    fn test_apply_config_baud_last() {
+        // given: a module with the default settings
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: changing every setting
+        let target = DeviceConfigTarget {
+            security_level: Some(4),
+            packet_size: Some(3),
+            baud_setting: Some(12),
+        };
+        let report = r502.apply_config(&target).unwrap();
+
+        // then: the baud rate is written last, after the other settings were confirmed
+        assert_eq!(
+            emulator.state().sys_para_writes,
+            vec![(5, 4), (6, 3), (4, 12)]
+        );
+        assert_eq!(emulator.instructions(), vec![0x0f, 0x0e, 0x0e, 0x0f, 0x0e]);
+
+        // and: the report says the baud rate changed
+        assert_eq!(report.baud_changed, true);
+        assert_eq!(report.parameters.packet_size, 3);
+        assert_eq!(emulator.state().baud_setting, 12);
+    }
+
+    #[test]
+    fn test_apply_config_invalid_target() {
+        // given: a module with the default settings
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: asking for a security level that does not exist
+        let target = DeviceConfigTarget {
+            security_level: Some(6),
+            ..DeviceConfigTarget::default()
+        };
+        let result = r502.apply_config(&target);
+
+        // then: nothing is sent to the module
+        match result {
+            Err(ConfigError::InvalidTarget {
+                parameter: 5,
+                value: 6,
+            }) => {}
+            other => panic!("Expected ConfigError::InvalidTarget, got {:?}", other),
+        };
+        assert_eq!(emulator.instructions().is_empty(), true);
+    }
+
+    /// A module at the default 57600 baud, talked to over a link which is just as fast.
+    fn linked_emulator() -> Emulator {
+        let emulator = Emulator::new();
+        emulator.state().link_baud = Some(6);
+        return emulator;
+    }
+
+    #[test]
+    fn test_change_baud() {
+        // given: a module at 57600 baud, and a driver with two reserved slots
+        let emulator = linked_emulator();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        let allocation = SlotAllocation { reserved_count: 2, ..SlotAllocation::new() };
+        r502.set_slot_allocation(allocation);
+
+        // when: changing to 115200 baud
+        let (tx, rx, change) = r502.change_baud(12).map_err(|(_, error)| error).unwrap();
+
+        // then: the module acknowledged at the old rate, and now listens at the new one
+        assert_eq!(change.baud_setting(), 12);
+        assert_eq!(change.bps(), 115200);
+        assert_eq!(emulator.state().sys_para_writes, vec![(4, 12)]);
+        assert_eq!(emulator.state().baud_setting, 12);
+
+        // when: switching the host UART over and resuming
+        emulator.state().link_baud = Some(12);
+        let mut r502 = change.resume(tx, rx).map_err(|error| error.error).unwrap();
+
+        // then: the module answered the HandShake, and the driver kept its settings
+        assert_eq!(emulator.instructions(), vec![0x0e, 0x40]);
+        assert_eq!(r502.slot_allocation(), allocation);
+        assert_eq!(r502.send_command(Command::TemplateNum).is_ok(), true);
+    }
+
+    #[test]
+    fn test_resume_at_old_rate() {
+        // given: a module switched to 115200 baud
+        let emulator = linked_emulator();
+        let (tx, rx) = emulator.serial();
+        let r502 = R502::new(tx, rx, 0xffffffff);
+        let (tx, rx, change) = r502.change_baud(12).map_err(|(_, error)| error).unwrap();
+
+        // when: resuming without switching the host UART
+        let error = match change.resume(tx, rx) {
+            Err(error) => error,
+            Ok(_) => panic!("Expected the HandShake to fail"),
+        };
+
+        // then: the module never heard the HandShake, and the halves are handed back
+        assert_eq!(matches!(error.error, Error::RecvReadError(_)), true);
+        assert_eq!(emulator.instructions(), vec![0x0e]);
+
+        // when: switching the host UART over and trying again
+        emulator.state().link_baud = Some(12);
+        let result = change.resume(error.tx, error.rx);
+
+        // then: the module answers
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(emulator.instructions(), vec![0x0e, 0x40]);
+    }
+
+    #[test]
+    fn test_change_baud_rejected() {
+        // given: a module which refuses the next SetSysPara
+        let emulator = linked_emulator();
+        emulator.fail_next(0x0e, 0x1a);
+        let (tx, rx) = emulator.serial();
+        let r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: changing the baud rate
+        let (mut r502, error) = match r502.change_baud(12) {
+            Err(failure) => failure,
+            Ok(_) => panic!("Expected the change to be refused"),
+        };
+
+        // then: the driver is handed back, still talking at the old rate
+        let rejected = matches!(
+            error,
+            ConfigError::Rejected { parameter: 4, status: SetSysParaStatus::WrongRegister }
+        );
+        assert_eq!(rejected, true);
+        assert_eq!(emulator.state().baud_setting, 6);
+        assert_eq!(r502.send_command(Command::TemplateNum).is_ok(), true);
+    }
+
+    #[test]
+    fn test_change_baud_invalid_setting() {
+        // given: a module at 57600 baud
+        let emulator = linked_emulator();
+        let (tx, rx) = emulator.serial();
+        let r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: asking for a rate the module does not have
+        let result = r502.change_baud(13);
+
+        // then: nothing is sent to the module
+        let invalid = matches!(
+            result,
+            Err((_, ConfigError::InvalidTarget { parameter: 4, value: 13 }))
+        );
+        assert_eq!(invalid, true);
+        assert_eq!(emulator.instructions().is_empty(), true);
+    }
+}
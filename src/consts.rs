@@ -0,0 +1,278 @@
+//! The dimensions of the protocol and of what goes over it: packets, templates, images and the
+//! notepad. Everything here is `const`, for sizing DMA and scratch buffers at compile time.
+
+/// Length of the packet header: start code, address, packet ID and length. Once this much of
+/// a packet is in, `frame_length` tells how long the whole packet is.
+pub const FRAME_HEADER_LENGTH: usize = 9;
+
+/// Length of the checksum which ends every packet.
+pub const FRAME_CHECKSUM_LENGTH: usize = 2;
+
+/// Longest command packet `encode_command` produces.
+pub const MAX_COMMAND_LENGTH: usize = 128;
+
+/// Longest packet the drivers can receive, and the default receive buffer of `R502`. This is
+/// well above `max_frame_len(PacketSize::Bytes256)`, the longest packet a module sends.
+pub const MAX_PACKET_LENGTH: usize = 1024;
+
+/// Length of a template (_character file_) of the R502, as `UpChar` sends it.
+pub const R502_TEMPLATE_LEN: usize = 1536;
+
+/// Length of a template of the R307 and other older modules.
+pub const R307_TEMPLATE_LEN: usize = 512;
+
+/// Width in pixels of the R502's sensor.
+pub const R502_IMAGE_WIDTH: u32 = 192;
+
+/// Height in pixels of the R502's sensor.
+pub const R502_IMAGE_HEIGHT: u32 = 192;
+
+/// Width in pixels of the R307's sensor.
+pub const R307_IMAGE_WIDTH: u32 = 256;
+
+/// Height in pixels of the R307's sensor.
+pub const R307_IMAGE_HEIGHT: u32 = 288;
+
+/// Length of an image from the R502's sensor: 192 by 192 pixels, two to a byte.
+pub const R502_IMAGE_LEN: usize = (R502_IMAGE_WIDTH * R502_IMAGE_HEIGHT / 2) as usize;
+
+/// Length of an image from the R307's sensor: 256 by 288 pixels, two to a byte.
+pub const R307_IMAGE_LEN: usize = (R307_IMAGE_WIDTH * R307_IMAGE_HEIGHT / 2) as usize;
+
+/// Number of pages in the notepad.
+pub const NOTEPAD_PAGES: u8 = 16;
+
+/// Size of a notepad page in bytes.
+pub const NOTEPAD_PAGE_SIZE: usize = 32;
+
+/// The size of the data packets of template and image transfers, which the module keeps as
+/// its `PacketSize` parameter (`SystemParameters::packet_size`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PacketSize {
+    /// 32 bytes, parameter value 0.
+    Bytes32,
+
+    /// 64 bytes, parameter value 1.
+    Bytes64,
+
+    /// 128 bytes, parameter value 2. The default.
+    Bytes128,
+
+    /// 256 bytes, parameter value 3.
+    Bytes256,
+}
+
+/// The packet size modules leave the factory with.
+pub const DEFAULT_PACKET_SIZE: PacketSize = PacketSize::Bytes128;
+
+impl PacketSize {
+    /// The size with the parameter value `code`, 0 to 3, as `ReadSysPara` reports it.
+    pub const fn from_code(code: u16) -> Option<Self> {
+        return match code {
+            0 => Some(Self::Bytes32),
+            1 => Some(Self::Bytes64),
+            2 => Some(Self::Bytes128),
+            3 => Some(Self::Bytes256),
+            _ => None,
+        };
+    }
+
+    /// The parameter value for this size, as `SetSysPara` takes it.
+    pub const fn code(self) -> u8 {
+        return match self {
+            Self::Bytes32 => 0,
+            Self::Bytes64 => 1,
+            Self::Bytes128 => 2,
+            Self::Bytes256 => 3,
+        };
+    }
+
+    /// Bytes of data in each data packet.
+    pub const fn bytes(self) -> u16 {
+        return 32 << self.code();
+    }
+}
+
+impl Default for PacketSize {
+    fn default() -> Self {
+        return DEFAULT_PACKET_SIZE;
+    }
+}
+
+/// Length of the longest data packet with packets of `size`: the header, the data and the
+/// checksum. A receive buffer this long takes templates and images at that packet size.
+pub const fn max_frame_len(size: PacketSize) -> usize {
+    return FRAME_HEADER_LENGTH + size.bytes() as usize + FRAME_CHECKSUM_LENGTH;
+}
+
+/// Length of a buffer which holds an image from the sensor of any supported module; use
+/// `ModuleFamily::image_len` for the length of a particular family's images.
+pub const fn image_buf_len() -> usize {
+    if R502_IMAGE_LEN > R307_IMAGE_LEN {
+        return R502_IMAGE_LEN;
+    }
+    return R307_IMAGE_LEN;
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::compat::ModuleFamily;
+    use crate::emulator::CHAR_FILE_LEN;
+    use crate::template::TEMPLATE_CAPACITY;
+    #[cfg(feature = "cmd-transfer")]
+    use crate::buffer::ByteBuffer;
+    #[cfg(feature = "cmd-transfer")]
+    use crate::codec;
+    #[cfg(feature = "cmd-transfer")]
+    use crate::commands::Command;
+    #[cfg(feature = "cmd-transfer")]
+    use crate::driver::R502;
+    #[cfg(feature = "cmd-transfer")]
+    use crate::emulator::{char_file, Emulator};
+    #[cfg(feature = "cmd-transfer")]
+    use crate::observer::{Observed, WireObserver};
+    #[cfg(feature = "cmd-transfer")]
+    use crate::parser::ReplyParser;
+    #[cfg(feature = "cmd-transfer")]
+    use crate::utils::Error;
+    #[cfg(feature = "cmd-transfer")]
+    use std::cell::RefCell;
+    #[cfg(feature = "cmd-transfer")]
+    use std::rc::Rc;
+    #[cfg(feature = "cmd-transfer")]
+    use std::vec::Vec;
+
+    const SIZES: [PacketSize; 4] =
+        [PacketSize::Bytes32, PacketSize::Bytes64, PacketSize::Bytes128, PacketSize::Bytes256];
+
+    /// The longest packet a module sends.
+    #[cfg(feature = "cmd-transfer")]
+    const LONGEST: usize = max_frame_len(PacketSize::Bytes256);
+
+    /// Keeps the lengths of the frames the driver writes and reads.
+    #[cfg(feature = "cmd-transfer")]
+    struct Lengths(Rc<RefCell<(Vec<usize>, Vec<usize>)>>);
+
+    #[cfg(feature = "cmd-transfer")]
+    impl WireObserver for Lengths {
+        fn on_tx(&mut self, frame: &[u8]) {
+            self.0.borrow_mut().0.push(frame.len());
+        }
+
+        fn on_rx(&mut self, frame: &[u8]) {
+            self.0.borrow_mut().1.push(frame.len());
+        }
+    }
+
+    #[test]
+    fn test_packet_size_codes() {
+        for (code, size) in SIZES.iter().enumerate() {
+            assert_eq!(PacketSize::from_code(code as u16), Some(*size));
+            assert_eq!(size.code() as usize, code);
+            assert_eq!(size.bytes(), 32 << code);
+        }
+        assert_eq!(PacketSize::from_code(4), None);
+        assert_eq!(PacketSize::default().bytes(), 128);
+    }
+
+    #[test]
+    fn test_dimensions() {
+        assert_eq!(R502_IMAGE_LEN, 18432);
+        assert_eq!(image_buf_len(), 36864);
+        assert_eq!(max_frame_len(PacketSize::Bytes32), 43);
+        assert_eq!(max_frame_len(PacketSize::Bytes256), 267);
+        assert_eq!(CHAR_FILE_LEN, R502_TEMPLATE_LEN);
+        assert_eq!(R502_TEMPLATE_LEN <= TEMPLATE_CAPACITY, true);
+        for family in [ModuleFamily::R502, ModuleFamily::R307].iter() {
+            let pixels = family.image_width() * family.image_height();
+            assert_eq!(family.image_len(), pixels as usize / 2);
+            assert_eq!(family.image_len() <= image_buf_len(), true);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_parser_takes_longest_data_packet() {
+        // given: a data packet of 256 bytes, as the module sends them at its largest size
+        let data = [0xa5u8; PacketSize::Bytes256.bytes() as usize];
+        let mut header = ByteBuffer::<FRAME_HEADER_LENGTH>::new();
+        let chk = codec::encode_data_header(&mut header, 0xffffffff, &data, true);
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&header);
+        frame.extend_from_slice(&data);
+        frame.extend_from_slice(&chk);
+
+        // when: pushing it into a parser
+        let mut parser = ReplyParser::new();
+        let mut taken = None;
+        for byte in frame.iter() {
+            if let Some(result) = parser.push(*byte) {
+                taken = Some(result.unwrap().as_bytes().len());
+            }
+        }
+
+        // then: the whole packet comes out, and is as long as the constants say
+        assert_eq!(frame.len(), max_frame_len(PacketSize::Bytes256));
+        assert_eq!(frame.len() <= MAX_PACKET_LENGTH, true);
+        assert_eq!(taken, Some(frame.len()));
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_transfer_frames_agree() {
+        for size in SIZES.iter() {
+            // given: a module and a driver with packets of `size`, and a receive buffer just
+            // long enough for the largest packets
+            let emulator = Emulator::new();
+            emulator.state().packet_size = size.bytes() as usize;
+            emulator.state().buffers[0] = Some(char_file(7));
+            let lengths = Rc::new(RefCell::new((Vec::new(), Vec::new())));
+            let observed = Observed::new(emulator.serial(), Lengths(lengths.clone()));
+            let mut r502 = R502::<_, LONGEST, 64>::with_buffer_sizes(observed, 0xffffffff);
+            r502.set_data_packet_size(*size);
+
+            // when: uploading a template, and downloading it again
+            let mut template = Vec::new();
+            r502.send_command(Command::UpChar { buffer: 1 }).unwrap();
+            r502.receive_data(|data| template.extend_from_slice(data)).unwrap();
+            r502.send_command(Command::DownChar { buffer: 2 }).unwrap();
+            r502.send_data(&template).unwrap();
+
+            // then: the data packets either way are `max_frame_len(size)` long
+            let packets = R502_TEMPLATE_LEN / size.bytes() as usize;
+            let full = |lengths: &Vec<usize>| {
+                return lengths.iter().filter(|len| **len == max_frame_len(*size)).count();
+            };
+            let lengths = lengths.borrow();
+            assert_eq!(template.len(), R502_TEMPLATE_LEN);
+            assert_eq!(full(&lengths.0), packets);
+            assert_eq!(full(&lengths.1), packets);
+            assert_eq!(emulator.state().buffers[1], Some(char_file(7)));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_receive_buffer_one_short() {
+        // given: a module sending 256-byte packets, and a receive buffer one byte too short
+        let emulator = Emulator::new();
+        emulator.state().packet_size = PacketSize::Bytes256.bytes() as usize;
+        emulator.state().buffers[0] = Some(char_file(7));
+        let mut r502 =
+            R502::<_, { LONGEST - 1 }, 64>::with_buffer_sizes(emulator.serial(), 0xffffffff);
+
+        // when: uploading a template
+        r502.send_command(Command::UpChar { buffer: 1 }).unwrap();
+        let result = r502.receive_data(|_| {});
+
+        // then: the first data packet does not fit
+        assert_eq!(
+            matches!(result, Err(Error::RecvPacketTooLong { length }) if length == LONGEST),
+            true
+        );
+    }
+}
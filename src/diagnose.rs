@@ -0,0 +1,302 @@
+
+#[cfg(feature = "cmd-enroll")]
+use crate::commands::Command;
+#[cfg(feature = "cmd-enroll")]
+use crate::driver::R502;
+#[cfg(feature = "cmd-enroll")]
+use crate::maintenance::StoreVerifyError;
+#[cfg(feature = "cmd-enroll")]
+use crate::responses::*;
+#[cfg(feature = "cmd-enroll")]
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// Outcome of one of the checks run by `diagnose`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    /// The check ran and passed.
+    Passed,
+
+    /// The check ran and failed.
+    Failed,
+
+    /// The check could not run: the firmware does not support it, or it depends on a check
+    /// which did not pass.
+    NotRun,
+}
+
+/// Result of `diagnose`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiagnosisReport {
+    /// Result of `CheckSensor`.
+    pub sensor: Check,
+
+    /// True if a finger was on the sensor when the capture check ran.
+    pub finger_presented: bool,
+
+    /// Capturing an image and converting it to a template. Not run without a finger.
+    pub capture: Check,
+
+    /// Storing the captured template in the scratch slot, loading it back and matching it.
+    /// Not run unless the capture check passed.
+    pub flash: Check,
+
+    /// True if the scratch slot was left empty. Only false if deleting it failed.
+    pub scratch_cleared: bool,
+
+    /// Round-trip time of a `HandShake`, in the units of the clock passed to `diagnose`.
+    pub handshake_time: u32,
+
+    /// Round-trip time of a `ReadSysPara`, whose reply is one of the longest.
+    pub status_time: u32,
+}
+
+impl DiagnosisReport {
+    /// True if no check failed and the scratch slot was cleared.
+    pub fn passed(&self) -> bool {
+        return self.sensor != Check::Failed
+            && self.capture != Check::Failed
+            && self.flash != Check::Failed
+            && self.scratch_cleared;
+    }
+}
+
+/// Error type for `diagnose`.
+#[derive(Debug)]
+pub enum DiagnoseError<TXE, RXE> {
+    /// Communication with the R502 failed. If this happened during the flash check, the
+    /// scratch slot was still deleted if possible.
+    Comms(Error<TXE, RXE>),
+
+    /// `ReadSysPara` failed with the given confirmation code.
+    ReadSysPara(u8),
+
+    /// The scratch slot is not in the library. Nothing beyond `ReadSysPara` was sent.
+    InvalidScratchSlot(u16),
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for DiagnoseError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+#[cfg(feature = "cmd-enroll")]
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Runs an invasive self-test, for the production line rather than the field.
+    ///
+    /// Times a `ReadSysPara` and a `HandShake`, runs `CheckSensor`, then captures a finger if
+    /// one is on the sensor. The captured template is stored at `scratch_slot`, loaded back and
+    /// matched to check the flash, and the slot is then deleted again, whether the check
+    /// passed or not. No other slot is touched.
+    ///
+    /// `now` reads a free-running timer in whatever unit suits the caller; the round-trip
+    /// times are reported in that unit, and may wrap.
+    ///
+    /// **Note:** Anything stored at `scratch_slot` is deleted, and the contents of both
+    /// _character buffers_ are overwritten. Needs the `cmd-enroll` feature, for `Store`.
+    pub fn diagnose<F>(
+        &mut self,
+        scratch_slot: u16,
+        mut now: F,
+    ) -> Result<DiagnosisReport, DiagnoseError<T::WriteError, T::ReadError>>
+    where
+        F: FnMut() -> u32,
+    {
+        let start = now();
+        let result = expect_reply!(self.send_command(Command::ReadSysPara), Reply::ReadSysPara)?;
+        let status_time = now().wrapping_sub(start);
+        if result.confirmation_code != 0x00 {
+            return Err(DiagnoseError::ReadSysPara(result.confirmation_code));
+        }
+        let library_size = self.usable_library_size(result.system_parameters.finger_library_size);
+        if scratch_slot >= library_size {
+            return Err(DiagnoseError::InvalidScratchSlot(scratch_slot));
+        }
+
+        let start = now();
+        expect_reply!(self.send_command(Command::HandShake), Reply::HandShake)?;
+        let handshake_time = now().wrapping_sub(start);
+
+        let result = expect_reply!(self.send_command(Command::CheckSensor), Reply::CheckSensor)?;
+        let sensor = match result.confirmation_code {
+            CheckSensorStatus::Success => Check::Passed,
+            CheckSensorStatus::PacketError => Check::NotRun,
+            CheckSensorStatus::SensorAbnormal => Check::Failed,
+        };
+
+        let mut report = DiagnosisReport {
+            sensor,
+            finger_presented: true,
+            capture: Check::NotRun,
+            flash: Check::NotRun,
+            scratch_cleared: true,
+            handshake_time,
+            status_time,
+        };
+
+        let result = expect_reply!(self.send_command(Command::GenImg), Reply::GenImg)?;
+        report.capture = match result.confirmation_code {
+            GenImgStatus::Success => {
+                let result = expect_reply!(
+                    self.send_command(Command::Img2Tz { buffer: 1 }),
+                    Reply::Img2Tz
+                )?;
+                match result.confirmation_code {
+                    Img2TzStatus::Success => Check::Passed,
+                    _ => Check::Failed,
+                }
+            }
+            GenImgStatus::FingerNotDetected => {
+                report.finger_presented = false;
+                Check::NotRun
+            }
+            _ => Check::Failed,
+        };
+        if report.capture != Check::Passed {
+            return Ok(report);
+        }
+
+        let stored = self.store_and_verify(1, scratch_slot, false);
+        let cleared = expect_reply!(
+            self.send_command(Command::DeletChar { start_index: scratch_slot, num_to_delete: 1 }),
+            Reply::DeletChar
+        );
+        report.flash = match stored {
+            Ok(()) => Check::Passed,
+            Err(StoreVerifyError::Comms(error)) => return Err(DiagnoseError::Comms(error)),
+            Err(_) => Check::Failed,
+        };
+        report.scratch_cleared = cleared?.confirmation_code == DeletCharStatus::Success;
+        return Ok(report);
+    }
+}
+
+#[cfg(all(test, feature = "cmd-enroll"))]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+
+    /// A clock which advances by 5 every time it is read.
+    fn clock() -> impl FnMut() -> u32 {
+        let mut time = 0u32;
+        return move || {
+            time += 5;
+            time
+        };
+    }
+
+    #[test]
+    fn test_diagnose() {
+        // given: a module with templates either side of the scratch slot, and a finger on it
+        let emulator = Emulator::new();
+        emulator.enroll(9, 3);
+        emulator.enroll(11, 4);
+        emulator.touch(&[Some(7)]);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: diagnosing it
+        let report = r502.diagnose(10, clock()).unwrap();
+
+        // then: every check passed
+        assert_eq!(report.passed(), true);
+        assert_eq!(
+            report,
+            DiagnosisReport {
+                sensor: Check::Passed,
+                finger_presented: true,
+                capture: Check::Passed,
+                flash: Check::Passed,
+                scratch_cleared: true,
+                handshake_time: 5,
+                status_time: 5,
+            }
+        );
+
+        // and: the scratch slot is empty again, and its neighbours untouched
+        assert_eq!(emulator.slot(10), None);
+        assert_eq!(emulator.slot(9).unwrap()[0], 3);
+        assert_eq!(emulator.slot(11).unwrap()[0], 4);
+        assert_eq!(
+            emulator.instructions(),
+            [0x0f, 0x40, 0x36, 0x01, 0x02, 0x06, 0x07, 0x03, 0x0c]
+        );
+    }
+
+    #[test]
+    fn test_diagnose_without_finger() {
+        // given: a module with nothing on the sensor
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: diagnosing it
+        let report = r502.diagnose(10, clock()).unwrap();
+
+        // then: the capture and flash checks did not run, and nothing was stored
+        assert_eq!(report.passed(), true);
+        assert_eq!(report.finger_presented, false);
+        assert_eq!(report.capture, Check::NotRun);
+        assert_eq!(report.flash, Check::NotRun);
+        assert_eq!(emulator.instructions().contains(&0x06), false);
+    }
+
+    #[test]
+    fn test_diagnose_flash_failure() {
+        // given: a module whose next flash write leaves an unreadable slot
+        let emulator = Emulator::new();
+        emulator.state().corrupt_stores = 1;
+        emulator.touch(&[Some(7)]);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: diagnosing it
+        let report = r502.diagnose(10, clock()).unwrap();
+
+        // then: the flash check failed, but the scratch slot was still cleaned up
+        assert_eq!(report.passed(), false);
+        assert_eq!(report.flash, Check::Failed);
+        assert_eq!(report.scratch_cleared, true);
+        assert_eq!(emulator.slot(10), None);
+    }
+
+    #[test]
+    fn test_diagnose_store_failure() {
+        // given: a module which fails to write to flash
+        let emulator = Emulator::new();
+        emulator.fail_next(0x06, 0x18);
+        emulator.touch(&[Some(7)]);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: diagnosing it
+        let report = r502.diagnose(10, clock()).unwrap();
+
+        // then: the flash check failed, and the scratch slot was deleted regardless
+        assert_eq!(report.flash, Check::Failed);
+        assert_eq!(report.scratch_cleared, true);
+        assert_eq!(emulator.instructions().last(), Some(&0x0c));
+    }
+
+    #[test]
+    fn test_diagnose_invalid_scratch_slot() {
+        // given: a 200-slot module
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: diagnosing it with a scratch slot past the end of the library
+        let result = r502.diagnose(200, clock());
+
+        // then: nothing but ReadSysPara was sent
+        match result {
+            Err(DiagnoseError::InvalidScratchSlot(200)) => {}
+            other => panic!("Expected DiagnoseError::InvalidScratchSlot, got {:?}", other),
+        };
+        assert_eq!(emulator.instructions(), [0x0f]);
+    }
+}
@@ -0,0 +1,572 @@
+use core::fmt;
+
+use crate::responses::*;
+
+/// Implements `Display` for a status code, one short phrase per variant, and `uDisplay` with
+/// the same phrases under the `ufmt` feature.
+macro_rules! status_display {
+    ($status:ty { $($(#[$attr:meta])* $variant:ident => $text:expr),+ $(,)? }) => {
+        impl fmt::Display for $status {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                return f.write_str(match self {
+                    $($(#[$attr])* Self::$variant => $text,)+
+                });
+            }
+        }
+
+        #[cfg(feature = "ufmt")]
+        impl ufmt::uDisplay for $status {
+            fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+            where
+                W: ufmt::uWrite + ?Sized,
+            {
+                return f.write_str(match self {
+                    $($(#[$attr])* Self::$variant => $text,)+
+                });
+            }
+        }
+    };
+}
+
+/// Implements `Display` for a result which carries nothing but its status code, and
+/// `uDisplay` under the `ufmt` feature.
+macro_rules! result_display {
+    ($($result:ty => $name:expr),+ $(,)?) => {
+        $(
+            impl fmt::Display for $result {
+                fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                    return write!(f, "{}: {}", $name, self.confirmation_code);
+                }
+            }
+
+            #[cfg(feature = "ufmt")]
+            impl ufmt::uDisplay for $result {
+                fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
+                where
+                    W: ufmt::uWrite + ?Sized,
+                {
+                    f.write_str($name)?;
+                    f.write_str(": ")?;
+                    return ufmt::uDisplay::fmt(&self.confirmation_code, f);
+                }
+            }
+        )+
+    };
+}
+
+status_display!(PasswordVerificationState {
+    Correct => "password correct",
+    Incorrect => "wrong password",
+    Error => "packet error",
+});
+status_display!(GenImgStatus {
+    Success => "image captured",
+    PacketError => "packet error",
+    FingerNotDetected => "no finger on the sensor",
+    ImageNotCaptured => "image not captured",
+});
+status_display!(Img2TzStatus {
+    Success => "features extracted",
+    PacketError => "packet error",
+    FingerprintImageDistorted => "image too distorted",
+    ProcessingFailed => "too few features in the image",
+    InvalidInput => "no valid image",
+});
+status_display!(SearchStatus {
+    Success => "match found",
+    PacketError => "packet error",
+    NoMatch => "no match",
+    #[cfg(feature = "r503")]
+    LibraryEmpty => "library empty",
+});
+status_display!(LoadCharStatus {
+    Success => "template loaded",
+    PacketError => "packet error",
+    LibraryReadError => "library read error",
+    IndexOutOfRange => "slot out of range",
+    #[cfg(feature = "r503")]
+    TemplateEmpty => "slot empty",
+});
+status_display!(MatchStatus {
+    Success => "fingers match",
+    PacketError => "packet error",
+    NoMatch => "no match",
+});
+status_display!(TemplateNumStatus { Success => "ok", PacketError => "packet error" });
+status_display!(ReadIndexTableStatus { Success => "ok", PacketError => "packet error" });
+status_display!(RegModelStatus {
+    Success => "template created",
+    PacketError => "packet error",
+    ProcessingError => "captures are not of the same finger",
+});
+status_display!(StoreStatus {
+    Success => "template stored",
+    PacketError => "packet error",
+    IndexOutOfRange => "slot out of range",
+    WriteError => "flash write error",
+    #[cfg(feature = "r503")]
+    LibraryFull => "library full",
+});
+status_display!(UpCharStatus {
+    Success => "upload started",
+    PacketError => "packet error",
+    UploadFailed => "upload failed",
+});
+status_display!(DownCharStatus {
+    Success => "ready to receive",
+    PacketError => "packet error",
+    CannotReceive => "cannot receive data",
+});
+status_display!(DownImageStatus {
+    Success => "ready to receive",
+    PacketError => "packet error",
+    CannotReceive => "cannot receive data",
+});
+status_display!(SetPwdStatus { Success => "password set", PacketError => "packet error" });
+status_display!(SetSysParaStatus {
+    Success => "parameter set",
+    PacketError => "packet error",
+    WrongRegister => "no such parameter",
+});
+status_display!(SetAdderStatus { Success => "address set", PacketError => "packet error" });
+status_display!(GetChipSNStatus { Success => "ok", PacketError => "packet error" });
+status_display!(GetRandomCodeStatus { Success => "ok", PacketError => "packet error" });
+status_display!(HandShakeStatus { Success => "module ready", PacketError => "packet error" });
+status_display!(SoftRstStatus { Success => "module reset", PacketError => "packet error" });
+status_display!(GetFwVerStatus { Success => "ok", PacketError => "packet error" });
+status_display!(GetAlgVerStatus { Success => "ok", PacketError => "packet error" });
+status_display!(WriteNotepadStatus {
+    Success => "page written",
+    PacketError => "packet error",
+    WriteError => "flash write error",
+});
+status_display!(ReadNotepadStatus { Success => "ok", PacketError => "packet error" });
+status_display!(SleepStatus { Success => "module asleep", PacketError => "packet error" });
+status_display!(PortControlStatus {
+    Success => "port switched",
+    PacketError => "packet error",
+    PortOperationFailed => "port operation failed",
+});
+status_display!(AuraLedConfigStatus { Success => "LED set", PacketError => "packet error" });
+status_display!(CheckSensorStatus {
+    Success => "sensor working",
+    PacketError => "packet error",
+    SensorAbnormal => "sensor abnormal",
+});
+status_display!(DeletCharStatus {
+    Success => "templates deleted",
+    PacketError => "packet error",
+    DeleteFailed => "delete failed",
+});
+status_display!(EmptyStatus {
+    Success => "library emptied",
+    PacketError => "packet error",
+    ClearFailed => "clear failed",
+});
+
+result_display!(
+    VfyPwdResult => "VfyPwd",
+    GenImgResult => "GenImg",
+    Img2TzResult => "Img2Tz",
+    LoadCharResult => "LoadChar",
+    RegModelResult => "RegModel",
+    StoreResult => "Store",
+    UpCharResult => "UpChar",
+    DownCharResult => "DownChar",
+    DownImageResult => "DownImage",
+    SetPwdResult => "SetPwd",
+    SetSysParaResult => "SetSysPara",
+    SetAdderResult => "SetAdder",
+    HandShakeResult => "HandShake",
+    SoftRstResult => "SoftRst",
+    WriteNotepadResult => "WriteNotepad",
+    SleepResult => "Sleep",
+    PortControlResult => "PortControl",
+    CheckSensorResult => "CheckSensor",
+    AuraLedConfigResult => "AuraLedConfig",
+    DeletCharResult => "DeletChar",
+    EmptyResult => "Empty",
+);
+
+/// Writes `bytes` as hex, with no separators.
+fn write_hex(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    for byte in bytes {
+        write!(f, "{:02x}", byte)?;
+    }
+    return Ok(());
+}
+
+/// Writes a version string padded with zeroes, with anything but printable ASCII as `?`.
+fn write_version(f: &mut fmt::Formatter<'_>, version: &[u8]) -> fmt::Result {
+    for byte in version.iter().take_while(|byte| **byte != 0) {
+        let c = if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '?' };
+        write!(f, "{}", c)?;
+    }
+    return Ok(());
+}
+
+impl fmt::Display for SystemParameters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(
+            f,
+            "library of {}, security level {}, {} baud, address {:#010x}",
+            self.finger_library_size,
+            self.security_level,
+            self.baud_setting as u32 * 9600,
+            self.device_address
+        );
+    }
+}
+
+impl fmt::Display for ReadSysParaResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self.confirmation_code {
+            0x00 => write!(f, "ReadSysPara: {}", self.system_parameters),
+            0x01 => write!(f, "ReadSysPara: packet error"),
+            code => write!(f, "ReadSysPara: error {:#04x}", code),
+        };
+    }
+}
+
+impl fmt::Display for SearchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self.confirmation_code {
+            SearchStatus::Success => write!(
+                f,
+                "Search: match at slot {} (score {})",
+                self.match_id, self.match_score
+            ),
+            ref status => write!(f, "Search: {}", status),
+        };
+    }
+}
+
+impl fmt::Display for MatchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self.confirmation_code {
+            MatchStatus::Success => write!(f, "Match: fingers match (score {})", self.match_score),
+            ref status => write!(f, "Match: {}", status),
+        };
+    }
+}
+
+impl fmt::Display for TemplateNumResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self.confirmation_code {
+            TemplateNumStatus::Success => {
+                write!(f, "TemplateNum: {} templates stored", self.template_num)
+            }
+            ref status => write!(f, "TemplateNum: {}", status),
+        };
+    }
+}
+
+impl fmt::Display for ReadIndexTableResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self.confirmation_code {
+            ReadIndexTableStatus::Success => {
+                let used: u32 = self.index_table.iter().map(|byte| byte.count_ones()).sum();
+                write!(f, "ReadIndexTable: {} slots in use on this page", used)
+            }
+            ref status => write!(f, "ReadIndexTable: {}", status),
+        };
+    }
+}
+
+impl fmt::Display for GetChipSNResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self.confirmation_code {
+            GetChipSNStatus::Success => {
+                f.write_str("GetChipSN: serial number ")?;
+                write_hex(f, &self.serial_number)
+            }
+            ref status => write!(f, "GetChipSN: {}", status),
+        };
+    }
+}
+
+impl fmt::Display for GetRandomCodeResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self.confirmation_code {
+            GetRandomCodeStatus::Success => write!(f, "GetRandomCode: {:#010x}", self.random_code),
+            ref status => write!(f, "GetRandomCode: {}", status),
+        };
+    }
+}
+
+impl fmt::Display for GetFwVerResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self.confirmation_code {
+            GetFwVerStatus::Success => {
+                f.write_str("GetFwVer: firmware ")?;
+                write_version(f, &self.version)
+            }
+            ref status => write!(f, "GetFwVer: {}", status),
+        };
+    }
+}
+
+impl fmt::Display for GetAlgVerResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self.confirmation_code {
+            GetAlgVerStatus::Success => {
+                f.write_str("GetAlgVer: algorithm ")?;
+                write_version(f, &self.version)
+            }
+            ref status => write!(f, "GetAlgVer: {}", status),
+        };
+    }
+}
+
+impl fmt::Display for ReadNotepadResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self.confirmation_code {
+            ReadNotepadStatus::Success => {
+                f.write_str("ReadNotepad: ")?;
+                write_hex(f, &self.data)
+            }
+            ref status => write!(f, "ReadNotepad: {}", status),
+        };
+    }
+}
+
+/// A one-line summary of the reply for people rather than developers, such as
+/// `Search: match at slot 12 (score 96)`. Use `Debug` for every field.
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Reply::ReadSysPara(result) => result.fmt(f),
+            Reply::VfyPwd(result) => result.fmt(f),
+            Reply::GenImg(result) => result.fmt(f),
+            Reply::Img2Tz(result) => result.fmt(f),
+            Reply::Search(result) => result.fmt(f),
+            Reply::LoadChar(result) => result.fmt(f),
+            Reply::Match(result) => result.fmt(f),
+            Reply::TemplateNum(result) => result.fmt(f),
+            Reply::ReadIndexTable(result) => result.fmt(f),
+            #[cfg(feature = "cmd-enroll")]
+            Reply::RegModel(result) => result.fmt(f),
+            #[cfg(feature = "cmd-enroll")]
+            Reply::Store(result) => result.fmt(f),
+            #[cfg(feature = "cmd-transfer")]
+            Reply::UpChar(result) => result.fmt(f),
+            #[cfg(feature = "cmd-transfer")]
+            Reply::DownChar(result) => result.fmt(f),
+            #[cfg(feature = "cmd-transfer")]
+            Reply::DownImage(result) => result.fmt(f),
+            Reply::SetPwd(result) => result.fmt(f),
+            Reply::SetSysPara(result) => result.fmt(f),
+            Reply::SetAdder(result) => result.fmt(f),
+            Reply::GetChipSN(result) => result.fmt(f),
+            Reply::GetRandomCode(result) => result.fmt(f),
+            Reply::GetFwVer(result) => result.fmt(f),
+            Reply::GetAlgVer(result) => result.fmt(f),
+            #[cfg(feature = "cmd-notepad")]
+            Reply::WriteNotepad(result) => result.fmt(f),
+            #[cfg(feature = "cmd-notepad")]
+            Reply::ReadNotepad(result) => result.fmt(f),
+            Reply::HandShake(result) => result.fmt(f),
+            Reply::CheckSensor(result) => result.fmt(f),
+            Reply::SoftRst(result) => result.fmt(f),
+            Reply::Sleep(result) => result.fmt(f),
+            Reply::PortControl(result) => result.fmt(f),
+            #[cfg(feature = "cmd-led")]
+            Reply::AuraLedConfig(result) => result.fmt(f),
+            Reply::DeletChar(result) => result.fmt(f),
+            Reply::Empty(result) => result.fmt(f),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::ToString;
+
+    fn version(text: &str) -> [u8; 32] {
+        let mut version = [0u8; 32];
+        version[..text.len()].copy_from_slice(text.as_bytes());
+        return version;
+    }
+
+    #[test]
+    fn test_display_search() {
+        // given: a match and a miss
+        let found = SearchResult {
+            address: 0xffffffff,
+            confirmation_code: SearchStatus::Success,
+            match_id: 12,
+            match_score: 96,
+            checksum: 0,
+        };
+        let missed = SearchResult {
+            address: 0xffffffff,
+            confirmation_code: SearchStatus::NoMatch,
+            match_id: 0,
+            match_score: 0,
+            checksum: 0,
+        };
+
+        // then: the slot and score show only for the match
+        assert_eq!(Reply::Search(found).to_string(), "Search: match at slot 12 (score 96)");
+        assert_eq!(Reply::Search(missed).to_string(), "Search: no match");
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_display_status_only() {
+        // given: results which carry only a status code
+        let stored = StoreResult {
+            address: 0xffffffff,
+            confirmation_code: StoreStatus::WriteError,
+            checksum: 0,
+        };
+        let captured = GenImgResult {
+            address: 0xffffffff,
+            confirmation_code: GenImgStatus::FingerNotDetected,
+            checksum: 0,
+        };
+        let password = VfyPwdResult {
+            address: 0xffffffff,
+            confirmation_code: PasswordVerificationState::Incorrect,
+            checksum: 0,
+        };
+
+        // then: each shows the command and what the status means
+        assert_eq!(Reply::Store(stored).to_string(), "Store: flash write error");
+        assert_eq!(Reply::GenImg(captured).to_string(), "GenImg: no finger on the sensor");
+        assert_eq!(Reply::VfyPwd(password).to_string(), "VfyPwd: wrong password");
+    }
+
+    #[test]
+    fn test_display_counts() {
+        // given: a template count and an index table page with three slots in use
+        let count = TemplateNumResult {
+            address: 0xffffffff,
+            confirmation_code: TemplateNumStatus::Success,
+            template_num: 3,
+            checksum: 0,
+        };
+        let mut index_table = [0u8; 32];
+        index_table[0] = 0b0000_0101;
+        index_table[31] = 0b1000_0000;
+        let table = ReadIndexTableResult {
+            address: 0xffffffff,
+            confirmation_code: ReadIndexTableStatus::Success,
+            index_table,
+            checksum: 0,
+        };
+        let matched = MatchResult {
+            address: 0xffffffff,
+            confirmation_code: MatchStatus::Success,
+            match_score: 150,
+            checksum: 0,
+        };
+
+        // then: they show the numbers
+        assert_eq!(Reply::TemplateNum(count).to_string(), "TemplateNum: 3 templates stored");
+        assert_eq!(
+            Reply::ReadIndexTable(table).to_string(),
+            "ReadIndexTable: 3 slots in use on this page"
+        );
+        assert_eq!(Reply::Match(matched).to_string(), "Match: fingers match (score 150)");
+    }
+
+    #[test]
+    fn test_display_system_parameters() {
+        // given: the parameters of a module at its defaults
+        let result = ReadSysParaResult {
+            address: 0xffffffff,
+            confirmation_code: 0x00,
+            system_parameters: SystemParameters {
+                status_register: 0,
+                system_identifier_code: 0x0009,
+                finger_library_size: 200,
+                security_level: 3,
+                device_address: 0xffffffff,
+                packet_size: 2,
+                baud_setting: 6,
+            },
+            checksum: 0,
+        };
+
+        // then: the parameters worth knowing are summarised
+        assert_eq!(
+            Reply::ReadSysPara(result).to_string(),
+            "ReadSysPara: library of 200, security level 3, 57600 baud, address 0xffffffff"
+        );
+    }
+
+    #[test]
+    fn test_display_versions_and_serial() {
+        // given: version strings, one with a stray control character, and a serial number
+        let firmware = GetFwVerResult {
+            address: 0xffffffff,
+            confirmation_code: GetFwVerStatus::Success,
+            version: version("V1.2 R502"),
+            checksum: 0,
+        };
+        let algorithm = GetAlgVerResult {
+            address: 0xffffffff,
+            confirmation_code: GetAlgVerStatus::Success,
+            version: version("AL\x07G"),
+            checksum: 0,
+        };
+        let mut serial_number = [0u8; 32];
+        serial_number[0] = 0xab;
+        serial_number[31] = 0x01;
+        let serial = GetChipSNResult {
+            address: 0xffffffff,
+            confirmation_code: GetChipSNStatus::Success,
+            serial_number,
+            checksum: 0,
+        };
+
+        // then: versions show as text up to the padding, and the serial number as hex
+        assert_eq!(Reply::GetFwVer(firmware).to_string(), "GetFwVer: firmware V1.2 R502");
+        assert_eq!(Reply::GetAlgVer(algorithm).to_string(), "GetAlgVer: algorithm AL?G");
+        let expected = "GetChipSN: serial number ab".to_string() + &"00".repeat(30) + "01";
+        assert_eq!(Reply::GetChipSN(serial).to_string(), expected);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_display_failures() {
+        // given: failed replies
+        let parameters = ReadSysParaResult {
+            address: 0xffffffff,
+            confirmation_code: 0x01,
+            system_parameters: SystemParameters {
+                status_register: 0,
+                system_identifier_code: 0,
+                finger_library_size: 0,
+                security_level: 0,
+                device_address: 0,
+                packet_size: 0,
+                baud_setting: 0,
+            },
+            checksum: 0,
+        };
+        let firmware = GetFwVerResult {
+            address: 0xffffffff,
+            confirmation_code: GetFwVerStatus::PacketError,
+            version: [0; 32],
+            checksum: 0,
+        };
+        let model = RegModelResult {
+            address: 0xffffffff,
+            confirmation_code: RegModelStatus::ProcessingError,
+            checksum: 0,
+        };
+
+        // then: each shows what went wrong and nothing else
+        assert_eq!(Reply::ReadSysPara(parameters).to_string(), "ReadSysPara: packet error");
+        assert_eq!(Reply::GetFwVer(firmware).to_string(), "GetFwVer: packet error");
+        assert_eq!(
+            Reply::RegModel(model).to_string(),
+            "RegModel: captures are not of the same finger"
+        );
+    }
+}
@@ -1,56 +1,294 @@
-use arrayvec::ArrayVec;
 use byteorder::{BigEndian, ByteOrder};
-use core::cell::RefCell;
 use embedded_hal::serial::{Read, Write};
 use nb::block;
 
-use crate::commands::Command;
+use crate::address::AddressDisagreement;
+use crate::allocation::SlotAllocation;
+use crate::audit::AuditLog;
+use crate::buffer::ByteBuffer;
+use crate::codec::{self, ReplyView, REPLY_HEADER_LENGTH};
+use crate::commands::{Command, CommandKind};
+use crate::compat::ModuleFamily;
+use crate::consts::{
+    PacketSize, DEFAULT_PACKET_SIZE, FRAME_CHECKSUM_LENGTH, FRAME_HEADER_LENGTH, MAX_PACKET_LENGTH,
+};
+use crate::library::IndexCache;
+use crate::power::{MAX_READY_NOISE, READY_BYTE};
+use crate::profile::TimingProfile;
 use crate::responses::*;
-use crate::utils::{CommandWriter, Error, FromPayload, ToPayload};
+use crate::stats::DriverStats;
+use crate::transport::{CombinedSerial, Transport};
+use crate::utils::{Error, ProtocolCommand};
+use crate::workflow::WorkflowTracker;
 
-const REPLY_HEADER_LENGTH: u16 = 9;
+/// Where the driver is in exchanging a packet with the R502.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CommandState {
+    /// No command is in progress.
+    Idle,
 
-/// Represents a R502 device connected to a U(S)ART.
-///
-/// A R502 has an address, which may mean that the intention is to use one USART line as a bus
-/// network with multiple sensors attached to it. This is not explicitly supported by this driver.
-#[derive(Debug)]
-pub struct R502<TX, RX> {
-    address: u32,
-    tx: TX,
-    rx: RX,
-    received: ArrayVec<[u8; 1024]>,
-    cmd_buffer: ArrayVec<[u8; 128]>,
-    inflight_request: RefCell<Option<Command>>,
+    /// `sent` bytes of the command have been written.
+    Writing { sent: usize },
+
+    /// The command has been written, and the transmitter is being flushed.
+    Flushing,
+
+    /// Waiting for the rest of the 9-byte packet header.
+    AwaitingHeader,
+
+    /// The header is in, and says `length` more bytes follow.
+    AwaitingBody { length: u16 },
+
+    /// No command is in progress, but part of a packet nobody asked for has arrived, see
+    /// `R502::poll_event`. Starting a command drops it.
+    Listening,
 }
 
-impl<TX, RX> CommandWriter for R502<TX, RX> {
-    fn write_cmd_bytes(&mut self, bytes: &[u8]) {
-        self.cmd_buffer.try_extend_from_slice(bytes).unwrap();
+impl CommandState {
+    /// True if a command is in progress.
+    fn is_busy(self) -> bool {
+        return !matches!(self, CommandState::Idle | CommandState::Listening);
     }
 }
 
-impl<TX, RX> R502<TX, RX>
+/// A packet nobody asked for, `None` for the ready byte, see `R502::poll_unsolicited`.
+type UnsolicitedResult<'a, TXE, RXE> = nb::Result<Option<&'a [u8]>, Error<TXE, RXE>>;
+
+/// Shortest packet there is: the header, one byte, and the checksum. Neither buffer of `R502`
+/// can be smaller.
+const MIN_PACKET_LENGTH: usize = FRAME_HEADER_LENGTH + 1 + FRAME_CHECKSUM_LENGTH;
+
+/// Represents a R502 device connected to a U(S)ART, or to some other `Transport`.
+///
+/// A R502 has an address, which may mean that the intention is to use one USART line as a bus
+/// network with multiple sensors attached to it. An `R502` talks to one address at a time; see
+/// `R502Bus` for several sensors sharing a line.
+///
+/// The driver holds a packet being received in a buffer of `RX_BUF` bytes, and a command being
+/// sent in one of `CMD_BUF` bytes. The defaults fit anything the module sends; on targets short
+/// of RAM, smaller buffers can be chosen with
+/// [`with_buffer_sizes`](#method.with_buffer_sizes). The helpers built on the driver, such as
+/// enrolment and template transfers, are only there with the default sizes.
+///
+/// # Static allocation
+///
+/// The constructors are `const fn`, so if the transport can be made in a `const` context too,
+/// the driver can be placed in a `static` as it is, buffers and all, rather than being built on
+/// the stack and moved there:
+///
+/// ```
+/// # use hzgrow_r502::R502;
+/// # struct Port;
+/// # impl embedded_hal::serial::Write<u8> for Port {
+/// #     type Error = ();
+/// #     fn write(&mut self, _: u8) -> nb::Result<(), ()> { Ok(()) }
+/// #     fn flush(&mut self) -> nb::Result<(), ()> { Ok(()) }
+/// # }
+/// # impl embedded_hal::serial::Read<u8> for Port {
+/// #     type Error = ();
+/// #     fn read(&mut self) -> nb::Result<u8, ()> { Err(nb::Error::Other(())) }
+/// # }
+/// use std::sync::Mutex;
+///
+/// static SENSOR: Mutex<R502<(Port, Port)>> = Mutex::new(R502::new(Port, Port, 0xffffffff));
+/// ```
+///
+/// Most HALs only hand out the serial port at runtime. Then the `static` can hold an
+/// `Option<R502<..>>`, starting out as `None`, or be a `StaticCell`, and the driver goes in
+/// once the port has been set up. The buffers start out zeroed, so they cost nothing in flash.
+#[derive(Debug)]
+pub struct R502<T, const RX_BUF: usize = MAX_PACKET_LENGTH, const CMD_BUF: usize = 64> {
+    pub(crate) address: u32,
+    pub(crate) transport: T,
+    received: ByteBuffer<RX_BUF>,
+    cmd_buffer: ByteBuffer<CMD_BUF>,
+    inflight_request: Option<Command>,
+    pub(crate) data_packet_size: u16,
+    asleep: bool,
+    state: CommandState,
+    pub(crate) index_cache: IndexCache,
+    pub(crate) allocation: SlotAllocation,
+    pub(crate) family: ModuleFamily,
+    pub(crate) profile: TimingProfile,
+    pub(crate) stats: DriverStats,
+    pub(crate) workflow: WorkflowTracker,
+    pub(crate) adopt_address: bool,
+    pub(crate) address_disagreement: Option<AddressDisagreement>,
+    pub(crate) audit: Option<AuditLog>,
+}
+
+impl<TX, RX> R502<(TX, RX)>
 where
     TX: Write<u8>,
     RX: Read<u8>,
 {
     /// Creates an instance of the R502. `tx` and `rx` are the transmit and receive halves of a
     /// USART, and `address` is the R502 address. By default this should be `0xffffffff`.
-    pub fn new(tx: TX, rx: RX, address: u32) -> Self {
-        Self {
-            address: address,
-            tx: tx,
-            rx: rx,
-            received: ArrayVec::<[u8; 1024]>::new(),
-            cmd_buffer: ArrayVec::<[u8; 128]>::new(),
-            inflight_request: RefCell::from(None),
-        }
+    pub const fn new(tx: TX, rx: RX, address: u32) -> Self {
+        return Self::with_transport((tx, rx), address);
+    }
+}
+
+impl<S> R502<CombinedSerial<S>>
+where
+    S: Read<u8> + Write<u8>,
+{
+    /// Creates an instance of the R502 on `serial`, a USART which both reads and writes, for
+    /// HALs which do not split it into transmit and receive halves. `address` is the R502
+    /// address. By default this should be `0xffffffff`.
+    pub const fn from_serial(serial: S, address: u32) -> Self {
+        return Self::with_transport(CombinedSerial::new(serial), address);
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Creates an instance of the R502 talking over `transport`, for modules which are not
+    /// connected to an embedded-hal serial port. `address` is the R502 address. By default
+    /// this should be `0xffffffff`.
+    pub const fn with_transport(transport: T, address: u32) -> Self {
+        return Self::with_buffer_sizes(transport, address);
+    }
+}
+
+impl<T, const RX_BUF: usize, const CMD_BUF: usize> R502<T, RX_BUF, CMD_BUF>
+where
+    T: Transport,
+{
+    /// Fails the build for buffers too small to hold any packet at all.
+    const BUFFERS_FIT: () = assert!(
+        RX_BUF >= MIN_PACKET_LENGTH && CMD_BUF >= MIN_PACKET_LENGTH,
+        "R502 buffers must hold at least 12 bytes"
+    );
+
+    /// Creates an instance of the R502 talking over `transport`, like
+    /// [`with_transport`](#method.with_transport), receiving into a buffer of `RX_BUF` bytes
+    /// and writing commands into one of `CMD_BUF` bytes. Commands and replies which do not fit
+    /// fail with `Error::CommandTooLong` and `Error::RecvPacketTooLong`.
+    ///
+    /// With 64 bytes each, every command goes through, and every reply except template data.
+    ///
+    /// ```
+    /// # use hzgrow_r502::R502;
+    /// # struct Port;
+    /// # impl embedded_hal::serial::Write<u8> for Port {
+    /// #     type Error = ();
+    /// #     fn write(&mut self, _: u8) -> nb::Result<(), ()> { Ok(()) }
+    /// #     fn flush(&mut self) -> nb::Result<(), ()> { Ok(()) }
+    /// # }
+    /// # impl embedded_hal::serial::Read<u8> for Port {
+    /// #     type Error = ();
+    /// #     fn read(&mut self) -> nb::Result<u8, ()> { Err(nb::Error::Other(())) }
+    /// # }
+    /// # let (tx, rx) = (Port, Port);
+    /// let r502 = R502::<_, 64, 64>::with_buffer_sizes((tx, rx), 0xffffffff);
+    /// ```
+    ///
+    /// Buffers of less than 12 bytes, too small for any packet, fail the build:
+    ///
+    /// ```compile_fail
+    /// # use hzgrow_r502::R502;
+    /// # struct Port;
+    /// # impl embedded_hal::serial::Write<u8> for Port {
+    /// #     type Error = ();
+    /// #     fn write(&mut self, _: u8) -> nb::Result<(), ()> { Ok(()) }
+    /// #     fn flush(&mut self) -> nb::Result<(), ()> { Ok(()) }
+    /// # }
+    /// # impl embedded_hal::serial::Read<u8> for Port {
+    /// #     type Error = ();
+    /// #     fn read(&mut self) -> nb::Result<u8, ()> { Err(nb::Error::Other(())) }
+    /// # }
+    /// # let (tx, rx) = (Port, Port);
+    /// let r502 = R502::<_, 8, 64>::with_buffer_sizes((tx, rx), 0xffffffff);
+    /// ```
+    pub const fn with_buffer_sizes(transport: T, address: u32) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::BUFFERS_FIT;
+        return Self {
+            address,
+            transport,
+            received: ByteBuffer::new(),
+            cmd_buffer: ByteBuffer::new(),
+            inflight_request: None,
+            data_packet_size: DEFAULT_PACKET_SIZE.bytes(),
+            asleep: false,
+            state: CommandState::Idle,
+            index_cache: IndexCache::new(),
+            allocation: SlotAllocation::new(),
+            family: ModuleFamily::R502,
+            profile: TimingProfile::datasheet(),
+            stats: DriverStats::new(),
+            workflow: WorkflowTracker::new(),
+            adopt_address: false,
+            address_disagreement: None,
+            audit: None,
+        };
+    }
+
+    /// Moves the driver, as it is, onto the transport `f` makes of its current one.
+    pub(crate) fn map_transport<U, F>(self, f: F) -> R502<U, RX_BUF, CMD_BUF>
+    where
+        F: FnOnce(T) -> U,
+    {
+        return R502 {
+            address: self.address,
+            transport: f(self.transport),
+            received: self.received,
+            cmd_buffer: self.cmd_buffer,
+            inflight_request: self.inflight_request,
+            data_packet_size: self.data_packet_size,
+            asleep: self.asleep,
+            state: self.state,
+            index_cache: self.index_cache,
+            allocation: self.allocation,
+            family: self.family,
+            profile: self.profile,
+            stats: self.stats,
+            workflow: self.workflow,
+            adopt_address: self.adopt_address,
+            address_disagreement: self.address_disagreement,
+            audit: self.audit,
+        };
+    }
+
+    /// The transport the driver talks over.
+    pub fn transport(&self) -> &T {
+        return &self.transport;
+    }
+
+    /// The transport the driver talks over, for example to reconnect it. Reading or writing
+    /// anything with it while a command is in progress will throw the driver off.
+    pub fn transport_mut(&mut self) -> &mut T {
+        return &mut self.transport;
+    }
+
+    /// The address commands are sent to.
+    pub fn address(&self) -> u32 {
+        return self.address;
+    }
+
+    /// Changes the address commands are sent to, for example after the module has been given
+    /// a new one with `SetAdder`. Drops the cached _index table_, if any.
+    pub fn set_address(&mut self, address: u32) {
+        self.address = address;
+        self.address_disagreement = None;
+        self.index_cache.invalidate();
+        self.index_cache.source = None;
+    }
+
+    /// Sets the size of the data packets the host sends when transferring templates to the
+    /// R502. This must agree with the packet size setting of the module (see
+    /// `SystemParameters::packet_size`), which is 128 bytes by default.
+    pub fn set_data_packet_size(&mut self, size: PacketSize) {
+        self.data_packet_size = size.bytes();
     }
 
     /// Sends a command `cmd` to the R502 and then blocks waiting for the reply.
-    /// The return value is either a response from the R502 or an error. Uses blocking USART
-    /// API.
+    /// The return value is either a response from the R502 or an error. This is
+    /// [`start_command`](#method.start_command) followed by [`poll`](#method.poll) until the
+    /// reply is in.
     ///
     /// # Errors
     ///
@@ -63,133 +301,545 @@ where
     /// Wraps the underlying error.
     ///
     /// ## `Error::RecvPacketTooShort`
-    /// Returned if the reply was only partially received.
+    /// Returned if the reply was only partially received, or was too short for the command.
     ///
     /// ## `Error::RecvWrongReplyType`
     /// Returned if the response packet was not a reply.
-    pub fn send_command(&mut self, cmd: Command) -> Result<Reply, Error<TX::Error, RX::Error>> {
-        self.cmd_buffer.clear();
+    ///
+    /// ## `Error::RecvUnknownCode(code)`
+    /// Returned if the reply carried a confirmation code the command is not known to return.
+    ///
+    /// ## `Error::ModuleAsleep`
+    /// Returned without sending anything if the module has been put to sleep with
+    /// [`standby`](#method.standby) and not woken since.
+    ///
+    /// ## `Error::InvalidBuffer(buffer)`
+    /// Returned without sending anything if the command names a _character buffer_ outside
+    /// 1 to [`CHAR_BUFFERS`](constant.CHAR_BUFFERS.html).
+    pub fn send_command(
+        &mut self,
+        cmd: Command,
+    ) -> Result<Reply, Error<T::WriteError, T::ReadError>> {
+        self.start_command(cmd)?;
+        return block!(self.poll());
+    }
+
+    /// Sends `cmd` and blocks waiting for the reply, as [`send_command`](#method.send_command)
+    /// does, but leaves the reply in the receive buffer rather than copying it out. The view
+    /// borrows the driver, so it has to go before the next command is sent:
+    ///
+    /// ```
+    /// # use hzgrow_r502::{Command, Transport, R502};
+    /// fn first_byte<T: Transport>(r502: &mut R502<T>) -> Option<u8> {
+    ///     let page = r502.send_command_view(Command::ReadIndexTable { page: 0 }).ok()?;
+    ///     return page.data().first().copied();
+    /// }
+    /// ```
+    ///
+    /// ```compile_fail
+    /// # use hzgrow_r502::{Command, Transport, R502};
+    /// fn first_byte<T: Transport>(r502: &mut R502<T>) -> Option<u8> {
+    ///     let page = r502.send_command_view(Command::ReadIndexTable { page: 0 }).ok()?;
+    ///     r502.send_command_view(Command::ReadIndexTable { page: 1 }).ok()?;
+    ///     return page.data().first().copied();
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// As for `send_command`, except that the confirmation code is left to the caller, and
+    /// so never gives `Error::RecvUnknownCode`.
+    pub fn send_command_view(
+        &mut self,
+        cmd: Command,
+    ) -> Result<ReplyView<'_>, Error<T::WriteError, T::ReadError>> {
+        let kind = cmd.kind();
+        self.start_command(cmd)?;
+        let result = block!(self.poll_frame());
+        self.state = CommandState::Idle;
+        let view = match result {
+            Ok(_) => ReplyView::new(kind, &self.received).map_err(Error::from),
+            Err(error) => Err(error),
+        };
+        if let Err(error) = view.as_ref() {
+            self.stats.count_error(error);
+        }
+
+        // Only the commands which change the library are decoded, to keep the cache in step.
+        if let Some(cmd) = self.inflight_request.as_ref() {
+            let reply = match (kind, view.as_ref()) {
+                #[cfg(feature = "cmd-enroll")]
+                (CommandKind::Store, Ok(view)) => view.decode().ok(),
+                (CommandKind::DeletChar, Ok(view)) | (CommandKind::Empty, Ok(view)) => {
+                    view.decode().ok()
+                }
+                _ => None,
+            };
+            self.index_cache.observe(cmd, reply.as_ref());
+        }
+        return view;
+    }
+
+    /// Sends `cmd`, which may be a command the driver does not know of, such as a vendor
+    /// instruction defined in another crate, and then blocks waiting for the reply. Errors are
+    /// as for [`send_command`](#method.send_command).
+    ///
+    /// The driver cannot tell what such a command does to the library, so it forgets its
+    /// cached _index table_ (see [`enable_index_cache`](#method.enable_index_cache)). A command
+    /// with the instruction code of a `Command` is counted in the statistics, and reported to
+    /// the workflow observer, as that command; one with any other code is not, as it has no
+    /// `CommandKind`.
+    pub fn send<C>(&mut self, cmd: &C) -> Result<C::Reply, Error<T::WriteError, T::ReadError>>
+    where
+        C: ProtocolCommand,
+    {
+        check_can_start(self.state, self.asleep, None)?;
+        codec::write_command(&mut self.cmd_buffer, self.address, cmd)?;
+        if let Ok(known) = codec::decode_command(&self.cmd_buffer) {
+            self.stats.count_command(known.kind());
+            self.workflow.command(known.kind());
+        }
         self.received.clear();
-        self.prepare_cmd(cmd);
+        self.inflight_request = None;
+        self.index_cache.invalidate();
+        self.state = CommandState::Writing { sent: 0 };
+
+        let result = block!(self.poll_write()).and_then(|_| block!(self.poll_packet()));
+        self.state = CommandState::Idle;
+        let reply = result.and_then(|_| {
+            return Ok(cmd.decode_reply(codec::check_reply(&self.received)?)?);
+        });
+        if let Err(error) = reply.as_ref() {
+            self.stats.count_error(error);
+        }
+        return reply;
+    }
+
+    /// Starts sending a command `cmd` to the R502 without blocking, writing as much of it as
+    /// the transmitter takes straight away. Call [`poll`](#method.poll) to carry on until the
+    /// reply is in, for example from a UART interrupt.
+    ///
+    /// # Errors
+    ///
+    /// As for [`send_command`](#method.send_command), and `Error::CommandInProgress` if the
+    /// previous command has not been seen through with `poll`. An error other than that one
+    /// ends the command.
+    pub fn start_command(
+        &mut self,
+        cmd: Command,
+    ) -> Result<(), Error<T::WriteError, T::ReadError>> {
+        check_can_start(self.state, self.asleep, Some(&cmd))?;
+        let kind = cmd.kind();
+        self.prepare_cmd(cmd)?;
+        self.stats.count_command(kind);
+        self.workflow.command(kind);
+        self.received.clear();
+        self.state = CommandState::Writing { sent: 0 };
+
+        if let Err(nb::Error::Other(error)) = self.poll_write() {
+            let inflight = self.inflight_request.as_ref();
+            abandon_command(&mut self.state, inflight, &mut self.index_cache);
+            return Err(error);
+        }
+        return Ok(());
+    }
 
-        let cmd_bytes = &self.cmd_buffer[..];
-        for byte in cmd_bytes {
-            match block!(self.tx.write(*byte)) {
-                Err(e) => return Err(Error::WriteError(e)),
-                Ok(..) => {}
+    /// Makes as much progress on the command started with
+    /// [`start_command`](#method.start_command) as the serial port allows without blocking:
+    /// writing the rest of the command, then reading the reply. Returns `WouldBlock` until the
+    /// reply is complete, and then the reply.
+    ///
+    /// # Errors
+    ///
+    /// As for [`send_command`](#method.send_command), and `Error::NoCommandInProgress` if
+    /// there is nothing to poll for. Any error ends the command.
+    pub fn poll(&mut self) -> nb::Result<Reply, Error<T::WriteError, T::ReadError>> {
+        if !self.state.is_busy() {
+            return Err(nb::Error::Other(Error::NoCommandInProgress));
+        }
+        match self.poll_frame() {
+            Ok(_) => {}
+            Err(nb::Error::WouldBlock) => return Err(nb::Error::WouldBlock),
+            Err(nb::Error::Other(error)) => {
+                let inflight = self.inflight_request.as_ref();
+                abandon_command(&mut self.state, inflight, &mut self.index_cache);
+                self.stats.count_error(&error);
+                return Err(nb::Error::Other(error));
             }
         }
 
-        match block!(self.tx.flush()) {
-            Err(e) => return Err(Error::WriteError(e)),
-            Ok(..) => {}
+        let inflight = self.inflight_request.as_ref();
+        let cache = &mut self.index_cache;
+        let reply = complete_command(&mut self.state, inflight, &self.received, cache);
+        if let Ok(Reply::ReadSysPara(result)) = &reply {
+            self.check_reported_address(result);
         }
+        return reply.map_err(|error| {
+            let error = error.into();
+            self.stats.count_error(&error);
+            return nb::Error::Other(error);
+        });
+    }
+
+    /// Gives up on the command in progress, if any, without waiting for the rest of its reply.
+    pub(crate) fn abandon(&mut self) {
+        let inflight = self.inflight_request.as_ref();
+        abandon_command(&mut self.state, inflight, &mut self.index_cache);
+    }
 
-        return self.read_reply().and_then(|_| self.parse_reply());
+    /// The address in the header of the latest reply, once it has been read in full.
+    pub(crate) fn reply_address(&self) -> Option<u32> {
+        return self.received.get(2..6).map(BigEndian::read_u32);
     }
 
-    fn prepare_cmd(&mut self, cmd: Command) {
-        self.write_header(self.address);
-        cmd.to_payload(self);
-        let chk = self.compute_checksum();
-        self.write_cmd_bytes(&chk.to_be_bytes()[..]);
+    /// Writes what is left of the command in progress, then reads the reply into the receive
+    /// buffer, returning its length once it is complete.
+    fn poll_frame(&mut self) -> nb::Result<u16, Error<T::WriteError, T::ReadError>> {
+        if let CommandState::Writing { .. } | CommandState::Flushing = self.state {
+            self.poll_write()?;
+        }
+        return self.poll_packet();
+    }
 
-        *self.inflight_request.borrow_mut() = Some(cmd);
+    /// Writes and flushes what is left of the command, moving on to `AwaitingHeader` once it
+    /// has all gone out.
+    fn poll_write(&mut self) -> nb::Result<(), Error<T::WriteError, T::ReadError>> {
+        while let CommandState::Writing { sent } = self.state {
+            if sent == self.cmd_buffer.len() {
+                self.state = CommandState::Flushing;
+                break;
+            }
+            let taken = self
+                .transport
+                .write_slice(&self.cmd_buffer[sent..])
+                .map_err(|e| e.map(Error::WriteError))?;
+            self.stats.count_sent(taken);
+            self.state = CommandState::Writing { sent: sent + taken };
+        }
+
+        self.transport.flush().map_err(|e| e.map(Error::WriteError))?;
+        self.state = CommandState::AwaitingHeader;
+        return Ok(());
     }
 
-    fn write_header(&mut self, address: u32) {
-        self.write_cmd_bytes(&[0xEF, 0x01]);
-        self.write_cmd_bytes(&address.to_be_bytes()[..]);
+    /// Reads whatever has arrived of the packet being received, from `AwaitingHeader` through
+    /// `AwaitingBody`, and returns its length once it is complete.
+    fn poll_packet(&mut self) -> nb::Result<u16, Error<T::WriteError, T::ReadError>> {
+        loop {
+            let progress = advance_packet(&mut self.state, &self.received, RX_BUF);
+            if let Some(length) = progress.map_err(|error| nb::Error::Other(error.into()))? {
+                return Ok(length);
+            }
+
+            let word = self.transport.read_byte().map_err(|e| e.map(Error::RecvReadError))?;
+            self.stats.count_received(1);
+            self.received.push(word);
+        }
     }
 
-    fn compute_checksum(&self) -> u16 {
-        let mut checksum = 0u16;
-        let check_end = self.cmd_buffer.len();
-        let checked_bytes = &self.cmd_buffer[6..check_end];
-        for byte in checked_bytes {
-            checksum += (*byte) as u16;
+    /// Reads the data packets which follow the acknowledgement of an upload command,
+    /// passing the payload of each to `sink`, until the end-of-data packet arrives.
+    #[cfg(feature = "cmd-transfer")]
+    pub(crate) fn receive_data<F>(
+        &mut self,
+        mut sink: F,
+    ) -> Result<(), Error<T::WriteError, T::ReadError>>
+    where
+        F: FnMut(&[u8]),
+    {
+        loop {
+            self.received.clear();
+            self.state = CommandState::AwaitingHeader;
+            let length = block!(self.poll_packet());
+            self.state = CommandState::Idle;
+            length?;
+
+            let (payload, last) = match codec::data_payload(&self.received) {
+                Ok(data) => data,
+                Err(error) => {
+                    let error = error.into();
+                    self.stats.count_error(&error);
+                    return Err(error);
+                }
+            };
+            sink(payload);
+            if last {
+                return Ok(());
+            }
         }
-        return checksum;
     }
 
-    fn read_reply(&mut self) -> Result<u16, Error<TX::Error, RX::Error>> {
-        // At first, we don't know the full packet size, so read in the
-        // first 9 bytes of the packet header.
-        for _ in 0..REPLY_HEADER_LENGTH {
-            match block!(self.rx.read()) {
-                Ok(word) => self.received.push(word),
-                Err(error) => return Err(Error::RecvReadError(error)),
+    /// Reads whatever has arrived while no command is in progress, without blocking, and
+    /// returns the packet once it is complete and its checksum checked. Returns `None` for the
+    /// ready byte, and `WouldBlock` while nothing is complete or a command is in progress.
+    /// Bytes which cannot start a packet are skipped.
+    pub(crate) fn poll_unsolicited(
+        &mut self,
+    ) -> UnsolicitedResult<'_, T::WriteError, T::ReadError> {
+        match self.state {
+            CommandState::Idle => self.received.clear(),
+            CommandState::Listening => {}
+            _ => return Err(nb::Error::WouldBlock),
+        }
+        loop {
+            if self.received.len() >= FRAME_HEADER_LENGTH {
+                let length = match codec::frame_length(&self.received) {
+                    Ok(length) if length <= RX_BUF => length,
+                    Ok(length) if length <= MAX_PACKET_LENGTH => {
+                        self.state = CommandState::Idle;
+                        return Err(nb::Error::Other(Error::RecvPacketTooLong { length }));
+                    }
+                    _ => {
+                        self.state = CommandState::Idle;
+                        return Err(nb::Error::Other(Error::RecvBadStartCode));
+                    }
+                };
+                if self.received.len() == length {
+                    self.state = CommandState::Idle;
+                    return match codec::check_frame(&self.received) {
+                        Ok(packet) => Ok(Some(packet)),
+                        Err(error) => {
+                            let error = error.into();
+                            self.stats.count_error(&error);
+                            Err(nb::Error::Other(error))
+                        }
+                    };
+                }
+            }
+
+            let byte = match self.transport.read_byte() {
+                Ok(byte) => {
+                    self.stats.count_received(1);
+                    byte
+                }
+                Err(nb::Error::WouldBlock) => return Err(nb::Error::WouldBlock),
+                Err(nb::Error::Other(error)) => {
+                    self.state = CommandState::Idle;
+                    return Err(nb::Error::Other(Error::RecvReadError(error)));
+                }
+            };
+            if self.received.is_empty() {
+                if byte == READY_BYTE {
+                    return Ok(None);
+                }
+                if byte != 0xEF {
+                    self.stats.count_resync();
+                    continue;
+                }
+                self.state = CommandState::Listening;
             }
+            self.received.push(byte);
         }
+    }
+
+    /// True if the module has been put to sleep with [`standby`](#method.standby) and not
+    /// woken since.
+    pub fn is_asleep(&self) -> bool {
+        return self.asleep;
+    }
+
+    pub(crate) fn set_asleep(&mut self, asleep: bool) {
+        self.asleep = asleep;
+    }
+
+    /// Reads a single byte outside of any reply, without blocking.
+    pub(crate) fn read_byte(&mut self) -> nb::Result<u8, T::ReadError> {
+        let byte = self.transport.read_byte()?;
+        self.stats.count_received(1);
+        return Ok(byte);
+    }
 
-        let length = BigEndian::read_u16(&self.received[7..9]);
-        for _ in 0..length {
-            match block!(self.rx.read()) {
-                Ok(word) => self.received.push(word),
-                Err(error) => return Err(Error::RecvReadError(error)),
+    /// Throws away whatever is waiting to be read, up to `MAX_READY_NOISE` bytes, such as a
+    /// late reply to a command given up on.
+    pub(crate) fn drain_input(&mut self) {
+        let mut drained = false;
+        for _ in 0..MAX_READY_NOISE {
+            match self.read_byte() {
+                Ok(_) => drained = true,
+                Err(nb::Error::WouldBlock) => break,
+                Err(nb::Error::Other(_)) => {}
             }
         }
+        if drained {
+            self.stats.count_resync();
+        }
+    }
+
+    /// Writes `data` as a series of data packets, to follow the acknowledgement of a
+    /// download command. The module does not reply to data packets.
+    #[cfg(feature = "cmd-transfer")]
+    pub(crate) fn send_data(
+        &mut self,
+        data: &[u8],
+    ) -> Result<(), Error<T::WriteError, T::ReadError>> {
+        let mut chunks = data.chunks(self.data_packet_size as usize).peekable();
+        while let Some(chunk) = chunks.next() {
+            let last = chunks.peek().is_none();
+            let chk = codec::encode_data_header(&mut self.cmd_buffer, self.address, chunk, last);
+
+            self.transport.write_all(&self.cmd_buffer).map_err(Error::WriteError)?;
+            self.transport.write_all(chunk).map_err(Error::WriteError)?;
+            self.transport.write_all(&chk).map_err(Error::WriteError)?;
+            self.stats.count_sent(self.cmd_buffer.len() + chunk.len() + chk.len());
+        }
+
+        block!(self.transport.flush()).map_err(Error::WriteError)?;
+        return Ok(());
+    }
+
+    fn prepare_cmd(&mut self, cmd: Command) -> Result<(), Error<T::WriteError, T::ReadError>> {
+        codec::write_builtin_command(&mut self.cmd_buffer, self.address, &cmd)?;
+        self.inflight_request = Some(cmd);
+        return Ok(());
+    }
+
+    #[cfg(test)]
+    fn parse_reply(&self) -> Result<Reply, Error<T::WriteError, T::ReadError>> {
+        return decode_reply_to(self.inflight_request.as_ref(), &self.received).map_err(Error::from);
+    }
+}
+
+// The parts of the driver which do not depend on the transport are kept out of the generic
+// impls above, so that a program driving modules over more than one kind of transport pays for
+// them once rather than once per transport.
+
+/// What can go wrong in the parts of the driver which do not touch the transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CoreError {
+    CommandInProgress,
+    ModuleAsleep,
+    InvalidBuffer(u8),
+    UnsolicitedReply,
+    PacketTooLong(usize),
+    Decode(codec::DecodeError),
+}
+
+impl<TXE, RXE> From<CoreError> for Error<TXE, RXE> {
+    #[inline(never)]
+    fn from(error: CoreError) -> Self {
+        return match error {
+            CoreError::CommandInProgress => Error::CommandInProgress,
+            CoreError::ModuleAsleep => Error::ModuleAsleep,
+            CoreError::InvalidBuffer(buffer) => Error::InvalidBuffer(buffer),
+            CoreError::UnsolicitedReply => Error::RecvUnsolicitedReply,
+            CoreError::PacketTooLong(length) => Error::RecvPacketTooLong { length },
+            CoreError::Decode(error) => error.into(),
+        };
+    }
+}
 
-        return Ok(REPLY_HEADER_LENGTH + length);
-    }
-
-    fn parse_reply(&self) -> Result<Reply, Error<TX::Error, RX::Error>> {
-        // Packet ID is in byte 6
-        if self.received.len() < 7 {
-            return Err(Error::RecvPacketTooShort);
-        }
-
-        // We have no business reading anything if there's no request in flight
-        let inflight = self.inflight_request.borrow();
-        if inflight.is_none() {
-            return Err(Error::RecvUnsolicitedReply);
-        }
-
-        // We are looking for a response packet
-        if self.received[6] != 0x07 {
-            return Err(Error::RecvWrongReplyType);
-        }
-
-        return match *inflight {
-            Some(Command::ReadSysPara) => Ok(Reply::ReadSysPara(ReadSysParaResult::from_payload(
-                &self.received[..],
-            ))),
-            Some(Command::VfyPwd { .. }) => Ok(Reply::VfyPwd(VfyPwdResult::from_payload(
-                &self.received[..],
-            ))),
-            Some(Command::GenImg) => Ok(Reply::GenImg(GenImgResult::from_payload(
-                &self.received[..],
-            ))),
-            Some(Command::Img2Tz { .. }) => Ok(Reply::Img2Tz(Img2TzResult::from_payload(
-                &self.received[..],
-            ))),
-            Some(Command::Search { .. }) => Ok(Reply::Search(SearchResult::from_payload(
-                &self.received[..],
-            ))),
-            Some(Command::LoadChar { .. }) => Ok(Reply::LoadChar(LoadCharResult::from_payload(
-                &self.received[..],
-            ))),
-            Some(Command::Match) => Ok(Reply::Match(MatchResult::from_payload(&self.received[..]))),
-            Some(Command::TemplateNum) => Ok(Reply::TemplateNum(TemplateNumResult::from_payload(
-                &self.received[..],
-            ))),
-            Some(Command::RegModel) => Ok(Reply::RegModel(RegModelResult::from_payload(
-                &self.received[..],
-            ))),
-            Some(Command::Store { .. }) => Ok(Reply::Store(StoreResult::from_payload(
-                &self.received[..],
-            ))),
-            Some(Command::DeletChar { .. }) => Ok(Reply::DeletChar(DeletCharResult::from_payload(
-                &self.received[..],
-            ))),
-            None => panic!("Should not be reached"),
+/// Fails if a command, `cmd` if it is a built-in one, cannot be started now.
+#[inline(never)]
+fn check_can_start(
+    state: CommandState,
+    asleep: bool,
+    cmd: Option<&Command>,
+) -> Result<(), CoreError> {
+    if state.is_busy() {
+        return Err(CoreError::CommandInProgress);
+    }
+    if asleep {
+        return Err(CoreError::ModuleAsleep);
+    }
+    if let Some(buffer) = cmd.and_then(Command::invalid_buffer) {
+        return Err(CoreError::InvalidBuffer(buffer));
+    }
+    return Ok(());
+}
+
+/// Moves `state` on as far as the bytes `received` so far allow, returning the length of the
+/// packet once it is complete, or `None` while another byte is needed. `capacity` is the size
+/// of the receive buffer.
+#[inline(never)]
+fn advance_packet(
+    state: &mut CommandState,
+    received: &[u8],
+    capacity: usize,
+) -> Result<Option<u16>, CoreError> {
+    loop {
+        let expected = match *state {
+            CommandState::AwaitingHeader => REPLY_HEADER_LENGTH,
+            CommandState::AwaitingBody { length } => REPLY_HEADER_LENGTH + length,
+            _ => unreachable!(),
         };
+        if received.len() as u16 != expected {
+            return Ok(None);
+        }
+        if let CommandState::AwaitingBody { .. } = *state {
+            return Ok(Some(expected));
+        }
+        let length = codec::frame_length(received).map_err(CoreError::Decode)?;
+        if length > capacity {
+            return Err(CoreError::PacketTooLong(length));
+        }
+        let length = length as u16 - REPLY_HEADER_LENGTH;
+        *state = CommandState::AwaitingBody { length };
+    }
+}
+
+/// Decodes the packet `received` as the reply to `inflight`.
+fn decode_reply_to(inflight: Option<&Command>, received: &[u8]) -> Result<Reply, CoreError> {
+    let cmd = inflight.ok_or(CoreError::UnsolicitedReply)?;
+    let packet = codec::check_reply(received).map_err(CoreError::Decode)?;
+    return cmd.decode_reply(packet).map_err(CoreError::Decode);
+}
+
+/// Ends the command `inflight` with its reply, `received`, keeping the cached _index table_ in
+/// step.
+#[inline(never)]
+fn complete_command(
+    state: &mut CommandState,
+    inflight: Option<&Command>,
+    received: &[u8],
+    index_cache: &mut IndexCache,
+) -> Result<Reply, CoreError> {
+    *state = CommandState::Idle;
+    let reply = decode_reply_to(inflight, received);
+    if let Some(cmd) = inflight {
+        index_cache.observe(cmd, reply.as_ref().ok());
+    }
+    return reply;
+}
+
+/// Ends the command `inflight` without a reply, for example when the transport failed.
+#[inline(never)]
+fn abandon_command(
+    state: &mut CommandState,
+    inflight: Option<&Command>,
+    index_cache: &mut IndexCache,
+) {
+    *state = CommandState::Idle;
+    if let Some(cmd) = inflight {
+        index_cache.observe(cmd, None);
     }
 }
 
 #[cfg(test)]
 mod tests {
+    extern crate std;
+
     use super::*;
+    use crate::codec::DecodeError;
+    #[cfg(feature = "cmd-transfer")]
+    use crate::emulator::char_file;
+    use crate::emulator::Emulator;
+    use crate::utils::{CommandWriter, ToPayload};
+    use core::cell::{Cell, RefCell};
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_driver_is_send() {
+        // given: serial halves which can be sent to another thread or interrupt handler
+        struct SendTx;
+        struct SendRx;
+        fn assert_send<T: Send>() {}
+
+        // then: the driver can be too, for example to be an RTIC resource
+        assert_send::<R502<(SendTx, SendRx)>>();
+        assert_send::<R502<CombinedSerial<SendTx>>>();
+    }
 
     struct TestTx;
     struct TestRx;
@@ -213,16 +863,12 @@ mod tests {
 
     #[test]
     fn checksum_tests() {
-        // given: a r502 instance
-        let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
-        r502.cmd_buffer.clear();
-
-        // and: some data to compute a checksum of
-        r502.write_cmd_bytes(&[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0xc0, 0xc1]);
+        // given: some data to compute a checksum of
+        let packet = [0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0xc0, 0xc1];
 
         // when: computing the command checksum
         // then: the checksum is correct
-        assert_eq!(r502.compute_checksum(), 0x0181u16);
+        assert_eq!(codec::checksum(&packet[6..]), 0x0181u16);
     }
 
     #[test]
@@ -233,7 +879,7 @@ mod tests {
         r502.received.clear();
 
         // when: preparing a ReadSysPara command
-        r502.prepare_cmd(Command::ReadSysPara);
+        r502.prepare_cmd(Command::ReadSysPara).unwrap();
 
         // then: the resulting packet length is correct
         assert_eq!(r502.cmd_buffer.len(), 12);
@@ -250,7 +896,7 @@ mod tests {
         let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
         r502.cmd_buffer.clear();
         r502.received.clear();
-        *r502.inflight_request.borrow_mut() = Some(Command::ReadSysPara);
+        r502.inflight_request = Some(Command::ReadSysPara);
 
         // and: a reply in the receive buffer
         r502.received
@@ -277,6 +923,8 @@ mod tests {
             }) => {
                 assert_eq!(address, 0xffffffff);
                 assert_eq!(system_parameters.finger_library_size, 200);
+                assert_eq!(system_parameters.packet_size, 2);
+                assert_eq!(system_parameters.baud_setting, 6);
             }
             _ => panic!("Expected Reply::ReadSysPara, got something else!"),
         };
@@ -292,7 +940,7 @@ mod tests {
         // when: preparing a VfyPwd command
         r502.prepare_cmd(Command::VfyPwd {
             password: 0x00000000,
-        });
+        }).unwrap();
 
         // then: the resulting packet length is ok
         assert_eq!(r502.cmd_buffer.len(), 16);
@@ -312,7 +960,7 @@ mod tests {
         let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
         r502.cmd_buffer.clear();
         r502.received.clear();
-        *r502.inflight_request.borrow_mut() = Some(Command::VfyPwd {
+        r502.inflight_request = Some(Command::VfyPwd {
             password: 0x00000000,
         });
 
@@ -355,7 +1003,7 @@ mod tests {
         r502.received.clear();
 
         // when: preparing a GenImg command
-        r502.prepare_cmd(Command::GenImg);
+        r502.prepare_cmd(Command::GenImg).unwrap();
 
         // then: the resulting packet length is correct
         assert_eq!(r502.cmd_buffer.len(), 12);
@@ -372,7 +1020,7 @@ mod tests {
         let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
         r502.cmd_buffer.clear();
         r502.received.clear();
-        *r502.inflight_request.borrow_mut() = Some(Command::GenImg);
+        r502.inflight_request = Some(Command::GenImg);
 
         // and: a reply in the receive buffer
         r502.received
@@ -406,21 +1054,239 @@ mod tests {
     }
 
     #[test]
-    fn test_img_2_tz_serialisation() {
+    fn test_img_2_tz_serialisation() {
+        // given: a r502 instance
+        let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
+        r502.cmd_buffer.clear();
+        r502.received.clear();
+
+        // when: preparing a GenImg command
+        r502.prepare_cmd(Command::Img2Tz { buffer: 1 }).unwrap();
+
+        // then: the resulting packet length is correct
+        assert_eq!(r502.cmd_buffer.len(), 13);
+        // and: the packet is correct
+        assert_eq!(
+            &r502.cmd_buffer[..],
+            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x04, 0x02, 0x01, 0x00, 0x08,]
+        );
+    }
+
+    #[test]
+    fn test_set_sys_para_serialisation() {
+        // given: a r502 instance
+        let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
+        r502.cmd_buffer.clear();
+        r502.received.clear();
+
+        // when: preparing a SetSysPara command for security level 5
+        r502.prepare_cmd(Command::SetSysPara { parameter: 5, value: 5 }).unwrap();
+
+        // then: the resulting packet length is correct
+        assert_eq!(r502.cmd_buffer.len(), 14);
+        // and: the packet is correct
+        assert_eq!(
+            &r502.cmd_buffer[..],
+            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x05, 0x0e, 0x05, 0x05, 0x00, 0x1e]
+        );
+    }
+
+    #[test]
+    fn test_soft_rst_serialisation() {
+        // given: a r502 instance
+        let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
+        r502.cmd_buffer.clear();
+        r502.received.clear();
+
+        // when: preparing a SoftRst command
+        r502.prepare_cmd(Command::SoftRst).unwrap();
+
+        // then: the packet is correct
+        assert_eq!(
+            &r502.cmd_buffer[..],
+            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x3d, 0x00, 0x41]
+        );
+    }
+
+    #[test]
+    fn test_get_fw_ver_serialisation() {
+        // given: a r502 instance
+        let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
+        r502.cmd_buffer.clear();
+        r502.received.clear();
+
+        // when: preparing a GetFwVer command
+        r502.prepare_cmd(Command::GetFwVer).unwrap();
+
+        // then: the packet is correct
+        assert_eq!(
+            &r502.cmd_buffer[..],
+            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x3a, 0x00, 0x3e]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-notepad")]
+    fn test_read_notepad_serialisation() {
+        // given: a r502 instance
+        let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
+        r502.cmd_buffer.clear();
+        r502.received.clear();
+
+        // when: preparing a ReadNotepad command for page 3
+        r502.prepare_cmd(Command::ReadNotepad { page: 3 }).unwrap();
+
+        // then: the packet is correct
+        assert_eq!(
+            &r502.cmd_buffer[..],
+            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x04, 0x19, 0x03, 0x00, 0x21]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-notepad")]
+    fn test_write_notepad_serialisation() {
+        // given: a r502 instance
+        let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
+        r502.cmd_buffer.clear();
+        r502.received.clear();
+
+        // when: preparing a WriteNotepad command for page 1
+        r502.prepare_cmd(Command::WriteNotepad { page: 1, data: [0x02; 32] }).unwrap();
+
+        // then: the packet is correct
+        assert_eq!(r502.cmd_buffer.len(), 45);
+        assert_eq!(
+            &r502.cmd_buffer[..11],
+            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x24, 0x18, 0x01]
+        );
+        assert_eq!(&r502.cmd_buffer[11..43], &[0x02; 32]);
+        assert_eq!(&r502.cmd_buffer[43..], &[0x00, 0x7e]);
+    }
+
+    #[test]
+    fn test_sleep_serialisation() {
+        // given: a r502 instance
+        let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
+        r502.cmd_buffer.clear();
+        r502.received.clear();
+
+        // when: preparing a Sleep command
+        r502.prepare_cmd(Command::Sleep).unwrap();
+
+        // then: the packet is correct
+        assert_eq!(
+            &r502.cmd_buffer[..],
+            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x33, 0x00, 0x37]
+        );
+    }
+
+    #[test]
+    fn test_port_control_serialisation() {
+        // given: a r502 instance
+        let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
+        r502.cmd_buffer.clear();
+        r502.received.clear();
+
+        // when: preparing a PortControl command turning the port off
+        r502.prepare_cmd(Command::PortControl { enable: false }).unwrap();
+
+        // then: the packet is correct
+        assert_eq!(
+            &r502.cmd_buffer[..],
+            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x04, 0x17, 0x00, 0x00, 0x1c]
+        );
+    }
+
+    #[test]
+    fn test_set_adder_serialisation() {
+        // given: a r502 instance
+        let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
+        r502.cmd_buffer.clear();
+        r502.received.clear();
+
+        // when: preparing a SetAdder command
+        r502.prepare_cmd(Command::SetAdder { address: 0x0000abcd }).unwrap();
+
+        // then: the packet is correct
+        assert_eq!(
+            &r502.cmd_buffer[..],
+            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x07, 0x15, 0x00, 0x00, 0xab, 0xcd, 0x01, 0x95]
+        );
+    }
+
+    #[test]
+    fn test_get_chip_sn_deserialisation() {
+        // given: a r502 instance
+        let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
+        r502.cmd_buffer.clear();
+        r502.received.clear();
+        r502.inflight_request = Some(Command::GetChipSN);
+
+        // and: a reply carrying the serial number 0x01..0x20
+        let mut packet = [0u8; 44];
+        packet[..10].copy_from_slice(&[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x23, 0x00]);
+        for (i, byte) in packet[10..42].iter_mut().enumerate() {
+            *byte = i as u8 + 1;
+        }
+        let checksum = packet[6..42].iter().fold(0u16, |acc, b| acc + *b as u16);
+        packet[42..].copy_from_slice(&checksum.to_be_bytes());
+        r502.received.try_extend_from_slice(&packet).unwrap();
+
+        // when: parsing the reply
+        let reply = r502.parse_reply();
+
+        // then: the serial number is extracted
+        match reply {
+            Ok(Reply::GetChipSN(GetChipSNResult {
+                confirmation_code: GetChipSNStatus::Success,
+                serial_number,
+                ..
+            })) => {
+                assert_eq!(serial_number[0], 0x01);
+                assert_eq!(serial_number[31], 0x20);
+            }
+            _ => panic!("Expected Reply::GetChipSN, got something else!"),
+        };
+    }
+
+    #[test]
+    fn test_set_pwd_serialisation() {
+        // given: a r502 instance
+        let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
+        r502.cmd_buffer.clear();
+        r502.received.clear();
+
+        // when: preparing a SetPwd command
+        r502.prepare_cmd(Command::SetPwd { password: 0x12345678 }).unwrap();
+
+        // then: the resulting packet length is correct
+        assert_eq!(r502.cmd_buffer.len(), 16);
+        // and: the packet is correct
+        assert_eq!(
+            &r502.cmd_buffer[..],
+            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x07, 0x12, 0x12, 0x34, 0x56, 0x78, 0x01, 0x2e]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-led")]
+    fn test_aura_led_config_serialisation() {
         // given: a r502 instance
         let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
         r502.cmd_buffer.clear();
         r502.received.clear();
 
-        // when: preparing a GenImg command
-        r502.prepare_cmd(Command::Img2Tz { buffer: 1 });
+        // when: preparing an AuraLedConfig command for a blue breathing light
+        let cmd = Command::AuraLedConfig { control: 1, speed: 0x80, color: 2, times: 0 };
+        r502.prepare_cmd(cmd).unwrap();
 
         // then: the resulting packet length is correct
-        assert_eq!(r502.cmd_buffer.len(), 13);
+        assert_eq!(r502.cmd_buffer.len(), 16);
         // and: the packet is correct
         assert_eq!(
             &r502.cmd_buffer[..],
-            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x04, 0x02, 0x01, 0x00, 0x08,]
+            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x07, 0x35, 0x01, 0x80, 0x02, 0x00, 0x00, 0xc0]
         );
     }
 
@@ -430,7 +1296,7 @@ mod tests {
         let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
         r502.cmd_buffer.clear();
         r502.received.clear();
-        *r502.inflight_request.borrow_mut() = Some(Command::Img2Tz { buffer: 1 });
+        r502.inflight_request = Some(Command::Img2Tz { buffer: 1 });
 
         // and: a reply in the receive buffer
         r502.received
@@ -475,7 +1341,7 @@ mod tests {
             buffer: 1,
             start_index: 0,
             end_index: 0xffff,
-        });
+        }).unwrap();
 
         // then: the resulting packet length is correct
         assert_eq!(r502.cmd_buffer.len(), 17);
@@ -495,7 +1361,7 @@ mod tests {
         let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
         r502.cmd_buffer.clear();
         r502.received.clear();
-        *r502.inflight_request.borrow_mut() = Some(Command::Search {
+        r502.inflight_request = Some(Command::Search {
             buffer: 1,
             start_index: 0,
             end_index: 0xffff,
@@ -505,7 +1371,7 @@ mod tests {
         r502.received
             .try_extend_from_slice(&[
                 0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00, 0xff,
-                0x00, 0x4a,
+                0x01, 0x0d,
             ])
             .unwrap();
 
@@ -548,7 +1414,7 @@ mod tests {
         r502.prepare_cmd(Command::LoadChar {
             buffer: 2,
             index: 0,
-        });
+        }).unwrap();
 
         // then: the resulting packet length is correct
         assert_eq!(r502.cmd_buffer.len(), 15);
@@ -568,7 +1434,7 @@ mod tests {
         let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
         r502.cmd_buffer.clear();
         r502.received.clear();
-        *r502.inflight_request.borrow_mut() = Some(Command::LoadChar {
+        r502.inflight_request = Some(Command::LoadChar {
             buffer: 2,
             index: 0,
         });
@@ -612,7 +1478,7 @@ mod tests {
         r502.received.clear();
 
         // when: preparing a GenImg command
-        r502.prepare_cmd(Command::Match);
+        r502.prepare_cmd(Command::Match).unwrap();
 
         // then: the resulting packet length is correct
         assert_eq!(r502.cmd_buffer.len(), 12);
@@ -629,7 +1495,7 @@ mod tests {
         let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
         r502.cmd_buffer.clear();
         r502.received.clear();
-        *r502.inflight_request.borrow_mut() = Some(Command::Match);
+        r502.inflight_request = Some(Command::Match);
 
         // and: a reply in the receive buffer
         r502.received
@@ -672,7 +1538,7 @@ mod tests {
         r502.received.clear();
 
         // when: preparing a GenImg command
-        r502.prepare_cmd(Command::TemplateNum);
+        r502.prepare_cmd(Command::TemplateNum).unwrap();
 
         // then: the resulting packet length is correct
         assert_eq!(r502.cmd_buffer.len(), 12);
@@ -689,7 +1555,7 @@ mod tests {
         let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
         r502.cmd_buffer.clear();
         r502.received.clear();
-        *r502.inflight_request.borrow_mut() = Some(Command::TemplateNum);
+        r502.inflight_request = Some(Command::TemplateNum);
 
         // and: a reply in the receive buffer
         r502.received
@@ -725,6 +1591,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "cmd-enroll")]
     fn test_reg_model_serialisation() {
         // given: a r502 instance
         let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
@@ -732,7 +1599,7 @@ mod tests {
         r502.received.clear();
 
         // when: preparing a GenImg command
-        r502.prepare_cmd(Command::RegModel);
+        r502.prepare_cmd(Command::RegModel).unwrap();
 
         // then: the resulting packet length is correct
         assert_eq!(r502.cmd_buffer.len(), 12);
@@ -744,12 +1611,13 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "cmd-enroll")]
     fn test_reg_model_deserialisation() {
         // given: a r502 instance
         let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
         r502.cmd_buffer.clear();
         r502.received.clear();
-        *r502.inflight_request.borrow_mut() = Some(Command::RegModel);
+        r502.inflight_request = Some(Command::RegModel);
 
         // and: a reply in the receive buffer
         r502.received
@@ -783,6 +1651,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "cmd-enroll")]
     fn test_store_serialisation() {
         // given: a r502 instance
         let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
@@ -790,7 +1659,7 @@ mod tests {
         r502.received.clear();
 
         // when: preparing a GenImg command
-        r502.prepare_cmd(Command::Store { buffer: 1, index: 4 });
+        r502.prepare_cmd(Command::Store { buffer: 1, index: 4 }).unwrap();
 
         // then: the resulting packet length is correct
         assert_eq!(r502.cmd_buffer.len(), 15);
@@ -818,12 +1687,13 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "cmd-enroll")]
     fn test_store_deserialisation() {
         // given: a r502 instance
         let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
         r502.cmd_buffer.clear();
         r502.received.clear();
-        *r502.inflight_request.borrow_mut() = Some(Command::Store { index: 1, buffer: 1});
+        r502.inflight_request = Some(Command::Store { index: 1, buffer: 1});
 
         // and: a reply in the receive buffer
         r502.received
@@ -864,7 +1734,7 @@ mod tests {
         r502.received.clear();
 
         // when: preparing a GenImg command
-        r502.prepare_cmd(Command::DeletChar { start_index: 4, num_to_delete: 1 });
+        r502.prepare_cmd(Command::DeletChar { start_index: 4, num_to_delete: 1 }).unwrap();
 
         // then: the resulting packet length is correct
         assert_eq!(r502.cmd_buffer.len(), 16);
@@ -898,7 +1768,7 @@ mod tests {
         let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
         r502.cmd_buffer.clear();
         r502.received.clear();
-        *r502.inflight_request.borrow_mut() = Some(Command::DeletChar { start_index: 1, num_to_delete: 1});
+        r502.inflight_request = Some(Command::DeletChar { start_index: 1, num_to_delete: 1});
 
         // and: a reply in the receive buffer
         r502.received
@@ -930,4 +1800,681 @@ mod tests {
             _ => panic!("Expected Reply::DeletChar, got something else!"),
         };
     }
+
+    #[test]
+    fn test_empty_serialisation() {
+        // given: a r502 instance
+        let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
+        r502.cmd_buffer.clear();
+        r502.received.clear();
+
+        // when: preparing an Empty command
+        r502.prepare_cmd(Command::Empty).unwrap();
+
+        // then: the packet is correct
+        assert_eq!(
+            &r502.cmd_buffer[..],
+            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x0d, 0x00, 0x11]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_up_char_serialisation() {
+        // given: a r502 instance
+        let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
+        r502.cmd_buffer.clear();
+        r502.received.clear();
+
+        // when: preparing an UpChar command
+        r502.prepare_cmd(Command::UpChar { buffer: 1 }).unwrap();
+
+        // then: the resulting packet length is correct
+        assert_eq!(r502.cmd_buffer.len(), 13);
+        // and: the packet is correct
+        assert_eq!(
+            &r502.cmd_buffer[..],
+            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x04, 0x08, 0x01, 0x00, 0x0e,]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_down_char_serialisation() {
+        // given: a r502 instance
+        let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
+        r502.cmd_buffer.clear();
+        r502.received.clear();
+
+        // when: preparing a DownChar command
+        r502.prepare_cmd(Command::DownChar { buffer: 2 }).unwrap();
+
+        // then: the resulting packet length is correct
+        assert_eq!(r502.cmd_buffer.len(), 13);
+        // and: the packet is correct
+        assert_eq!(
+            &r502.cmd_buffer[..],
+            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x04, 0x09, 0x02, 0x00, 0x10,]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_up_char_deserialisation() {
+        // given: a r502 instance
+        let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
+        r502.cmd_buffer.clear();
+        r502.received.clear();
+        r502.inflight_request = Some(Command::UpChar { buffer: 1 });
+
+        // and: a reply in the receive buffer
+        r502.received
+            .try_extend_from_slice(&[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x0d, 0x00, 0x17,
+            ])
+            .unwrap();
+
+        // when: parsing a reply
+        let r = r502.parse_reply();
+
+        // then: reply is ok
+        assert_eq!(r.is_ok(), true);
+
+        // and: the reply is correct
+        match r.unwrap() {
+            Reply::UpChar(UpCharResult {
+                address,
+                confirmation_code,
+                checksum: _,
+            }) => {
+                assert_eq!(address, 0xffffffff);
+                match confirmation_code {
+                    UpCharStatus::UploadFailed => (),
+                    _ => panic!("Expected UpCharStatus::UploadFailed"),
+                };
+            }
+            _ => panic!("Expected Reply::UpChar, got something else!"),
+        };
+    }
+
+    #[test]
+    fn test_read_index_table_serialisation() {
+        // given: a r502 instance
+        let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
+        r502.cmd_buffer.clear();
+        r502.received.clear();
+
+        // when: preparing a ReadIndexTable command
+        r502.prepare_cmd(Command::ReadIndexTable { page: 1 }).unwrap();
+
+        // then: the resulting packet length is correct
+        assert_eq!(r502.cmd_buffer.len(), 13);
+        // and: the packet is correct
+        assert_eq!(
+            &r502.cmd_buffer[..],
+            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x04, 0x1f, 0x01, 0x00, 0x25,]
+        );
+    }
+
+    #[test]
+    fn test_read_index_table_deserialisation() {
+        // given: a r502 instance
+        let mut r502 = R502::new(TestTx, TestRx, 0xffffffff);
+        r502.cmd_buffer.clear();
+        r502.received.clear();
+        r502.inflight_request = Some(Command::ReadIndexTable { page: 0 });
+
+        // and: a reply in the receive buffer, with slots 0, 1 and 9 occupied
+        r502.received
+            .try_extend_from_slice(&[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x23, 0x00, 0x03, 0x02, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x2f,
+            ])
+            .unwrap();
+
+        // when: parsing a reply
+        let r = r502.parse_reply();
+
+        // then: reply is ok
+        assert_eq!(r.is_ok(), true);
+
+        // and: the reply is correct
+        match r.unwrap() {
+            Reply::ReadIndexTable(result) => {
+                assert_eq!(result.address, 0xffffffff);
+                match result.confirmation_code {
+                    ReadIndexTableStatus::Success => (),
+                    _ => panic!("Expected ReadIndexTableStatus::Success"),
+                };
+                assert_eq!(result.is_occupied(0), true);
+                assert_eq!(result.is_occupied(1), true);
+                assert_eq!(result.is_occupied(2), false);
+                assert_eq!(result.is_occupied(9), true);
+            }
+            _ => panic!("Expected Reply::ReadIndexTable, got something else!"),
+        };
+    }
+
+    /// A serial port which only moves as many bytes as it has been allowed to, and otherwise
+    /// reports `WouldBlock`.
+    #[derive(Clone, Default)]
+    struct Trickle {
+        written: Rc<RefCell<Vec<u8>>>,
+        write_budget: Rc<Cell<usize>>,
+        incoming: Rc<RefCell<VecDeque<u8>>>,
+        read_budget: Rc<Cell<usize>>,
+    }
+
+    impl Write<u8> for Trickle {
+        type Error = ();
+        fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+            if self.write_budget.get() == 0 {
+                return Err(nb::Error::WouldBlock);
+            }
+            self.write_budget.set(self.write_budget.get() - 1);
+            self.written.borrow_mut().push(word);
+            return Ok(());
+        }
+        fn flush(&mut self) -> nb::Result<(), Self::Error> {
+            return Ok(());
+        }
+    }
+
+    impl Read<u8> for Trickle {
+        type Error = ();
+        fn read(&mut self) -> nb::Result<u8, Self::Error> {
+            if self.read_budget.get() == 0 {
+                return Err(nb::Error::WouldBlock);
+            }
+            self.read_budget.set(self.read_budget.get() - 1);
+            return self.incoming.borrow_mut().pop_front().ok_or(nb::Error::Other(()));
+        }
+    }
+
+    #[test]
+    fn test_poll_byte_by_byte() {
+        // given: a serial port which has not taken or delivered anything yet
+        let port = Trickle::default();
+        let mut r502 = R502::new(port.clone(), port.clone(), 0xffffffff);
+
+        // when: starting a TemplateNum command
+        r502.start_command(Command::TemplateNum).unwrap();
+
+        // then: nothing has been written, and polling would block
+        assert_eq!(r502.state, CommandState::Writing { sent: 0 });
+        assert_eq!(r502.poll().err().map(|e| matches!(e, nb::Error::WouldBlock)), Some(true));
+
+        // when: the port takes one byte per poll
+        for sent in 1..=12 {
+            port.write_budget.set(1);
+            assert_eq!(r502.poll().is_err(), true);
+            assert_eq!(port.written.borrow().len(), sent);
+        }
+
+        // then: the whole command went out, and the reply is awaited
+        assert_eq!(
+            &port.written.borrow()[..],
+            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x1d, 0x00, 0x21]
+        );
+        assert_eq!(r502.state, CommandState::AwaitingHeader);
+
+        // when: the reply arrives one byte per poll
+        let reply = [
+            0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x05, 0x00, 0x00, 0x2a, 0x00, 0x36,
+        ];
+        port.incoming.borrow_mut().extend(reply.iter());
+        for received in 1..reply.len() {
+            port.read_budget.set(1);
+            assert_eq!(r502.poll().is_err(), true);
+            if received == 9 {
+                assert_eq!(r502.state, CommandState::AwaitingBody { length: 5 });
+            }
+        }
+        port.read_budget.set(1);
+
+        // then: the last byte completes the reply
+        match r502.poll() {
+            Ok(Reply::TemplateNum(result)) => assert_eq!(result.template_num, 42),
+            Ok(_) => panic!("Expected Reply::TemplateNum, got something else!"),
+            Err(_) => panic!("Expected the reply to be complete"),
+        };
+        assert_eq!(r502.state, CommandState::Idle);
+    }
+
+    #[test]
+    fn test_poll_misuse() {
+        // given: a serial port which takes nothing
+        let port = Trickle::default();
+        let mut r502 = R502::new(port.clone(), port.clone(), 0xffffffff);
+
+        // then: polling with no command in progress is an error
+        match r502.poll() {
+            Err(nb::Error::Other(Error::NoCommandInProgress)) => {}
+            _ => panic!("Expected Error::NoCommandInProgress"),
+        };
+
+        // and: so is starting a command while another one is in progress
+        r502.start_command(Command::HandShake).unwrap();
+        match r502.start_command(Command::HandShake) {
+            Err(Error::CommandInProgress) => {}
+            other => panic!("Expected Error::CommandInProgress, got {:?}", other),
+        };
+
+        // when: the port fails while reading the reply
+        port.write_budget.set(12);
+        port.read_budget.set(1);
+
+        // then: the command ends with the error, and the driver can be used again
+        match r502.poll() {
+            Err(nb::Error::Other(Error::RecvReadError(()))) => {}
+            _ => panic!("Expected Error::RecvReadError"),
+        };
+        assert_eq!(r502.state, CommandState::Idle);
+        assert_eq!(r502.start_command(Command::HandShake).is_ok(), true);
+    }
+
+    #[cfg(all(not(feature = "r503"), feature = "cmd-enroll", feature = "cmd-transfer"))]
+    #[test]
+    fn test_invalid_buffer() {
+        // given: an R502
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: naming buffers the R502 does not have
+        let none = r502.send_command(Command::Img2Tz { buffer: 0 });
+        let third = r502.send_command(Command::UpChar { buffer: 3 });
+        let sixth = r502.send_command(Command::Store { buffer: 6, index: 0 });
+
+        // then: the commands are refused without being sent
+        assert_eq!(matches!(none, Err(Error::InvalidBuffer(0))), true);
+        assert_eq!(matches!(third, Err(Error::InvalidBuffer(3))), true);
+        assert_eq!(matches!(sixth, Err(Error::InvalidBuffer(6))), true);
+        assert_eq!(emulator.instructions().is_empty(), true);
+    }
+
+    #[cfg(feature = "r503")]
+    #[test]
+    fn test_r503_buffers() {
+        // given: an R503 with a fingerprint image captured
+        let emulator = Emulator::r503();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        emulator.script_captures(7, 1);
+        r502.send_command(Command::GenImg).unwrap();
+
+        // when: converting the image into buffers 3 to 6
+        for buffer in 3..=6 {
+            match r502.send_command(Command::Img2Tz { buffer }).unwrap() {
+                Reply::Img2Tz(result) => {
+                    assert_eq!(matches!(result.confirmation_code, Img2TzStatus::Success), true)
+                }
+                other => panic!("Expected Reply::Img2Tz, got {:?}", other),
+            }
+        }
+
+        // then: every one of them holds the character file
+        for buffer in 2..6 {
+            assert_eq!(emulator.state().buffers[buffer].as_ref().unwrap()[0], 7);
+        }
+
+        // and: a seventh buffer is still refused without being sent
+        let seventh = r502.send_command(Command::Img2Tz { buffer: 7 });
+        assert_eq!(matches!(seventh, Err(Error::InvalidBuffer(7))), true);
+        assert_eq!(emulator.instructions().len(), 5);
+    }
+
+    #[cfg(all(feature = "r503", feature = "cmd-enroll"))]
+    #[test]
+    fn test_r503_confirmation_codes() {
+        // given: an R503 with an empty library, and a store it will refuse as full
+        let emulator = Emulator::r503();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        emulator.fail_next(0x06, 0x1f);
+
+        // when: searching the library, loading a slot, and storing a template
+        let search = r502.send_command(Command::Search { buffer: 1, start_index: 0, end_index: 9 });
+        let load = r502.send_command(Command::LoadChar { buffer: 1, index: 5 });
+        let store = r502.send_command(Command::Store { buffer: 1, index: 5 });
+
+        // then: each comes back with the R503's own code
+        match search.unwrap() {
+            Reply::Search(result) => {
+                assert_eq!(matches!(result.confirmation_code, SearchStatus::LibraryEmpty), true)
+            }
+            other => panic!("Expected Reply::Search, got {:?}", other),
+        }
+        match load.unwrap() {
+            Reply::LoadChar(result) => {
+                assert_eq!(matches!(result.confirmation_code, LoadCharStatus::TemplateEmpty), true)
+            }
+            other => panic!("Expected Reply::LoadChar, got {:?}", other),
+        }
+        match store.unwrap() {
+            Reply::Store(result) => {
+                assert_eq!(matches!(result.confirmation_code, StoreStatus::LibraryFull), true)
+            }
+            other => panic!("Expected Reply::Store, got {:?}", other),
+        }
+    }
+
+    /// `GetRandomCode`, defined as another crate would for a command the driver lacks.
+    struct GetRandomCode;
+
+    struct RandomCode {
+        confirmation_code: u8,
+        code: u32,
+    }
+
+    impl ToPayload for GetRandomCode {
+        fn to_payload(&self, writer: &mut dyn CommandWriter) {
+            writer.write_cmd_bytes(&[0x01]);
+            writer.write_cmd_bytes(&[0x00, 0x03]);
+            writer.write_cmd_bytes(&[0x14]);
+        }
+    }
+
+    impl ProtocolCommand for GetRandomCode {
+        type Reply = RandomCode;
+
+        fn decode_reply(&self, packet: &[u8]) -> Result<RandomCode, DecodeError> {
+            if packet.len() < 16 {
+                return Err(DecodeError::TooShort);
+            }
+            return Ok(RandomCode {
+                confirmation_code: packet[9],
+                code: u32::from_be_bytes([packet[10], packet[11], packet[12], packet[13]]),
+            });
+        }
+    }
+
+    /// Replays a canned `TemplateNum` reply, and can be made in a `const` context.
+    struct CannedReply {
+        position: usize,
+    }
+
+    const TEMPLATE_NUM_REPLY: [u8; 14] =
+        [0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x05, 0x00, 0x00, 0x03, 0x00, 0x0f];
+
+    impl Transport for CannedReply {
+        type WriteError = ();
+        type ReadError = ();
+
+        fn write_byte(&mut self, _: u8) -> nb::Result<(), ()> {
+            self.position = 0;
+            return Ok(());
+        }
+
+        fn flush(&mut self) -> nb::Result<(), ()> {
+            return Ok(());
+        }
+
+        fn read_byte(&mut self) -> nb::Result<u8, ()> {
+            let byte = TEMPLATE_NUM_REPLY.get(self.position).ok_or(nb::Error::Other(()))?;
+            self.position += 1;
+            return Ok(*byte);
+        }
+    }
+
+    static SENSOR: std::sync::Mutex<R502<CannedReply>> =
+        std::sync::Mutex::new(R502::with_transport(CannedReply { position: 0 }, 0xffffffff));
+
+    #[test]
+    fn test_static_driver() {
+        // given: a driver built in a static
+        let mut r502 = SENSOR.lock().unwrap();
+
+        // when: sending a command through it
+        let result = r502.send_command(Command::TemplateNum);
+
+        // then: the reply comes back
+        match result {
+            Ok(Reply::TemplateNum(result)) => assert_eq!(result.template_num, 3),
+            other => panic!("Expected Reply::TemplateNum, got {:?}", other),
+        }
+    }
+
+    /// Counts the templates through any transport, as code built on the driver would.
+    fn template_count<T: Transport>(r502: &mut R502<T>) -> Option<u16> {
+        r502.start_command(Command::TemplateNum).ok()?;
+        return match block!(r502.poll()) {
+            Ok(Reply::TemplateNum(result)) => Some(result.template_num),
+            _ => None,
+        };
+    }
+
+    #[test]
+    fn test_two_transports() {
+        // given: one driver on the emulator, and one on a transport of another type
+        let emulator = Emulator::new();
+        emulator.enroll(0, 1);
+        let (tx, rx) = emulator.serial();
+        let mut emulated = R502::new(tx, rx, 0xffffffff);
+        let mut canned = R502::with_transport(CannedReply { position: 0 }, 0xffffffff);
+
+        // when: using the same code with both, one after the other, then again
+        let first = (template_count(&mut emulated), template_count(&mut canned));
+        emulator.enroll(1, 2);
+        let second = (template_count(&mut emulated), template_count(&mut canned));
+
+        // then: each driver kept to its own module
+        assert_eq!(first, (Some(1), Some(3)));
+        assert_eq!(second, (Some(2), Some(3)));
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-notepad")]
+    fn test_send_command_view() {
+        // given: an R502 with a page of its notepad written
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        let mut data = [0u8; 32];
+        data[..5].copy_from_slice(b"hello");
+        r502.send_command(Command::WriteNotepad { page: 3, data }).unwrap();
+
+        // when: reading it back without copying it out
+        let view = r502.send_command_view(Command::ReadNotepad { page: 3 }).unwrap();
+
+        // then: the page is seen where it was received
+        assert_eq!(view.kind(), CommandKind::ReadNotepad);
+        assert_eq!(view.confirmation_code(), 0x00);
+        assert_eq!(view.data(), &data[..]);
+
+        // and: the next command replaces it
+        let view = r502.send_command_view(Command::GetFwVer).unwrap();
+        assert_eq!(view.data().starts_with(b"EMU-FW-1.4"), true);
+        assert_eq!(r502.state.is_busy(), false);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_send_command_view_index_cache() {
+        // given: an R502 with its index table cached, and a fingerprint to store
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.enable_index_cache(true);
+        r502.read_index_table().unwrap();
+        emulator.script_captures(7, 1);
+        r502.send_command(Command::GenImg).unwrap();
+        r502.send_command(Command::Img2Tz { buffer: 1 }).unwrap();
+
+        // when: storing it, looking at the reply in place
+        let view = r502.send_command_view(Command::Store { buffer: 1, index: 4 }).unwrap();
+        assert_eq!(view.confirmation_code(), 0x00);
+
+        // then: the cached index table has kept up
+        let table = r502.index_cache.table().unwrap();
+        assert_eq!(table.is_occupied(4), true);
+    }
+
+    #[test]
+    fn test_send_vendor_command() {
+        // given: an R502 with its index table cached
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.enable_index_cache(true);
+        r502.read_index_table().unwrap();
+
+        // when: sending a command defined outside the driver
+        let reply = r502.send(&GetRandomCode).unwrap();
+
+        // then: it went out as written, and the reply was decoded as the command said
+        assert_eq!(emulator.instructions().last(), Some(&0x14));
+        assert_eq!(reply.confirmation_code, 0x00);
+        assert_eq!(reply.code, 0xdeadbeef);
+
+        // and: the driver no longer trusts its cached index table
+        assert_eq!(r502.index_cache.table().is_none(), true);
+    }
+
+    #[test]
+    fn test_send_built_in_command() {
+        // given: an R502 with two templates enrolled
+        let emulator = Emulator::new();
+        emulator.enroll(0, 1);
+        emulator.enroll(1, 2);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: sending a built-in command the same way
+        let reply = r502.send(&Command::TemplateNum).unwrap();
+
+        // then: it decodes into a `Reply`, as with `send_command`
+        match reply {
+            Reply::TemplateNum(result) => assert_eq!(result.template_num, 2),
+            other => panic!("Expected Reply::TemplateNum, got {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "cmd-enroll", feature = "cmd-notepad", feature = "cmd-led"))]
+    fn test_small_buffers() {
+        // given: a driver with 64-byte buffers, and a user placing finger 7 twice
+        let emulator = Emulator::new();
+        emulator.enroll(0, 3);
+        emulator.script_captures(7, 2);
+        let mut r502 = R502::<_, 64, 64>::with_buffer_sizes(emulator.serial(), 0xffffffff);
+
+        // when: running through the commands which do not move template data
+        let commands = std::vec![
+            Command::HandShake,
+            Command::CheckSensor,
+            Command::VfyPwd { password: 0 },
+            Command::ReadSysPara,
+            Command::TemplateNum,
+            Command::ReadIndexTable { page: 0 },
+            Command::GenImg,
+            Command::Img2Tz { buffer: 1 },
+            Command::GenImg,
+            Command::GenImg,
+            Command::Img2Tz { buffer: 2 },
+            Command::Match,
+            Command::RegModel,
+            Command::Store { buffer: 1, index: 5 },
+            Command::LoadChar { buffer: 2, index: 5 },
+            Command::Search { buffer: 1, start_index: 0, end_index: 199 },
+            Command::WriteNotepad { page: 2, data: [0x5a; 32] },
+            Command::ReadNotepad { page: 2 },
+            Command::GetChipSN,
+            Command::GetAlgVer,
+            Command::GetFwVer,
+            Command::AuraLedConfig { control: 1, speed: 0x80, color: 2, times: 0 },
+            Command::SetSysPara { parameter: 5, value: 4 },
+            Command::SetPwd { password: 0 },
+            Command::PortControl { enable: true },
+            Command::DeletChar { start_index: 0, num_to_delete: 1 },
+            Command::Empty,
+        ];
+        let count = commands.len();
+        let replies: Vec<_> = commands.into_iter().map(|c| r502.send_command(c)).collect();
+
+        // then: every one of them went through
+        let failed: Vec<_> = replies.iter().filter(|reply| reply.is_err()).collect();
+        assert_eq!(failed.len(), 0, "{:?}", failed);
+        assert_eq!(emulator.instructions().len(), count);
+
+        // and: the replies made sense
+        match &replies[15] {
+            Ok(Reply::Search(result)) => assert_eq!(result.match_id, 5),
+            other => panic!("Expected Reply::Search, got {:?}", other),
+        }
+        match &replies[17] {
+            Ok(Reply::ReadNotepad(result)) => assert_eq!(result.data, [0x5a; 32]),
+            other => panic!("Expected Reply::ReadNotepad, got {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-notepad")]
+    fn test_command_too_long() {
+        // given: a driver with a command buffer too small for `WriteNotepad`
+        let emulator = Emulator::new();
+        let mut r502 = R502::<_, 64, 32>::with_buffer_sizes(emulator.serial(), 0xffffffff);
+
+        // when: sending it
+        let result = r502.send_command(Command::WriteNotepad { page: 0, data: [0x00; 32] });
+
+        // then: it is refused without being sent
+        assert_eq!(matches!(result, Err(Error::CommandTooLong { length: 45 })), true);
+        assert_eq!(emulator.instructions().is_empty(), true);
+
+        // and: shorter commands still go through
+        assert_eq!(r502.send_command(Command::HandShake).is_ok(), true);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-notepad")]
+    fn test_reply_too_long() {
+        // given: a driver with a receive buffer too small for the reply to `ReadNotepad`
+        let emulator = Emulator::new();
+        let mut r502 = R502::<_, 32, 64>::with_buffer_sizes(emulator.serial(), 0xffffffff);
+
+        // when: sending it
+        let result = r502.send_command(Command::ReadNotepad { page: 0 });
+
+        // then: the reply is refused once its header says how long it is
+        assert_eq!(matches!(result, Err(Error::RecvPacketTooLong { length: 44 })), true);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_template_data_too_long() {
+        // given: a driver with 64-byte buffers, and a template in buffer 1
+        let emulator = Emulator::new();
+        emulator.enroll(0, 3);
+        let mut r502 = R502::<_, 64, 64>::with_buffer_sizes(emulator.serial(), 0xffffffff);
+        r502.send_command(Command::LoadChar { buffer: 1, index: 0 }).unwrap();
+        r502.send_command(Command::UpChar { buffer: 1 }).unwrap();
+
+        // when: receiving the data packets which follow
+        let result = r502.receive_data(|_| {});
+
+        // then: they do not fit
+        assert_eq!(matches!(result, Err(Error::RecvPacketTooLong { length: 139 })), true);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_set_data_packet_size() {
+        // given: a module and a driver both set to 32-byte data packets
+        let emulator = Emulator::new();
+        emulator.state().packet_size = 32;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.set_data_packet_size(PacketSize::Bytes32);
+
+        // when: downloading a template into buffer 1
+        r502.send_command(Command::DownChar { buffer: 1 }).unwrap();
+        r502.send_data(&char_file(5)).unwrap();
+
+        // then: it arrives whole
+        assert_eq!(r502.data_packet_size, 32);
+        assert_eq!(emulator.state().buffers[0], Some(char_file(5)));
+    }
 }
@@ -0,0 +1,963 @@
+//! A wire-level emulation of an R502-style module, for testing code built on the driver
+//! without a module. It decodes the command packets the driver writes and answers them with
+//! properly framed replies, the way a real module would, keeping just enough state (library,
+//! character buffers, image buffer, system parameters) to make the workflows meaningful:
+//! enrolment, search, backup and restore all run against it as they would against hardware.
+//!
+//! Fingers are modelled as small integers. Placing finger `7` on the sensor and
+//! running `Img2Tz` produces a character file whose first byte is `7`, so
+//! `Match`, `Search` and `RegModel` can tell fingers apart. An image downloaded with `DownImage`
+//! shows the finger in its first byte, see `image_file`, unless all its bytes are the same, in
+//! which case there is nothing to convert. There is no real matching; the
+//! scores reported are `EmulatorState::match_score`, or set per slot with
+//! `Emulator::set_score`.
+#![cfg_attr(not(feature = "emulator"), allow(dead_code))]
+
+extern crate std;
+
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::digital::v2::InputPin;
+use embedded_hal::serial::{Read, Write};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::rc::Rc;
+use std::vec;
+use std::vec::Vec;
+
+use crate::consts::{
+    DEFAULT_PACKET_SIZE, NOTEPAD_PAGES, NOTEPAD_PAGE_SIZE, R502_IMAGE_LEN, R502_TEMPLATE_LEN,
+};
+use crate::led::LedState;
+
+/// Size of the character files produced by the emulated module.
+pub const CHAR_FILE_LEN: usize = R502_TEMPLATE_LEN;
+
+/// Transport error reported by the emulated serial port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmulatorError {
+    /// The driver tried to read a byte but the module had nothing to say.
+    Timeout,
+}
+
+#[derive(Debug)]
+pub struct EmulatorState {
+    /// The module address, which packets must carry to be answered.
+    pub address: u32,
+    /// The password `VfyPwd` checks against.
+    pub password: u32,
+    /// Whether `VfyPwd` has succeeded since the last restart.
+    pub authenticated: bool,
+    /// The template library, one entry per slot.
+    pub library: Vec<Option<Vec<u8>>>,
+    /// The character buffers, numbered from 1 on the wire.
+    pub buffers: Vec<Option<Vec<u8>>>,
+    /// The finger in the image buffer, if any.
+    pub image: Option<u8>,
+    /// Sensor readings for the next `GenImg` calls, see `Emulator::touch`.
+    pub touches: VecDeque<Option<u8>>,
+    /// Pending faults: (instruction, confirmation code, matching calls to let through first).
+    pub faults: VecDeque<(u8, u8, usize)>,
+    /// Instructions which are carried out, but whose next reply is lost on the way back.
+    pub lost_replies: Vec<u8>,
+    /// Every instruction code received, oldest first.
+    pub instructions: Vec<u8>,
+    /// Never reply, as if disconnected.
+    pub silent: bool,
+    /// `ReadSysPara` reports the module busy.
+    pub busy: bool,
+    /// How many of the next `ReadSysPara` calls report the module busy.
+    pub busy_reads: usize,
+    /// Reads with nothing to read return `WouldBlock`, like a real UART, instead of failing.
+    pub would_block: bool,
+    /// Bytes sent once the module has restarted after `SoftRst`.
+    pub boot_output: Vec<u8>,
+    /// Set by `Sleep`; a sleeping module ignores everything until `Emulator::wake`.
+    pub asleep: bool,
+    /// Whether the USB port is on, as set by `PortControl`.
+    pub port_enabled: bool,
+    /// The notepad pages.
+    pub notepad: [[u8; NOTEPAD_PAGE_SIZE]; NOTEPAD_PAGES as usize],
+    /// Whether `CheckSensor` succeeds.
+    pub sensor_ok: bool,
+    /// Instructions refused with a packet error, as on older modules, which send nothing but
+    /// the confirmation code.
+    pub unsupported: Vec<u8>,
+    /// The data packet size in bytes, as set with `SetSysPara`.
+    pub packet_size: usize,
+    /// The security level, as set with `SetSysPara`.
+    pub security_level: u16,
+    /// The serial number `GetChipSN` reports.
+    pub chip_serial: [u8; 32],
+    /// The baud rate setting, in units of 9600, as set with `SetSysPara`.
+    pub baud_setting: u16,
+    /// The rate the host's serial port is set to, in the same units, or `None` for one which
+    /// always matches the module. Bytes sent at one rate and received at another are lost.
+    pub link_baud: Option<u16>,
+    /// Every `SetSysPara` write as (parameter, value), oldest first.
+    pub sys_para_writes: Vec<(u8, u8)>,
+    /// How many of the next `Store` calls report success, but leave an unreadable slot.
+    pub corrupt_stores: usize,
+    /// Score reported for successful `Match` and `Search` calls.
+    pub match_score: u16,
+    /// Scores reported for `Search` hits in particular slots, instead of `match_score`.
+    pub scores: BTreeMap<usize, u16>,
+    /// Every setting sent to the ring LED, oldest first.
+    pub led: Vec<LedState>,
+    /// Readings of the touch pin still to come; once they run out it reads high.
+    pub touch_pin: VecDeque<bool>,
+    /// Every touch pin reading, with how many instructions had been received by then.
+    pub touch_pin_reads: Vec<(bool, usize)>,
+    /// Reading the touch pin fails.
+    pub touch_pin_broken: bool,
+    /// Library capacity reported by `ReadSysPara`, if not the real one.
+    pub reported_library_size: Option<u16>,
+    /// Address reported by `ReadSysPara`, if not the one it answers to.
+    pub reported_address: Option<u32>,
+    /// Answer with the R503's own confirmation codes where it has them: 0x24 for `Search` in an
+    /// empty library, 0x22 for `LoadChar` of an empty slot.
+    pub r503_codes: bool,
+    /// The character buffer a download goes into, or `None` for the image buffer, and what
+    /// has come in so far.
+    download: Option<(Option<usize>, Vec<u8>)>,
+    /// The rate the bytes waiting in `outgoing` go out at, if not `baud_setting`: the reply to
+    /// a baud rate change is sent at the old rate.
+    outgoing_baud: Option<u16>,
+    incoming: Vec<u8>,
+    outgoing: VecDeque<u8>,
+}
+
+/// Handle to a module emulated down to the wire protocol, to test against without hardware.
+/// Clones share the same state. `serial` gives the serial port to hand to the driver; the other
+/// methods set the scene and check on the module afterwards.
+///
+/// Fingers are modelled as small integers, and there is no real matching: `Search` finds the
+/// slots holding the finger on the sensor, with the score set by `set_score`, or
+/// `EmulatorState::match_score`.
+///
+/// ```
+/// use hzgrow_r502::{Command, Emulator, Reply, R502};
+///
+/// let emulator = Emulator::new();
+/// emulator.enroll(4, 7);
+/// emulator.touch(&[Some(7)]);
+///
+/// let (tx, rx) = emulator.serial();
+/// let mut r502 = R502::new(tx, rx, 0xffffffff);
+/// r502.send_command(Command::GenImg).unwrap();
+/// r502.send_command(Command::Img2Tz { buffer: 1 }).unwrap();
+/// let search = Command::Search { buffer: 1, start_index: 0, end_index: 199 };
+/// match r502.send_command(search) {
+///     Ok(Reply::Search(result)) => assert_eq!(result.match_id, 4),
+///     other => panic!("Expected Reply::Search, got {:?}", other),
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Emulator {
+    state: Rc<RefCell<EmulatorState>>,
+}
+
+/// The module's receive line, written to by the driver.
+#[derive(Debug)]
+pub struct EmulatorTx(Rc<RefCell<EmulatorState>>);
+
+/// The module's transmit line, read from by the driver.
+#[derive(Debug)]
+pub struct EmulatorRx(Rc<RefCell<EmulatorState>>);
+
+/// The module's touch output (WAKEUP), reading out `EmulatorState::touch_pin`.
+#[derive(Debug)]
+pub struct TouchPin(Rc<RefCell<EmulatorState>>);
+
+/// A delay that does not actually wait, so tests run instantly.
+#[derive(Debug)]
+pub struct NoDelay;
+
+impl DelayMs<u16> for NoDelay {
+    fn delay_ms(&mut self, _ms: u16) {}
+}
+
+/// Builds the character file the emulated module produces for `finger`.
+pub fn char_file(finger: u8) -> Vec<u8> {
+    let mut data = vec![0u8; CHAR_FILE_LEN];
+    data[0] = finger;
+    data[1] = 1;
+    for (i, byte) in data.iter_mut().enumerate().skip(2) {
+        *byte = (i as u8) ^ finger;
+    }
+    return data;
+}
+
+/// Builds an image of `finger`, as `DownImage` takes it: `R502_IMAGE_LEN` bytes with the
+/// finger in the first, and a pattern in the rest for `Img2Tz` to find features in.
+pub fn image_file(finger: u8) -> Vec<u8> {
+    let mut data = vec![0u8; R502_IMAGE_LEN];
+    data[0] = finger;
+    for (i, byte) in data.iter_mut().enumerate().skip(1) {
+        *byte = ((i / 96) as u8).wrapping_mul(17) ^ finger;
+    }
+    return data;
+}
+
+/// A version string as the module reports it: ASCII padded with zeroes to 32 bytes.
+pub fn version_string(version: &[u8]) -> [u8; 32] {
+    let mut padded = [0u8; 32];
+    padded[..version.len()].copy_from_slice(version);
+    return padded;
+}
+
+impl Emulator {
+    /// An R502: 200 library slots and two character buffers.
+    pub fn new() -> Self {
+        return Self::with_geometry(200, 2);
+    }
+
+    /// An R503-style module with six character buffers, and the R503's confirmation codes,
+    /// which the driver only decodes with the `r503` feature.
+    pub fn r503() -> Self {
+        let emulator = Self::with_geometry(200, 6);
+        emulator.state().r503_codes = true;
+        return emulator;
+    }
+
+    /// An R307-flavoured module: 1000 library slots, and none of `AuraLedConfig`,
+    /// `ReadIndexTable`, `GetAlgVer` or `GetFwVer`.
+    pub fn r307() -> Self {
+        let emulator = Self::with_geometry(1000, 2);
+        emulator.state().unsupported = vec![0x35, 0x1f, 0x39, 0x3a];
+        return emulator;
+    }
+
+    /// A module with `library_size` slots and `char_buffers` character buffers.
+    pub fn with_geometry(library_size: usize, char_buffers: usize) -> Self {
+        return Self {
+            state: Rc::new(RefCell::new(EmulatorState {
+                address: 0xffffffff,
+                password: 0x00000000,
+                authenticated: false,
+                library: vec![None; library_size],
+                buffers: vec![None; char_buffers],
+                image: None,
+                touches: VecDeque::new(),
+                faults: VecDeque::new(),
+                lost_replies: Vec::new(),
+                instructions: Vec::new(),
+                silent: false,
+                busy: false,
+                busy_reads: 0,
+                would_block: false,
+                boot_output: vec![0x55],
+                asleep: false,
+                port_enabled: true,
+                notepad: [[0x00; NOTEPAD_PAGE_SIZE]; NOTEPAD_PAGES as usize],
+                sensor_ok: true,
+                unsupported: Vec::new(),
+                packet_size: DEFAULT_PACKET_SIZE.bytes() as usize,
+                security_level: 3,
+                chip_serial: [0x5a; 32],
+                baud_setting: 6,
+                link_baud: None,
+                sys_para_writes: Vec::new(),
+                corrupt_stores: 0,
+                match_score: 200,
+                scores: BTreeMap::new(),
+                led: Vec::new(),
+                touch_pin: VecDeque::new(),
+                touch_pin_reads: Vec::new(),
+                touch_pin_broken: false,
+                reported_library_size: None,
+                reported_address: None,
+                r503_codes: false,
+                download: None,
+                outgoing_baud: None,
+                incoming: Vec::new(),
+                outgoing: VecDeque::new(),
+            })),
+        };
+    }
+
+    /// Returns the transmit and receive halves to hand over to `R502::new`.
+    pub fn serial(&self) -> (EmulatorTx, EmulatorRx) {
+        return (
+            EmulatorTx(self.state.clone()),
+            EmulatorRx(self.state.clone()),
+        );
+    }
+
+    /// The module's state, to set up a test or check on it afterwards.
+    pub fn state(&self) -> std::cell::RefMut<'_, EmulatorState> {
+        return self.state.borrow_mut();
+    }
+
+    /// Queues sensor readings: `Some(finger)` for a finger on the glass,
+    /// `None` for an empty sensor. Once the queue runs dry the sensor is empty.
+    pub fn touch(&self, readings: &[Option<u8>]) {
+        self.state().touches.extend(readings.iter().cloned());
+    }
+
+    /// Queues the readings of a user placing and lifting `finger` `captures` times.
+    pub fn script_captures(&self, finger: u8, captures: u8) {
+        for i in 0..captures {
+            self.touch(&[Some(finger)]);
+            if i + 1 < captures {
+                self.touch(&[None]);
+            }
+        }
+    }
+
+    /// Makes the next `instruction` fail with the given confirmation `code`.
+    pub fn fail_next(&self, instruction: u8, code: u8) {
+        self.fail_after(instruction, 0, code);
+    }
+
+    /// Lets `skip` calls of `instruction` through, then makes the next one fail with `code`.
+    pub fn fail_after(&self, instruction: u8, skip: usize, code: u8) {
+        self.state().faults.push_back((instruction, code, skip));
+    }
+
+    /// Carries out the next `instruction`, but loses its reply.
+    pub fn lose_next_reply(&self, instruction: u8) {
+        self.state().lost_replies.push(instruction);
+    }
+
+    /// Queues `bytes` to be read by the host, outside of any reply.
+    pub fn send_raw(&self, bytes: &[u8]) {
+        self.state().outgoing.extend(bytes.iter());
+    }
+
+    /// Stores the template of `finger` at `index` in the library.
+    pub fn enroll(&self, index: usize, finger: u8) {
+        self.state().library[index] = Some(char_file(finger));
+    }
+
+    /// The template in library slot `index`, if any.
+    pub fn slot(&self, index: usize) -> Option<Vec<u8>> {
+        return self.state().library[index].clone();
+    }
+
+    /// Makes `Search` report `score` when it finds a match in slot `index`.
+    pub fn set_score(&self, index: usize, score: u16) {
+        self.state().scores.insert(index, score);
+    }
+
+    /// Wakes a sleeping module, as a touch or a power cycle would, which then restarts.
+    pub fn wake(&self) {
+        let mut state = self.state();
+        state.asleep = false;
+        state.restart();
+    }
+
+    /// The touch output pin, reading out `readings`, then high.
+    pub fn touch_pin(&self, readings: &[bool]) -> TouchPin {
+        self.state().touch_pin.extend(readings.iter().copied());
+        return TouchPin(self.state.clone());
+    }
+
+    /// Every instruction code received so far, in order.
+    pub fn instructions(&self) -> Vec<u8> {
+        return self.state().instructions.clone();
+    }
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl Write<u8> for EmulatorTx {
+    type Error = EmulatorError;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        let mut state = self.0.borrow_mut();
+        if matches!(state.link_baud, Some(baud) if baud != state.baud_setting) {
+            return Ok(());
+        }
+        state.incoming.push(word);
+        state.process_incoming();
+        return Ok(());
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        return Ok(());
+    }
+}
+
+impl Read<u8> for EmulatorRx {
+    type Error = EmulatorError;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        let mut state = self.0.borrow_mut();
+        let sent_at = state.outgoing_baud.unwrap_or(state.baud_setting);
+        if matches!(state.link_baud, Some(baud) if baud != sent_at) {
+            state.outgoing.clear();
+        }
+        let word = state.outgoing.pop_front();
+        if state.outgoing.is_empty() {
+            state.outgoing_baud = None;
+        }
+        return match word {
+            Some(word) => Ok(word),
+            None if state.would_block => Err(nb::Error::WouldBlock),
+            None => Err(nb::Error::Other(EmulatorError::Timeout)),
+        };
+    }
+}
+
+impl InputPin for TouchPin {
+    type Error = ();
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        let mut state = self.0.borrow_mut();
+        if state.touch_pin_broken {
+            return Err(());
+        }
+        let high = state.touch_pin.pop_front().unwrap_or(true);
+        let sent = state.instructions.len();
+        state.touch_pin_reads.push((high, sent));
+        return Ok(high);
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        return self.is_high().map(|high| !high);
+    }
+}
+
+impl EmulatorState {
+    fn process_incoming(&mut self) {
+        if self.incoming.len() < 9 {
+            return;
+        }
+        let length = u16::from_be_bytes([self.incoming[7], self.incoming[8]]) as usize;
+        if self.incoming.len() < 9 + length {
+            return;
+        }
+
+        let packet: Vec<u8> = self.incoming.drain(..).collect();
+        let address = u32::from_be_bytes([packet[2], packet[3], packet[4], packet[5]]);
+        let pid = packet[6];
+        let body = &packet[9..packet.len() - 2];
+        let checksum = u16::from_be_bytes([packet[packet.len() - 2], packet[packet.len() - 1]]);
+        let computed = packet[6..packet.len() - 2]
+            .iter()
+            .fold(0u16, |acc, b| acc.wrapping_add(*b as u16));
+
+        if self.silent || self.asleep || address != self.address {
+            return;
+        }
+        if checksum == computed && (pid == 0x02 || pid == 0x08) {
+            self.receive_data(pid, body);
+            return;
+        }
+        if pid != 0x01 || checksum != computed || body.is_empty() {
+            self.reply(0x01, &[]);
+            return;
+        }
+
+        let instruction = body[0];
+        self.instructions.push(instruction);
+
+        if let Some(position) = self.faults.iter().position(|(i, _, _)| *i == instruction) {
+            if self.faults[position].2 > 0 {
+                self.faults[position].2 -= 1;
+            } else {
+                let (_, code, _) = self.faults.remove(position).unwrap();
+                let padding = vec![0u8; Self::reply_data_len(instruction)];
+                self.reply(code, &padding);
+                return;
+            }
+        }
+
+        if self.unsupported.contains(&instruction) {
+            self.reply(0x01, &[]);
+            return;
+        }
+
+        let replied = self.outgoing.len();
+        self.execute(instruction, &body[1..]);
+        if let Some(position) = self.lost_replies.iter().position(|i| *i == instruction) {
+            self.lost_replies.remove(position);
+            self.outgoing.truncate(replied);
+        }
+    }
+
+    fn execute(&mut self, instruction: u8, args: &[u8]) {
+        match instruction {
+            // GenImg
+            0x01 => match self.touches.pop_front().unwrap_or(None) {
+                Some(finger) => {
+                    self.image = Some(finger);
+                    self.reply(0x00, &[]);
+                }
+                None => self.reply(0x02, &[]),
+            },
+
+            // Img2Tz
+            0x02 => match self.image {
+                Some(finger) => match self.buffer_slot(args[0]) {
+                    Some(slot) => {
+                        self.buffers[slot] = Some(char_file(finger));
+                        self.reply(0x00, &[]);
+                    }
+                    None => self.reply(0x01, &[]),
+                },
+                None => self.reply(0x15, &[]),
+            },
+
+            // Match
+            0x03 => {
+                let first = self.buffers[0].as_ref().map(|b| b[0]);
+                let second = self.buffers[1].as_ref().map(|b| b[0]);
+                if first.is_some() && first == second {
+                    let score = self.match_score.to_be_bytes();
+                    self.reply(0x00, &score);
+                } else {
+                    self.reply(0x08, &[0x00, 0x00]);
+                }
+            }
+
+            // Search
+            0x04 => {
+                let probe = self
+                    .buffer_slot(args[0])
+                    .and_then(|slot| self.buffers[slot].as_ref().map(|b| b[0]));
+                let start = u16::from_be_bytes([args[1], args[2]]) as usize;
+                let end = u16::from_be_bytes([args[3], args[4]]) as usize;
+                let found = probe.and_then(|finger| {
+                    self.library
+                        .iter()
+                        .enumerate()
+                        .skip(start)
+                        .take(end.saturating_sub(start) + 1)
+                        .find(|(_, slot)| slot.as_ref().and_then(|t| t.first()) == Some(&finger))
+                        .map(|(index, _)| index as u16)
+                });
+                match found {
+                    Some(index) => {
+                        let score = self.scores.get(&(index as usize)).copied();
+                        let score = score.unwrap_or(self.match_score).to_be_bytes();
+                        let index = index.to_be_bytes();
+                        self.reply(0x00, &[index[0], index[1], score[0], score[1]]);
+                    }
+                    None if self.r503_codes && self.library.iter().all(Option::is_none) => {
+                        self.reply(0x24, &[0x00, 0x00, 0x00, 0x00])
+                    }
+                    None => self.reply(0x09, &[0x00, 0x00, 0x00, 0x00]),
+                }
+            }
+
+            // RegModel
+            0x05 => {
+                let samples: Vec<&Vec<u8>> = self.buffers.iter().flatten().collect();
+                let fingers_agree = samples.windows(2).all(|pair| pair[0][0] == pair[1][0]);
+                if samples.len() < 2 || !fingers_agree {
+                    self.reply(0x0a, &[]);
+                    return;
+                }
+                let mut template = samples[0].clone();
+                template[1] = samples
+                    .iter()
+                    .fold(0u8, |acc, sample| acc.saturating_add(sample[1]));
+                for buffer in self.buffers.iter_mut() {
+                    *buffer = None;
+                }
+                self.buffers[0] = Some(template.clone());
+                self.buffers[1] = Some(template);
+                self.reply(0x00, &[]);
+            }
+
+            // Store
+            0x06 => {
+                let index = u16::from_be_bytes([args[1], args[2]]) as usize;
+                let template = self
+                    .buffer_slot(args[0])
+                    .and_then(|slot| self.buffers[slot].clone());
+                if index >= self.library.len() {
+                    self.reply(0x0b, &[]);
+                } else if let Some(template) = template {
+                    if self.corrupt_stores > 0 {
+                        self.corrupt_stores -= 1;
+                        self.library[index] = Some(Vec::new());
+                    } else {
+                        self.library[index] = Some(template);
+                    }
+                    self.reply(0x00, &[]);
+                } else {
+                    self.reply(0x18, &[]);
+                }
+            }
+
+            // LoadChar
+            0x07 => {
+                let index = u16::from_be_bytes([args[1], args[2]]) as usize;
+                let slot = self.buffer_slot(args[0]);
+                if index >= self.library.len() {
+                    self.reply(0x0b, &[]);
+                } else {
+                    match (slot, self.library[index].clone()) {
+                        (Some(slot), Some(template)) if !template.is_empty() => {
+                            self.buffers[slot] = Some(template);
+                            self.reply(0x00, &[]);
+                        }
+                        (Some(_), None) if self.r503_codes => self.reply(0x22, &[]),
+                        _ => self.reply(0x0c, &[]),
+                    }
+                }
+            }
+
+            // UpChar
+            0x08 => match self.buffer_slot(args[0]).and_then(|slot| self.buffers[slot].clone()) {
+                Some(data) => {
+                    self.reply(0x00, &[]);
+                    self.send_data(&data);
+                }
+                None => self.reply(0x0d, &[]),
+            },
+
+            // DownChar
+            0x09 => match self.buffer_slot(args[0]) {
+                Some(slot) => {
+                    self.download = Some((Some(slot), Vec::new()));
+                    self.reply(0x00, &[]);
+                }
+                None => self.reply(0x0e, &[]),
+            },
+
+            // DownImage
+            0x0b => {
+                self.download = Some((None, Vec::new()));
+                self.reply(0x00, &[]);
+            }
+
+            // DeletChar
+            0x0c => {
+                let start = u16::from_be_bytes([args[0], args[1]]) as usize;
+                let count = u16::from_be_bytes([args[2], args[3]]) as usize;
+                if start + count > self.library.len() {
+                    self.reply(0x10, &[]);
+                    return;
+                }
+                for slot in &mut self.library[start..start + count] {
+                    *slot = None;
+                }
+                self.reply(0x00, &[]);
+            }
+
+            // Empty
+            0x0d => {
+                self.library.iter_mut().for_each(|slot| *slot = None);
+                self.reply(0x00, &[]);
+            }
+
+            // ReadSysPara
+            0x0f => {
+                let mut status = 0u16;
+                if self.busy_reads > 0 {
+                    self.busy_reads -= 1;
+                    status |= 1 << 0;
+                }
+                if self.busy {
+                    status |= 1 << 0;
+                }
+                if self.authenticated {
+                    status |= 1 << 2;
+                }
+                if self.image.is_some() {
+                    status |= 1 << 3;
+                }
+                let mut params = Vec::new();
+                params.extend_from_slice(&status.to_be_bytes());
+                params.extend_from_slice(&0x0009u16.to_be_bytes());
+                let library_size = self.reported_library_size.unwrap_or(self.library.len() as u16);
+                params.extend_from_slice(&library_size.to_be_bytes());
+                let packet_size_code = (self.packet_size / 32).trailing_zeros() as u16;
+                params.extend_from_slice(&self.security_level.to_be_bytes());
+                let address = self.reported_address.unwrap_or(self.address);
+                params.extend_from_slice(&address.to_be_bytes());
+                params.extend_from_slice(&packet_size_code.to_be_bytes());
+                params.extend_from_slice(&self.baud_setting.to_be_bytes());
+                self.reply(0x00, &params);
+            }
+
+            // VfyPwd
+            0x13 => {
+                let password = u32::from_be_bytes([args[0], args[1], args[2], args[3]]);
+                self.authenticated = password == self.password;
+                self.reply(if self.authenticated { 0x00 } else { 0x13 }, &[]);
+            }
+
+            // SetSysPara
+            0x0e => {
+                self.sys_para_writes.push((args[0], args[1]));
+                match (args[0], args[1]) {
+                    (4, baud @ 1..=12) => {
+                        self.outgoing_baud = Some(self.baud_setting);
+                        self.baud_setting = baud as u16;
+                    }
+                    (5, level @ 1..=5) => self.security_level = level as u16,
+                    (6, code @ 0..=3) => self.packet_size = 32 << code,
+                    (4..=6, _) => return self.reply(0x01, &[]),
+                    _ => return self.reply(0x1a, &[]),
+                }
+                self.reply(0x00, &[]);
+            }
+
+            // SetAdder
+            0x15 => {
+                self.address = u32::from_be_bytes([args[0], args[1], args[2], args[3]]);
+                self.reply(0x00, &[]);
+            }
+
+            // GetChipSN
+            0x34 => {
+                let serial = self.chip_serial;
+                self.reply(0x00, &serial);
+            }
+
+            // SetPwd
+            0x12 => {
+                self.password = u32::from_be_bytes([args[0], args[1], args[2], args[3]]);
+                self.reply(0x00, &[]);
+            }
+
+            // TemplateNum
+            0x1d => {
+                let count = self.library.iter().filter(|slot| slot.is_some()).count() as u16;
+                self.reply(0x00, &count.to_be_bytes());
+            }
+
+            // CheckSensor
+            0x36 => self.reply(if self.sensor_ok { 0x00 } else { 0x29 }, &[]),
+
+            // HandShake
+            0x40 => self.reply(0x00, &[]),
+
+            // SoftRst
+            0x3d => {
+                self.reply(0x00, &[]);
+                self.restart();
+            }
+
+            // Sleep
+            0x33 => {
+                self.reply(0x00, &[]);
+                self.asleep = true;
+            }
+
+            // GetAlgVer
+            0x39 => self.reply(0x00, &version_string(b"EMU-ALG-2.1")),
+
+            // GetFwVer
+            0x3a => self.reply(0x00, &version_string(b"EMU-FW-1.4")),
+
+            // GetRandomCode
+            0x14 => self.reply(0x00, &[0xde, 0xad, 0xbe, 0xef]),
+
+            // WriteNotepad
+            0x18 => match self.notepad.get_mut(args[0] as usize) {
+                Some(page) => {
+                    page.copy_from_slice(&args[1..33]);
+                    self.reply(0x00, &[]);
+                }
+                None => self.reply(0x01, &[]),
+            },
+
+            // ReadNotepad
+            0x19 => match self.notepad.get(args[0] as usize) {
+                Some(page) => {
+                    let page = *page;
+                    self.reply(0x00, &page);
+                }
+                None => self.reply(0x01, &[0x00; 32]),
+            },
+
+            // PortControl
+            0x17 => {
+                self.port_enabled = args[0] != 0;
+                self.reply(0x00, &[]);
+            }
+
+            // AuraLedConfig
+            0x35 => {
+                self.led.push(LedState {
+                    control: args[0],
+                    speed: args[1],
+                    color: args[2],
+                    times: args[3],
+                });
+                self.reply(0x00, &[]);
+            }
+
+            // ReadIndexTable
+            0x1f => {
+                let mut table = [0u8; 32];
+                let first = args[0] as usize * 256;
+                for (slot, template) in self.library.iter().enumerate().skip(first).take(256) {
+                    if template.is_some() {
+                        table[(slot - first) / 8] |= 1 << ((slot - first) % 8);
+                    }
+                }
+                self.reply(0x00, &table);
+            }
+
+            _ => self.reply(0x01, &[]),
+        }
+    }
+
+    /// Forgets the session and buffers, then says it is ready again.
+    fn restart(&mut self) {
+        self.authenticated = false;
+        self.buffers.iter_mut().for_each(|buffer| *buffer = None);
+        let boot_output = self.boot_output.clone();
+        self.outgoing.extend(boot_output.iter());
+    }
+
+    /// How many bytes follow the confirmation code in the reply to `instruction`.
+    fn reply_data_len(instruction: u8) -> usize {
+        return match instruction {
+            0x03 | 0x1d => 2,
+            0x04 | 0x14 => 4,
+            0x0f => 16,
+            0x19 | 0x1f | 0x34 | 0x39 | 0x3a => 32,
+            _ => 0,
+        };
+    }
+
+    fn receive_data(&mut self, pid: u8, data: &[u8]) {
+        if let Some((_, received)) = self.download.as_mut() {
+            received.extend_from_slice(data);
+        }
+        if pid == 0x08 {
+            match self.download.take() {
+                Some((Some(slot), received)) => self.buffers[slot] = Some(received),
+                Some((None, received)) => {
+                    let featureless = received.iter().all(|byte| Some(byte) == received.first());
+                    self.image = if featureless { None } else { Some(received[0]) };
+                }
+                None => {}
+            }
+        }
+    }
+
+    fn send_data(&mut self, data: &[u8]) {
+        let mut chunks = data.chunks(self.packet_size).peekable();
+        while let Some(chunk) = chunks.next() {
+            let pid = if chunks.peek().is_some() { 0x02 } else { 0x08 };
+            self.packet(pid, chunk);
+        }
+    }
+
+    fn buffer_slot(&self, buffer: u8) -> Option<usize> {
+        let slot = (buffer as usize).checked_sub(1)?;
+        return if slot < self.buffers.len() {
+            Some(slot)
+        } else {
+            None
+        };
+    }
+
+    fn reply(&mut self, code: u8, data: &[u8]) {
+        let mut body = vec![code];
+        body.extend_from_slice(data);
+        self.packet(0x07, &body);
+    }
+
+    fn packet(&mut self, pid: u8, body: &[u8]) {
+        let length = (body.len() + 2) as u16;
+        let mut checksum = (pid as u16).wrapping_add(length >> 8).wrapping_add(length & 0xff);
+        for byte in body {
+            checksum = checksum.wrapping_add(*byte as u16);
+        }
+        self.outgoing.extend([0xef, 0x01].iter());
+        self.outgoing.extend(self.address.to_be_bytes().iter());
+        self.outgoing.push_back(pid);
+        self.outgoing.extend(length.to_be_bytes().iter());
+        self.outgoing.extend(body.iter());
+        self.outgoing.extend(checksum.to_be_bytes().iter());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Command;
+    use crate::driver::R502;
+    #[cfg(feature = "cmd-enroll")]
+    use crate::responses::GenImgStatus;
+    use crate::responses::{Reply, SearchStatus};
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_enroll_sequence() {
+        // given: an emulated module, and a user placing finger 7 twice
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        emulator.script_captures(7, 2);
+
+        // when: capturing into both buffers, merging and storing at slot 5
+        let commands = std::vec![
+            Command::GenImg,
+            Command::Img2Tz { buffer: 1 },
+            Command::GenImg,
+            Command::GenImg,
+            Command::Img2Tz { buffer: 2 },
+            Command::RegModel,
+            Command::Store { buffer: 1, index: 5 },
+        ];
+        let replies: Vec<_> = commands.into_iter().map(|c| r502.send_command(c)).collect();
+
+        // then: every step but the capture with no finger succeeds
+        assert_eq!(matches!(replies[0], Ok(Reply::GenImg(_))), true);
+        match &replies[2] {
+            Ok(Reply::GenImg(result)) => {
+                let status = &result.confirmation_code;
+                assert_eq!(matches!(status, GenImgStatus::FingerNotDetected), true);
+            }
+            other => panic!("Expected Reply::GenImg, got {:?}", other),
+        }
+        assert_eq!(matches!(replies[5], Ok(Reply::RegModel(_))), true);
+        assert_eq!(matches!(replies[6], Ok(Reply::Store(_))), true);
+
+        // and: the library holds the merged template of finger 7
+        let template = emulator.slot(5).unwrap();
+        assert_eq!(template.len(), CHAR_FILE_LEN);
+        assert_eq!(template[0], 7);
+        assert_eq!(template[1], 2);
+        assert_eq!(emulator.instructions(), std::vec![0x01, 0x02, 0x01, 0x01, 0x02, 0x05, 0x06]);
+    }
+
+    #[test]
+    fn test_search_sequence() {
+        // given: a library with fingers 3 and 7, the latter with its own score
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        emulator.enroll(10, 3);
+        emulator.enroll(20, 7);
+        emulator.set_score(20, 87);
+
+        // when: searching for each finger, and for one not enrolled
+        let mut search = |finger| {
+            emulator.touch(&[Some(finger)]);
+            r502.send_command(Command::GenImg).unwrap();
+            r502.send_command(Command::Img2Tz { buffer: 1 }).unwrap();
+            let command = Command::Search { buffer: 1, start_index: 0, end_index: 199 };
+            return match r502.send_command(command) {
+                Ok(Reply::Search(result)) => result,
+                other => panic!("Expected Reply::Search, got {:?}", other),
+            };
+        };
+        let three = search(3);
+        let seven = search(7);
+        let nine = search(9);
+
+        // then: each enrolled finger is found in its slot, with its score
+        assert_eq!(matches!(three.confirmation_code, SearchStatus::Success), true);
+        assert_eq!((three.match_id, three.match_score), (10, 200));
+        assert_eq!(matches!(seven.confirmation_code, SearchStatus::Success), true);
+        assert_eq!((seven.match_id, seven.match_score), (20, 87));
+
+        // and: the other is not
+        assert_eq!(matches!(nine.confirmation_code, SearchStatus::NoMatch), true);
+    }
+}
@@ -0,0 +1,1275 @@
+use embedded_hal::blocking::delay::DelayMs;
+
+use crate::audit::{AuditError, AuditOperation};
+use crate::cancel::{CancelToken, NeverCancel};
+use crate::commands::{Command, CHAR_BUFFERS};
+use crate::driver::R502;
+use crate::identify::meets_min_score;
+#[cfg(feature = "cmd-transfer")]
+use crate::identify::VerifyError;
+use crate::led::LedFeedback;
+use crate::library::{IndexTable, LibraryError, MAX_LIBRARY_SIZE};
+use crate::maintenance::StoreVerifyError;
+use crate::notepad::NotepadError;
+use crate::responses::*;
+use crate::system::{AuthError, IdleError};
+use crate::template::{Template, TransferError};
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// The largest library `EnrollmentBatch` can keep track of.
+pub const MAX_BATCH_LIBRARY_SIZE: u16 = MAX_LIBRARY_SIZE;
+
+/// Settings for the enrolment helper.
+#[derive(Debug, Clone, Copy)]
+pub struct EnrollConfig {
+    /// How many times the finger is captured before the template is stored [2-6].
+    /// More captures make for a better template.
+    pub captures: u8,
+
+    /// How many _character buffers_ the module firmware has. The R502 has 2, R503-class
+    /// modules have 6. If there are fewer buffers than captures, the helper merges the
+    /// extra captures into the template one at a time. Defaults to `CHAR_BUFFERS`, and more
+    /// than that are refused by the driver with `Error::InvalidBuffer`.
+    pub char_buffers: u8,
+
+    /// How long to wait between polls of the sensor, in milliseconds.
+    pub poll_interval_ms: u16,
+
+    /// How many times to poll the sensor for a finger being placed or lifted
+    /// before giving up.
+    pub max_polls: u16,
+
+    /// If set, the new template is searched for in the library before it is stored, and
+    /// enrolment fails with `EnrollError::Duplicate` if the finger is already enrolled.
+    pub reject_duplicates: bool,
+
+    /// Lowest match score accepted when checking for duplicates and when verifying the owner
+    /// of a template in `update_template`, on top of the module's own security level.
+    pub min_score: Option<u16>,
+
+    /// Ring LED feedback: waiting while a finger should be placed, then success or failure
+    /// once enrolment is over. `None` leaves the LED alone.
+    pub led: Option<LedFeedback>,
+
+    /// If set, the template is stored with `store_and_verify`, which reads it back and checks
+    /// it against the original, storing it once more if the check fails.
+    pub verify_store: bool,
+
+    /// If set, waits up to this many milliseconds for the module to clear its busy bit after
+    /// combining the captures, before the template is checked and stored. Only used by the
+    /// blocking helpers.
+    pub idle_timeout_ms: Option<u32>,
+}
+
+impl Default for EnrollConfig {
+    fn default() -> Self {
+        return Self {
+            captures: 2,
+            char_buffers: CHAR_BUFFERS,
+            poll_interval_ms: 100,
+            max_polls: 100,
+            reject_duplicates: false,
+            min_score: None,
+            led: None,
+            verify_store: false,
+            idle_timeout_ms: None,
+        };
+    }
+}
+
+/// Prompts emitted by the enrolment helper, meant to be shown to the user.
+/// `capture` is 1-based and `captures` is the total number of captures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnrollPrompt {
+    /// The user should place their finger on the sensor.
+    PlaceFinger { capture: u8, captures: u8 },
+
+    /// The finger has been captured and processed.
+    Captured { capture: u8, captures: u8 },
+
+    /// The user should lift their finger off the sensor.
+    RemoveFinger { capture: u8, captures: u8 },
+
+    /// The user should place the finger that is currently enrolled, to prove
+    /// they own the template being replaced.
+    VerifyFinger,
+
+    /// The finger matched the enrolled template. The user should lift it before
+    /// the new captures start.
+    Verified,
+}
+
+/// Error type for the enrolment helper. The variants name the stage of enrolment which
+/// failed; [`is_user_recoverable`](#method.is_user_recoverable) tells whether the user should
+/// simply try again.
+#[derive(Debug)]
+pub enum EnrollError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// The capture count was outside of [2-6].
+    InvalidCaptureCount(u8),
+
+    /// The finger was not placed on, or lifted off, the sensor in time.
+    Timeout,
+
+    /// The sensor failed to capture an image for capture number `capture`. `capture` is 0 for
+    /// the finger presented to `update_template` for verification.
+    Capture { capture: u8, status: GenImgStatus },
+
+    /// The captured image could not be converted into a _character file_ in `buffer`.
+    Convert { buffer: u8, status: Img2TzStatus },
+
+    /// A later capture did not match the template built from the earlier ones.
+    Mismatch,
+
+    /// A later capture could not be checked against the template built from the earlier
+    /// ones.
+    Match(MatchStatus),
+
+    /// The captures could not be combined into a template.
+    Combine(RegModelStatus),
+
+    /// The duplicate check could not search the library.
+    Search(SearchStatus),
+
+    /// The finger is already enrolled at `index`, matching with `score`. Only checked if
+    /// `EnrollConfig::reject_duplicates` is set.
+    Duplicate { index: u16, score: u16 },
+
+    /// There is no free slot left in the library.
+    LibraryFull,
+
+    /// The template could not be stored in the library.
+    Store(StoreStatus),
+
+    /// The template was stored, but could not be read back intact, even after storing it
+    /// again. Only checked if `EnrollConfig::verify_store` is set.
+    StoreCorrupt,
+
+    /// An `EnrollmentSession` which had already failed was stepped again.
+    SessionOver,
+
+    /// The `CancelToken` asked for enrolment to stop. Nothing was stored.
+    Cancelled,
+
+    /// The module did not report itself idle within `EnrollConfig::idle_timeout_ms`.
+    Busy,
+
+    /// The cursor of `AllocationStrategy::Rotating` could not be saved to the notepad.
+    /// Nothing was stored.
+    Allocation(NotepadError<TXE, RXE>),
+
+    /// The template was stored, but the record of it could not be added to the `AuditLog`.
+    Audit(AuditError<TXE, RXE>),
+}
+
+impl<TXE, RXE> EnrollError<TXE, RXE> {
+    /// True if the failure comes down to how the finger was presented, so the user should
+    /// simply try again. Anything else needs fixing by someone other than the user.
+    pub fn is_user_recoverable(&self) -> bool {
+        return match self {
+            Self::Timeout | Self::Mismatch => true,
+            Self::Capture { status, .. } => matches!(status, GenImgStatus::ImageNotCaptured),
+            Self::Convert { status, .. } => matches!(
+                status,
+                Img2TzStatus::FingerprintImageDistorted | Img2TzStatus::ProcessingFailed
+            ),
+            Self::Combine(status) => matches!(status, RegModelStatus::ProcessingError),
+            _ => false,
+        };
+    }
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for EnrollError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+/// Error type for `update_template`.
+// The safety copy has to be handed back by value, as there is no heap to box it on.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum UpdateError<TXE, RXE> {
+    /// Communication with the R502 failed before anything was changed.
+    Comms(Error<TXE, RXE>),
+
+    /// The finger presented for verification could not be captured.
+    Verification(EnrollError<TXE, RXE>),
+
+    /// The slot could not be loaded, most likely because it is empty.
+    NotEnrolled(LoadCharStatus),
+
+    /// The finger presented for verification does not match the enrolled template, or only
+    /// with a score below `EnrollConfig::min_score`. `score` is 0 if there was no match at all.
+    NotOwner { score: u16 },
+
+    /// The safety copy of the old template could not be taken. Nothing was changed.
+    Backup(TransferError<TXE, RXE>),
+
+    /// The old template could not be deleted. Nothing was changed.
+    Delete(DeletCharStatus),
+
+    /// Enrolment of the new template failed. The old template has been restored.
+    Enroll(EnrollError<TXE, RXE>),
+
+    /// Enrolment of the new template failed, and so did restoring the old template.
+    /// The slot is now empty; the safety copy is returned so it is not lost.
+    RollbackFailed {
+        enroll: EnrollError<TXE, RXE>,
+        backup: Template,
+    },
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for UpdateError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+/// Error type for starting an `EnrollmentBatch`.
+#[derive(Debug)]
+pub enum BatchError<TXE, RXE> {
+    /// Authenticating with the R502 failed.
+    Auth(AuthError<TXE, RXE>),
+
+    /// The _index table_ could not be read.
+    Library(LibraryError<TXE, RXE>),
+}
+
+/// An enrolment session for enrolling many people back-to-back, for example when
+/// provisioning a new site.
+///
+/// Starting the batch authenticates and reads the _index table_ once. Every call to
+/// [`enroll_next`](#method.enroll_next) then picks a free slot from the cached table, as per
+/// [`R502::set_slot_allocation`](struct.R502.html#method.set_slot_allocation), enrols into it
+/// and marks it as used, without asking the R502 again.
+#[derive(Debug)]
+pub struct EnrollmentBatch<'a, T> {
+    r502: &'a mut R502<T>,
+    config: EnrollConfig,
+    table: IndexTable,
+    cursor: u16,
+}
+
+impl<'a, T> EnrollmentBatch<'a, T>
+where
+    T: Transport,
+{
+    /// Authenticates with `password` and caches the _index table_. Libraries larger than
+    /// `MAX_BATCH_LIBRARY_SIZE` are only used up to that size.
+    pub fn start(
+        r502: &'a mut R502<T>,
+        password: u32,
+        config: EnrollConfig,
+    ) -> Result<Self, BatchError<T::WriteError, T::ReadError>> {
+        let parameters = r502.authenticate(password).map_err(BatchError::Auth)?;
+        let table = r502
+            .read_index_table_pages(parameters.finger_library_size)
+            .map_err(BatchError::Library)?;
+        let cursor = r502
+            .allocation_cursor()
+            .map_err(|error| BatchError::Library(LibraryError::Notepad(error)))?;
+
+        return Ok(Self { r502, config, table, cursor });
+    }
+
+    /// True if slot `index` is in use, according to the cached _index table_.
+    pub fn is_occupied(&self, index: u16) -> bool {
+        return self.table.is_occupied(index);
+    }
+
+    /// Enrols the next person into the next free slot and returns its index. With
+    /// `AllocationStrategy::Rotating`, the cursor is saved before enrolling, so a failed
+    /// enrolment leaves its slot to be picked again only once the cursor comes back around.
+    /// See [`R502::enroll`](struct.R502.html#method.enroll) for the enrolment itself.
+    pub fn enroll_next<D, P>(
+        &mut self,
+        delay: &mut D,
+        prompts: P,
+    ) -> Result<u16, EnrollError<T::WriteError, T::ReadError>>
+    where
+        D: DelayMs<u16>,
+        P: FnMut(EnrollPrompt),
+    {
+        let index = match self.r502.slot_allocation().pick(&self.table, self.cursor) {
+            Some(index) => index,
+            None => return Err(EnrollError::LibraryFull),
+        };
+        self.r502.advance_allocation_cursor(index).map_err(EnrollError::Allocation)?;
+        self.cursor = index.wrapping_add(1);
+
+        self.r502.enroll(index, &self.config, delay, prompts)?;
+        self.table.set_occupied(index, true);
+        return Ok(index);
+    }
+}
+
+/// The _character buffer_ capture number `capture` is converted into. Without a buffer for
+/// every capture, the later captures share buffer 2 once the first two are combined.
+pub(crate) fn capture_buffer(capture: u8, config: &EnrollConfig) -> u8 {
+    if config.char_buffers >= config.captures || capture <= 2 {
+        return capture;
+    }
+    return 2;
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Enrols a new fingerprint into the library at `index`, capturing the finger
+    /// `config.captures` times. `prompts` is called whenever the user should do something.
+    ///
+    /// When the module has enough _character buffers_, each capture goes into its own buffer
+    /// and a single `RegModel` combines them all. Otherwise the first two captures are
+    /// combined, and every further capture is checked against the template with `Match`
+    /// and then merged into it with another `RegModel`.
+    ///
+    /// **Note:** This will overwrite any template already stored at `index`.
+    pub fn enroll<D, P>(
+        &mut self,
+        index: u16,
+        config: &EnrollConfig,
+        delay: &mut D,
+        prompts: P,
+    ) -> Result<(), EnrollError<T::WriteError, T::ReadError>>
+    where
+        D: DelayMs<u16>,
+        P: FnMut(EnrollPrompt),
+    {
+        return self.enroll_with_cancel(index, config, delay, prompts, &mut NeverCancel);
+    }
+
+    /// Like [`enroll`](#method.enroll), but checks `cancel` before every command sent while
+    /// waiting for the user, and between the captures. Once it asks to stop, the LED is set to
+    /// idle and `EnrollError::Cancelled` is returned, leaving the library as it was.
+    ///
+    /// The R502 carries out each command before replying to it, so there is nothing left
+    /// running on the module to abort; the driver can be used again straight away.
+    pub fn enroll_with_cancel<D, P, C>(
+        &mut self,
+        index: u16,
+        config: &EnrollConfig,
+        delay: &mut D,
+        prompts: P,
+        cancel: &mut C,
+    ) -> Result<(), EnrollError<T::WriteError, T::ReadError>>
+    where
+        D: DelayMs<u16>,
+        P: FnMut(EnrollPrompt),
+        C: CancelToken,
+    {
+        let captures = config.captures;
+        if !(2..=6).contains(&captures) {
+            return Err(EnrollError::InvalidCaptureCount(captures));
+        }
+
+        let result = self.enroll_captures(index, config, delay, prompts, cancel);
+        match result {
+            Ok(()) => self.led_feedback(&config.led, |led| led.success),
+            Err(EnrollError::Cancelled) => self.led_feedback(&config.led, |led| led.idle),
+            Err(_) => self.led_feedback(&config.led, |led| led.failure),
+        }
+        return result;
+    }
+
+    fn enroll_captures<D, P>(
+        &mut self,
+        index: u16,
+        config: &EnrollConfig,
+        delay: &mut D,
+        mut prompts: P,
+        cancel: &mut dyn CancelToken,
+    ) -> Result<(), EnrollError<T::WriteError, T::ReadError>>
+    where
+        D: DelayMs<u16>,
+        P: FnMut(EnrollPrompt),
+    {
+        for capture in 1..=config.captures {
+            let buffer = capture_buffer(capture, config);
+            self.enroll_capture(capture, buffer, config, delay, &mut prompts, cancel)?;
+            self.merge_capture(capture, config)?;
+        }
+        if cancel.is_cancelled() {
+            return Err(EnrollError::Cancelled);
+        }
+        if let Some(timeout_ms) = config.idle_timeout_ms {
+            match self.wait_until_idle(delay, config.poll_interval_ms, timeout_ms) {
+                Ok(()) => {}
+                Err(IdleError::Comms(error)) => return Err(EnrollError::Comms(error)),
+                Err(_) => return Err(EnrollError::Busy),
+            }
+        }
+        return self.finish_enrollment(index, config);
+    }
+
+    /// Folds capture number `capture`, already converted into its buffer, into the template.
+    ///
+    /// When the module has a buffer for every capture, nothing happens until the last one,
+    /// which combines them all. Otherwise the first two captures are combined, and every
+    /// later one is checked against the template and merged into it.
+    pub(crate) fn merge_capture(
+        &mut self,
+        capture: u8,
+        config: &EnrollConfig,
+    ) -> Result<(), EnrollError<T::WriteError, T::ReadError>> {
+        if config.char_buffers >= config.captures {
+            if capture == config.captures {
+                self.enroll_reg_model()?;
+            }
+            return Ok(());
+        }
+
+        if capture >= 3 {
+            let result = expect_reply!(self.send_command(Command::Match), Reply::Match)?;
+            match result.confirmation_code {
+                MatchStatus::Success => {}
+                MatchStatus::NoMatch => return Err(EnrollError::Mismatch),
+                status => return Err(EnrollError::Match(status)),
+            }
+        }
+        if capture >= 2 {
+            self.enroll_reg_model()?;
+        }
+        return Ok(());
+    }
+
+    /// Checks the finished template in buffer 1 for duplicates, if configured, and stores it
+    /// at `index`.
+    pub(crate) fn finish_enrollment(
+        &mut self,
+        index: u16,
+        config: &EnrollConfig,
+    ) -> Result<(), EnrollError<T::WriteError, T::ReadError>> {
+        if config.reject_duplicates {
+            let result = expect_reply!(
+                self.send_command(Command::Search { buffer: 1, start_index: 0, end_index: 0xffff }),
+                Reply::Search
+            )?;
+            match result.confirmation_code {
+                SearchStatus::Success if meets_min_score(result.match_score, config.min_score) => {
+                    return Err(EnrollError::Duplicate {
+                        index: result.match_id,
+                        score: result.match_score,
+                    });
+                }
+                SearchStatus::Success | SearchStatus::NoMatch => {}
+                #[cfg(feature = "r503")]
+                SearchStatus::LibraryEmpty => {}
+                status => return Err(EnrollError::Search(status)),
+            }
+        }
+
+        if config.verify_store {
+            match self.store_and_verify(1, index, true) {
+                Ok(()) => {}
+                Err(StoreVerifyError::Comms(error)) => return Err(EnrollError::Comms(error)),
+                Err(StoreVerifyError::Store(status)) => return Err(EnrollError::Store(status)),
+                Err(_) => return Err(EnrollError::StoreCorrupt),
+            }
+        } else {
+            let result = expect_reply!(
+                self.send_command(Command::Store { buffer: 1, index }),
+                Reply::Store
+            )?;
+            match result.confirmation_code {
+                StoreStatus::Success => {}
+                status => return Err(EnrollError::Store(status)),
+            }
+        }
+
+        return self.record_audit(AuditOperation::Enrolled, index, 1).map_err(EnrollError::Audit);
+    }
+
+    /// Replaces the template at `index` with a fresh enrolment, for when a user's finger has
+    /// changed enough that it no longer matches reliably.
+    ///
+    /// The user first has to present a finger that matches the existing template. A safety copy
+    /// of the template is then taken with `UpChar`, the slot is cleared, and the finger is
+    /// enrolled anew as per [`enroll`](#method.enroll). If enrolment fails, the safety copy is
+    /// written back with `DownChar` and `Store`, so the slot ends up as it was.
+    #[cfg(feature = "cmd-transfer")]
+    #[allow(clippy::result_large_err)]
+    pub fn update_template<D, P>(
+        &mut self,
+        index: u16,
+        config: &EnrollConfig,
+        delay: &mut D,
+        mut prompts: P,
+    ) -> Result<(), UpdateError<T::WriteError, T::ReadError>>
+    where
+        D: DelayMs<u16>,
+        P: FnMut(EnrollPrompt),
+    {
+        prompts(EnrollPrompt::VerifyFinger);
+        self.poll_finger(0, true, config, delay, &mut NeverCancel)
+            .map_err(UpdateError::Verification)?;
+        match self.verify_captured(index, config.min_score) {
+            Ok(_) => {}
+            Err(VerifyError::Comms(error)) => return Err(UpdateError::Comms(error)),
+            Err(VerifyError::Convert(status)) => {
+                let error = EnrollError::Convert { buffer: 1, status };
+                return Err(UpdateError::Verification(error));
+            }
+            Err(VerifyError::Load(status)) => return Err(UpdateError::NotEnrolled(status)),
+            Err(VerifyError::NotMatched { score }) => return Err(UpdateError::NotOwner { score }),
+            Err(_) => return Err(UpdateError::NotOwner { score: 0 }),
+        }
+
+        let backup = self.upload_template(2).map_err(UpdateError::Backup)?;
+
+        prompts(EnrollPrompt::Verified);
+        self.poll_finger(0, false, config, delay, &mut NeverCancel)
+            .map_err(UpdateError::Verification)?;
+
+        let result = expect_reply!(
+            self.send_command(Command::DeletChar { start_index: index, num_to_delete: 1 }),
+            Reply::DeletChar
+        )?;
+        match result.confirmation_code {
+            DeletCharStatus::Success => {}
+            status => return Err(UpdateError::Delete(status)),
+        }
+
+        let error = match self.enroll(index, config, delay, &mut prompts) {
+            Ok(()) => return Ok(()),
+            Err(error) => error,
+        };
+
+        if self.restore_template(index, &backup) {
+            return Err(UpdateError::Enroll(error));
+        }
+        return Err(UpdateError::RollbackFailed { enroll: error, backup });
+    }
+
+    /// Writes `template` back into the library at `index`, returning whether that worked.
+    #[cfg(feature = "cmd-transfer")]
+    fn restore_template(&mut self, index: u16, template: &Template) -> bool {
+        if self.download_template(1, template).is_err() {
+            return false;
+        }
+        return matches!(
+            self.send_command(Command::Store { buffer: 1, index }),
+            Ok(Reply::Store(StoreResult { confirmation_code: StoreStatus::Success, .. }))
+        );
+    }
+
+    fn enroll_capture<D, P>(
+        &mut self,
+        capture: u8,
+        buffer: u8,
+        config: &EnrollConfig,
+        delay: &mut D,
+        prompts: &mut P,
+        cancel: &mut dyn CancelToken,
+    ) -> Result<(), EnrollError<T::WriteError, T::ReadError>>
+    where
+        D: DelayMs<u16>,
+        P: FnMut(EnrollPrompt),
+    {
+        let captures = config.captures;
+
+        if capture > 1 {
+            prompts(EnrollPrompt::RemoveFinger { capture: capture - 1, captures });
+            self.poll_finger(capture - 1, false, config, delay, cancel)?;
+        }
+
+        prompts(EnrollPrompt::PlaceFinger { capture, captures });
+        self.led_feedback(&config.led, |led| led.waiting);
+        self.poll_finger(capture, true, config, delay, cancel)?;
+
+        self.convert_capture(buffer)?;
+        prompts(EnrollPrompt::Captured { capture, captures });
+        return Ok(());
+    }
+
+    /// Converts the image on the sensor into a _character file_ in `buffer`.
+    pub(crate) fn convert_capture(
+        &mut self,
+        buffer: u8,
+    ) -> Result<(), EnrollError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(
+            self.send_command(Command::Img2Tz { buffer }),
+            Reply::Img2Tz
+        )?;
+        return match result.confirmation_code {
+            Img2TzStatus::Success => Ok(()),
+            status => Err(EnrollError::Convert { buffer, status }),
+        };
+    }
+
+    /// Polls `GenImg` until a finger is on the sensor (if `present`) or the sensor is clear.
+    fn poll_finger<D>(
+        &mut self,
+        capture: u8,
+        present: bool,
+        config: &EnrollConfig,
+        delay: &mut D,
+        cancel: &mut dyn CancelToken,
+    ) -> Result<(), EnrollError<T::WriteError, T::ReadError>>
+    where
+        D: DelayMs<u16>,
+    {
+        for _ in 0..config.max_polls {
+            if cancel.is_cancelled() {
+                return Err(EnrollError::Cancelled);
+            }
+            if self.poll_sensor(capture, present)? {
+                return Ok(());
+            }
+            delay.delay_ms(config.poll_interval_ms);
+        }
+        return Err(EnrollError::Timeout);
+    }
+
+    /// Polls `GenImg` once for capture number `capture`, returning true if a finger is on the
+    /// sensor (if `present`) or the sensor is clear.
+    pub(crate) fn poll_sensor(
+        &mut self,
+        capture: u8,
+        present: bool,
+    ) -> Result<bool, EnrollError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(self.send_command(Command::GenImg), Reply::GenImg)?;
+        return match (present, result.confirmation_code) {
+            (true, GenImgStatus::Success) => Ok(true),
+            (false, GenImgStatus::FingerNotDetected) => Ok(true),
+            (_, GenImgStatus::PacketError) => {
+                Err(EnrollError::Capture { capture, status: GenImgStatus::PacketError })
+            }
+            _ => Ok(false),
+        };
+    }
+
+    fn enroll_reg_model(&mut self) -> Result<(), EnrollError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(self.send_command(Command::RegModel), Reply::RegModel)?;
+        return match result.confirmation_code {
+            RegModelStatus::Success => Ok(()),
+            status => Err(EnrollError::Combine(status)),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    #[cfg(feature = "cmd-notepad")]
+    use crate::allocation::{AllocationStrategy, SlotAllocation};
+    #[cfg(feature = "cmd-transfer")]
+    use crate::emulator::char_file;
+    use crate::emulator::{Emulator, EmulatorError, NoDelay};
+    use crate::led::LedFeedback;
+    #[cfg(feature = "cmd-notepad")]
+    use crate::notepad::NotepadPage;
+    #[cfg(feature = "cmd-led")]
+    use std::vec;
+    #[cfg(feature = "r503")]
+    use std::vec::Vec;
+
+    #[cfg(feature = "r503")]
+    #[test]
+    fn test_enroll_four_captures_r503() {
+        // given: an R503-style module with six character buffers
+        let emulator = Emulator::r503();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // and: a user who will place the same finger four times
+        emulator.script_captures(7, 4);
+
+        // when: enrolling with four captures
+        let config = EnrollConfig { captures: 4, char_buffers: 6, ..EnrollConfig::default() };
+        let mut prompts = Vec::new();
+        let result = r502.enroll(3, &config, &mut NoDelay, |prompt| prompts.push(prompt));
+
+        // then: enrolment succeeds
+        assert_eq!(result.is_ok(), true);
+
+        // and: every capture went into its own buffer, merged by a single RegModel
+        let instructions = emulator.instructions();
+        assert_eq!(instructions.iter().filter(|i| **i == 0x02).count(), 4);
+        assert_eq!(instructions.iter().filter(|i| **i == 0x05).count(), 1);
+        assert_eq!(instructions.iter().filter(|i| **i == 0x03).count(), 0);
+
+        // and: the stored template combines all four samples
+        let template = emulator.slot(3).unwrap();
+        assert_eq!(template[0], 7);
+        assert_eq!(template[1], 4);
+
+        // and: the user was prompted for every capture and every removal in between
+        let places = prompts
+            .iter()
+            .filter(|p| matches!(p, EnrollPrompt::PlaceFinger { .. }))
+            .count();
+        let removals = prompts
+            .iter()
+            .filter(|p| matches!(p, EnrollPrompt::RemoveFinger { .. }))
+            .count();
+        assert_eq!(places, 4);
+        assert_eq!(removals, 3);
+        assert_eq!(
+            prompts[prompts.len() - 1],
+            EnrollPrompt::Captured { capture: 4, captures: 4 }
+        );
+    }
+
+    #[test]
+    fn test_enroll_four_captures_two_buffer_fallback() {
+        // given: an R502 with two character buffers
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // and: a user who will place the same finger four times
+        emulator.script_captures(7, 4);
+
+        // when: enrolling with four captures
+        let config = EnrollConfig { captures: 4, char_buffers: 2, ..EnrollConfig::default() };
+        let result = r502.enroll(0, &config, &mut NoDelay, |_| {});
+
+        // then: enrolment succeeds
+        assert_eq!(result.is_ok(), true);
+
+        // and: captures only ever used buffers 1 and 2, matching and merging one at a time
+        let instructions = emulator.instructions();
+        assert_eq!(instructions.iter().filter(|i| **i == 0x03).count(), 2);
+        assert_eq!(instructions.iter().filter(|i| **i == 0x05).count(), 3);
+
+        // and: the stored template combines all four samples
+        let template = emulator.slot(0).unwrap();
+        assert_eq!(template[0], 7);
+        assert_eq!(template[1], 4);
+    }
+
+    #[test]
+    fn test_enroll_fallback_rejects_different_finger() {
+        // given: an R502 with two character buffers
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // and: a user who switches fingers on the third capture
+        emulator.touch(&[Some(7), None, Some(7), None, Some(8)]);
+
+        // when: enrolling with three captures
+        let config = EnrollConfig { captures: 3, char_buffers: 2, ..EnrollConfig::default() };
+        let result = r502.enroll(0, &config, &mut NoDelay, |_| {});
+
+        // then: enrolment fails and nothing is stored
+        match result {
+            Err(EnrollError::Mismatch) => {}
+            other => panic!("Expected EnrollError::Mismatch, got {:?}", other),
+        };
+        assert_eq!(emulator.slot(0).is_none(), true);
+    }
+
+    #[test]
+    fn test_enroll_fallback_stops_when_match_fails() {
+        // given: an R502 with two character buffers, which fails the check of the third
+        // capture with a packet error
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        emulator.script_captures(7, 3);
+        emulator.fail_next(0x03, 0x01);
+
+        // when: enrolling with three captures
+        let config = EnrollConfig { captures: 3, char_buffers: 2, ..EnrollConfig::default() };
+        let result = r502.enroll(0, &config, &mut NoDelay, |_| {});
+
+        // then: enrolment fails without merging the unchecked capture, and nothing is stored
+        match result {
+            Err(EnrollError::Match(MatchStatus::PacketError)) => {}
+            other => panic!("Expected EnrollError::Match, got {:?}", other),
+        };
+        assert_eq!(emulator.instructions().iter().filter(|i| **i == 0x05).count(), 1);
+        assert_eq!(emulator.slot(0).is_none(), true);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_update_template() {
+        // given: an R502 with finger 7 enrolled at index 5
+        let emulator = Emulator::new();
+        emulator.enroll(5, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // and: a user who verifies with finger 7, lifts it, then enrols twice more
+        emulator.touch(&[Some(7), None]);
+        emulator.script_captures(7, 2);
+
+        // when: updating the template
+        let config = EnrollConfig::default();
+        let result = r502.update_template(5, &config, &mut NoDelay, |_| {});
+
+        // then: the update succeeds with a freshly merged template
+        assert_eq!(result.is_ok(), true);
+        let template = emulator.slot(5).unwrap();
+        assert_eq!(template[0], 7);
+        assert_eq!(template[1], 2);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_update_template_wrong_finger() {
+        // given: an R502 with finger 7 enrolled at index 5
+        let emulator = Emulator::new();
+        emulator.enroll(5, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // and: someone presenting finger 8
+        emulator.touch(&[Some(8)]);
+
+        // when: updating the template
+        let result = r502.update_template(5, &EnrollConfig::default(), &mut NoDelay, |_| {});
+
+        // then: the update is refused and the slot is untouched
+        match result {
+            Err(UpdateError::NotOwner { score: 0 }) => {}
+            other => panic!("Expected UpdateError::NotOwner, got {:?}", other),
+        };
+        assert_eq!(emulator.slot(5), Some(char_file(7)));
+        assert_eq!(emulator.instructions().contains(&0x0c), false);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_update_template_rolls_back_on_reg_model_failure() {
+        // given: an R502 with finger 7 enrolled at index 5
+        let emulator = Emulator::new();
+        emulator.enroll(5, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // and: a user who verifies and re-enrols finger 7
+        emulator.touch(&[Some(7), None]);
+        emulator.script_captures(7, 2);
+
+        // and: a module which fails to combine the new captures
+        emulator.fail_next(0x05, 0x0a);
+
+        // when: updating the template
+        let result = r502.update_template(5, &EnrollConfig::default(), &mut NoDelay, |_| {});
+
+        // then: the enrolment error is reported
+        match result {
+            Err(UpdateError::Enroll(EnrollError::Combine(RegModelStatus::ProcessingError))) => {}
+            other => panic!("Expected UpdateError::Enroll, got {:?}", other),
+        };
+
+        // and: the slot was deleted, then restored from the safety copy
+        let instructions = emulator.instructions();
+        let deleted_at = instructions.iter().position(|i| *i == 0x0c).unwrap();
+        let restored_at = instructions.iter().rposition(|i| *i == 0x09).unwrap();
+        assert_eq!(deleted_at < restored_at, true);
+        assert_eq!(emulator.slot(5), Some(char_file(7)));
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_update_template_reports_failed_rollback() {
+        // given: an R502 with finger 7 enrolled at index 5
+        let emulator = Emulator::new();
+        emulator.enroll(5, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // and: a user who verifies and re-enrols finger 7
+        emulator.touch(&[Some(7), None]);
+        emulator.script_captures(7, 2);
+
+        // and: a module which fails to combine the captures and then to write flash
+        emulator.fail_next(0x05, 0x0a);
+        emulator.fail_next(0x06, 0x18);
+
+        // when: updating the template
+        let result = r502.update_template(5, &EnrollConfig::default(), &mut NoDelay, |_| {});
+
+        // then: the safety copy is handed back
+        match result {
+            Err(UpdateError::RollbackFailed { backup, .. }) => {
+                assert_eq!(backup.as_bytes(), &char_file(7)[..]);
+            }
+            other => panic!("Expected UpdateError::RollbackFailed, got {:?}", other),
+        };
+        assert_eq!(emulator.slot(5), None);
+    }
+
+    #[test]
+    fn test_enroll_rejects_duplicate() {
+        // given: an R502 with finger 7 enrolled at index 4
+        let emulator = Emulator::new();
+        emulator.enroll(4, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // and: a user who enrols finger 7 again
+        emulator.script_captures(7, 2);
+
+        // when: enrolling with duplicate checks enabled
+        let config = EnrollConfig { reject_duplicates: true, ..EnrollConfig::default() };
+        let result = r502.enroll(5, &config, &mut NoDelay, |_| {});
+
+        // then: the existing enrolment is reported and nothing is stored
+        match result {
+            Err(EnrollError::Duplicate { index: 4, score: 200 }) => {}
+            other => panic!("Expected EnrollError::Duplicate, got {:?}", other),
+        };
+        assert_eq!(emulator.slot(5), None);
+    }
+
+    #[test]
+    fn test_enroll_verify_store() {
+        // given: an R502 which corrupts the first store
+        let emulator = Emulator::new();
+        emulator.state().corrupt_stores = 1;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // and: a user who enrols finger 7
+        emulator.script_captures(7, 2);
+
+        // when: enrolling with store verification enabled
+        let config = EnrollConfig { verify_store: true, ..EnrollConfig::default() };
+        let result = r502.enroll(5, &config, &mut NoDelay, |_| {});
+
+        // then: the corrupt store is caught and the template stored again
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(emulator.instructions().iter().filter(|i| **i == 0x06).count(), 2);
+        assert_eq!(emulator.slot(5).unwrap()[0], 7);
+    }
+
+    #[test]
+    fn test_enroll_duplicate_check_min_score() {
+        for (score, duplicate) in [(49, false), (50, true), (51, true)] {
+            // given: an R502 with finger 7 enrolled at index 4, matching it with `score`
+            let emulator = Emulator::new();
+            emulator.enroll(4, 7);
+            emulator.state().match_score = score;
+            let (tx, rx) = emulator.serial();
+            let mut r502 = R502::new(tx, rx, 0xffffffff);
+            emulator.script_captures(7, 2);
+
+            // when: enrolling finger 7 again with a threshold of 50
+            let config = EnrollConfig {
+                reject_duplicates: true,
+                min_score: Some(50),
+                ..EnrollConfig::default()
+            };
+            let result = r502.enroll(5, &config, &mut NoDelay, |_| {});
+
+            // then: only matches at or above the threshold count as duplicates
+            assert_eq!(matches!(result, Err(EnrollError::Duplicate { index: 4, .. })), duplicate);
+            assert_eq!(emulator.slot(5).is_some(), !duplicate);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_update_template_min_score() {
+        // given: an R502 with finger 7 enrolled at index 5, which matches weakly
+        let emulator = Emulator::new();
+        emulator.enroll(5, 7);
+        emulator.state().match_score = 12;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        emulator.touch(&[Some(7)]);
+
+        // when: updating the template with a threshold of 50
+        let config = EnrollConfig { min_score: Some(50), ..EnrollConfig::default() };
+        let result = r502.update_template(5, &config, &mut NoDelay, |_| {});
+
+        // then: the owner is not accepted, and the raw score is reported
+        match result {
+            Err(UpdateError::NotOwner { score: 12 }) => {}
+            other => panic!("Expected UpdateError::NotOwner, got {:?}", other),
+        };
+        assert_eq!(emulator.slot(5), Some(char_file(7)));
+    }
+
+    #[test]
+    fn test_enrollment_batch() {
+        // given: an R502 with slot 1 already in use
+        let emulator = Emulator::new();
+        emulator.enroll(1, 6);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // and: three users waiting to enrol
+        emulator.script_captures(7, 2);
+        emulator.touch(&[None]);
+        emulator.script_captures(8, 2);
+        emulator.touch(&[None]);
+        emulator.script_captures(9, 2);
+
+        // when: enrolling all three in one batch
+        let mut batch = EnrollmentBatch::start(&mut r502, 0, EnrollConfig::default()).unwrap();
+        let first = batch.enroll_next(&mut NoDelay, |_| {}).unwrap();
+        assert_eq!(batch.is_occupied(first), true);
+        let second = batch.enroll_next(&mut NoDelay, |_| {}).unwrap();
+        let third = batch.enroll_next(&mut NoDelay, |_| {}).unwrap();
+
+        // then: they got the free slots in order, skipping the one in use
+        assert_eq!((first, second, third), (0, 2, 3));
+        assert_eq!(emulator.slot(0).unwrap()[0], 7);
+        assert_eq!(emulator.slot(2).unwrap()[0], 8);
+        assert_eq!(emulator.slot(3).unwrap()[0], 9);
+
+        // and: the index table was only read once
+        let instructions = emulator.instructions();
+        assert_eq!(instructions.iter().filter(|i| **i == 0x1f).count(), 1);
+        assert_eq!(instructions.iter().filter(|i| **i == 0x13).count(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-notepad")]
+    fn test_enrollment_batch_rotating() {
+        // given: an R502 whose rotation cursor was left at slot 5, with slot 6 in use
+        let emulator = Emulator::with_geometry(8, 2);
+        emulator.enroll(6, 6);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        let page = NotepadPage::new(0).unwrap();
+        r502.set_slot_allocation(SlotAllocation {
+            strategy: AllocationStrategy::Rotating { page },
+            ..SlotAllocation::default()
+        });
+        r502.advance_allocation_cursor(4).unwrap();
+
+        // and: three users waiting to enrol
+        emulator.script_captures(7, 2);
+        emulator.touch(&[None]);
+        emulator.script_captures(8, 2);
+        emulator.touch(&[None]);
+        emulator.script_captures(9, 2);
+
+        // when: enrolling all three in one batch
+        let mut batch = EnrollmentBatch::start(&mut r502, 0, EnrollConfig::default()).unwrap();
+        let first = batch.enroll_next(&mut NoDelay, |_| {}).unwrap();
+        let second = batch.enroll_next(&mut NoDelay, |_| {}).unwrap();
+        let third = batch.enroll_next(&mut NoDelay, |_| {}).unwrap();
+
+        // then: they got the free slots from the cursor onwards, wrapping around
+        assert_eq!((first, second, third), (5, 7, 0));
+        assert_eq!(emulator.slot(0).unwrap()[0], 9);
+
+        // and: the cursor was saved past the last one
+        assert_eq!(r502.allocation_cursor().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_enrollment_batch_library_full() {
+        // given: an R502 with a two-slot library, one of which is in use
+        let emulator = Emulator::with_geometry(2, 2);
+        emulator.enroll(0, 6);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        emulator.script_captures(7, 2);
+
+        // when: enrolling two users
+        let mut batch = EnrollmentBatch::start(&mut r502, 0, EnrollConfig::default()).unwrap();
+        let first = batch.enroll_next(&mut NoDelay, |_| {});
+        let second = batch.enroll_next(&mut NoDelay, |_| {});
+
+        // then: the second one is told the library is full
+        assert_eq!(first.unwrap(), 1);
+        match second {
+            Err(EnrollError::LibraryFull) => {}
+            other => panic!("Expected EnrollError::LibraryFull, got {:?}", other),
+        };
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-led")]
+    fn test_enroll_led_feedback() {
+        // given: an R502 with a ring LED
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        emulator.script_captures(7, 2);
+
+        // when: enrolling with LED feedback
+        let feedback = LedFeedback::default();
+        let config = EnrollConfig { led: Some(feedback), ..EnrollConfig::default() };
+        r502.enroll(3, &config, &mut NoDelay, |_| {}).unwrap();
+
+        // then: the LED breathes before each capture, and lights up once the template is stored
+        let led = 0x35;
+        assert_eq!(
+            emulator.instructions(),
+            vec![led, 0x01, 0x02, 0x01, led, 0x01, 0x02, 0x05, 0x06, led]
+        );
+        assert_eq!(
+            emulator.state().led,
+            vec![feedback.waiting, feedback.waiting, feedback.success]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-led")]
+    fn test_enroll_led_feedback_failure_without_led() {
+        // given: an R502 without a ring LED
+        let emulator = Emulator::new();
+        emulator.state().unsupported.push(0x35);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // and: a user who places two different fingers
+        emulator.script_captures(7, 1);
+        emulator.touch(&[None]);
+        emulator.script_captures(8, 1);
+
+        // when: enrolling with LED feedback
+        let config = EnrollConfig { led: Some(LedFeedback::default()), ..EnrollConfig::default() };
+        let result = r502.enroll(3, &config, &mut NoDelay, |_| {});
+
+        // then: the enrolment error is reported, not the missing LED
+        match result {
+            Err(EnrollError::Combine(RegModelStatus::ProcessingError)) => {}
+            other => panic!("Expected EnrollError::RegModel, got {:?}", other),
+        };
+        assert_eq!(emulator.instructions().last(), Some(&0x35));
+    }
+
+    #[test]
+    fn test_enroll_invalid_capture_count() {
+        // given: an R502
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: enrolling with seven captures
+        let config = EnrollConfig { captures: 7, ..EnrollConfig::default() };
+        let result = r502.enroll(0, &config, &mut NoDelay, |_| {});
+
+        // then: the helper refuses without talking to the module
+        match result {
+            Err(EnrollError::InvalidCaptureCount(7)) => {}
+            other => panic!("Expected EnrollError::InvalidCaptureCount, got {:?}", other),
+        };
+        assert_eq!(emulator.instructions().is_empty(), true);
+    }
+
+    #[test]
+    fn test_enroll_cancelled_between_captures() {
+        // given: a user who places finger 7 once, then walks away
+        let emulator = Emulator::new();
+        emulator.script_captures(7, 1);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: the application cancels once the first capture is done
+        let captured = core::cell::Cell::new(false);
+        let config = EnrollConfig { led: Some(LedFeedback::default()), ..EnrollConfig::default() };
+        let result = r502.enroll_with_cancel(
+            5,
+            &config,
+            &mut NoDelay,
+            |prompt| {
+                if let EnrollPrompt::Captured { capture: 1, .. } = prompt {
+                    captured.set(true);
+                }
+            },
+            &mut || captured.get(),
+        );
+
+        // then: enrolment stops before the second capture, storing nothing
+        match result {
+            Err(EnrollError::Cancelled) => {}
+            other => panic!("Expected EnrollError::Cancelled, got {:?}", other),
+        };
+        assert_eq!(emulator.slot(5), None);
+        assert_eq!(emulator.instructions().iter().filter(|i| **i == 0x01).count(), 1);
+        #[cfg(feature = "cmd-led")]
+        assert_eq!(emulator.state().led.last(), Some(&LedFeedback::default().idle));
+
+        // and: the driver is usable straight away
+        emulator.script_captures(7, 2);
+        let result = r502.enroll(5, &EnrollConfig::default(), &mut NoDelay, |_| {});
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(emulator.slot(5).unwrap()[0], 7);
+    }
+
+    /// Enrols finger 7 with `instruction` failing with `code` after `skip` calls.
+    fn enroll_with_fault(
+        instruction: u8,
+        skip: usize,
+        code: u8,
+    ) -> EnrollError<EmulatorError, EmulatorError> {
+        let emulator = Emulator::new();
+        emulator.script_captures(7, 2);
+        emulator.fail_after(instruction, skip, code);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        let config = EnrollConfig { reject_duplicates: true, ..EnrollConfig::default() };
+        return r502.enroll(5, &config, &mut NoDelay, |_| {}).unwrap_err();
+    }
+
+    #[test]
+    fn test_enroll_error_stages() {
+        // then: a broken capture is reported with its capture number
+        match enroll_with_fault(0x01, 0, 0x01) {
+            EnrollError::Capture { capture: 1, status: GenImgStatus::PacketError } => {}
+            other => panic!("Expected EnrollError::Capture, got {:?}", other),
+        };
+
+        // and: a poor second image is reported with its buffer, as something to retry
+        let error = enroll_with_fault(0x02, 1, 0x06);
+        match error {
+            EnrollError::Convert {
+                buffer: 2,
+                status: Img2TzStatus::FingerprintImageDistorted,
+            } => {}
+            ref other => panic!("Expected EnrollError::Convert, got {:?}", other),
+        };
+        assert_eq!(error.is_user_recoverable(), true);
+
+        // and: so are captures which do not combine
+        let error = enroll_with_fault(0x05, 0, 0x0a);
+        match error {
+            EnrollError::Combine(RegModelStatus::ProcessingError) => {}
+            ref other => panic!("Expected EnrollError::Combine, got {:?}", other),
+        };
+        assert_eq!(error.is_user_recoverable(), true);
+
+        // and: a failed duplicate check is reported as such
+        match enroll_with_fault(0x04, 0, 0x01) {
+            EnrollError::Search(SearchStatus::PacketError) => {}
+            other => panic!("Expected EnrollError::Search, got {:?}", other),
+        };
+
+        // and: a flash write failure is not the user's problem
+        let error = enroll_with_fault(0x06, 0, 0x18);
+        match error {
+            EnrollError::Store(StoreStatus::WriteError) => {}
+            ref other => panic!("Expected EnrollError::Store, got {:?}", other),
+        };
+        assert_eq!(error.is_user_recoverable(), false);
+    }
+
+    #[test]
+    fn test_enroll_waits_until_idle() {
+        // given: a module which stays busy for two polls after combining the captures
+        let emulator = Emulator::new();
+        emulator.script_captures(7, 2);
+        emulator.state().busy_reads = 2;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: enrolling with an idle wait
+        let config = EnrollConfig { idle_timeout_ms: Some(1000), ..EnrollConfig::default() };
+        let result = r502.enroll(5, &config, &mut NoDelay, |_| {});
+
+        // then: the template is only stored once the module is idle
+        assert_eq!(result.is_ok(), true);
+        let instructions = emulator.instructions();
+        assert_eq!(instructions[instructions.len() - 4..], [0x0f, 0x0f, 0x0f, 0x06]);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::consts::FRAME_HEADER_LENGTH;
+use crate::driver::R502;
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// What `R502::poll_event` returns.
+pub type EventResult<'a, TXE, RXE> = Result<Option<Event<'a>>, Error<TXE, RXE>>;
+
+/// Something the module sent without being asked, see
+/// [`R502::poll_event`](struct.R502.html#method.poll_event).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// The ready byte, sent once the module has powered up or restarted.
+    Ready,
+
+    /// A reply packet which answers no command in progress, such as one of the staged
+    /// acknowledgements modules send from their automatic modes, or a reply which came too
+    /// late. `data` is what follows the confirmation code.
+    Reply { confirmation_code: u8, data: &'a [u8] },
+
+    /// A data packet which is part of no transfer. `last` marks the end-of-data packet.
+    Data { payload: &'a [u8], last: bool },
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Checks, without blocking, for something the module sent without being asked, and
+    /// returns it once it has arrived whole. Call it from an event loop between commands.
+    ///
+    /// Returns `None` while nothing is complete yet, and also while a command is in progress:
+    /// its reply is left for [`poll`](#method.poll) or [`send_command`](#method.send_command).
+    /// Starting a command while an event is only partly in drops what there is of it. Bytes
+    /// which cannot start a packet are skipped.
+    ///
+    /// # Errors
+    ///
+    /// `Error::RecvReadError` if the serial port failed, `Error::RecvBadChecksum` if the
+    /// packet was damaged, `Error::RecvBadStartCode` if its header made no sense, and
+    /// `Error::RecvWrongReplyType` for a packet which the module should never send, such as a
+    /// command. Each error ends the event, and the next call starts afresh.
+    pub fn poll_event(&mut self) -> EventResult<'_, T::WriteError, T::ReadError> {
+        let packet = match self.poll_unsolicited() {
+            Ok(Some(packet)) => packet,
+            Ok(None) => return Ok(Some(Event::Ready)),
+            Err(nb::Error::WouldBlock) => return Ok(None),
+            Err(nb::Error::Other(error)) => return Err(error),
+        };
+
+        let payload = &packet[FRAME_HEADER_LENGTH..packet.len() - 2];
+        return match packet[6] {
+            0x07 => Ok(Some(Event::Reply { confirmation_code: payload[0], data: &payload[1..] })),
+            0x02 => Ok(Some(Event::Data { payload, last: false })),
+            0x08 => Ok(Some(Event::Data { payload, last: true })),
+            _ => Err(Error::RecvWrongReplyType),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::codec::checksum;
+    use crate::commands::Command;
+    use crate::emulator::{Emulator, EmulatorRx, EmulatorTx};
+    use crate::power::READY_BYTE;
+    use crate::responses::Reply;
+    use std::vec::Vec;
+
+    /// A packet of type `packet_id` from the default address, carrying `payload`.
+    fn packet(packet_id: u8, payload: &[u8]) -> Vec<u8> {
+        let mut packet = std::vec![0xef, 0x01, 0xff, 0xff, 0xff, 0xff, packet_id];
+        packet.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        packet.extend_from_slice(payload);
+        let chk = checksum(&packet[6..]);
+        packet.extend_from_slice(&chk.to_be_bytes());
+        return packet;
+    }
+
+    fn r502(emulator: &Emulator) -> R502<(EmulatorTx, EmulatorRx)> {
+        emulator.state().would_block = true;
+        let (tx, rx) = emulator.serial();
+        return R502::new(tx, rx, 0xffffffff);
+    }
+
+    #[test]
+    fn test_events_between_commands() {
+        // given: an R502 which has answered a command
+        let emulator = Emulator::new();
+        let mut r502 = r502(&emulator);
+        r502.send_command(Command::TemplateNum).unwrap();
+
+        // when: the module sends a reply and then a data packet of its own accord
+        emulator.send_raw(&packet(0x07, &[0x00, 0x03, 0x00, 0x07]));
+        emulator.send_raw(&packet(0x08, &[0xaa, 0xbb]));
+
+        // then: each comes out as an event, and then there is nothing more
+        assert_eq!(
+            r502.poll_event().unwrap(),
+            Some(Event::Reply { confirmation_code: 0x00, data: &[0x03, 0x00, 0x07] })
+        );
+        let data = Event::Data { payload: &[0xaa, 0xbb], last: true };
+        assert_eq!(r502.poll_event().unwrap(), Some(data));
+        assert_eq!(r502.poll_event().unwrap(), None);
+
+        // and: commands work as before
+        let reply = r502.send_command(Command::TemplateNum);
+        assert_eq!(matches!(reply, Ok(Reply::TemplateNum(_))), true);
+    }
+
+    #[test]
+    fn test_event_arriving_in_pieces() {
+        // given: an R502, with noise and the ready byte on the line
+        let emulator = Emulator::new();
+        let mut r502 = r502(&emulator);
+        emulator.send_raw(&[0x00, READY_BYTE]);
+        let ack = packet(0x07, &[0x00, 0x01]);
+
+        // when: polling as an unsolicited reply trickles in
+        // then: the noise is skipped, and each event comes out once complete
+        assert_eq!(r502.poll_event().unwrap(), Some(Event::Ready));
+        emulator.send_raw(&ack[..5]);
+        assert_eq!(r502.poll_event().unwrap(), None);
+        emulator.send_raw(&ack[5..]);
+        let event = r502.poll_event().unwrap();
+        assert_eq!(event, Some(Event::Reply { confirmation_code: 0x00, data: &[0x01] }));
+    }
+
+    #[test]
+    fn test_solicited_reply_left_alone() {
+        // given: an R502 with a command in progress, whose reply has arrived
+        let emulator = Emulator::new();
+        let mut r502 = r502(&emulator);
+        r502.start_command(Command::HandShake).unwrap();
+
+        // when: polling for events
+        let event = r502.poll_event().unwrap();
+
+        // then: there are none, and the reply is still there for the command
+        assert_eq!(event, None);
+        assert_eq!(matches!(nb::block!(r502.poll()), Ok(Reply::HandShake(_))), true);
+    }
+
+    #[test]
+    fn test_command_drops_partial_event() {
+        // given: an R502 with part of an unsolicited packet received
+        let emulator = Emulator::new();
+        let mut r502 = r502(&emulator);
+        emulator.send_raw(&packet(0x07, &[0x00])[..4]);
+        assert_eq!(r502.poll_event().unwrap(), None);
+
+        // when: sending a command
+        let reply = r502.send_command(Command::TemplateNum);
+
+        // then: the command goes ahead
+        assert_eq!(matches!(reply, Ok(Reply::TemplateNum(_))), true);
+    }
+}
@@ -0,0 +1,230 @@
+#![cfg_attr(not(feature = "mock"), allow(dead_code))]
+
+extern crate std;
+
+use core::convert::Infallible;
+use std::collections::{BTreeSet, VecDeque};
+use std::vec::Vec;
+
+use arrayvec::ArrayVec;
+#[cfg(feature = "cmd-enroll")]
+use embedded_hal::blocking::delay::DelayMs;
+
+#[cfg(feature = "cmd-enroll")]
+use crate::enroll::{EnrollConfig, EnrollError, EnrollPrompt};
+use crate::identify::{IdentifyConfig, IdentifyError};
+use crate::library::{IndexTable, LibraryError};
+use crate::maintenance::{DeleteError, DeleteRange, DeleteReport};
+use crate::reader::FingerprintReader;
+use crate::responses::{DeletCharStatus, SystemParameters};
+use crate::system::AuthError;
+
+/// Outcome of a scripted `FakeReader::identify`.
+pub type FakeIdentify = Result<(u16, u16), IdentifyError<Infallible, Infallible>>;
+
+/// Outcome of a scripted `FakeReader::enroll`.
+#[cfg(feature = "cmd-enroll")]
+pub type FakeEnroll = Result<(), EnrollError<Infallible, Infallible>>;
+
+/// A call made to a `FakeReader`, as recorded in `FakeReader::calls`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FakeCall {
+    Authenticate { password: u32 },
+    Identify,
+    Enroll { index: u16 },
+    Delete { indices: Vec<u16> },
+    TemplateCount,
+}
+
+/// A stand-in for a fingerprint module, implementing `FingerprintReader` without any serial
+/// port, for unit-testing application code written against the trait. Each call is recorded
+/// in `calls` and answered from the fields, which tests set up and inspect directly:
+///
+/// * `authenticate` accepts `password` and returns `parameters`.
+/// * `identify` returns the next of `identifications`, or `IdentifyError::NoFinger` once they
+///   run out, as an empty sensor would.
+/// * `enroll` returns the next of `enrollments`, or succeeds once they run out. A success
+///   adds the slot to `library`, after prompting for each capture as the driver would.
+/// * `delete` and `template_count` work on `library`, within the capacity in `parameters`.
+///
+/// The serial port errors are `Infallible`; script an `Error` without one, such as
+/// `Error::Timeout`, to have a call fail the way a silent module would.
+#[derive(Debug)]
+pub struct FakeReader {
+    /// The password `authenticate` accepts. 0 by default.
+    pub password: u32,
+
+    /// The system parameters `authenticate` returns: a 200-slot library by default.
+    pub parameters: SystemParameters,
+
+    /// The occupied library slots.
+    pub library: BTreeSet<u16>,
+
+    /// Outcomes of the next calls to `identify`, first to last.
+    pub identifications: VecDeque<FakeIdentify>,
+
+    /// Outcomes of the next calls to `enroll`, first to last.
+    #[cfg(feature = "cmd-enroll")]
+    pub enrollments: VecDeque<FakeEnroll>,
+
+    /// Every call made so far, oldest first.
+    pub calls: Vec<FakeCall>,
+}
+
+impl FakeReader {
+    /// A fake with an empty 200-slot library, password 0, and nothing scripted.
+    pub fn new() -> Self {
+        return Self {
+            password: 0,
+            parameters: SystemParameters {
+                status_register: 0x0004,
+                system_identifier_code: 0x0009,
+                finger_library_size: 200,
+                security_level: 3,
+                device_address: 0xffffffff,
+                packet_size: 2,
+                baud_setting: 6,
+            },
+            library: BTreeSet::new(),
+            identifications: VecDeque::new(),
+            #[cfg(feature = "cmd-enroll")]
+            enrollments: VecDeque::new(),
+            calls: Vec::new(),
+        };
+    }
+}
+
+impl Default for FakeReader {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl FingerprintReader for FakeReader {
+    type WriteError = Infallible;
+    type ReadError = Infallible;
+
+    fn authenticate(
+        &mut self,
+        password: u32,
+    ) -> Result<SystemParameters, AuthError<Infallible, Infallible>> {
+        self.calls.push(FakeCall::Authenticate { password });
+        if password != self.password {
+            return Err(AuthError::WrongPassword);
+        }
+        return Ok(self.parameters);
+    }
+
+    fn identify(&mut self, _config: &IdentifyConfig) -> FakeIdentify {
+        self.calls.push(FakeCall::Identify);
+        return self.identifications.pop_front().unwrap_or(Err(IdentifyError::NoFinger));
+    }
+
+    #[cfg(feature = "cmd-enroll")]
+    fn enroll(
+        &mut self,
+        index: u16,
+        config: &EnrollConfig,
+        _delay: &mut dyn DelayMs<u16>,
+        prompts: &mut dyn FnMut(EnrollPrompt),
+    ) -> FakeEnroll {
+        self.calls.push(FakeCall::Enroll { index });
+        self.enrollments.pop_front().unwrap_or(Ok(()))?;
+
+        let captures = config.captures;
+        for capture in 1..=captures {
+            if capture > 1 {
+                prompts(EnrollPrompt::RemoveFinger { capture, captures });
+            }
+            prompts(EnrollPrompt::PlaceFinger { capture, captures });
+            prompts(EnrollPrompt::Captured { capture, captures });
+        }
+        self.library.insert(index);
+        return Ok(());
+    }
+
+    fn delete(
+        &mut self,
+        indices: &[u16],
+    ) -> Result<DeleteReport, DeleteError<Infallible, Infallible>> {
+        self.calls.push(FakeCall::Delete { indices: indices.to_vec() });
+        let capacity = self.parameters.finger_library_size;
+        let (table, out_of_range) = IndexTable::from_indices(indices, capacity);
+        let mut report = DeleteReport {
+            ranges: ArrayVec::new(),
+            overflowed: false,
+            deleted: 0,
+            failed: 0,
+            out_of_range,
+        };
+        for (start, count) in table.occupied_runs() {
+            (start..start + count).for_each(|index| {
+                self.library.remove(&index);
+            });
+            report.deleted += count;
+            let range = DeleteRange { start, count, status: DeletCharStatus::Success };
+            if report.ranges.try_push(range).is_err() {
+                report.overflowed = true;
+            }
+        }
+        return Ok(report);
+    }
+
+    fn template_count(&mut self) -> Result<u16, LibraryError<Infallible, Infallible>> {
+        self.calls.push(FakeCall::TemplateCount);
+        return Ok(self.library.len() as u16);
+    }
+}
+
+#[cfg(all(test, feature = "cmd-enroll"))]
+mod tests {
+    use super::*;
+    use crate::emulator::NoDelay;
+    use crate::utils::Error;
+    use std::vec;
+
+    #[test]
+    fn test_fake_scripted_outcomes() {
+        // given: a fake scripted to time out once, then recognise slot 4
+        let mut fake = FakeReader::new();
+        fake.identifications.push_back(Err(IdentifyError::Comms(Error::Timeout)));
+        fake.identifications.push_back(Ok((4, 120)));
+        let config = IdentifyConfig::default();
+
+        // then: the outcomes come back in order, and then the sensor is empty
+        match fake.identify(&config) {
+            Err(IdentifyError::Comms(Error::Timeout)) => {}
+            other => panic!("Expected a timeout, got {:?}", other),
+        }
+        assert_eq!(fake.identify(&config).unwrap(), (4, 120));
+        match fake.identify(&config) {
+            Err(IdentifyError::NoFinger) => {}
+            other => panic!("Expected no finger, got {:?}", other),
+        }
+
+        // and: a wrong password is refused
+        assert_eq!(matches!(fake.authenticate(1), Err(AuthError::WrongPassword)), true);
+    }
+
+    #[test]
+    fn test_fake_library() {
+        // given: a fake which fails its first enrolment
+        let mut fake = FakeReader::new();
+        fake.enrollments.push_back(Err(EnrollError::Mismatch));
+        let config = EnrollConfig::default();
+        let mut prompts = vec![];
+
+        // when: enrolling three times, then deleting two slots and one past the library
+        let first = fake.enroll(1, &config, &mut NoDelay, &mut |prompt| prompts.push(prompt));
+        fake.enroll(1, &config, &mut NoDelay, &mut |prompt| prompts.push(prompt)).unwrap();
+        fake.enroll(2, &config, &mut NoDelay, &mut |_| {}).unwrap();
+        let report = fake.delete(&[2, 1, 300]).unwrap();
+
+        // then: only the failed enrolment left the library alone
+        assert_eq!(matches!(first, Err(EnrollError::Mismatch)), true);
+        assert_eq!(prompts.len(), 5);
+        assert_eq!(report.deleted, 2);
+        assert_eq!(report.out_of_range, 1);
+        assert_eq!(fake.template_count().unwrap(), 0);
+    }
+}
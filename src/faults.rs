@@ -0,0 +1,325 @@
+extern crate std;
+
+use std::collections::VecDeque;
+use std::vec::Vec;
+
+use crate::driver::R502;
+use crate::transport::Transport;
+
+/// A fault for [`FaultyTransport`](struct.FaultyTransport.html) to inject. Byte positions count
+/// from 0, from when the fault comes up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Inverts the `n`th byte written, as line noise on the way to the module would.
+    CorruptWrite(usize),
+
+    /// Inverts the `n`th byte read.
+    CorruptRead(usize),
+
+    /// Loses the `n`th byte read.
+    DropRead(usize),
+
+    /// Reads these bytes before anything the module sent, as noise on the line or a module
+    /// still booting would.
+    Garbage(Vec<u8>),
+
+    /// Lets `n` bytes be read, then throws away everything else the module has sent, so a
+    /// reply stops short.
+    Truncate(usize),
+
+    /// Has the next `k` reads return `WouldBlock`, as a slow line would.
+    Stall(usize),
+}
+
+/// A `Transport` which injects faults into the bytes going through it, for testing how the
+/// driver and the code built on it cope with a bad line. It wraps any transport, usually the
+/// [`MockTransport`](struct.MockTransport.html) or the [`Emulator`](struct.Emulator.html); see
+/// [`R502::with_faults`](struct.R502.html#method.with_faults).
+///
+/// Faults are injected one after the other: the first one [`inject`](#method.inject)ed applies
+/// until it has happened, then the next one comes up, and once none are left bytes go through
+/// untouched.
+#[derive(Debug)]
+pub struct FaultyTransport<T> {
+    transport: T,
+    faults: VecDeque<Fault>,
+    written: usize,
+    read: usize,
+}
+
+impl<T> FaultyTransport<T>
+where
+    T: Transport,
+{
+    /// Wraps `transport`, with no faults to inject yet.
+    pub fn new(transport: T) -> Self {
+        return Self { transport, faults: VecDeque::new(), written: 0, read: 0 };
+    }
+
+    /// Queues `fault` to be injected once those before it have been.
+    pub fn inject(&mut self, fault: Fault) {
+        self.faults.push_back(fault);
+    }
+
+    /// Whether every fault injected has happened.
+    pub fn is_done(&self) -> bool {
+        return self.faults.is_empty();
+    }
+
+    /// The transport.
+    pub fn transport_mut(&mut self) -> &mut T {
+        return &mut self.transport;
+    }
+
+    /// Gives back the transport.
+    pub fn release(self) -> T {
+        return self.transport;
+    }
+
+    /// Moves on to the next fault.
+    fn next_fault(&mut self) {
+        self.faults.pop_front();
+        self.written = 0;
+        self.read = 0;
+    }
+}
+
+impl<T> Transport for FaultyTransport<T>
+where
+    T: Transport,
+{
+    type WriteError = T::WriteError;
+    type ReadError = T::ReadError;
+
+    fn write_byte(&mut self, byte: u8) -> nb::Result<(), Self::WriteError> {
+        if let Some(Fault::CorruptWrite(n)) = self.faults.front() {
+            if self.written == *n {
+                self.transport.write_byte(!byte)?;
+                self.next_fault();
+                return Ok(());
+            }
+            self.transport.write_byte(byte)?;
+            self.written += 1;
+            return Ok(());
+        }
+        return self.transport.write_byte(byte);
+    }
+
+    fn write_slice(&mut self, bytes: &[u8]) -> nb::Result<usize, Self::WriteError> {
+        // Byte by byte while there is a byte to corrupt, so it can be picked out.
+        if let (Some(Fault::CorruptWrite(_)), Some(byte)) = (self.faults.front(), bytes.first()) {
+            return self.write_byte(*byte).map(|_| 1);
+        }
+        return self.transport.write_slice(bytes);
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::WriteError> {
+        return self.transport.flush();
+    }
+
+    fn read_byte(&mut self) -> nb::Result<u8, Self::ReadError> {
+        loop {
+            match self.faults.front_mut() {
+                Some(Fault::Garbage(bytes)) => {
+                    if bytes.is_empty() {
+                        self.next_fault();
+                        continue;
+                    }
+                    return Ok(bytes.remove(0));
+                }
+                Some(Fault::Stall(k)) => {
+                    if *k == 0 {
+                        self.next_fault();
+                        continue;
+                    }
+                    *k -= 1;
+                    return Err(nb::Error::WouldBlock);
+                }
+                Some(Fault::Truncate(n)) if self.read == *n => {
+                    // Throw away what has arrived, and report what the transport does once
+                    // there is nothing left.
+                    loop {
+                        if let Err(error) = self.transport.read_byte() {
+                            self.next_fault();
+                            return Err(error);
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            let byte = self.transport.read_byte()?;
+            let position = self.read;
+            self.read += 1;
+            match self.faults.front() {
+                Some(Fault::CorruptRead(n)) if *n == position => {
+                    self.next_fault();
+                    return Ok(!byte);
+                }
+                Some(Fault::DropRead(n)) if *n == position => {
+                    self.next_fault();
+                    continue;
+                }
+                _ => return Ok(byte),
+            }
+        }
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Wraps the transport in a [`FaultyTransport`](struct.FaultyTransport.html), to inject
+    /// faults with `transport_mut().inject(..)`.
+    pub fn with_faults(self) -> R502<FaultyTransport<T>> {
+        return self.map_transport(FaultyTransport::new);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Command;
+    use crate::emulator::{Emulator, EmulatorError, EmulatorRx, EmulatorTx};
+    use crate::responses::{GenImgStatus, Reply};
+    use crate::utils::Error;
+
+    type FaultyR502 = R502<FaultyTransport<(EmulatorTx, EmulatorRx)>>;
+
+    fn setup() -> (Emulator, FaultyR502) {
+        let emulator = Emulator::new();
+        emulator.enroll(0, 3);
+        emulator.enroll(1, 5);
+        let (tx, rx) = emulator.serial();
+        return (emulator, R502::new(tx, rx, 0xffffffff).with_faults());
+    }
+
+    /// Checks the driver carries on as normal after a fault.
+    fn assert_recovers(r502: &mut FaultyR502) {
+        assert_eq!(r502.transport().is_done(), true);
+        match r502.send_command(Command::TemplateNum) {
+            Ok(Reply::TemplateNum(result)) => assert_eq!(result.template_num, 2),
+            other => panic!("Expected Reply::TemplateNum, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_corrupt_checksum() {
+        // given: the last byte of the next reply, part of its checksum, damaged
+        let (_, mut r502) = setup();
+        r502.transport_mut().inject(Fault::CorruptRead(13));
+
+        // when: sending a command
+        let result = r502.send_command(Command::TemplateNum);
+
+        // then: the damage is caught by the checksum
+        assert_eq!(matches!(result, Err(Error::RecvBadChecksum)), true);
+
+        // and: the next command goes through
+        assert_recovers(&mut r502);
+    }
+
+    #[test]
+    fn test_corrupt_start_code() {
+        // given: the first byte of the next reply damaged
+        let (_, mut r502) = setup();
+        r502.transport_mut().inject(Fault::CorruptRead(0));
+
+        // when: sending a command
+        let result = r502.send_command(Command::TemplateNum);
+
+        // then: the reply is not taken for a packet
+        assert_eq!(matches!(result, Err(Error::RecvBadStartCode)), true);
+    }
+
+    #[test]
+    fn test_corrupt_command() {
+        // given: the command damaged on the way to the module
+        let (emulator, mut r502) = setup();
+        r502.transport_mut().inject(Fault::CorruptWrite(10));
+
+        // when: sending it
+        let result = r502.send_command(Command::GenImg);
+
+        // then: the module refuses it as a bad packet, without carrying it out
+        match result {
+            Ok(Reply::GenImg(result)) => {
+                let status = &result.confirmation_code;
+                assert_eq!(matches!(status, GenImgStatus::PacketError), true);
+            }
+            other => panic!("Expected Reply::GenImg, got {:?}", other),
+        }
+        assert_eq!(emulator.instructions().is_empty(), true);
+
+        // and: the next command goes through
+        assert_recovers(&mut r502);
+    }
+
+    #[test]
+    fn test_dropped_byte() {
+        // given: a byte lost from the middle of the next reply
+        let (_, mut r502) = setup();
+        r502.transport_mut().inject(Fault::DropRead(10));
+
+        // when: sending a command
+        let result = r502.send_command(Command::TemplateNum);
+
+        // then: the driver waits for the byte which never comes
+        assert_eq!(matches!(result, Err(Error::RecvReadError(EmulatorError::Timeout))), true);
+
+        // and: the next command goes through
+        assert_recovers(&mut r502);
+    }
+
+    #[test]
+    fn test_truncated_reply() {
+        // given: the next reply cut off after its header
+        let (_, mut r502) = setup();
+        r502.transport_mut().inject(Fault::Truncate(9));
+
+        // when: sending a command
+        let result = r502.send_command(Command::TemplateNum);
+
+        // then: the driver waits for the rest, which never comes
+        assert_eq!(matches!(result, Err(Error::RecvReadError(EmulatorError::Timeout))), true);
+
+        // and: the next command goes through
+        assert_recovers(&mut r502);
+    }
+
+    #[test]
+    fn test_stalled_line() {
+        // given: a line slow to deliver the next reply
+        let (_, mut r502) = setup();
+        r502.transport_mut().inject(Fault::Stall(5));
+        r502.transport_mut().inject(Fault::DropRead(100));
+
+        // when: sending a command
+        let result = r502.send_command(Command::TemplateNum);
+
+        // then: the driver waits it out, and gets the reply
+        match result {
+            Ok(Reply::TemplateNum(result)) => assert_eq!(result.template_num, 2),
+            other => panic!("Expected Reply::TemplateNum, got {:?}", other),
+        }
+        assert_eq!(r502.transport().faults.front(), Some(&Fault::DropRead(100)));
+    }
+
+    #[test]
+    fn test_garbage_prefix() {
+        // given: noise on the line ahead of the next reply
+        let (_, mut r502) = setup();
+        r502.transport_mut().inject(Fault::Garbage(std::vec![0x55, 0x00, 0xff]));
+
+        // when: sending a command
+        let result = r502.send_command(Command::TemplateNum);
+
+        // then: what was received is not taken for a packet
+        assert_eq!(matches!(result, Err(Error::RecvBadStartCode)), true);
+
+        // and: once the rest of the reply is drained, the next command goes through
+        while r502.transport_mut().read_byte().is_ok() {}
+        assert_recovers(&mut r502);
+    }
+}
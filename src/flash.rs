@@ -0,0 +1,492 @@
+use byteorder::{BigEndian, ByteOrder};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+
+#[cfg(feature = "cmd-transfer")]
+use crate::driver::R502;
+#[cfg(feature = "cmd-transfer")]
+use crate::library::LibraryError;
+use crate::template::{Template, TemplateWireError, TEMPLATE_CAPACITY, TEMPLATE_WIRE_OVERHEAD};
+#[cfg(feature = "cmd-transfer")]
+use crate::template::ExportError;
+#[cfg(feature = "cmd-enroll")]
+use crate::template::ImportError;
+#[cfg(feature = "cmd-transfer")]
+use crate::transport::Transport;
+
+/// Largest `READ_SIZE` and `WRITE_SIZE` of flash the helpers can work with. Templates are
+/// padded out to a whole number of these.
+pub const MAX_FLASH_WORD_SIZE: usize = 256;
+
+/// Bytes at the start of each entry of a backup: the slot number, big-endian.
+const ENTRY_HEADER: usize = 2;
+
+/// Slot number which ends a backup, as it reads from erased flash.
+#[cfg(feature = "cmd-enroll")]
+const END_OF_BACKUP: u16 = 0xffff;
+
+/// Room for an entry of a backup, with a template of `TEMPLATE_CAPACITY` bytes, padded to
+/// `MAX_FLASH_WORD_SIZE`.
+const SPAN_BUFFER: usize = round_up(
+    ENTRY_HEADER + TEMPLATE_CAPACITY + TEMPLATE_WIRE_OVERHEAD,
+    MAX_FLASH_WORD_SIZE,
+);
+
+/// Error type for `write_template` and `read_template`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashError<E> {
+    /// The flash itself reported an error.
+    Flash(E),
+
+    /// The offset is not at the start of an erase block, or of a read word for reading.
+    Misaligned { offset: u32 },
+
+    /// The template does not fit between the offset and the end of the flash; `needed` bytes
+    /// are needed.
+    OutOfSpace { needed: u32 },
+
+    /// The flash reads or writes in words larger than `MAX_FLASH_WORD_SIZE`.
+    UnsupportedWordSize,
+
+    /// There is no template in the format of `Template::to_wire` at the offset, or it was
+    /// damaged; `TemplateWireError::BadCrc` means the CRC check failed.
+    Wire(TemplateWireError),
+}
+
+/// Writes `template` to `flash` at `offset`, in the format of `Template::to_wire`, for
+/// example to keep a copy exported with
+/// [`R502::export_template`](struct.R502.html#method.export_template) in the MCU's external
+/// flash. Returns how many bytes of flash it took, so the next template can follow on.
+///
+/// `offset` must be at the start of an erase block. The template takes whole erase blocks,
+/// which are erased first; the padding after it is left erased.
+pub fn write_template<F>(
+    flash: &mut F,
+    offset: u32,
+    template: &Template,
+) -> Result<u32, FlashError<F::Error>>
+where
+    F: NorFlash,
+{
+    return write_span(flash, offset, &[], template);
+}
+
+/// Reads a template written by [`write_template`](fn.write_template.html) from `flash` at
+/// `offset`, checking its CRC.
+///
+/// # Errors
+///
+/// ## `FlashError::Wire(TemplateWireError::BadMagic)`
+/// Returned if there is no template at `offset`, for example because the flash is erased.
+///
+/// ## `FlashError::Wire(TemplateWireError::BadCrc)`
+/// Returned if the template was damaged since it was written.
+pub fn read_template<F>(flash: &mut F, offset: u32) -> Result<Template, FlashError<F::Error>>
+where
+    F: ReadNorFlash,
+{
+    let mut buffer = [0u8; SPAN_BUFFER];
+    let bytes = read_span(flash, offset, 0, &mut buffer)?;
+    return Template::from_wire(bytes).map_err(FlashError::Wire);
+}
+
+/// Erases whole blocks from `offset` and writes `prefix` and `template` to them, padded to
+/// `F::WRITE_SIZE`. Returns the bytes of flash taken.
+fn write_span<F>(
+    flash: &mut F,
+    offset: u32,
+    prefix: &[u8],
+    template: &Template,
+) -> Result<u32, FlashError<F::Error>>
+where
+    F: NorFlash,
+{
+    if F::WRITE_SIZE > MAX_FLASH_WORD_SIZE {
+        return Err(FlashError::UnsupportedWordSize);
+    }
+    if !(offset as usize).is_multiple_of(F::ERASE_SIZE) {
+        return Err(FlashError::Misaligned { offset });
+    }
+
+    let length = prefix.len() + template.wire_len();
+    let taken = round_up(length, F::ERASE_SIZE);
+    if offset as usize + taken > flash.capacity() {
+        return Err(FlashError::OutOfSpace { needed: taken as u32 });
+    }
+
+    let mut buffer = [0xffu8; SPAN_BUFFER];
+    buffer[..prefix.len()].copy_from_slice(prefix);
+    template.to_wire(&mut buffer[prefix.len()..]).map_err(FlashError::Wire)?;
+
+    flash.erase(offset, offset + taken as u32).map_err(FlashError::Flash)?;
+    flash
+        .write(offset, &buffer[..round_up(length, F::WRITE_SIZE)])
+        .map_err(FlashError::Flash)?;
+    return Ok(taken as u32);
+}
+
+/// Reads what `write_span` wrote at `offset` into `buffer`, returning the template in the
+/// format of `Template::to_wire`, after the `prefix` bytes before it. The header is read
+/// first, so only as much flash is read as the template takes.
+fn read_span<'a, F>(
+    flash: &mut F,
+    offset: u32,
+    prefix: usize,
+    buffer: &'a mut [u8; SPAN_BUFFER],
+) -> Result<&'a [u8], FlashError<F::Error>>
+where
+    F: ReadNorFlash,
+{
+    if F::READ_SIZE > MAX_FLASH_WORD_SIZE {
+        return Err(FlashError::UnsupportedWordSize);
+    }
+    if !(offset as usize).is_multiple_of(F::READ_SIZE) {
+        return Err(FlashError::Misaligned { offset });
+    }
+
+    // Magic, version and length.
+    let header = round_up(prefix + 7, F::READ_SIZE);
+    read_within(flash, offset, &mut buffer[..header])?;
+    let wire = &buffer[prefix..header];
+    let length = match Template::from_wire(wire) {
+        Ok(_) => return Ok(&buffer[prefix..header]),
+        Err(TemplateWireError::Truncated) => {
+            BigEndian::read_u16(&wire[5..7]) as usize + TEMPLATE_WIRE_OVERHEAD
+        }
+        Err(error) => return Err(FlashError::Wire(error)),
+    };
+
+    let total = round_up(prefix + length, F::READ_SIZE);
+    read_within(flash, offset + header as u32, &mut buffer[header..total])?;
+    return Ok(&buffer[prefix..prefix + length]);
+}
+
+/// Reads `bytes` from `offset`, or fails with `FlashError::OutOfSpace` if they run past the
+/// end of the flash, so a cut-off template is not mistaken for a fault of the flash.
+fn read_within<F>(flash: &mut F, offset: u32, bytes: &mut [u8]) -> Result<(), FlashError<F::Error>>
+where
+    F: ReadNorFlash,
+{
+    if offset as usize + bytes.len() > flash.capacity() {
+        return Err(FlashError::OutOfSpace { needed: bytes.len() as u32 });
+    }
+    return flash.read(offset, bytes).map_err(FlashError::Flash);
+}
+
+const fn round_up(length: usize, multiple: usize) -> usize {
+    return length.div_ceil(multiple) * multiple;
+}
+
+/// A backup written by `persist_backup`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlashBackup {
+    /// How many templates it holds.
+    pub templates: u16,
+
+    /// Bytes of flash it takes from the offset it was written at, a whole number of erase
+    /// blocks.
+    pub length: u32,
+}
+
+/// Error type for `persist_backup` and `restore_backup`.
+#[cfg(feature = "cmd-transfer")]
+#[derive(Debug)]
+pub enum FlashBackupError<TXE, RXE, E> {
+    /// The _index table_ could not be read. Nothing was written.
+    Library(LibraryError<TXE, RXE>),
+
+    /// The template at `index` could not be exported.
+    Export {
+        index: u16,
+        error: ExportError<TXE, RXE>,
+    },
+
+    /// The template for `index` was read from flash, but could not be imported.
+    #[cfg(feature = "cmd-enroll")]
+    Import {
+        index: u16,
+        error: ImportError<TXE, RXE>,
+    },
+
+    /// The flash could not be written or read at `offset`.
+    Flash { offset: u32, error: FlashError<E> },
+}
+
+#[cfg(feature = "cmd-transfer")]
+type BackupResult<TXE, RXE, E> = Result<FlashBackup, FlashBackupError<TXE, RXE, E>>;
+
+#[cfg(feature = "cmd-transfer")]
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Exports every template in the library to `flash`, starting at `base_offset`, which must
+    /// be at the start of an erase block. Each template is exported and written before the next
+    /// is read, so only one is held in memory at a time.
+    ///
+    /// Each template is written as with [`write_template`](fn.write_template.html), after its
+    /// slot number, big-endian, and takes whole erase blocks. The erase block after the last
+    /// one is erased too, if there is room, to mark the end of the backup.
+    ///
+    /// **Note:** This overwrites the contents of _character buffer_ 2.
+    pub fn persist_backup<F>(
+        &mut self,
+        flash: &mut F,
+        base_offset: u32,
+    ) -> BackupResult<T::WriteError, T::ReadError, F::Error>
+    where
+        F: NorFlash,
+    {
+        let table = self.read_index_table().map_err(FlashBackupError::Library)?;
+        let mut backup = FlashBackup { templates: 0, length: 0 };
+        for index in table.occupied() {
+            let template = self
+                .export_template(index)
+                .map_err(|error| FlashBackupError::Export { index, error })?;
+            let mut prefix = [0u8; ENTRY_HEADER];
+            BigEndian::write_u16(&mut prefix, index);
+            let offset = base_offset + backup.length;
+            backup.length += write_span(flash, offset, &prefix, &template)
+                .map_err(|error| FlashBackupError::Flash { offset, error })?;
+            backup.templates += 1;
+        }
+
+        let end = base_offset as usize + backup.length as usize;
+        if end + F::ERASE_SIZE <= flash.capacity() {
+            flash
+                .erase(end as u32, (end + F::ERASE_SIZE) as u32)
+                .map_err(|error| FlashBackupError::Flash {
+                    offset: end as u32,
+                    error: FlashError::Flash(error),
+                })?;
+        }
+        return Ok(backup);
+    }
+
+    /// Imports every template of a backup written by
+    /// [`persist_backup`](#method.persist_backup) at `base_offset` back into the library, each
+    /// to the slot it was taken from, overwriting what is there. Slots not in the backup are
+    /// left alone. Stops at the first template which cannot be read or imported.
+    ///
+    /// **Note:** This overwrites the contents of _character buffer_ 1.
+    #[cfg(feature = "cmd-enroll")]
+    pub fn restore_backup<F>(
+        &mut self,
+        flash: &mut F,
+        base_offset: u32,
+    ) -> BackupResult<T::WriteError, T::ReadError, F::Error>
+    where
+        F: NorFlash,
+    {
+        let mut backup = FlashBackup { templates: 0, length: 0 };
+        let mut buffer = [0u8; SPAN_BUFFER];
+        loop {
+            let offset = base_offset + backup.length;
+            let header = round_up(ENTRY_HEADER, F::READ_SIZE);
+            if offset as usize + header > flash.capacity() {
+                return Ok(backup);
+            }
+            read_within(flash, offset, &mut buffer[..header])
+                .map_err(|error| FlashBackupError::Flash { offset, error })?;
+            let index = BigEndian::read_u16(&buffer[..ENTRY_HEADER]);
+            if index == END_OF_BACKUP {
+                return Ok(backup);
+            }
+
+            let template = read_span(flash, offset, ENTRY_HEADER, &mut buffer)
+                .and_then(|bytes| Template::from_wire(bytes).map_err(FlashError::Wire))
+                .map_err(|error| FlashBackupError::Flash { offset, error })?;
+            self.import_template(index, &template, true)
+                .map_err(|error| FlashBackupError::Import { index, error })?;
+            backup.templates += 1;
+            backup.length += round_up(ENTRY_HEADER + template.wire_len(), F::ERASE_SIZE) as u32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::emulator::{char_file, Emulator};
+    use embedded_storage::nor_flash::{ErrorType, NorFlashErrorKind};
+    use std::vec;
+    use std::vec::Vec;
+
+    /// An in-memory NOR flash with 4-byte words and 256-byte erase blocks, which only lets
+    /// writes clear bits, as the real thing does.
+    struct FakeFlash {
+        memory: Vec<u8>,
+        erases: usize,
+    }
+
+    impl FakeFlash {
+        fn new(blocks: usize) -> Self {
+            return Self { memory: vec![0xff; blocks * 256], erases: 0 };
+        }
+    }
+
+    impl ErrorType for FakeFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for FakeFlash {
+        const READ_SIZE: usize = 4;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            if !offset.is_multiple_of(4) || !bytes.len().is_multiple_of(4) {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            match self.memory.get(offset..offset + bytes.len()) {
+                Some(memory) => bytes.copy_from_slice(memory),
+                None => return Err(NorFlashErrorKind::OutOfBounds),
+            }
+            return Ok(());
+        }
+
+        fn capacity(&self) -> usize {
+            return self.memory.len();
+        }
+    }
+
+    impl NorFlash for FakeFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = 256;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            let (from, to) = (from as usize, to as usize);
+            if !from.is_multiple_of(256) || !to.is_multiple_of(256) {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            match self.memory.get_mut(from..to) {
+                Some(memory) => memory.iter_mut().for_each(|byte| *byte = 0xff),
+                None => return Err(NorFlashErrorKind::OutOfBounds),
+            }
+            self.erases += (to - from) / 256;
+            return Ok(());
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            if !offset.is_multiple_of(4) || !bytes.len().is_multiple_of(4) {
+                return Err(NorFlashErrorKind::NotAligned);
+            }
+            let memory = match self.memory.get_mut(offset..offset + bytes.len()) {
+                Some(memory) => memory,
+                None => return Err(NorFlashErrorKind::OutOfBounds),
+            };
+            for (cell, byte) in memory.iter_mut().zip(bytes.iter()) {
+                assert_eq!(*cell & *byte, *byte, "write to flash which was not erased");
+                *cell = *byte;
+            }
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn test_write_and_read_template() {
+        // given: flash which already holds something, and a 1536-byte template
+        let mut flash = FakeFlash::new(16);
+        flash.memory.iter_mut().for_each(|byte| *byte = 0x00);
+        let template = Template::from_bytes(&char_file(7)).unwrap();
+
+        // when: writing it at the start of the third block
+        let taken = write_template(&mut flash, 512, &template).unwrap();
+
+        // then: it took seven whole blocks, erased first, and reads back the same
+        assert_eq!(taken, 7 * 256);
+        assert_eq!(flash.erases, 7);
+        assert_eq!(read_template(&mut flash, 512).unwrap() == template, true);
+
+        // and: odd lengths are padded out to whole words
+        let odd = Template::from_bytes(&[1, 2, 3]).unwrap();
+        assert_eq!(write_template(&mut flash, 0, &odd).unwrap(), 256);
+        assert_eq!(read_template(&mut flash, 0).unwrap() == odd, true);
+    }
+
+    #[test]
+    fn test_flash_errors() {
+        // given: a flash of four blocks, with a template in the first
+        let mut flash = FakeFlash::new(4);
+        let small = Template::from_bytes(&[7; 100]).unwrap();
+        write_template(&mut flash, 0, &small).unwrap();
+        let large = Template::from_bytes(&char_file(7)).unwrap();
+
+        // then: offsets off a block, and templates running off the end, are refused
+        let misaligned = write_template(&mut flash, 4, &small);
+        assert_eq!(misaligned, Err(FlashError::Misaligned { offset: 4 }));
+        assert_eq!(
+            write_template(&mut flash, 256, &large),
+            Err(FlashError::OutOfSpace { needed: 7 * 256 })
+        );
+
+        // and: erased flash has no template in it
+        let erased = read_template(&mut flash, 256);
+        assert_eq!(erased, Err(FlashError::Wire(TemplateWireError::BadMagic)));
+
+        // when: a bit of the template flips
+        flash.memory[50] ^= 0x01;
+
+        // then: the CRC check catches it
+        let damaged = read_template(&mut flash, 0);
+        assert_eq!(damaged, Err(FlashError::Wire(TemplateWireError::BadCrc)));
+    }
+
+    #[test]
+    fn test_persist_and_restore_backup() {
+        // given: a module with three templates, and flash holding a longer, older backup
+        let emulator = Emulator::new();
+        emulator.enroll(2, 7);
+        emulator.enroll(5, 8);
+        emulator.enroll(40, 9);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        let mut flash = FakeFlash::new(64);
+        flash.memory[256 + 21 * 256..].iter_mut().for_each(|byte| *byte = 0x00);
+
+        // when: persisting the library after the first block
+        let backup = r502.persist_backup(&mut flash, 256).unwrap();
+
+        // then: each template took seven blocks, and the block after them marks the end
+        assert_eq!(backup, FlashBackup { templates: 3, length: 21 * 256 });
+        assert_eq!(flash.memory[256..258], [0x00, 0x02]);
+        assert_eq!(flash.memory[256 + 21 * 256], 0xff);
+
+        // when: the library is wiped, and restored from the backup
+        emulator.state().library.iter_mut().for_each(|slot| *slot = None);
+        let restored = r502.restore_backup(&mut flash, 256).unwrap();
+
+        // then: every template is back in its slot, and the older backup was not read
+        assert_eq!(restored, backup);
+        assert_eq!(emulator.slot(2), Some(char_file(7)));
+        assert_eq!(emulator.slot(5), Some(char_file(8)));
+        assert_eq!(emulator.slot(40), Some(char_file(9)));
+        assert_eq!(emulator.state().library.iter().filter(|slot| slot.is_some()).count(), 3);
+    }
+
+    #[test]
+    fn test_restore_damaged_backup() {
+        // given: a backup of two templates, with the second one damaged
+        let emulator = Emulator::new();
+        emulator.enroll(0, 7);
+        emulator.enroll(1, 8);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        let mut flash = FakeFlash::new(32);
+        r502.persist_backup(&mut flash, 0).unwrap();
+        flash.memory[7 * 256 + 100] ^= 0x80;
+
+        // when: restoring it
+        let result = r502.restore_backup(&mut flash, 0);
+
+        // then: the damaged template is reported, where it is in the flash
+        match result {
+            Err(FlashBackupError::Flash {
+                offset: 1792,
+                error: FlashError::Wire(TemplateWireError::BadCrc),
+            }) => {}
+            other => panic!("Expected FlashError::Wire(BadCrc), got {:?}", other),
+        };
+    }
+}
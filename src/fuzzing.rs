@@ -0,0 +1,154 @@
+#[cfg(feature = "fuzzing")]
+use core::fmt::{self, Write};
+
+#[cfg(feature = "fuzzing")]
+use crate::codec::encode_command;
+use crate::codec::{decode_command, decode_reply, frame_length, DecodeError};
+use crate::commands::COMMAND_KINDS;
+#[cfg(feature = "fuzzing")]
+use crate::commands::Command;
+#[cfg(feature = "fuzzing")]
+use crate::consts::MAX_COMMAND_LENGTH;
+use crate::parser::ReplyParser;
+#[cfg(feature = "fuzzing")]
+use crate::responses::Reply;
+
+/// The body of the `frames` fuzz target in `fuzz/`: feeds `data` through the frame parsing and
+/// reply decoding, as if it had come off the wire, and checks they hold up. Malformed input
+/// must come out as errors; a panic, which includes reading out of bounds, is a bug.
+#[doc(hidden)]
+pub fn fuzz_frames(data: &[u8]) {
+    // The whole input as a frame, as `decode_reply` would be handed a receive buffer.
+    if let Ok(length) = frame_length(data) {
+        for kind in COMMAND_KINDS.iter() {
+            if decode_reply(*kind, data).is_ok() {
+                assert!(length <= data.len(), "decoded past the end of the input");
+            }
+        }
+    }
+    #[cfg(feature = "cmd-transfer")]
+    let _ = crate::codec::data_payload(data);
+    let _ = decode_command(data);
+
+    // The input as a stream, one byte at a time, as from a UART interrupt.
+    let mut parser = ReplyParser::new();
+    for byte in data {
+        if let Some(Ok(frame)) = parser.push(*byte) {
+            let bytes = frame.as_bytes();
+            assert_eq!(frame_length(bytes), Ok(bytes.len()));
+            let _ = (frame.address(), frame.packet_id(), frame.payload());
+            for kind in COMMAND_KINDS.iter() {
+                // The parser has checked the frame, so only its contents can be wrong.
+                match decode_reply(*kind, bytes) {
+                    Err(DecodeError::BadStartCode) | Err(DecodeError::BadChecksum) => {
+                        panic!("the parser let a bad frame through")
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// The body of the `commands` fuzz target: encodes `command`, which the target makes with
+/// `Arbitrary`, and checks that decoding the packet gives back a command which encodes the same.
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub fn fuzz_commands(command: &Command, address: u32) {
+    let mut frame = [0u8; MAX_COMMAND_LENGTH];
+    let length = encode_command(command, address, &mut frame).expect("a command did not fit");
+    let decoded = decode_command(&frame[..length]).expect("an encoded command did not decode");
+
+    let mut again = [0u8; MAX_COMMAND_LENGTH];
+    let again_length = encode_command(&decoded, address, &mut again).unwrap();
+    let (frame, again) = (&frame[..length], &again[..again_length]);
+    assert_eq!(frame, again, "{:?} came back as {:?}", command, decoded);
+    fuzz_frames(frame);
+}
+
+/// Throws away what is written to it.
+#[cfg(feature = "fuzzing")]
+struct Discard;
+
+#[cfg(feature = "fuzzing")]
+impl Write for Discard {
+    fn write_str(&mut self, _text: &str) -> fmt::Result {
+        return Ok(());
+    }
+}
+
+/// The body of the `replies` fuzz target: formats `reply`, which the target makes with
+/// `Arbitrary`, as `Display` and `Debug`, which must hold up for any field values, including
+/// confirmation codes and counts no module sends.
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub fn fuzz_replies(reply: &Reply) {
+    write!(Discard, "{} {:?}", reply, reply).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The corpus the fuzz target starts from.
+    const SEEDS: [&[u8]; 7] = [
+        include_bytes!("../fuzz/corpus/frames/end_data.bin"),
+        include_bytes!("../fuzz/corpus/frames/gen_img_reply.bin"),
+        include_bytes!("../fuzz/corpus/frames/read_index_table_reply.bin"),
+        include_bytes!("../fuzz/corpus/frames/read_sys_para_reply.bin"),
+        include_bytes!("../fuzz/corpus/frames/search_reply.bin"),
+        include_bytes!("../fuzz/corpus/frames/stream.bin"),
+        include_bytes!("../fuzz/corpus/frames/template_num_reply.bin"),
+    ];
+
+    #[test]
+    fn test_fuzz_seeds() {
+        // given: the seeds of the fuzz target, and every prefix of each
+        // when: running the fuzz body over them
+        // then: nothing panics
+        for seed in SEEDS.iter() {
+            for end in 0..=seed.len() {
+                fuzz_frames(&seed[..end]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fuzz_mutated_seeds() {
+        // given: the seeds with each byte in turn overwritten, so that lengths, packet IDs and
+        // confirmation codes take values no module sends
+        // when: running the fuzz body over them
+        // then: nothing panics
+        for seed in SEEDS.iter() {
+            for at in 0..seed.len() {
+                for value in [0x00, 0x01, 0x7f, 0xff].iter() {
+                    let mut mutated = [0u8; 64];
+                    mutated[..seed.len()].copy_from_slice(seed);
+                    mutated[at] = *value;
+                    fuzz_frames(&mutated[..seed.len()]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "fuzzing")]
+    fn test_fuzz_arbitrary_seeds() {
+        use arbitrary::{Arbitrary, Unstructured};
+
+        // given: commands and replies made with `Arbitrary` from the seeds, as the fuzz targets
+        // make them
+        // when: running the fuzz bodies over them
+        // then: nothing panics, and every command comes back from its packet
+        for seed in SEEDS.iter() {
+            let mut input = Unstructured::new(seed);
+            while !input.is_empty() {
+                fuzz_commands(&Command::arbitrary(&mut input).unwrap(), 0xffffffff);
+            }
+            let mut input = Unstructured::new(seed);
+            while !input.is_empty() {
+                fuzz_replies(&Reply::arbitrary(&mut input).unwrap());
+            }
+        }
+    }
+}
@@ -0,0 +1,473 @@
+//! Golden frames: for every command, the exact packet the driver sends for it and a reply a
+//! module sends back, driven end to end through `send_command` and the serial port. Together
+//! they are a reference to the protocol as the driver speaks it.
+//!
+//! All packets are to and from the default address, `0xFFFFFFFF`. A packet is the start code
+//! `EF 01`, the address, the packet ID (`01` for a command, `07` for a reply), the length of
+//! what follows, the instruction or confirmation code and its parameters, and a checksum: the
+//! sum of the bytes from the packet ID to the end of the parameters.
+extern crate std;
+
+use crate::codec::{decode_command, encode_command, DecodeError};
+use crate::consts::MAX_COMMAND_LENGTH;
+use crate::commands::{Command, CommandKind, COMMAND_KINDS};
+use crate::driver::R502;
+use crate::mock::{Expectation, MockTransport};
+use crate::responses::*;
+use std::vec::Vec;
+
+/// A command, the packet the driver sends for it, a reply to it, and what the reply must decode
+/// into.
+struct Golden {
+    command: Command,
+    request: &'static [u8],
+    reply: &'static [u8],
+    check: fn(&Reply) -> bool,
+}
+
+/// The golden frames, one for each command. The replies use a variety of confirmation codes,
+/// and data which tells its fields apart.
+fn golden_frames() -> Vec<Golden> {
+    return std::vec![
+        Golden {
+            command: Command::ReadSysPara,
+            request: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x0f, 0x00, 0x13],
+            reply: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x13, 0x00, 0x00, 0x04, 0x00, 0x09,
+                0x00, 0xc8, 0x00, 0x03, 0xff, 0xff, 0xff, 0xff, 0x00, 0x02, 0x00, 0x06, 0x04, 0xf6,
+            ],
+            check: |reply| match reply {
+                Reply::ReadSysPara(r) => {
+                    let p = r.system_parameters;
+                    (p.finger_library_size, p.security_level, p.baud_setting) == (200, 3, 6)
+                        && p.password_ok()
+                }
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::VfyPwd { password: 0x12345678 },
+            request: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x07, 0x13, 0x12, 0x34, 0x56, 0x78,
+                0x01, 0x2f,
+            ],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x13, 0x00, 0x1d],
+            check: |reply| match reply {
+                Reply::VfyPwd(r) => {
+                    matches!(r.confirmation_code, PasswordVerificationState::Incorrect)
+                }
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::GenImg,
+            request: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x01, 0x00, 0x05],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x02, 0x00, 0x0c],
+            check: |reply| match reply {
+                Reply::GenImg(r) => matches!(r.confirmation_code, GenImgStatus::FingerNotDetected),
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::Img2Tz { buffer: 2 },
+            request: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x04, 0x02, 0x02, 0x00, 0x09,
+            ],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x07, 0x00, 0x11],
+            check: |reply| match reply {
+                Reply::Img2Tz(r) => matches!(r.confirmation_code, Img2TzStatus::ProcessingFailed),
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::Search { buffer: 1, start_index: 0, end_index: 199 },
+            request: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x08, 0x04, 0x01, 0x00, 0x00, 0x00,
+                0xc7, 0x00, 0xd5,
+            ],
+            reply: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x07, 0x00, 0x00, 0x05, 0x00, 0x64,
+                0x00, 0x77,
+            ],
+            check: |reply| match reply {
+                Reply::Search(r) => (r.match_id, r.match_score) == (5, 100),
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::LoadChar { buffer: 2, index: 300 },
+            request: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x06, 0x07, 0x02, 0x01, 0x2c, 0x00,
+                0x3d,
+            ],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x0c, 0x00, 0x16],
+            check: |reply| match reply {
+                Reply::LoadChar(r) => {
+                    matches!(r.confirmation_code, LoadCharStatus::LibraryReadError)
+                }
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::Match,
+            request: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x03, 0x00, 0x07],
+            reply: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x05, 0x00, 0x00, 0x96, 0x00, 0xa2,
+            ],
+            check: |reply| match reply {
+                Reply::Match(r) => r.match_score == 150,
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::TemplateNum,
+            request: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x1d, 0x00, 0x21],
+            reply: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x05, 0x00, 0x00, 0x2a, 0x00, 0x36,
+            ],
+            check: |reply| match reply {
+                Reply::TemplateNum(r) => r.template_num == 42,
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::ReadIndexTable { page: 1 },
+            request: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x04, 0x1f, 0x01, 0x00, 0x25,
+            ],
+            reply: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x23, 0x00, 0x81, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+                0x00, 0xac,
+            ],
+            check: |reply| match reply {
+                Reply::ReadIndexTable(r) => (r.index_table[0], r.index_table[31]) == (0x81, 0x01),
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::RegModel,
+            request: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x05, 0x00, 0x09],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x0a, 0x00, 0x14],
+            check: |reply| match reply {
+                Reply::RegModel(r) => {
+                    matches!(r.confirmation_code, RegModelStatus::ProcessingError)
+                }
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::Store { buffer: 1, index: 7 },
+            request: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x06, 0x06, 0x01, 0x00, 0x07, 0x00,
+                0x15,
+            ],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x18, 0x00, 0x22],
+            check: |reply| match reply {
+                Reply::Store(r) => matches!(r.confirmation_code, StoreStatus::WriteError),
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::UpChar { buffer: 1 },
+            request: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x04, 0x08, 0x01, 0x00, 0x0e,
+            ],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x0d, 0x00, 0x17],
+            check: |reply| match reply {
+                Reply::UpChar(r) => matches!(r.confirmation_code, UpCharStatus::UploadFailed),
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::DownChar { buffer: 2 },
+            request: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x04, 0x09, 0x02, 0x00, 0x10,
+            ],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x0e, 0x00, 0x18],
+            check: |reply| match reply {
+                Reply::DownChar(r) => matches!(r.confirmation_code, DownCharStatus::CannotReceive),
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::DownImage,
+            request: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x0b, 0x00, 0x0f],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x01, 0x00, 0x0b],
+            check: |reply| match reply {
+                Reply::DownImage(r) => matches!(r.confirmation_code, DownImageStatus::PacketError),
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::SetSysPara { parameter: 5, value: 4 },
+            request: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x05, 0x0e, 0x05, 0x04, 0x00, 0x1d,
+            ],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x1a, 0x00, 0x24],
+            check: |reply| match reply {
+                Reply::SetSysPara(r) => {
+                    matches!(r.confirmation_code, SetSysParaStatus::WrongRegister)
+                }
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::SetPwd { password: 0xcafef00d },
+            request: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x07, 0x12, 0xca, 0xfe, 0xf0, 0x0d,
+                0x02, 0xdf,
+            ],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x00, 0x00, 0x0a],
+            check: |reply| match reply {
+                Reply::SetPwd(r) => matches!(r.confirmation_code, SetPwdStatus::Success),
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::SetAdder { address: 0x00000001 },
+            request: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x07, 0x15, 0x00, 0x00, 0x00, 0x01,
+                0x00, 0x1e,
+            ],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x00, 0x00, 0x0a],
+            check: |reply| match reply {
+                Reply::SetAdder(r) => matches!(r.confirmation_code, SetAdderStatus::Success),
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::GetChipSN,
+            request: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x04, 0x34, 0x00, 0x00, 0x39,
+            ],
+            reply: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x23, 0x00, 0x01, 0x02, 0x03, 0x04,
+                0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12,
+                0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f, 0x20,
+                0x02, 0x3a,
+            ],
+            check: |reply| match reply {
+                Reply::GetChipSN(r) => (r.serial_number[0], r.serial_number[31]) == (1, 32),
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::GetRandomCode,
+            request: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x14, 0x00, 0x18],
+            reply: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x07, 0x00, 0xde, 0xad, 0xbe, 0xef,
+                0x03, 0x46,
+            ],
+            check: |reply| match reply {
+                Reply::GetRandomCode(r) => r.random_code == 0xdeadbeef,
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::WriteNotepad { page: 15, data: [0x5a; 32] },
+            request: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x24, 0x18, 0x0f, 0x5a, 0x5a, 0x5a,
+                0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a,
+                0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a,
+                0x5a, 0x0b, 0x8c,
+            ],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x18, 0x00, 0x22],
+            check: |reply| match reply {
+                Reply::WriteNotepad(r) => {
+                    matches!(r.confirmation_code, WriteNotepadStatus::WriteError)
+                }
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::ReadNotepad { page: 3 },
+            request: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x04, 0x19, 0x03, 0x00, 0x21,
+            ],
+            reply: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x23, 0x00, 0xa5, 0xa5, 0xa5, 0xa5,
+                0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5,
+                0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5, 0xa5,
+                0x14, 0xca,
+            ],
+            check: |reply| match reply {
+                Reply::ReadNotepad(r) => r.data == [0xa5; 32],
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::GetFwVer,
+            request: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x3a, 0x00, 0x3e],
+            reply: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x23, 0x00, 0x31, 0x2e, 0x32, 0x2e,
+                0x33, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x01, 0x1c,
+            ],
+            check: |reply| match reply {
+                Reply::GetFwVer(r) => &r.version[..6] == b"1.2.3\0",
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::GetAlgVer,
+            request: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x39, 0x00, 0x3d],
+            reply: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x23, 0x01, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x2b,
+            ],
+            check: |reply| match reply {
+                Reply::GetAlgVer(r) => r.confirmation_code == GetAlgVerStatus::PacketError,
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::HandShake,
+            request: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x40, 0x00, 0x44],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x00, 0x00, 0x0a],
+            check: |reply| match reply {
+                Reply::HandShake(r) => matches!(r.confirmation_code, HandShakeStatus::Success),
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::CheckSensor,
+            request: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x36, 0x00, 0x3a],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x29, 0x00, 0x33],
+            check: |reply| match reply {
+                Reply::CheckSensor(r) => {
+                    matches!(r.confirmation_code, CheckSensorStatus::SensorAbnormal)
+                }
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::SoftRst,
+            request: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x3d, 0x00, 0x41],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x00, 0x00, 0x0a],
+            check: |reply| match reply {
+                Reply::SoftRst(r) => matches!(r.confirmation_code, SoftRstStatus::Success),
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::Sleep,
+            request: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x33, 0x00, 0x37],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x00, 0x00, 0x0a],
+            check: |reply| match reply {
+                Reply::Sleep(r) => matches!(r.confirmation_code, SleepStatus::Success),
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::PortControl { enable: true },
+            request: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x04, 0x17, 0x01, 0x00, 0x1d,
+            ],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x1d, 0x00, 0x27],
+            check: |reply| match reply {
+                Reply::PortControl(r) => {
+                    matches!(r.confirmation_code, PortControlStatus::PortOperationFailed)
+                }
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::AuraLedConfig { control: 1, speed: 0x80, color: 2, times: 3 },
+            request: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x07, 0x35, 0x01, 0x80, 0x02, 0x03,
+                0x00, 0xc3,
+            ],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x00, 0x00, 0x0a],
+            check: |reply| match reply {
+                Reply::AuraLedConfig(r) => {
+                    matches!(r.confirmation_code, AuraLedConfigStatus::Success)
+                }
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::DeletChar { start_index: 10, num_to_delete: 5 },
+            request: &[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x07, 0x0c, 0x00, 0x0a, 0x00, 0x05,
+                0x00, 0x23,
+            ],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x10, 0x00, 0x1a],
+            check: |reply| match reply {
+                Reply::DeletChar(r) => r.confirmation_code == DeletCharStatus::DeleteFailed,
+                _ => false,
+            },
+        },
+        Golden {
+            command: Command::Empty,
+            request: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x0d, 0x00, 0x11],
+            reply: &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x11, 0x00, 0x1b],
+            check: |reply| match reply {
+                Reply::Empty(r) => r.confirmation_code == EmptyStatus::ClearFailed,
+                _ => false,
+            },
+        },
+    ];
+}
+
+#[test]
+fn test_golden_frames_cover_every_command() {
+    // given: the golden frames
+    let kinds: Vec<CommandKind> = golden_frames().iter().map(|g| g.command.kind()).collect();
+
+    // then: there is exactly one for each command
+    assert_eq!(kinds, COMMAND_KINDS);
+}
+
+#[test]
+fn test_golden_frames() {
+    for golden in golden_frames() {
+        // given: a module which expects the golden command packet, and answers with the reply
+        let kind = golden.command.kind();
+        let mock = MockTransport::new(
+            0xffffffff,
+            std::vec![Expectation::raw(golden.request).reply_raw(golden.reply)],
+        );
+        let mut r502 = R502::from_serial(mock.clone(), 0xffffffff);
+        let mut encoded = [0u8; MAX_COMMAND_LENGTH];
+        let length = encode_command(&golden.command, 0xffffffff, &mut encoded).unwrap();
+
+        // when: sending the command
+        let reply = match r502.send_command(golden.command) {
+            Ok(reply) => reply,
+            Err(error) => panic!("{:?} failed: {:?}", kind, error),
+        };
+
+        // then: the golden packet went out, and the reply decoded as it should
+        mock.done();
+        assert!((golden.check)(&reply), "{:?} decoded as {:?}", kind, reply);
+
+        // and: encoding the command on its own gives the same packet
+        assert_eq!(&encoded[..length], golden.request, "{:?}", kind);
+    }
+}
+
+#[test]
+fn test_golden_frames_decode_as_commands() {
+    for golden in golden_frames() {
+        // when: decoding the golden command packet, and encoding what it decodes into
+        let command = match decode_command(golden.request) {
+            Ok(command) => command,
+            Err(error) => panic!("{:?} failed to decode: {:?}", golden.command.kind(), error),
+        };
+        let mut encoded = [0u8; MAX_COMMAND_LENGTH];
+        let length = encode_command(&command, 0xffffffff, &mut encoded).unwrap();
+
+        // then: it is the same command, with the same parameters
+        assert_eq!(command.kind(), golden.command.kind());
+        assert_eq!(&encoded[..length], golden.request, "{:?}", command.kind());
+
+        // and: the reply is not taken for a command
+        let wrong_type = decode_command(golden.reply);
+        assert_eq!(matches!(wrong_type, Err(DecodeError::WrongPacketType)), true);
+    }
+}
@@ -0,0 +1,1062 @@
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::digital::v2::InputPin;
+
+use crate::cancel::{CancelToken, NeverCancel};
+use crate::commands::Command;
+use crate::driver::R502;
+use crate::led::LedFeedback;
+use crate::library::{IndexTable, MAX_LIBRARY_SIZE};
+use crate::responses::*;
+use crate::touch::read_touch;
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// Settings for the identification helpers.
+#[derive(Debug, Clone, Copy)]
+pub struct IdentifyConfig {
+    /// How long to wait between polls of the sensor, in milliseconds.
+    pub poll_interval_ms: u16,
+
+    /// How many consecutive empty readings of the sensor count as the finger having been
+    /// lifted. Stops a finger which is slowly lifted from being identified twice.
+    pub removal_debounce: u8,
+
+    /// First library index to search.
+    pub start_index: u16,
+
+    /// Last library index to search.
+    pub end_index: u16,
+
+    /// Lowest match score accepted as a match. The module's own security level is applied
+    /// first; this allows a stricter threshold on top of it. Matches scoring lower are
+    /// reported as `IdentifyEvent::BelowThreshold`.
+    pub min_score: Option<u16>,
+
+    /// Ring LED feedback: waiting while the sensor is empty, success or failure for each
+    /// finger until it is lifted, and idle once the loop stops. `None` leaves the LED alone.
+    pub led: Option<LedFeedback>,
+}
+
+impl Default for IdentifyConfig {
+    fn default() -> Self {
+        return Self {
+            poll_interval_ms: 100,
+            removal_debounce: 3,
+            start_index: 0,
+            end_index: 0xffff,
+            min_score: None,
+            led: None,
+        };
+    }
+}
+
+/// Events reported by `run_identify_loop`.
+#[derive(Debug)]
+pub enum IdentifyEvent<TXE, RXE> {
+    /// The finger matched the template at `index` in the library.
+    Matched { index: u16, score: u16 },
+
+    /// The finger was captured, but did not match anything in the library.
+    NoMatch,
+
+    /// The module matched the finger to the template at `index`, but the score was below
+    /// `IdentifyConfig::min_score`. This should be treated like `NoMatch`; the raw result is
+    /// only kept for logging.
+    BelowThreshold { index: u16, score: u16 },
+
+    /// A finger was detected, but could not be captured or processed well enough to search.
+    CaptureFailed,
+
+    /// Communication with the R502 failed. The loop carries on unless told to stop.
+    Error(Error<TXE, RXE>),
+}
+
+/// Returned by the `run_identify_loop` callback to decide whether to keep going.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopControl {
+    Continue,
+    Stop,
+}
+
+/// Error type for `identify`, naming the stage which failed.
+#[derive(Debug)]
+pub enum IdentifyError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// There is no finger on the sensor.
+    NoFinger,
+
+    /// The sensor failed to capture an image.
+    Capture(GenImgStatus),
+
+    /// The captured image could not be converted into a _character file_.
+    Convert(Img2TzStatus),
+
+    /// The R502 could not search the library.
+    Search(SearchStatus),
+
+    /// The finger does not match anything in the library.
+    NoMatch,
+
+    /// The finger matched the template at `index`, but with a score below
+    /// `IdentifyConfig::min_score`.
+    BelowThreshold { index: u16, score: u16 },
+}
+
+impl<TXE, RXE> IdentifyError<TXE, RXE> {
+    /// True if the failure comes down to how the finger was presented, or which finger it
+    /// was, so the user should simply try again.
+    pub fn is_user_recoverable(&self) -> bool {
+        return match self {
+            Self::NoFinger | Self::NoMatch | Self::BelowThreshold { .. } => true,
+            Self::Capture(status) => matches!(status, GenImgStatus::ImageNotCaptured),
+            Self::Convert(status) => is_bad_image(status),
+            _ => false,
+        };
+    }
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for IdentifyError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+/// Error type for `verify`, naming the stage which failed.
+#[derive(Debug)]
+pub enum VerifyError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// There is no finger on the sensor.
+    NoFinger,
+
+    /// The sensor failed to capture an image.
+    Capture(GenImgStatus),
+
+    /// The captured image could not be converted into a _character file_.
+    Convert(Img2TzStatus),
+
+    /// The template to verify against could not be loaded, most likely because the slot is
+    /// empty.
+    Load(LoadCharStatus),
+
+    /// The R502 could not compare the two templates.
+    Match(MatchStatus),
+
+    /// The finger does not match the template, or only with a score below the threshold.
+    /// `score` is 0 if there was no match at all.
+    NotMatched { score: u16 },
+}
+
+impl<TXE, RXE> VerifyError<TXE, RXE> {
+    /// True if the failure comes down to how the finger was presented, or which finger it
+    /// was, so the user should simply try again.
+    pub fn is_user_recoverable(&self) -> bool {
+        return match self {
+            Self::NoFinger | Self::NotMatched { .. } => true,
+            Self::Capture(status) => matches!(status, GenImgStatus::ImageNotCaptured),
+            Self::Convert(status) => is_bad_image(status),
+            _ => false,
+        };
+    }
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for VerifyError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+/// True if `Img2Tz` failed because of a poor image rather than a fault.
+fn is_bad_image(status: &Img2TzStatus) -> bool {
+    return matches!(
+        status,
+        Img2TzStatus::FingerprintImageDistorted | Img2TzStatus::ProcessingFailed
+    );
+}
+
+/// Error type for `search_in_slots`.
+#[derive(Debug)]
+pub enum SlotSearchError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// Only _character buffers_ 1 and 2 can be searched with.
+    InvalidBuffer(u8),
+
+    /// The R502 could not search a range of slots.
+    Search(SearchStatus),
+
+    /// The template at `index` could not be loaded for comparison.
+    LoadChar { index: u16, status: LoadCharStatus },
+
+    /// The R502 could not compare two templates.
+    Match(MatchStatus),
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for SlotSearchError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+/// True if a match with `score` is good enough to accept under `min_score`.
+pub(crate) fn meets_min_score(score: u16, min_score: Option<u16>) -> bool {
+    return match min_score {
+        Some(min_score) => score >= min_score,
+        None => true,
+    };
+}
+
+/// Outcome of a single poll of the identification loop: `None` if the sensor was empty.
+type StepResult<TXE, RXE> = Result<Option<IdentifyEvent<TXE, RXE>>, Error<TXE, RXE>>;
+
+/// Outcome of `search_in_slots`: the best match as `(index, score)`, if any.
+pub type SlotSearchResult<TXE, RXE> = Result<Option<(u16, u16)>, SlotSearchError<TXE, RXE>>;
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Runs a continuous identification loop, as for an attendance terminal: waits for a
+    /// finger, searches the library for it, reports the outcome to `on_event`, waits for the
+    /// finger to be lifted, and repeats.
+    ///
+    /// The loop only returns once `on_event` returns `LoopControl::Stop`. Errors are reported
+    /// as `IdentifyEvent::Error` rather than ending the loop, after which the loop waits for
+    /// `config.poll_interval_ms` before trying again.
+    pub fn run_identify_loop<D, F>(
+        &mut self,
+        delay: &mut D,
+        config: &IdentifyConfig,
+        on_event: F,
+    ) where
+        D: DelayMs<u16>,
+        F: FnMut(IdentifyEvent<T::WriteError, T::ReadError>) -> LoopControl,
+    {
+        self.run_identify_loop_with_cancel(delay, config, &mut NeverCancel, on_event);
+    }
+
+    /// Like [`run_identify_loop`](#method.run_identify_loop), but also stops once `cancel`
+    /// asks to, which is checked before every poll of the sensor. This allows stopping the
+    /// loop while nobody is touching the sensor, when `on_event` is never called.
+    pub fn run_identify_loop_with_cancel<D, C, F>(
+        &mut self,
+        delay: &mut D,
+        config: &IdentifyConfig,
+        cancel: &mut C,
+        on_event: F,
+    ) where
+        D: DelayMs<u16>,
+        C: CancelToken,
+        F: FnMut(IdentifyEvent<T::WriteError, T::ReadError>) -> LoopControl,
+    {
+        self.identify_loop(delay, config, cancel, &mut |_| true, on_event);
+    }
+
+    /// Like [`run_identify_loop_with_cancel`](#method.run_identify_loop_with_cancel), but
+    /// watches the module's touch output on `pin` while waiting for a finger, as per
+    /// [`wait_for_touch`](#method.wait_for_touch), and only polls the sensor over the UART
+    /// once it has been touched. Lifting the finger is still detected with `GenImg`.
+    ///
+    /// If `pin` cannot be read, the loop falls back to polling the sensor.
+    pub fn run_identify_loop_on_touch<D, P, C, F>(
+        &mut self,
+        delay: &mut D,
+        config: &IdentifyConfig,
+        pin: &mut P,
+        cancel: &mut C,
+        on_event: F,
+    ) where
+        D: DelayMs<u16>,
+        P: InputPin,
+        C: CancelToken,
+        F: FnMut(IdentifyEvent<T::WriteError, T::ReadError>) -> LoopControl,
+    {
+        let mut touched = |delay: &mut D| read_touch(pin, delay).unwrap_or(true);
+        self.identify_loop(delay, config, cancel, &mut touched, on_event);
+    }
+
+    /// The identification loop. `touched` says whether the sensor may have a finger on it,
+    /// and is checked before every poll of the sensor while waiting for one.
+    fn identify_loop<D, F>(
+        &mut self,
+        delay: &mut D,
+        config: &IdentifyConfig,
+        cancel: &mut dyn CancelToken,
+        touched: &mut dyn FnMut(&mut D) -> bool,
+        mut on_event: F,
+    ) where
+        D: DelayMs<u16>,
+        F: FnMut(IdentifyEvent<T::WriteError, T::ReadError>) -> LoopControl,
+    {
+        self.led_feedback(&config.led, |led| led.waiting);
+        loop {
+            if cancel.is_cancelled() {
+                self.led_feedback(&config.led, |led| led.idle);
+                return;
+            }
+            if !touched(delay) {
+                delay.delay_ms(config.poll_interval_ms);
+                continue;
+            }
+            let event = match self.identify_step(config) {
+                Ok(Some(event)) => event,
+                Ok(None) => {
+                    delay.delay_ms(config.poll_interval_ms);
+                    continue;
+                }
+                Err(error) => IdentifyEvent::Error(error),
+            };
+
+            let failed = matches!(event, IdentifyEvent::Error(_));
+            match event {
+                IdentifyEvent::Matched { .. } => self.led_feedback(&config.led, |led| led.success),
+                _ => self.led_feedback(&config.led, |led| led.failure),
+            }
+            if on_event(event) == LoopControl::Stop {
+                self.led_feedback(&config.led, |led| led.idle);
+                return;
+            }
+
+            if failed {
+                delay.delay_ms(config.poll_interval_ms);
+            } else if let Err(error) = self.wait_for_removal(delay, config, cancel) {
+                if on_event(IdentifyEvent::Error(error)) == LoopControl::Stop {
+                    self.led_feedback(&config.led, |led| led.idle);
+                    return;
+                }
+            }
+            self.led_feedback(&config.led, |led| led.waiting);
+        }
+    }
+
+    /// Searches only the library slots in `slots` for the template in _character buffer_
+    /// `buffer`, returning the best match as `(index, score)`, or `None` if nothing matched
+    /// with a score of at least `min_score`.
+    ///
+    /// Runs of consecutive slots are searched with a single ranged `Search`. Slots on their
+    /// own are loaded into the other buffer with `LoadChar` and compared with `Match`; empty
+    /// slots are skipped.
+    ///
+    /// **Note:** This overwrites the contents of the other _character buffer_.
+    pub fn search_in_slots(
+        &mut self,
+        buffer: u8,
+        slots: &[u16],
+        min_score: Option<u16>,
+    ) -> SlotSearchResult<T::WriteError, T::ReadError> {
+        let other = match buffer {
+            1 => 2,
+            2 => 1,
+            _ => return Err(SlotSearchError::InvalidBuffer(buffer)),
+        };
+
+        let (table, _) = IndexTable::from_indices(slots, MAX_LIBRARY_SIZE);
+        let mut best: Option<(u16, u16)> = None;
+        for (start, count) in table.occupied_runs() {
+            let hit = if count > 1 {
+                self.search_range(buffer, start, start + count - 1)?
+            } else {
+                self.match_slot(other, start)?
+            };
+            if let Some((index, score)) = hit {
+                let better = best.is_none_or(|(_, best_score)| score > best_score);
+                if better && meets_min_score(score, min_score) {
+                    best = Some((index, score));
+                }
+            }
+        }
+        return Ok(best);
+    }
+
+    fn search_range(
+        &mut self,
+        buffer: u8,
+        start_index: u16,
+        end_index: u16,
+    ) -> SlotSearchResult<T::WriteError, T::ReadError> {
+        let result = expect_reply!(
+            self.send_command(Command::Search { buffer, start_index, end_index }),
+            Reply::Search
+        )?;
+        return match result.confirmation_code {
+            SearchStatus::Success => Ok(Some((result.match_id, result.match_score))),
+            SearchStatus::NoMatch => Ok(None),
+            #[cfg(feature = "r503")]
+            SearchStatus::LibraryEmpty => Ok(None),
+            status => Err(SlotSearchError::Search(status)),
+        };
+    }
+
+    fn match_slot(
+        &mut self,
+        buffer: u8,
+        index: u16,
+    ) -> SlotSearchResult<T::WriteError, T::ReadError> {
+        let result = expect_reply!(
+            self.send_command(Command::LoadChar { buffer, index }),
+            Reply::LoadChar
+        )?;
+        match result.confirmation_code {
+            LoadCharStatus::Success => {}
+            LoadCharStatus::LibraryReadError => return Ok(None),
+            #[cfg(feature = "r503")]
+            LoadCharStatus::TemplateEmpty => return Ok(None),
+            status => return Err(SlotSearchError::LoadChar { index, status }),
+        }
+
+        let result = expect_reply!(self.send_command(Command::Match), Reply::Match)?;
+        return match result.confirmation_code {
+            MatchStatus::Success => Ok(Some((index, result.match_score))),
+            MatchStatus::NoMatch => Ok(None),
+            status => Err(SlotSearchError::Match(status)),
+        };
+    }
+
+    /// Captures the finger on the sensor and searches the library for it between
+    /// `config.start_index` and `config.end_index`, returning the match as `(index, score)`.
+    ///
+    /// This does not wait for a finger; see
+    /// [`run_identify_loop`](#method.run_identify_loop) for that.
+    pub fn identify(
+        &mut self,
+        config: &IdentifyConfig,
+    ) -> Result<(u16, u16), IdentifyError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(self.send_command(Command::GenImg), Reply::GenImg)?;
+        match result.confirmation_code {
+            GenImgStatus::Success => {}
+            GenImgStatus::FingerNotDetected => return Err(IdentifyError::NoFinger),
+            status => return Err(IdentifyError::Capture(status)),
+        }
+
+        let result = expect_reply!(
+            self.send_command(Command::Img2Tz { buffer: 1 }),
+            Reply::Img2Tz
+        )?;
+        match result.confirmation_code {
+            Img2TzStatus::Success => {}
+            status => return Err(IdentifyError::Convert(status)),
+        }
+
+        let result = expect_reply!(
+            self.send_command(Command::Search {
+                buffer: 1,
+                start_index: config.start_index,
+                end_index: config.end_index,
+            }),
+            Reply::Search
+        )?;
+        let (index, score) = (result.match_id, result.match_score);
+        return match result.confirmation_code {
+            SearchStatus::Success if meets_min_score(score, config.min_score) => Ok((index, score)),
+            SearchStatus::Success => Err(IdentifyError::BelowThreshold { index, score }),
+            SearchStatus::NoMatch => Err(IdentifyError::NoMatch),
+            #[cfg(feature = "r503")]
+            SearchStatus::LibraryEmpty => Err(IdentifyError::NoMatch),
+            status => Err(IdentifyError::Search(status)),
+        };
+    }
+
+    /// Polls the sensor with `GenImg` every `poll_interval_ms` until a finger has been captured,
+    /// giving up after `max_polls` attempts. Returns the status of the last `GenImg`, which is
+    /// `GenImgStatus::Success` if the image is ready for `Img2Tz`.
+    ///
+    /// Stops straight away if the module reports `GenImgStatus::PacketError`.
+    pub fn wait_for_finger<D>(
+        &mut self,
+        delay: &mut D,
+        poll_interval_ms: u16,
+        max_polls: u16,
+    ) -> Result<GenImgStatus, Error<T::WriteError, T::ReadError>>
+    where
+        D: DelayMs<u16>,
+    {
+        let mut status = GenImgStatus::FingerNotDetected;
+        for poll in 0..max_polls {
+            if poll > 0 {
+                delay.delay_ms(poll_interval_ms);
+            }
+            status = expect_reply!(self.send_command(Command::GenImg), Reply::GenImg)?
+                .confirmation_code;
+            match status {
+                GenImgStatus::FingerNotDetected | GenImgStatus::ImageNotCaptured => {}
+                _ => break,
+            }
+        }
+        return Ok(status);
+    }
+
+    /// Captures the finger on the sensor and checks it against the template at `index`,
+    /// returning the match score. Matches scoring below `min_score` are refused.
+    ///
+    /// **Note:** This overwrites the contents of both _character buffers_.
+    pub fn verify(
+        &mut self,
+        index: u16,
+        min_score: Option<u16>,
+    ) -> Result<u16, VerifyError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(self.send_command(Command::GenImg), Reply::GenImg)?;
+        match result.confirmation_code {
+            GenImgStatus::Success => {}
+            GenImgStatus::FingerNotDetected => return Err(VerifyError::NoFinger),
+            status => return Err(VerifyError::Capture(status)),
+        }
+        return self.verify_captured(index, min_score);
+    }
+
+    /// The part of `verify` after the image has been captured.
+    pub(crate) fn verify_captured(
+        &mut self,
+        index: u16,
+        min_score: Option<u16>,
+    ) -> Result<u16, VerifyError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(
+            self.send_command(Command::Img2Tz { buffer: 1 }),
+            Reply::Img2Tz
+        )?;
+        match result.confirmation_code {
+            Img2TzStatus::Success => {}
+            status => return Err(VerifyError::Convert(status)),
+        }
+
+        let result = expect_reply!(
+            self.send_command(Command::LoadChar { buffer: 2, index }),
+            Reply::LoadChar
+        )?;
+        match result.confirmation_code {
+            LoadCharStatus::Success => {}
+            status => return Err(VerifyError::Load(status)),
+        }
+
+        let result = expect_reply!(self.send_command(Command::Match), Reply::Match)?;
+        let score = result.match_score;
+        return match result.confirmation_code {
+            MatchStatus::Success if meets_min_score(score, min_score) => Ok(score),
+            MatchStatus::Success | MatchStatus::NoMatch => Err(VerifyError::NotMatched { score }),
+            status => Err(VerifyError::Match(status)),
+        };
+    }
+
+    /// Polls the sensor once, and if there is a finger on it, runs a search. Returns `None`
+    /// if the sensor was empty.
+    fn identify_step(
+        &mut self,
+        config: &IdentifyConfig,
+    ) -> StepResult<T::WriteError, T::ReadError> {
+        return match self.identify(config) {
+            Ok((index, score)) => Ok(Some(IdentifyEvent::Matched { index, score })),
+            Err(IdentifyError::NoFinger) => Ok(None),
+            Err(IdentifyError::NoMatch) => Ok(Some(IdentifyEvent::NoMatch)),
+            Err(IdentifyError::BelowThreshold { index, score }) => {
+                Ok(Some(IdentifyEvent::BelowThreshold { index, score }))
+            }
+            Err(IdentifyError::Comms(error)) => Err(error),
+            Err(_) => Ok(Some(IdentifyEvent::CaptureFailed)),
+        };
+    }
+
+    /// Polls the sensor until it has read empty `config.removal_debounce` times in a row.
+    fn wait_for_removal<D>(
+        &mut self,
+        delay: &mut D,
+        config: &IdentifyConfig,
+        cancel: &mut dyn CancelToken,
+    ) -> Result<(), Error<T::WriteError, T::ReadError>>
+    where
+        D: DelayMs<u16>,
+    {
+        let mut empty_readings = 0;
+        while empty_readings < config.removal_debounce && !cancel.is_cancelled() {
+            let result = expect_reply!(self.send_command(Command::GenImg), Reply::GenImg)?;
+            match result.confirmation_code {
+                GenImgStatus::FingerNotDetected => empty_readings += 1,
+                _ => empty_readings = 0,
+            }
+            delay.delay_ms(config.poll_interval_ms);
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::cancel::NeverCancel;
+    use crate::emulator::{char_file, Emulator, EmulatorError, NoDelay};
+    #[cfg(feature = "cmd-led")]
+    use crate::led::LedFeedback;
+    use crate::mock::{Expectation, MockTransport};
+    use std::vec;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_identify_loop() {
+        // given: a module with finger 7 enrolled at index 2
+        let emulator = Emulator::new();
+        emulator.enroll(2, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // and: a bouncy finger 7, then unknown finger 9, then finger 7 again on the sensor
+        emulator.touch(&[None, Some(7), None, Some(7), None, None, None]);
+        emulator.touch(&[Some(9), None, None, None]);
+        emulator.touch(&[Some(7)]);
+
+        // when: running the loop until three fingers were seen
+        let mut events = Vec::new();
+        let config = IdentifyConfig::default();
+        r502.run_identify_loop(&mut NoDelay, &config, |event| {
+            events.push(event);
+            if events.len() == 3 {
+                LoopControl::Stop
+            } else {
+                LoopControl::Continue
+            }
+        });
+
+        // then: each finger was identified exactly once
+        assert_eq!(events.len(), 3);
+        assert_eq!(matches!(events[0], IdentifyEvent::Matched { index: 2, .. }), true);
+        assert_eq!(matches!(events[1], IdentifyEvent::NoMatch), true);
+        assert_eq!(matches!(events[2], IdentifyEvent::Matched { index: 2, .. }), true);
+
+        // and: the bounce while lifting finger 7 was not mistaken for a new touch
+        assert_eq!(emulator.state().touches.is_empty(), true);
+    }
+
+    #[test]
+    fn test_identify_loop_on_touch() {
+        // given: a module with finger 7 enrolled at index 2
+        let emulator = Emulator::new();
+        emulator.enroll(2, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // and: a touch pin which stays low for a while, bounces, then goes high for finger 7
+        let mut pin = emulator.touch_pin(&[false, false, true, false, false, true, true]);
+        emulator.touch(&[Some(7)]);
+
+        // when: running the loop until a finger was seen
+        let mut events = Vec::new();
+        let config = IdentifyConfig::default();
+        r502.run_identify_loop_on_touch(&mut NoDelay, &config, &mut pin, &mut NeverCancel, |e| {
+            events.push(e);
+            return LoopControl::Stop;
+        });
+
+        // then: the finger was identified
+        assert_eq!(events.len(), 1);
+        assert_eq!(matches!(events[0], IdentifyEvent::Matched { index: 2, .. }), true);
+
+        // and: nothing was sent until the pin had settled high
+        let reads = emulator.state().touch_pin_reads.clone();
+        assert_eq!(reads.len(), 7);
+        assert_eq!(reads.iter().all(|(_, sent)| *sent == 0), true);
+        assert_eq!(emulator.instructions(), vec![0x01, 0x02, 0x04]);
+    }
+
+    #[test]
+    fn test_identify_loop_on_touch_pin_error() {
+        // given: a module with finger 7 enrolled at index 2, and a touch pin which cannot be read
+        let emulator = Emulator::new();
+        emulator.enroll(2, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        let mut pin = emulator.touch_pin(&[]);
+        emulator.state().touch_pin_broken = true;
+        emulator.touch(&[None, Some(7)]);
+
+        // when: running the loop until a finger was seen
+        let mut events = Vec::new();
+        let config = IdentifyConfig::default();
+        r502.run_identify_loop_on_touch(&mut NoDelay, &config, &mut pin, &mut NeverCancel, |e| {
+            events.push(e);
+            return LoopControl::Stop;
+        });
+
+        // then: the loop fell back to polling the sensor
+        assert_eq!(matches!(events[0], IdentifyEvent::Matched { index: 2, .. }), true);
+        assert_eq!(emulator.instructions(), vec![0x01, 0x01, 0x02, 0x04]);
+    }
+
+    /// Counts the delays asked for.
+    #[derive(Default)]
+    struct CountingDelay(Vec<u16>);
+
+    impl DelayMs<u16> for CountingDelay {
+        fn delay_ms(&mut self, ms: u16) {
+            self.0.push(ms);
+        }
+    }
+
+    #[test]
+    fn test_wait_for_finger() {
+        // given: a sensor which is empty for three polls, then has finger 7 on it
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        emulator.touch(&[None, None, None, Some(7)]);
+
+        // when: waiting for a finger, polling every 50 ms
+        let mut delay = CountingDelay::default();
+        let status = r502.wait_for_finger(&mut delay, 50, 10).unwrap();
+
+        // then: the finger was captured on the fourth poll, with a wait before each retry
+        assert_eq!(matches!(status, GenImgStatus::Success), true);
+        assert_eq!(emulator.instructions(), vec![0x01; 4]);
+        assert_eq!(delay.0, vec![50; 3]);
+    }
+
+    #[test]
+    fn test_wait_for_finger_gives_up() {
+        // given: a sensor which stays empty
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: waiting for a finger for five polls
+        let mut delay = CountingDelay::default();
+        let status = r502.wait_for_finger(&mut delay, 20, 5).unwrap();
+
+        // then: it stopped after five polls, reporting the empty sensor
+        assert_eq!(matches!(status, GenImgStatus::FingerNotDetected), true);
+        assert_eq!(emulator.instructions(), vec![0x01; 5]);
+        assert_eq!(delay.0, vec![20; 4]);
+    }
+
+    fn identify_once_with_score(
+        score: u16,
+        min_score: Option<u16>,
+    ) -> IdentifyEvent<EmulatorError, EmulatorError> {
+        // given: a module with finger 7 enrolled at index 2, which matches it with `score`
+        let emulator = Emulator::new();
+        emulator.enroll(2, 7);
+        emulator.state().match_score = score;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        emulator.touch(&[Some(7)]);
+
+        // when: identifying one finger
+        let config = IdentifyConfig { min_score, ..IdentifyConfig::default() };
+        let mut events = Vec::new();
+        r502.run_identify_loop(&mut NoDelay, &config, |event| {
+            events.push(event);
+            LoopControl::Stop
+        });
+        return events.pop().unwrap();
+    }
+
+    #[test]
+    fn test_identify_loop_min_score() {
+        // then: a score above the threshold is a match
+        let event = identify_once_with_score(100, Some(50));
+        assert_eq!(matches!(event, IdentifyEvent::Matched { index: 2, score: 100 }), true);
+
+        // and: so is a score right at the threshold
+        let event = identify_once_with_score(50, Some(50));
+        assert_eq!(matches!(event, IdentifyEvent::Matched { index: 2, score: 50 }), true);
+
+        // and: a score below it is rejected, but still reported
+        let event = identify_once_with_score(12, Some(50));
+        assert_eq!(matches!(event, IdentifyEvent::BelowThreshold { index: 2, score: 12 }), true);
+
+        // and: without a threshold, any match the module reports is accepted
+        let event = identify_once_with_score(12, None);
+        assert_eq!(matches!(event, IdentifyEvent::Matched { index: 2, score: 12 }), true);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-led")]
+    fn test_identify_loop_led_feedback() {
+        // given: a module with a ring LED and finger 7 enrolled at index 2
+        let emulator = Emulator::new();
+        emulator.enroll(2, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // and: finger 7 followed by unknown finger 9 on the sensor
+        emulator.touch(&[None, Some(7), None]);
+        emulator.touch(&[Some(9)]);
+
+        // when: running the loop with LED feedback until two fingers were seen
+        let feedback = LedFeedback::default();
+        let config = IdentifyConfig {
+            removal_debounce: 1,
+            led: Some(feedback),
+            ..IdentifyConfig::default()
+        };
+        let mut seen = 0;
+        r502.run_identify_loop(&mut NoDelay, &config, |_| {
+            seen += 1;
+            if seen == 2 {
+                LoopControl::Stop
+            } else {
+                LoopControl::Continue
+            }
+        });
+
+        // then: the LED shows each outcome, returns to waiting in between, and goes off at the end
+        assert_eq!(
+            emulator.state().led,
+            vec![
+                feedback.waiting,
+                feedback.success,
+                feedback.waiting,
+                feedback.failure,
+                feedback.idle
+            ]
+        );
+
+        // and: the LED commands were sent around the right steps
+        let led = 0x35;
+        assert_eq!(
+            emulator.instructions(),
+            vec![led, 0x01, 0x01, 0x02, 0x04, led, 0x01, led, 0x01, 0x02, 0x04, led, led]
+        );
+    }
+
+    #[test]
+    fn test_identify_loop_reports_errors() {
+        // given: a module which does not reply
+        let emulator = Emulator::new();
+        emulator.state().silent = true;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: running the loop until two errors were seen
+        let mut errors = 0;
+        r502.run_identify_loop(&mut NoDelay, &IdentifyConfig::default(), |event| {
+            match event {
+                IdentifyEvent::Error(Error::RecvReadError(EmulatorError::Timeout)) => errors += 1,
+                other => panic!("Expected IdentifyEvent::Error, got {:?}", other),
+            };
+            if errors == 2 {
+                LoopControl::Stop
+            } else {
+                LoopControl::Continue
+            }
+        });
+
+        // then: the loop kept going after the first error
+        assert_eq!(errors, 2);
+    }
+
+    #[test]
+    fn test_identify_high_capacity() {
+        // given: a 1500-slot module with finger 7 enrolled near the end of the library
+        let emulator = Emulator::with_geometry(1500, 2);
+        emulator.enroll(1400, 7);
+        emulator.touch(&[Some(7)]);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: identifying the finger with the default range
+        let hit = r502.identify(&IdentifyConfig::default()).unwrap();
+
+        // then: the search reached it
+        assert_eq!(hit, (1400, 200));
+    }
+
+    #[test]
+    fn test_search_in_contiguous_slots() {
+        // given: finger 7 at 11 and in buffer 1, and finger 8 at 3
+        let emulator = Emulator::new();
+        emulator.enroll(3, 8);
+        emulator.enroll(11, 7);
+        emulator.state().buffers[0] = Some(char_file(7));
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: searching a run of slots
+        let hit = r502.search_in_slots(1, &[12, 10, 11], None).unwrap();
+
+        // then: a single ranged Search finds it
+        assert_eq!(hit, Some((11, 200)));
+        assert_eq!(emulator.instructions(), vec![0x04]);
+    }
+
+    #[test]
+    fn test_search_in_scattered_slots() {
+        // given: finger 7 at 2 and 11, and in buffer 1, with finger 8 at 6
+        let emulator = Emulator::new();
+        emulator.enroll(2, 7);
+        emulator.enroll(6, 8);
+        emulator.enroll(11, 7);
+        emulator.state().buffers[0] = Some(char_file(7));
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: searching slots that are not next to each other, one of them empty
+        let hit = r502.search_in_slots(1, &[6, 11, 9], None).unwrap();
+
+        // then: each slot is loaded and matched, and slot 2 is never looked at
+        assert_eq!(hit, Some((11, 200)));
+        assert_eq!(emulator.instructions(), vec![0x07, 0x03, 0x07, 0x07, 0x03]);
+    }
+
+    #[test]
+    fn test_search_in_slots_min_score() {
+        // given: finger 7 at 4 and in buffer 2, matching with a score of 40
+        let emulator = Emulator::new();
+        emulator.enroll(4, 7);
+        emulator.state().buffers[1] = Some(char_file(7));
+        emulator.state().match_score = 40;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: searching with a threshold above that score
+        let hit = r502.search_in_slots(2, &[4, 5], Some(50)).unwrap();
+
+        // then: the weak match is not accepted
+        assert_eq!(hit, None);
+    }
+
+    #[test]
+    fn test_identify_loop_cancelled() {
+        // given: a module nobody is touching
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: running the loop with a budget of three polls
+        let mut budget = 3;
+        let mut cancel = || {
+            budget -= 1;
+            budget < 0
+        };
+        let mut events = 0;
+        let config = IdentifyConfig::default();
+        r502.run_identify_loop_with_cancel(&mut NoDelay, &config, &mut cancel, |_| {
+            events += 1;
+            LoopControl::Continue
+        });
+
+        // then: the loop returns after three polls without any events
+        assert_eq!(events, 0);
+        assert_eq!(emulator.instructions(), vec![0x01, 0x01, 0x01]);
+    }
+
+    #[test]
+    fn test_identify_error_stages() {
+        // given: a module with finger 7 enrolled at index 2
+        let emulator = Emulator::new();
+        emulator.enroll(2, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        let config = IdentifyConfig::default();
+
+        // then: an empty sensor is reported as such
+        match r502.identify(&config) {
+            Err(IdentifyError::NoFinger) => {}
+            other => panic!("Expected IdentifyError::NoFinger, got {:?}", other),
+        };
+
+        // and: a failed capture, conversion or search names the stage
+        emulator.touch(&[Some(7), Some(7)]);
+        emulator.fail_next(0x01, 0x03);
+        match r502.identify(&config) {
+            Err(IdentifyError::Capture(GenImgStatus::ImageNotCaptured)) => {}
+            other => panic!("Expected IdentifyError::Capture, got {:?}", other),
+        };
+        emulator.fail_next(0x02, 0x07);
+        match r502.identify(&config) {
+            Err(IdentifyError::Convert(Img2TzStatus::ProcessingFailed)) => {}
+            other => panic!("Expected IdentifyError::Convert, got {:?}", other),
+        };
+        emulator.fail_next(0x04, 0x01);
+        let error = r502.identify(&config).unwrap_err();
+        match error {
+            IdentifyError::Search(SearchStatus::PacketError) => {}
+            ref other => panic!("Expected IdentifyError::Search, got {:?}", other),
+        };
+        assert_eq!(error.is_user_recoverable(), false);
+
+        // and: an unknown finger is something to try again
+        emulator.touch(&[Some(9)]);
+        let error = r502.identify(&config).unwrap_err();
+        match error {
+            IdentifyError::NoMatch => {}
+            ref other => panic!("Expected IdentifyError::NoMatch, got {:?}", other),
+        };
+        assert_eq!(error.is_user_recoverable(), true);
+    }
+
+    #[cfg(feature = "r503")]
+    #[test]
+    fn test_identify_empty_library_r503() {
+        // given: an R503 with nothing enrolled, and a finger on the sensor
+        let emulator = Emulator::r503();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        emulator.touch(&[Some(9)]);
+
+        // when: identifying it
+        let result = r502.identify(&IdentifyConfig::default());
+
+        // then: the R503's empty library is no match, as it is on the R502
+        match result {
+            Err(IdentifyError::NoMatch) => {}
+            other => panic!("Expected IdentifyError::NoMatch, got {:?}", other),
+        };
+        assert_eq!(emulator.instructions(), vec![0x01, 0x02, 0x04]);
+    }
+
+    #[test]
+    fn test_verify() {
+        // given: a module with finger 7 enrolled at index 2
+        let emulator = Emulator::new();
+        emulator.enroll(2, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: finger 7 is checked against slots 2 and 3
+        emulator.touch(&[Some(7), Some(7)]);
+        let matched = r502.verify(2, None);
+        let empty = r502.verify(3, None);
+
+        // then: slot 2 matches and the empty slot fails to load
+        assert_eq!(matched.unwrap(), 200);
+        match empty {
+            Err(VerifyError::Load(LoadCharStatus::LibraryReadError)) => {}
+            other => panic!("Expected VerifyError::Load, got {:?}", other),
+        };
+
+        // and: a different finger is not matched
+        emulator.touch(&[Some(9)]);
+        match r502.verify(2, None) {
+            Err(VerifyError::NotMatched { score: 0 }) => {}
+            other => panic!("Expected VerifyError::NotMatched, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn test_verify_command_sequence() {
+        // given: a module scripted to match the finger against slot 4, scoring below the
+        // threshold
+        let mock = MockTransport::new(
+            0xffffffff,
+            vec![
+                Expectation::new(Command::GenImg),
+                Expectation::new(Command::Img2Tz { buffer: 1 }),
+                Expectation::new(Command::LoadChar { buffer: 2, index: 4 }),
+                Expectation::new(Command::Match).reply(0x00, &[0x00, 0x28]),
+            ],
+        );
+        let mut r502 = R502::from_serial(mock.clone(), 0xffffffff);
+
+        // when: verifying the finger against slot 4
+        let result = r502.verify(4, Some(50));
+
+        // then: exactly those commands were sent, and the low score was refused
+        mock.done();
+        match result {
+            Err(VerifyError::NotMatched { score: 40 }) => {}
+            other => panic!("Expected VerifyError::NotMatched, got {:?}", other),
+        };
+    }
+}
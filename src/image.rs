@@ -0,0 +1,256 @@
+#[cfg(feature = "cmd-enroll")]
+use crate::audit::{AuditError, AuditOperation};
+use crate::commands::Command;
+use crate::driver::R502;
+use crate::responses::*;
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// Error type for `download_image`.
+#[derive(Debug)]
+pub enum ImageDownloadError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// The image is `length` bytes long, but the module's sensor takes images of `expected`
+    /// bytes, see `ModuleFamily::image_len`. Nothing was sent.
+    WrongLength { length: usize, expected: usize },
+
+    /// The R502 refused to accept a download into the _image buffer_.
+    Rejected(DownImageStatus),
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for ImageDownloadError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+/// Error type for `enroll_from_images`. Says which stage refused the data, and for which of
+/// the two images; `image` is 1 or 2.
+#[cfg(feature = "cmd-enroll")]
+#[derive(Debug)]
+pub enum ImageEnrollError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// The image could not be downloaded into the _image buffer_.
+    Download { image: u8, error: ImageDownloadError<TXE, RXE> },
+
+    /// The image could not be converted into a _character file_.
+    Convert { image: u8, status: Img2TzStatus },
+
+    /// The two images could not be combined into a template, most likely because they are not
+    /// of the same finger.
+    Combine(RegModelStatus),
+
+    /// The template could not be stored in the library.
+    Store(StoreStatus),
+
+    /// The template was stored, but the record of it could not be added to the `AuditLog`.
+    Audit(AuditError<TXE, RXE>),
+}
+
+#[cfg(feature = "cmd-enroll")]
+impl<TXE, RXE> From<Error<TXE, RXE>> for ImageEnrollError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Downloads `image` into the _image buffer_ using `DownImage`, in place of an image
+    /// captured with `GenImg`, for example to test matching against a library of captured
+    /// images. The image must be as long as the images the sensor takes, see
+    /// `ModuleFamily::image_len`: one byte for every two pixels, row by row.
+    pub fn download_image(
+        &mut self,
+        image: &[u8],
+    ) -> Result<(), ImageDownloadError<T::WriteError, T::ReadError>> {
+        let expected = self.family.image_len();
+        if image.len() != expected {
+            return Err(ImageDownloadError::WrongLength { length: image.len(), expected });
+        }
+
+        let result = expect_reply!(self.send_command(Command::DownImage), Reply::DownImage)?;
+        match result.confirmation_code {
+            DownImageStatus::Success => {}
+            status => return Err(ImageDownloadError::Rejected(status)),
+        }
+
+        self.send_data(image)?;
+        return Ok(());
+    }
+
+    /// Enrols a finger at `index` from two images supplied by the host rather than captured
+    /// by the sensor: each is downloaded with
+    /// [`download_image`](#method.download_image) and converted into a _character buffer_,
+    /// and the two are combined with `RegModel` and stored with `Store`. This makes matching
+    /// behaviour testable without a finger on the sensor.
+    ///
+    /// Both images are checked for length before anything is sent. The slot is overwritten if
+    /// it is occupied.
+    ///
+    /// **Note:** This overwrites the contents of the _image buffer_ and both _character
+    /// buffers_.
+    #[cfg(feature = "cmd-enroll")]
+    pub fn enroll_from_images(
+        &mut self,
+        index: u16,
+        img_a: &[u8],
+        img_b: &[u8],
+    ) -> Result<(), ImageEnrollError<T::WriteError, T::ReadError>> {
+        let expected = self.family.image_len();
+        for (image, data) in [(1u8, img_a), (2, img_b)].iter() {
+            if data.len() != expected {
+                let error = ImageDownloadError::WrongLength { length: data.len(), expected };
+                return Err(ImageEnrollError::Download { image: *image, error });
+            }
+        }
+
+        for (image, data) in [(1u8, img_a), (2, img_b)].iter() {
+            self.download_image(data)
+                .map_err(|error| ImageEnrollError::Download { image: *image, error })?;
+            let result = expect_reply!(
+                self.send_command(Command::Img2Tz { buffer: *image }),
+                Reply::Img2Tz
+            )?;
+            match result.confirmation_code {
+                Img2TzStatus::Success => {}
+                status => return Err(ImageEnrollError::Convert { image: *image, status }),
+            }
+        }
+
+        let result = expect_reply!(self.send_command(Command::RegModel), Reply::RegModel)?;
+        match result.confirmation_code {
+            RegModelStatus::Success => {}
+            status => return Err(ImageEnrollError::Combine(status)),
+        }
+
+        let result = expect_reply!(
+            self.send_command(Command::Store { buffer: 1, index }),
+            Reply::Store
+        )?;
+        match result.confirmation_code {
+            StoreStatus::Success => {}
+            status => return Err(ImageEnrollError::Store(status)),
+        }
+        return self
+            .record_audit(AuditOperation::Enrolled, index, 1)
+            .map_err(ImageEnrollError::Audit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::compat::ModuleFamily;
+    use crate::consts::{R307_IMAGE_LEN, R502_IMAGE_LEN};
+    use crate::emulator::{image_file, Emulator, EmulatorRx, EmulatorTx};
+    use std::vec;
+
+    fn r502(emulator: &Emulator) -> R502<(EmulatorTx, EmulatorRx)> {
+        let (tx, rx) = emulator.serial();
+        return R502::new(tx, rx, 0xffffffff);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_enroll_from_images() {
+        // given: two images of the same finger
+        let emulator = Emulator::new();
+        let mut r502 = r502(&emulator);
+
+        // when: enrolling from them
+        r502.enroll_from_images(4, &image_file(7), &image_file(7)).unwrap();
+
+        // then: the finger is in the library, having gone through each stage in turn
+        assert_eq!(emulator.slot(4).map(|template| template[0]), Some(7));
+        assert_eq!(emulator.instructions(), vec![0x0b, 0x02, 0x0b, 0x02, 0x05, 0x06]);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_enroll_from_images_wrong_length() {
+        // given: a second image cut short
+        let emulator = Emulator::new();
+        let mut r502 = r502(&emulator);
+        let short = &image_file(7)[..1000];
+
+        // when: enrolling from it
+        let result = r502.enroll_from_images(4, &image_file(7), short);
+
+        // then: it is refused, naming the image, before anything is sent
+        match result {
+            Err(ImageEnrollError::Download {
+                image: 2,
+                error: ImageDownloadError::WrongLength { length: 1000, expected: R502_IMAGE_LEN },
+            }) => {}
+            other => panic!("Expected a wrong length, got {:?}", other),
+        }
+        assert_eq!(emulator.instructions().is_empty(), true);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_enroll_from_images_featureless() {
+        // given: a second image which is blank
+        let emulator = Emulator::new();
+        let mut r502 = r502(&emulator);
+        let blank = vec![0xff; R502_IMAGE_LEN];
+
+        // when: enrolling from it
+        let result = r502.enroll_from_images(4, &image_file(7), &blank);
+
+        // then: the conversion of the second image failed, and nothing was stored
+        match result {
+            Err(ImageEnrollError::Convert { image: 2, status: Img2TzStatus::InvalidInput }) => {}
+            other => panic!("Expected a failed conversion, got {:?}", other),
+        }
+        assert_eq!(emulator.slot(4), None);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_enroll_from_images_different_fingers() {
+        // given: images of two different fingers
+        let emulator = Emulator::new();
+        let mut r502 = r502(&emulator);
+
+        // when: enrolling from them
+        let result = r502.enroll_from_images(4, &image_file(7), &image_file(8));
+
+        // then: they could not be combined
+        match result {
+            Err(ImageEnrollError::Combine(RegModelStatus::ProcessingError)) => {}
+            other => panic!("Expected a failed combination, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_download_image_family_length() {
+        // given: a driver in R307 mode
+        let emulator = Emulator::r307();
+        let mut r502 = r502(&emulator);
+        r502.set_module_family(ModuleFamily::R307);
+
+        // when: downloading an R502 image, then one of the R307's size
+        let wrong = r502.download_image(&image_file(7));
+        let right = r502.download_image(&vec![0x11; R307_IMAGE_LEN]);
+
+        // then: only the second is sent
+        match wrong {
+            Err(ImageDownloadError::WrongLength { length, expected: R307_IMAGE_LEN }) => {
+                assert_eq!(length, R502_IMAGE_LEN);
+            }
+            other => panic!("Expected a wrong length, got {:?}", other),
+        }
+        assert_eq!(right.is_ok(), true);
+        assert_eq!(emulator.instructions(), vec![0x0b]);
+    }
+}
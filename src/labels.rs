@@ -0,0 +1,348 @@
+use arrayvec::{ArrayString, ArrayVec};
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::driver::R502;
+use crate::consts::NOTEPAD_PAGE_SIZE;
+use crate::notepad::{NotepadError, NotepadPage};
+use crate::transport::Transport;
+
+/// Longest label in bytes of UTF-8. Longer labels are truncated.
+pub const LABEL_LENGTH: usize = 24;
+
+/// Most labels that fit in the notepad.
+pub const MAX_LABELS: usize = 19;
+
+/// A label read back from the notepad.
+pub type Label = ArrayString<LABEL_LENGTH>;
+
+/// Every stored label, as (index, label).
+pub type Labels = ArrayVec<(u16, Label), MAX_LABELS>;
+
+/// Marks a notepad which holds labels.
+const MAGIC: [u8; 2] = *b"LB";
+
+/// Header entry of an unused label record.
+const FREE: u16 = 0xffff;
+
+/// The header is the magic followed by the slot index of each label record.
+const HEADER_SIZE: usize = MAGIC.len() + 2 * MAX_LABELS;
+
+type LabelSlots = [u16; MAX_LABELS];
+
+/// Error type for the label helpers.
+#[derive(Debug)]
+pub enum LabelError<TXE, RXE> {
+    /// A notepad page could not be read or written.
+    Notepad(NotepadError<TXE, RXE>),
+
+    /// All `MAX_LABELS` labels are in use.
+    Full,
+
+    /// The label stored for the given index is not valid UTF-8.
+    Corrupt(u16),
+}
+
+impl<TXE, RXE> From<NotepadError<TXE, RXE>> for LabelError<TXE, RXE> {
+    fn from(error: NotepadError<TXE, RXE>) -> Self {
+        return Self::Notepad(error);
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Stores a short label for the template at `index` in the notepad, replacing any label it
+    /// already has. Labels longer than `LABEL_LENGTH` bytes are cut short at the last whole
+    /// character that fits, and a label ends at its first NUL character.
+    ///
+    /// The labels take over the whole notepad: up to `MAX_LABELS` of them are kept behind a
+    /// small header saying which index each one belongs to. A notepad without that header is
+    /// taken to hold no labels, and is overwritten. Deleting a template does not remove its
+    /// label; use [`clear_label`](#method.clear_label) for that.
+    pub fn set_label(
+        &mut self,
+        index: u16,
+        label: &str,
+    ) -> Result<(), LabelError<T::WriteError, T::ReadError>> {
+        let mut slots = self.read_label_slots()?;
+        let entry = match slots.iter().position(|slot| *slot == index) {
+            Some(entry) => entry,
+            None => match slots.iter().position(|slot| *slot == FREE) {
+                Some(entry) => entry,
+                None => return Err(LabelError::Full),
+            },
+        };
+
+        let mut end = label.len().min(LABEL_LENGTH);
+        while !label.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut record = [0u8; LABEL_LENGTH];
+        record[..end].copy_from_slice(&label.as_bytes()[..end]);
+
+        // The record goes first, so that an interrupted write never leaves the header pointing
+        // at someone else's label.
+        self.write_notepad(HEADER_SIZE + entry * LABEL_LENGTH, &record)?;
+        if slots[entry] != index {
+            slots[entry] = index;
+            self.write_label_slots(&slots)?;
+        }
+        return Ok(());
+    }
+
+    /// Reads the label stored for the template at `index` with
+    /// [`set_label`](#method.set_label), if it has one.
+    pub fn get_label(
+        &mut self,
+        index: u16,
+    ) -> Result<Option<Label>, LabelError<T::WriteError, T::ReadError>> {
+        let slots = self.read_label_slots()?;
+        let entry = match slots.iter().position(|slot| *slot == index) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        return Ok(Some(self.read_label_record(index, entry)?));
+    }
+
+    /// Removes the label of the template at `index`, freeing its space for another one.
+    /// Returns false if there was no label to remove.
+    pub fn clear_label(
+        &mut self,
+        index: u16,
+    ) -> Result<bool, LabelError<T::WriteError, T::ReadError>> {
+        let mut slots = self.read_label_slots()?;
+        let entry = match slots.iter().position(|slot| *slot == index) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        slots[entry] = FREE;
+        self.write_label_slots(&slots)?;
+        return Ok(true);
+    }
+
+    /// Reads every stored label with the index it belongs to, in storage order. Cheaper than
+    /// calling [`get_label`](#method.get_label) for each index, as the header is read once.
+    pub fn read_labels(&mut self) -> Result<Labels, LabelError<T::WriteError, T::ReadError>> {
+        let slots = self.read_label_slots()?;
+        let mut labels = Labels::new();
+        for (entry, index) in slots.iter().enumerate().filter(|(_, slot)| **slot != FREE) {
+            labels.push((*index, self.read_label_record(*index, entry)?));
+        }
+        return Ok(labels);
+    }
+
+    fn read_label_record(
+        &mut self,
+        index: u16,
+        entry: usize,
+    ) -> Result<Label, LabelError<T::WriteError, T::ReadError>> {
+        let mut record = [0u8; LABEL_LENGTH];
+        self.read_notepad(HEADER_SIZE + entry * LABEL_LENGTH, &mut record)?;
+        let end = record.iter().position(|byte| *byte == 0).unwrap_or(LABEL_LENGTH);
+        let text = match core::str::from_utf8(&record[..end]) {
+            Ok(text) => text,
+            Err(_) => return Err(LabelError::Corrupt(index)),
+        };
+
+        let mut label = Label::new();
+        label.push_str(text);
+        return Ok(label);
+    }
+
+    fn read_label_slots(&mut self) -> Result<LabelSlots, LabelError<T::WriteError, T::ReadError>> {
+        let mut header = [0u8; HEADER_SIZE];
+        self.read_notepad(0, &mut header)?;
+
+        let mut slots = [FREE; MAX_LABELS];
+        if header[..MAGIC.len()] == MAGIC {
+            for (slot, bytes) in slots.iter_mut().zip(header[MAGIC.len()..].chunks(2)) {
+                *slot = BigEndian::read_u16(bytes);
+            }
+        }
+        return Ok(slots);
+    }
+
+    fn write_label_slots(
+        &mut self,
+        slots: &LabelSlots,
+    ) -> Result<(), LabelError<T::WriteError, T::ReadError>> {
+        let mut header = [0u8; HEADER_SIZE];
+        header[..MAGIC.len()].copy_from_slice(&MAGIC);
+        for (slot, bytes) in slots.iter().zip(header[MAGIC.len()..].chunks_mut(2)) {
+            BigEndian::write_u16(bytes, *slot);
+        }
+        return self.write_notepad(0, &header);
+    }
+
+    /// Reads `buffer.len()` bytes of the notepad starting at byte `offset`.
+    fn read_notepad(
+        &mut self,
+        offset: usize,
+        buffer: &mut [u8],
+    ) -> Result<(), LabelError<T::WriteError, T::ReadError>> {
+        let end = offset + buffer.len();
+        for page in offset / NOTEPAD_PAGE_SIZE..end.div_ceil(NOTEPAD_PAGE_SIZE) {
+            let data = self.read_notepad_page(label_page(page))?;
+            let start = page * NOTEPAD_PAGE_SIZE;
+            let from = offset.max(start);
+            let to = end.min(start + NOTEPAD_PAGE_SIZE);
+            buffer[from - offset..to - offset].copy_from_slice(&data[from - start..to - start]);
+        }
+        return Ok(());
+    }
+
+    /// Writes `bytes` to the notepad starting at byte `offset`. Pages which are only partly
+    /// covered are read first, so the rest of them is kept.
+    fn write_notepad(
+        &mut self,
+        offset: usize,
+        bytes: &[u8],
+    ) -> Result<(), LabelError<T::WriteError, T::ReadError>> {
+        let end = offset + bytes.len();
+        for page in offset / NOTEPAD_PAGE_SIZE..end.div_ceil(NOTEPAD_PAGE_SIZE) {
+            let start = page * NOTEPAD_PAGE_SIZE;
+            let from = offset.max(start);
+            let to = end.min(start + NOTEPAD_PAGE_SIZE);
+            let mut data = if to - from == NOTEPAD_PAGE_SIZE {
+                [0u8; NOTEPAD_PAGE_SIZE]
+            } else {
+                self.read_notepad_page(label_page(page))?
+            };
+            data[from - start..to - start].copy_from_slice(&bytes[from - offset..to - offset]);
+            self.write_notepad_page(label_page(page), &data)?;
+        }
+        return Ok(());
+    }
+}
+
+/// The labels never reach past the end of the notepad, so every page they touch exists.
+fn label_page(page: usize) -> NotepadPage {
+    return NotepadPage::new(page as u8).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::emulator::Emulator;
+
+    #[test]
+    fn test_label_round_trip() {
+        // given: a module with a blank notepad
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: labelling slot 5, and then relabelling it
+        assert_eq!(r502.set_label(5, "J. Smith L-index").is_ok(), true);
+        assert_eq!(r502.set_label(5, "J. Smith R-index").is_ok(), true);
+
+        // then: the latest label is read back, and other slots have none
+        assert_eq!(r502.get_label(5).unwrap().unwrap().as_str(), "J. Smith R-index");
+        assert_eq!(r502.get_label(6).unwrap(), None);
+
+        // and: the label took a single record
+        let notepad = emulator.state().notepad;
+        assert_eq!(&notepad[0][..6], &[b'L', b'B', 0x00, 0x05, 0xff, 0xff]);
+
+        // when: clearing the label
+        assert_eq!(r502.clear_label(5).unwrap(), true);
+
+        // then: it is gone
+        assert_eq!(r502.get_label(5).unwrap(), None);
+        assert_eq!(r502.clear_label(5).unwrap(), false);
+    }
+
+    #[test]
+    fn test_read_labels() {
+        // given: two labelled slots, one of them since cleared, and a third
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.set_label(8, "first").unwrap();
+        r502.set_label(2, "second").unwrap();
+        r502.clear_label(8).unwrap();
+        r502.set_label(5, "third").unwrap();
+
+        // when: reading every label
+        let labels = r502.read_labels().unwrap();
+
+        // then: the remaining ones come back in storage order
+        let labels: std::vec::Vec<(u16, &str)> =
+            labels.iter().map(|(index, label)| (*index, label.as_str())).collect();
+        assert_eq!(labels, [(5, "third"), (2, "second")]);
+    }
+
+    #[test]
+    fn test_label_truncation() {
+        // given: a module with a blank notepad
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: storing labels which are too long
+        r502.set_label(1, "Alexandra Konstantinopoulou").unwrap();
+        r502.set_label(2, "Zoë Wójcik-Øvergårdøy").unwrap();
+
+        // then: they are cut short without splitting a character
+        assert_eq!(r502.get_label(1).unwrap().unwrap().as_str(), "Alexandra Konstantinopou");
+        assert_eq!(r502.get_label(2).unwrap().unwrap().as_str(), "Zoë Wójcik-Øvergård");
+    }
+
+    #[test]
+    fn test_label_page_boundaries() {
+        // given: a module with a blank notepad
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: storing three labels, the third of which starts at byte 88
+        r502.set_label(10, "first").unwrap();
+        r502.set_label(20, "second").unwrap();
+        r502.set_label(30, "0123456789abcdefghijklmn").unwrap();
+
+        // then: the third label is split across pages 2 and 3
+        let notepad = emulator.state().notepad;
+        assert_eq!(&notepad[2][24..], b"01234567");
+        assert_eq!(&notepad[3][..16], b"89abcdefghijklmn");
+
+        // and: every label reads back intact
+        assert_eq!(r502.get_label(10).unwrap().unwrap().as_str(), "first");
+        assert_eq!(r502.get_label(20).unwrap().unwrap().as_str(), "second");
+        assert_eq!(
+            r502.get_label(30).unwrap().unwrap().as_str(),
+            "0123456789abcdefghijklmn"
+        );
+    }
+
+    #[test]
+    fn test_labels_full() {
+        // given: a notepad holding as many labels as it can
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        for index in 0..MAX_LABELS as u16 {
+            r502.set_label(index, "someone").unwrap();
+        }
+
+        // when: adding one more
+        let result = r502.set_label(100, "one too many");
+
+        // then: there is no room for it
+        match result {
+            Err(LabelError::Full) => {}
+            other => panic!("Expected LabelError::Full, got {:?}", other),
+        };
+
+        // and: relabelling and freeing space still work
+        r502.set_label(3, "someone else").unwrap();
+        r502.clear_label(4).unwrap();
+        r502.set_label(100, "one too many").unwrap();
+        assert_eq!(r502.get_label(3).unwrap().unwrap().as_str(), "someone else");
+        assert_eq!(r502.get_label(100).unwrap().unwrap().as_str(), "one too many");
+        assert_eq!(r502.get_label(18).unwrap().unwrap().as_str(), "someone");
+    }
+}
@@ -0,0 +1,375 @@
+
+#[cfg(feature = "cmd-led")]
+use crate::commands::Command;
+use crate::compat::ModuleFamily;
+use crate::driver::R502;
+#[cfg(feature = "cmd-led")]
+use crate::responses::*;
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// Colours of the ring LED.
+///
+/// **Note:** Firmware which only has the red and blue LEDs knows `Red`, `Blue` and `Purple`.
+/// It ignores the other colours and keeps the LED as it was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedColor {
+    Red,
+    Blue,
+    Purple,
+    Green,
+    Yellow,
+    Cyan,
+    White,
+}
+
+impl LedColor {
+    /// Looks up a colour by its `AuraLedConfig` index [1-7].
+    pub fn from_index(index: u8) -> Option<Self> {
+        return match index {
+            0x01 => Some(Self::Red),
+            0x02 => Some(Self::Blue),
+            0x03 => Some(Self::Purple),
+            0x04 => Some(Self::Green),
+            0x05 => Some(Self::Yellow),
+            0x06 => Some(Self::Cyan),
+            0x07 => Some(Self::White),
+            _ => None,
+        };
+    }
+
+    /// The `AuraLedConfig` colour index.
+    pub fn index(&self) -> u8 {
+        return match self {
+            Self::Red => 0x01,
+            Self::Blue => 0x02,
+            Self::Purple => 0x03,
+            Self::Green => 0x04,
+            Self::Yellow => 0x05,
+            Self::Cyan => 0x06,
+            Self::White => 0x07,
+        };
+    }
+}
+
+/// What the ring LED should do. `speed` is the length of one cycle or fade, from 0 (fastest)
+/// to 255 (slowest, roughly five seconds).
+///
+/// **Note:** The firmware ignores the colour for `Off` and `GraduallyOff`, which turn off
+/// whichever colour is lit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedPattern {
+    /// Breathes continuously.
+    Breathing { speed: u8 },
+
+    /// Flashes `times` times and then turns off. A `times` of 0 flashes forever.
+    Flashing { speed: u8, times: u8 },
+
+    /// Turns on.
+    On,
+
+    /// Turns off.
+    Off,
+
+    /// Fades in and stays on.
+    GraduallyOn { speed: u8 },
+
+    /// Fades out.
+    GraduallyOff { speed: u8 },
+}
+
+impl LedPattern {
+    /// Decodes the control code, speed and times of an `AuraLedConfig` command, or returns
+    /// `None` if the control code is not one of [1-6].
+    pub fn from_raw(control: u8, speed: u8, times: u8) -> Option<Self> {
+        return match control {
+            0x01 => Some(Self::Breathing { speed }),
+            0x02 => Some(Self::Flashing { speed, times }),
+            0x03 => Some(Self::On),
+            0x04 => Some(Self::Off),
+            0x05 => Some(Self::GraduallyOn { speed }),
+            0x06 => Some(Self::GraduallyOff { speed }),
+            _ => None,
+        };
+    }
+}
+
+/// One setting of the ring LED, as sent with `AuraLedConfig`. See
+/// [`Command::AuraLedConfig`](enum.Command.html#variant.AuraLedConfig) for the meaning of
+/// each field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LedState {
+    pub control: u8,
+    pub speed: u8,
+    pub color: u8,
+    pub times: u8,
+}
+
+impl LedState {
+    /// Encodes `pattern` in `color` into the `AuraLedConfig` parameters. Fields the firmware
+    /// ignores for the pattern are sent as 0.
+    pub fn new(pattern: LedPattern, color: LedColor) -> Self {
+        let (control, speed, times) = match pattern {
+            LedPattern::Breathing { speed } => (0x01, speed, 0x00),
+            LedPattern::Flashing { speed, times } => (0x02, speed, times),
+            LedPattern::On => (0x03, 0x00, 0x00),
+            LedPattern::Off => (0x04, 0x00, 0x00),
+            LedPattern::GraduallyOn { speed } => (0x05, speed, 0x00),
+            LedPattern::GraduallyOff { speed } => (0x06, speed, 0x00),
+        };
+        return Self {
+            control,
+            speed,
+            color: color.index(),
+            times,
+        };
+    }
+
+    /// Decodes the raw parameters, or returns `None` if the control code or colour index is
+    /// out of range.
+    pub fn decode(&self) -> Option<(LedPattern, LedColor)> {
+        let pattern = LedPattern::from_raw(self.control, self.speed, self.times)?;
+        let color = LedColor::from_index(self.color)?;
+        return Some((pattern, color));
+    }
+
+    #[cfg(feature = "cmd-led")]
+    fn command(&self) -> Command {
+        return Command::AuraLedConfig {
+            control: self.control,
+            speed: self.speed,
+            color: self.color,
+            times: self.times,
+        };
+    }
+}
+
+/// Ring LED settings used by the enrolment and identification helpers to show the user what
+/// is going on. Leave it out of the helper config on modules without the LED.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LedFeedback {
+    /// Shown while waiting for a finger to be placed. Breathing blue by default.
+    pub waiting: LedState,
+
+    /// Shown when a finger was enrolled or identified. Solid green by default.
+    pub success: LedState,
+
+    /// Shown when enrolment or identification failed. Flashing red by default.
+    pub failure: LedState,
+
+    /// Shown when the helper is done with the sensor. Off by default.
+    pub idle: LedState,
+}
+
+impl Default for LedFeedback {
+    fn default() -> Self {
+        return Self {
+            waiting: LedState::new(LedPattern::Breathing { speed: 0x80 }, LedColor::Blue),
+            success: LedState::new(LedPattern::On, LedColor::Green),
+            failure: LedState::new(
+                LedPattern::Flashing {
+                    speed: 0x20,
+                    times: 3,
+                },
+                LedColor::Red,
+            ),
+            idle: LedState::new(LedPattern::Off, LedColor::Blue),
+        };
+    }
+}
+
+/// Error type for `set_led`.
+#[derive(Debug)]
+pub enum LedError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// The module rejected the command, most likely because it has no ring LED.
+    Unsupported,
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for LedError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+#[cfg(feature = "cmd-led")]
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Sets the ring LED to show `pattern` in `color`, using `AuraLedConfig`. In
+    /// `ModuleFamily::R307` mode, returns `LedError::Unsupported` without sending anything.
+    pub fn set_led(
+        &mut self,
+        pattern: LedPattern,
+        color: LedColor,
+    ) -> Result<(), LedError<T::WriteError, T::ReadError>> {
+        if self.family == ModuleFamily::R307 {
+            return Err(LedError::Unsupported);
+        }
+        let command = LedState::new(pattern, color).command();
+        let result = expect_reply!(self.send_command(command), Reply::AuraLedConfig)?;
+        return match result.confirmation_code {
+            AuraLedConfigStatus::Success => Ok(()),
+            AuraLedConfigStatus::PacketError => Err(LedError::Unsupported),
+        };
+    }
+
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Sets the ring LED if `feedback` is configured, picking the state with `stage`.
+    ///
+    /// This is best-effort: the LED is only a hint to the user, so failures are ignored rather
+    /// than failing the enrolment or identification around it. Modules without the LED reply
+    /// with a `PacketError` anyway, and in `ModuleFamily::R307` mode nothing is sent. Without
+    /// the `cmd-led` feature there is no LED command to send, so this does nothing.
+    pub(crate) fn led_feedback<F>(&mut self, feedback: &Option<LedFeedback>, stage: F)
+    where
+        F: FnOnce(&LedFeedback) -> LedState,
+    {
+        if self.family == ModuleFamily::R307 {
+            return;
+        }
+        #[cfg(feature = "cmd-led")]
+        if let Some(feedback) = feedback {
+            let _ = self.send_command(stage(feedback).command());
+        }
+        #[cfg(not(feature = "cmd-led"))]
+        let _ = (feedback, stage);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "cmd-led")]
+    use crate::emulator::Emulator;
+
+    fn encode(pattern: LedPattern, color: LedColor) -> (u8, u8, u8, u8) {
+        let state = LedState::new(pattern, color);
+        return (state.control, state.speed, state.color, state.times);
+    }
+
+    #[test]
+    fn test_led_pattern_encoding() {
+        // then: each pattern maps onto its control code, with unused fields zeroed
+        assert_eq!(
+            encode(LedPattern::Breathing { speed: 0x80 }, LedColor::Blue),
+            (0x01, 0x80, 0x02, 0x00)
+        );
+        assert_eq!(
+            encode(
+                LedPattern::Flashing {
+                    speed: 0x20,
+                    times: 3
+                },
+                LedColor::Red
+            ),
+            (0x02, 0x20, 0x01, 0x03)
+        );
+        assert_eq!(
+            encode(LedPattern::On, LedColor::Purple),
+            (0x03, 0x00, 0x03, 0x00)
+        );
+        assert_eq!(
+            encode(LedPattern::Off, LedColor::Green),
+            (0x04, 0x00, 0x04, 0x00)
+        );
+        assert_eq!(
+            encode(LedPattern::GraduallyOn { speed: 0xff }, LedColor::White),
+            (0x05, 0xff, 0x07, 0x00)
+        );
+        assert_eq!(
+            encode(LedPattern::GraduallyOff { speed: 0x10 }, LedColor::Cyan),
+            (0x06, 0x10, 0x06, 0x00)
+        );
+    }
+
+    #[test]
+    fn test_led_state_decoding() {
+        // given: a flashing yellow setting
+        let pattern = LedPattern::Flashing {
+            speed: 0x40,
+            times: 0,
+        };
+        let state = LedState::new(pattern, LedColor::Yellow);
+
+        // then: it decodes back to the same pattern and colour
+        assert_eq!(state.decode(), Some((pattern, LedColor::Yellow)));
+
+        // and: out of range control codes and colours are refused
+        assert_eq!(
+            LedState {
+                control: 0x07,
+                ..state
+            }
+            .decode(),
+            None
+        );
+        assert_eq!(
+            LedState {
+                color: 0x00,
+                ..state
+            }
+            .decode(),
+            None
+        );
+        assert_eq!(
+            LedState {
+                color: 0x08,
+                ..state
+            }
+            .decode(),
+            None
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-led")]
+    fn test_set_led() {
+        // given: a module with a ring LED
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: setting a purple breathing light
+        let result = r502.set_led(LedPattern::Breathing { speed: 0x40 }, LedColor::Purple);
+
+        // then: the LED is set
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(
+            emulator.state().led.last(),
+            Some(&LedState {
+                control: 0x01,
+                speed: 0x40,
+                color: 0x03,
+                times: 0x00
+            })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-led")]
+    fn test_set_led_unsupported() {
+        // given: a module without a ring LED
+        let emulator = Emulator::new();
+        emulator.state().unsupported.push(0x35);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: turning the LED on
+        let result = r502.set_led(LedPattern::On, LedColor::Red);
+
+        // then: the missing LED is reported
+        match result {
+            Err(LedError::Unsupported) => {}
+            other => panic!("Expected LedError::Unsupported, got {:?}", other),
+        };
+    }
+}
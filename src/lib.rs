@@ -11,12 +11,24 @@
 //! 
 //! Response types are all linked from [`Reply`](enum.Reply.html).
 //!
+//! Instructions `Command` does not cover, such as vendor extensions, can be defined outside the
+//! crate by implementing [`ProtocolCommand`](trait.ProtocolCommand.html) and sent with
+//! `R502::send`.
+//!
+//! To move the bytes yourself, for example with DMA, encode commands with
+//! [`encode_command`](fn.encode_command.html) and decode replies with
+//! [`decode_reply`](fn.decode_reply.html); no `R502` is needed. To put replies together as
+//! the bytes arrive, push them into a [`ReplyParser`](struct.ReplyParser.html).
+//! Buffers for either can be sized at compile time with
+//! [`max_frame_len`](fn.max_frame_len.html), [`image_buf_len`](fn.image_buf_len.html) and the
+//! dimension constants next to them.
+//!
 //! ## Example
 //!
 //! To authenticate with the R502:
 //! ```
 //! # use embedded_hal::serial::{Read, Write};
-//! use hzgrow_r502::{R502, Command, Reply};
+//! use hzgrow_r502::R502;
 //! # struct TestTx;
 //! # struct TestRx(usize);
 //! #
@@ -30,7 +42,11 @@
 //! #     }
 //! # }
 //! #
-//! # const res_data: &[u8] = &[ 0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x00, 0x00, 0x0a ];
+//! # const res_data: &[u8] = &[
+//! #     0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x00, 0x00, 0x0a,
+//! #     0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x13, 0x00, 0x00, 0x04, 0x00, 0x00,
+//! #     0x00, 0xc8, 0x00, 0x03, 0xff, 0xff, 0xff, 0xff, 0x00, 0x02, 0x00, 0x06, 0x04, 0xed,
+//! # ];
 //! #
 //! # impl Read<u8> for TestRx {
 //! #     type Error = ();
@@ -45,28 +61,291 @@
 //!
 //! // Obtain tx, rx from some serial port implementation
 //! let mut r502 = R502::new(tx, rx, 0xffffffff);
-//! match r502.send_command(Command::VfyPwd { password: 0x00000000 }) {
-//!     Ok(Reply::VfyPwd(result)) => println!("Status: {:#?}", result.confirmation_code),
+//! match r502.authenticate(0x00000000) {
+//!     Ok(parameters) => println!("Library size: {}", parameters.finger_library_size),
 //!     Err(error) => panic!("Error: {:#?}", error),
-//!     _ => {},
 //! }
 //! ```
 //!
+//! If your HAL gives you one serial object which both reads and writes rather than a pair of
+//! halves, use `R502::from_serial(serial, address)` instead.
+//!
+//! For the R307, ZFM-20 and their clones, which lack some instructions, call
+//! [`R502::detect_module_family`](struct.R502.html#method.detect_module_family) or
+//! `set_module_family(ModuleFamily::R307)` first.
+//!
 //! For more examples, see [the `examples` directory](https://github.com/FLamparski/hzgrow-r502/tree/master/examples).
 #![warn(missing_debug_implementations, rust_2018_idioms)]
 #![no_std]
 
+#[cfg(feature = "std")]
+extern crate std;
+
+#[macro_use]
+mod utils;
+
+mod cancel;
+mod address;
+mod allocation;
+mod audit;
+mod autobaud;
+#[cfg(feature = "bridge")]
+mod bridge;
+mod buffer;
+mod bus;
+#[cfg(feature = "async")]
+mod async_driver;
+mod clock;
+mod codec;
+mod command_str;
 mod commands;
+mod compat;
+mod config;
+mod consts;
+mod diagnose;
+mod display;
 mod driver;
+#[cfg(any(test, feature = "emulator"))]
+mod emulator;
+#[cfg(feature = "cmd-enroll")]
+mod enroll;
+mod events;
+#[cfg(any(test, feature = "mock"))]
+mod fake;
+#[cfg(any(test, feature = "mock", feature = "emulator"))]
+mod faults;
+#[cfg(feature = "embedded-storage")]
+mod flash;
+#[cfg(all(
+    test,
+    feature = "cmd-enroll",
+    feature = "cmd-transfer",
+    feature = "cmd-notepad",
+    feature = "cmd-led"
+))]
+mod golden;
+#[cfg(any(test, feature = "fuzzing"))]
+mod fuzzing;
+mod identify;
+#[cfg(feature = "cmd-transfer")]
+mod image;
+#[cfg(feature = "cmd-notepad")]
+mod labels;
+mod led;
+mod library;
+mod lockout;
+mod maintenance;
+#[cfg(any(test, feature = "mock"))]
+mod mock;
+#[cfg(all(feature = "std", feature = "serde"))]
+mod manifest;
+#[cfg(feature = "cmd-transfer")]
+mod mirror;
+mod notepad;
+mod observer;
+mod pacing;
+mod parser;
+mod power;
+#[cfg(feature = "embedded-graphics")]
+mod preview;
+mod profile;
+mod provision;
+mod quality;
+mod random;
+mod reader;
+#[cfg(feature = "std")]
+mod record;
+#[cfg(feature = "cmd-notepad")]
+mod registry;
 mod responses;
-mod utils;
+mod rs485;
+mod scan;
+mod sequence;
+#[cfg(feature = "serialport")]
+mod serial_port;
+#[cfg(feature = "cmd-enroll")]
+mod session;
+mod split;
+mod stats;
+#[cfg(all(feature = "std", feature = "serde"))]
+mod sync;
+mod system;
+mod template;
+mod timing;
+#[cfg(feature = "tokio")]
+mod tokio_port;
+mod touch;
+mod transport;
+#[cfg(feature = "ufmt")]
+mod ufmt_impls;
+mod workflow;
 
-pub use crate::commands::Command;
+pub use crate::address::AddressDisagreement;
+pub use crate::allocation::{AllocationStrategy, SlotAllocation};
+pub use crate::audit::{
+    AuditEntries, AuditError, AuditLog, AuditOperation, AuditRecord, AUDIT_RECORDS_PER_PAGE,
+    MAX_AUDIT_RECORDS,
+};
+pub use crate::autobaud::{
+    BaudAttempt, BaudAttempts, DetectBaudError, BAUD_CANDIDATES, MAX_BAUD_ATTEMPTS,
+};
+#[cfg(feature = "bridge")]
+pub use crate::bridge::{Bridge, BridgeError, BridgeEvent, BridgeObserver};
+#[cfg(feature = "async")]
+pub use crate::async_driver::R502Async;
+pub use crate::bus::{BusError, DeviceHandle, R502Bus, MAX_BUS_DEVICES};
+pub use crate::cancel::{CancelToken, NeverCancel};
+pub use crate::clock::Clock;
+pub use crate::codec::{
+    decode_command, decode_reply, encode_command, frame_length, DecodeError, EncodeError,
+    ReplyView,
+};
+pub use crate::command_str::CommandParseError;
+pub use crate::commands::{Command, CommandKind, CHAR_BUFFERS};
+pub use crate::compat::{ModuleFamily, R307_MAX_LIBRARY_SIZE};
+pub use crate::config::{
+    BaudChange, BaudChangeResult, ConfigError, ConfigReport, DeviceConfigTarget, ResumeError,
+};
+pub use crate::consts::{
+    image_buf_len, max_frame_len, PacketSize, DEFAULT_PACKET_SIZE, FRAME_CHECKSUM_LENGTH,
+    FRAME_HEADER_LENGTH, MAX_COMMAND_LENGTH, MAX_PACKET_LENGTH, NOTEPAD_PAGES, NOTEPAD_PAGE_SIZE,
+    R307_IMAGE_HEIGHT, R307_IMAGE_LEN, R307_IMAGE_WIDTH, R307_TEMPLATE_LEN, R502_IMAGE_HEIGHT,
+    R502_IMAGE_LEN, R502_IMAGE_WIDTH, R502_TEMPLATE_LEN,
+};
+pub use crate::diagnose::{Check, DiagnoseError, DiagnosisReport};
 pub use crate::driver::R502;
+#[cfg(feature = "emulator")]
+pub use crate::emulator::{
+    char_file, image_file, Emulator, EmulatorError, EmulatorRx, EmulatorState, EmulatorTx,
+    NoDelay, TouchPin, CHAR_FILE_LEN,
+};
+#[cfg(feature = "cmd-enroll")]
+pub use crate::enroll::{
+    BatchError, EnrollConfig, EnrollError, EnrollPrompt, EnrollmentBatch, UpdateError,
+    MAX_BATCH_LIBRARY_SIZE,
+};
+#[cfg(feature = "mock")]
+pub use crate::fake::{FakeCall, FakeEnroll, FakeIdentify, FakeReader};
+#[cfg(any(feature = "mock", feature = "emulator"))]
+pub use crate::faults::{Fault, FaultyTransport};
+#[cfg(feature = "embedded-storage")]
+pub use crate::flash::{
+    read_template, write_template, FlashBackup, FlashError, MAX_FLASH_WORD_SIZE,
+};
+#[cfg(all(feature = "embedded-storage", feature = "cmd-transfer"))]
+pub use crate::flash::FlashBackupError;
 pub use crate::responses::{
     GenImgResult, GenImgStatus, Img2TzResult, Img2TzStatus, LoadCharResult, LoadCharStatus,
-    MatchResult, MatchStatus, PasswordVerificationState, ReadSysParaResult, RegModelResult,
+    MatchResult, MatchStatus, PasswordVerificationState, ReadIndexTableResult,
+    ReadIndexTableStatus, ReadSysParaResult, RegModelResult,
     RegModelStatus, Reply, SearchResult, SearchStatus, SystemParameters, TemplateNumResult,
     TemplateNumStatus, VfyPwdResult, StoreResult, StoreStatus, DeletCharResult, DeletCharStatus,
+    UpCharResult, UpCharStatus, DownCharResult, DownCharStatus, DownImageResult, DownImageStatus,
+    HandShakeResult, HandShakeStatus,
+    CheckSensorResult, CheckSensorStatus, AuraLedConfigResult, AuraLedConfigStatus, SetPwdResult,
+    SetPwdStatus, SetSysParaResult, SetSysParaStatus,
+    SetAdderResult, SetAdderStatus, GetChipSNResult, GetChipSNStatus,
+    GetRandomCodeResult, GetRandomCodeStatus, SoftRstResult, SoftRstStatus, SleepResult,
+    SleepStatus, PortControlResult, PortControlStatus,
+    WriteNotepadResult, WriteNotepadStatus, ReadNotepadResult, ReadNotepadStatus,
+    GetFwVerResult, GetFwVerStatus, GetAlgVerResult, GetAlgVerStatus, EmptyResult, EmptyStatus,
+};
+pub use crate::events::{Event, EventResult};
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub use crate::fuzzing::{fuzz_commands, fuzz_frames, fuzz_replies};
+pub use crate::identify::{
+    IdentifyConfig, IdentifyError, IdentifyEvent, LoopControl, SlotSearchError, SlotSearchResult,
+    VerifyError,
+};
+#[cfg(feature = "cmd-transfer")]
+pub use crate::image::ImageDownloadError;
+#[cfg(all(feature = "cmd-enroll", feature = "cmd-transfer"))]
+pub use crate::image::ImageEnrollError;
+#[cfg(feature = "cmd-notepad")]
+pub use crate::labels::{Label, LabelError, Labels, LABEL_LENGTH, MAX_LABELS};
+pub use crate::led::{LedColor, LedError, LedFeedback, LedPattern, LedState};
+pub use crate::library::{
+    DuplicatePair, DuplicateReport, IndexTable, IndexTableSource, LibraryError, LibraryStats,
+    ScanProgress, INDEX_TABLE_PAGE_SIZE, MAX_DUPLICATE_PAIRS, MAX_LIBRARY_SIZE,
+};
+pub use crate::lockout::{Lockout, LockoutError, LockoutPolicy, LockoutResult};
+pub use crate::maintenance::{
+    DefragError, DeleteError, DeleteRange, DeleteReport, SlotMove, StoreVerifyError,
+    MAX_DELETE_RANGES,
+};
+#[cfg(all(feature = "std", feature = "serde"))]
+pub use crate::manifest::{
+    LibraryManifest, ManifestError, ManifestSlot, MANIFEST_SCHEMA_VERSION,
+};
+#[cfg(feature = "cmd-transfer")]
+pub use crate::mirror::{
+    LibraryMirror, MirrorDiscrepancy, MirrorError, MirrorFull, MirrorLabel, MirrorMismatch,
+    MirrorReport, MirrorSlot, MAX_MIRROR_DISCREPANCIES, MAX_MIRROR_SLOTS, MIRROR_LABEL_LENGTH,
+};
+#[cfg(feature = "mock")]
+pub use crate::mock::{Expectation, MockError, MockTransport};
+pub use crate::notepad::{
+    NotepadError, NotepadPage, NOTEPAD_CHECKED_SIZE, NOTEPAD_SIZE,
+};
+#[cfg(feature = "defmt")]
+pub use crate::observer::DefmtObserver;
+#[cfg(feature = "log")]
+pub use crate::observer::LogObserver;
+pub use crate::observer::{Observed, WireObserver};
+pub use crate::pacing::CommandGap;
+pub use crate::parser::{FrameError, RawFrame, ReplyParser};
+pub use crate::power::{ReadyError, StandbyError, MAX_READY_NOISE, READY_BYTE};
+#[cfg(feature = "embedded-graphics")]
+pub use crate::preview::{ImagePreview, PreviewScale};
+pub use crate::profile::{
+    CommandTimes, TimingProfile, TimingProfileFull, MAX_TIMING_OVERRIDES,
+};
+pub use crate::provision::{
+    ProvisionError, ProvisionReport, ProvisionStep, ProvisioningPlan, StepOutcome,
+};
+pub use crate::quality::{
+    ImageQuality, QualityProblem, QUALITY_BLANK_LEVEL, QUALITY_DARK_LEVEL,
+    QUALITY_MAX_BLANK_PERMILLE, QUALITY_MAX_DARK_PERMILLE, QUALITY_MAX_MEAN, QUALITY_MIN_CONTRAST,
+    QUALITY_MIN_MEAN,
+};
+#[cfg(feature = "rand")]
+pub use crate::random::ModuleRng;
+pub use crate::random::RandomError;
+pub use crate::reader::FingerprintReader;
+#[cfg(feature = "std")]
+pub use crate::record::{
+    RecordingTransport, ReplayError, ReplayTransport, RECORDING_HEADER,
+};
+#[cfg(feature = "cmd-notepad")]
+pub use crate::registry::{
+    RegistryError, UserRegistry, UserSlots, MAX_USER_SLOTS, REGISTRY_ENTRIES_PER_PAGE,
+};
+pub use crate::rs485::{Rs485, Rs485Error};
+pub use crate::scan::{FoundAddresses, COMMON_ADDRESSES, MAX_FOUND_ADDRESSES};
+pub use crate::sequence::{SequenceError, SequenceReplies, MAX_SEQUENCE_LENGTH};
+#[cfg(feature = "serialport")]
+pub use crate::serial_port::{SerialPortAdapter, SerialPortError};
+#[cfg(feature = "cmd-enroll")]
+pub use crate::session::{EnrollmentSession, SessionState};
+pub use crate::split::{R502Receiver, R502Sender};
+#[cfg(feature = "stats")]
+pub use crate::stats::DriverStats;
+#[cfg(all(feature = "std", feature = "serde"))]
+pub use crate::sync::{SlotChange, SyncAction, SyncError, SyncReport};
+pub use crate::system::{
+    AuthError, ChangePasswordError, HealthError, HealthReport, IdleError, Probe,
+};
+pub use crate::template::{
+    ExportError, ImportError, Template, TemplateWireError, TransferError, TEMPLATE_BASE64_CAPACITY,
+    TEMPLATE_CAPACITY, TEMPLATE_WIRE_MAGIC, TEMPLATE_WIRE_OVERHEAD, TEMPLATE_WIRE_VERSION,
+};
+pub use crate::timing::{CommandTiming, Timed};
+#[cfg(feature = "tokio")]
+pub use crate::tokio_port::{R502Tokio, TokioSerial, TokioSerialError};
+pub use crate::touch::{TouchError, TOUCH_DEBOUNCE_READS};
+pub use crate::transport::{CombinedSerial, Direction, Transport};
+pub use crate::utils::{
+    CommandWriter, Error, FromPayload, ProtocolCommand, ProtocolError, ToPayload,
 };
-pub use crate::utils::Error;
+pub use crate::workflow::{WorkflowObserver, WorkflowStep};
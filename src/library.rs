@@ -0,0 +1,1099 @@
+
+use arrayvec::ArrayVec;
+
+use crate::allocation::AllocationStrategy;
+use crate::commands::Command;
+use crate::driver::R502;
+use crate::identify::LoopControl;
+use crate::notepad::NotepadError;
+use crate::responses::*;
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// Number of library slots covered by one page of the _index table_.
+pub const INDEX_TABLE_PAGE_SIZE: u16 = 256;
+
+/// The largest library an `IndexTable` can hold. Larger libraries are only used up to
+/// this size.
+pub const MAX_LIBRARY_SIZE: u16 = 2048;
+
+/// Most duplicate pairs `find_duplicates` reports.
+pub const MAX_DUPLICATE_PAIRS: usize = 32;
+
+/// A copy of the whole _index table_, as read by
+/// [`R502::read_index_table`](struct.R502.html#method.read_index_table).
+#[derive(Clone)]
+pub struct IndexTable {
+    capacity: u16,
+    occupied: [u8; MAX_LIBRARY_SIZE as usize / 8],
+}
+
+impl IndexTable {
+    /// A table of `capacity` slots, all of them free.
+    pub(crate) fn empty(capacity: u16) -> Self {
+        return Self {
+            capacity: core::cmp::min(capacity, MAX_LIBRARY_SIZE),
+            occupied: [0u8; MAX_LIBRARY_SIZE as usize / 8],
+        };
+    }
+
+    /// A table of `capacity` slots with the given `indices` marked occupied, which sorts and
+    /// deduplicates them. Also returns how many indices did not fit.
+    pub(crate) fn from_indices(indices: &[u16], capacity: u16) -> (Self, u16) {
+        let mut table = Self::empty(capacity);
+        let mut out_of_range = 0;
+        for index in indices {
+            if *index < table.capacity {
+                table.set_occupied(*index, true);
+            } else {
+                out_of_range += 1;
+            }
+        }
+        return (table, out_of_range);
+    }
+
+    /// Number of library slots covered by the table.
+    pub fn capacity(&self) -> u16 {
+        return self.capacity;
+    }
+
+    /// True if slot `index` holds a template.
+    pub fn is_occupied(&self, index: u16) -> bool {
+        if index >= self.capacity {
+            return false;
+        }
+        return self.occupied[index as usize / 8] & (1u8 << (index % 8)) != 0;
+    }
+
+    /// Records whether slot `index` holds a template, to keep the copy in step with changes
+    /// made to the library. Indices past the capacity are ignored.
+    pub fn set_occupied(&mut self, index: u16, occupied: bool) {
+        if index >= self.capacity {
+            return;
+        }
+        if occupied {
+            self.occupied[index as usize / 8] |= 1u8 << (index % 8);
+        } else {
+            self.occupied[index as usize / 8] &= !(1u8 << (index % 8));
+        }
+    }
+
+    /// The occupied slots, lowest first.
+    pub fn occupied(&self) -> impl Iterator<Item = u16> + '_ {
+        return (0..self.capacity).filter(move |index| self.is_occupied(*index));
+    }
+
+    /// The lowest free slot, or `None` if the library is full.
+    pub fn first_free(&self) -> Option<u16> {
+        return (0..self.capacity).find(|index| !self.is_occupied(*index));
+    }
+
+    /// Runs of consecutive occupied slots, lowest first, as `(start, count)`.
+    pub fn occupied_runs(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        let mut index = 0;
+        return core::iter::from_fn(move || {
+            while index < self.capacity && !self.is_occupied(index) {
+                index += 1;
+            }
+            let start = index;
+            while index < self.capacity && self.is_occupied(index) {
+                index += 1;
+            }
+            return if index > start { Some((start, index - start)) } else { None };
+        });
+    }
+
+    /// Usage figures for the library, as per
+    /// [`R502::library_stats`](struct.R502.html#method.library_stats).
+    pub fn stats(&self) -> LibraryStats {
+        let mut stats = LibraryStats {
+            capacity: self.capacity,
+            used: 0,
+            free: 0,
+            largest_free_start: 0,
+            largest_free_run: 0,
+        };
+        let mut run_start = 0;
+        for index in 0..self.capacity {
+            if self.is_occupied(index) {
+                stats.used += 1;
+                run_start = index + 1;
+                continue;
+            }
+            stats.free += 1;
+            if index + 1 - run_start > stats.largest_free_run {
+                stats.largest_free_start = run_start;
+                stats.largest_free_run = index + 1 - run_start;
+            }
+        }
+        return stats;
+    }
+}
+
+/// How full the library is, as returned by
+/// [`R502::library_stats`](struct.R502.html#method.library_stats).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LibraryStats {
+    /// Number of slots in the library.
+    pub capacity: u16,
+
+    /// Slots holding a template.
+    pub used: u16,
+
+    /// Slots not holding a template.
+    pub free: u16,
+
+    /// First slot of the longest run of consecutive free slots. The lowest such run is
+    /// reported if there are several; 0 if the library is full.
+    pub largest_free_start: u16,
+
+    /// Length of the longest run of consecutive free slots, 0 if the library is full. This
+    /// many templates can be imported in one block starting at `largest_free_start`.
+    pub largest_free_run: u16,
+}
+
+impl core::fmt::Debug for IndexTable {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        return f
+            .debug_struct("IndexTable")
+            .field("capacity", &self.capacity)
+            .field("occupied", &self.occupied().count())
+            .finish();
+    }
+}
+
+/// How the driver finds out which slots are occupied, as reported by
+/// [`R502::index_table_source`](struct.R502.html#method.index_table_source).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IndexTableSource {
+    /// The module answers `ReadIndexTable`.
+    ReadIndexTable,
+
+    /// The module answered `ReadIndexTable` with a `PacketError`, as older firmware does, so
+    /// each slot is loaded with `LoadChar` to see whether it holds a template. This takes a
+    /// round trip per slot, and overwrites _character buffer_ 2.
+    Probing,
+}
+
+/// The driver's copy of the _index table_, see
+/// [`R502::enable_index_cache`](struct.R502.html#method.enable_index_cache).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IndexCache {
+    enabled: bool,
+    table: Option<IndexTable>,
+    pub(crate) source: Option<IndexTableSource>,
+}
+
+impl IndexCache {
+    pub(crate) const fn new() -> Self {
+        return Self { enabled: false, table: None, source: None };
+    }
+
+    /// The cached table, if it is warm.
+    pub(crate) fn table(&self) -> Option<&IndexTable> {
+        return self.table.as_ref();
+    }
+
+    /// Keeps a copy of `table`, if the cache is enabled or the table had to be probed.
+    pub(crate) fn fill(&mut self, table: &IndexTable) {
+        if self.enabled || self.source == Some(IndexTableSource::Probing) {
+            self.table = Some(table.clone());
+        }
+    }
+
+    pub(crate) fn invalidate(&mut self) {
+        self.table = None;
+    }
+
+    /// Brings the cached table in step with `command`, which has just been sent. `reply` is
+    /// `None` if no valid reply came back, in which case the command may or may not have
+    /// taken effect.
+    pub(crate) fn observe(&mut self, command: &Command, reply: Option<&Reply>) {
+        let table = match self.table.as_mut() {
+            Some(table) => table,
+            None => return,
+        };
+        let keep = match (command, reply) {
+            #[cfg(feature = "cmd-enroll")]
+            (Command::Store { index, .. }, Some(Reply::Store(result)))
+                if matches!(result.confirmation_code, StoreStatus::Success) =>
+            {
+                table.set_occupied(*index, true);
+                true
+            }
+            (Command::DeletChar { start_index, num_to_delete }, Some(Reply::DeletChar(result)))
+                if result.confirmation_code == DeletCharStatus::Success =>
+            {
+                let end = *start_index as u32 + *num_to_delete as u32;
+                let end = core::cmp::min(end, table.capacity as u32) as u16;
+                for index in *start_index..end {
+                    table.set_occupied(index, false);
+                }
+                true
+            }
+            (Command::Empty, Some(Reply::Empty(result)))
+                if result.confirmation_code == EmptyStatus::Success =>
+            {
+                *table = IndexTable::empty(table.capacity);
+                true
+            }
+            #[cfg(feature = "cmd-enroll")]
+            (Command::Store { .. }, _) => false,
+            (Command::DeletChar { .. }, _)
+            | (Command::Empty, _)
+            | (Command::SetAdder { .. }, _)
+            | (Command::SoftRst, _) => false,
+            _ => true,
+        };
+        if !keep {
+            self.table = None;
+        }
+    }
+}
+
+/// Two library slots holding the same finger, as found by `find_duplicates`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DuplicatePair {
+    pub first: u16,
+    pub second: u16,
+    pub score: u16,
+}
+
+/// Result of `find_duplicates`.
+#[derive(Debug, Clone)]
+pub struct DuplicateReport {
+    /// The duplicate pairs found, up to `MAX_DUPLICATE_PAIRS`.
+    pub pairs: ArrayVec<DuplicatePair, MAX_DUPLICATE_PAIRS>,
+
+    /// More pairs were found than fit into `pairs`.
+    pub overflowed: bool,
+
+    /// Every pair of slots was compared; false if the scan was stopped early.
+    pub completed: bool,
+}
+
+/// Progress of `find_duplicates`, reported after each comparison.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScanProgress {
+    /// Comparisons made so far.
+    pub compared: u32,
+
+    /// Comparisons needed for the whole library.
+    pub total: u32,
+}
+
+/// Error type for the helpers that inspect the fingerprint library.
+#[derive(Debug)]
+pub enum LibraryError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// The R502 refused to return the _index table_.
+    IndexTable(ReadIndexTableStatus),
+
+    /// The R502 refused to return the template count.
+    TemplateNum(TemplateNumStatus),
+
+    /// The R502 refused to return its system parameters.
+    ReadSysPara(u8),
+
+    /// The template at `index` could not be loaded.
+    LoadChar { index: u16, status: LoadCharStatus },
+
+    /// The R502 could not compare two templates.
+    Match(MatchStatus),
+
+    /// The cursor of `AllocationStrategy::Rotating` could not be read from the notepad.
+    Notepad(NotepadError<TXE, RXE>),
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for LibraryError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Reads the page of the _index table_ which covers library slot `index`.
+    pub fn read_index_table_page(
+        &mut self,
+        index: u16,
+    ) -> Result<ReadIndexTableResult, LibraryError<T::WriteError, T::ReadError>> {
+        let page = (index / INDEX_TABLE_PAGE_SIZE) as u8;
+        let result = expect_reply!(
+            self.send_command(Command::ReadIndexTable { page }),
+            Reply::ReadIndexTable
+        )?;
+        return match result.confirmation_code {
+            ReadIndexTableStatus::Success => Ok(result),
+            status => Err(LibraryError::IndexTable(status)),
+        };
+    }
+
+    /// Turns the driver's copy of the _index table_ on or off; it is off by default.
+    ///
+    /// While on, the first full read of the _index table_ is kept and updated whenever a
+    /// `Store`, `DeletChar` or `Empty` succeeds, so that `is_slot_occupied`, `next_free_slot`,
+    /// `read_index_table` and the helpers built on them answer without reading it again. The
+    /// copy is dropped if one of those commands fails or goes unanswered, after `SetAdder`,
+    /// `SoftRst` or [`set_address`](#method.set_address), and by
+    /// [`invalidate_index_cache`](#method.invalidate_index_cache), which should be called if
+    /// anything other than this driver could change the library.
+    ///
+    /// A table found by probing, on firmware without `ReadIndexTable`, is always kept, as
+    /// probing takes a round trip per slot.
+    pub fn enable_index_cache(&mut self, enabled: bool) {
+        self.index_cache.enabled = enabled;
+        if !enabled {
+            self.index_cache.invalidate();
+        }
+    }
+
+    /// Drops the driver's copy of the _index table_, so that the next helper which needs it
+    /// reads it from the module again.
+    pub fn invalidate_index_cache(&mut self) {
+        self.index_cache.invalidate();
+    }
+
+    /// The driver's copy of the _index table_, or `None` if the cache is off or has not been
+    /// filled since it was last dropped.
+    pub fn cached_index_table(&self) -> Option<&IndexTable> {
+        return self.index_cache.table();
+    }
+
+    /// How the driver finds out which slots are occupied, or `None` until it first had to.
+    /// Decided the first time the _index table_ is read, and forgotten by
+    /// [`set_address`](#method.set_address).
+    pub fn index_table_source(&self) -> Option<IndexTableSource> {
+        return self.index_cache.source;
+    }
+
+    /// True if library slot `index` holds a template, according to the _index table_. Needs
+    /// no I/O if the _index table_ is cached.
+    pub fn is_slot_occupied(
+        &mut self,
+        index: u16,
+    ) -> Result<bool, LibraryError<T::WriteError, T::ReadError>> {
+        if let Some(table) = self.index_cache.table() {
+            return Ok(table.is_occupied(index));
+        }
+        return match self.read_index_page_or_probe(index)? {
+            Some(page) => Ok(page.is_occupied((index % INDEX_TABLE_PAGE_SIZE) as u8)),
+            None => self.probe_slot(index),
+        };
+    }
+
+    /// Reads the page of the _index table_ which covers slot `index`, or returns `None` if
+    /// the module does not support `ReadIndexTable`, recording which is the case.
+    fn read_index_page_or_probe(
+        &mut self,
+        index: u16,
+    ) -> Result<Option<ReadIndexTableResult>, LibraryError<T::WriteError, T::ReadError>> {
+        if self.index_cache.source == Some(IndexTableSource::Probing) {
+            return Ok(None);
+        }
+        return match self.read_index_table_page(index) {
+            Ok(page) => {
+                self.index_cache.source = Some(IndexTableSource::ReadIndexTable);
+                Ok(Some(page))
+            }
+            Err(LibraryError::IndexTable(ReadIndexTableStatus::PacketError)) => {
+                self.index_cache.source = Some(IndexTableSource::Probing);
+                Ok(None)
+            }
+            Err(error) => Err(error),
+        };
+    }
+
+    /// True if slot `index` holds a template which `LoadChar` can load into buffer 2. Slots
+    /// past the end of the library are free.
+    fn probe_slot(
+        &mut self,
+        index: u16,
+    ) -> Result<bool, LibraryError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(
+            self.send_command(Command::LoadChar { buffer: 2, index }),
+            Reply::LoadChar
+        )?;
+        return match result.confirmation_code {
+            LoadCharStatus::Success => Ok(true),
+            LoadCharStatus::LibraryReadError | LoadCharStatus::IndexOutOfRange => Ok(false),
+            #[cfg(feature = "r503")]
+            LoadCharStatus::TemplateEmpty => Ok(false),
+            status => Err(LibraryError::LoadChar { index, status }),
+        };
+    }
+
+    /// Builds the _index table_ for the first `capacity` slots by probing each of them, and
+    /// caches it.
+    fn probe_index_table(
+        &mut self,
+        capacity: u16,
+    ) -> Result<IndexTable, LibraryError<T::WriteError, T::ReadError>> {
+        let mut table = IndexTable::empty(self.usable_library_size(capacity));
+        for index in 0..table.capacity {
+            let occupied = self.probe_slot(index)?;
+            table.set_occupied(index, occupied);
+        }
+        self.index_cache.fill(&table);
+        return Ok(table);
+    }
+
+    /// Returns the number of templates stored in the library, as reported by `TemplateNum`.
+    ///
+    /// **Note:** This is a count, not an index. Once a template in the middle of the library
+    /// has been deleted, the count no longer points at a free slot; use
+    /// [`next_free_slot`](#method.next_free_slot) to find out where to enrol next.
+    pub fn template_count(&mut self) -> Result<u16, LibraryError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(self.send_command(Command::TemplateNum), Reply::TemplateNum)?;
+        return match result.confirmation_code {
+            TemplateNumStatus::Success => Ok(result.template_num),
+            status => Err(LibraryError::TemplateNum(status)),
+        };
+    }
+
+    /// Returns the library capacity, as reported by `ReadSysPara`.
+    pub fn library_capacity(&mut self) -> Result<u16, LibraryError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(self.send_command(Command::ReadSysPara), Reply::ReadSysPara)?;
+        if result.confirmation_code != 0x00 {
+            return Err(LibraryError::ReadSysPara(result.confirmation_code));
+        }
+        return Ok(self.usable_library_size(result.system_parameters.finger_library_size));
+    }
+
+    /// Reads the whole _index table_, covering the library capacity reported by `ReadSysPara`.
+    pub fn read_index_table(
+        &mut self,
+    ) -> Result<IndexTable, LibraryError<T::WriteError, T::ReadError>> {
+        if let Some(table) = self.index_cache.table() {
+            return Ok(table.clone());
+        }
+        let capacity = self.library_capacity()?;
+        return self.read_index_table_pages(capacity);
+    }
+
+    /// Reads the _index table_ and sums up how the library is used: how many slots are taken
+    /// and free, and the longest run of free slots. Costs one `ReadSysPara` and a
+    /// `ReadIndexTable` per 256 slots.
+    pub fn library_stats(
+        &mut self,
+    ) -> Result<LibraryStats, LibraryError<T::WriteError, T::ReadError>> {
+        return Ok(self.read_index_table()?.stats());
+    }
+
+    /// Reads the _index table_ pages covering the first `capacity` slots, where `capacity` is
+    /// as `ReadSysPara` reported it.
+    pub(crate) fn read_index_table_pages(
+        &mut self,
+        capacity: u16,
+    ) -> Result<IndexTable, LibraryError<T::WriteError, T::ReadError>> {
+        let mut table = IndexTable::empty(self.usable_library_size(capacity));
+        if let Some(cached) = self.index_cache.table() {
+            if cached.capacity == table.capacity {
+                return Ok(cached.clone());
+            }
+        }
+        let mut page_start = 0u16;
+        while page_start < table.capacity {
+            let page = match self.read_index_page_or_probe(page_start)? {
+                Some(page) => page,
+                None => return self.probe_index_table(table.capacity),
+            };
+            let offset = page_start as usize / 8;
+            table.occupied[offset..offset + 32].copy_from_slice(&page.index_table);
+            page_start += INDEX_TABLE_PAGE_SIZE;
+        }
+        self.index_cache.fill(&table);
+        return Ok(table);
+    }
+
+    /// Scans the library for fingers enrolled more than once, comparing every occupied slot
+    /// with every later one and reporting pairs which match with a score of at least
+    /// `min_score`.
+    ///
+    /// This takes a round trip per pair of templates, so it gets slow quickly as the library
+    /// grows. `progress` is called after every comparison, and the scan stops early if it
+    /// returns `LoopControl::Stop`.
+    ///
+    /// **Note:** This overwrites the contents of both _character buffers_.
+    pub fn find_duplicates<F>(
+        &mut self,
+        min_score: u16,
+        mut progress: F,
+    ) -> Result<DuplicateReport, LibraryError<T::WriteError, T::ReadError>>
+    where
+        F: FnMut(ScanProgress) -> LoopControl,
+    {
+        let table = self.read_index_table()?;
+        let count = table.occupied().count() as u32;
+        let mut report = DuplicateReport {
+            pairs: ArrayVec::new(),
+            overflowed: false,
+            completed: false,
+        };
+        let mut scan = ScanProgress {
+            compared: 0,
+            total: count * count.saturating_sub(1) / 2,
+        };
+
+        for first in table.occupied() {
+            self.load_for_scan(2, first)?;
+            for second in table.occupied().filter(|second| *second > first) {
+                self.load_for_scan(1, second)?;
+                let result = expect_reply!(self.send_command(Command::Match), Reply::Match)?;
+                match result.confirmation_code {
+                    MatchStatus::Success if result.match_score >= min_score => {
+                        let pair = DuplicatePair { first, second, score: result.match_score };
+                        if report.pairs.try_push(pair).is_err() {
+                            report.overflowed = true;
+                        }
+                    }
+                    MatchStatus::Success | MatchStatus::NoMatch => {}
+                    status => return Err(LibraryError::Match(status)),
+                }
+
+                scan.compared += 1;
+                if progress(scan) == LoopControl::Stop {
+                    return Ok(report);
+                }
+            }
+        }
+
+        report.completed = true;
+        return Ok(report);
+    }
+
+    fn load_for_scan(
+        &mut self,
+        buffer: u8,
+        index: u16,
+    ) -> Result<(), LibraryError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(
+            self.send_command(Command::LoadChar { buffer, index }),
+            Reply::LoadChar
+        )?;
+        return match result.confirmation_code {
+            LoadCharStatus::Success => Ok(()),
+            status => Err(LibraryError::LoadChar { index, status }),
+        };
+    }
+
+    /// Returns the library index to store the next template at, based on the _index table_,
+    /// or `None` if the library is full. This is the lowest index which does not hold a
+    /// template, unless [`set_slot_allocation`](#method.set_slot_allocation) says otherwise;
+    /// reserved slots are never returned. Needs no I/O if the _index table_ is cached, apart
+    /// from reading the cursor of `AllocationStrategy::Rotating`.
+    ///
+    /// Where the slots have to be probed, the lowest free slot is found by probing only up to
+    /// it.
+    pub fn next_free_slot(
+        &mut self,
+    ) -> Result<Option<u16>, LibraryError<T::WriteError, T::ReadError>> {
+        let allocation = self.allocation;
+        let rotating = allocation.strategy != AllocationStrategy::LowestFree;
+        if rotating || self.index_cache.table().is_some() {
+            let cursor = self.allocation_cursor().map_err(LibraryError::Notepad)?;
+            return Ok(allocation.pick(&self.read_index_table()?, cursor));
+        }
+        let capacity = self.library_capacity()?;
+        let mut page_start = 0u16;
+        while page_start < capacity {
+            let page = match self.read_index_page_or_probe(page_start)? {
+                Some(page) => page,
+                None => {
+                    for index in (0..capacity).filter(|index| !allocation.is_reserved(*index)) {
+                        if !self.probe_slot(index)? {
+                            return Ok(Some(index));
+                        }
+                    }
+                    return Ok(None);
+                }
+            };
+            let slots = core::cmp::min(capacity - page_start, INDEX_TABLE_PAGE_SIZE);
+            for slot in 0..slots {
+                if !page.is_occupied(slot as u8) && !allocation.is_reserved(page_start + slot) {
+                    return Ok(Some(page_start + slot));
+                }
+            }
+            page_start += INDEX_TABLE_PAGE_SIZE;
+        }
+        return Ok(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::emulator::Emulator;
+    use std::vec;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_is_slot_occupied() {
+        // given: a module with templates at indices 1 and 300
+        let emulator = Emulator::with_geometry(1000, 2);
+        emulator.enroll(1, 7);
+        emulator.enroll(300, 8);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // then: the index table reflects that, across pages
+        assert_eq!(r502.is_slot_occupied(0).unwrap(), false);
+        assert_eq!(r502.is_slot_occupied(1).unwrap(), true);
+        assert_eq!(r502.is_slot_occupied(299).unwrap(), false);
+        assert_eq!(r502.is_slot_occupied(300).unwrap(), true);
+    }
+
+    #[test]
+    fn test_template_count_diverges_from_next_free_slot() {
+        // given: a module with templates at indices 0, 1 and 2
+        let emulator = Emulator::new();
+        emulator.enroll(0, 7);
+        emulator.enroll(1, 8);
+        emulator.enroll(2, 9);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // and: the template in the middle was deleted
+        r502.send_command(Command::DeletChar { start_index: 1, num_to_delete: 1 })
+            .unwrap();
+
+        // when: asking for the template count and the next free slot
+        let count = r502.template_count().unwrap();
+        let free = r502.next_free_slot().unwrap();
+
+        // then: the count is 2, which would overwrite index 2 if used as an index
+        assert_eq!(count, 2);
+        assert_eq!(emulator.slot(2).is_some(), true);
+
+        // and: the next free slot is the gap at index 1
+        assert_eq!(free, Some(1));
+    }
+
+    #[test]
+    fn test_next_free_slot_full_library() {
+        // given: a module with a full library
+        let emulator = Emulator::with_geometry(3, 2);
+        emulator.enroll(0, 7);
+        emulator.enroll(1, 8);
+        emulator.enroll(2, 9);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: asking for the next free slot
+        let free = r502.next_free_slot().unwrap();
+
+        // then: there is none
+        assert_eq!(free, None);
+    }
+
+    #[test]
+    fn test_next_free_slot_high_capacity() {
+        // given: a 1500-slot module with its first 1300 slots taken
+        let emulator = Emulator::with_geometry(1500, 2);
+        for index in 0..1300 {
+            emulator.enroll(index, 7);
+        }
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: asking for the next free slot
+        let free = r502.next_free_slot().unwrap();
+
+        // then: it is found on the sixth page of the index table
+        assert_eq!(free, Some(1300));
+        assert_eq!(emulator.instructions(), [0x0f, 0x1f, 0x1f, 0x1f, 0x1f, 0x1f, 0x1f]);
+    }
+
+    #[test]
+    fn test_index_table_stats() {
+        // given: crafted tables for an empty, a full and a fragmented 1500-slot library
+        let (empty, _) = IndexTable::from_indices(&[], 1500);
+        let all: Vec<u16> = (0..200).collect();
+        let (full, _) = IndexTable::from_indices(&all, 200);
+        let (fragmented, _) = IndexTable::from_indices(&[0, 1, 5, 6, 20, 1499], 1500);
+
+        // then: the figures add up
+        assert_eq!(
+            empty.stats(),
+            LibraryStats {
+                capacity: 1500,
+                used: 0,
+                free: 1500,
+                largest_free_start: 0,
+                largest_free_run: 1500,
+            }
+        );
+        assert_eq!(
+            full.stats(),
+            LibraryStats {
+                capacity: 200,
+                used: 200,
+                free: 0,
+                largest_free_start: 0,
+                largest_free_run: 0,
+            }
+        );
+        assert_eq!(
+            fragmented.stats(),
+            LibraryStats {
+                capacity: 1500,
+                used: 6,
+                free: 1494,
+                largest_free_start: 21,
+                largest_free_run: 1478,
+            }
+        );
+    }
+
+    #[test]
+    fn test_library_stats() {
+        // given: a 200-slot module with a gap of 3 after slot 0, and 150 slots used after it
+        let emulator = Emulator::new();
+        emulator.enroll(0, 7);
+        for slot in 4..154 {
+            emulator.enroll(slot, 8);
+        }
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: asking for the library stats
+        let stats = r502.library_stats().unwrap();
+
+        // then: the longest free run is at the end
+        assert_eq!(stats.capacity, 200);
+        assert_eq!(stats.used, 151);
+        assert_eq!(stats.free, 49);
+        assert_eq!(stats.largest_free_start, 154);
+        assert_eq!(stats.largest_free_run, 46);
+    }
+
+    #[test]
+    fn test_library_stats_large_module() {
+        // given: a 1500-slot module with templates either side of an index table page boundary
+        let emulator = Emulator::with_geometry(1500, 2);
+        emulator.enroll(255, 7);
+        emulator.enroll(256, 8);
+        emulator.enroll(1024, 9);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: asking for the library stats
+        let stats = r502.library_stats().unwrap();
+
+        // then: every index table page was taken into account
+        assert_eq!(stats.capacity, 1500);
+        assert_eq!(stats.used, 3);
+        assert_eq!(stats.free, 1497);
+        assert_eq!(stats.largest_free_start, 257);
+        assert_eq!(stats.largest_free_run, 767);
+        assert_eq!(emulator.instructions(), [0x0f, 0x1f, 0x1f, 0x1f, 0x1f, 0x1f, 0x1f]);
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        // given: a module with finger 7 enrolled at both index 3 and index 10
+        let emulator = Emulator::new();
+        emulator.enroll(1, 6);
+        emulator.enroll(3, 7);
+        emulator.enroll(5, 8);
+        emulator.enroll(10, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: scanning for duplicates
+        let mut progress = Vec::new();
+        let report = r502
+            .find_duplicates(100, |scan| {
+                progress.push(scan);
+                LoopControl::Continue
+            })
+            .unwrap();
+
+        // then: the planted pair is found
+        assert_eq!(report.completed, true);
+        assert_eq!(&report.pairs[..], &[DuplicatePair { first: 3, second: 10, score: 200 }]);
+
+        // and: every pair of the four templates was compared once
+        assert_eq!(progress.len(), 6);
+        assert_eq!(progress.last(), Some(&ScanProgress { compared: 6, total: 6 }));
+    }
+
+    #[test]
+    fn test_find_duplicates_clean_library() {
+        // given: a module with three different fingers enrolled
+        let emulator = Emulator::new();
+        emulator.enroll(0, 6);
+        emulator.enroll(1, 7);
+        emulator.enroll(2, 8);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: scanning for duplicates
+        let report = r502.find_duplicates(100, |_| LoopControl::Continue).unwrap();
+
+        // then: nothing is found
+        assert_eq!(report.completed, true);
+        assert_eq!(report.pairs.is_empty(), true);
+    }
+
+    #[test]
+    fn test_find_duplicates_below_threshold() {
+        // given: a module with a weakly matching duplicate pair
+        let emulator = Emulator::new();
+        emulator.enroll(0, 7);
+        emulator.enroll(1, 7);
+        emulator.state().match_score = 40;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: scanning with a threshold above that score
+        let report = r502.find_duplicates(50, |_| LoopControl::Continue).unwrap();
+
+        // then: the pair is not reported
+        assert_eq!(report.pairs.is_empty(), true);
+    }
+
+    #[test]
+    fn test_find_duplicates_stopped_early() {
+        // given: a module with four templates
+        let emulator = Emulator::new();
+        for index in 0..4 {
+            emulator.enroll(index, 7);
+        }
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: stopping the scan after two comparisons
+        let report = r502
+            .find_duplicates(100, |scan| {
+                if scan.compared == 2 {
+                    LoopControl::Stop
+                } else {
+                    LoopControl::Continue
+                }
+            })
+            .unwrap();
+
+        // then: only the pairs found so far are reported
+        assert_eq!(report.completed, false);
+        assert_eq!(report.pairs.len(), 2);
+        assert_eq!(emulator.instructions().iter().filter(|i| **i == 0x03).count(), 2);
+    }
+
+    #[test]
+    fn test_index_cache_off_by_default() {
+        // given: a driver with the default settings
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: reading the index table twice
+        r502.read_index_table().unwrap();
+        r502.read_index_table().unwrap();
+
+        // then: it was read from the module both times
+        assert_eq!(r502.cached_index_table().is_none(), true);
+        assert_eq!(emulator.instructions(), [0x0f, 0x1f, 0x0f, 0x1f]);
+    }
+
+    #[test]
+    fn test_index_cache_warm() {
+        // given: a module with templates at indices 0, 1 and 300, and the cache on
+        let emulator = Emulator::with_geometry(1000, 2);
+        emulator.enroll(0, 7);
+        emulator.enroll(1, 7);
+        emulator.enroll(300, 8);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.enable_index_cache(true);
+
+        // when: the index table has been read once
+        r502.read_index_table().unwrap();
+        let sent = emulator.instructions().len();
+
+        // then: the helpers answer without talking to the module
+        assert_eq!(r502.is_slot_occupied(300).unwrap(), true);
+        assert_eq!(r502.is_slot_occupied(299).unwrap(), false);
+        assert_eq!(r502.next_free_slot().unwrap(), Some(2));
+        assert_eq!(r502.library_stats().unwrap().used, 3);
+        assert_eq!(emulator.instructions().len(), sent);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_index_cache_follows_mutations() {
+        // given: a module with templates at indices 1 and 2, and a warm cache
+        let emulator = Emulator::new();
+        emulator.enroll(1, 7);
+        emulator.enroll(2, 8);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.enable_index_cache(true);
+        r502.read_index_table().unwrap();
+        let occupied = |r502: &R502<_>| -> Vec<u16> {
+            return r502.cached_index_table().unwrap().occupied().collect();
+        };
+
+        // when: storing a template at index 5
+        r502.send_command(Command::LoadChar { buffer: 1, index: 1 }).unwrap();
+        r502.send_command(Command::Store { buffer: 1, index: 5 }).unwrap();
+
+        // then: the cache has it
+        assert_eq!(occupied(&r502), [1, 2, 5]);
+
+        // when: deleting indices 1 and 2
+        r502.send_command(Command::DeletChar { start_index: 1, num_to_delete: 2 }).unwrap();
+
+        // then: the cache has them free
+        assert_eq!(occupied(&r502), [5]);
+
+        // when: emptying the library
+        r502.send_command(Command::Empty).unwrap();
+
+        // then: the cache is still warm, and empty like the library
+        assert_eq!(occupied(&r502).is_empty(), true);
+        assert_eq!(emulator.slot(5), None);
+        assert_eq!(emulator.instructions().iter().filter(|i| **i == 0x1f).count(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_index_cache_invalidation() {
+        // given: a module with the cache on
+        let emulator = Emulator::new();
+        emulator.state().boot_output.clear();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.enable_index_cache(true);
+
+        // when: a Store fails
+        r502.read_index_table().unwrap();
+        emulator.fail_next(0x06, 0x18);
+        r502.send_command(Command::Store { buffer: 1, index: 5 }).unwrap();
+
+        // then: the cache is dropped
+        assert_eq!(r502.cached_index_table().is_none(), true);
+
+        // when: the module restarts
+        r502.read_index_table().unwrap();
+        r502.send_command(Command::SoftRst).unwrap();
+
+        // then: the cache is dropped
+        assert_eq!(r502.cached_index_table().is_none(), true);
+
+        // when: it is invalidated by hand
+        r502.read_index_table().unwrap();
+        r502.invalidate_index_cache();
+
+        // then: the cache is dropped
+        assert_eq!(r502.cached_index_table().is_none(), true);
+
+        // when: the driver moves to another address
+        r502.read_index_table().unwrap();
+        r502.set_address(0x0000abcd);
+
+        // then: the cache is dropped
+        assert_eq!(r502.cached_index_table().is_none(), true);
+
+        // and: reads other than the index table leave it alone
+        r502.set_address(0xffffffff);
+        r502.read_index_table().unwrap();
+        r502.template_count().unwrap();
+        assert_eq!(r502.cached_index_table().is_some(), true);
+    }
+
+    /// A 20-slot module whose firmware does not know `ReadIndexTable`.
+    fn old_firmware() -> Emulator {
+        let emulator = Emulator::with_geometry(20, 2);
+        emulator.state().unsupported = vec![0x1f];
+        return emulator;
+    }
+
+    #[test]
+    fn test_index_table_probed_on_old_firmware() {
+        // given: an old module with templates at indices 1 and 4
+        let emulator = old_firmware();
+        emulator.enroll(1, 7);
+        emulator.enroll(4, 8);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: reading the index table
+        let table = r502.read_index_table().unwrap();
+
+        // then: it was found by loading every slot
+        assert_eq!(table.occupied().collect::<Vec<_>>(), [1, 4]);
+        assert_eq!(r502.index_table_source(), Some(IndexTableSource::Probing));
+        let instructions = emulator.instructions();
+        assert_eq!(instructions[..2], [0x0f, 0x1f]);
+        assert_eq!(instructions.iter().filter(|i| **i == 0x07).count(), 20);
+
+        // and: it was cached, even though the cache was not turned on
+        let sent = emulator.instructions().len();
+        assert_eq!(r502.read_index_table().unwrap().occupied().count(), 2);
+        assert_eq!(r502.is_slot_occupied(4).unwrap(), true);
+        assert_eq!(emulator.instructions().len(), sent);
+
+        // when: a template is deleted
+        r502.send_command(Command::DeletChar { start_index: 1, num_to_delete: 1 }).unwrap();
+
+        // then: the cache follows
+        assert_eq!(r502.next_free_slot().unwrap(), Some(0));
+        assert_eq!(r502.is_slot_occupied(1).unwrap(), false);
+        assert_eq!(emulator.instructions().len(), sent + 1);
+    }
+
+    #[test]
+    fn test_next_free_slot_probes_up_to_first_free() {
+        // given: an old module with its first three slots taken
+        let emulator = old_firmware();
+        for index in 0..3 {
+            emulator.enroll(index, 7);
+        }
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: looking for a free slot
+        let free = r502.next_free_slot().unwrap();
+
+        // then: only the slots up to the first free one were probed
+        assert_eq!(free, Some(3));
+        assert_eq!(emulator.instructions(), [0x0f, 0x1f, 0x07, 0x07, 0x07, 0x07]);
+
+        // and: the path is remembered, so ReadIndexTable is not tried again
+        assert_eq!(r502.is_slot_occupied(2).unwrap(), true);
+        assert_eq!(emulator.instructions().last(), Some(&0x07));
+        assert_eq!(emulator.instructions().iter().filter(|i| **i == 0x1f).count(), 1);
+    }
+
+    #[test]
+    fn test_index_table_source_on_new_firmware() {
+        // given: a module which knows ReadIndexTable
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        assert_eq!(r502.index_table_source(), None);
+
+        // when: reading the index table
+        r502.read_index_table().unwrap();
+
+        // then: the command is used, and nothing is cached without being asked to
+        assert_eq!(r502.index_table_source(), Some(IndexTableSource::ReadIndexTable));
+        assert_eq!(r502.cached_index_table().is_none(), true);
+        assert_eq!(emulator.instructions().contains(&0x07), false);
+
+        // and: moving to another address forgets the path
+        r502.set_address(0x0000abcd);
+        assert_eq!(r502.index_table_source(), None);
+    }
+}
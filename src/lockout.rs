@@ -0,0 +1,252 @@
+
+use crate::clock::Clock;
+use crate::driver::R502;
+use crate::identify::{IdentifyConfig, IdentifyError, VerifyError};
+use crate::transport::Transport;
+
+/// When `Lockout` stops accepting fingers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    /// Consecutive failed attempts which trigger the lockout. Default: 5
+    pub max_failures: u8,
+
+    /// How long the lockout lasts. Default: 30 seconds
+    pub cooldown_ms: u32,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        return Self {
+            max_failures: 5,
+            cooldown_ms: 30_000,
+        };
+    }
+}
+
+/// Error type for the helpers wrapped by `Lockout`.
+#[derive(Debug)]
+pub enum LockoutError<E> {
+    /// Too many attempts failed in a row. Nothing was sent to the module; try again once the
+    /// cooldown is over.
+    LockedOut {
+        /// Milliseconds left until the cooldown is over.
+        remaining_ms: u32,
+    },
+
+    /// The attempt itself failed.
+    Attempt(E),
+}
+
+/// Result of the helpers wrapped by `Lockout`.
+pub type LockoutResult<T, E> = Result<T, LockoutError<E>>;
+
+/// Counts consecutive failed attempts to identify or verify a finger, and refuses further
+/// attempts for a while once there are too many, as access control guidelines often ask.
+///
+/// Only a finger which is read but not recognised counts as a failed attempt. An empty
+/// sensor or a communication problem neither counts nor resets the count; a recognised
+/// finger resets it.
+///
+/// The count lives in RAM, so it starts over when the device restarts.
+#[derive(Debug, Clone)]
+pub struct Lockout {
+    policy: LockoutPolicy,
+    failures: u8,
+    locked_at: Option<u32>,
+}
+
+impl Lockout {
+    /// A lockout following `policy`, with no failed attempts so far.
+    pub fn new(policy: LockoutPolicy) -> Self {
+        return Self {
+            policy,
+            failures: 0,
+            locked_at: None,
+        };
+    }
+
+    /// Failed attempts in a row so far, for example to show how many are left.
+    pub fn failures(&self) -> u8 {
+        return self.failures;
+    }
+
+    /// Milliseconds left of the current lockout, or `None` if attempts are allowed. Ends the
+    /// lockout and resets the count once the cooldown is over.
+    pub fn remaining_ms<C: Clock>(&mut self, clock: &mut C) -> Option<u32> {
+        let locked_at = self.locked_at?;
+        let elapsed = clock.now_ms().wrapping_sub(locked_at);
+        if elapsed >= self.policy.cooldown_ms {
+            self.locked_at = None;
+            self.failures = 0;
+            return None;
+        }
+        return Some(self.policy.cooldown_ms - elapsed);
+    }
+
+    /// Counts a failed attempt made outside of the wrapped helpers, starting the lockout if
+    /// it was one too many.
+    pub fn record_failure<C: Clock>(&mut self, clock: &mut C) {
+        self.failures = self.failures.saturating_add(1);
+        if self.failures >= self.policy.max_failures && self.locked_at.is_none() {
+            self.locked_at = Some(clock.now_ms());
+        }
+    }
+
+    /// Counts a successful attempt made outside of the wrapped helpers, resetting the count.
+    pub fn record_success(&mut self) {
+        self.failures = 0;
+        self.locked_at = None;
+    }
+
+    /// [`R502::identify`](struct.R502.html#method.identify), unless locked out.
+    pub fn identify<T, C>(
+        &mut self,
+        r502: &mut R502<T>,
+        config: &IdentifyConfig,
+        clock: &mut C,
+    ) -> LockoutResult<(u16, u16), IdentifyError<T::WriteError, T::ReadError>>
+    where
+        T: Transport,
+        C: Clock,
+    {
+        self.check(clock)?;
+        let result = r502.identify(config);
+        match result {
+            Ok(_) => self.record_success(),
+            Err(IdentifyError::NoMatch) | Err(IdentifyError::BelowThreshold { .. }) => {
+                self.record_failure(clock)
+            }
+            Err(_) => {}
+        }
+        return result.map_err(LockoutError::Attempt);
+    }
+
+    /// [`R502::verify`](struct.R502.html#method.verify), unless locked out.
+    pub fn verify<T, C>(
+        &mut self,
+        r502: &mut R502<T>,
+        index: u16,
+        min_score: Option<u16>,
+        clock: &mut C,
+    ) -> LockoutResult<u16, VerifyError<T::WriteError, T::ReadError>>
+    where
+        T: Transport,
+        C: Clock,
+    {
+        self.check(clock)?;
+        let result = r502.verify(index, min_score);
+        match result {
+            Ok(_) => self.record_success(),
+            Err(VerifyError::NotMatched { .. }) => self.record_failure(clock),
+            Err(_) => {}
+        }
+        return result.map_err(LockoutError::Attempt);
+    }
+
+    fn check<E, C: Clock>(&mut self, clock: &mut C) -> Result<(), LockoutError<E>> {
+        return match self.remaining_ms(clock) {
+            Some(remaining_ms) => Err(LockoutError::LockedOut { remaining_ms }),
+            None => Ok(()),
+        };
+    }
+}
+
+impl Default for Lockout {
+    fn default() -> Self {
+        return Self::new(LockoutPolicy::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+    use core::cell::Cell;
+
+    #[test]
+    fn test_lockout_after_failures() {
+        // given: a user whose finger 7 is enrolled, and an intruder trying finger 8
+        let emulator = Emulator::new();
+        emulator.enroll(0, 7);
+        emulator.touch(&[Some(8), Some(8), Some(8), Some(7)]);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        let time = Cell::new(1_000u32);
+        let mut clock = || time.get();
+        let policy = LockoutPolicy { max_failures: 3, cooldown_ms: 10_000 };
+        let mut lockout = Lockout::new(policy);
+        let config = IdentifyConfig::default();
+
+        // when: the intruder fails three times
+        for failures in 1..=3 {
+            match lockout.identify(&mut r502, &config, &mut clock) {
+                Err(LockoutError::Attempt(IdentifyError::NoMatch)) => {}
+                other => panic!("Expected IdentifyError::NoMatch, got {:?}", other),
+            };
+            assert_eq!(lockout.failures(), failures);
+        }
+
+        // then: the next attempt is refused without talking to the module
+        let sent = emulator.instructions().len();
+        time.set(4_000);
+        match lockout.identify(&mut r502, &config, &mut clock) {
+            Err(LockoutError::LockedOut { remaining_ms: 7_000 }) => {}
+            other => panic!("Expected LockoutError::LockedOut, got {:?}", other),
+        };
+        assert_eq!(emulator.instructions().len(), sent);
+
+        // when: the cooldown runs out
+        time.set(11_000);
+
+        // then: the count is reset, and the user gets in
+        assert_eq!(lockout.remaining_ms(&mut clock), None);
+        assert_eq!(lockout.failures(), 0);
+        assert_eq!(lockout.identify(&mut r502, &config, &mut clock).unwrap().0, 0);
+    }
+
+    #[test]
+    fn test_lockout_reset_on_success() {
+        // given: a user who gets their finger wrong twice, then right
+        let emulator = Emulator::new();
+        emulator.enroll(4, 7);
+        emulator.touch(&[Some(8), Some(8), Some(7), Some(8), None]);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        let mut clock = || 0u32;
+        let mut lockout = Lockout::new(LockoutPolicy { max_failures: 3, cooldown_ms: 10_000 });
+
+        // when: verifying each attempt against slot 4
+        for _ in 0..2 {
+            assert_eq!(lockout.verify(&mut r502, 4, None, &mut clock).is_err(), true);
+        }
+        assert_eq!(lockout.failures(), 2);
+        assert_eq!(lockout.verify(&mut r502, 4, None, &mut clock).is_ok(), true);
+
+        // then: the success reset the count
+        assert_eq!(lockout.failures(), 0);
+
+        // and: a failure counts again, but an empty sensor does not
+        assert_eq!(lockout.verify(&mut r502, 4, None, &mut clock).is_err(), true);
+        match lockout.verify(&mut r502, 4, None, &mut clock) {
+            Err(LockoutError::Attempt(VerifyError::NoFinger)) => {}
+            other => panic!("Expected VerifyError::NoFinger, got {:?}", other),
+        };
+        assert_eq!(lockout.failures(), 1);
+        assert_eq!(lockout.remaining_ms(&mut clock), None);
+    }
+
+    #[test]
+    fn test_lockout_clock_wraps() {
+        // given: a lockout which starts just before the clock wraps around
+        let time = Cell::new(u32::MAX - 1_000);
+        let mut clock = || time.get();
+        let mut lockout = Lockout::new(LockoutPolicy { max_failures: 1, cooldown_ms: 5_000 });
+        lockout.record_failure(&mut clock);
+
+        // when: the clock has wrapped, but the cooldown is not over
+        time.set(1_000);
+
+        // then: the lockout still holds
+        assert_eq!(lockout.remaining_ms(&mut clock), Some(2_999));
+    }
+}
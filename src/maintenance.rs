@@ -0,0 +1,761 @@
+use arrayvec::ArrayVec;
+
+use crate::audit::{AuditError, AuditOperation};
+use crate::commands::Command;
+use crate::driver::R502;
+use crate::library::{IndexTable, LibraryError};
+use crate::responses::*;
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// A template moved by `defragment_library`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotMove {
+    pub from: u16,
+    pub to: u16,
+}
+
+/// Error type for `defragment_library`. Moves reported before the error are complete.
+#[derive(Debug)]
+pub enum DefragError<TXE, RXE> {
+    /// The _index table_ could not be read. Nothing was changed.
+    Library(LibraryError<TXE, RXE>),
+
+    /// Communication with the R502 failed before the template was copied.
+    Comms(Error<TXE, RXE>),
+
+    /// The template at `from` could not be loaded.
+    Load { from: u16, status: LoadCharStatus },
+
+    /// The template could not be stored at `to`. It is still at `from`.
+    Store {
+        from: u16,
+        to: u16,
+        status: StoreStatus,
+    },
+
+    /// The template was copied to `to`, but could not be deleted from `from`. Both slots hold
+    /// it; delete either one to finish the move.
+    Delete {
+        from: u16,
+        to: u16,
+        status: DeletCharStatus,
+    },
+
+    /// Communication failed after the template was sent to be stored at `to`. It is still at
+    /// `from`, and may also be at `to`.
+    Interrupted {
+        from: u16,
+        to: u16,
+        error: Error<TXE, RXE>,
+    },
+
+    /// The template was stored at `to`, and may also have been deleted from `from`, but the
+    /// record of it could not be added to the `AuditLog`.
+    Audit {
+        from: u16,
+        to: u16,
+        error: AuditError<TXE, RXE>,
+    },
+}
+
+impl<TXE, RXE> DefragError<TXE, RXE> {
+    /// The move left unfinished, which may have left the template in both slots, for
+    /// `R502::resume_defragment`. `None` for errors which left the library as it was.
+    pub fn unfinished_move(&self) -> Option<SlotMove> {
+        return match *self {
+            Self::Delete { from, to, .. }
+            | Self::Interrupted { from, to, .. }
+            | Self::Audit { from, to, .. } => {
+                Some(SlotMove { from, to })
+            }
+            _ => None,
+        };
+    }
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for DefragError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+/// Most ranges `delete_indices` reports individually.
+pub const MAX_DELETE_RANGES: usize = 32;
+
+/// One `DeletChar` call made by `delete_indices`, and how it went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeleteRange {
+    pub start: u16,
+    pub count: u16,
+    pub status: DeletCharStatus,
+}
+
+/// Result of `delete_indices`.
+#[derive(Debug, Clone)]
+pub struct DeleteReport {
+    /// The ranges deleted, lowest first, up to `MAX_DELETE_RANGES`.
+    pub ranges: ArrayVec<DeleteRange, MAX_DELETE_RANGES>,
+
+    /// More ranges were deleted than fit into `ranges`. The counts below still cover all of them.
+    pub overflowed: bool,
+
+    /// Slots the R502 reported as deleted.
+    pub deleted: u16,
+
+    /// Slots in ranges the R502 failed to delete.
+    pub failed: u16,
+
+    /// Indices past the library capacity, which were left out.
+    pub out_of_range: u16,
+}
+
+impl DeleteReport {
+    /// True if every requested index was deleted.
+    pub fn is_complete(&self) -> bool {
+        return self.failed == 0 && self.out_of_range == 0;
+    }
+}
+
+/// Error type for `delete_indices`. Ranges deleted before the error stay deleted.
+#[derive(Debug)]
+pub enum DeleteError<TXE, RXE> {
+    /// The library capacity could not be read. Nothing was deleted.
+    Library(LibraryError<TXE, RXE>),
+
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// The run of `count` templates from `start` was deleted, but the record of it could not
+    /// be added to the `AuditLog`. Runs after it were not deleted.
+    Audit {
+        start: u16,
+        count: u16,
+        error: AuditError<TXE, RXE>,
+    },
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for DeleteError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+/// Error type for `store_and_verify`.
+#[derive(Debug)]
+pub enum StoreVerifyError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// Only _character buffers_ 1 and 2 can be stored and verified.
+    InvalidBuffer(u8),
+
+    /// The R502 refused to store the template.
+    Store(StoreStatus),
+
+    /// The stored template could not be read back.
+    ReadBack(LoadCharStatus),
+
+    /// The stored template was read back, but does not match the original.
+    Mismatch,
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for StoreVerifyError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Compacts the library by moving templates from the highest occupied slots into the
+    /// lowest free ones, until there are no holes left. `on_move` is told about every move,
+    /// so the host can update which user is in which slot. Returns the number of moves.
+    ///
+    /// Each move loads the template with `LoadChar`, stores it at the new slot with `Store`,
+    /// and only then deletes the old slot with `DeletChar`. If the process is interrupted, the
+    /// template is therefore never lost, though it may be left in both slots; see
+    /// `DefragError` for which. To carry on after such an error, pass
+    /// `DefragError::unfinished_move` to [`resume_defragment`](#method.resume_defragment).
+    /// Running this again instead would move the copy left behind into another hole, leaving
+    /// the finger enrolled twice.
+    ///
+    /// If an `AuditLog` is being kept, the store and the delete of each move are recorded in it.
+    ///
+    /// **Note:** This overwrites the contents of _character buffer_ 1.
+    #[cfg(feature = "cmd-enroll")]
+    pub fn defragment_library<F>(
+        &mut self,
+        mut on_move: F,
+    ) -> Result<u16, DefragError<T::WriteError, T::ReadError>>
+    where
+        F: FnMut(SlotMove),
+    {
+        let mut table = self.read_index_table().map_err(DefragError::Library)?;
+        let mut moves = 0;
+
+        loop {
+            let to = match table.first_free() {
+                Some(to) => to,
+                None => return Ok(moves),
+            };
+            let from = match table.occupied().last() {
+                Some(from) if from > to => from,
+                _ => return Ok(moves),
+            };
+
+            self.move_template(from, to)?;
+            table.set_occupied(to, true);
+            table.set_occupied(from, false);
+            moves += 1;
+            on_move(SlotMove { from, to });
+        }
+    }
+
+    /// Finishes `unfinished`, a move `defragment_library` failed to complete, then carries on
+    /// compacting the library. Returns the number of moves, counting `unfinished` if it is
+    /// completed here.
+    ///
+    /// The move is finished by deleting its source slot, but only if the module matches the
+    /// templates at both ends, so a copy is never deleted unless the other one is intact. If
+    /// the source is already gone, or the target does not hold the template, nothing is
+    /// deleted and the slots are left to the usual compaction.
+    ///
+    /// **Note:** This overwrites the contents of _character buffers_ 1 and 2.
+    #[cfg(feature = "cmd-enroll")]
+    pub fn resume_defragment<F>(
+        &mut self,
+        unfinished: SlotMove,
+        mut on_move: F,
+    ) -> Result<u16, DefragError<T::WriteError, T::ReadError>>
+    where
+        F: FnMut(SlotMove),
+    {
+        let SlotMove { from, to } = unfinished;
+        let mut moves = 0;
+        if self.slots_match(from, to)? {
+            self.delete_moved(from, to)?;
+            moves += 1;
+            on_move(unfinished);
+        }
+        return Ok(moves + self.defragment_library(&mut on_move)?);
+    }
+
+    /// Deletes the templates at `indices`, which may be in any order and contain repeats.
+    ///
+    /// Runs of consecutive indices are deleted with a single `DeletChar` each. A range the R502
+    /// fails to delete is recorded in the report and the remaining ranges are still deleted.
+    /// Indices past the library capacity reported by `ReadSysPara` are counted, but not sent.
+    /// If an `AuditLog` is being kept, each range deleted is recorded in it.
+    pub fn delete_indices(
+        &mut self,
+        indices: &[u16],
+    ) -> Result<DeleteReport, DeleteError<T::WriteError, T::ReadError>> {
+        let mut report = DeleteReport {
+            ranges: ArrayVec::new(),
+            overflowed: false,
+            deleted: 0,
+            failed: 0,
+            out_of_range: 0,
+        };
+        if indices.is_empty() {
+            return Ok(report);
+        }
+
+        let capacity = self.library_capacity().map_err(DeleteError::Library)?;
+        let (table, out_of_range) = IndexTable::from_indices(indices, capacity);
+        report.out_of_range = out_of_range;
+
+        for (start, count) in table.occupied_runs() {
+            let result = expect_reply!(
+                self.send_command(Command::DeletChar { start_index: start, num_to_delete: count }),
+                Reply::DeletChar
+            )?;
+            let status = result.confirmation_code;
+            if report.ranges.try_push(DeleteRange { start, count, status }).is_err() {
+                report.overflowed = true;
+            }
+            match status {
+                DeletCharStatus::Success => {
+                    report.deleted += count;
+                    self.record_audit(AuditOperation::Deleted, start, count)
+                        .map_err(|error| DeleteError::Audit { start, count, error })?;
+                }
+                _ => report.failed += count,
+            }
+        }
+
+        return Ok(report);
+    }
+
+    /// Stores the template in _character buffer_ `buffer` at `index`, then checks the flash
+    /// write by loading the slot back into the other buffer and matching the two. If `retry`
+    /// is set and the check fails, the template is stored and checked once more.
+    ///
+    /// **Note:** This overwrites the contents of the other _character buffer_.
+    #[cfg(feature = "cmd-enroll")]
+    pub fn store_and_verify(
+        &mut self,
+        buffer: u8,
+        index: u16,
+        retry: bool,
+    ) -> Result<(), StoreVerifyError<T::WriteError, T::ReadError>> {
+        let other = match buffer {
+            1 => 2,
+            2 => 1,
+            _ => return Err(StoreVerifyError::InvalidBuffer(buffer)),
+        };
+
+        let result = self.store_and_check(buffer, other, index);
+        return match result {
+            Err(StoreVerifyError::ReadBack(_)) | Err(StoreVerifyError::Mismatch) if retry => {
+                self.stats.count_retry();
+                self.store_and_check(buffer, other, index)
+            }
+            result => result,
+        };
+    }
+
+    #[cfg(feature = "cmd-enroll")]
+    fn store_and_check(
+        &mut self,
+        buffer: u8,
+        other: u8,
+        index: u16,
+    ) -> Result<(), StoreVerifyError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(
+            self.send_command(Command::Store { buffer, index }),
+            Reply::Store
+        )?;
+        match result.confirmation_code {
+            StoreStatus::Success => {}
+            status => return Err(StoreVerifyError::Store(status)),
+        }
+
+        let result = expect_reply!(
+            self.send_command(Command::LoadChar { buffer: other, index }),
+            Reply::LoadChar
+        )?;
+        match result.confirmation_code {
+            LoadCharStatus::Success => {}
+            status => return Err(StoreVerifyError::ReadBack(status)),
+        }
+
+        let result = expect_reply!(self.send_command(Command::Match), Reply::Match)?;
+        return match result.confirmation_code {
+            MatchStatus::Success => Ok(()),
+            _ => Err(StoreVerifyError::Mismatch),
+        };
+    }
+
+    #[cfg(feature = "cmd-enroll")]
+    fn move_template(
+        &mut self,
+        from: u16,
+        to: u16,
+    ) -> Result<(), DefragError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(
+            self.send_command(Command::LoadChar { buffer: 1, index: from }),
+            Reply::LoadChar
+        )?;
+        match result.confirmation_code {
+            LoadCharStatus::Success => {}
+            status => return Err(DefragError::Load { from, status }),
+        }
+
+        let result = expect_reply!(
+            self.send_command(Command::Store { buffer: 1, index: to }),
+            Reply::Store
+        )
+        .map_err(|error| DefragError::Interrupted { from, to, error })?;
+        match result.confirmation_code {
+            StoreStatus::Success => {}
+            status => return Err(DefragError::Store { from, to, status }),
+        }
+        self.record_audit(AuditOperation::Stored, to, 1)
+            .map_err(|error| DefragError::Audit { from, to, error })?;
+        return self.delete_moved(from, to);
+    }
+
+    /// Deletes `from`, the source of a move whose template is already stored at `to`.
+    #[cfg(feature = "cmd-enroll")]
+    fn delete_moved(
+        &mut self,
+        from: u16,
+        to: u16,
+    ) -> Result<(), DefragError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(
+            self.send_command(Command::DeletChar { start_index: from, num_to_delete: 1 }),
+            Reply::DeletChar
+        )
+        .map_err(|error| DefragError::Interrupted { from, to, error })?;
+        match result.confirmation_code {
+            DeletCharStatus::Success => {}
+            status => return Err(DefragError::Delete { from, to, status }),
+        }
+        return self
+            .record_audit(AuditOperation::Deleted, from, 1)
+            .map_err(|error| DefragError::Audit { from, to, error });
+    }
+
+    /// True if the slots `first` and `second` both hold a template, and the module matches
+    /// them. Loads them into _character buffers_ 1 and 2.
+    #[cfg(feature = "cmd-enroll")]
+    fn slots_match(
+        &mut self,
+        first: u16,
+        second: u16,
+    ) -> Result<bool, Error<T::WriteError, T::ReadError>> {
+        for (buffer, index) in [(1, first), (2, second)].iter() {
+            let result = expect_reply!(
+                self.send_command(Command::LoadChar { buffer: *buffer, index: *index }),
+                Reply::LoadChar
+            )?;
+            if !matches!(result.confirmation_code, LoadCharStatus::Success) {
+                return Ok(false);
+            }
+        }
+        let result = expect_reply!(self.send_command(Command::Match), Reply::Match)?;
+        return Ok(matches!(result.confirmation_code, MatchStatus::Success));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    #[cfg(feature = "cmd-enroll")]
+    use crate::emulator::char_file;
+    use crate::emulator::Emulator;
+    use std::vec;
+    use std::vec::Vec;
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_defragment_library() {
+        // given: a module with holes at 0, 2 and 3
+        let emulator = Emulator::new();
+        emulator.enroll(1, 6);
+        emulator.enroll(4, 7);
+        emulator.enroll(8, 8);
+        emulator.enroll(9, 9);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: defragmenting
+        let mut moves = Vec::new();
+        let count = r502.defragment_library(|m| moves.push(m)).unwrap();
+
+        // then: the highest templates fill the lowest holes
+        assert_eq!(count, 3);
+        assert_eq!(
+            moves,
+            vec![
+                SlotMove { from: 9, to: 0 },
+                SlotMove { from: 8, to: 2 },
+                SlotMove { from: 4, to: 3 },
+            ]
+        );
+
+        // and: the library is contiguous, with every template still there
+        assert_eq!(emulator.slot(0), Some(char_file(9)));
+        assert_eq!(emulator.slot(1), Some(char_file(6)));
+        assert_eq!(emulator.slot(2), Some(char_file(8)));
+        assert_eq!(emulator.slot(3), Some(char_file(7)));
+        for index in 4..10 {
+            assert_eq!(emulator.slot(index), None);
+        }
+    }
+
+    fn runs(indices: &[u16], capacity: u16) -> (Vec<(u16, u16)>, u16) {
+        let (table, out_of_range) = IndexTable::from_indices(indices, capacity);
+        return (table.occupied_runs().collect(), out_of_range);
+    }
+
+    #[test]
+    fn test_coalesce_indices() {
+        // then: unordered and repeated indices are merged into contiguous runs
+        assert_eq!(runs(&[5, 3, 4, 9, 3, 10, 0], 200), (vec![(0, 1), (3, 3), (9, 2)], 0));
+
+        // and: runs reaching the end of the library are closed
+        assert_eq!(runs(&[198, 199, 197], 200), (vec![(197, 3)], 0));
+
+        // and: indices past the capacity are counted, not deleted
+        assert_eq!(runs(&[199, 200, 0xffff], 200), (vec![(199, 1)], 2));
+
+        // and: no indices means no runs
+        assert_eq!(runs(&[], 200), (vec![], 0));
+    }
+
+    #[test]
+    fn test_delete_indices() {
+        // given: a module with templates at 0-5 and 8
+        let emulator = Emulator::new();
+        for index in [0, 1, 2, 3, 4, 5, 8].iter() {
+            emulator.enroll(*index, *index as u8);
+        }
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: deleting an unordered set of indices, one of them past the library
+        let report = r502.delete_indices(&[4, 1, 2, 8, 1, 500]).unwrap();
+
+        // then: one DeletChar is sent per run
+        assert_eq!(emulator.instructions(), vec![0x0f, 0x0c, 0x0c, 0x0c]);
+        let ranges: Vec<(u16, u16)> = report.ranges.iter().map(|r| (r.start, r.count)).collect();
+        assert_eq!(ranges, vec![(1, 2), (4, 1), (8, 1)]);
+        assert_eq!(report.deleted, 4);
+        assert_eq!(report.out_of_range, 1);
+        assert_eq!(report.is_complete(), false);
+
+        // and: only those slots were emptied
+        assert_eq!(emulator.slot(0).is_some(), true);
+        assert_eq!(emulator.slot(1), None);
+        assert_eq!(emulator.slot(2), None);
+        assert_eq!(emulator.slot(3).is_some(), true);
+        assert_eq!(emulator.slot(4), None);
+        assert_eq!(emulator.slot(5).is_some(), true);
+        assert_eq!(emulator.slot(8), None);
+    }
+
+    #[test]
+    fn test_delete_indices_high_capacity() {
+        // given: a 1500-slot module with templates either side of slot 255 and in the last slot
+        let emulator = Emulator::with_geometry(1500, 2);
+        for index in [254, 255, 256, 257, 1499].iter() {
+            emulator.enroll(*index, 7);
+        }
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: deleting all but the first of them, and one slot past the library
+        let report = r502.delete_indices(&[1499, 257, 256, 255, 1500]).unwrap();
+
+        // then: the run across slot 255 is deleted in one go, and the last slot on its own
+        let ranges: Vec<(u16, u16)> = report.ranges.iter().map(|r| (r.start, r.count)).collect();
+        assert_eq!(ranges, vec![(255, 3), (1499, 1)]);
+        assert_eq!(report.deleted, 4);
+        assert_eq!(report.out_of_range, 1);
+        assert_eq!(emulator.slot(254).is_some(), true);
+        assert_eq!(emulator.slot(256), None);
+        assert_eq!(emulator.slot(1499), None);
+    }
+
+    #[test]
+    fn test_delete_indices_reports_failed_range() {
+        // given: a module which fails the second delete
+        let emulator = Emulator::new();
+        emulator.fail_after(0x0c, 1, 0x10);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: deleting three runs
+        let report = r502.delete_indices(&[0, 1, 5, 7, 8, 9]).unwrap();
+
+        // then: the failure is recorded and the other runs are still deleted
+        let statuses: Vec<DeletCharStatus> = report.ranges.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            vec![DeletCharStatus::Success, DeletCharStatus::DeleteFailed, DeletCharStatus::Success]
+        );
+        assert_eq!(report.deleted, 5);
+        assert_eq!(report.failed, 1);
+    }
+
+    #[test]
+    fn test_delete_indices_empty() {
+        // given: a module
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: deleting nothing
+        let report = r502.delete_indices(&[]).unwrap();
+
+        // then: nothing is sent
+        assert_eq!(report.is_complete(), true);
+        assert_eq!(emulator.instructions().is_empty(), true);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_store_and_verify() {
+        // given: a module with finger 7 in buffer 1
+        let emulator = Emulator::new();
+        emulator.state().buffers[0] = Some(char_file(7));
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: storing and verifying it
+        let result = r502.store_and_verify(1, 4, false);
+
+        // then: the template is stored, read back into buffer 2 and matched
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(emulator.slot(4), Some(char_file(7)));
+        assert_eq!(emulator.instructions(), vec![0x06, 0x07, 0x03]);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_store_and_verify_retries_corrupt_store() {
+        // given: a module which corrupts the first store
+        let emulator = Emulator::new();
+        emulator.state().buffers[0] = Some(char_file(7));
+        emulator.state().corrupt_stores = 1;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: storing and verifying with a retry
+        let result = r502.store_and_verify(1, 4, true);
+
+        // then: the second store is good
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(emulator.slot(4), Some(char_file(7)));
+        assert_eq!(emulator.instructions(), vec![0x06, 0x07, 0x06, 0x07, 0x03]);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_store_and_verify_reports_corrupt_store() {
+        // given: a module which corrupts the first store
+        let emulator = Emulator::new();
+        emulator.state().buffers[1] = Some(char_file(7));
+        emulator.state().corrupt_stores = 1;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: storing and verifying without a retry
+        let result = r502.store_and_verify(2, 4, false);
+
+        // then: the corrupt slot is reported
+        match result {
+            Err(StoreVerifyError::ReadBack(LoadCharStatus::LibraryReadError)) => {}
+            other => panic!("Expected StoreVerifyError::ReadBack, got {:?}", other),
+        };
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_defragment_compact_library() {
+        // given: a module without holes
+        let emulator = Emulator::new();
+        emulator.enroll(0, 6);
+        emulator.enroll(1, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: defragmenting
+        let count = r502
+            .defragment_library(|_| panic!("Nothing should move"))
+            .unwrap();
+
+        // then: nothing is moved
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_defragment_interrupted_between_store_and_delete() {
+        // given: a module with a hole at 0
+        let emulator = Emulator::new();
+        emulator.enroll(1, 6);
+        emulator.enroll(5, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // and: the link drops after the template has been stored, before it is deleted
+        emulator.lose_next_reply(0x06);
+
+        // when: defragmenting
+        let mut moves = Vec::new();
+        let result = r502.defragment_library(|m| moves.push(m));
+
+        // then: the interrupted move is reported, and was not announced as complete
+        match result {
+            Err(DefragError::Interrupted { from: 5, to: 0, .. }) => {}
+            other => panic!("Expected DefragError::Interrupted, got {:?}", other),
+        };
+        assert_eq!(moves.is_empty(), true);
+
+        // and: the template was not lost
+        assert_eq!(emulator.slot(0), Some(char_file(7)));
+        assert_eq!(emulator.slot(5), Some(char_file(7)));
+        assert_eq!(emulator.instructions().contains(&0x0c), false);
+
+        // when: resuming from the unfinished move
+        let unfinished = result.unwrap_err().unfinished_move().unwrap();
+        let count = r502.resume_defragment(unfinished, |m| moves.push(m)).unwrap();
+
+        // then: the move is finished, leaving exactly one copy
+        assert_eq!(count, 1);
+        assert_eq!(moves, vec![SlotMove { from: 5, to: 0 }]);
+        assert_eq!(emulator.slot(0), Some(char_file(7)));
+        assert_eq!(emulator.slot(1), Some(char_file(6)));
+        assert_eq!(emulator.state().library.iter().filter(|slot| slot.is_some()).count(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_defragment_delete_failure_keeps_both_copies() {
+        // given: a module with a hole at 0, which fails to delete
+        let emulator = Emulator::new();
+        emulator.enroll(1, 6);
+        emulator.enroll(5, 7);
+        emulator.fail_next(0x0c, 0x10);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: defragmenting
+        let result = r502.defragment_library(|_| {});
+
+        // then: the failed delete is reported
+        match result {
+            Err(DefragError::Delete {
+                from: 5,
+                to: 0,
+                status: DeletCharStatus::DeleteFailed,
+            }) => {}
+            other => panic!("Expected DefragError::Delete, got {:?}", other),
+        };
+
+        // and: both slots hold the template
+        assert_eq!(emulator.slot(0), Some(char_file(7)));
+        assert_eq!(emulator.slot(5), Some(char_file(7)));
+
+        // when: resuming from the unfinished move
+        let unfinished = result.unwrap_err().unfinished_move().unwrap();
+        let mut moves = Vec::new();
+        r502.resume_defragment(unfinished, |m| moves.push(m)).unwrap();
+
+        // then: the copy left behind is deleted rather than moved, leaving exactly one copy
+        assert_eq!(moves, vec![SlotMove { from: 5, to: 0 }]);
+        assert_eq!(emulator.slot(0), Some(char_file(7)));
+        assert_eq!(emulator.slot(1), Some(char_file(6)));
+        assert_eq!(emulator.state().library.iter().filter(|slot| slot.is_some()).count(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_resume_defragment_before_store() {
+        // given: a module with a hole at 0, and a move to it which never reached the module
+        let emulator = Emulator::new();
+        emulator.enroll(1, 6);
+        emulator.enroll(5, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: resuming from that move
+        let mut moves = Vec::new();
+        let unfinished = SlotMove { from: 5, to: 0 };
+        r502.resume_defragment(unfinished, |m| moves.push(m)).unwrap();
+
+        // then: nothing is deleted unchecked, and the template is moved as usual
+        assert_eq!(moves, vec![SlotMove { from: 5, to: 0 }]);
+        assert_eq!(emulator.slot(0), Some(char_file(7)));
+        assert_eq!(emulator.slot(1), Some(char_file(6)));
+        assert_eq!(emulator.state().library.iter().filter(|slot| slot.is_some()).count(), 2);
+    }
+}
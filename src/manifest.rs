@@ -0,0 +1,261 @@
+use core::fmt::Write as _;
+use serde::{Deserialize, Serialize};
+use std::string::String;
+use std::vec::Vec;
+
+use crate::commands::Command;
+use crate::driver::R502;
+use crate::labels::LabelError;
+use crate::library::LibraryError;
+use crate::responses::*;
+use crate::template::ExportError;
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// Version of the `LibraryManifest` layout written by this driver. Bumped whenever a field
+/// changes meaning or is removed; new optional fields do not bump it.
+pub const MANIFEST_SCHEMA_VERSION: u32 = 1;
+
+/// A portable description of a module and its library, as returned by
+/// [`R502::export_manifest`](struct.R502.html#method.export_manifest). Serialises to JSON and
+/// other formats with `serde`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LibraryManifest {
+    /// `MANIFEST_SCHEMA_VERSION` at the time the manifest was written.
+    pub schema_version: u32,
+
+    /// The chip serial number in lowercase hex, if the firmware reports it.
+    pub chip_serial: Option<String>,
+
+    /// The firmware version, if the firmware reports it.
+    pub firmware_version: Option<String>,
+
+    /// The algorithm library version, if the firmware reports it.
+    pub algorithm_version: Option<String>,
+
+    /// The system identifier code from `ReadSysPara`.
+    pub system_identifier_code: u16,
+
+    /// Number of slots in the library.
+    pub capacity: u16,
+
+    /// Every occupied slot, lowest index first.
+    pub slots: Vec<ManifestSlot>,
+}
+
+/// An occupied slot in a `LibraryManifest`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestSlot {
+    /// Index of the slot in the library.
+    pub index: u16,
+
+    /// The label stored with [`R502::set_label`](struct.R502.html#method.set_label), if any.
+    pub label: Option<String>,
+
+    /// Length of the template in bytes.
+    pub size: u32,
+
+    /// `Template::digest` of the template.
+    pub digest: u32,
+}
+
+/// Error type for `export_manifest`.
+#[derive(Debug)]
+pub enum ManifestError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// `ReadSysPara` failed with the given confirmation code.
+    ReadSysPara(u8),
+
+    /// The _index table_ could not be read.
+    Library(LibraryError<TXE, RXE>),
+
+    /// The template at `index` could not be exported.
+    Export {
+        index: u16,
+        error: ExportError<TXE, RXE>,
+    },
+
+    /// The labels could not be read from the notepad.
+    Labels(LabelError<TXE, RXE>),
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for ManifestError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Describes the module and every template in its library, for a provisioning tool to
+    /// compare devices or restore one from a backup. Each occupied slot is exported to take its
+    /// digest, so this takes a few round trips per template.
+    ///
+    /// **Note:** This overwrites the contents of _character buffer_ 2.
+    pub fn export_manifest(
+        &mut self,
+    ) -> Result<LibraryManifest, ManifestError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(self.send_command(Command::ReadSysPara), Reply::ReadSysPara)?;
+        if result.confirmation_code != 0x00 {
+            return Err(ManifestError::ReadSysPara(result.confirmation_code));
+        }
+        let parameters = result.system_parameters;
+
+        let result = expect_reply!(self.send_command(Command::GetChipSN), Reply::GetChipSN)?;
+        let chip_serial = match result.confirmation_code {
+            GetChipSNStatus::Success => {
+                let mut hex = String::new();
+                for byte in result.serial_number.iter() {
+                    write!(hex, "{:02x}", byte).unwrap();
+                }
+                Some(hex)
+            }
+            GetChipSNStatus::PacketError => None,
+        };
+
+        let result = expect_reply!(self.send_command(Command::GetFwVer), Reply::GetFwVer)?;
+        let firmware_version = match result.confirmation_code {
+            GetFwVerStatus::Success => Some(version_string(&result.version)),
+            GetFwVerStatus::PacketError => None,
+        };
+
+        let result = expect_reply!(self.send_command(Command::GetAlgVer), Reply::GetAlgVer)?;
+        let algorithm_version = match result.confirmation_code {
+            GetAlgVerStatus::Success => Some(version_string(&result.version)),
+            GetAlgVerStatus::PacketError => None,
+        };
+
+        let table = self
+            .read_index_table_pages(parameters.finger_library_size)
+            .map_err(ManifestError::Library)?;
+        let labels = self.read_labels().map_err(ManifestError::Labels)?;
+
+        let mut slots = Vec::new();
+        for index in table.occupied() {
+            let template = self
+                .export_template(index)
+                .map_err(|error| ManifestError::Export { index, error })?;
+            let label = labels
+                .iter()
+                .find(|(labelled, _)| *labelled == index)
+                .map(|(_, label)| String::from(label.as_str()));
+            slots.push(ManifestSlot {
+                index,
+                label,
+                size: template.len() as u32,
+                digest: template.digest(),
+            });
+        }
+
+        return Ok(LibraryManifest {
+            schema_version: MANIFEST_SCHEMA_VERSION,
+            chip_serial,
+            firmware_version,
+            algorithm_version,
+            system_identifier_code: parameters.system_identifier_code,
+            capacity: table.capacity(),
+            slots,
+        });
+    }
+}
+
+/// A version string as reported by the module, without its zero padding.
+fn version_string(version: &[u8; 32]) -> String {
+    let end = version.iter().position(|byte| *byte == 0).unwrap_or(version.len());
+    return String::from_utf8_lossy(&version[..end]).into_owned();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{char_file, Emulator};
+    use crate::template::Template;
+    use std::vec;
+
+    #[test]
+    fn test_export_manifest() {
+        // given: a module with two templates, one of them labelled
+        let emulator = Emulator::new();
+        emulator.enroll(2, 7);
+        emulator.enroll(40, 8);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.set_label(40, "J. Smith R-index").unwrap();
+
+        // when: exporting the manifest
+        let manifest = r502.export_manifest().unwrap();
+
+        // then: the module and both slots are described
+        let digest = |finger| Template::from_bytes(&char_file(finger)).unwrap().digest();
+        assert_eq!(
+            manifest,
+            LibraryManifest {
+                schema_version: MANIFEST_SCHEMA_VERSION,
+                chip_serial: Some("5a".repeat(32)),
+                firmware_version: Some(String::from("EMU-FW-1.4")),
+                algorithm_version: Some(String::from("EMU-ALG-2.1")),
+                system_identifier_code: manifest.system_identifier_code,
+                capacity: 200,
+                slots: vec![
+                    ManifestSlot { index: 2, label: None, size: 1536, digest: digest(7) },
+                    ManifestSlot {
+                        index: 40,
+                        label: Some(String::from("J. Smith R-index")),
+                        size: 1536,
+                        digest: digest(8),
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_export_manifest_old_firmware() {
+        // given: a module which knows none of the identification commands
+        let emulator = Emulator::new();
+        emulator.state().unsupported = vec![0x34, 0x39, 0x3a];
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: exporting the manifest
+        let manifest = r502.export_manifest().unwrap();
+
+        // then: what the module could not report is left out
+        assert_eq!(manifest.chip_serial, None);
+        assert_eq!(manifest.firmware_version, None);
+        assert_eq!(manifest.algorithm_version, None);
+        assert_eq!(manifest.slots.len(), 0);
+    }
+
+    #[test]
+    fn test_manifest_json_round_trip() {
+        // given: a small manifest
+        let manifest = LibraryManifest {
+            schema_version: MANIFEST_SCHEMA_VERSION,
+            chip_serial: Some(String::from("00ff")),
+            firmware_version: None,
+            algorithm_version: Some(String::from("2.1")),
+            system_identifier_code: 9,
+            capacity: 200,
+            slots: vec![ManifestSlot {
+                index: 3,
+                label: Some(String::from("Zoë")),
+                size: 1536,
+                digest: 0xdeadbeef,
+            }],
+        };
+
+        // when: serialising it to JSON and parsing it back
+        let json = serde_json::to_string(&manifest).unwrap();
+        let parsed: LibraryManifest = serde_json::from_str(&json).unwrap();
+
+        // then: nothing was lost
+        assert_eq!(parsed, manifest);
+        assert_eq!(json.contains("\"schema_version\":1"), true);
+        assert_eq!(json.contains("\"digest\":3735928559"), true);
+    }
+}
@@ -0,0 +1,402 @@
+use arrayvec::{ArrayString, ArrayVec};
+
+use crate::driver::R502;
+use crate::library::LibraryError;
+use crate::template::{ExportError, Template};
+use crate::transport::Transport;
+
+/// Most slots a `LibraryMirror` holds by default.
+pub const MAX_MIRROR_SLOTS: usize = 64;
+
+/// Longest label a `MirrorSlot` keeps, in bytes of UTF-8; the same as the notepad labels, so
+/// those fit. Longer labels are truncated.
+pub const MIRROR_LABEL_LENGTH: usize = 24;
+
+/// Most discrepancies one `MirrorReport` holds.
+pub const MAX_MIRROR_DISCREPANCIES: usize = 32;
+
+/// A label kept in a `LibraryMirror`.
+pub type MirrorLabel = ArrayString<MIRROR_LABEL_LENGTH>;
+
+/// What should be in one slot of the library, see `LibraryMirror`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MirrorSlot {
+    /// Index of the slot in the library.
+    pub index: u16,
+
+    /// `Template::digest` of the template which belongs there.
+    pub digest: u32,
+
+    /// Whatever the host calls the template, such as a name. May be empty.
+    pub label: MirrorLabel,
+}
+
+/// The host's own record of what should be in the library, slot by slot, for keeping in its
+/// own flash and checking the module against now and then with
+/// [`R502::verify_against_mirror`](struct.R502.html#method.verify_against_mirror). With the
+/// `serde` feature it can be serialised in any format `serde` knows.
+///
+/// Slots are kept in order of index. Record each template as it is enrolled or imported, and
+/// remove it when it is deleted; a mirror which falls behind reports the changes as
+/// discrepancies.
+///
+/// A mirror holds up to `N` slots, `MAX_MIRROR_SLOTS` unless given. Each takes about 36 bytes,
+/// so size it for the templates actually enrolled rather than the capacity of the library.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LibraryMirror<const N: usize = MAX_MIRROR_SLOTS> {
+    slots: ArrayVec<MirrorSlot, N>,
+}
+
+impl LibraryMirror {
+    /// A mirror of an empty library, holding up to `MAX_MIRROR_SLOTS` slots. Use
+    /// [`with_slots`](#method.with_slots) for other sizes.
+    pub const fn new() -> Self {
+        return Self::with_slots();
+    }
+}
+
+impl<const N: usize> LibraryMirror<N> {
+    /// A mirror of an empty library, holding up to `N` slots.
+    pub const fn with_slots() -> Self {
+        return Self { slots: ArrayVec::new_const() };
+    }
+
+    /// Records that slot `index` holds a template with `digest`, replacing whatever was
+    /// recorded for it. A label longer than `MIRROR_LABEL_LENGTH` bytes is cut short at the
+    /// last whole character that fits. Fails, changing nothing, if the slot is new and `N`
+    /// are already recorded.
+    pub fn insert(&mut self, index: u16, digest: u32, label: &str) -> Result<(), MirrorFull> {
+        let mut end = label.len().min(MIRROR_LABEL_LENGTH);
+        while !label.is_char_boundary(end) {
+            end -= 1;
+        }
+        let slot = MirrorSlot { index, digest, label: MirrorLabel::from(&label[..end]).unwrap() };
+
+        return match self.slots.binary_search_by_key(&index, |slot| slot.index) {
+            Ok(position) => {
+                self.slots[position] = slot;
+                Ok(())
+            }
+            Err(position) => self.slots.try_insert(position, slot).map_err(|_| MirrorFull),
+        };
+    }
+
+    /// Records that slot `index` holds `template`, as [`insert`](#method.insert) does.
+    pub fn record(
+        &mut self,
+        index: u16,
+        template: &Template,
+        label: &str,
+    ) -> Result<(), MirrorFull> {
+        return self.insert(index, template.digest(), label);
+    }
+
+    /// Forgets slot `index`, returning what was recorded for it.
+    pub fn remove(&mut self, index: u16) -> Option<MirrorSlot> {
+        let position = self.slots.binary_search_by_key(&index, |slot| slot.index).ok()?;
+        return Some(self.slots.remove(position));
+    }
+
+    /// What is recorded for slot `index`, if anything.
+    pub fn get(&self, index: u16) -> Option<&MirrorSlot> {
+        let position = self.slots.binary_search_by_key(&index, |slot| slot.index).ok()?;
+        return Some(&self.slots[position]);
+    }
+
+    /// Every recorded slot, lowest index first.
+    pub fn slots(&self) -> &[MirrorSlot] {
+        return &self.slots[..];
+    }
+
+    /// Number of recorded slots.
+    pub fn len(&self) -> usize {
+        return self.slots.len();
+    }
+
+    /// True if no slots are recorded.
+    pub fn is_empty(&self) -> bool {
+        return self.slots.is_empty();
+    }
+}
+
+/// Error type for `LibraryMirror::insert`: the mirror holds as many slots as it can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MirrorFull;
+
+/// How a slot of the library differs from its `LibraryMirror`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MirrorMismatch {
+    /// The mirror has a template for the slot, but the slot is empty or past the end of the
+    /// library.
+    Missing,
+
+    /// The slot holds a template the mirror does not know of.
+    Extra,
+
+    /// The slot holds a template, but not the one in the mirror: `found` is its digest.
+    Modified { expected: u32, found: u32 },
+}
+
+/// A slot which does not match its `LibraryMirror`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MirrorDiscrepancy {
+    pub index: u16,
+    pub mismatch: MirrorMismatch,
+}
+
+/// Result of `verify_against_mirror`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorReport {
+    /// The slots which do not match, lowest index first, up to `MAX_MIRROR_DISCREPANCIES`.
+    pub discrepancies: ArrayVec<MirrorDiscrepancy, MAX_MIRROR_DISCREPANCIES>,
+
+    /// More discrepancies were found than fit into `discrepancies`.
+    pub overflowed: bool,
+
+    /// Number of templates exported and compared.
+    pub checked: u16,
+}
+
+impl MirrorReport {
+    /// True if the library matches the mirror.
+    pub fn is_clean(&self) -> bool {
+        return self.discrepancies.is_empty() && !self.overflowed;
+    }
+
+    fn push(&mut self, index: u16, mismatch: MirrorMismatch) {
+        if self.discrepancies.try_push(MirrorDiscrepancy { index, mismatch }).is_err() {
+            self.overflowed = true;
+        }
+    }
+}
+
+/// Error type for `verify_against_mirror`.
+#[derive(Debug)]
+pub enum MirrorError<TXE, RXE> {
+    /// The _index table_ could not be read.
+    Library(LibraryError<TXE, RXE>),
+
+    /// The template at `index` could not be exported.
+    Export {
+        index: u16,
+        error: ExportError<TXE, RXE>,
+    },
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Checks the library against `mirror`, to catch templates which were changed behind the
+    /// host's back or damaged in the module's flash. The _index table_ is read afresh, not
+    /// taken from the driver's cache, and every occupied slot is exported to take its digest,
+    /// so this takes a few round trips per template.
+    ///
+    /// Slots the mirror has but the module does not are `Missing`, slots the module has but
+    /// the mirror does not are `Extra`, and slots whose digest differs are `Modified`.
+    ///
+    /// **Note:** This overwrites the contents of _character buffer_ 2.
+    pub fn verify_against_mirror<const N: usize>(
+        &mut self,
+        mirror: &LibraryMirror<N>,
+    ) -> Result<MirrorReport, MirrorError<T::WriteError, T::ReadError>> {
+        self.index_cache.invalidate();
+        let table = self.read_index_table().map_err(MirrorError::Library)?;
+
+        let mut report = MirrorReport {
+            discrepancies: ArrayVec::new(),
+            overflowed: false,
+            checked: 0,
+        };
+        let mut expected = mirror.slots().iter().peekable();
+        for index in table.occupied() {
+            while let Some(slot) = expected.next_if(|slot| slot.index < index) {
+                report.push(slot.index, MirrorMismatch::Missing);
+            }
+
+            let template = match self.export_template(index) {
+                Ok(template) => template,
+                Err(ExportError::SlotEmpty) => {
+                    if let Some(slot) = expected.next_if(|slot| slot.index == index) {
+                        report.push(slot.index, MirrorMismatch::Missing);
+                    }
+                    continue;
+                }
+                Err(error) => return Err(MirrorError::Export { index, error }),
+            };
+            report.checked += 1;
+
+            let found = template.digest();
+            match expected.next_if(|slot| slot.index == index) {
+                Some(slot) if slot.digest == found => {}
+                Some(slot) => {
+                    report.push(index, MirrorMismatch::Modified { expected: slot.digest, found });
+                }
+                None => report.push(index, MirrorMismatch::Extra),
+            }
+        }
+        for slot in expected {
+            report.push(slot.index, MirrorMismatch::Missing);
+        }
+
+        return Ok(report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::emulator::{char_file, Emulator, EmulatorRx, EmulatorTx};
+
+    fn digest(finger: u8) -> u32 {
+        return Template::from_bytes(&char_file(finger)).unwrap().digest();
+    }
+
+    /// A module with fingers 7, 8 and 9 in slots 1, 5 and 9, and a mirror of it.
+    fn audited() -> (Emulator, R502<(EmulatorTx, EmulatorRx)>, LibraryMirror) {
+        let emulator = Emulator::new();
+        let mut mirror = LibraryMirror::new();
+        for (index, finger) in [(1u16, 7u8), (5, 8), (9, 9)].iter() {
+            emulator.enroll(*index as usize, *finger);
+            mirror.insert(*index, digest(*finger), "staff").unwrap();
+        }
+        let (tx, rx) = emulator.serial();
+        return (emulator, R502::new(tx, rx, 0xffffffff), mirror);
+    }
+
+    #[test]
+    fn test_verify_against_mirror_clean() {
+        // given: a module which matches its mirror
+        let (_emulator, mut r502, mirror) = audited();
+
+        // when: verifying it
+        let report = r502.verify_against_mirror(&mirror).unwrap();
+
+        // then: every template was checked, and nothing is amiss
+        assert_eq!(report.is_clean(), true);
+        assert_eq!(report.checked, 3);
+    }
+
+    #[test]
+    fn test_verify_against_mirror_altered() {
+        // given: a module where slot 5 was overwritten, slot 9 emptied and slot 3 filled
+        let (emulator, mut r502, mirror) = audited();
+        let mut altered = char_file(8);
+        altered[100] ^= 0x01;
+        emulator.state().library[5] = Some(altered.clone());
+        emulator.state().library[9] = None;
+        emulator.enroll(3, 4);
+
+        // when: verifying it
+        let report = r502.verify_against_mirror(&mirror).unwrap();
+
+        // then: each change is reported
+        let found = Template::from_bytes(&altered).unwrap().digest();
+        let modified = MirrorMismatch::Modified { expected: digest(8), found };
+        assert_eq!(
+            &report.discrepancies[..],
+            &[
+                MirrorDiscrepancy { index: 3, mismatch: MirrorMismatch::Extra },
+                MirrorDiscrepancy { index: 5, mismatch: modified },
+                MirrorDiscrepancy { index: 9, mismatch: MirrorMismatch::Missing },
+            ]
+        );
+        assert_eq!(report.checked, 3);
+        assert_eq!(report.is_clean(), false);
+    }
+
+    #[test]
+    fn test_verify_against_mirror_ignores_cache() {
+        // given: a driver whose cached index table is warm, and a slot emptied since
+        let (emulator, mut r502, mirror) = audited();
+        r502.enable_index_cache(true);
+        r502.read_index_table().unwrap();
+        emulator.state().library[1] = None;
+
+        // when: verifying it
+        let report = r502.verify_against_mirror(&mirror).unwrap();
+
+        // then: the module was asked, not the cache
+        let missing = MirrorDiscrepancy { index: 1, mismatch: MirrorMismatch::Missing };
+        assert_eq!(&report.discrepancies[..], &[missing]);
+    }
+
+    #[test]
+    fn test_mirror_insert_and_remove() {
+        // given: a mirror with two slots, recorded out of order
+        let mut mirror = LibraryMirror::new();
+        mirror.insert(8, 0xaaaa, "second").unwrap();
+        mirror.insert(2, 0xbbbb, "a label which is far too long to keep").unwrap();
+
+        // when: replacing one and removing the other
+        mirror.insert(8, 0xcccc, "replaced").unwrap();
+        let removed = mirror.remove(2).unwrap();
+
+        // then: the slots stay in order, and labels are cut short
+        assert_eq!(removed.label.as_str(), "a label which is far too");
+        assert_eq!(mirror.len(), 1);
+        assert_eq!(mirror.get(8).map(|slot| slot.digest), Some(0xcccc));
+        assert_eq!(mirror.get(2), None);
+    }
+
+    #[test]
+    fn test_mirror_full() {
+        // given: a full mirror
+        let mut mirror = LibraryMirror::new();
+        for index in 0..MAX_MIRROR_SLOTS as u16 {
+            mirror.insert(index, 0, "").unwrap();
+        }
+
+        // then: another slot cannot be added, but those already there can be changed
+        assert_eq!(mirror.insert(1000, 0, ""), Err(MirrorFull));
+        assert_eq!(mirror.insert(3, 1, ""), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_against_mirror_high_capacity() {
+        // given: a 1500-slot module with templates above slot 255, and a mirror sized for them
+        let emulator = Emulator::with_geometry(1500, 2);
+        let mut mirror = LibraryMirror::<3>::with_slots();
+        for (index, finger) in [(255u16, 7u8), (256, 8), (1499, 9)].iter() {
+            emulator.enroll(*index as usize, *finger);
+            mirror.insert(*index, digest(*finger), "").unwrap();
+        }
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: verifying the module, with the template in the last slot since deleted
+        emulator.state().library[1499] = None;
+        let report = r502.verify_against_mirror(&mirror).unwrap();
+
+        // then: the slot at the very end of the library was checked
+        assert_eq!(report.checked, 2);
+        assert_eq!(
+            &report.discrepancies[..],
+            &[MirrorDiscrepancy { index: 1499, mismatch: MirrorMismatch::Missing }]
+        );
+
+        // and: the mirror holds no more than it was sized for
+        assert_eq!(mirror.insert(1000, 0, ""), Err(MirrorFull));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_mirror_json_round_trip() {
+        // given: a mirror with a labelled slot
+        let mut mirror = LibraryMirror::new();
+        mirror.insert(4, 0xdeadbeef, "Zoë").unwrap();
+
+        // when: serialising it to JSON and parsing it back
+        let json = serde_json::to_string(&mirror).unwrap();
+        let parsed: LibraryMirror = serde_json::from_str(&json).unwrap();
+
+        // then: nothing was lost
+        assert_eq!(parsed, mirror);
+    }
+}
@@ -0,0 +1,419 @@
+#![cfg_attr(not(feature = "mock"), allow(dead_code))]
+
+extern crate std;
+
+use core::cell::RefCell;
+use embedded_hal::serial::{Read, Write};
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::vec::Vec;
+
+use crate::codec::{self, CommandBuffer};
+use crate::commands::Command;
+
+const REPLY_PACKET: u8 = 0x07;
+const DATA_PACKET: u8 = 0x02;
+const END_DATA_PACKET: u8 = 0x08;
+
+/// Error the mock serial port reads with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockError {
+    /// The driver read a byte, but no expectation left anything to read. The driver reports
+    /// this as `Error::RecvReadError`, as it would a module which does not answer.
+    NoReply,
+}
+
+/// What the driver is expected to write next, see [`Expectation`](struct.Expectation.html).
+#[derive(Debug)]
+enum Expected {
+    Command(Command),
+    #[cfg(feature = "cmd-transfer")]
+    Data { payload: Vec<u8>, last: bool },
+    Raw(Vec<u8>),
+}
+
+/// Something the mock module sends back.
+#[derive(Debug)]
+enum Response {
+    /// A packet of the given type, carrying the given bytes.
+    Packet(u8, Vec<u8>),
+    /// Exactly these bytes.
+    Raw(Vec<u8>),
+}
+
+/// One packet the driver is expected to write, and what the module sends back once it has.
+///
+/// Commands are given as `Command` values and replies by confirmation code, so expectations
+/// read like the protocol rather than like bytes; replies are framed with the mock's address
+/// and checksummed for you.
+#[derive(Debug)]
+pub struct Expectation {
+    expected: Expected,
+    responses: Vec<Response>,
+}
+
+impl Expectation {
+    /// Expects `cmd`, and answers it with success (confirmation code `0x00`). Any data the
+    /// reply to `cmd` carries, such as the match of `Search`, is zeroes; see
+    /// [`reply`](#method.reply) to set it.
+    pub fn new(cmd: Command) -> Self {
+        let mut reply = std::vec![0x00];
+        reply.resize(1 + codec::reply_data_length(cmd.kind()), 0);
+        let responses = std::vec![Response::Packet(REPLY_PACKET, reply)];
+        return Self { expected: Expected::Command(cmd), responses };
+    }
+
+    /// Expects a data packet from the host carrying `payload`, as sent after `DownChar`.
+    /// `last` marks the end-of-data packet. Nothing is sent back.
+    #[cfg(feature = "cmd-transfer")]
+    pub fn data(payload: &[u8], last: bool) -> Self {
+        let expected = Expected::Data { payload: payload.to_vec(), last };
+        return Self { expected, responses: Vec::new() };
+    }
+
+    /// Expects exactly `bytes`, for packets the driver should write that are neither a
+    /// `Command` nor data. Nothing is sent back unless a [`reply`](#method.reply) is given.
+    pub fn raw(bytes: &[u8]) -> Self {
+        return Self { expected: Expected::Raw(bytes.to_vec()), responses: Vec::new() };
+    }
+
+    /// Answers with confirmation code `code`, followed by `data`. Replies shorter than the
+    /// command's are padded with zeroes, as a module does when a command fails.
+    pub fn reply(mut self, code: u8, data: &[u8]) -> Self {
+        let mut reply = std::vec![code];
+        reply.extend_from_slice(data);
+        if let Expected::Command(cmd) = &self.expected {
+            reply.resize(reply.len().max(1 + codec::reply_data_length(cmd.kind())), 0);
+        }
+        self.responses.retain(|response| !matches!(response, Response::Packet(REPLY_PACKET, _)));
+        self.responses.insert(0, Response::Packet(REPLY_PACKET, reply));
+        return self;
+    }
+
+    /// Answers with exactly `bytes` rather than a reply put together by the mock, for golden
+    /// frames and for replies which are deliberately damaged.
+    pub fn reply_raw(mut self, bytes: &[u8]) -> Self {
+        self.responses.clear();
+        self.responses.push(Response::Raw(bytes.to_vec()));
+        return self;
+    }
+
+    /// Sends nothing back, as a module which missed the command would.
+    pub fn no_reply(mut self) -> Self {
+        self.responses.clear();
+        return self;
+    }
+
+    /// After the reply, sends a data packet carrying `payload`, as the module does after
+    /// `UpChar`. `last` marks the end-of-data packet.
+    pub fn then_data(mut self, payload: &[u8], last: bool) -> Self {
+        let packet_id = if last { END_DATA_PACKET } else { DATA_PACKET };
+        self.responses.push(Response::Packet(packet_id, payload.to_vec()));
+        return self;
+    }
+
+    /// The packet expected, sent to `address`.
+    fn frame(&self, address: u32) -> Vec<u8> {
+        let mut buffer = CommandBuffer::new();
+        return match &self.expected {
+            Expected::Command(cmd) => {
+                codec::write_command(&mut buffer, address, cmd).unwrap();
+                buffer.to_vec()
+            }
+            #[cfg(feature = "cmd-transfer")]
+            Expected::Data { payload, last } => {
+                let chk = codec::encode_data_header(&mut buffer, address, payload, *last);
+                let mut frame = buffer.to_vec();
+                frame.extend_from_slice(payload);
+                frame.extend_from_slice(&chk);
+                frame
+            }
+            Expected::Raw(bytes) => bytes.clone(),
+        };
+    }
+}
+
+#[derive(Debug)]
+struct MockState {
+    address: u32,
+    expectations: VecDeque<Expectation>,
+    /// The expectation being written, with its packet and how much of it has been written.
+    current: Option<(Expectation, Vec<u8>, usize)>,
+    incoming: VecDeque<u8>,
+}
+
+/// A serial port, implementing embedded-hal's `Read<u8>` and `Write<u8>`, which plays a module
+/// following a script of [`Expectation`](struct.Expectation.html)s, for testing code built on
+/// the driver without a module.
+///
+/// Anything written which the script does not expect fails the test on the spot, and
+/// [`done`](#method.done) fails it if any expectations are left over, so a test pins down the
+/// exact sequence of commands. Clones share the script, so hand a clone to the driver (with
+/// `R502::from_serial`, or as both halves to `R502::new`) and keep one to call `done` on:
+///
+/// ```
+/// use hzgrow_r502::{Command, Expectation, IdentifyConfig, MockTransport, R502};
+///
+/// let mock = MockTransport::new(0xffffffff, vec![
+///     Expectation::new(Command::GenImg),
+///     Expectation::new(Command::Img2Tz { buffer: 1 }),
+///     Expectation::new(Command::Search { buffer: 1, start_index: 0, end_index: 0xffff })
+///         .reply(0x00, &[0x00, 0x05, 0x00, 0x64]),
+/// ]);
+/// let mut r502 = R502::from_serial(mock.clone(), 0xffffffff);
+///
+/// assert_eq!(r502.identify(&IdentifyConfig::default()).unwrap(), (5, 100));
+/// mock.done();
+/// ```
+///
+/// # Panics
+///
+/// Writing a byte which differs from the packet expected, or writing with no expectations
+/// left, panics with what was expected and what was written.
+#[derive(Debug, Clone)]
+pub struct MockTransport {
+    state: Rc<RefCell<MockState>>,
+}
+
+impl MockTransport {
+    /// Creates a mock module at `address` which expects `expectations`, in order.
+    pub fn new(address: u32, expectations: Vec<Expectation>) -> Self {
+        let state = MockState {
+            address,
+            expectations: expectations.into_iter().collect(),
+            current: None,
+            incoming: VecDeque::new(),
+        };
+        return Self { state: Rc::new(RefCell::new(state)) };
+    }
+
+    /// Adds `expectation` to the end of the script.
+    pub fn expect(&self, expectation: Expectation) {
+        self.state.borrow_mut().expectations.push_back(expectation);
+    }
+
+    /// Checks that the script has been played out: every expectation was met, and everything
+    /// sent back was read.
+    ///
+    /// # Panics
+    ///
+    /// If any of it was not, with what is left.
+    pub fn done(&self) {
+        let state = self.state.borrow();
+        if let Some((expectation, frame, written)) = &state.current {
+            panic!(
+                "MockTransport: {:?} was only partly written: {:02x?}",
+                expectation.expected,
+                &frame[..*written]
+            );
+        }
+        let left: Vec<_> = state.expectations.iter().map(|e| &e.expected).collect();
+        assert!(left.is_empty(), "MockTransport: expectations left over: {:?}", left);
+        assert!(
+            state.incoming.is_empty(),
+            "MockTransport: {} bytes sent back were never read",
+            state.incoming.len()
+        );
+    }
+}
+
+impl Write<u8> for MockTransport {
+    type Error = MockError;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        let mut state = self.state.borrow_mut();
+        if state.current.is_none() {
+            let expectation = match state.expectations.pop_front() {
+                Some(expectation) => expectation,
+                None => panic!("MockTransport: wrote {:02x} with no expectations left", word),
+            };
+            let frame = expectation.frame(state.address);
+            state.current = Some((expectation, frame, 0));
+        }
+
+        let address = state.address;
+        let (expectation, frame, written) = state.current.as_mut().unwrap();
+        if frame[*written] != word {
+            panic!(
+                "MockTransport: expected {:?}, i.e. {:02x?}, but byte {} written was {:02x} \
+                 (written so far: {:02x?})",
+                expectation.expected,
+                frame,
+                *written,
+                word,
+                &frame[..*written]
+            );
+        }
+        *written += 1;
+        if *written == frame.len() {
+            let (expectation, _, _) = state.current.take().unwrap();
+            for response in expectation.responses.iter() {
+                match response {
+                    Response::Packet(packet_id, body) => {
+                        let packet = packet(address, *packet_id, body);
+                        state.incoming.extend(packet.iter());
+                    }
+                    Response::Raw(bytes) => state.incoming.extend(bytes.iter()),
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        return Ok(());
+    }
+}
+
+impl Read<u8> for MockTransport {
+    type Error = MockError;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        let mut state = self.state.borrow_mut();
+        return state.incoming.pop_front().ok_or(nb::Error::Other(MockError::NoReply));
+    }
+}
+
+/// A packet of type `packet_id` from `address`, carrying `body`.
+fn packet(address: u32, packet_id: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = std::vec![0xef, 0x01];
+    packet.extend_from_slice(&address.to_be_bytes());
+    packet.push(packet_id);
+    packet.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+    packet.extend_from_slice(body);
+    let chk = codec::checksum(&packet[6..]);
+    packet.extend_from_slice(&chk.to_be_bytes());
+    return packet;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "cmd-transfer")]
+    use crate::consts::PacketSize;
+    use crate::driver::R502;
+    use crate::responses::Reply;
+    use crate::utils::Error;
+
+    #[test]
+    fn test_exact_sequence() {
+        // given: a mock expecting two commands, the second of which fails
+        let mock = MockTransport::new(
+            0xffffffff,
+            std::vec![
+                Expectation::new(Command::TemplateNum).reply(0x00, &[0x00, 0x03]),
+                Expectation::new(Command::GenImg).reply(0x02, &[]),
+            ],
+        );
+        let mut r502 = R502::from_serial(mock.clone(), 0xffffffff);
+
+        // when: sending them in order
+        let template_num = r502.send_command(Command::TemplateNum);
+        let gen_img = r502.send_command(Command::GenImg);
+
+        // then: each gets its scripted reply, and the script is played out
+        match template_num {
+            Ok(Reply::TemplateNum(result)) => assert_eq!(result.template_num, 3),
+            other => panic!("Expected Reply::TemplateNum, got {:?}", other),
+        }
+        assert_eq!(matches!(gen_img, Ok(Reply::GenImg(_))), true);
+        mock.done();
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_data_packets() {
+        // given: a mock which sends a template after `UpChar`, and takes one after `DownChar`
+        let mock = MockTransport::new(
+            0xffffffff,
+            std::vec![
+                Expectation::new(Command::UpChar { buffer: 1 })
+                    .then_data(&[0x11; 32], false)
+                    .then_data(&[0x22; 8], true),
+                Expectation::new(Command::DownChar { buffer: 2 }),
+                Expectation::data(&[0x11; 32], false),
+                Expectation::data(&[0x22; 8], true),
+            ],
+        );
+        let mut r502 = R502::from_serial(mock.clone(), 0xffffffff);
+        r502.set_data_packet_size(PacketSize::Bytes32);
+
+        // when: uploading the template and downloading it again
+        let template = r502.upload_template(1).unwrap();
+        r502.download_template(2, &template).unwrap();
+
+        // then: the template came through whole, and went back in the same packets
+        let mut expected = std::vec![0x11; 32];
+        expected.extend_from_slice(&[0x22; 8]);
+        assert_eq!(template.as_bytes(), &expected[..]);
+        mock.done();
+    }
+
+    #[test]
+    fn test_no_reply() {
+        // given: a mock module which misses the command
+        let mock = MockTransport::new(
+            0xffffffff,
+            std::vec![Expectation::new(Command::HandShake).no_reply()],
+        );
+        let mut r502 = R502::from_serial(mock.clone(), 0xffffffff);
+
+        // when: sending it
+        let result = r502.send_command(Command::HandShake);
+
+        // then: reading the reply fails
+        assert_eq!(matches!(result, Err(Error::RecvReadError(MockError::NoReply))), true);
+        mock.done();
+    }
+
+    #[test]
+    fn test_raw_and_appended_expectations() {
+        // given: a mock expecting a `ReadSysPara` packet given as bytes
+        let mock = MockTransport::new(
+            0xffffffff,
+            std::vec![Expectation::raw(&[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x0f, 0x00, 0x13,
+            ])
+            .reply(0x00, &[0x00; 16])],
+        );
+        let mut r502 = R502::from_serial(mock.clone(), 0xffffffff);
+        r502.send_command(Command::ReadSysPara).unwrap();
+
+        // when: appending an expectation once the first has been met
+        mock.expect(Expectation::new(Command::HandShake));
+        let reply = r502.send_command(Command::HandShake);
+
+        // then: it is met in turn
+        assert_eq!(matches!(reply, Ok(Reply::HandShake(_))), true);
+        mock.done();
+    }
+
+    #[test]
+    #[should_panic(expected = "expected Command(GenImg)")]
+    fn test_unexpected_command() {
+        // given: a mock expecting `GenImg`
+        let mock = MockTransport::new(0xffffffff, std::vec![Expectation::new(Command::GenImg)]);
+        let mut r502 = R502::from_serial(mock, 0xffffffff);
+
+        // when: sending something else
+        // then: the test fails
+        let _ = r502.send_command(Command::TemplateNum);
+    }
+
+    #[test]
+    #[should_panic(expected = "expectations left over: [Command(Match)]")]
+    fn test_leftover_expectations() {
+        // given: a mock expecting two commands
+        let mock = MockTransport::new(
+            0xffffffff,
+            std::vec![Expectation::new(Command::GenImg), Expectation::new(Command::Match)],
+        );
+        let mut r502 = R502::from_serial(mock.clone(), 0xffffffff);
+
+        // when: only the first is sent
+        r502.send_command(Command::GenImg).unwrap();
+
+        // then: checking the script fails the test
+        mock.done();
+    }
+}
@@ -0,0 +1,278 @@
+#[cfg(feature = "cmd-notepad")]
+use byteorder::{BigEndian, ByteOrder};
+
+#[cfg(feature = "cmd-notepad")]
+use crate::commands::Command;
+#[cfg(feature = "cmd-notepad")]
+use crate::driver::R502;
+use crate::consts::{NOTEPAD_PAGES, NOTEPAD_PAGE_SIZE};
+use crate::responses::*;
+#[cfg(feature = "cmd-notepad")]
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// Size of the notepad in bytes.
+pub const NOTEPAD_SIZE: usize = NOTEPAD_PAGES as usize * NOTEPAD_PAGE_SIZE;
+
+/// Bytes of data in a page written with a checksum; the last two bytes hold the CRC.
+pub const NOTEPAD_CHECKED_SIZE: usize = NOTEPAD_PAGE_SIZE - 2;
+
+/// A notepad page number, known to be below `NOTEPAD_PAGES`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NotepadPage(u8);
+
+impl NotepadPage {
+    /// Returns the page with the given number, or `None` if there is no such page.
+    pub fn new(page: u8) -> Option<Self> {
+        if page < NOTEPAD_PAGES {
+            return Some(Self(page));
+        }
+        return None;
+    }
+
+    /// The page number, 0 to 15.
+    pub fn number(self) -> u8 {
+        return self.0;
+    }
+
+    /// Every page, in order.
+    pub fn all() -> impl Iterator<Item = NotepadPage> {
+        return (0..NOTEPAD_PAGES).map(NotepadPage);
+    }
+}
+
+/// Error type for the notepad helpers.
+#[derive(Debug)]
+pub enum NotepadError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// The page could not be read.
+    Read(ReadNotepadStatus),
+
+    /// The page could not be written.
+    Write(WriteNotepadStatus),
+
+    /// The page is blank, as it is on a module where it was never written.
+    NeverWritten(NotepadPage),
+
+    /// The checksum stored in the page does not match its contents.
+    CrcMismatch(NotepadPage),
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for NotepadError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+#[cfg(feature = "cmd-notepad")]
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Writes a whole notepad page.
+    pub fn write_notepad_page(
+        &mut self,
+        page: NotepadPage,
+        data: &[u8; NOTEPAD_PAGE_SIZE],
+    ) -> Result<(), NotepadError<T::WriteError, T::ReadError>> {
+        let command = Command::WriteNotepad { page: page.number(), data: *data };
+        let result = expect_reply!(self.send_command(command), Reply::WriteNotepad)?;
+        return match result.confirmation_code {
+            WriteNotepadStatus::Success => Ok(()),
+            status => Err(NotepadError::Write(status)),
+        };
+    }
+
+    /// Reads a whole notepad page.
+    pub fn read_notepad_page(
+        &mut self,
+        page: NotepadPage,
+    ) -> Result<[u8; NOTEPAD_PAGE_SIZE], NotepadError<T::WriteError, T::ReadError>> {
+        let command = Command::ReadNotepad { page: page.number() };
+        let result = expect_reply!(self.send_command(command), Reply::ReadNotepad)?;
+        return match result.confirmation_code {
+            ReadNotepadStatus::Success => Ok(result.data),
+            status => Err(NotepadError::Read(status)),
+        };
+    }
+
+    /// Reads the whole notepad, one page after another.
+    pub fn read_all_notepad(
+        &mut self,
+    ) -> Result<[u8; NOTEPAD_SIZE], NotepadError<T::WriteError, T::ReadError>> {
+        let mut notepad = [0u8; NOTEPAD_SIZE];
+        for (page, chunk) in NotepadPage::all().zip(notepad.chunks_mut(NOTEPAD_PAGE_SIZE)) {
+            chunk.copy_from_slice(&self.read_notepad_page(page)?);
+        }
+        return Ok(notepad);
+    }
+
+    /// Writes `data` to a notepad page followed by its CRC, so that
+    /// [`read_notepad_page_checked`](#method.read_notepad_page_checked) can tell a page it
+    /// wrote from a blank or damaged one.
+    pub fn write_notepad_page_checked(
+        &mut self,
+        page: NotepadPage,
+        data: &[u8; NOTEPAD_CHECKED_SIZE],
+    ) -> Result<(), NotepadError<T::WriteError, T::ReadError>> {
+        let mut contents = [0u8; NOTEPAD_PAGE_SIZE];
+        contents[..NOTEPAD_CHECKED_SIZE].copy_from_slice(data);
+        BigEndian::write_u16(&mut contents[NOTEPAD_CHECKED_SIZE..], crc16(data));
+        return self.write_notepad_page(page, &contents);
+    }
+
+    /// Reads a page written with
+    /// [`write_notepad_page_checked`](#method.write_notepad_page_checked) and checks its CRC.
+    ///
+    /// # Errors
+    ///
+    /// ## `NotepadError::NeverWritten(page)`
+    /// Returned if the page is all `0x00` or all `0xff`, as blank pages are.
+    ///
+    /// ## `NotepadError::CrcMismatch(page)`
+    /// Returned if the page was written without a CRC, or has been damaged since.
+    pub fn read_notepad_page_checked(
+        &mut self,
+        page: NotepadPage,
+    ) -> Result<[u8; NOTEPAD_CHECKED_SIZE], NotepadError<T::WriteError, T::ReadError>> {
+        let contents = self.read_notepad_page(page)?;
+        if contents.iter().all(|byte| *byte == 0x00) || contents.iter().all(|byte| *byte == 0xff)
+        {
+            return Err(NotepadError::NeverWritten(page));
+        }
+
+        let mut data = [0u8; NOTEPAD_CHECKED_SIZE];
+        data.copy_from_slice(&contents[..NOTEPAD_CHECKED_SIZE]);
+        if BigEndian::read_u16(&contents[NOTEPAD_CHECKED_SIZE..]) != crc16(&data) {
+            return Err(NotepadError::CrcMismatch(page));
+        }
+        return Ok(data);
+    }
+}
+
+/// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xffff.
+pub(crate) fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    return crc;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "cmd-notepad")]
+    use crate::emulator::Emulator;
+
+    #[test]
+    fn test_notepad_page_validation() {
+        // when: making pages from numbers
+        // then: only 0 to 15 are pages
+        assert_eq!(NotepadPage::new(0).map(NotepadPage::number), Some(0));
+        assert_eq!(NotepadPage::new(15).map(NotepadPage::number), Some(15));
+        assert_eq!(NotepadPage::new(16), None);
+        assert_eq!(NotepadPage::all().count(), 16);
+    }
+
+    #[test]
+    fn test_crc16() {
+        // given: the standard check input
+        // when: computing its CRC
+        // then: it matches the published check value
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-notepad")]
+    fn test_notepad_page_round_trip() {
+        // given: a module with a blank notepad
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        let page = NotepadPage::new(7).unwrap();
+
+        // when: writing a page and reading it back
+        r502.write_notepad_page(page, &[0xa5; NOTEPAD_PAGE_SIZE]).unwrap();
+        let data = r502.read_notepad_page(page).unwrap();
+
+        // then: the data made the round trip, and no other page was touched
+        assert_eq!(data, [0xa5; NOTEPAD_PAGE_SIZE]);
+        assert_eq!(emulator.state().notepad[6], [0x00; NOTEPAD_PAGE_SIZE]);
+        assert_eq!(emulator.instructions(), [0x18, 0x19]);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-notepad")]
+    fn test_read_all_notepad() {
+        // given: a module whose every page is filled with its own number
+        let emulator = Emulator::new();
+        for (number, page) in emulator.state().notepad.iter_mut().enumerate() {
+            *page = [number as u8; NOTEPAD_PAGE_SIZE];
+        }
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: reading the whole notepad
+        let notepad = r502.read_all_notepad().unwrap();
+
+        // then: the pages are laid out in order
+        assert_eq!(notepad[0], 0);
+        assert_eq!(notepad[31], 0);
+        assert_eq!(notepad[32], 1);
+        assert_eq!(notepad[NOTEPAD_SIZE - 1], 15);
+        assert_eq!(emulator.instructions().len(), 16);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-notepad")]
+    fn test_notepad_page_checked() {
+        // given: a module with a page written with a CRC
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        let page = NotepadPage::new(2).unwrap();
+        r502.write_notepad_page_checked(page, &[0x42; NOTEPAD_CHECKED_SIZE]).unwrap();
+
+        // when: reading it back
+        let data = r502.read_notepad_page_checked(page).unwrap();
+
+        // then: the data made the round trip
+        assert_eq!(data, [0x42; NOTEPAD_CHECKED_SIZE]);
+
+        // when: the page is damaged and read again
+        emulator.state().notepad[2][5] = 0x43;
+        let result = r502.read_notepad_page_checked(page);
+
+        // then: the damage is noticed
+        match result {
+            Err(NotepadError::CrcMismatch(damaged)) => assert_eq!(damaged, page),
+            other => panic!("Expected NotepadError::CrcMismatch, got {:?}", other),
+        };
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-notepad")]
+    fn test_notepad_page_never_written() {
+        // given: a module with a blank page, and an erased one
+        let emulator = Emulator::new();
+        emulator.state().notepad[4] = [0xff; NOTEPAD_PAGE_SIZE];
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: reading them with a CRC check
+        // then: both are flagged as never written
+        for number in [3, 4] {
+            let page = NotepadPage::new(number).unwrap();
+            match r502.read_notepad_page_checked(page) {
+                Err(NotepadError::NeverWritten(blank)) => assert_eq!(blank, page),
+                other => panic!("Expected NotepadError::NeverWritten, got {:?}", other),
+            };
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use crate::driver::R502;
+use crate::parser::ReplyParser;
+use crate::transport::Transport;
+
+/// Sees every frame going to and coming from the module, byte for byte, for logging and
+/// debugging the protocol. See [`R502::with_observer`](struct.R502.html#method.with_observer).
+pub trait WireObserver {
+    /// A complete frame was written: a command, or a data packet of a template download.
+    fn on_tx(&mut self, frame: &[u8]);
+
+    /// A complete frame, with a good checksum, was read: a reply, or a data packet of a
+    /// template upload. Damaged frames are not reported; the driver reports those itself.
+    fn on_rx(&mut self, frame: &[u8]);
+}
+
+/// A `Transport` which shows the frames passing through it to a `WireObserver`.
+///
+/// The frames are put together from the bytes as they pass, which takes a frame-sized buffer
+/// in each direction (about 2 KiB in all).
+#[derive(Debug)]
+pub struct Observed<T, O> {
+    transport: T,
+    observer: O,
+    tx: ReplyParser,
+    rx: ReplyParser,
+}
+
+impl<T, O> Observed<T, O>
+where
+    T: Transport,
+    O: WireObserver,
+{
+    /// Wraps `transport`, showing what passes through it to `observer`.
+    pub fn new(transport: T, observer: O) -> Self {
+        return Self { transport, observer, tx: ReplyParser::new(), rx: ReplyParser::new() };
+    }
+
+    /// The observer.
+    pub fn observer(&self) -> &O {
+        return &self.observer;
+    }
+
+    /// The observer, for example to change how much it logs.
+    pub fn observer_mut(&mut self) -> &mut O {
+        return &mut self.observer;
+    }
+
+    /// Gives back the transport and observer.
+    pub fn release(self) -> (T, O) {
+        return (self.transport, self.observer);
+    }
+}
+
+impl<T, O> Transport for Observed<T, O>
+where
+    T: Transport,
+    O: WireObserver,
+{
+    type WriteError = T::WriteError;
+    type ReadError = T::ReadError;
+
+    fn write_byte(&mut self, byte: u8) -> nb::Result<(), Self::WriteError> {
+        self.transport.write_byte(byte)?;
+        if let Some(Ok(frame)) = self.tx.push(byte) {
+            self.observer.on_tx(frame.as_bytes());
+        }
+        return Ok(());
+    }
+
+    fn write_slice(&mut self, bytes: &[u8]) -> nb::Result<usize, Self::WriteError> {
+        let taken = self.transport.write_slice(bytes)?;
+        for byte in &bytes[..taken] {
+            if let Some(Ok(frame)) = self.tx.push(*byte) {
+                self.observer.on_tx(frame.as_bytes());
+            }
+        }
+        return Ok(taken);
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::WriteError> {
+        return self.transport.flush();
+    }
+
+    fn read_byte(&mut self) -> nb::Result<u8, Self::ReadError> {
+        let byte = self.transport.read_byte()?;
+        if let Some(Ok(frame)) = self.rx.push(byte) {
+            self.observer.on_rx(frame.as_bytes());
+        }
+        return Ok(byte);
+    }
+}
+
+/// A `WireObserver` which logs every frame as hex through the `log` crate, at `level`, with
+/// the target `hzgrow_r502::wire`.
+#[cfg(feature = "log")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogObserver {
+    pub level: log::Level,
+}
+
+#[cfg(feature = "log")]
+impl LogObserver {
+    /// An observer logging at `level`.
+    pub fn new(level: log::Level) -> Self {
+        return Self { level };
+    }
+}
+
+#[cfg(feature = "log")]
+impl Default for LogObserver {
+    /// An observer logging at `Level::Trace`.
+    fn default() -> Self {
+        return Self::new(log::Level::Trace);
+    }
+}
+
+#[cfg(feature = "log")]
+impl WireObserver for LogObserver {
+    fn on_tx(&mut self, frame: &[u8]) {
+        log::log!(target: "hzgrow_r502::wire", self.level, "tx {:02x?}", frame);
+    }
+
+    fn on_rx(&mut self, frame: &[u8]) {
+        log::log!(target: "hzgrow_r502::wire", self.level, "rx {:02x?}", frame);
+    }
+}
+
+/// A `WireObserver` which logs every frame as hex through `defmt`, at the trace level, for
+/// firmware. Leave it out of a release build with `DEFMT_LOG`.
+#[cfg(feature = "defmt")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DefmtObserver;
+
+#[cfg(feature = "defmt")]
+impl WireObserver for DefmtObserver {
+    fn on_tx(&mut self, frame: &[u8]) {
+        defmt::trace!("tx {=[u8]:02x}", frame);
+    }
+
+    fn on_rx(&mut self, frame: &[u8]) {
+        defmt::trace!("rx {=[u8]:02x}", frame);
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Attaches `observer` to the driver, to be shown every frame sent and received from now
+    /// on. Call it before the first command, or between commands.
+    pub fn with_observer<O>(self, observer: O) -> R502<Observed<T, O>>
+    where
+        O: WireObserver,
+    {
+        return self.map_transport(|transport| Observed::new(transport, observer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::commands::Command;
+    #[cfg(feature = "cmd-transfer")]
+    use crate::consts::{DEFAULT_PACKET_SIZE, R502_TEMPLATE_LEN};
+    #[cfg(feature = "cmd-transfer")]
+    use crate::emulator::char_file;
+    use crate::emulator::{Emulator, EmulatorRx, EmulatorTx};
+    #[cfg(feature = "cmd-transfer")]
+    use crate::template::Template;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::vec::Vec;
+
+    /// What went over the wire, as seen by the transport and as seen by the observer.
+    #[derive(Default)]
+    struct Wire {
+        written: Vec<u8>,
+        read: Vec<u8>,
+        tx_frames: Vec<Vec<u8>>,
+        rx_frames: Vec<Vec<u8>>,
+    }
+
+    /// Records the bytes passing through the emulator's serial port.
+    struct Tap {
+        serial: (EmulatorTx, EmulatorRx),
+        wire: Rc<RefCell<Wire>>,
+    }
+
+    impl Transport for Tap {
+        type WriteError = <(EmulatorTx, EmulatorRx) as Transport>::WriteError;
+        type ReadError = <(EmulatorTx, EmulatorRx) as Transport>::ReadError;
+
+        fn write_byte(&mut self, byte: u8) -> nb::Result<(), Self::WriteError> {
+            self.serial.write_byte(byte)?;
+            self.wire.borrow_mut().written.push(byte);
+            return Ok(());
+        }
+
+        fn flush(&mut self) -> nb::Result<(), Self::WriteError> {
+            return self.serial.flush();
+        }
+
+        fn read_byte(&mut self) -> nb::Result<u8, Self::ReadError> {
+            let byte = self.serial.read_byte()?;
+            self.wire.borrow_mut().read.push(byte);
+            return Ok(byte);
+        }
+    }
+
+    struct Recorder(Rc<RefCell<Wire>>);
+
+    impl WireObserver for Recorder {
+        fn on_tx(&mut self, frame: &[u8]) {
+            self.0.borrow_mut().tx_frames.push(frame.to_vec());
+        }
+
+        fn on_rx(&mut self, frame: &[u8]) {
+            self.0.borrow_mut().rx_frames.push(frame.to_vec());
+        }
+    }
+
+    fn observed(emulator: &Emulator) -> (R502<Observed<Tap, Recorder>>, Rc<RefCell<Wire>>) {
+        let wire = Rc::new(RefCell::new(Wire::default()));
+        let tap = Tap { serial: emulator.serial(), wire: wire.clone() };
+        let r502 = R502::with_transport(tap, 0xffffffff).with_observer(Recorder(wire.clone()));
+        return (r502, wire);
+    }
+
+    #[test]
+    fn test_observe_command() {
+        // given: an observed R502
+        let emulator = Emulator::new();
+        let (mut r502, wire) = observed(&emulator);
+
+        // when: sending two commands
+        r502.send_command(Command::TemplateNum).unwrap();
+        r502.send_command(Command::GenImg).unwrap();
+
+        // then: the observer saw each command and reply as a frame, exactly as they went
+        let wire = wire.borrow();
+        assert_eq!(wire.tx_frames.len(), 2);
+        assert_eq!(wire.rx_frames.len(), 2);
+        assert_eq!(wire.tx_frames.concat(), wire.written);
+        assert_eq!(wire.rx_frames.concat(), wire.read);
+        assert_eq!(wire.tx_frames[0][9], 0x1d);
+        assert_eq!(wire.rx_frames[1][6], 0x07);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_observe_data_packets() {
+        // given: an observed R502 with finger 7 in buffer 1
+        let emulator = Emulator::new();
+        emulator.state().buffers[0] = Some(char_file(7));
+        let (mut r502, wire) = observed(&emulator);
+
+        // when: uploading the template, and downloading it into buffer 2
+        let template = r502.upload_template(1).unwrap();
+        r502.download_template(2, &template).unwrap();
+
+        // then: the observer saw each data packet as a frame of its own
+        let wire = wire.borrow();
+        let data_packets = R502_TEMPLATE_LEN / DEFAULT_PACKET_SIZE.bytes() as usize;
+        assert_eq!(wire.tx_frames.len(), 2 + data_packets);
+        assert_eq!(wire.rx_frames.len(), 2 + data_packets);
+        assert_eq!(wire.tx_frames.concat(), wire.written);
+        assert_eq!(wire.rx_frames.concat(), wire.read);
+        assert_eq!(wire.tx_frames.last().unwrap()[6], 0x08);
+        assert_eq!(Template::from_bytes(&char_file(7)).unwrap(), template);
+    }
+
+    /// Keeps what the `log` crate is given, for `test_log_observer`.
+    #[cfg(feature = "log")]
+    struct Lines;
+
+    #[cfg(feature = "log")]
+    static LINES: std::sync::Mutex<Vec<std::string::String>> = std::sync::Mutex::new(Vec::new());
+
+    #[cfg(feature = "log")]
+    impl log::Log for Lines {
+        fn enabled(&self, _metadata: &log::Metadata<'_>) -> bool {
+            return true;
+        }
+
+        fn log(&self, record: &log::Record<'_>) {
+            let line = std::format!("{} {} {}", record.level(), record.target(), record.args());
+            LINES.lock().unwrap().push(line);
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    #[cfg(feature = "log")]
+    fn test_log_observer() {
+        // given: an R502 observed by a `LogObserver` at debug level
+        log::set_logger(&Lines).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let observer = LogObserver::new(log::Level::Debug);
+        let mut r502 = R502::new(tx, rx, 0xffffffff).with_observer(observer);
+
+        // when: sending a command
+        r502.send_command(Command::TemplateNum).unwrap();
+
+        // then: the command and its reply were logged as hex
+        let lines = LINES.lock().unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            "DEBUG hzgrow_r502::wire tx [ef, 01, ff, ff, ff, ff, 01, 00, 03, 1d, 00, 21]"
+        );
+        let reply = "DEBUG hzgrow_r502::wire rx [ef, 01, ff, ff, ff, ff, 07,";
+        assert_eq!(lines[1].starts_with(reply), true);
+    }
+
+    /// Counts the frames `defmt` is given, for `test_defmt_observer`.
+    #[cfg(feature = "defmt")]
+    #[defmt::global_logger]
+    struct Frames;
+
+    #[cfg(feature = "defmt")]
+    defmt::timestamp!("{=u32}", 0);
+
+    #[cfg(feature = "defmt")]
+    static FRAMES: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    #[cfg(feature = "defmt")]
+    unsafe impl defmt::Logger for Frames {
+        fn acquire() {
+            FRAMES.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+
+        unsafe fn flush() {}
+
+        unsafe fn release() {}
+
+        unsafe fn write(_bytes: &[u8]) {}
+    }
+
+    #[test]
+    #[cfg(feature = "defmt")]
+    fn test_defmt_observer() {
+        // given: an R502 observed by a `DefmtObserver`
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff).with_observer(DefmtObserver);
+
+        // when: sending a command
+        r502.send_command(Command::TemplateNum).unwrap();
+
+        // then: the command and its reply were logged
+        assert_eq!(FRAMES.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,171 @@
+use embedded_hal::blocking::delay::DelayMs;
+
+use crate::transport::Transport;
+
+/// A `Transport` which waits `gap_ms` milliseconds before every frame it sends but the first,
+/// for modules which need some settling time between commands. The wait comes after the
+/// previous reply has arrived, so it is the least time the module gets between replying and
+/// hearing the next command.
+///
+/// A frame is everything written between two flushes, so the data packets sent by
+/// `download_template` count as one frame, paced like a command. For a gap which depends on
+/// the command, see the `TimingProfile` used by `R502::send_command_with_timeout`.
+///
+/// ```ignore
+/// let transport = CommandGap::new((tx, rx), delay, 10);
+/// let mut r502 = R502::with_transport(transport, 0xffffffff);
+/// ```
+#[derive(Debug)]
+pub struct CommandGap<T, D> {
+    transport: T,
+    delay: D,
+    gap_ms: u16,
+    sent_before: bool,
+    writing: bool,
+}
+
+impl<T, D> CommandGap<T, D>
+where
+    T: Transport,
+    D: DelayMs<u16>,
+{
+    /// Wraps `transport`, waiting `gap_ms` on `delay` before every frame after the first.
+    pub fn new(transport: T, delay: D, gap_ms: u16) -> Self {
+        return Self { transport, delay, gap_ms, sent_before: false, writing: false };
+    }
+
+    /// Changes the time to wait before every frame.
+    pub fn set_gap_ms(&mut self, gap_ms: u16) {
+        self.gap_ms = gap_ms;
+    }
+
+    /// Gives back the transport and delay.
+    pub fn release(self) -> (T, D) {
+        return (self.transport, self.delay);
+    }
+    /// Waits out the gap ahead of the first byte of a frame.
+    fn start_frame(&mut self) {
+        if !self.writing {
+            if self.sent_before && self.gap_ms > 0 {
+                self.delay.delay_ms(self.gap_ms);
+            }
+            self.writing = true;
+        }
+    }
+}
+
+impl<T, D> Transport for CommandGap<T, D>
+where
+    T: Transport,
+    D: DelayMs<u16>,
+{
+    type WriteError = T::WriteError;
+    type ReadError = T::ReadError;
+
+    fn write_byte(&mut self, byte: u8) -> nb::Result<(), Self::WriteError> {
+        self.start_frame();
+        return self.transport.write_byte(byte);
+    }
+
+    fn write_slice(&mut self, bytes: &[u8]) -> nb::Result<usize, Self::WriteError> {
+        self.start_frame();
+        return self.transport.write_slice(bytes);
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::WriteError> {
+        self.transport.flush()?;
+        if self.writing {
+            self.writing = false;
+            self.sent_before = true;
+        }
+        return Ok(());
+    }
+
+    fn read_byte(&mut self) -> nb::Result<u8, Self::ReadError> {
+        return self.transport.read_byte();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::commands::Command;
+    use crate::driver::R502;
+    use crate::emulator::{Emulator, EmulatorRx, EmulatorTx};
+    #[cfg(feature = "cmd-transfer")]
+    use crate::template::Template;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// Every delay, with how many instructions the emulator had received by then.
+    type Delays = Rc<RefCell<Vec<(u16, usize)>>>;
+
+    struct CountingDelay {
+        emulator: Emulator,
+        delays: Delays,
+    }
+
+    impl DelayMs<u16> for CountingDelay {
+        fn delay_ms(&mut self, ms: u16) {
+            self.delays.borrow_mut().push((ms, self.emulator.instructions().len()));
+        }
+    }
+
+    type PacedR502 = R502<CommandGap<(EmulatorTx, EmulatorRx), CountingDelay>>;
+
+    fn paced(emulator: &Emulator, gap_ms: u16) -> (PacedR502, Delays) {
+        let delays = Delays::default();
+        let delay = CountingDelay { emulator: emulator.clone(), delays: delays.clone() };
+        let transport = CommandGap::new(emulator.serial(), delay, gap_ms);
+        return (R502::with_transport(transport, 0xffffffff), delays);
+    }
+
+    #[test]
+    fn test_gap_between_commands() {
+        // given: an R502 with a 20 ms gap between commands
+        let emulator = Emulator::new();
+        let (mut r502, delays) = paced(&emulator, 20);
+
+        // when: sending three commands
+        r502.send_command(Command::TemplateNum).unwrap();
+        r502.send_command(Command::GenImg).unwrap();
+        r502.send_command(Command::TemplateNum).unwrap();
+
+        // then: it waited before the second and third, after the previous reply
+        assert_eq!(emulator.instructions(), vec![0x1d, 0x01, 0x1d]);
+        assert_eq!(*delays.borrow(), vec![(20, 1), (20, 2)]);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_gap_before_data_packets() {
+        // given: an R502 with a 5 ms gap between commands
+        let emulator = Emulator::new();
+        let (mut r502, delays) = paced(&emulator, 5);
+
+        // when: downloading a template
+        let template = Template::from_bytes(&[0x11; 40]).unwrap();
+        r502.download_template(1, &template).unwrap();
+
+        // then: the data packets waited for the gap after `DownChar` was acknowledged
+        assert_eq!(*delays.borrow(), vec![(5, 1)]);
+    }
+
+    #[test]
+    fn test_no_gap() {
+        // given: an R502 with no gap between commands
+        let emulator = Emulator::new();
+        let (mut r502, delays) = paced(&emulator, 0);
+
+        // when: sending two commands
+        r502.send_command(Command::TemplateNum).unwrap();
+        r502.send_command(Command::TemplateNum).unwrap();
+
+        // then: it never waited
+        assert_eq!(delays.borrow().is_empty(), true);
+    }
+}
@@ -0,0 +1,332 @@
+use arrayvec::ArrayVec;
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::codec::{checksum, frame_length};
+use crate::consts::{FRAME_CHECKSUM_LENGTH, FRAME_HEADER_LENGTH, MAX_PACKET_LENGTH};
+
+/// Why `ReplyParser::push` threw away what it had received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// The checksum at the end of the frame does not match its contents.
+    BadChecksum,
+
+    /// The header gives a length too short to hold a checksum, or too long for the parser.
+    BadLength,
+}
+
+/// A complete frame, with a good checksum, as put together by `ReplyParser`. Pass
+/// [`as_bytes`](#method.as_bytes) to `decode_reply` to make sense of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawFrame<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> RawFrame<'a> {
+    /// The whole frame, from the start code to the checksum.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        return self.bytes;
+    }
+
+    /// Address of the module which sent the frame.
+    pub fn address(&self) -> u32 {
+        return BigEndian::read_u32(&self.bytes[2..6]);
+    }
+
+    /// The packet ID: `0x07` for a reply, `0x02` or `0x08` for data.
+    pub fn packet_id(&self) -> u8 {
+        return self.bytes[6];
+    }
+
+    /// The contents of the frame between the header and the checksum.
+    pub fn payload(&self) -> &'a [u8] {
+        return &self.bytes[FRAME_HEADER_LENGTH..self.bytes.len() - 2];
+    }
+}
+
+/// Puts frames together from bytes as they arrive, for example from a UART interrupt, one
+/// byte at a time.
+///
+/// Bytes before a start code are skipped. When a frame turns out to be bad, the parser looks
+/// for the next start code among the bytes it already has, so a good frame which began
+/// inside the bad one is not lost.
+#[derive(Debug, Default)]
+pub struct ReplyParser {
+    buffer: ArrayVec<u8, MAX_PACKET_LENGTH>,
+    taken: usize,
+}
+
+impl ReplyParser {
+    /// Creates a parser waiting for the start of a frame.
+    pub fn new() -> Self {
+        return Self::default();
+    }
+
+    /// Takes the next byte received. Returns the frame it completes, if any, or an error if it
+    /// showed the frame so far to be bad. The frame returned is only kept until the next push.
+    pub fn push(&mut self, byte: u8) -> Option<Result<RawFrame<'_>, FrameError>> {
+        if self.taken > 0 {
+            self.buffer.drain(..self.taken);
+            self.taken = 0;
+            self.align(0);
+        }
+        self.buffer.push(byte);
+        self.align(0);
+
+        if self.buffer.len() < FRAME_HEADER_LENGTH {
+            return None;
+        }
+        let length = frame_length(&self.buffer).unwrap();
+        if length < FRAME_HEADER_LENGTH + FRAME_CHECKSUM_LENGTH || length > self.buffer.capacity() {
+            self.align(1);
+            return Some(Err(FrameError::BadLength));
+        }
+        if self.buffer.len() < length {
+            return None;
+        }
+
+        let frame = &self.buffer[..length];
+        if BigEndian::read_u16(&frame[length - 2..]) != checksum(&frame[6..length - 2]) {
+            self.align(1);
+            return Some(Err(FrameError::BadChecksum));
+        }
+        self.taken = length;
+        return Some(Ok(RawFrame { bytes: &self.buffer[..length] }));
+    }
+
+    /// Forgets everything received so far.
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.taken = 0;
+    }
+
+    /// Drops the bytes before the first start code at or after `from`, or all of them if there
+    /// is none. A `0xEF` at the very end counts, as the `0x01` may be yet to come.
+    fn align(&mut self, from: usize) {
+        let start = (from..self.buffer.len()).find(|i| {
+            return self.buffer[*i] == 0xEF
+                && self.buffer.get(*i + 1).is_none_or(|next| *next == 0x01);
+        });
+        match start {
+            Some(0) => {}
+            Some(start) => {
+                self.buffer.drain(..start);
+            }
+            None => self.buffer.clear(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::codec::decode_reply;
+    use crate::commands::CommandKind;
+    use crate::responses::Reply;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// A reply to `TemplateNum`, saying there are 42 templates.
+    const TEMPLATE_NUM_REPLY: [u8; 14] =
+        [0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x05, 0x00, 0x00, 0x2a, 0x00, 0x36];
+
+    /// A reply to `GenImg`, saying there is no finger on the sensor.
+    const GEN_IMG_REPLY: [u8; 12] =
+        [0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x02, 0x00, 0x0c];
+
+    /// Pushes `bytes` in `chunks` of the given sizes, or one at a time if there are no more,
+    /// and collects what comes out, with the position of the byte it came out on.
+    fn feed(
+        parser: &mut ReplyParser,
+        bytes: &[u8],
+        chunks: &[usize],
+    ) -> Vec<(usize, Result<Vec<u8>, FrameError>)> {
+        let mut results = Vec::new();
+        let mut position = 0;
+        let mut chunks = chunks.iter();
+        while position < bytes.len() {
+            let size = *chunks.next().unwrap_or(&1);
+            for byte in &bytes[position..(position + size).min(bytes.len())] {
+                if let Some(result) = parser.push(*byte) {
+                    results.push((position, result.map(|frame| frame.as_bytes().to_vec())));
+                }
+                position += 1;
+            }
+        }
+        return results;
+    }
+
+    #[test]
+    fn test_single_frame() {
+        // given: a parser
+        let mut parser = ReplyParser::new();
+
+        // when: pushing a reply one byte at a time
+        let results = feed(&mut parser, &TEMPLATE_NUM_REPLY, &[]);
+
+        // then: it comes out on its last byte, and decodes
+        assert_eq!(results, [(13, Ok(TEMPLATE_NUM_REPLY.to_vec()))]);
+        match decode_reply(CommandKind::TemplateNum, results[0].1.as_ref().unwrap()) {
+            Ok(Reply::TemplateNum(result)) => assert_eq!(result.template_num, 42),
+            other => panic!("Expected Reply::TemplateNum, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_frame_fields() {
+        // given: a parser with a reply pushed into it, bar the last byte
+        let mut parser = ReplyParser::new();
+        for byte in &TEMPLATE_NUM_REPLY[..13] {
+            assert_eq!(parser.push(*byte), None);
+        }
+
+        // when: pushing the last byte
+        let frame = parser.push(TEMPLATE_NUM_REPLY[13]).unwrap().unwrap();
+
+        // then: the frame can be taken apart
+        assert_eq!(frame.address(), 0xffffffff);
+        assert_eq!(frame.packet_id(), 0x07);
+        assert_eq!(frame.payload(), &[0x00, 0x00, 0x2a]);
+    }
+
+    #[test]
+    fn test_split_deliveries() {
+        // given: two replies back to back
+        let mut bytes = TEMPLATE_NUM_REPLY.to_vec();
+        bytes.extend_from_slice(&GEN_IMG_REPLY);
+
+        for chunks in [vec![26], vec![1, 13], vec![9, 9, 8], vec![3, 14, 2, 7], vec![14, 12]] {
+            // when: delivering them in chunks of varying sizes
+            let mut parser = ReplyParser::new();
+            let results = feed(&mut parser, &bytes, &chunks);
+
+            // then: both come out whole, however they were split
+            assert_eq!(
+                results,
+                [(13, Ok(TEMPLATE_NUM_REPLY.to_vec())), (25, Ok(GEN_IMG_REPLY.to_vec()))],
+                "chunks {:?}",
+                chunks
+            );
+        }
+    }
+
+    #[test]
+    fn test_back_to_back_frames() {
+        // given: the same reply three times over, with nothing in between
+        let bytes: Vec<u8> = GEN_IMG_REPLY.iter().cycle().take(36).cloned().collect();
+
+        // when: pushing them
+        let mut parser = ReplyParser::new();
+        let results = feed(&mut parser, &bytes, &[]);
+
+        // then: each comes out
+        let positions: Vec<usize> = results.iter().map(|(position, _)| *position).collect();
+        assert_eq!(positions, [11, 23, 35]);
+        assert_eq!(results.iter().all(|(_, result)| result.is_ok()), true);
+    }
+
+    #[test]
+    fn test_garbage_prefix() {
+        // given: noise before a reply, including a start code cut short and the ready byte
+        let mut bytes = vec![0x00, 0x55, 0x01, 0xef, 0x00, 0xef, 0xef, 0x02, 0xff];
+        bytes.extend_from_slice(&TEMPLATE_NUM_REPLY);
+
+        // when: pushing it all
+        let mut parser = ReplyParser::new();
+        let results = feed(&mut parser, &bytes, &[]);
+
+        // then: the noise is skipped without fuss
+        assert_eq!(results, [(22, Ok(TEMPLATE_NUM_REPLY.to_vec()))]);
+    }
+
+    #[test]
+    fn test_resync_after_bad_checksum() {
+        // given: a damaged reply, followed by a good one
+        let mut bytes = GEN_IMG_REPLY.to_vec();
+        bytes[9] = 0x00;
+        bytes.extend_from_slice(&TEMPLATE_NUM_REPLY);
+
+        // when: pushing them
+        let mut parser = ReplyParser::new();
+        let results = feed(&mut parser, &bytes, &[]);
+
+        // then: the first is reported, and the second still comes out
+        assert_eq!(
+            results,
+            [(11, Err(FrameError::BadChecksum)), (25, Ok(TEMPLATE_NUM_REPLY.to_vec()))]
+        );
+    }
+
+    #[test]
+    fn test_resync_inside_false_start() {
+        // given: a stray start code right before a reply, so the reply's own start code is
+        // taken for the address at first
+        let mut bytes = vec![0xef, 0x01];
+        bytes.extend_from_slice(&TEMPLATE_NUM_REPLY);
+
+        // when: pushing them
+        let mut parser = ReplyParser::new();
+        let results = feed(&mut parser, &bytes, &[]);
+
+        // then: the false frame's length is rejected, and the reply is found inside it
+        assert_eq!(
+            results,
+            [(8, Err(FrameError::BadLength)), (15, Ok(TEMPLATE_NUM_REPLY.to_vec()))]
+        );
+    }
+
+    #[test]
+    fn test_frame_inside_bad_frame() {
+        // given: a header claiming 14 more bytes, which turn out to hold a whole reply
+        let mut bytes = vec![0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x0e];
+        bytes.extend_from_slice(&GEN_IMG_REPLY);
+        bytes.extend_from_slice(&[0x00, 0x00]);
+
+        // when: pushing them, and then one more byte
+        let mut parser = ReplyParser::new();
+        let mut results = feed(&mut parser, &bytes, &[]);
+        results.extend(feed(&mut parser, &[0x00], &[]));
+
+        // then: the bad frame is reported, and the reply inside it still comes out
+        assert_eq!(results[0], (22, Err(FrameError::BadChecksum)));
+        assert_eq!(results[1], (0, Ok(GEN_IMG_REPLY.to_vec())));
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn test_bad_lengths() {
+        // given: a header claiming no room for a checksum, and one claiming too much room
+        let mut bytes = vec![0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x01];
+        bytes.extend_from_slice(&[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0xff, 0xff]);
+        bytes.extend_from_slice(&GEN_IMG_REPLY);
+
+        // when: pushing them
+        let mut parser = ReplyParser::new();
+        let results = feed(&mut parser, &bytes, &[]);
+
+        // then: both are rejected straight away
+        assert_eq!(
+            results,
+            [
+                (8, Err(FrameError::BadLength)),
+                (17, Err(FrameError::BadLength)),
+                (29, Ok(GEN_IMG_REPLY.to_vec())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reset() {
+        // given: a parser halfway through a reply
+        let mut parser = ReplyParser::new();
+        feed(&mut parser, &TEMPLATE_NUM_REPLY[..7], &[]);
+
+        // when: resetting it and pushing a whole reply
+        parser.reset();
+        let results = feed(&mut parser, &GEN_IMG_REPLY, &[]);
+
+        // then: only the new reply comes out
+        assert_eq!(results, [(11, Ok(GEN_IMG_REPLY.to_vec()))]);
+    }
+}
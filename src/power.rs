@@ -0,0 +1,350 @@
+use arrayvec::ArrayVec;
+use byteorder::{BigEndian, ByteOrder};
+use embedded_hal::blocking::delay::DelayMs;
+
+use crate::commands::Command;
+use crate::driver::R502;
+use crate::responses::*;
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// The byte the R502 sends once it is ready after power-up or a reset.
+pub const READY_BYTE: u8 = 0x55;
+
+/// Most bytes `wait_ready` discards while looking for the ready byte.
+pub const MAX_READY_NOISE: usize = 256;
+
+/// Error type for `wait_ready` and `soft_reset`.
+#[derive(Debug)]
+pub enum ReadyError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// The R502 refused to reset.
+    Rejected(SoftRstStatus),
+
+    /// The ready byte did not arrive in time.
+    Timeout,
+
+    /// More than `MAX_READY_NOISE` other bytes arrived before the ready byte.
+    Noise,
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for ReadyError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+/// Looks for the ready byte among the bytes received, one at a time, for `wait_ready`.
+#[derive(Debug, Default)]
+pub(crate) struct ReadyScanner {
+    discarded: usize,
+    header: ArrayVec<u8, 9>,
+    skip: u16,
+}
+
+impl ReadyScanner {
+    /// Takes the next byte received. True if it is the ready byte; `ReadyError::Noise` once
+    /// too many other bytes have gone by.
+    pub(crate) fn feed<TXE, RXE>(&mut self, byte: u8) -> Result<bool, ReadyError<TXE, RXE>> {
+        if self.skip > 0 {
+            self.skip -= 1;
+        } else {
+            // A packet starts with 0xEF 0x01; anything else after 0xEF is not a packet.
+            if self.header.len() == 1 && byte != 0x01 {
+                self.header.clear();
+            }
+            if self.header.is_empty() && byte != 0xef {
+                if byte == READY_BYTE {
+                    return Ok(true);
+                }
+            } else {
+                self.header.push(byte);
+                if self.header.is_full() {
+                    self.skip = BigEndian::read_u16(&self.header[7..9]);
+                    self.header.clear();
+                }
+            }
+        }
+
+        self.discarded += 1;
+        if self.discarded > MAX_READY_NOISE {
+            return Err(ReadyError::Noise);
+        }
+        return Ok(false);
+    }
+}
+
+/// Error type for `standby`.
+#[derive(Debug)]
+pub enum StandbyError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// The R502 refused to turn its USB port off.
+    PortControl(PortControlStatus),
+
+    /// The R502 refused to go to sleep.
+    Rejected(SleepStatus),
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for StandbyError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Waits for the ready byte the R502 sends after power-up or a reset; commands sent
+    /// before it arrives are silently ignored. Gives up with `ReadyError::Timeout` after
+    /// roughly `timeout_ms` spent waiting for bytes.
+    ///
+    /// Other bytes before the ready byte are discarded, up to `MAX_READY_NOISE` of them. Whole
+    /// packets among them are skipped, so a `0x55` inside a packet is not taken for the ready
+    /// byte. Nothing after the ready byte is read.
+    pub fn wait_ready<D>(
+        &mut self,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<(), ReadyError<T::WriteError, T::ReadError>>
+    where
+        D: DelayMs<u16>,
+    {
+        let mut waited = 0u32;
+        let mut scanner = ReadyScanner::default();
+
+        loop {
+            let byte = match self.read_byte() {
+                Ok(byte) => byte,
+                Err(nb::Error::WouldBlock) => {
+                    if waited >= timeout_ms {
+                        return Err(ReadyError::Timeout);
+                    }
+                    delay.delay_ms(1);
+                    waited += 1;
+                    continue;
+                }
+                Err(nb::Error::Other(error)) => {
+                    return Err(ReadyError::Comms(Error::RecvReadError(error)))
+                }
+            };
+
+            if scanner.feed(byte)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Resets the module with `SoftRst` and waits for it to be ready again, as per
+    /// [`wait_ready`](#method.wait_ready).
+    ///
+    /// The reset ends the session: the password has to be verified again, and the
+    /// _character buffers_ are empty.
+    pub fn soft_reset<D>(
+        &mut self,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<(), ReadyError<T::WriteError, T::ReadError>>
+    where
+        D: DelayMs<u16>,
+    {
+        let result = expect_reply!(self.send_command(Command::SoftRst), Reply::SoftRst)?;
+        match result.confirmation_code {
+            SoftRstStatus::Success => {}
+            status => return Err(ReadyError::Rejected(status)),
+        }
+        return self.wait_ready(delay, timeout_ms);
+    }
+
+    /// Puts the module to sleep, first turning its USB port off if `port_off` is set.
+    ///
+    /// A sleeping module does not answer, so from here on every command fails with
+    /// `Error::ModuleAsleep` without being sent, until [`wake`](#method.wake) is called. Wake
+    /// the module up first, with a touch or by powering it up again.
+    pub fn standby(
+        &mut self,
+        port_off: bool,
+    ) -> Result<(), StandbyError<T::WriteError, T::ReadError>> {
+        if port_off {
+            let result = expect_reply!(
+                self.send_command(Command::PortControl { enable: false }),
+                Reply::PortControl
+            )?;
+            match result.confirmation_code {
+                PortControlStatus::Success => {}
+                status => return Err(StandbyError::PortControl(status)),
+            }
+        }
+
+        let result = expect_reply!(self.send_command(Command::Sleep), Reply::Sleep)?;
+        match result.confirmation_code {
+            SleepStatus::Success => {}
+            status => return Err(StandbyError::Rejected(status)),
+        }
+        self.set_asleep(true);
+        return Ok(());
+    }
+
+    /// Waits for a module put to sleep with [`standby`](#method.standby) to say it is ready,
+    /// as per [`wait_ready`](#method.wait_ready), then checks it answers a `HandShake`.
+    ///
+    /// The driver only lets commands through again once this succeeds. Like a reset, waking
+    /// up ends the session: the password has to be verified again, and the _character
+    /// buffers_ are empty. A USB port turned off by `standby` stays off.
+    pub fn wake<D>(
+        &mut self,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<(), ReadyError<T::WriteError, T::ReadError>>
+    where
+        D: DelayMs<u16>,
+    {
+        self.wait_ready(delay, timeout_ms)?;
+
+        self.set_asleep(false);
+        if let Err(error) = expect_reply!(self.send_command(Command::HandShake), Reply::HandShake)
+        {
+            self.set_asleep(true);
+            return Err(ReadyError::Comms(error));
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{Emulator, NoDelay};
+
+    #[test]
+    fn test_wait_ready_skips_noise() {
+        // given: a module which sends junk and a stray packet containing 0x55 before it is ready
+        let emulator = Emulator::new();
+        emulator.state().would_block = true;
+        emulator.send_raw(&[0x00, 0xef, 0x13]);
+        emulator.send_raw(&[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03]);
+        emulator.send_raw(&[0x55, 0x00, 0x5f]);
+        emulator.send_raw(&[READY_BYTE]);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: waiting for it
+        let result = r502.wait_ready(&mut NoDelay, 100);
+
+        // then: the ready byte is found, and the next reply is read as normal
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(r502.send_command(Command::HandShake).is_ok(), true);
+    }
+
+    #[test]
+    fn test_wait_ready_timeout() {
+        // given: a module which never says it is ready
+        let emulator = Emulator::new();
+        emulator.state().would_block = true;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: waiting for it
+        let result = r502.wait_ready(&mut NoDelay, 100);
+
+        // then: it gives up
+        match result {
+            Err(ReadyError::Timeout) => {}
+            other => panic!("Expected ReadyError::Timeout, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn test_wait_ready_too_much_noise() {
+        // given: a module which sends nothing but junk
+        let emulator = Emulator::new();
+        emulator.state().would_block = true;
+        emulator.send_raw(&[0x00; MAX_READY_NOISE + 1]);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: waiting for it
+        let result = r502.wait_ready(&mut NoDelay, 100);
+
+        // then: it gives up on the noise
+        match result {
+            Err(ReadyError::Noise) => {}
+            other => panic!("Expected ReadyError::Noise, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn test_soft_reset() {
+        // given: an authenticated module which sends some junk as it restarts
+        let emulator = Emulator::new();
+        emulator.state().authenticated = true;
+        emulator.state().boot_output = [0x00, 0xff, READY_BYTE].to_vec();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: resetting it
+        let result = r502.soft_reset(&mut NoDelay, 100);
+
+        // then: the driver waited for the module to be ready, and can talk to it
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(emulator.state().authenticated, false);
+        assert_eq!(r502.send_command(Command::HandShake).is_ok(), true);
+    }
+
+    #[test]
+    fn test_standby_rejects_commands() {
+        // given: a module put to sleep with its USB port turned off
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        assert_eq!(r502.standby(true).is_ok(), true);
+
+        // when: sending it a command
+        let result = r502.send_command(Command::TemplateNum);
+
+        // then: the driver refuses without sending anything
+        match result {
+            Err(Error::ModuleAsleep) => {}
+            other => panic!("Expected Error::ModuleAsleep, got {:?}", other),
+        };
+        assert_eq!(r502.is_asleep(), true);
+        assert_eq!(emulator.instructions(), [0x17, 0x33]);
+        assert_eq!(emulator.state().port_enabled, false);
+    }
+
+    #[test]
+    fn test_standby_wake_cycle() {
+        // given: an authenticated module put to sleep
+        let emulator = Emulator::new();
+        emulator.state().authenticated = true;
+        emulator.state().would_block = true;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        assert_eq!(r502.standby(false).is_ok(), true);
+
+        // when: trying to wake it before it has woken up
+        let result = r502.wake(&mut NoDelay, 100);
+
+        // then: it times out, and the driver still treats the module as asleep
+        match result {
+            Err(ReadyError::Timeout) => {}
+            other => panic!("Expected ReadyError::Timeout, got {:?}", other),
+        };
+        assert_eq!(r502.is_asleep(), true);
+
+        // when: the module is woken up by a touch, and then by the driver
+        emulator.wake();
+        let result = r502.wake(&mut NoDelay, 100);
+
+        // then: the driver talks to it again, and it has forgotten the session
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(r502.is_asleep(), false);
+        assert_eq!(emulator.state().authenticated, false);
+        assert_eq!(r502.send_command(Command::TemplateNum).is_ok(), true);
+        assert_eq!(emulator.instructions(), [0x33, 0x40, 0x1d]);
+    }
+}
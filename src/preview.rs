@@ -0,0 +1,214 @@
+use embedded_graphics_core::geometry::{Dimensions, OriginDimensions, Point, Size};
+use embedded_graphics_core::image::ImageDrawable;
+use embedded_graphics_core::pixelcolor::Gray8;
+use embedded_graphics_core::primitives::{PointsIter, Rectangle};
+use embedded_graphics_core::{draw_target::DrawTarget, Pixel};
+
+use crate::compat::ModuleFamily;
+
+/// How much `ImagePreview` shrinks the sensor's image by. Each pixel of the preview is the
+/// average of a square of the image's, so a smudge still shows as a blur rather than being
+/// skipped over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreviewScale {
+    /// Every pixel: 192 by 192 for the R502.
+    Full,
+
+    /// Every 2 by 2 square averaged to one pixel: 96 by 96 for the R502.
+    Half,
+
+    /// Every 4 by 4 square averaged to one pixel: 48 by 48 for the R502.
+    Quarter,
+}
+
+impl PreviewScale {
+    /// The side of the square of image pixels which makes one preview pixel.
+    pub const fn factor(self) -> u32 {
+        return match self {
+            Self::Full => 1,
+            Self::Half => 2,
+            Self::Quarter => 4,
+        };
+    }
+}
+
+impl Default for PreviewScale {
+    fn default() -> Self {
+        return Self::Full;
+    }
+}
+
+/// A fingerprint image, as the sensor takes it and `DownImage` sends it, drawable with
+/// `embedded-graphics` to show the user their finger on a small display, for example while
+/// enrolling, so smudges and poor placement can be seen.
+///
+/// The data is kept packed, two 4-bit pixels to a byte with the left one in the high bits, row
+/// by row from the top; each pixel is drawn as a `Gray8` from 0 to 255. Nothing is copied, and
+/// pixels are unpacked as they are drawn.
+///
+/// Draw it with `embedded_graphics::image::Image`:
+///
+/// ```ignore
+/// let preview = ImagePreview::new(&image, ModuleFamily::R502).unwrap()
+///     .scaled(PreviewScale::Half);
+/// Image::new(&preview, Point::new(16, 0)).draw(&mut display)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImagePreview<'a> {
+    data: &'a [u8],
+    width: u32,
+    height: u32,
+    scale: PreviewScale,
+}
+
+impl<'a> ImagePreview<'a> {
+    /// Previews `data`, an image from a sensor of `family`, at `PreviewScale::Full`. `None` if
+    /// `data` is not `family.image_len()` bytes long.
+    pub fn new(data: &'a [u8], family: ModuleFamily) -> Option<Self> {
+        if data.len() != family.image_len() {
+            return None;
+        }
+        return Some(Self {
+            data,
+            width: family.image_width(),
+            height: family.image_height(),
+            scale: PreviewScale::Full,
+        });
+    }
+
+    /// The same image, shrunk by `scale`. Rows and columns left over at the right and bottom
+    /// edges, which would make less than a full square, are left out.
+    pub fn scaled(self, scale: PreviewScale) -> Self {
+        return Self { scale, ..self };
+    }
+
+    /// The pixels of the preview, row by row from the top left, as `embedded-graphics` draws
+    /// them.
+    pub fn pixels(&self) -> impl Iterator<Item = Pixel<Gray8>> + '_ {
+        return self
+            .bounding_box()
+            .points()
+            .map(move |point| Pixel(point, self.pixel_at(point)));
+    }
+
+    /// The preview pixel at `point`, which is inside the preview: the average of its square
+    /// of image pixels.
+    fn pixel_at(&self, point: Point) -> Gray8 {
+        let factor = self.scale.factor();
+        let mut sum = 0u32;
+        for y in point.y as u32 * factor..(point.y as u32 + 1) * factor {
+            for x in point.x as u32 * factor..(point.x as u32 + 1) * factor {
+                let index = (y * self.width + x) as usize;
+                let byte = self.data[index / 2];
+                sum += if index.is_multiple_of(2) { byte >> 4 } else { byte & 0x0f } as u32;
+            }
+        }
+        return Gray8::new((sum * 17 / (factor * factor)) as u8);
+    }
+}
+
+impl OriginDimensions for ImagePreview<'_> {
+    fn size(&self) -> Size {
+        let factor = self.scale.factor();
+        return Size::new(self.width / factor, self.height / factor);
+    }
+}
+
+impl ImageDrawable for ImagePreview<'_> {
+    type Color = Gray8;
+
+    fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Gray8>,
+    {
+        return self.draw_sub_image(target, &self.bounding_box());
+    }
+
+    fn draw_sub_image<D>(&self, target: &mut D, area: &Rectangle) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Gray8>,
+    {
+        // As for embedded-graphics' own images, an area not wholly inside draws nothing.
+        let inside = self.bounding_box().intersection(area);
+        if inside != *area || area.is_zero_sized() {
+            return Ok(());
+        }
+        let colors = area.points().map(|point| self.pixel_at(point));
+        return target.fill_contiguous(&Rectangle::new(Point::zero(), area.size), colors);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::consts::{R307_IMAGE_LEN, R502_IMAGE_LEN};
+    use embedded_graphics::image::{Image, ImageDrawableExt};
+    use embedded_graphics::mock_display::MockDisplay;
+    use embedded_graphics::Drawable;
+    use std::vec;
+
+    #[test]
+    fn test_full_scale_pixels() {
+        // given: an image whose first pixels are black, white, and a mid grey under the first
+        let mut data = vec![0u8; R502_IMAGE_LEN];
+        data[0] = 0x0f;
+        data[96] = 0x80;
+        let preview = ImagePreview::new(&data, ModuleFamily::R502).unwrap();
+
+        // when: drawing its top left corner
+        let mut display = MockDisplay::<Gray8>::new();
+        let corner = preview.sub_image(&Rectangle::new(Point::zero(), Size::new(4, 4)));
+        Image::new(&corner, Point::zero()).draw(&mut display).unwrap();
+
+        // then: the pixels were unpacked high bits first, and only the corner was drawn
+        assert_eq!(display.get_pixel(Point::new(0, 0)), Some(Gray8::new(0)));
+        assert_eq!(display.get_pixel(Point::new(1, 0)), Some(Gray8::new(255)));
+        assert_eq!(display.get_pixel(Point::new(0, 1)), Some(Gray8::new(136)));
+        assert_eq!(display.affected_area(), Rectangle::new(Point::zero(), Size::new(4, 4)));
+    }
+
+    #[test]
+    fn test_quarter_scale_averages() {
+        // given: an image with its first 4 by 4 square white, and the next half white
+        let mut data = vec![0u8; R502_IMAGE_LEN];
+        for row in 0..4 {
+            data[row * 96] = 0xff;
+            data[row * 96 + 1] = 0xff;
+        }
+        for row in 0..2 {
+            data[row * 96 + 2] = 0xff;
+            data[row * 96 + 3] = 0xff;
+        }
+        let preview = ImagePreview::new(&data, ModuleFamily::R502)
+            .unwrap()
+            .scaled(PreviewScale::Quarter);
+
+        // when: drawing the whole preview, offset on the display
+        let mut display = MockDisplay::<Gray8>::new();
+        Image::new(&preview, Point::new(8, 8)).draw(&mut display).unwrap();
+
+        // then: it fits, and each pixel is the average of its square
+        assert_eq!(preview.size(), Size::new(48, 48));
+        assert_eq!(display.get_pixel(Point::new(8, 8)), Some(Gray8::new(255)));
+        assert_eq!(display.get_pixel(Point::new(9, 8)), Some(Gray8::new(127)));
+        assert_eq!(display.get_pixel(Point::new(10, 8)), Some(Gray8::new(0)));
+        assert_eq!(display.get_pixel(Point::new(7, 8)), None);
+        assert_eq!(preview.pixels().count(), 48 * 48);
+    }
+
+    #[test]
+    fn test_preview_geometry() {
+        // given: images of both families, and one of the wrong length
+        let r307 = vec![0u8; R307_IMAGE_LEN];
+        let short = vec![0u8; 1000];
+
+        // then: the size follows the family and the scale
+        let preview = ImagePreview::new(&r307, ModuleFamily::R307).unwrap();
+        assert_eq!(preview.size(), Size::new(256, 288));
+        assert_eq!(preview.scaled(PreviewScale::Half).size(), Size::new(128, 144));
+        assert_eq!(ImagePreview::new(&r307, ModuleFamily::R502), None);
+        assert_eq!(ImagePreview::new(&short, ModuleFamily::R502), None);
+    }
+}
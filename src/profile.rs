@@ -0,0 +1,310 @@
+use arrayvec::ArrayVec;
+use embedded_hal::blocking::delay::DelayMs;
+
+use crate::commands::{Command, CommandKind};
+use crate::driver::R502;
+use crate::responses::Reply;
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// Most kinds of command a `TimingProfile` can hold figures of its own for.
+pub const MAX_TIMING_OVERRIDES: usize = 8;
+
+/// How long one kind of command may take, and how long the module needs after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommandTimes {
+    /// How long to wait for the reply, from when the command has been sent, before giving up
+    /// with `Error::Timeout`.
+    pub timeout_ms: u32,
+
+    /// How long to leave the module alone after the reply, before the next command.
+    pub gap_ms: u16,
+}
+
+impl CommandTimes {
+    /// The datasheet figures for `kind`, with some margin. Commands which only read or set a
+    /// register answer within a few milliseconds; those which capture an image, search the
+    /// library or write to flash take up to a few seconds.
+    pub const fn datasheet(kind: CommandKind) -> Self {
+        return match kind {
+            CommandKind::GenImg => Self { timeout_ms: 300, gap_ms: 0 },
+            CommandKind::Img2Tz | CommandKind::Match => Self { timeout_ms: 500, gap_ms: 0 },
+            #[cfg(feature = "cmd-enroll")]
+            CommandKind::RegModel => Self { timeout_ms: 500, gap_ms: 0 },
+            CommandKind::Search => Self { timeout_ms: 1500, gap_ms: 0 },
+            #[cfg(feature = "cmd-enroll")]
+            CommandKind::Store => Self { timeout_ms: 1000, gap_ms: 0 },
+            CommandKind::DeletChar => Self { timeout_ms: 1000, gap_ms: 0 },
+            CommandKind::Empty => Self { timeout_ms: 3000, gap_ms: 0 },
+            #[cfg(feature = "cmd-transfer")]
+            CommandKind::UpChar | CommandKind::DownChar | CommandKind::DownImage => {
+                Self { timeout_ms: 1000, gap_ms: 0 }
+            }
+            CommandKind::SetSysPara | CommandKind::SetPwd | CommandKind::SetAdder => {
+                Self { timeout_ms: 500, gap_ms: 50 }
+            }
+            CommandKind::SoftRst => Self { timeout_ms: 500, gap_ms: 300 },
+            _ => Self { timeout_ms: 200, gap_ms: 0 },
+        };
+    }
+}
+
+/// The timeout and gap after each kind of command, see `CommandTimes`: the datasheet figures,
+/// except where they have been changed with [`set`](#method.set). An `R502` keeps one, see
+/// [`R502::send_command_with_timeout`](struct.R502.html#method.send_command_with_timeout).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimingProfile {
+    overrides: ArrayVec<(CommandKind, CommandTimes), MAX_TIMING_OVERRIDES>,
+}
+
+impl TimingProfile {
+    /// A profile with the datasheet figures for every command.
+    pub const fn datasheet() -> Self {
+        return Self { overrides: ArrayVec::new_const() };
+    }
+
+    /// The times for `kind`.
+    pub fn times(&self, kind: CommandKind) -> CommandTimes {
+        return match self.overrides.iter().find(|(overridden, _)| *overridden == kind) {
+            Some((_, times)) => *times,
+            None => CommandTimes::datasheet(kind),
+        };
+    }
+
+    /// Uses `times` for `kind` from now on, for modules slower or quicker than the datasheet
+    /// says. Fails, changing nothing, if `MAX_TIMING_OVERRIDES` kinds already have their own.
+    pub fn set(&mut self, kind: CommandKind, times: CommandTimes) -> Result<(), TimingProfileFull> {
+        if let Some(entry) = self.overrides.iter_mut().find(|(overridden, _)| *overridden == kind) {
+            entry.1 = times;
+            return Ok(());
+        }
+        return self.overrides.try_push((kind, times)).map_err(|_| TimingProfileFull);
+    }
+
+    /// Goes back to the datasheet figures for `kind`.
+    pub fn reset(&mut self, kind: CommandKind) {
+        self.overrides.retain(|(overridden, _)| *overridden != kind);
+    }
+}
+
+impl Default for TimingProfile {
+    fn default() -> Self {
+        return Self::datasheet();
+    }
+}
+
+/// Error type for `TimingProfile::set`: `MAX_TIMING_OVERRIDES` kinds of command already have
+/// times of their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimingProfileFull;
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// The timeouts and gaps used by
+    /// [`send_command_with_timeout`](#method.send_command_with_timeout).
+    pub fn timing_profile(&self) -> &TimingProfile {
+        return &self.profile;
+    }
+
+    /// The timeouts and gaps used by
+    /// [`send_command_with_timeout`](#method.send_command_with_timeout), to change them.
+    pub fn timing_profile_mut(&mut self) -> &mut TimingProfile {
+        return &mut self.profile;
+    }
+
+    /// Sends `cmd` as [`send_command`](#method.send_command) does, but gives up with
+    /// `Error::Timeout` if the reply is not in within the timeout the
+    /// [`timing_profile`](#method.timing_profile) has for it, counted in 1 ms steps on
+    /// `delay`. Once the reply is in, waits out the gap the profile has after it, so the next
+    /// command can go straight out.
+    ///
+    /// This needs a transport which returns `WouldBlock` while there is nothing to read. A
+    /// reply which arrives after the timeout is not read; see
+    /// [`wait_ready`](#method.wait_ready) to catch a module which is back.
+    pub fn send_command_with_timeout<D>(
+        &mut self,
+        cmd: Command,
+        delay: &mut D,
+    ) -> Result<Reply, Error<T::WriteError, T::ReadError>>
+    where
+        D: DelayMs<u16>,
+    {
+        let times = self.profile.times(cmd.kind());
+        self.start_command(cmd)?;
+        let reply = self.await_reply(delay, times.timeout_ms)?;
+        if times.gap_ms > 0 {
+            delay.delay_ms(times.gap_ms);
+        }
+        return Ok(reply);
+    }
+
+    /// Polls the command in progress until its reply is in, giving up on it after
+    /// `timeout_ms`.
+    pub(crate) fn await_reply<D>(
+        &mut self,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<Reply, Error<T::WriteError, T::ReadError>>
+    where
+        D: DelayMs<u16>,
+    {
+        let mut waited = 0u32;
+        loop {
+            match self.poll() {
+                Ok(reply) => return Ok(reply),
+                Err(nb::Error::Other(error)) => return Err(error),
+                Err(nb::Error::WouldBlock) if waited >= timeout_ms => {
+                    self.abandon();
+                    let error = Error::Timeout;
+                    self.stats.count_error(&error);
+                    return Err(error);
+                }
+                Err(nb::Error::WouldBlock) => {
+                    delay.delay_ms(1);
+                    waited += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::emulator::{Emulator, EmulatorRx, EmulatorTx};
+    use std::vec;
+    use std::vec::Vec;
+
+    /// Every delay asked for.
+    #[derive(Default)]
+    struct RecordingDelay(Vec<u16>);
+
+    impl RecordingDelay {
+        fn total(&self) -> u32 {
+            return self.0.iter().map(|ms| *ms as u32).sum();
+        }
+    }
+
+    impl DelayMs<u16> for RecordingDelay {
+        fn delay_ms(&mut self, ms: u16) {
+            self.0.push(ms);
+        }
+    }
+
+    fn r502(emulator: &Emulator) -> R502<(EmulatorTx, EmulatorRx)> {
+        emulator.state().would_block = true;
+        let (tx, rx) = emulator.serial();
+        return R502::new(tx, rx, 0xffffffff);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_slow_commands_get_more_time() {
+        // given: a module which never answers
+        let emulator = Emulator::new();
+        emulator.state().silent = true;
+        let mut r502 = r502(&emulator);
+
+        // when: sending a `HandShake`, then a `Store`
+        let mut handshake_delay = RecordingDelay::default();
+        let handshake = r502.send_command_with_timeout(Command::HandShake, &mut handshake_delay);
+        let mut store_delay = RecordingDelay::default();
+        let store = Command::Store { buffer: 1, index: 0 };
+        let store = r502.send_command_with_timeout(store, &mut store_delay);
+
+        // then: both timed out, the `Store` after waiting longer
+        assert_eq!(matches!(handshake, Err(Error::Timeout)), true);
+        assert_eq!(matches!(store, Err(Error::Timeout)), true);
+        assert_eq!(handshake_delay.total(), 200);
+        assert_eq!(store_delay.total(), 1000);
+    }
+
+    #[test]
+    fn test_timeout_frees_driver() {
+        // given: a module which does not answer the first command
+        let emulator = Emulator::new();
+        emulator.state().silent = true;
+        let mut r502 = r502(&emulator);
+        let mut delay = RecordingDelay::default();
+        let result = r502.send_command_with_timeout(Command::TemplateNum, &mut delay);
+        assert_eq!(matches!(result, Err(Error::Timeout)), true);
+
+        // when: it answers again
+        emulator.state().silent = false;
+        let result = r502.send_command_with_timeout(Command::TemplateNum, &mut delay);
+
+        // then: the next command goes through
+        assert_eq!(matches!(result, Ok(Reply::TemplateNum(_))), true);
+    }
+
+    #[test]
+    fn test_gap_after_command() {
+        // given: a module which answers
+        let emulator = Emulator::new();
+        let mut r502 = r502(&emulator);
+
+        // when: changing a setting, then asking for the template count
+        let mut delay = RecordingDelay::default();
+        let set = Command::SetSysPara { parameter: 5, value: 3 };
+        r502.send_command_with_timeout(set, &mut delay).unwrap();
+        r502.send_command_with_timeout(Command::TemplateNum, &mut delay).unwrap();
+
+        // then: only the setting is followed by a gap
+        assert_eq!(delay.0, vec![50]);
+    }
+
+    #[test]
+    fn test_overridden_times() {
+        // given: a module which never answers, and a profile with a shorter `HandShake`
+        let emulator = Emulator::new();
+        emulator.state().silent = true;
+        let mut r502 = r502(&emulator);
+        let quick = CommandTimes { timeout_ms: 20, gap_ms: 5 };
+        r502.timing_profile_mut().set(CommandKind::HandShake, quick).unwrap();
+
+        // when: sending a `HandShake`
+        let mut delay = RecordingDelay::default();
+        let result = r502.send_command_with_timeout(Command::HandShake, &mut delay);
+
+        // then: it gave up sooner
+        assert_eq!(matches!(result, Err(Error::Timeout)), true);
+        assert_eq!(delay.total(), 20);
+        assert_eq!(r502.timing_profile().times(CommandKind::HandShake), quick);
+
+        // and: the datasheet figure is back once the override is gone
+        r502.timing_profile_mut().reset(CommandKind::HandShake);
+        let datasheet = CommandTimes::datasheet(CommandKind::HandShake);
+        assert_eq!(r502.timing_profile().times(CommandKind::HandShake), datasheet);
+    }
+
+    #[test]
+    fn test_profile_full() {
+        // given: a profile with as many overrides as it holds
+        let mut profile = TimingProfile::datasheet();
+        let times = CommandTimes { timeout_ms: 10, gap_ms: 0 };
+        let kinds = [
+            CommandKind::ReadSysPara,
+            CommandKind::VfyPwd,
+            CommandKind::GenImg,
+            CommandKind::Img2Tz,
+            CommandKind::Search,
+            CommandKind::LoadChar,
+            CommandKind::Match,
+            CommandKind::TemplateNum,
+        ];
+        for kind in kinds.iter() {
+            profile.set(*kind, times).unwrap();
+        }
+
+        // then: another kind cannot be added, but those already there can be changed
+        assert_eq!(profile.set(CommandKind::HandShake, times), Err(TimingProfileFull));
+        assert_eq!(profile.set(CommandKind::GenImg, times), Ok(()));
+        let datasheet = CommandTimes::datasheet(CommandKind::HandShake);
+        assert_eq!(profile.times(CommandKind::HandShake), datasheet);
+    }
+}
@@ -0,0 +1,364 @@
+
+use crate::commands::Command;
+use crate::config::{ConfigError, ConfigReport, DeviceConfigTarget};
+use crate::driver::R502;
+use crate::responses::*;
+use crate::system::{AuthError, ChangePasswordError};
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// What a factory-fresh module should be turned into by `provision`.
+#[derive(Debug, Clone, Copy)]
+pub struct ProvisioningPlan {
+    /// Address the module ships with, normally `0xffffffff`.
+    pub default_address: u32,
+
+    /// Password the module ships with, normally `0x00000000`.
+    pub default_password: u32,
+
+    /// Address to give the module.
+    pub address: u32,
+
+    /// Password to give the module.
+    pub password: u32,
+
+    /// System parameters to apply, as per `apply_config`.
+    pub config: DeviceConfigTarget,
+}
+
+/// The steps `provision` goes through, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProvisionStep {
+    /// Finding the module at its default or its production address.
+    Handshake,
+
+    /// Setting the production password.
+    Password,
+
+    /// Setting the production address.
+    Address,
+
+    /// Reading the chip serial number.
+    ChipSerial,
+
+    /// Applying the system parameters.
+    Config,
+}
+
+/// How a provisioning step went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// The step changed the module.
+    Done,
+
+    /// The module was already set up this way, so nothing was changed.
+    AlreadyDone,
+}
+
+/// Result of `provision`.
+#[derive(Debug, Clone, Copy)]
+pub struct ProvisionReport {
+    /// Unique serial number of the module's chip, for the records.
+    pub chip_serial: [u8; 32],
+
+    /// What applying the system parameters changed.
+    pub config: ConfigReport,
+}
+
+/// Error type for `provision`. The driver is left talking to whichever address the module
+/// last answered on.
+#[derive(Debug)]
+pub enum ProvisionError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// The module answered neither at its default nor at its production address.
+    NotResponding,
+
+    /// The module accepted neither the default nor the production password, so it has been
+    /// set up by someone else.
+    UnknownPassword,
+
+    /// Verifying a password failed for a reason other than a wrong password.
+    Auth(AuthError<TXE, RXE>),
+
+    /// Setting the production password failed.
+    ChangePassword(ChangePasswordError<TXE, RXE>),
+
+    /// The module refused the new address.
+    Address(SetAdderStatus),
+
+    /// The module accepted the new address, but did not answer on it afterwards.
+    AddressUnconfirmed(Error<TXE, RXE>),
+
+    /// The chip serial number could not be read.
+    ChipSerial(GetChipSNStatus),
+
+    /// Applying the system parameters failed.
+    Config(ConfigError<TXE, RXE>),
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for ProvisionError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Brings up a module according to `plan`: finds it, sets the production password and
+    /// address, reads the chip serial number and applies the system parameters. `on_step` is
+    /// told how each step went.
+    ///
+    /// This is safe to run again on a module which has already been provisioned, fully or in
+    /// part: steps whose outcome is already in place are reported as `StepOutcome::AlreadyDone`.
+    /// A module set up with a different password fails with `ProvisionError::UnknownPassword`
+    /// without anything being changed.
+    ///
+    /// The driver's address is updated as the module's changes. The chip serial number is read
+    /// before the system parameters are applied, as a baud rate change ends the session.
+    pub fn provision<F>(
+        &mut self,
+        plan: &ProvisioningPlan,
+        mut on_step: F,
+    ) -> Result<ProvisionReport, ProvisionError<T::WriteError, T::ReadError>>
+    where
+        F: FnMut(ProvisionStep, StepOutcome),
+    {
+        self.set_address(plan.default_address);
+        if self.send_command(Command::HandShake).is_ok() {
+            on_step(ProvisionStep::Handshake, StepOutcome::Done);
+        } else {
+            self.set_address(plan.address);
+            if self.send_command(Command::HandShake).is_err() {
+                return Err(ProvisionError::NotResponding);
+            }
+            on_step(ProvisionStep::Handshake, StepOutcome::AlreadyDone);
+        }
+
+        let outcome = self.provision_password(plan)?;
+        on_step(ProvisionStep::Password, outcome);
+
+        let outcome = self.provision_address(plan)?;
+        on_step(ProvisionStep::Address, outcome);
+
+        let result = expect_reply!(self.send_command(Command::GetChipSN), Reply::GetChipSN)?;
+        match result.confirmation_code {
+            GetChipSNStatus::Success => {}
+            status => return Err(ProvisionError::ChipSerial(status)),
+        }
+        on_step(ProvisionStep::ChipSerial, StepOutcome::Done);
+
+        let config = self
+            .apply_config(&plan.config)
+            .map_err(ProvisionError::Config)?;
+        let outcome = if config.is_noop() {
+            StepOutcome::AlreadyDone
+        } else {
+            StepOutcome::Done
+        };
+        on_step(ProvisionStep::Config, outcome);
+
+        return Ok(ProvisionReport {
+            chip_serial: result.serial_number,
+            config,
+        });
+    }
+
+    fn provision_password(
+        &mut self,
+        plan: &ProvisioningPlan,
+    ) -> Result<StepOutcome, ProvisionError<T::WriteError, T::ReadError>> {
+        match self.authenticate(plan.default_password) {
+            Ok(_) if plan.default_password == plan.password => {
+                return Ok(StepOutcome::AlreadyDone);
+            }
+            Ok(_) => {
+                self.change_password(plan.default_password, plan.password)
+                    .map_err(ProvisionError::ChangePassword)?;
+                return Ok(StepOutcome::Done);
+            }
+            Err(AuthError::WrongPassword) => {}
+            Err(error) => return Err(ProvisionError::Auth(error)),
+        }
+
+        return match self.authenticate(plan.password) {
+            Ok(_) => Ok(StepOutcome::AlreadyDone),
+            Err(AuthError::WrongPassword) => Err(ProvisionError::UnknownPassword),
+            Err(error) => Err(ProvisionError::Auth(error)),
+        };
+    }
+
+    fn provision_address(
+        &mut self,
+        plan: &ProvisioningPlan,
+    ) -> Result<StepOutcome, ProvisionError<T::WriteError, T::ReadError>> {
+        if self.address() == plan.address {
+            return Ok(StepOutcome::AlreadyDone);
+        }
+
+        let result = expect_reply!(
+            self.send_command(Command::SetAdder {
+                address: plan.address
+            }),
+            Reply::SetAdder
+        )?;
+        match result.confirmation_code {
+            SetAdderStatus::Success => {}
+            status => return Err(ProvisionError::Address(status)),
+        }
+
+        self.set_address(plan.address);
+        self.send_command(Command::HandShake)
+            .map_err(ProvisionError::AddressUnconfirmed)?;
+        return Ok(StepOutcome::Done);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::emulator::Emulator;
+    use std::vec;
+    use std::vec::Vec;
+
+    fn plan() -> ProvisioningPlan {
+        return ProvisioningPlan {
+            default_address: 0xffffffff,
+            default_password: 0x00000000,
+            address: 0x00000042,
+            password: 0x13371337,
+            config: DeviceConfigTarget {
+                security_level: Some(4),
+                ..DeviceConfigTarget::default()
+            },
+        };
+    }
+
+    #[test]
+    fn test_provision_fresh_module() {
+        // given: a factory-fresh module
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: provisioning it
+        let mut steps = Vec::new();
+        let report = r502
+            .provision(&plan(), |step, outcome| steps.push((step, outcome)))
+            .unwrap();
+
+        // then: every step changed the module
+        assert_eq!(
+            steps,
+            vec![
+                (ProvisionStep::Handshake, StepOutcome::Done),
+                (ProvisionStep::Password, StepOutcome::Done),
+                (ProvisionStep::Address, StepOutcome::Done),
+                (ProvisionStep::ChipSerial, StepOutcome::Done),
+                (ProvisionStep::Config, StepOutcome::Done),
+            ]
+        );
+        assert_eq!(report.chip_serial, [0x5a; 32]);
+
+        // and: the module and driver agree on the new identity
+        assert_eq!(emulator.state().password, 0x13371337);
+        assert_eq!(emulator.state().address, 0x00000042);
+        assert_eq!(emulator.state().security_level, 4);
+        assert_eq!(r502.address(), 0x00000042);
+    }
+
+    #[test]
+    fn test_provision_already_provisioned() {
+        // given: a module that has already been provisioned
+        let emulator = Emulator::new();
+        emulator.state().address = 0x00000042;
+        emulator.state().password = 0x13371337;
+        emulator.state().security_level = 4;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: provisioning it again
+        let mut steps = Vec::new();
+        let result = r502.provision(&plan(), |step, outcome| steps.push((step, outcome)));
+
+        // then: nothing is changed
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(
+            steps,
+            vec![
+                (ProvisionStep::Handshake, StepOutcome::AlreadyDone),
+                (ProvisionStep::Password, StepOutcome::AlreadyDone),
+                (ProvisionStep::Address, StepOutcome::AlreadyDone),
+                (ProvisionStep::ChipSerial, StepOutcome::Done),
+                (ProvisionStep::Config, StepOutcome::AlreadyDone),
+            ]
+        );
+        assert_eq!(emulator.instructions().contains(&0x12), false);
+        assert_eq!(emulator.instructions().contains(&0x15), false);
+    }
+
+    #[test]
+    fn test_provision_half_provisioned() {
+        // given: a module whose password was set, but whose address was not
+        let emulator = Emulator::new();
+        emulator.state().password = 0x13371337;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: provisioning it
+        let mut steps = Vec::new();
+        let result = r502.provision(&plan(), |step, outcome| steps.push((step, outcome)));
+
+        // then: the remaining steps are carried out
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(
+            steps[1],
+            (ProvisionStep::Password, StepOutcome::AlreadyDone)
+        );
+        assert_eq!(steps[2], (ProvisionStep::Address, StepOutcome::Done));
+        assert_eq!(emulator.state().address, 0x00000042);
+    }
+
+    #[test]
+    fn test_provision_foreign_module() {
+        // given: a module set up with some other password
+        let emulator = Emulator::new();
+        emulator.state().password = 0xdeadbeef;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: provisioning it
+        let result = r502.provision(&plan(), |_, _| {});
+
+        // then: it fails without changing anything
+        match result {
+            Err(ProvisionError::UnknownPassword) => {}
+            other => panic!("Expected ProvisionError::UnknownPassword, got {:?}", other),
+        };
+        assert_eq!(emulator.state().password, 0xdeadbeef);
+        assert_eq!(emulator.state().address, 0xffffffff);
+    }
+
+    #[test]
+    fn test_provision_not_responding() {
+        // given: a module which does not answer at all
+        let emulator = Emulator::new();
+        emulator.state().silent = true;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: provisioning it
+        let result = r502.provision(&plan(), |_, _| {});
+
+        // then: the missing module is reported
+        match result {
+            Err(ProvisionError::NotResponding) => {}
+            other => panic!("Expected ProvisionError::NotResponding, got {:?}", other),
+        };
+    }
+}
@@ -0,0 +1,228 @@
+/// Pixels at or below this value count as saturated: the sensor saw nothing but ridge, which
+/// happens when the finger is pressed too hard or is wet.
+pub const QUALITY_DARK_LEVEL: u8 = 32;
+
+/// Pixels at or above this value count as blank: the sensor saw no skin there at all.
+pub const QUALITY_BLANK_LEVEL: u8 = 224;
+
+/// Lowest mean pixel value `ImageQuality::is_acceptable` allows.
+pub const QUALITY_MIN_MEAN: u8 = 48;
+
+/// Highest mean pixel value `ImageQuality::is_acceptable` allows.
+pub const QUALITY_MAX_MEAN: u8 = 208;
+
+/// Lowest contrast `ImageQuality::is_acceptable` allows. A sharp print of ridges and valleys
+/// scores well above this; a smudge or an empty sensor scores well below.
+pub const QUALITY_MIN_CONTRAST: u8 = 24;
+
+/// Most saturated pixels `ImageQuality::is_acceptable` allows, per mille.
+pub const QUALITY_MAX_DARK_PERMILLE: u16 = 250;
+
+/// Most blank pixels `ImageQuality::is_acceptable` allows, per mille. The edges of the image
+/// are usually blank, even with a finger placed well.
+pub const QUALITY_MAX_BLANK_PERMILLE: u16 = 500;
+
+/// Why an image is not good enough to enrol or match with, as reported by
+/// `ImageQuality::problem`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QualityProblem {
+    /// Too little of the finger touched the sensor. Ask the user to press harder.
+    TooLight,
+
+    /// The ridges have run together. Ask the user to press more lightly, or to dry their
+    /// finger.
+    TooDark,
+
+    /// The ridges cannot be told apart, most likely because the finger moved or the sensor is
+    /// dirty. Ask the user to try again, holding still.
+    LowContrast,
+}
+
+/// Figures describing a fingerprint image, for deciding on the host whether a capture is worth
+/// processing. Integer arithmetic only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageQuality {
+    /// Mean pixel value, from 0 (black) to 255 (white).
+    pub mean: u8,
+
+    /// Mean absolute difference between neighbouring pixels, horizontally and vertically, from
+    /// 0 to 255. Ridges make this high; smooth shading does not.
+    pub contrast: u8,
+
+    /// Pixels at or below `QUALITY_DARK_LEVEL`, per mille.
+    pub dark_permille: u16,
+
+    /// Pixels at or above `QUALITY_BLANK_LEVEL`, per mille.
+    pub blank_permille: u16,
+}
+
+impl ImageQuality {
+    /// Analyses an image of 8-bit pixels, one byte each, stored row by row, `width` pixels to
+    /// a row. An image with no pixels has every figure at 0.
+    pub fn analyze(pixels: &[u8], width: usize) -> Self {
+        if pixels.is_empty() {
+            return Self { mean: 0, contrast: 0, dark_permille: 0, blank_permille: 0 };
+        }
+        let width = if width == 0 { pixels.len() } else { width };
+
+        let mut sum = 0u64;
+        let mut dark = 0u64;
+        let mut blank = 0u64;
+        let mut differences = 0u64;
+        let mut pairs = 0u64;
+        for (i, pixel) in pixels.iter().enumerate() {
+            sum += *pixel as u64;
+            if *pixel <= QUALITY_DARK_LEVEL {
+                dark += 1;
+            }
+            if *pixel >= QUALITY_BLANK_LEVEL {
+                blank += 1;
+            }
+            if i % width != 0 {
+                differences += (*pixel as i16 - pixels[i - 1] as i16).unsigned_abs() as u64;
+                pairs += 1;
+            }
+            if i >= width {
+                differences += (*pixel as i16 - pixels[i - width] as i16).unsigned_abs() as u64;
+                pairs += 1;
+            }
+        }
+
+        let count = pixels.len() as u64;
+        return Self {
+            mean: (sum / count) as u8,
+            contrast: differences.checked_div(pairs).unwrap_or(0) as u8,
+            dark_permille: (dark * 1000 / count) as u16,
+            blank_permille: (blank * 1000 / count) as u16,
+        };
+    }
+
+    /// What is wrong with the image, or `None` if all the figures are within the thresholds
+    /// above. The most likely cause is reported if several figures are out.
+    pub fn problem(&self) -> Option<QualityProblem> {
+        if self.blank_permille > QUALITY_MAX_BLANK_PERMILLE || self.mean > QUALITY_MAX_MEAN {
+            return Some(QualityProblem::TooLight);
+        }
+        if self.dark_permille > QUALITY_MAX_DARK_PERMILLE || self.mean < QUALITY_MIN_MEAN {
+            return Some(QualityProblem::TooDark);
+        }
+        if self.contrast < QUALITY_MIN_CONTRAST {
+            return Some(QualityProblem::LowContrast);
+        }
+        return None;
+    }
+
+    /// True if the image is worth processing, as per [`problem`](#method.problem).
+    pub fn is_acceptable(&self) -> bool {
+        return self.problem().is_none();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 48;
+
+    fn image<F: Fn(usize, usize) -> u8>(pixel: F) -> Vec<u8> {
+        return (0..WIDTH * HEIGHT).map(|i| pixel(i % WIDTH, i / WIDTH)).collect();
+    }
+
+    #[test]
+    fn test_all_black() {
+        // given: an image with every pixel black
+        let pixels = image(|_, _| 0);
+
+        // when: analysing it
+        let quality = ImageQuality::analyze(&pixels, WIDTH);
+
+        // then: it is saturated
+        assert_eq!(
+            quality,
+            ImageQuality { mean: 0, contrast: 0, dark_permille: 1000, blank_permille: 0 }
+        );
+        assert_eq!(quality.problem(), Some(QualityProblem::TooDark));
+    }
+
+    #[test]
+    fn test_all_white() {
+        // given: an image with every pixel white, as with no finger on the sensor
+        let pixels = image(|_, _| 255);
+
+        // when: analysing it
+        let quality = ImageQuality::analyze(&pixels, WIDTH);
+
+        // then: it is blank
+        assert_eq!(
+            quality,
+            ImageQuality { mean: 255, contrast: 0, dark_permille: 0, blank_permille: 1000 }
+        );
+        assert_eq!(quality.problem(), Some(QualityProblem::TooLight));
+    }
+
+    #[test]
+    fn test_checkerboard() {
+        // given: a checkerboard of black and white pixels
+        let pixels = image(|x, y| if (x + y) % 2 == 0 { 0 } else { 255 });
+
+        // when: analysing it
+        let quality = ImageQuality::analyze(&pixels, WIDTH);
+
+        // then: it has all the contrast there is, but half of it is saturated
+        assert_eq!(quality.mean, 127);
+        assert_eq!(quality.contrast, 255);
+        assert_eq!(quality.dark_permille, 500);
+        assert_eq!(quality.blank_permille, 500);
+        assert_eq!(quality.problem(), Some(QualityProblem::TooDark));
+    }
+
+    #[test]
+    fn test_smooth_gradient() {
+        // given: shading from dark to light with no ridges, as from a smudged finger
+        let pixels = image(|x, _| (64 + x * 2) as u8);
+
+        // when: analysing it
+        let quality = ImageQuality::analyze(&pixels, WIDTH);
+
+        // then: the contrast is too low
+        assert_eq!(quality.contrast, 1);
+        assert_eq!(quality.problem(), Some(QualityProblem::LowContrast));
+    }
+
+    #[test]
+    fn test_realistic_print() {
+        // given: diagonal ridges every 6 pixels, over shading which gets lighter to the right
+        let pixels = image(|x, y| {
+            let ridge = [40u8, 70, 130, 190, 130, 70][(x + y) % 6];
+            return ridge.saturating_add((x / 4) as u8);
+        });
+
+        // when: analysing it
+        let quality = ImageQuality::analyze(&pixels, WIDTH);
+
+        // then: it is acceptable
+        assert_eq!(quality.is_acceptable(), true);
+        assert_eq!(quality.dark_permille, 0);
+        assert_eq!(quality.blank_permille, 0);
+        assert_eq!(quality.contrast >= QUALITY_MIN_CONTRAST, true);
+    }
+
+    #[test]
+    fn test_rows_do_not_wrap() {
+        // given: two rows, one black and one white
+        let pixels = [0u8, 0, 0, 255, 255, 255];
+
+        // when: analysing them as rows of three, and as a single row
+        let rows = ImageQuality::analyze(&pixels, 3);
+        let row = ImageQuality::analyze(&pixels, 0);
+
+        // then: only the vertical neighbours differ in the first, and one pair in the second
+        assert_eq!(rows.contrast, (3 * 255 / 7) as u8);
+        assert_eq!(row.contrast, (255 / 5) as u8);
+        assert_eq!(ImageQuality::analyze(&[], 3).mean, 0);
+    }
+}
@@ -0,0 +1,192 @@
+use core::fmt;
+
+use crate::commands::Command;
+use crate::driver::R502;
+use crate::responses::*;
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// Error type for `random_code`, and for `ModuleRng`.
+#[derive(Debug)]
+pub enum RandomError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// The R502 could not generate a random number.
+    Refused(GetRandomCodeStatus),
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for RandomError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+/// Needed for `rand_core::TryRngCore::Error`. The serial port errors are shown with `Debug`.
+impl<TXE, RXE> fmt::Display for RandomError<TXE, RXE>
+where
+    TXE: fmt::Debug,
+    RXE: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Self::Comms(error) => write!(f, "GetRandomCode failed: {:?}", error),
+            Self::Refused(status) => write!(f, "GetRandomCode refused: {}", status),
+        };
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Reads a 32-bit random number from the module with `GetRandomCode`.
+    ///
+    /// The datasheet says nothing of how the numbers are made, so do not rely on them for
+    /// keys or nonces without mixing them with another source.
+    pub fn random_code(&mut self) -> Result<u32, RandomError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(
+            self.send_command(Command::GetRandomCode),
+            Reply::GetRandomCode
+        )?;
+        return match result.confirmation_code {
+            GetRandomCodeStatus::Success => Ok(result.random_code),
+            status => Err(RandomError::Refused(status)),
+        };
+    }
+
+    /// Borrows the driver as a `rand_core` random number generator, see `ModuleRng`.
+    #[cfg(feature = "rand")]
+    pub fn rng(&mut self) -> ModuleRng<'_, T> {
+        return ModuleRng { r502: self, spare: [0; 4], spare_len: 0 };
+    }
+}
+
+/// A `rand_core::TryRngCore` fed by the module's `GetRandomCode`, made by
+/// [`R502::rng`](struct.R502.html#method.rng).
+///
+/// Each round trip to the module brings 32 bits. Bytes left over from one call are kept for
+/// the next, so `fill_bytes` of 6 bytes then 2 costs two round trips, not three. A failed
+/// round trip is returned as the `RandomError`, and leaves the bytes already filled in place.
+///
+/// Use `unwrap_err()` for an infallible `RngCore`, which panics on an error instead, or
+/// `unwrap_mut()` for one borrowing this. It is not a `CryptoRng`, for the reasons given at
+/// [`R502::random_code`](struct.R502.html#method.random_code).
+#[cfg(feature = "rand")]
+#[derive(Debug)]
+pub struct ModuleRng<'a, T> {
+    r502: &'a mut R502<T>,
+    spare: [u8; 4],
+    spare_len: usize,
+}
+
+#[cfg(feature = "rand")]
+impl<T> rand_core::TryRngCore for ModuleRng<'_, T>
+where
+    T: Transport,
+    T::WriteError: fmt::Debug,
+    T::ReadError: fmt::Debug,
+{
+    type Error = RandomError<T::WriteError, T::ReadError>;
+
+    fn try_next_u32(&mut self) -> Result<u32, Self::Error> {
+        let mut bytes = [0; 4];
+        self.try_fill_bytes(&mut bytes)?;
+        return Ok(u32::from_be_bytes(bytes));
+    }
+
+    fn try_next_u64(&mut self) -> Result<u64, Self::Error> {
+        let mut bytes = [0; 8];
+        self.try_fill_bytes(&mut bytes)?;
+        return Ok(u64::from_be_bytes(bytes));
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Self::Error> {
+        for byte in dest.iter_mut() {
+            if self.spare_len == 0 {
+                self.spare = self.r502.random_code()?.to_be_bytes();
+                self.spare_len = self.spare.len();
+            }
+            *byte = self.spare[self.spare.len() - self.spare_len];
+            self.spare_len -= 1;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(all(test, feature = "rand"))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::mock::{Expectation, MockTransport};
+    use rand_core::{RngCore, TryRngCore};
+    use std::vec;
+
+    fn code(random: u32) -> Expectation {
+        return Expectation::new(Command::GetRandomCode).reply(0x00, &random.to_be_bytes());
+    }
+
+    #[test]
+    fn test_fill_bytes_across_codes() {
+        // given: a module which will hand out three known numbers
+        let mock = MockTransport::new(
+            0xffffffff,
+            vec![code(0x01020304), code(0x05060708), code(0x090a0b0c)],
+        );
+        let mut r502 = R502::from_serial(mock.clone(), 0xffffffff);
+        let mut rng = r502.rng();
+
+        // when: filling 6 bytes, then 2, then reading a u32
+        let mut first = [0; 6];
+        let mut second = [0; 2];
+        rng.try_fill_bytes(&mut first).unwrap();
+        rng.try_fill_bytes(&mut second).unwrap();
+        let last = rng.unwrap_mut().next_u32();
+
+        // then: the numbers were stitched together in order, with nothing skipped
+        assert_eq!(first, [0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+        assert_eq!(second, [0x07, 0x08]);
+        assert_eq!(last, 0x090a0b0c);
+        mock.done();
+    }
+
+    #[test]
+    fn test_next_u64() {
+        // given: a module which will hand out two known numbers
+        let mock = MockTransport::new(0xffffffff, vec![code(0xdeadbeef), code(0x00c0ffee)]);
+        let mut r502 = R502::from_serial(mock.clone(), 0xffffffff);
+
+        // when: reading a u64
+        let random = r502.rng().try_next_u64().unwrap();
+
+        // then: it is the two numbers, first one high
+        assert_eq!(random, 0xdeadbeef_00c0ffee);
+        mock.done();
+    }
+
+    #[test]
+    fn test_refused_code() {
+        // given: a module which cannot generate the second number
+        let mock = MockTransport::new(
+            0xffffffff,
+            vec![
+                code(0x01020304),
+                Expectation::new(Command::GetRandomCode).reply(0x01, &[0; 4]),
+            ],
+        );
+        let mut r502 = R502::from_serial(mock.clone(), 0xffffffff);
+
+        // when: filling more bytes than one number holds
+        let mut bytes = [0; 6];
+        let result = r502.rng().try_fill_bytes(&mut bytes);
+
+        // then: the error comes back, after the bytes which could be filled
+        match result {
+            Err(RandomError::Refused(GetRandomCodeStatus::PacketError)) => {}
+            other => panic!("Expected RandomError::Refused, got {:?}", other),
+        };
+        assert_eq!(bytes, [0x01, 0x02, 0x03, 0x04, 0x00, 0x00]);
+        mock.done();
+    }
+}
@@ -0,0 +1,194 @@
+#[cfg(feature = "cmd-enroll")]
+use embedded_hal::blocking::delay::DelayMs;
+
+use crate::driver::R502;
+#[cfg(feature = "cmd-enroll")]
+use crate::enroll::{EnrollConfig, EnrollError, EnrollPrompt};
+use crate::identify::{IdentifyConfig, IdentifyError};
+use crate::library::LibraryError;
+use crate::maintenance::{DeleteError, DeleteReport};
+use crate::responses::SystemParameters;
+use crate::system::AuthError;
+use crate::transport::Transport;
+
+/// The high-level operations application code usually needs from a fingerprint module, for
+/// code which should not care whether it talks to an `R502` or to a stand-in. Write the
+/// application against this trait, and test it against `FakeReader` (with the `mock`
+/// feature), which answers each call as scripted with no serial port involved.
+///
+/// The errors are the driver's own, over the serial port's `WriteError` and `ReadError`, so
+/// the application handles the same cases either way.
+///
+/// The trait is object safe: `enroll` takes its delay and prompt callback as trait objects,
+/// so code can hold a `&mut dyn FingerprintReader<WriteError = _, ReadError = _>`.
+///
+/// ```ignore
+/// fn open_door<F>(reader: &mut F) -> bool
+/// where
+///     F: FingerprintReader,
+/// {
+///     return reader.identify(&IdentifyConfig::default()).is_ok();
+/// }
+/// ```
+pub trait FingerprintReader {
+    /// Error the serial port writes with.
+    type WriteError;
+
+    /// Error the serial port reads with.
+    type ReadError;
+
+    /// Verifies `password` and returns the system parameters, see
+    /// [`R502::authenticate`](struct.R502.html#method.authenticate).
+    fn authenticate(
+        &mut self,
+        password: u32,
+    ) -> Result<SystemParameters, AuthError<Self::WriteError, Self::ReadError>>;
+
+    /// Captures the finger on the sensor and searches the library for it, returning the match
+    /// as `(index, score)`, see [`R502::identify`](struct.R502.html#method.identify).
+    fn identify(
+        &mut self,
+        config: &IdentifyConfig,
+    ) -> Result<(u16, u16), IdentifyError<Self::WriteError, Self::ReadError>>;
+
+    /// Enrols a finger at `index`, see [`R502::enroll`](struct.R502.html#method.enroll).
+    #[cfg(feature = "cmd-enroll")]
+    fn enroll(
+        &mut self,
+        index: u16,
+        config: &EnrollConfig,
+        delay: &mut dyn DelayMs<u16>,
+        prompts: &mut dyn FnMut(EnrollPrompt),
+    ) -> Result<(), EnrollError<Self::WriteError, Self::ReadError>>;
+
+    /// Deletes the templates at `indices`, see
+    /// [`R502::delete_indices`](struct.R502.html#method.delete_indices).
+    fn delete(
+        &mut self,
+        indices: &[u16],
+    ) -> Result<DeleteReport, DeleteError<Self::WriteError, Self::ReadError>>;
+
+    /// Returns the number of templates stored in the library, see
+    /// [`R502::template_count`](struct.R502.html#method.template_count).
+    fn template_count(&mut self) -> Result<u16, LibraryError<Self::WriteError, Self::ReadError>>;
+}
+
+impl<T> FingerprintReader for R502<T>
+where
+    T: Transport,
+{
+    type WriteError = T::WriteError;
+    type ReadError = T::ReadError;
+
+    fn authenticate(
+        &mut self,
+        password: u32,
+    ) -> Result<SystemParameters, AuthError<T::WriteError, T::ReadError>> {
+        return R502::authenticate(self, password);
+    }
+
+    fn identify(
+        &mut self,
+        config: &IdentifyConfig,
+    ) -> Result<(u16, u16), IdentifyError<T::WriteError, T::ReadError>> {
+        return R502::identify(self, config);
+    }
+
+    #[cfg(feature = "cmd-enroll")]
+    fn enroll(
+        &mut self,
+        index: u16,
+        config: &EnrollConfig,
+        delay: &mut dyn DelayMs<u16>,
+        prompts: &mut dyn FnMut(EnrollPrompt),
+    ) -> Result<(), EnrollError<T::WriteError, T::ReadError>> {
+        return R502::enroll(self, index, config, &mut DynDelay(delay), prompts);
+    }
+
+    fn delete(
+        &mut self,
+        indices: &[u16],
+    ) -> Result<DeleteReport, DeleteError<T::WriteError, T::ReadError>> {
+        return self.delete_indices(indices);
+    }
+
+    fn template_count(&mut self) -> Result<u16, LibraryError<T::WriteError, T::ReadError>> {
+        return R502::template_count(self);
+    }
+}
+
+/// Lets a `dyn DelayMs` stand in where the helpers want a sized one.
+#[cfg(feature = "cmd-enroll")]
+struct DynDelay<'a>(&'a mut dyn DelayMs<u16>);
+
+#[cfg(feature = "cmd-enroll")]
+impl DelayMs<u16> for DynDelay<'_> {
+    fn delay_ms(&mut self, ms: u16) {
+        self.0.delay_ms(ms);
+    }
+}
+
+#[cfg(all(test, feature = "cmd-enroll"))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::emulator::{Emulator, NoDelay};
+    use crate::fake::{FakeCall, FakeReader};
+    use core::convert::Infallible;
+    use std::vec;
+
+    /// Application code which only knows the trait: enrols a finger in the first slot if the
+    /// library is empty, then checks that it is recognised.
+    fn first_run<F>(reader: &mut F) -> Option<u16>
+    where
+        F: FingerprintReader + ?Sized,
+    {
+        reader.authenticate(0).ok()?;
+        if reader.template_count().ok()? == 0 {
+            let config = EnrollConfig::default();
+            reader.enroll(0, &config, &mut NoDelay, &mut |_| {}).ok()?;
+        }
+        return reader.identify(&IdentifyConfig::default()).ok().map(|(index, _)| index);
+    }
+
+    #[test]
+    fn test_reader_drives_r502() {
+        // given: an emulated module with an empty library, and a finger to enrol and show
+        let emulator = Emulator::new();
+        emulator.touch(&[Some(7), None, Some(7), Some(7)]);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: running the application code on the driver
+        let found = first_run(&mut r502);
+
+        // then: the finger was enrolled and identified
+        assert_eq!(found, Some(0));
+        assert_eq!(emulator.slot(0).is_some(), true);
+    }
+
+    #[test]
+    fn test_reader_drives_fake() {
+        // given: a fake which will recognise the finger in slot 0
+        let mut fake = FakeReader::new();
+        fake.identifications.push_back(Ok((0, 150)));
+
+        // when: running the same code on it, as a trait object
+        let reader: &mut dyn FingerprintReader<WriteError = Infallible, ReadError = Infallible> =
+            &mut fake;
+        let found = first_run(reader);
+
+        // then: it went through the same steps
+        assert_eq!(found, Some(0));
+        assert_eq!(
+            fake.calls,
+            vec![
+                FakeCall::Authenticate { password: 0 },
+                FakeCall::TemplateCount,
+                FakeCall::Enroll { index: 0 },
+                FakeCall::Identify,
+            ]
+        );
+    }
+}
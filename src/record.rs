@@ -0,0 +1,373 @@
+use core::fmt::Write as _;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::string::String;
+use std::time::Instant;
+use std::vec::Vec;
+
+use crate::driver::R502;
+use crate::transport::{Direction, Transport};
+
+/// The first line of a recording, naming the format.
+pub const RECORDING_HEADER: &str = "# hzgrow-r502 recording v1";
+
+impl Direction {
+    fn name(self) -> &'static str {
+        return match self {
+            Self::Tx => "tx",
+            Self::Rx => "rx",
+        };
+    }
+}
+
+/// A `Transport` which writes every byte passing through it to a log, for capturing a session
+/// with a real module so it can be replayed with [`ReplayTransport`](struct.ReplayTransport.html)
+/// in a test. See [`R502::with_recording`](struct.R502.html#method.with_recording).
+///
+/// The log is text. After the [`RECORDING_HEADER`](constant.RECORDING_HEADER.html) line, each
+/// line is a run of bytes going one way: the time it started, in microseconds since recording
+/// began, `tx` or `rx`, and the bytes in hex:
+///
+/// ```text
+/// # hzgrow-r502 recording v1
+/// 0 tx ef 01 ff ff ff ff 01 00 03 1d 00 21
+/// 10412 rx ef 01 ff ff ff ff 07 00 05 00 00 2a 00 36
+/// ```
+///
+/// A run is written out once the bytes change direction, so the last one is only written by
+/// [`flush_log`](#method.flush_log). Errors writing the log do not disturb the session; the
+/// first is kept and returned by `flush_log`.
+#[derive(Debug)]
+pub struct RecordingTransport<T, W> {
+    transport: T,
+    log: W,
+    started: Instant,
+    run: Option<(Direction, u128, Vec<u8>)>,
+    error: Option<io::Error>,
+}
+
+impl<T, W> RecordingTransport<T, W>
+where
+    T: Transport,
+    W: Write,
+{
+    /// Wraps `transport`, writing what passes through it to `log`.
+    pub fn new(transport: T, mut log: W) -> Self {
+        let error = writeln!(log, "{}", RECORDING_HEADER).err();
+        return Self { transport, log, started: Instant::now(), run: None, error };
+    }
+
+    /// Writes out the run of bytes in progress and flushes the log. Call it once the session
+    /// is over.
+    ///
+    /// # Errors
+    ///
+    /// The first error writing the log, since it was last returned.
+    pub fn flush_log(&mut self) -> io::Result<()> {
+        if let Some((direction, at, bytes)) = self.run.take() {
+            self.write_run(direction, at, &bytes);
+        }
+        if let Err(error) = self.log.flush() {
+            self.error.get_or_insert(error);
+        }
+        return match self.error.take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        };
+    }
+
+    /// The log.
+    pub fn log(&self) -> &W {
+        return &self.log;
+    }
+
+    /// Gives back the transport and the log, without writing out the run of bytes in progress.
+    pub fn release(self) -> (T, W) {
+        return (self.transport, self.log);
+    }
+
+    fn note(&mut self, direction: Direction, byte: u8) {
+        match &mut self.run {
+            Some((run_direction, _, bytes)) if *run_direction == direction => bytes.push(byte),
+            run => {
+                let at = self.started.elapsed().as_micros();
+                let previous = run.replace((direction, at, Vec::new()));
+                if let Some((previous, previous_at, bytes)) = previous {
+                    self.write_run(previous, previous_at, &bytes);
+                }
+                self.run.as_mut().unwrap().2.push(byte);
+            }
+        }
+    }
+
+    fn write_run(&mut self, direction: Direction, at: u128, bytes: &[u8]) {
+        let mut line = String::new();
+        let _ = write!(line, "{} {}", at, direction.name());
+        for byte in bytes {
+            let _ = write!(line, " {:02x}", byte);
+        }
+        if let Err(error) = writeln!(self.log, "{}", line) {
+            self.error.get_or_insert(error);
+        }
+    }
+}
+
+impl<T, W> Transport for RecordingTransport<T, W>
+where
+    T: Transport,
+    W: Write,
+{
+    type WriteError = T::WriteError;
+    type ReadError = T::ReadError;
+
+    fn write_byte(&mut self, byte: u8) -> nb::Result<(), Self::WriteError> {
+        self.transport.write_byte(byte)?;
+        self.note(Direction::Tx, byte);
+        return Ok(());
+    }
+
+    fn write_slice(&mut self, bytes: &[u8]) -> nb::Result<usize, Self::WriteError> {
+        let taken = self.transport.write_slice(bytes)?;
+        for byte in &bytes[..taken] {
+            self.note(Direction::Tx, *byte);
+        }
+        return Ok(taken);
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::WriteError> {
+        return self.transport.flush();
+    }
+
+    fn read_byte(&mut self) -> nb::Result<u8, Self::ReadError> {
+        let byte = self.transport.read_byte()?;
+        self.note(Direction::Rx, byte);
+        return Ok(byte);
+    }
+}
+
+/// Error reading a recording, or replaying it.
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The recording could not be read.
+    Io(io::Error),
+
+    /// Line `line` (counting from 1) of the recording makes no sense.
+    BadLine(usize),
+
+    /// The driver wrote `written` where the recording has `expected`, at `position` bytes
+    /// into what the host sent.
+    Mismatch { position: usize, expected: u8, written: u8 },
+
+    /// The driver wrote `written` where the recording has the module replying, or has ended.
+    UnexpectedWrite(u8),
+
+    /// The driver read where the recording has the host still sending.
+    UnexpectedRead,
+
+    /// The driver read past the end of the recording.
+    EndOfRecording,
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(error: io::Error) -> Self {
+        return Self::Io(error);
+    }
+}
+
+/// A `Transport` which plays back a recording made by
+/// [`RecordingTransport`](struct.RecordingTransport.html): it serves the bytes the module sent,
+/// and checks that the driver writes the bytes the host sent, in the same order. A session
+/// recorded with a real module can so be run again offline, in a test, to reproduce a bug.
+///
+/// Timing is not replayed; bytes are there as soon as the driver reads them.
+#[derive(Debug)]
+pub struct ReplayTransport {
+    runs: VecDeque<(Direction, VecDeque<u8>)>,
+    written: usize,
+}
+
+impl ReplayTransport {
+    /// Reads a recording from `reader`. Lines starting with `#`, such as the header, and blank
+    /// lines are skipped.
+    ///
+    /// # Errors
+    ///
+    /// `ReplayError::Io` if reading failed, and `ReplayError::BadLine` for a line which is not
+    /// a run of bytes.
+    pub fn from_reader<R>(reader: R) -> Result<Self, ReplayError>
+    where
+        R: BufRead,
+    {
+        let mut runs = VecDeque::new();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let run = parse_run(line).ok_or(ReplayError::BadLine(number + 1))?;
+            runs.push_back(run);
+        }
+        return Ok(Self { runs, written: 0 });
+    }
+
+    /// Whether the whole recording has been played back.
+    pub fn is_finished(&self) -> bool {
+        return self.runs.iter().all(|(_, bytes)| bytes.is_empty());
+    }
+
+    /// The run the next byte comes from, dropping those which are used up.
+    fn next_run(&mut self) -> Option<&mut (Direction, VecDeque<u8>)> {
+        while matches!(self.runs.front(), Some((_, bytes)) if bytes.is_empty()) {
+            self.runs.pop_front();
+        }
+        return self.runs.front_mut();
+    }
+}
+
+/// A line of a recording: the time, the direction, and the bytes in hex.
+fn parse_run(line: &str) -> Option<(Direction, VecDeque<u8>)> {
+    let mut fields = line.split_whitespace();
+    fields.next()?.parse::<u128>().ok()?;
+    let direction = match fields.next()? {
+        "tx" => Direction::Tx,
+        "rx" => Direction::Rx,
+        _ => return None,
+    };
+    let mut bytes = VecDeque::new();
+    for field in fields {
+        if field.len() != 2 {
+            return None;
+        }
+        bytes.push_back(u8::from_str_radix(field, 16).ok()?);
+    }
+    return Some((direction, bytes));
+}
+
+impl Transport for ReplayTransport {
+    type WriteError = ReplayError;
+    type ReadError = ReplayError;
+
+    fn write_byte(&mut self, byte: u8) -> nb::Result<(), Self::WriteError> {
+        let position = self.written;
+        let expected = match self.next_run() {
+            Some((Direction::Tx, bytes)) => bytes.pop_front().unwrap(),
+            _ => return Err(nb::Error::Other(ReplayError::UnexpectedWrite(byte))),
+        };
+        if expected != byte {
+            let mismatch = ReplayError::Mismatch { position, expected, written: byte };
+            return Err(nb::Error::Other(mismatch));
+        }
+        self.written += 1;
+        return Ok(());
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::WriteError> {
+        return Ok(());
+    }
+
+    fn read_byte(&mut self) -> nb::Result<u8, Self::ReadError> {
+        return match self.next_run() {
+            Some((Direction::Rx, bytes)) => Ok(bytes.pop_front().unwrap()),
+            Some((Direction::Tx, _)) => Err(nb::Error::Other(ReplayError::UnexpectedRead)),
+            None => Err(nb::Error::Other(ReplayError::EndOfRecording)),
+        };
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Records every byte sent and received from now on to `log`, see
+    /// [`RecordingTransport`](struct.RecordingTransport.html). Call it before the first
+    /// command, and `transport_mut().flush_log()` at the end.
+    pub fn with_recording<W>(self, log: W) -> R502<RecordingTransport<T, W>>
+    where
+        W: Write,
+    {
+        return self.map_transport(|transport| RecordingTransport::new(transport, log));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Command;
+    use crate::mock::{Expectation, MockTransport};
+    use crate::responses::Reply;
+    use crate::utils::Error;
+
+    #[test]
+    fn test_record_and_replay() {
+        // given: a session with a (mock) module, recorded
+        let mock = MockTransport::new(
+            0xffffffff,
+            std::vec![
+                Expectation::new(Command::TemplateNum).reply(0x00, &[0x00, 0x2a]),
+                Expectation::new(Command::GenImg).reply(0x02, &[]),
+            ],
+        );
+        let mut r502 = R502::from_serial(mock.clone(), 0xffffffff).with_recording(Vec::new());
+        r502.send_command(Command::TemplateNum).unwrap();
+        r502.send_command(Command::GenImg).unwrap();
+        r502.transport_mut().flush_log().unwrap();
+        mock.done();
+        let log = String::from_utf8(r502.transport().log().clone()).unwrap();
+
+        // when: replaying the recording, with the same commands
+        let replay = ReplayTransport::from_reader(log.as_bytes()).unwrap();
+        let mut r502 = R502::with_transport(replay, 0xffffffff);
+        let template_num = r502.send_command(Command::TemplateNum);
+        let gen_img = r502.send_command(Command::GenImg);
+
+        // then: the log has a line for each command and reply
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], RECORDING_HEADER);
+        assert_eq!(lines[1].ends_with(" tx ef 01 ff ff ff ff 01 00 03 1d 00 21"), true);
+        assert_eq!(lines[2].contains(" rx ef 01 ff ff ff ff 07 00 05 00 00 2a 00 36"), true);
+
+        // and: the replies come back as they did from the module
+        match template_num {
+            Ok(Reply::TemplateNum(result)) => assert_eq!(result.template_num, 42),
+            other => panic!("Expected Reply::TemplateNum, got {:?}", other),
+        }
+        assert_eq!(matches!(gen_img, Ok(Reply::GenImg(_))), true);
+        assert_eq!(r502.transport().is_finished(), true);
+    }
+
+    #[test]
+    fn test_replay_mismatch() {
+        // given: a recording of `TemplateNum`
+        let log = "# hzgrow-r502 recording v1\n\
+                   0 tx ef 01 ff ff ff ff 01 00 03 1d 00 21\n\
+                   812 rx ef 01 ff ff ff ff 07 00 05 00 00 2a 00 36\n";
+        let replay = ReplayTransport::from_reader(log.as_bytes()).unwrap();
+        let mut r502 = R502::with_transport(replay, 0xffffffff);
+
+        // when: the driver sends something else
+        let result = r502.send_command(Command::GenImg);
+
+        // then: replaying stops at the first byte which differs
+        let mismatch = ReplayError::Mismatch { position: 9, expected: 0x1d, written: 0x01 };
+        match result {
+            Err(Error::WriteError(error)) => {
+                assert_eq!(std::format!("{:?}", error), std::format!("{:?}", mismatch))
+            }
+            other => panic!("Expected Error::WriteError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_replay_bad_line() {
+        // given: a recording with a damaged line
+        let log = "# hzgrow-r502 recording v1\n0 tx ef 01\n5 up ef 01\n";
+
+        // when: reading it
+        let result = ReplayTransport::from_reader(log.as_bytes());
+
+        // then: the line is pointed out
+        assert_eq!(matches!(result, Err(ReplayError::BadLine(3))), true);
+    }
+}
@@ -0,0 +1,430 @@
+use arrayvec::ArrayVec;
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::driver::R502;
+use crate::maintenance::{DeleteError, DeleteReport};
+use crate::consts::NOTEPAD_PAGES;
+use crate::notepad::{NotepadError, NotepadPage, NOTEPAD_CHECKED_SIZE};
+use crate::transport::Transport;
+
+/// Most slots a single user can have in a `UserRegistry`.
+pub const MAX_USER_SLOTS: usize = 5;
+
+/// Number of slots a `UserRegistry` can record per notepad page.
+pub const REGISTRY_ENTRIES_PER_PAGE: usize = 9;
+
+/// Slots of a user, as returned by
+/// [`UserRegistry::slots_of`](struct.UserRegistry.html#method.slots_of).
+pub type UserSlots = ArrayVec<u16, MAX_USER_SLOTS>;
+
+/// Version of the page layout written by this driver.
+const REGISTRY_VERSION: u8 = 1;
+
+/// Slot of an unused entry.
+const FREE: u16 = 0xffff;
+
+const ENTRY_SIZE: usize = 3;
+
+type RegistryPage = [u8; NOTEPAD_CHECKED_SIZE];
+
+type RegistryPages = ArrayVec<(NotepadPage, RegistryPage), { NOTEPAD_PAGES as usize }>;
+
+/// Error type for `UserRegistry`.
+#[derive(Debug)]
+pub enum RegistryError<TXE, RXE> {
+    /// A registry page could not be read or written, or failed its CRC check.
+    Notepad(NotepadError<TXE, RXE>),
+
+    /// The page was written by a newer version of the registry, and is left alone.
+    UnknownVersion(NotepadPage, u8),
+
+    /// The user already has `MAX_USER_SLOTS` slots.
+    TooManySlots(u8),
+
+    /// Every entry of the registry is in use.
+    Full,
+
+    /// The templates of the user could not be deleted.
+    Delete(DeleteError<TXE, RXE>),
+}
+
+impl<TXE, RXE> From<NotepadError<TXE, RXE>> for RegistryError<TXE, RXE> {
+    fn from(error: NotepadError<TXE, RXE>) -> Self {
+        return Self::Notepad(error);
+    }
+}
+
+impl<TXE, RXE> From<DeleteError<TXE, RXE>> for RegistryError<TXE, RXE> {
+    fn from(error: DeleteError<TXE, RXE>) -> Self {
+        return Self::Delete(error);
+    }
+}
+
+/// Maps small numeric user IDs to the library slots holding their fingers, so that a host
+/// without a database of its own can tell who a matched slot belongs to. The registry lives in
+/// a range of notepad pages, and is read from the module on every call.
+///
+/// # Storage layout
+///
+/// Every page is written with
+/// [`R502::write_notepad_page_checked`](struct.R502.html#method.write_notepad_page_checked),
+/// so its last two bytes are a CRC of the first 30:
+///
+/// | Bytes  | Contents                                                              |
+/// |--------|-----------------------------------------------------------------------|
+/// | 0      | Layout version, currently 1                                           |
+/// | 1..28  | 9 entries of 3 bytes: user ID, then slot index (big-endian)           |
+/// | 28..30 | Reserved, written as zero                                             |
+///
+/// An entry whose slot is `0xffff` is unused, and a blank page has no entries. Pages with a
+/// higher version are reported as `RegistryError::UnknownVersion` rather than overwritten, so
+/// a later layout can be introduced without older firmware destroying it.
+///
+/// **Note:** Labels stored with [`R502::set_label`](struct.R502.html#method.set_label) take
+/// up the whole notepad, so they cannot be used together with a registry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UserRegistry {
+    first: u8,
+    pages: u8,
+}
+
+impl UserRegistry {
+    /// A registry kept in `pages` notepad pages starting from `first`. Returns `None` if
+    /// `pages` is zero or the range runs past the end of the notepad.
+    pub fn new(first: NotepadPage, pages: u8) -> Option<Self> {
+        if pages == 0 || first.number() as usize + pages as usize > NOTEPAD_PAGES as usize {
+            return None;
+        }
+        return Some(Self { first: first.number(), pages });
+    }
+
+    /// How many slots the registry can record across all users.
+    pub fn capacity(&self) -> usize {
+        return self.pages as usize * REGISTRY_ENTRIES_PER_PAGE;
+    }
+
+    /// Records `slot` as belonging to `user`. A slot which belonged to someone else is handed
+    /// over to `user`.
+    pub fn assign<T>(
+        &self,
+        r502: &mut R502<T>,
+        user: u8,
+        slot: u16,
+    ) -> Result<(), RegistryError<T::WriteError, T::ReadError>>
+    where
+        T: Transport,
+    {
+        let mut pages = self.load(r502)?;
+        let owned = entries(&pages).filter(|(owner, _)| *owner == user).count();
+        let mut target = None;
+        let mut free = None;
+        for (page_index, (_, page)) in pages.iter().enumerate() {
+            for entry in 0..REGISTRY_ENTRIES_PER_PAGE {
+                let (owner, entry_slot) = read_entry(page, entry);
+                if entry_slot == slot {
+                    if owner == user {
+                        return Ok(());
+                    }
+                    target = Some((page_index, entry));
+                } else if entry_slot == FREE && free.is_none() {
+                    free = Some((page_index, entry));
+                }
+            }
+        }
+
+        if owned >= MAX_USER_SLOTS {
+            return Err(RegistryError::TooManySlots(user));
+        }
+        let (page_index, entry) = match target.or(free) {
+            Some(position) => position,
+            None => return Err(RegistryError::Full),
+        };
+        let (page, data) = &mut pages[page_index];
+        write_entry(data, entry, user, slot);
+        r502.write_notepad_page_checked(*page, data)?;
+        return Ok(());
+    }
+
+    /// The slots recorded for `user`, in the order they are stored.
+    pub fn slots_of<T>(
+        &self,
+        r502: &mut R502<T>,
+        user: u8,
+    ) -> Result<UserSlots, RegistryError<T::WriteError, T::ReadError>>
+    where
+        T: Transport,
+    {
+        let pages = self.load(r502)?;
+        let mut slots = UserSlots::new();
+        for (_, slot) in entries(&pages).filter(|(owner, _)| *owner == user) {
+            if slots.try_push(slot).is_err() {
+                break;
+            }
+        }
+        return Ok(slots);
+    }
+
+    /// The user `slot` belongs to, if it has been assigned to one.
+    pub fn user_of<T>(
+        &self,
+        r502: &mut R502<T>,
+        slot: u16,
+    ) -> Result<Option<u8>, RegistryError<T::WriteError, T::ReadError>>
+    where
+        T: Transport,
+    {
+        let pages = self.load(r502)?;
+        return Ok(entries(&pages)
+            .find(|(_, entry_slot)| *entry_slot == slot)
+            .map(|(owner, _)| owner));
+    }
+
+    /// Deletes the templates of `user` from the library, as per
+    /// [`R502::delete_indices`](struct.R502.html#method.delete_indices), then forgets the user.
+    ///
+    /// The user is only forgotten if every template was deleted, so that a failed deletion can
+    /// be retried; check `DeleteReport::is_complete`.
+    pub fn remove_user<T>(
+        &self,
+        r502: &mut R502<T>,
+        user: u8,
+    ) -> Result<DeleteReport, RegistryError<T::WriteError, T::ReadError>>
+    where
+        T: Transport,
+    {
+        let mut pages = self.load(r502)?;
+        let slots: ArrayVec<u16, 256> = entries(&pages)
+            .filter(|(owner, _)| *owner == user)
+            .map(|(_, slot)| slot)
+            .collect();
+        let report = r502.delete_indices(&slots)?;
+        if !report.is_complete() {
+            return Ok(report);
+        }
+
+        for (page, data) in pages.iter_mut() {
+            let mut changed = false;
+            for entry in 0..REGISTRY_ENTRIES_PER_PAGE {
+                let (owner, slot) = read_entry(data, entry);
+                if owner == user && slot != FREE {
+                    write_entry(data, entry, 0, FREE);
+                    changed = true;
+                }
+            }
+            if changed {
+                r502.write_notepad_page_checked(*page, data)?;
+            }
+        }
+        return Ok(report);
+    }
+
+    /// Reads every page of the registry. Blank pages come back with no entries.
+    fn load<T>(
+        &self,
+        r502: &mut R502<T>,
+    ) -> Result<RegistryPages, RegistryError<T::WriteError, T::ReadError>>
+    where
+        T: Transport,
+    {
+        let mut pages = RegistryPages::new();
+        for page in NotepadPage::all().skip(self.first as usize).take(self.pages as usize) {
+            let data = match r502.read_notepad_page_checked(page) {
+                Ok(data) => data,
+                Err(NotepadError::NeverWritten(_)) => blank_page(),
+                Err(error) => return Err(RegistryError::Notepad(error)),
+            };
+            if data[0] != REGISTRY_VERSION {
+                return Err(RegistryError::UnknownVersion(page, data[0]));
+            }
+            pages.push((page, data));
+        }
+        return Ok(pages);
+    }
+}
+
+fn blank_page() -> RegistryPage {
+    let mut page = [0u8; NOTEPAD_CHECKED_SIZE];
+    page[0] = REGISTRY_VERSION;
+    for entry in 0..REGISTRY_ENTRIES_PER_PAGE {
+        write_entry(&mut page, entry, 0, FREE);
+    }
+    return page;
+}
+
+fn read_entry(page: &RegistryPage, entry: usize) -> (u8, u16) {
+    let offset = 1 + entry * ENTRY_SIZE;
+    return (page[offset], BigEndian::read_u16(&page[offset + 1..offset + 3]));
+}
+
+fn write_entry(page: &mut RegistryPage, entry: usize, user: u8, slot: u16) {
+    let offset = 1 + entry * ENTRY_SIZE;
+    page[offset] = user;
+    BigEndian::write_u16(&mut page[offset + 1..offset + 3], slot);
+}
+
+/// Every entry in use, as (user, slot).
+fn entries(pages: &RegistryPages) -> impl Iterator<Item = (u8, u16)> + '_ {
+    return pages
+        .iter()
+        .flat_map(|(_, page)| {
+            (0..REGISTRY_ENTRIES_PER_PAGE).map(move |entry| read_entry(page, entry))
+        })
+        .filter(|(_, slot)| *slot != FREE);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{Emulator, EmulatorRx, EmulatorTx};
+
+    fn registry(first: u8, pages: u8) -> UserRegistry {
+        return UserRegistry::new(NotepadPage::new(first).unwrap(), pages).unwrap();
+    }
+
+    fn setup() -> (Emulator, R502<(EmulatorTx, EmulatorRx)>) {
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        return (emulator, R502::new(tx, rx, 0xffffffff));
+    }
+
+    #[test]
+    fn test_registry_bounds() {
+        // when: placing registries in the notepad
+        // then: only those which fit are allowed
+        assert_eq!(UserRegistry::new(NotepadPage::new(0).unwrap(), 16).is_some(), true);
+        assert_eq!(UserRegistry::new(NotepadPage::new(15).unwrap(), 1).is_some(), true);
+        assert_eq!(UserRegistry::new(NotepadPage::new(15).unwrap(), 2), None);
+        assert_eq!(UserRegistry::new(NotepadPage::new(3).unwrap(), 0), None);
+        assert_eq!(registry(2, 2).capacity(), 18);
+    }
+
+    #[test]
+    fn test_registry_multi_finger_users() {
+        // given: a blank registry in pages 4 and 5
+        let (emulator, mut r502) = setup();
+        let registry = registry(4, 2);
+
+        // when: assigning three fingers to user 1 and two to user 2
+        for slot in [17, 3, 42] {
+            registry.assign(&mut r502, 1, slot).unwrap();
+        }
+        registry.assign(&mut r502, 2, 18).unwrap();
+        registry.assign(&mut r502, 2, 19).unwrap();
+
+        // then: slots resolve to their users, and users to their slots
+        assert_eq!(registry.user_of(&mut r502, 17).unwrap(), Some(1));
+        assert_eq!(registry.user_of(&mut r502, 19).unwrap(), Some(2));
+        assert_eq!(registry.user_of(&mut r502, 20).unwrap(), None);
+        assert_eq!(&registry.slots_of(&mut r502, 1).unwrap()[..], &[17, 3, 42]);
+        assert_eq!(&registry.slots_of(&mut r502, 2).unwrap()[..], &[18, 19]);
+        assert_eq!(registry.slots_of(&mut r502, 3).unwrap().len(), 0);
+
+        // and: only the registry pages were written
+        let state = emulator.state();
+        assert_eq!(state.notepad[4][0], REGISTRY_VERSION);
+        assert_eq!(&state.notepad[4][1..4], &[1, 0, 17]);
+        assert_eq!(state.notepad[3], [0x00; 32]);
+        assert_eq!(state.notepad[5], [0x00; 32]);
+    }
+
+    #[test]
+    fn test_registry_reassign() {
+        // given: slot 17 assigned to user 1
+        let (_emulator, mut r502) = setup();
+        let registry = registry(0, 1);
+        registry.assign(&mut r502, 1, 17).unwrap();
+
+        // when: assigning it again, and then to user 2
+        registry.assign(&mut r502, 1, 17).unwrap();
+        registry.assign(&mut r502, 2, 17).unwrap();
+
+        // then: it is recorded once, for user 2
+        assert_eq!(registry.user_of(&mut r502, 17).unwrap(), Some(2));
+        assert_eq!(registry.slots_of(&mut r502, 1).unwrap().len(), 0);
+        assert_eq!(&registry.slots_of(&mut r502, 2).unwrap()[..], &[17]);
+    }
+
+    #[test]
+    fn test_registry_full() {
+        // given: a one-page registry with every entry used
+        let (_emulator, mut r502) = setup();
+        let registry = registry(0, 1);
+        for slot in 0..REGISTRY_ENTRIES_PER_PAGE as u16 {
+            registry.assign(&mut r502, slot as u8 / 3, slot).unwrap();
+        }
+
+        // when: assigning one more slot
+        let result = registry.assign(&mut r502, 9, 100);
+
+        // then: there is no room for it
+        match result {
+            Err(RegistryError::Full) => {}
+            other => panic!("Expected RegistryError::Full, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn test_registry_too_many_slots() {
+        // given: a user with as many slots as allowed
+        let (_emulator, mut r502) = setup();
+        let registry = registry(0, 2);
+        for slot in 0..MAX_USER_SLOTS as u16 {
+            registry.assign(&mut r502, 7, slot).unwrap();
+        }
+
+        // when: giving them one more
+        let result = registry.assign(&mut r502, 7, 100);
+
+        // then: it is refused
+        match result {
+            Err(RegistryError::TooManySlots(7)) => {}
+            other => panic!("Expected RegistryError::TooManySlots, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn test_registry_remove_user() {
+        // given: two users with enrolled fingers
+        let (emulator, mut r502) = setup();
+        let registry = registry(0, 1);
+        for (user, slot) in [(1, 10), (2, 11), (1, 12)] {
+            emulator.enroll(slot as usize, user);
+            registry.assign(&mut r502, user, slot).unwrap();
+        }
+
+        // when: removing user 1
+        let report = registry.remove_user(&mut r502, 1).unwrap();
+
+        // then: their templates are deleted and they are forgotten
+        assert_eq!(report.is_complete(), true);
+        assert_eq!(report.deleted, 2);
+        assert_eq!(emulator.slot(10), None);
+        assert_eq!(emulator.slot(12), None);
+        assert_eq!(registry.user_of(&mut r502, 10).unwrap(), None);
+        assert_eq!(registry.slots_of(&mut r502, 1).unwrap().len(), 0);
+
+        // and: user 2 is untouched
+        assert_eq!(emulator.slot(11).is_some(), true);
+        assert_eq!(registry.user_of(&mut r502, 11).unwrap(), Some(2));
+    }
+
+    #[test]
+    fn test_registry_unknown_version() {
+        // given: a registry page written by a newer layout
+        let (emulator, mut r502) = setup();
+        let registry = registry(0, 1);
+        let mut page = blank_page();
+        page[0] = 2;
+        r502.write_notepad_page_checked(NotepadPage::new(0).unwrap(), &page).unwrap();
+
+        // when: assigning a slot
+        let result = registry.assign(&mut r502, 1, 1);
+
+        // then: the page is left alone
+        match result {
+            Err(RegistryError::UnknownVersion(_, 2)) => {}
+            other => panic!("Expected RegistryError::UnknownVersion, got {:?}", other),
+        };
+        assert_eq!(emulator.state().notepad[0][0], 2);
+    }
+}
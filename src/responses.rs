@@ -1,3 +1,5 @@
+use crate::codec::DecodeError;
+use crate::consts::NOTEPAD_PAGE_SIZE;
 use crate::utils::FromPayload;
 use byteorder::{BigEndian, ByteOrder};
 
@@ -14,6 +16,9 @@ use byteorder::{BigEndian, ByteOrder};
 /// that most `PacketError`s are actually Unauthorised errors. However, you may
 /// also want to check your wiring in case the packet gets corrupted along the way.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub enum Reply {
     /// Contains system status and configuration information
     ReadSysPara(ReadSysParaResult),
@@ -36,21 +41,170 @@ pub enum Reply {
     /// Contains result of matching two fingers against each other
     Match(MatchResult),
 
-    /// Contains result of retrieving the next valid library index
+    /// Contains the number of templates stored in the library
     TemplateNum(TemplateNumResult),
 
+    /// Contains one page of the _index table_
+    ReadIndexTable(ReadIndexTableResult),
+
     /// Contains result of creating a fingerprint _template_
+    #[cfg(feature = "cmd-enroll")]
     RegModel(RegModelResult),
 
     /// Contains result of storing a fingerprint _template_ into the library
+    #[cfg(feature = "cmd-enroll")]
     Store(StoreResult),
 
+    /// Contains the acknowledgement of an upload of a _character buffer_
+    #[cfg(feature = "cmd-transfer")]
+    UpChar(UpCharResult),
+
+    /// Contains the acknowledgement of a download into a _character buffer_
+    #[cfg(feature = "cmd-transfer")]
+    DownChar(DownCharResult),
+
+    /// Contains the acknowledgement of a download into the _image buffer_
+    #[cfg(feature = "cmd-transfer")]
+    DownImage(DownImageResult),
+
+    /// Contains result of setting a new password
+    SetPwd(SetPwdResult),
+
+    /// Contains result of writing a system parameter
+    SetSysPara(SetSysParaResult),
+
+    /// Contains result of setting a new module address
+    SetAdder(SetAdderResult),
+
+    /// Contains the chip serial number
+    GetChipSN(GetChipSNResult),
+
+    /// Contains a random number from the module
+    GetRandomCode(GetRandomCodeResult),
+
+    /// Contains the firmware version
+    GetFwVer(GetFwVerResult),
+
+    /// Contains the algorithm library version
+    GetAlgVer(GetAlgVerResult),
+
+    /// Contains result of writing a notepad page
+    #[cfg(feature = "cmd-notepad")]
+    WriteNotepad(WriteNotepadResult),
+
+    /// Contains the contents of a notepad page
+    #[cfg(feature = "cmd-notepad")]
+    ReadNotepad(ReadNotepadResult),
+
+    /// Contains result of the handshake
+    HandShake(HandShakeResult),
+
+    /// Contains result of the sensor self-check
+    CheckSensor(CheckSensorResult),
+
+    /// Contains result of the soft reset request
+    SoftRst(SoftRstResult),
+
+    /// Contains result of the sleep request
+    Sleep(SleepResult),
+
+    /// Contains result of turning the USB port on or off
+    PortControl(PortControlResult),
+
+    /// Contains result of setting the ring LED
+    #[cfg(feature = "cmd-led")]
+    AuraLedConfig(AuraLedConfigResult),
+
     /// Contains result of deleting an enrolled fingerprint
     DeletChar(DeletCharResult),
+
+    /// Contains result of emptying the library
+    Empty(EmptyResult),
+}
+
+impl Reply {
+    /// True if the confirmation code says the command succeeded: `0x00`, which is `Success`
+    /// for every status type and `PasswordVerificationState::Correct` for `VfyPwd`.
+    pub fn is_success(&self) -> bool {
+        return match self {
+            Reply::ReadSysPara(result) => result.confirmation_code == 0x00,
+            Reply::VfyPwd(result) => {
+                matches!(result.confirmation_code, PasswordVerificationState::Correct)
+            }
+            Reply::GenImg(result) => matches!(result.confirmation_code, GenImgStatus::Success),
+            Reply::Img2Tz(result) => matches!(result.confirmation_code, Img2TzStatus::Success),
+            Reply::Search(result) => matches!(result.confirmation_code, SearchStatus::Success),
+            Reply::LoadChar(result) => matches!(result.confirmation_code, LoadCharStatus::Success),
+            Reply::Match(result) => matches!(result.confirmation_code, MatchStatus::Success),
+            Reply::TemplateNum(result) => {
+                matches!(result.confirmation_code, TemplateNumStatus::Success)
+            }
+            Reply::ReadIndexTable(result) => {
+                matches!(result.confirmation_code, ReadIndexTableStatus::Success)
+            }
+            #[cfg(feature = "cmd-enroll")]
+            Reply::RegModel(result) => matches!(result.confirmation_code, RegModelStatus::Success),
+            #[cfg(feature = "cmd-enroll")]
+            Reply::Store(result) => matches!(result.confirmation_code, StoreStatus::Success),
+            #[cfg(feature = "cmd-transfer")]
+            Reply::UpChar(result) => matches!(result.confirmation_code, UpCharStatus::Success),
+            #[cfg(feature = "cmd-transfer")]
+            Reply::DownChar(result) => matches!(result.confirmation_code, DownCharStatus::Success),
+            #[cfg(feature = "cmd-transfer")]
+            Reply::DownImage(result) => {
+                matches!(result.confirmation_code, DownImageStatus::Success)
+            }
+            Reply::SetPwd(result) => matches!(result.confirmation_code, SetPwdStatus::Success),
+            Reply::SetSysPara(result) => {
+                matches!(result.confirmation_code, SetSysParaStatus::Success)
+            }
+            Reply::SetAdder(result) => matches!(result.confirmation_code, SetAdderStatus::Success),
+            Reply::GetChipSN(result) => {
+                matches!(result.confirmation_code, GetChipSNStatus::Success)
+            }
+            Reply::GetRandomCode(result) => {
+                matches!(result.confirmation_code, GetRandomCodeStatus::Success)
+            }
+            Reply::GetFwVer(result) => matches!(result.confirmation_code, GetFwVerStatus::Success),
+            Reply::GetAlgVer(result) => {
+                matches!(result.confirmation_code, GetAlgVerStatus::Success)
+            }
+            #[cfg(feature = "cmd-notepad")]
+            Reply::WriteNotepad(result) => {
+                matches!(result.confirmation_code, WriteNotepadStatus::Success)
+            }
+            #[cfg(feature = "cmd-notepad")]
+            Reply::ReadNotepad(result) => {
+                matches!(result.confirmation_code, ReadNotepadStatus::Success)
+            }
+            Reply::HandShake(result) => {
+                matches!(result.confirmation_code, HandShakeStatus::Success)
+            }
+            Reply::CheckSensor(result) => {
+                matches!(result.confirmation_code, CheckSensorStatus::Success)
+            }
+            Reply::SoftRst(result) => matches!(result.confirmation_code, SoftRstStatus::Success),
+            Reply::Sleep(result) => matches!(result.confirmation_code, SleepStatus::Success),
+            Reply::PortControl(result) => {
+                matches!(result.confirmation_code, PortControlStatus::Success)
+            }
+            #[cfg(feature = "cmd-led")]
+            Reply::AuraLedConfig(result) => {
+                matches!(result.confirmation_code, AuraLedConfigStatus::Success)
+            }
+            Reply::DeletChar(result) => {
+                matches!(result.confirmation_code, DeletCharStatus::Success)
+            }
+            Reply::Empty(result) => matches!(result.confirmation_code, EmptyStatus::Success),
+        };
+    }
 }
 
 /// Result struct for the `ReadSysPara` call
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub struct ReadSysParaResult {
     /// Address of the R502 this message came from
     pub address: u32,
@@ -73,18 +227,22 @@ impl FromPayload for ReadSysParaResult {
     // confrm | 0x0F [1]
     // params | (params) [16]
     // chksum | checksum [2]
-    fn from_payload(payload: &[u8]) -> Self {
-        return Self {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 28)?;
+        return Ok(Self {
             address: BigEndian::read_u32(&payload[2..6]),
             confirmation_code: payload[9],
             checksum: BigEndian::read_u16(&payload[26..28]),
-            system_parameters: SystemParameters::from_payload(&payload[10..26]),
-        };
+            system_parameters: SystemParameters::from_payload(&payload[10..26])?,
+        });
     }
 }
 
 /// Result struct for the `VfyPwd` call
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub struct VfyPwdResult {
     /// Address of the R502 this message came from
     pub address: u32,
@@ -96,17 +254,21 @@ pub struct VfyPwdResult {
 }
 
 impl FromPayload for VfyPwdResult {
-    fn from_payload(payload: &[u8]) -> Self {
-        return Self {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
             address: BigEndian::read_u32(&payload[2..6]),
-            confirmation_code: PasswordVerificationState::from(payload[9]),
+            confirmation_code: PasswordVerificationState::from(payload[9])?,
             checksum: BigEndian::read_u16(&payload[10..12]),
-        };
+        });
     }
 }
 
 /// Result struct for the `GenImg` call
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub struct GenImgResult {
     /// Address of the R502 that sent this message
     pub address: u32,
@@ -118,17 +280,21 @@ pub struct GenImgResult {
 }
 
 impl FromPayload for GenImgResult {
-    fn from_payload(payload: &[u8]) -> Self {
-        return Self {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
             address: BigEndian::read_u32(&payload[2..6]),
-            confirmation_code: GenImgStatus::from(payload[9]),
+            confirmation_code: GenImgStatus::from(payload[9])?,
             checksum: BigEndian::read_u16(&payload[10..12]),
-        };
+        });
     }
 }
 
 /// Result struct for the `Img2Tz` struct
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub struct Img2TzResult {
     /// Address of the R502 that sent this message
     pub address: u32,
@@ -140,17 +306,21 @@ pub struct Img2TzResult {
 }
 
 impl FromPayload for Img2TzResult {
-    fn from_payload(payload: &[u8]) -> Self {
-        return Self {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
             address: BigEndian::read_u32(&payload[2..6]),
-            confirmation_code: Img2TzStatus::from(payload[9]),
+            confirmation_code: Img2TzStatus::from(payload[9])?,
             checksum: BigEndian::read_u16(&payload[10..12]),
-        };
+        });
     }
 }
 
 /// Result struct for the `Search` call
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub struct SearchResult {
     /// Address of the R502 that sent this message
     pub address: u32,
@@ -170,19 +340,23 @@ pub struct SearchResult {
 }
 
 impl FromPayload for SearchResult {
-    fn from_payload(payload: &[u8]) -> Self {
-        return Self {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 16)?;
+        return Ok(Self {
             address: BigEndian::read_u32(&payload[2..6]),
-            confirmation_code: SearchStatus::from(payload[9]),
+            confirmation_code: SearchStatus::from(payload[9])?,
             match_id: BigEndian::read_u16(&payload[10..12]),
             match_score: BigEndian::read_u16(&payload[12..14]),
             checksum: BigEndian::read_u16(&payload[14..16]),
-        };
+        });
     }
 }
 
 /// Structure containing the status code of the `LoadChar` call
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub struct LoadCharResult {
     /// Address of the R502 that sent this message
     pub address: u32,
@@ -194,17 +368,21 @@ pub struct LoadCharResult {
 }
 
 impl FromPayload for LoadCharResult {
-    fn from_payload(payload: &[u8]) -> Self {
-        return Self {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
             address: BigEndian::read_u32(&payload[2..6]),
-            confirmation_code: LoadCharStatus::from(payload[9]),
+            confirmation_code: LoadCharStatus::from(payload[9])?,
             checksum: BigEndian::read_u16(&payload[10..12]),
-        };
+        });
     }
 }
 
 /// Structure containing the status code of the `Match` call
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub struct MatchResult {
     /// Address of the R502 that sent this message
     pub address: u32,
@@ -219,19 +397,26 @@ pub struct MatchResult {
 }
 
 impl FromPayload for MatchResult {
-    fn from_payload(payload: &[u8]) -> Self {
-        return Self {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 14)?;
+        return Ok(Self {
             address: BigEndian::read_u32(&payload[2..6]),
-            confirmation_code: MatchStatus::from(payload[9]),
+            confirmation_code: MatchStatus::from(payload[9])?,
             match_score: BigEndian::read_u16(&payload[10..12]),
             checksum: BigEndian::read_u16(&payload[12..14]),
-        };
+        });
     }
 }
 
-/// Contains the next available template number, or index in the library
-/// at which a fingerprint can be enrolled.
+/// Contains the number of templates stored in the library.
+///
+/// **Note:** This is not necessarily the index at which a new fingerprint can be enrolled:
+/// once a template in the middle of the library is deleted, the count points at an occupied
+/// slot. Use [`R502::next_free_slot`](struct.R502.html#method.next_free_slot) for that.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub struct TemplateNumResult {
     /// Address of the R502 that sent this message
     pub address: u32,
@@ -239,91 +424,709 @@ pub struct TemplateNumResult {
     /// Response code
     pub confirmation_code: TemplateNumStatus,
 
-    /// Valid template number
+    /// Number of templates stored in the library
     pub template_num: u16,
 
     pub checksum: u16,
 }
 
 impl FromPayload for TemplateNumResult {
-    fn from_payload(payload: &[u8]) -> Self {
-        return Self {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 14)?;
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: TemplateNumStatus::from(payload[9])?,
+            template_num: BigEndian::read_u16(&payload[10..12]),
+            checksum: BigEndian::read_u16(&payload[12..14]),
+        });
+    }
+}
+
+/// Contains one page of the _index table_: a bitmap of which library slots hold a template.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct ReadIndexTableResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: ReadIndexTableStatus,
+
+    /// The bitmap. Bit 0 of byte 0 is the first slot of the page, bit 1 of byte 0 the second
+    /// and so on.
+    pub index_table: [u8; 32],
+
+    pub checksum: u16,
+}
+
+impl ReadIndexTableResult {
+    /// True if slot `slot` (relative to the start of the page, [0-255]) holds a template.
+    pub fn is_occupied(&self, slot: u8) -> bool {
+        return self.index_table[slot as usize / 8] & (1u8 << (slot % 8)) != 0;
+    }
+}
+
+impl FromPayload for ReadIndexTableResult {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 44)?;
+        let mut index_table = [0u8; 32];
+        index_table.copy_from_slice(&payload[10..42]);
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: ReadIndexTableStatus::from(payload[9])?,
+            index_table,
+            checksum: BigEndian::read_u16(&payload[42..44]),
+        });
+    }
+}
+
+/// Result of generating the fingerprint template for enrollment.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct RegModelResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: RegModelStatus,
+
+    pub checksum: u16,
+}
+
+impl FromPayload for RegModelResult {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: RegModelStatus::from(payload[9])?,
+            checksum: BigEndian::read_u16(&payload[10..12]),
+        });
+    }
+}
+
+/// Result of storing a fingerprint template.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct StoreResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: StoreStatus,
+
+    pub checksum: u16,
+}
+
+impl FromPayload for StoreResult {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: StoreStatus::from(payload[9])?,
+            checksum: BigEndian::read_u16(&payload[10..12]),
+        });
+    }
+}
+
+/// Acknowledgement of the `UpChar` call. The data packets follow this reply.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct UpCharResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: UpCharStatus,
+
+    pub checksum: u16,
+}
+
+impl FromPayload for UpCharResult {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: UpCharStatus::from(payload[9])?,
+            checksum: BigEndian::read_u16(&payload[10..12]),
+        });
+    }
+}
+
+/// Acknowledgement of the `DownChar` call. The host sends the data packets after this reply.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct DownCharResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: DownCharStatus,
+
+    pub checksum: u16,
+}
+
+impl FromPayload for DownCharResult {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: DownCharStatus::from(payload[9])?,
+            checksum: BigEndian::read_u16(&payload[10..12]),
+        });
+    }
+}
+
+/// Acknowledgement of the `DownImage` call. The host sends the data packets after this reply.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct DownImageResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: DownImageStatus,
+
+    pub checksum: u16,
+}
+
+impl FromPayload for DownImageResult {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: DownImageStatus::from(payload[9])?,
+            checksum: BigEndian::read_u16(&payload[10..12]),
+        });
+    }
+}
+
+/// Result of the `SetPwd` call.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct SetPwdResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: SetPwdStatus,
+
+    pub checksum: u16,
+}
+
+impl FromPayload for SetPwdResult {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: SetPwdStatus::from(payload[9])?,
+            checksum: BigEndian::read_u16(&payload[10..12]),
+        });
+    }
+}
+
+/// Result of the `SetSysPara` call.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct SetSysParaResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: SetSysParaStatus,
+
+    pub checksum: u16,
+}
+
+impl FromPayload for SetSysParaResult {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: SetSysParaStatus::from(payload[9])?,
+            checksum: BigEndian::read_u16(&payload[10..12]),
+        });
+    }
+}
+
+/// Result of the `SetAdder` call.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct SetAdderResult {
+    /// Address of the R502 that sent this message. This is the new address.
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: SetAdderStatus,
+
+    pub checksum: u16,
+}
+
+impl FromPayload for SetAdderResult {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: SetAdderStatus::from(payload[9])?,
+            checksum: BigEndian::read_u16(&payload[10..12]),
+        });
+    }
+}
+
+/// Result of the `GetChipSN` call.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct GetChipSNResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: GetChipSNStatus,
+
+    /// Unique serial number of the chip
+    pub serial_number: [u8; 32],
+
+    pub checksum: u16,
+}
+
+impl FromPayload for GetChipSNResult {
+    // Expected packet:
+    // headr  | 0xEF 0x01 [2]
+    // addr   | cmd.address [4]
+    // ident  | 0x07 [1]
+    // length | 0x00 0x23 [2]
+    // confrm | confirmation code [1]
+    // serial | serial number [32]
+    // chksum | checksum [2]
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 44)?;
+        let mut serial_number = [0u8; 32];
+        serial_number.copy_from_slice(&payload[10..42]);
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: GetChipSNStatus::from(payload[9])?,
+            serial_number,
+            checksum: BigEndian::read_u16(&payload[42..44]),
+        });
+    }
+}
+
+/// Result of the `GetRandomCode` call.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct GetRandomCodeResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: GetRandomCodeStatus,
+
+    /// The random number
+    pub random_code: u32,
+
+    pub checksum: u16,
+}
+
+impl FromPayload for GetRandomCodeResult {
+    // Expected packet:
+    // headr  | 0xEF 0x01 [2]
+    // addr   | cmd.address [4]
+    // ident  | 0x07 [1]
+    // length | 0x00 0x07 [2]
+    // confrm | confirmation code [1]
+    // random | random number [4]
+    // chksum | checksum [2]
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 16)?;
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: GetRandomCodeStatus::from(payload[9])?,
+            random_code: BigEndian::read_u32(&payload[10..14]),
+            checksum: BigEndian::read_u16(&payload[14..16]),
+        });
+    }
+}
+
+/// Result of the `HandShake` call.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct HandShakeResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: HandShakeStatus,
+
+    pub checksum: u16,
+}
+
+impl FromPayload for HandShakeResult {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: HandShakeStatus::from(payload[9])?,
+            checksum: BigEndian::read_u16(&payload[10..12]),
+        });
+    }
+}
+
+/// Result of the `SoftRst` call.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct SoftRstResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: SoftRstStatus,
+
+    pub checksum: u16,
+}
+
+impl FromPayload for SoftRstResult {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: SoftRstStatus::from(payload[9])?,
+            checksum: BigEndian::read_u16(&payload[10..12]),
+        });
+    }
+}
+
+/// Result of the `GetFwVer` call.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct GetFwVerResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: GetFwVerStatus,
+
+    /// Version of the firmware, as ASCII padded with zeroes
+    pub version: [u8; 32],
+
+    pub checksum: u16,
+}
+
+impl FromPayload for GetFwVerResult {
+    // Expected packet:
+    // headr  | 0xEF 0x01 [2]
+    // addr   | cmd.address [4]
+    // ident  | 0x07 [1]
+    // length | 0x00 0x23 [2]
+    // confrm | confirmation code [1]
+    // vers   | version [32]
+    // chksum | checksum [2]
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 44)?;
+        let mut version = [0u8; 32];
+        version.copy_from_slice(&payload[10..42]);
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: GetFwVerStatus::from(payload[9])?,
+            version,
+            checksum: BigEndian::read_u16(&payload[42..44]),
+        });
+    }
+}
+
+/// Result of the `GetAlgVer` call.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct GetAlgVerResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: GetAlgVerStatus,
+
+    /// Version of the algorithm library, as ASCII padded with zeroes
+    pub version: [u8; 32],
+
+    pub checksum: u16,
+}
+
+impl FromPayload for GetAlgVerResult {
+    // Expected packet:
+    // headr  | 0xEF 0x01 [2]
+    // addr   | cmd.address [4]
+    // ident  | 0x07 [1]
+    // length | 0x00 0x23 [2]
+    // confrm | confirmation code [1]
+    // vers   | version [32]
+    // chksum | checksum [2]
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 44)?;
+        let mut version = [0u8; 32];
+        version.copy_from_slice(&payload[10..42]);
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: GetAlgVerStatus::from(payload[9])?,
+            version,
+            checksum: BigEndian::read_u16(&payload[42..44]),
+        });
+    }
+}
+
+/// Result of the `WriteNotepad` call.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct WriteNotepadResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: WriteNotepadStatus,
+
+    pub checksum: u16,
+}
+
+impl FromPayload for WriteNotepadResult {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: WriteNotepadStatus::from(payload[9])?,
+            checksum: BigEndian::read_u16(&payload[10..12]),
+        });
+    }
+}
+
+/// Result of the `ReadNotepad` call.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct ReadNotepadResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: ReadNotepadStatus,
+
+    /// Contents of the page
+    pub data: [u8; NOTEPAD_PAGE_SIZE],
+
+    pub checksum: u16,
+}
+
+impl FromPayload for ReadNotepadResult {
+    // Expected packet:
+    // headr  | 0xEF 0x01 [2]
+    // addr   | cmd.address [4]
+    // ident  | 0x07 [1]
+    // length | 0x00 0x23 [2]
+    // confrm | confirmation code [1]
+    // data   | page contents [32]
+    // chksum | checksum [2]
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 44)?;
+        let mut data = [0u8; NOTEPAD_PAGE_SIZE];
+        data.copy_from_slice(&payload[10..42]);
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: ReadNotepadStatus::from(payload[9])?,
+            data,
+            checksum: BigEndian::read_u16(&payload[42..44]),
+        });
+    }
+}
+
+/// Result of the `Sleep` call.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct SleepResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: SleepStatus,
+
+    pub checksum: u16,
+}
+
+impl FromPayload for SleepResult {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: SleepStatus::from(payload[9])?,
+            checksum: BigEndian::read_u16(&payload[10..12]),
+        });
+    }
+}
+
+/// Result of the `PortControl` call.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct PortControlResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: PortControlStatus,
+
+    pub checksum: u16,
+}
+
+impl FromPayload for PortControlResult {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
+            address: BigEndian::read_u32(&payload[2..6]),
+            confirmation_code: PortControlStatus::from(payload[9])?,
+            checksum: BigEndian::read_u16(&payload[10..12]),
+        });
+    }
+}
+
+/// Result of the `CheckSensor` call.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct CheckSensorResult {
+    /// Address of the R502 that sent this message
+    pub address: u32,
+
+    /// Response code
+    pub confirmation_code: CheckSensorStatus,
+
+    pub checksum: u16,
+}
+
+impl FromPayload for CheckSensorResult {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
             address: BigEndian::read_u32(&payload[2..6]),
-            confirmation_code: TemplateNumStatus::from(payload[9]),
-            template_num: BigEndian::read_u16(&payload[10..12]),
-            checksum: BigEndian::read_u16(&payload[12..14]),
-        };
+            confirmation_code: CheckSensorStatus::from(payload[9])?,
+            checksum: BigEndian::read_u16(&payload[10..12]),
+        });
     }
 }
 
-/// Result of generating the fingerprint template for enrollment.
+/// Result of the `AuraLedConfig` call.
 #[derive(Debug)]
-pub struct RegModelResult {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct AuraLedConfigResult {
     /// Address of the R502 that sent this message
     pub address: u32,
 
     /// Response code
-    pub confirmation_code: RegModelStatus,
+    pub confirmation_code: AuraLedConfigStatus,
 
     pub checksum: u16,
 }
 
-impl FromPayload for RegModelResult {
-    fn from_payload(payload: &[u8]) -> Self {
-        return Self {
+impl FromPayload for AuraLedConfigResult {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
             address: BigEndian::read_u32(&payload[2..6]),
-            confirmation_code: RegModelStatus::from(payload[9]),
+            confirmation_code: AuraLedConfigStatus::from(payload[9])?,
             checksum: BigEndian::read_u16(&payload[10..12]),
-        };
+        });
     }
 }
 
-/// Result of storing a fingerprint template.
+/// Result of deleting a fingerprint template.
 #[derive(Debug)]
-pub struct StoreResult {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct DeletCharResult {
     /// Address of the R502 that sent this message
     pub address: u32,
 
     /// Response code
-    pub confirmation_code: StoreStatus,
+    pub confirmation_code: DeletCharStatus,
 
     pub checksum: u16,
 }
 
-impl FromPayload for StoreResult {
-    fn from_payload(payload: &[u8]) -> Self {
-        return Self {
+impl FromPayload for DeletCharResult {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
             address: BigEndian::read_u32(&payload[2..6]),
-            confirmation_code: StoreStatus::from(payload[9]),
+            confirmation_code: DeletCharStatus::from(payload[9])?,
             checksum: BigEndian::read_u16(&payload[10..12]),
-        };
+        });
     }
 }
 
-/// Result of deleting a fingerprint template.
+/// Result of emptying the fingerprint library.
 #[derive(Debug)]
-pub struct DeletCharResult {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub struct EmptyResult {
     /// Address of the R502 that sent this message
     pub address: u32,
 
     /// Response code
-    pub confirmation_code: DeletCharStatus,
+    pub confirmation_code: EmptyStatus,
 
     pub checksum: u16,
 }
 
-impl FromPayload for DeletCharResult {
-    fn from_payload(payload: &[u8]) -> Self {
-        return Self {
+impl FromPayload for EmptyResult {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError> {
+        require(payload, 12)?;
+        return Ok(Self {
             address: BigEndian::read_u32(&payload[2..6]),
-            confirmation_code: DeletCharStatus::from(payload[9]),
+            confirmation_code: EmptyStatus::from(payload[9])?,
             checksum: BigEndian::read_u16(&payload[10..12]),
-        };
+        });
     }
 }
 
 /// System status and configuration.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub struct SystemParameters {
     /// Status information. Use instance methods of SystemParameters to get to individual bits.
     pub status_register: u16,
@@ -353,7 +1156,7 @@ pub struct SystemParameters {
     /// Note, the datasheet contradicts itself as to what's the maximum baud rate supported by
     /// the device, and consequently what's the maximum here. In one place, it says the range is
     /// [1-6], in another it states the max baud rate is 115,200 giving [1-12].
-    /// The default value is 6 for 57,600‬ baud.
+    /// The default value is 6 for 57,600 baud.
     pub baud_setting: u16,
 }
 
@@ -391,24 +1194,36 @@ impl SystemParameters {
 }
 
 impl FromPayload for SystemParameters {
-    fn from_payload(payload: &[u8]) -> SystemParameters {
+    fn from_payload(payload: &[u8]) -> Result<SystemParameters, DecodeError> {
+        require(payload, 16)?;
         // HZ R502's datasheet is a little inconsistent - sometimes the sizes are given in bytes
         // and sometimes in words; words are 16 bit (2 byte).
         // Pick a flipping unit and stick with it!
-        SystemParameters {
+        return Ok(SystemParameters {
             status_register: BigEndian::read_u16(&payload[0..2]),
             system_identifier_code: BigEndian::read_u16(&payload[2..4]),
             finger_library_size: BigEndian::read_u16(&payload[4..6]),
             security_level: BigEndian::read_u16(&payload[6..8]),
             device_address: BigEndian::read_u32(&payload[8..12]),
             packet_size: BigEndian::read_u16(&payload[12..14]),
-            baud_setting: BigEndian::read_u16(&payload[12..16]),
-        }
+            baud_setting: BigEndian::read_u16(&payload[14..16]),
+        });
+    }
+}
+
+/// Checks that `payload` is at least `length` bytes long, so that decoding it stays in bounds.
+fn require(payload: &[u8], length: usize) -> Result<(), DecodeError> {
+    if payload.len() < length {
+        return Err(DecodeError::TooShort);
     }
+    return Ok(());
 }
 
 /// Enum for the password handshake result
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub enum PasswordVerificationState {
     Correct,
     Incorrect,
@@ -416,18 +1231,21 @@ pub enum PasswordVerificationState {
 }
 
 impl PasswordVerificationState {
-    pub fn from(byte: u8) -> Self {
-        return match byte {
+    pub fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
             0x00 => Self::Correct,
             0x13 => Self::Incorrect,
             0x01 => Self::Error,
-            _ => panic!("Invalid VfyPwdResult: {:02x}", byte),
-        };
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
     }
 }
 
 /// Enum for the `GenImg` status code
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub enum GenImgStatus {
     /// Fingerprint has been captured successfully
     Success,
@@ -443,19 +1261,22 @@ pub enum GenImgStatus {
 }
 
 impl GenImgStatus {
-    pub fn from(byte: u8) -> Self {
-        return match byte {
+    pub fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
             0x00 => Self::Success,
             0x01 => Self::PacketError,
             0x02 => Self::FingerNotDetected,
             0x03 => Self::ImageNotCaptured,
-            _ => panic!("Invalid GenImgStatus: {:02x}", byte),
-        };
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
     }
 }
 
 /// Enum for the `Img2Tz` status code
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub enum Img2TzStatus {
     /// Fingerprint processed successfully
     Success,
@@ -476,20 +1297,23 @@ pub enum Img2TzStatus {
 }
 
 impl Img2TzStatus {
-    pub fn from(byte: u8) -> Self {
-        return match byte {
+    pub fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
             0x00 => Self::Success,
             0x01 => Self::PacketError,
             0x06 => Self::FingerprintImageDistorted,
             0x07 => Self::ProcessingFailed,
             0x15 => Self::InvalidInput,
-            _ => panic!("Invalid Img2TzStatus: {:02x}", byte),
-        };
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
     }
 }
 
 /// Enum for the `Search` status code
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub enum SearchStatus {
     /// There is a match
     Success,
@@ -497,21 +1321,30 @@ pub enum SearchStatus {
     PacketError,
     /// No match - index and score will be 0
     NoMatch,
+    /// The library holds no templates to search. Only the R503 says so; the R502 reports
+    /// `NoMatch`.
+    #[cfg(feature = "r503")]
+    LibraryEmpty,
 }
 
 impl SearchStatus {
-    pub fn from(byte: u8) -> Self {
-        return match byte {
+    pub fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
             0x00 => Self::Success,
             0x01 => Self::PacketError,
             0x09 => Self::NoMatch,
-            _ => panic!("Invalid SearchStatus: {:02x}", byte),
-        };
+            #[cfg(feature = "r503")]
+            0x24 => Self::LibraryEmpty,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
     }
 }
 
 /// `LoadChar` status code
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub enum LoadCharStatus {
     /// Operation completed successfully.
     Success,
@@ -523,22 +1356,30 @@ pub enum LoadCharStatus {
     LibraryReadError,
     /// Index given is out of range (eg. > 200 for the R502)
     IndexOutOfRange,
+    /// The slot holds no template. Only the R503 says so; the R502 reports `LibraryReadError`.
+    #[cfg(feature = "r503")]
+    TemplateEmpty,
 }
 
 impl LoadCharStatus {
-    pub fn from(byte: u8) -> Self {
-        return match byte {
+    pub fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
             0x00 => Self::Success,
             0x01 => Self::PacketError,
             0x0c => Self::LibraryReadError,
             0x0b => Self::IndexOutOfRange,
-            _ => panic!("Invalid LoadCharStatus: {:02x}", byte),
-        };
+            #[cfg(feature = "r503")]
+            0x22 => Self::TemplateEmpty,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
     }
 }
 
 /// `Match` status code
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub enum MatchStatus {
     /// Match performed successfully and the two buffers match
     Success,
@@ -549,18 +1390,21 @@ pub enum MatchStatus {
 }
 
 impl MatchStatus {
-    fn from(byte: u8) -> Self {
-        return match byte {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
             0x00 => Self::Success,
             0x01 => Self::PacketError,
             0x08 => Self::NoMatch,
-            _ => panic!("Invalid MatchStatus: {:02x}", byte),
-        };
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
     }
 }
 
 /// `TemplateNum` status code
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub enum TemplateNumStatus {
     /// Request was successful
     Success,
@@ -569,17 +1413,42 @@ pub enum TemplateNumStatus {
 }
 
 impl TemplateNumStatus {
-    fn from(byte: u8) -> Self {
-        return match byte {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
             0x00 => Self::Success,
             0x01 => Self::PacketError,
-            _ => panic!("Invalid TemplateNumStatus: {:02x}", byte),
-        };
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `ReadIndexTable` status code
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum ReadIndexTableStatus {
+    /// Request was successful
+    Success,
+    /// Error reading packet from the host
+    PacketError,
+}
+
+impl ReadIndexTableStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
     }
 }
 
 /// `RegModel` status code
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub enum RegModelStatus {
     /// Request was successful
     Success,
@@ -594,18 +1463,21 @@ pub enum RegModelStatus {
 }
 
 impl RegModelStatus {
-    fn from(byte: u8) -> Self {
-        return match byte {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
             0x00 => Self::Success,
             0x01 => Self::PacketError,
             0x0a => Self::ProcessingError,
-            _ => panic!("Invalid RegModelStatus: {:02x}", byte),
-        };
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
     }
 }
 
 /// `Store` status code
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub enum StoreStatus {
     /// Request was successful
     Success,
@@ -617,22 +1489,454 @@ pub enum StoreStatus {
     /// 
     /// It is unclear when this would happen.
     WriteError,
+    /// The library is full. Only the R503 says so.
+    #[cfg(feature = "r503")]
+    LibraryFull,
 }
 
 impl StoreStatus {
-    fn from(byte: u8) -> Self {
-        return match byte {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
             0x00 => Self::Success,
             0x01 => Self::PacketError,
             0x0b => Self::IndexOutOfRange,
             0x18 => Self::WriteError,
-            _ => panic!("Invalid StoreStatus: {:02x}", byte),
-        };
+            #[cfg(feature = "r503")]
+            0x1f => Self::LibraryFull,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
     }
 }
 
-/// `DeletChar` status code
+/// `UpChar` status code
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum UpCharStatus {
+    /// Request was successful, data packets will follow
+    Success,
+    /// Error reading packet from the host
+    PacketError,
+    /// The module failed to send the data packets
+    UploadFailed,
+}
+
+impl UpCharStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            0x0d => Self::UploadFailed,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `DownChar` status code
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum DownCharStatus {
+    /// Request was successful, the module is ready for the data packets
+    Success,
+    /// Error reading packet from the host
+    PacketError,
+    /// The module cannot receive the data packets
+    CannotReceive,
+}
+
+impl DownCharStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            0x0e => Self::CannotReceive,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `DownImage` status code
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum DownImageStatus {
+    /// Request was successful, the module is ready for the data packets
+    Success,
+    /// Error reading packet from the host
+    PacketError,
+    /// The module cannot receive the data packets
+    CannotReceive,
+}
+
+impl DownImageStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            0x0e => Self::CannotReceive,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `SetPwd` status code
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum SetPwdStatus {
+    /// The new password has been set
+    Success,
+    /// Error reading packet from the host
+    PacketError,
+}
+
+impl SetPwdStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `SetSysPara` status code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum SetSysParaStatus {
+    /// The parameter has been written
+    Success,
+    /// Error reading packet from the host
+    PacketError,
+    /// There is no parameter with the given number
+    WrongRegister,
+}
+
+impl SetSysParaStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            0x1a => Self::WrongRegister,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `SetAdder` status code
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum SetAdderStatus {
+    /// The new address has been set
+    Success,
+    /// Error reading packet from the host
+    PacketError,
+}
+
+impl SetAdderStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `GetChipSN` status code
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum GetChipSNStatus {
+    /// The serial number has been read
+    Success,
+    /// Error reading packet from the host. Older firmware which does not know this
+    /// command also replies with this code.
+    PacketError,
+}
+
+impl GetChipSNStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `GetRandomCode` status code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum GetRandomCodeStatus {
+    /// The random number has been generated
+    Success,
+    /// Error reading packet from the host. Firmware without a random number generator also
+    /// replies with this code.
+    PacketError,
+}
+
+impl GetRandomCodeStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `HandShake` status code
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum HandShakeStatus {
+    /// The module is working normally
+    Success,
+    /// Error reading packet from the host. Older firmware which does not know this
+    /// command also replies with this code.
+    PacketError,
+}
+
+impl HandShakeStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `SoftRst` status code
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum SoftRstStatus {
+    /// The module is resetting
+    Success,
+    /// Error reading packet from the host
+    PacketError,
+}
+
+impl SoftRstStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `GetFwVer` status code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum GetFwVerStatus {
+    /// The version has been read
+    Success,
+    /// Error reading packet from the host. Older firmware which does not know this
+    /// command also replies with this code.
+    PacketError,
+}
+
+impl GetFwVerStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `GetAlgVer` status code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum GetAlgVerStatus {
+    /// The version has been read
+    Success,
+    /// Error reading packet from the host. Older firmware which does not know this
+    /// command also replies with this code.
+    PacketError,
+}
+
+impl GetAlgVerStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `WriteNotepad` status code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum WriteNotepadStatus {
+    /// The page has been written
+    Success,
+    /// Error reading packet from the host
+    PacketError,
+    /// Error writing to flash
+    WriteError,
+}
+
+impl WriteNotepadStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            0x18 => Self::WriteError,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `ReadNotepad` status code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum ReadNotepadStatus {
+    /// The page has been read
+    Success,
+    /// Error reading packet from the host
+    PacketError,
+}
+
+impl ReadNotepadStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `Sleep` status code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum SleepStatus {
+    /// The module is going to sleep
+    Success,
+    /// Error reading packet from the host
+    PacketError,
+}
+
+impl SleepStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `PortControl` status code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum PortControlStatus {
+    /// The port has been turned on or off
+    Success,
+    /// Error reading packet from the host
+    PacketError,
+    /// The module failed to operate the port
+    PortOperationFailed,
+}
+
+impl PortControlStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            0x1d => Self::PortOperationFailed,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `AuraLedConfig` status code
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum AuraLedConfigStatus {
+    /// The LED has been set
+    Success,
+    /// Error reading packet from the host. Modules without the ring LED also reply
+    /// with this code.
+    PacketError,
+}
+
+impl AuraLedConfigStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `CheckSensor` status code
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum CheckSensorStatus {
+    /// The sensor is working normally
+    Success,
+    /// Error reading packet from the host. Older firmware which does not know this
+    /// command also replies with this code.
+    PacketError,
+    /// The sensor is not working
+    SensorAbnormal,
+}
+
+impl CheckSensorStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            0x29 => Self::SensorAbnormal,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `DeletChar` status code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
 pub enum DeletCharStatus {
     /// Request was successful
     Success,
@@ -646,12 +1950,112 @@ pub enum DeletCharStatus {
 }
 
 impl DeletCharStatus {
-    fn from(byte: u8) -> Self {
-        return match byte {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
             0x00 => Self::Success,
             0x01 => Self::PacketError,
             0x10 => Self::DeleteFailed,
-            _ => panic!("Invalid DeletCharStatus: {:02x}", byte),
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+/// `Empty` status code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "fuzzing", derive(arbitrary::Arbitrary))]
+pub enum EmptyStatus {
+    /// Request was successful
+    Success,
+    /// Error reading packet from the host
+    PacketError,
+    /// Failed to clear the library.
+    ClearFailed,
+}
+
+impl EmptyStatus {
+    fn from(byte: u8) -> Result<Self, DecodeError> {
+        return Ok(match byte {
+            0x00 => Self::Success,
+            0x01 => Self::PacketError,
+            0x11 => Self::ClearFailed,
+            _ => return Err(DecodeError::UnknownCode(byte)),
+        });
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    #[test]
+    fn test_system_parameters_round_trip() {
+        // given: the system parameters of a module
+        let parameters = SystemParameters::from_payload(&[
+            0x00, 0x04, 0x00, 0x09, 0x00, 0xc8, 0x00, 0x03, 0xff, 0xff, 0xff, 0xff, 0x00, 0x02,
+            0x00, 0x06,
+        ])
+        .unwrap();
+
+        // when: serialising them to JSON and parsing them back
+        let json = serde_json::to_string(&parameters).unwrap();
+        let parsed: SystemParameters = serde_json::from_str(&json).unwrap();
+
+        // then: nothing was lost
+        assert_eq!(parsed.password_ok(), true);
+        assert_eq!(parsed.finger_library_size, 200);
+        assert_eq!(parsed.device_address, 0xffffffff);
+        assert_eq!(parsed.baud_setting, 6);
+        assert_eq!(json.contains("\"security_level\":3"), true);
+    }
+
+    #[test]
+    fn test_reply_round_trip() {
+        // given: a reply to `Search`
+        let reply = Reply::Search(SearchResult {
+            address: 0xffffffff,
+            confirmation_code: SearchStatus::Success,
+            match_id: 12,
+            match_score: 150,
+            checksum: 0x01bb,
+        });
+
+        // when: serialising it to JSON and parsing it back
+        let json = serde_json::to_string(&reply).unwrap();
+        let parsed: Reply = serde_json::from_str(&json).unwrap();
+
+        // then: nothing was lost, and the status is written out by name
+        match parsed {
+            Reply::Search(result) => {
+                assert_eq!(matches!(result.confirmation_code, SearchStatus::Success), true);
+                assert_eq!((result.match_id, result.match_score), (12, 150));
+            }
+            other => panic!("Expected Reply::Search, got {:?}", other),
+        }
+        assert_eq!(json.contains("\"confirmation_code\":\"Success\""), true);
+    }
+
+    #[test]
+    fn test_version_round_trip() {
+        // given: a reply with a 32-byte version string
+        let mut version = [0u8; 32];
+        version[..3].copy_from_slice(b"1.2");
+        let result = GetFwVerResult {
+            address: 0xffffffff,
+            confirmation_code: GetFwVerStatus::Success,
+            version,
+            checksum: 0,
         };
+
+        // when: serialising it to JSON and parsing it back
+        let json = serde_json::to_string(&result).unwrap();
+        let parsed: GetFwVerResult = serde_json::from_str(&json).unwrap();
+
+        // then: the version survived
+        assert_eq!(parsed.version, version);
+        assert_eq!(parsed.confirmation_code, GetFwVerStatus::Success);
     }
 }
@@ -0,0 +1,271 @@
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::digital::v2::OutputPin;
+
+use crate::transport::Transport;
+
+/// Error writing through an `Rs485` transport.
+#[derive(Debug, PartialEq)]
+pub enum Rs485Error<E, PE> {
+    /// The underlying transport failed to write.
+    Serial(E),
+
+    /// The driver-enable pin could not be set.
+    Pin(PE),
+}
+
+/// A `Transport` for an RS485 transceiver in half-duplex mode, which needs its driver-enable
+/// (DE/RE) pin held high while transmitting and low to hear the reply.
+///
+/// The pin goes high before the first byte of a frame is written and low once the frame
+/// (together with any data packets sent after it) has been flushed. After the flush, it waits
+/// `turnaround_us` microseconds before releasing the pin, so the last bit has left the
+/// transceiver; the R502 does not reply quickly enough for this to lose anything.
+///
+/// ```ignore
+/// let transport = Rs485::new((tx, rx), de_pin, delay, 50);
+/// let mut r502 = R502::with_transport(transport, 0xffffffff);
+/// ```
+#[derive(Debug)]
+pub struct Rs485<T, P, D> {
+    transport: T,
+    de_pin: P,
+    delay: D,
+    turnaround_us: u16,
+    driving: bool,
+}
+
+impl<T, P, D> Rs485<T, P, D>
+where
+    T: Transport,
+    P: OutputPin,
+    D: DelayUs<u16>,
+{
+    /// Wraps `transport`, driving `de_pin` around transmissions. `de_pin` should start low.
+    pub fn new(transport: T, de_pin: P, delay: D, turnaround_us: u16) -> Self {
+        return Self { transport, de_pin, delay, turnaround_us, driving: false };
+    }
+
+    /// Changes the delay between the end of a flush and `de_pin` going low.
+    pub fn set_turnaround_us(&mut self, turnaround_us: u16) {
+        self.turnaround_us = turnaround_us;
+    }
+
+    /// Gives back the transport, pin and delay.
+    pub fn release(self) -> (T, P, D) {
+        return (self.transport, self.de_pin, self.delay);
+    }
+    /// Raises `de_pin` ahead of the first byte of a transmission.
+    fn drive(&mut self) -> Result<(), P::Error> {
+        if !self.driving {
+            self.de_pin.set_high()?;
+            self.driving = true;
+        }
+        return Ok(());
+    }
+}
+
+impl<T, P, D> Transport for Rs485<T, P, D>
+where
+    T: Transport,
+    P: OutputPin,
+    D: DelayUs<u16>,
+{
+    type WriteError = Rs485Error<T::WriteError, P::Error>;
+    type ReadError = T::ReadError;
+
+    fn write_byte(&mut self, byte: u8) -> nb::Result<(), Self::WriteError> {
+        self.drive().map_err(|e| nb::Error::Other(Rs485Error::Pin(e)))?;
+        return self.transport.write_byte(byte).map_err(|e| e.map(Rs485Error::Serial));
+    }
+
+    fn write_slice(&mut self, bytes: &[u8]) -> nb::Result<usize, Self::WriteError> {
+        self.drive().map_err(|e| nb::Error::Other(Rs485Error::Pin(e)))?;
+        return self.transport.write_slice(bytes).map_err(|e| e.map(Rs485Error::Serial));
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::WriteError> {
+        self.transport.flush().map_err(|e| e.map(Rs485Error::Serial))?;
+        if self.driving {
+            self.delay.delay_us(self.turnaround_us);
+            self.de_pin.set_low().map_err(|e| nb::Error::Other(Rs485Error::Pin(e)))?;
+            self.driving = false;
+        }
+        return Ok(());
+    }
+
+    fn read_byte(&mut self) -> nb::Result<u8, Self::ReadError> {
+        return self.transport.read_byte();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::commands::Command;
+    #[cfg(feature = "cmd-transfer")]
+    use crate::consts::PacketSize;
+    use crate::driver::R502;
+    use crate::responses::Reply;
+    #[cfg(feature = "cmd-transfer")]
+    use crate::template::Template;
+    use core::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        High,
+        Low,
+        Write(u8),
+        Flush,
+        Delay(u16),
+    }
+
+    /// Records everything the transport, pin and delay are asked to do, in order.
+    type Log = RefCell<Vec<Event>>;
+
+    struct FakeTransport<'a> {
+        log: &'a Log,
+        incoming: VecDeque<u8>,
+    }
+
+    impl Transport for FakeTransport<'_> {
+        type WriteError = ();
+        type ReadError = ();
+
+        fn write_byte(&mut self, byte: u8) -> nb::Result<(), Self::WriteError> {
+            self.log.borrow_mut().push(Event::Write(byte));
+            return Ok(());
+        }
+
+        fn flush(&mut self) -> nb::Result<(), Self::WriteError> {
+            self.log.borrow_mut().push(Event::Flush);
+            return Ok(());
+        }
+
+        fn read_byte(&mut self) -> nb::Result<u8, Self::ReadError> {
+            return self.incoming.pop_front().ok_or(nb::Error::WouldBlock);
+        }
+    }
+
+    struct FakePin<'a>(&'a Log);
+
+    impl OutputPin for FakePin<'_> {
+        type Error = ();
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.0.borrow_mut().push(Event::High);
+            return Ok(());
+        }
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.0.borrow_mut().push(Event::Low);
+            return Ok(());
+        }
+    }
+
+    struct FakeDelay<'a>(&'a Log);
+
+    impl DelayUs<u16> for FakeDelay<'_> {
+        fn delay_us(&mut self, us: u16) {
+            self.0.borrow_mut().push(Event::Delay(us));
+        }
+    }
+
+    fn r502<'a>(
+        log: &'a Log,
+        incoming: &[u8],
+    ) -> R502<Rs485<FakeTransport<'a>, FakePin<'a>, FakeDelay<'a>>> {
+        let transport = FakeTransport { log, incoming: incoming.iter().copied().collect() };
+        let rs485 = Rs485::new(transport, FakePin(log), FakeDelay(log), 50);
+        return R502::with_transport(rs485, 0xffffffff);
+    }
+
+    #[test]
+    fn test_pin_wraps_command() {
+        // given: an R502 behind an RS485 transceiver, with the reply to `TemplateNum` queued up
+        let log = Log::default();
+        let mut r502 = r502(
+            &log,
+            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x05, 0x00, 0x00, 0x2a, 0x00, 0x36],
+        );
+
+        // when: sending `TemplateNum`
+        let reply = r502.send_command(Command::TemplateNum).unwrap();
+
+        // then: the pin went high before the first byte, and low after the flush and delay
+        assert_eq!(matches!(reply, Reply::TemplateNum(_)), true);
+        let log = log.into_inner();
+        assert_eq!(log.len(), 1 + 12 + 3);
+        assert_eq!(log[0], Event::High);
+        assert_eq!(log[1], Event::Write(0xef));
+        assert_eq!(log[12], Event::Write(0x21));
+        assert_eq!(log[13..], [Event::Flush, Event::Delay(50), Event::Low]);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_pin_wraps_data_packets() {
+        // given: an R502 behind an RS485 transceiver, acknowledging `DownChar`
+        let log = Log::default();
+        let mut r502 = r502(
+            &log,
+            &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x00, 0x00, 0x0a],
+        );
+        r502.set_data_packet_size(PacketSize::Bytes32);
+
+        // when: downloading a 40-byte template
+        let template = Template::from_bytes(&[0x11; 40]).unwrap();
+        r502.download_template(1, &template).unwrap();
+
+        // then: the pin is released only after each flush, and never left high
+        let log = log.into_inner();
+        let pin: Vec<usize> = log
+            .iter()
+            .enumerate()
+            .filter(|(_, event)| matches!(event, Event::High | Event::Low))
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(pin.len(), 4);
+        for pair in pin.chunks(2) {
+            assert_eq!(log[pair[0]], Event::High);
+            assert_eq!(log[pair[1]], Event::Low);
+            assert_eq!(log[pair[1] - 2], Event::Flush);
+            let writes = &log[pair[0] + 1..pair[1] - 2];
+            assert_eq!(writes.iter().all(|event| matches!(event, Event::Write(_))), true);
+        }
+        assert_eq!(log[pin[1] + 1..pin[2]].is_empty(), true);
+        assert_eq!(log[pin[2] + 1..pin[3] - 2].len(), (11 + 32) + (11 + 8));
+    }
+
+    #[test]
+    fn test_pin_error() {
+        // given: a driver-enable pin which cannot be set
+        struct BrokenPin;
+
+        impl OutputPin for BrokenPin {
+            type Error = &'static str;
+
+            fn set_high(&mut self) -> Result<(), Self::Error> {
+                return Err("stuck");
+            }
+
+            fn set_low(&mut self) -> Result<(), Self::Error> {
+                return Ok(());
+            }
+        }
+
+        let log = Log::default();
+        let transport = FakeTransport { log: &log, incoming: VecDeque::new() };
+        let mut rs485 = Rs485::new(transport, BrokenPin, FakeDelay(&log), 50);
+
+        // when: writing
+        let result = rs485.write_all(&[0xef]);
+
+        // then: the pin error comes back and nothing is written
+        assert_eq!(result, Err(Rs485Error::Pin("stuck")));
+        assert_eq!(log.into_inner().is_empty(), true);
+    }
+}
@@ -0,0 +1,213 @@
+use arrayvec::ArrayVec;
+use embedded_hal::blocking::delay::DelayMs;
+
+use crate::bus::R502Bus;
+use crate::driver::R502;
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// Addresses [`R502::scan_addresses`](struct.R502.html#method.scan_addresses) tries before any
+/// other: the factory default, then all zeros, which some clones come with.
+pub const COMMON_ADDRESSES: [u32; 2] = [0xffffffff, 0x00000000];
+
+/// Most addresses one scan reports.
+pub const MAX_FOUND_ADDRESSES: usize = 8;
+
+/// The addresses a scan got an answer from, in the order they were tried.
+pub type FoundAddresses = ArrayVec<u32, MAX_FOUND_ADDRESSES>;
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Looks for modules at `COMMON_ADDRESSES`, and then at each of `candidates`, by sending
+    /// each a `HandShake`. An address is reported if a reply with a good checksum comes back
+    /// from it within `timeout_ms`, counted in 1 ms steps on `delay`; a garbled reply counts as
+    /// no answer. The scan stops early once `MAX_FOUND_ADDRESSES` have answered. Afterwards,
+    /// commands go to the address they went to before.
+    ///
+    /// Each address which does not answer costs `timeout_ms`, so a few milliseconds is best:
+    /// the module answers a `HandShake` straight away. As with
+    /// [`detect_baud`](#method.detect_baud), this needs a transport which returns `WouldBlock`
+    /// while there is nothing to read.
+    ///
+    /// ```ignore
+    /// let found = r502.scan_addresses(1..=16, &mut delay, 5)?;
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// `Error::WriteError` if a command could not be sent, and `Error::ModuleAsleep` or
+    /// `Error::CommandInProgress` as for `send_command`. Nothing else stops the scan.
+    pub fn scan_addresses<I, D>(
+        &mut self,
+        candidates: I,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<FoundAddresses, Error<T::WriteError, T::ReadError>>
+    where
+        I: IntoIterator<Item = u32>,
+        D: DelayMs<u16>,
+    {
+        let original = self.address;
+        let others = candidates.into_iter().filter(|address| !COMMON_ADDRESSES.contains(address));
+        let mut found = FoundAddresses::new();
+        for address in COMMON_ADDRESSES.iter().copied().chain(others) {
+            if found.is_full() {
+                break;
+            }
+            match self.probe(address, delay, timeout_ms) {
+                Ok(true) => found.push(address),
+                Ok(false) => {}
+                Err(error) => {
+                    self.address = original;
+                    return Err(error);
+                }
+            }
+        }
+        self.address = original;
+        return Ok(found);
+    }
+
+    /// Whether a module answers at `address`. Throws away whatever was waiting to be read
+    /// first, such as a late reply to the address before.
+    fn probe<D>(
+        &mut self,
+        address: u32,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<bool, Error<T::WriteError, T::ReadError>>
+    where
+        D: DelayMs<u16>,
+    {
+        self.drain_input();
+        self.address = address;
+        return match self.try_handshake(delay, timeout_ms) {
+            Ok(()) => Ok(self.reply_address() == Some(address)),
+            Err(error @ Error::WriteError(_))
+            | Err(error @ Error::ModuleAsleep)
+            | Err(error @ Error::CommandInProgress) => Err(error),
+            Err(_) => Ok(false),
+        };
+    }
+}
+
+impl<T> R502Bus<T>
+where
+    T: Transport,
+{
+    /// Looks for modules on the line, as for
+    /// [`R502::scan_addresses`](struct.R502.html#method.scan_addresses), to find what to
+    /// [`add_device`](#method.add_device). Addresses are probed with `HandShake`, which needs
+    /// no password.
+    pub fn scan_addresses<I, D>(
+        &mut self,
+        candidates: I,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<FoundAddresses, Error<T::WriteError, T::ReadError>>
+    where
+        I: IntoIterator<Item = u32>,
+        D: DelayMs<u16>,
+    {
+        return self.r502.scan_addresses(candidates, delay, timeout_ms);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::commands::Command;
+    use crate::emulator::{Emulator, EmulatorRx, EmulatorTx, NoDelay};
+    use std::vec;
+    use std::vec::Vec;
+
+    /// A module at `address`, which answers nothing else.
+    fn module_at(address: u32) -> (Emulator, R502<(EmulatorTx, EmulatorRx)>) {
+        let emulator = Emulator::new();
+        emulator.state().address = address;
+        emulator.state().would_block = true;
+        let (tx, rx) = emulator.serial();
+        return (emulator, R502::new(tx, rx, 0xffffffff));
+    }
+
+    #[test]
+    fn test_scan_addresses() {
+        // given: a module at an address other than the default
+        let (emulator, mut r502) = module_at(0x0000_0005);
+
+        // when: scanning a range of addresses
+        let found = r502.scan_addresses(1..=8, &mut NoDelay, 5).unwrap();
+
+        // then: only that address answered, and commands still go to the default address
+        assert_eq!(found.as_slice(), &[5]);
+        assert_eq!(emulator.instructions(), vec![0x40]);
+        assert_eq!(r502.address(), 0xffffffff);
+    }
+
+    #[test]
+    fn test_scan_common_addresses_first() {
+        // given: a clone whose address is all zeros
+        let (_emulator, mut r502) = module_at(0);
+
+        // when: scanning with no other candidates, and with the common ones among them
+        let alone = r502.scan_addresses(core::iter::empty(), &mut NoDelay, 5).unwrap();
+        let repeated = r502.scan_addresses(vec![0, 0xffffffff, 3], &mut NoDelay, 5).unwrap();
+
+        // then: it is found, once
+        assert_eq!(alone.as_slice(), &[0]);
+        assert_eq!(repeated.as_slice(), &[0]);
+    }
+
+    #[test]
+    fn test_scan_ignores_stale_replies() {
+        // given: a module at address 7, with a reply from it to an earlier command still
+        // waiting to be read
+        let (emulator, mut r502) = module_at(7);
+        r502.set_address(7);
+        r502.start_command(Command::HandShake).unwrap();
+        r502.abandon();
+
+        // when: scanning
+        let found = r502.scan_addresses(vec![6, 7], &mut NoDelay, 5).unwrap();
+
+        // then: the old reply was not taken for an answer from another address
+        assert_eq!(found.as_slice(), &[7]);
+        assert_eq!(emulator.instructions(), vec![0x40, 0x40]);
+        assert_eq!(r502.address(), 7);
+    }
+
+    #[test]
+    fn test_scan_long_range() {
+        // given: a module which answers at the default address
+        let (emulator, mut r502) = module_at(0xffffffff);
+
+        // when: scanning a hundred addresses, none of which answer
+        let candidates: Vec<u32> = (1..=100).collect();
+        let found = r502.scan_addresses(candidates, &mut NoDelay, 1).unwrap();
+
+        // then: only the default address is reported
+        assert_eq!(found.as_slice(), &[0xffffffff]);
+        assert_eq!(emulator.instructions(), vec![0x40]);
+    }
+
+    #[test]
+    fn test_bus_scan() {
+        // given: a bus with one module on it, at address 3
+        let emulator = Emulator::new();
+        emulator.state().address = 3;
+        emulator.state().would_block = true;
+        let (tx, rx) = emulator.serial();
+        let mut bus = R502Bus::new(tx, rx);
+
+        // when: scanning it, and adding what was found
+        let found = bus.scan_addresses(1..=4, &mut NoDelay, 5).unwrap();
+        let device = bus.add_device(found[0], 0).unwrap();
+
+        // then: the module can be talked to
+        assert_eq!(found.as_slice(), &[3]);
+        assert_eq!(bus.send_command(device, Command::TemplateNum).is_ok(), true);
+    }
+}
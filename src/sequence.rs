@@ -0,0 +1,192 @@
+use arrayvec::ArrayVec;
+
+use crate::commands::Command;
+use crate::driver::R502;
+use crate::responses::Reply;
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// Most commands [`R502::run_sequence`](struct.R502.html#method.run_sequence) runs, as it
+/// keeps every reply. `run_sequence_with` has no limit.
+pub const MAX_SEQUENCE_LENGTH: usize = 16;
+
+/// The replies to a sequence of commands, in order.
+pub type SequenceReplies = ArrayVec<Reply, MAX_SEQUENCE_LENGTH>;
+
+/// Error type for `run_sequence` and `run_sequence_with`. The commands before `position`
+/// succeeded; those after it were not sent.
+#[derive(Debug)]
+pub enum SequenceError<TXE, RXE> {
+    /// Communication with the R502 failed while sending the command at `position`.
+    Comms { position: usize, error: Error<TXE, RXE> },
+
+    /// The command at `position` was answered with a confirmation code other than success.
+    Failed { position: usize, reply: Reply },
+
+    /// The sequence is `length` commands long, more than `MAX_SEQUENCE_LENGTH`. Nothing was
+    /// sent.
+    TooLong { length: usize },
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Sends each of `cmds` in turn, stopping at the first which fails, and returns the
+    /// replies, for scripts which would otherwise check every reply by hand. A command fails
+    /// if it cannot be sent or its reply does not say it succeeded, see `Reply::is_success`.
+    ///
+    /// ```ignore
+    /// r502.run_sequence(&[
+    ///     Command::VfyPwd { password: 0 },
+    ///     Command::SetSysPara { parameter: 5, value: 4 },
+    ///     Command::SetPwd { password: 0x1234 },
+    /// ])?;
+    /// ```
+    ///
+    /// Sequences are limited to `MAX_SEQUENCE_LENGTH` commands; use
+    /// [`run_sequence_with`](#method.run_sequence_with) for longer ones.
+    pub fn run_sequence(
+        &mut self,
+        cmds: &[Command],
+    ) -> Result<SequenceReplies, SequenceError<T::WriteError, T::ReadError>> {
+        if cmds.len() > MAX_SEQUENCE_LENGTH {
+            return Err(SequenceError::TooLong { length: cmds.len() });
+        }
+        let mut replies = SequenceReplies::new();
+        self.run_sequence_with(cmds, |_, reply| replies.push(reply))?;
+        return Ok(replies);
+    }
+
+    /// Like [`run_sequence`](#method.run_sequence), but hands each successful reply to
+    /// `on_reply` with the position of its command, rather than keeping them. This needs no
+    /// room for the replies, so there is no limit on the length of the sequence.
+    pub fn run_sequence_with<F>(
+        &mut self,
+        cmds: &[Command],
+        mut on_reply: F,
+    ) -> Result<(), SequenceError<T::WriteError, T::ReadError>>
+    where
+        F: FnMut(usize, Reply),
+    {
+        for (position, cmd) in cmds.iter().enumerate() {
+            let reply = self
+                .send_command(*cmd)
+                .map_err(|error| SequenceError::Comms { position, error })?;
+            if !reply.is_success() {
+                return Err(SequenceError::Failed { position, reply });
+            }
+            on_reply(position, reply);
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::emulator::{Emulator, EmulatorRx, EmulatorTx};
+    use crate::responses::LoadCharStatus;
+    use std::vec;
+    use std::vec::Vec;
+
+    fn r502(emulator: &Emulator) -> R502<(EmulatorTx, EmulatorRx)> {
+        let (tx, rx) = emulator.serial();
+        return R502::new(tx, rx, 0xffffffff);
+    }
+
+    #[test]
+    fn test_run_sequence() {
+        // given: a module with a template in slot 5
+        let emulator = Emulator::new();
+        emulator.enroll(5, 7);
+        let mut r502 = r502(&emulator);
+
+        // when: running a sequence which only succeeds
+        let replies = r502
+            .run_sequence(&[
+                Command::HandShake,
+                Command::TemplateNum,
+                Command::LoadChar { buffer: 1, index: 5 },
+            ])
+            .unwrap();
+
+        // then: every reply is there, in order
+        assert_eq!(replies.len(), 3);
+        assert_eq!(matches!(replies[0], Reply::HandShake(_)), true);
+        assert_eq!(matches!(replies[1], Reply::TemplateNum(_)), true);
+        assert_eq!(matches!(replies[2], Reply::LoadChar(_)), true);
+        assert_eq!(emulator.instructions(), vec![0x40, 0x1d, 0x07]);
+    }
+
+    #[test]
+    fn test_run_sequence_stops_at_failure() {
+        // given: a sequence whose third command loads an empty slot
+        let emulator = Emulator::new();
+        let mut r502 = r502(&emulator);
+        let cmds = [
+            Command::HandShake,
+            Command::TemplateNum,
+            Command::LoadChar { buffer: 1, index: 5 },
+            Command::Empty,
+        ];
+
+        // when: running it
+        let mut positions = Vec::new();
+        let result = r502.run_sequence_with(&cmds, |position, _| positions.push(position));
+
+        // then: the failure is reported at the third command, and the fourth was not sent
+        match result {
+            Err(SequenceError::Failed { position: 2, reply: Reply::LoadChar(result) }) => {
+                assert_eq!(
+                    matches!(result.confirmation_code, LoadCharStatus::LibraryReadError),
+                    true
+                );
+            }
+            other => panic!("Expected a failure at the third command, got {:?}", other),
+        }
+        assert_eq!(positions, vec![0, 1]);
+        assert_eq!(emulator.instructions(), vec![0x40, 0x1d, 0x07]);
+    }
+
+    #[test]
+    fn test_run_sequence_comms_error() {
+        // given: a module which stops answering after the first command
+        let emulator = Emulator::new();
+        let mut r502 = r502(&emulator);
+        let mut silenced = Some(emulator.clone());
+
+        // when: running a sequence
+        let result = r502.run_sequence_with(&[Command::HandShake, Command::TemplateNum], |_, _| {
+            if let Some(emulator) = silenced.take() {
+                emulator.state().silent = true;
+            }
+        });
+
+        // then: the second command is blamed
+        match result {
+            Err(SequenceError::Comms { position: 1, .. }) => {}
+            other => panic!("Expected a comms error at the second command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_run_sequence_too_long() {
+        // given: a sequence longer than the replies can be kept for
+        let emulator = Emulator::new();
+        let mut r502 = r502(&emulator);
+        let cmds = [Command::HandShake; MAX_SEQUENCE_LENGTH + 1];
+
+        // when: running it
+        let result = r502.run_sequence(&cmds);
+
+        // then: it is refused before anything is sent
+        match result {
+            Err(SequenceError::TooLong { length }) => assert_eq!(length, MAX_SEQUENCE_LENGTH + 1),
+            other => panic!("Expected the sequence to be too long, got {:?}", other),
+        }
+        assert_eq!(emulator.instructions().is_empty(), true);
+    }
+}
@@ -0,0 +1,174 @@
+use core::fmt;
+use embedded_hal::serial::{Read, Write};
+use serialport::SerialPort;
+use std::boxed::Box;
+use std::io;
+
+use crate::driver::R502;
+use crate::transport::CombinedSerial;
+
+/// Error reading from or writing to a `SerialPortAdapter`.
+#[derive(Debug)]
+pub enum SerialPortError {
+    /// Nothing could be read or written within the port's timeout, as set with
+    /// `SerialPort::set_timeout`. When reading, the module most likely did not reply.
+    Timeout,
+
+    /// Any other I/O error.
+    Io(io::Error),
+}
+
+impl From<io::Error> for SerialPortError {
+    fn from(error: io::Error) -> Self {
+        return match error.kind() {
+            io::ErrorKind::TimedOut => Self::Timeout,
+            _ => Self::Io(error),
+        };
+    }
+}
+
+/// A host serial port from the `serialport` crate, as an embedded-hal serial port, for
+/// running the driver on a PC with a USB to serial converter.
+///
+/// The port's own timeout decides how long the driver waits for each byte of a reply; once it
+/// runs out, the driver returns `Error::RecvReadError(SerialPortError::Timeout)` rather than
+/// waiting forever.
+pub struct SerialPortAdapter(Box<dyn SerialPort>);
+
+impl fmt::Debug for SerialPortAdapter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.debug_tuple("SerialPortAdapter").field(&self.0.name()).finish();
+    }
+}
+
+impl SerialPortAdapter {
+    /// Wraps `port`, which should already be set to the module's baud rate.
+    pub fn new(port: Box<dyn SerialPort>) -> Self {
+        return Self(port);
+    }
+
+    /// The serial port, for changing its settings.
+    pub fn port_mut(&mut self) -> &mut dyn SerialPort {
+        return self.0.as_mut();
+    }
+
+    /// Unwraps the serial port.
+    pub fn into_inner(self) -> Box<dyn SerialPort> {
+        return self.0;
+    }
+}
+
+/// Maps an I/O error to the `nb` error the driver expects: errors which only mean "not yet"
+/// are retried, the rest are reported.
+fn nb_error(error: io::Error) -> nb::Error<SerialPortError> {
+    return match error.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => nb::Error::WouldBlock,
+        _ => nb::Error::Other(SerialPortError::from(error)),
+    };
+}
+
+impl Read<u8> for SerialPortAdapter {
+    type Error = SerialPortError;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        let mut buffer = [0u8; 1];
+        return match self.0.read(&mut buffer) {
+            Ok(1) => Ok(buffer[0]),
+            Ok(_) => Err(nb::Error::WouldBlock),
+            Err(error) => Err(nb_error(error)),
+        };
+    }
+}
+
+impl Write<u8> for SerialPortAdapter {
+    type Error = SerialPortError;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        return match self.0.write(&[word]) {
+            Ok(1) => Ok(()),
+            Ok(_) => Err(nb::Error::WouldBlock),
+            Err(error) => Err(nb_error(error)),
+        };
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        return self.0.flush().map_err(nb_error);
+    }
+}
+
+impl R502<CombinedSerial<SerialPortAdapter>> {
+    /// Creates an instance of the R502 on a host serial port from the `serialport` crate.
+    /// `port` should already be set to the module's baud rate (57600 by default) and a
+    /// timeout. `address` is the R502 address. By default this should be `0xffffffff`.
+    pub fn from_serialport(port: Box<dyn SerialPort>, address: u32) -> Self {
+        return Self::from_serial(SerialPortAdapter::new(port), address);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::commands::Command;
+    use crate::responses::Reply;
+    use crate::utils::Error;
+    use serialport::posix::TTYPort;
+    use std::io::{Read as _, Write as _};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_send_command_over_pty() {
+        // given: an R502 on one end of a pseudo-terminal, with a fake module on the other end
+        // which replies to `TemplateNum`
+        let (mut module, host) = TTYPort::pair().unwrap();
+        module.set_timeout(Duration::from_secs(5)).unwrap();
+        let fake = thread::spawn(move || {
+            let mut command = [0u8; 12];
+            module.read_exact(&mut command).unwrap();
+            module
+                .write_all(&[
+                    0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x05, 0x00, 0x00, 0x2a, 0x00,
+                    0x36,
+                ])
+                .unwrap();
+            // Keep the module's end open until the reply has been read
+            return (command, module);
+        });
+        let mut r502 = R502::from_serialport(Box::new(host), 0xffffffff);
+        let port = r502.transport_mut().inner_mut().port_mut();
+        port.set_timeout(Duration::from_secs(5)).unwrap();
+
+        // when: sending `TemplateNum`
+        let reply = r502.send_command(Command::TemplateNum).unwrap();
+
+        // then: the module got the command, and the reply came back
+        let (command, _module) = fake.join().unwrap();
+        assert_eq!(
+            command,
+            [0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x1d, 0x00, 0x21]
+        );
+        match reply {
+            Reply::TemplateNum(result) => assert_eq!(result.template_num, 42),
+            other => panic!("Expected Reply::TemplateNum, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        // given: an R502 on a pseudo-terminal, with a module on the other end which never
+        // replies
+        let (_module, host) = TTYPort::pair().unwrap();
+        let mut r502 = R502::from_serialport(Box::new(host), 0xffffffff);
+        let port = r502.transport_mut().inner_mut().port_mut();
+        port.set_timeout(Duration::from_millis(50)).unwrap();
+
+        // when: sending a command
+        let result = r502.send_command(Command::TemplateNum);
+
+        // then: it gives up once the port times out
+        match result {
+            Err(Error::RecvReadError(SerialPortError::Timeout)) => {}
+            other => panic!("Expected a read timeout, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,237 @@
+
+use crate::driver::R502;
+use crate::enroll::{capture_buffer, EnrollConfig, EnrollError};
+use crate::transport::Transport;
+
+/// What an `EnrollmentSession` is waiting for, as returned by
+/// [`step`](struct.EnrollmentSession.html#method.step).
+#[derive(Debug)]
+pub enum SessionState<TXE, RXE> {
+    /// The user should place their finger on the sensor for capture number `n` (1-based).
+    WaitingForFinger(u8),
+
+    /// The user should lift their finger off the sensor.
+    NeedFingerLift,
+
+    /// A finger was captured and is about to be processed. Call `step` again straight away.
+    Processing,
+
+    /// The template was stored at the given index.
+    Complete(u16),
+
+    /// Enrolment failed. The session is over; further steps return
+    /// `EnrollError::SessionOver`.
+    Failed(EnrollError<TXE, RXE>),
+}
+
+/// Outcome of a single step, before failures are turned into `SessionState::Failed`.
+type AdvanceResult<TXE, RXE> = Result<SessionState<TXE, RXE>, EnrollError<TXE, RXE>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Place(u8),
+    Process(u8),
+    Lift(u8),
+    Complete,
+    Failed,
+}
+
+/// A non-blocking enrolment, for firmware that cannot sit in
+/// [`R502::enroll`](struct.R502.html#method.enroll) while the user places and lifts their
+/// finger.
+///
+/// Each call to [`step`](#method.step) sends at most a handful of commands and reports what
+/// the session is waiting for. The caller decides how often to step: `config.max_polls` still
+/// limits how many steps are spent waiting for a finger to be placed or lifted, but
+/// `config.poll_interval_ms` is up to the caller. The captures are processed, combined and
+/// stored exactly as `R502::enroll` does it.
+///
+/// **Note:** This will overwrite any template already stored at `index`.
+#[derive(Debug, Clone)]
+pub struct EnrollmentSession {
+    index: u16,
+    config: EnrollConfig,
+    stage: Stage,
+    polls: u16,
+}
+
+impl EnrollmentSession {
+    /// Prepares to enrol a fingerprint at `index`. Nothing is sent until the first step.
+    pub fn new(index: u16, config: EnrollConfig) -> Self {
+        return Self {
+            index,
+            config,
+            stage: Stage::Place(1),
+            polls: 0,
+        };
+    }
+
+    /// True once the session has completed or failed.
+    pub fn is_finished(&self) -> bool {
+        return matches!(self.stage, Stage::Complete | Stage::Failed);
+    }
+
+    /// Moves the enrolment along as far as it can go without waiting for the user.
+    pub fn step<T>(&mut self, r502: &mut R502<T>) -> SessionState<T::WriteError, T::ReadError>
+    where
+        T: Transport,
+    {
+        let result = self.advance(r502);
+        return match result {
+            Ok(state) => {
+                if let SessionState::Complete(_) = state {
+                    r502.led_feedback(&self.config.led, |led| led.success);
+                }
+                state
+            }
+            Err(EnrollError::SessionOver) => SessionState::Failed(EnrollError::SessionOver),
+            Err(error) => {
+                self.stage = Stage::Failed;
+                r502.led_feedback(&self.config.led, |led| led.failure);
+                SessionState::Failed(error)
+            }
+        };
+    }
+
+    fn advance<T>(
+        &mut self,
+        r502: &mut R502<T>,
+    ) -> AdvanceResult<T::WriteError, T::ReadError>
+    where
+        T: Transport,
+    {
+        let captures = self.config.captures;
+        match self.stage {
+            Stage::Place(capture) => {
+                if self.polls == 0 {
+                    if !(2..=6).contains(&captures) {
+                        return Err(EnrollError::InvalidCaptureCount(captures));
+                    }
+                    r502.led_feedback(&self.config.led, |led| led.waiting);
+                }
+                if r502.poll_sensor(capture, true)? {
+                    self.enter(Stage::Process(capture));
+                    return Ok(SessionState::Processing);
+                }
+                self.count_poll()?;
+                return Ok(SessionState::WaitingForFinger(capture));
+            }
+            Stage::Process(capture) => {
+                r502.convert_capture(capture_buffer(capture, &self.config))?;
+                r502.merge_capture(capture, &self.config)?;
+                if capture < captures {
+                    self.enter(Stage::Lift(capture + 1));
+                    return Ok(SessionState::NeedFingerLift);
+                }
+                r502.finish_enrollment(self.index, &self.config)?;
+                self.enter(Stage::Complete);
+                return Ok(SessionState::Complete(self.index));
+            }
+            Stage::Lift(next) => {
+                if r502.poll_sensor(next - 1, false)? {
+                    self.enter(Stage::Place(next));
+                    return Ok(SessionState::WaitingForFinger(next));
+                }
+                self.count_poll()?;
+                return Ok(SessionState::NeedFingerLift);
+            }
+            Stage::Complete => return Ok(SessionState::Complete(self.index)),
+            Stage::Failed => return Err(EnrollError::SessionOver),
+        }
+    }
+
+    fn enter(&mut self, stage: Stage) {
+        self.stage = stage;
+        self.polls = 0;
+    }
+
+    fn count_poll<TXE, RXE>(&mut self) -> Result<(), EnrollError<TXE, RXE>> {
+        self.polls += 1;
+        if self.polls >= self.config.max_polls {
+            return Err(EnrollError::Timeout);
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::emulator::{Emulator, EmulatorError, EmulatorRx, EmulatorTx};
+    use std::vec::Vec;
+
+    /// Steps `session` until it finishes, collecting every state on the way.
+    fn run(
+        session: &mut EnrollmentSession,
+        r502: &mut R502<(EmulatorTx, EmulatorRx)>,
+    ) -> Vec<SessionState<EmulatorError, EmulatorError>> {
+        let mut states = Vec::new();
+        while !session.is_finished() {
+            states.push(session.step(r502));
+        }
+        return states;
+    }
+
+    #[test]
+    fn test_session_full_enrollment() {
+        // given: a user who places finger 7 after one empty poll, lifts it, and places it again
+        let emulator = Emulator::new();
+        emulator.touch(&[None, Some(7), Some(7), None, Some(7)]);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: stepping a two-capture session to the end
+        let mut session = EnrollmentSession::new(3, EnrollConfig::default());
+        let states = run(&mut session, &mut r502);
+
+        // then: the session goes through every state in turn
+        let states: Vec<&str> = states
+            .iter()
+            .map(|state| match state {
+                SessionState::WaitingForFinger(1) => "place 1",
+                SessionState::WaitingForFinger(2) => "place 2",
+                SessionState::NeedFingerLift => "lift",
+                SessionState::Processing => "processing",
+                SessionState::Complete(3) => "complete",
+                other => panic!("Unexpected state {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            states,
+            ["place 1", "processing", "lift", "lift", "place 2", "processing", "complete"]
+        );
+
+        // and: the template was stored like the blocking helper would have
+        assert_eq!(emulator.slot(3).unwrap()[0], 7);
+        assert_eq!(emulator.slot(3).unwrap()[1], 2);
+    }
+
+    #[test]
+    fn test_session_timeout() {
+        // given: a user who never places their finger
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: stepping a session allowing three polls
+        let config = EnrollConfig { max_polls: 3, ..EnrollConfig::default() };
+        let mut session = EnrollmentSession::new(3, config);
+        let states = run(&mut session, &mut r502);
+
+        // then: it fails after the third poll
+        assert_eq!(states.len(), 3);
+        match states.last() {
+            Some(SessionState::Failed(EnrollError::Timeout)) => {}
+            other => panic!("Expected EnrollError::Timeout, got {:?}", other),
+        };
+
+        // and: stepping it again does not talk to the module
+        match session.step(&mut r502) {
+            SessionState::Failed(EnrollError::SessionOver) => {}
+            other => panic!("Expected EnrollError::SessionOver, got {:?}", other),
+        };
+        assert_eq!(emulator.instructions().len(), 3);
+    }
+}
@@ -0,0 +1,257 @@
+use core::convert::Infallible;
+use embedded_hal::serial::{Read, Write};
+
+use crate::allocation::SlotAllocation;
+use crate::audit::AuditLog;
+use crate::codec::{self, CommandBuffer};
+use crate::commands::{Command, CommandKind};
+use crate::compat::ModuleFamily;
+use crate::driver::R502;
+use crate::library::IndexCache;
+use crate::parser::{FrameError, ReplyParser};
+use crate::profile::TimingProfile;
+use crate::stats::DriverStats;
+use crate::responses::Reply;
+use crate::utils::Error;
+use crate::workflow::WorkflowTracker;
+
+/// The transmitting half of a split [`R502`](struct.R502.html), see
+/// [`R502::split`](struct.R502.html#method.split). It encodes and writes commands, and keeps
+/// the driver's settings until the halves are joined again.
+#[derive(Debug)]
+pub struct R502Sender<TX> {
+    tx: TX,
+    address: u32,
+    cmd_buffer: CommandBuffer,
+    data_packet_size: u16,
+    index_cache: IndexCache,
+    allocation: SlotAllocation,
+    family: ModuleFamily,
+    profile: TimingProfile,
+    stats: DriverStats,
+    workflow: WorkflowTracker,
+    adopt_address: bool,
+    audit: Option<AuditLog>,
+}
+
+/// The receiving half of a split [`R502`](struct.R502.html), see
+/// [`R502::split`](struct.R502.html#method.split). It puts replies together from the bytes as
+/// they arrive, so it can live in a UART interrupt handler.
+#[derive(Debug)]
+pub struct R502Receiver<RX> {
+    rx: RX,
+    parser: ReplyParser,
+}
+
+impl<TX, RX> R502<(TX, RX)>
+where
+    TX: Write<u8>,
+    RX: Read<u8>,
+{
+    /// Splits the driver into a sender, which writes commands, and a receiver, which reads the
+    /// replies, so that each can be used from a different context. The sender hands back the
+    /// `CommandKind` of every command it sends, and the receiver needs it to decode the reply.
+    ///
+    /// Only single commands go through the halves; the helpers, and template transfers with
+    /// their data packets, need the driver whole again, see [`join`](#method.join). Neither
+    /// half keeps the cached _index table_ in step, so it is forgotten.
+    ///
+    /// Any command left in progress by [`start_command`](#method.start_command) is abandoned.
+    pub fn split(self) -> (R502Sender<TX>, R502Receiver<RX>) {
+        let mut index_cache = self.index_cache;
+        index_cache.invalidate();
+        let (tx, rx) = self.transport;
+        let sender = R502Sender {
+            tx,
+            address: self.address,
+            cmd_buffer: CommandBuffer::new(),
+            data_packet_size: self.data_packet_size,
+            index_cache,
+            allocation: self.allocation,
+            family: self.family,
+            profile: self.profile,
+            stats: self.stats,
+            workflow: self.workflow,
+            adopt_address: self.adopt_address,
+            audit: self.audit,
+        };
+        let receiver = R502Receiver { rx, parser: ReplyParser::new() };
+        return (sender, receiver);
+    }
+
+    /// Puts a driver split with [`split`](#method.split) back together, with the settings it
+    /// had. Whatever the receiver has of a reply not yet complete is dropped.
+    pub fn join(sender: R502Sender<TX>, receiver: R502Receiver<RX>) -> Self {
+        let mut r502 = Self::new(sender.tx, receiver.rx, sender.address);
+        r502.data_packet_size = sender.data_packet_size;
+        r502.index_cache = sender.index_cache;
+        r502.allocation = sender.allocation;
+        r502.family = sender.family;
+        r502.profile = sender.profile;
+        r502.stats = sender.stats;
+        r502.workflow = sender.workflow;
+        r502.adopt_address = sender.adopt_address;
+        r502.audit = sender.audit;
+        return r502;
+    }
+}
+
+impl<TX> R502Sender<TX>
+where
+    TX: Write<u8>,
+{
+    /// Writes `cmd` and flushes it, blocking until it has gone out. Returns the kind of the
+    /// command, to hand to [`R502Receiver::poll`](struct.R502Receiver.html#method.poll).
+    ///
+    /// # Errors
+    ///
+    /// `Error::WriteError` if the command could not be written, and `Error::InvalidBuffer` if
+    /// it names a _character buffer_ the module does not have, in which case nothing is sent.
+    pub fn send_command(
+        &mut self,
+        cmd: &Command,
+    ) -> Result<CommandKind, Error<TX::Error, Infallible>> {
+        if let Some(buffer) = cmd.invalid_buffer() {
+            return Err(Error::InvalidBuffer(buffer));
+        }
+        codec::write_builtin_command(&mut self.cmd_buffer, self.address, cmd)?;
+        for byte in self.cmd_buffer.iter() {
+            nb::block!(self.tx.write(*byte)).map_err(Error::WriteError)?;
+        }
+        nb::block!(self.tx.flush()).map_err(Error::WriteError)?;
+        return Ok(cmd.kind());
+    }
+}
+
+impl<RX> R502Receiver<RX>
+where
+    RX: Read<u8>,
+{
+    /// Reads whatever has arrived without blocking, and returns the reply once it is complete,
+    /// decoded as the reply to a command of kind `kind`. Returns `WouldBlock` until then.
+    ///
+    /// # Errors
+    ///
+    /// `Error::RecvReadError` if the serial port failed, `Error::RecvBadChecksum` if a frame
+    /// was damaged, `Error::RecvBadStartCode` if one had a nonsensical length, and
+    /// `Error::RecvWrongReplyType` if a frame came in which is not a reply, and
+    /// `Error::RecvUnknownCode` or `Error::RecvPacketTooShort` if the reply does not fit `kind`.
+    /// The receiver carries on with the bytes after the bad frame at the next call.
+    pub fn poll(&mut self, kind: CommandKind) -> nb::Result<Reply, Error<Infallible, RX::Error>> {
+        loop {
+            let byte = self.rx.read().map_err(|e| e.map(Error::RecvReadError))?;
+            let frame = match self.parser.push(byte) {
+                None => continue,
+                Some(Ok(frame)) => frame,
+                Some(Err(FrameError::BadChecksum)) => {
+                    return Err(nb::Error::Other(Error::RecvBadChecksum))
+                }
+                Some(Err(FrameError::BadLength)) => {
+                    return Err(nb::Error::Other(Error::RecvBadStartCode))
+                }
+            };
+            let packet = codec::check_reply(frame.as_bytes()).map_err(Error::from)?;
+            return Ok(codec::reply_from_packet(kind, packet).map_err(Error::from)?);
+        }
+    }
+
+    /// Forgets whatever has arrived of a reply not yet complete, for example after a timeout.
+    pub fn reset(&mut self) {
+        self.parser.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::consts::PacketSize;
+    use crate::emulator::{Emulator, EmulatorRx, EmulatorTx};
+    use nb::block;
+
+    /// The main loop: sends a command and hands over its kind.
+    fn main_loop(sender: &mut R502Sender<EmulatorTx>, cmd: Command) -> CommandKind {
+        return sender.send_command(&cmd).unwrap();
+    }
+
+    /// The interrupt handler: called whenever bytes may have arrived.
+    fn on_rx_interrupt(
+        receiver: &mut R502Receiver<EmulatorRx>,
+        kind: CommandKind,
+    ) -> Option<Reply> {
+        return match receiver.poll(kind) {
+            Ok(reply) => Some(reply),
+            Err(nb::Error::WouldBlock) => None,
+            Err(nb::Error::Other(error)) => panic!("Unexpected error: {:?}", error),
+        };
+    }
+
+    #[test]
+    fn test_split_exchange() {
+        // given: an R502 with two templates enrolled, split into halves
+        let emulator = Emulator::new();
+        emulator.enroll(3, 1);
+        emulator.enroll(4, 2);
+        emulator.state().would_block = true;
+        let (tx, rx) = emulator.serial();
+        let (mut sender, mut receiver) = R502::new(tx, rx, 0xffffffff).split();
+
+        // when: the receiver is polled before anything is sent
+        let early = on_rx_interrupt(&mut receiver, CommandKind::TemplateNum);
+
+        // and: the main loop sends a command, and the interrupt handler picks up the reply
+        let kind = main_loop(&mut sender, Command::TemplateNum);
+        let reply = on_rx_interrupt(&mut receiver, kind);
+
+        // then: there was nothing to begin with, and then the whole reply
+        assert_eq!(early.is_none(), true);
+        match reply {
+            Some(Reply::TemplateNum(result)) => assert_eq!(result.template_num, 2),
+            other => panic!("Expected Reply::TemplateNum, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_join() {
+        // given: a split R502 which has been used through its halves
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.set_data_packet_size(PacketSize::Bytes64);
+        r502.set_module_family(ModuleFamily::R307);
+        let (mut sender, mut receiver) = r502.split();
+        let kind = sender.send_command(&Command::HandShake).unwrap();
+        block!(receiver.poll(kind)).unwrap();
+
+        // when: joining the halves again
+        let mut r502 = R502::join(sender, receiver);
+
+        // then: the driver works as before, with its settings
+        assert_eq!(r502.module_family(), ModuleFamily::R307);
+        assert_eq!(r502.data_packet_size, 64);
+        assert_eq!(
+            matches!(
+                r502.send_command(Command::TemplateNum),
+                Ok(Reply::TemplateNum(_))
+            ),
+            true
+        );
+        assert_eq!(emulator.instructions(), [0x40, 0x1d]);
+    }
+
+    #[test]
+    fn test_sender_invalid_buffer() {
+        // given: the sender of a split R502
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let (mut sender, _receiver) = R502::new(tx, rx, 0xffffffff).split();
+
+        // when: sending a command naming a buffer the module does not have
+        let result = sender.send_command(&Command::Img2Tz { buffer: 9 });
+
+        // then: it is refused without being sent
+        assert_eq!(matches!(result, Err(Error::InvalidBuffer(9))), true);
+        assert_eq!(emulator.instructions().is_empty(), true);
+    }
+}
@@ -0,0 +1,263 @@
+use crate::commands::CommandKind;
+#[cfg(feature = "stats")]
+use crate::driver::R502;
+#[cfg(feature = "stats")]
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// How many kinds of command there are, with every command group enabled.
+#[cfg(feature = "stats")]
+const COMMAND_KIND_COUNT: usize = 31;
+
+/// Counters of what the driver has done since it was created, or since
+/// [`R502::reset_stats`](struct.R502.html#method.reset_stats), for keeping an eye on units in
+/// the field. See [`R502::stats`](struct.R502.html#method.stats).
+///
+/// The counters wrap around rather than overflow. Without the `stats` feature, they and the
+/// code which updates them are left out.
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DriverStats {
+    commands: [u32; COMMAND_KIND_COUNT],
+
+    /// Packets which came in with a bad checksum.
+    pub checksum_errors: u32,
+
+    /// Times the driver threw input away to get back in step with the module: bytes which
+    /// could not start a packet while listening between commands, and stale bytes drained
+    /// before `detect_baud` and `scan_addresses` try an answer.
+    pub resyncs: u32,
+
+    /// Steps the helpers repeated after a failure, such as the second try of
+    /// `store_and_verify`.
+    pub retries: u32,
+
+    /// Commands given up on because the reply did not come in time, with
+    /// `send_command_with_timeout`, `detect_baud` or `scan_addresses`.
+    pub timeouts: u32,
+
+    /// Bytes written to the transport, data packets included.
+    pub bytes_sent: u64,
+
+    /// Bytes read from the transport, junk and stale bytes included.
+    pub bytes_received: u64,
+}
+
+/// Nothing is counted without the `stats` feature.
+#[cfg(not(feature = "stats"))]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct DriverStats;
+
+#[cfg_attr(not(feature = "stats"), allow(unused_variables))]
+impl DriverStats {
+    pub(crate) const fn new() -> Self {
+        #[cfg(feature = "stats")]
+        return Self {
+            commands: [0; COMMAND_KIND_COUNT],
+            checksum_errors: 0,
+            resyncs: 0,
+            retries: 0,
+            timeouts: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+        };
+        #[cfg(not(feature = "stats"))]
+        return Self;
+    }
+
+    /// How many commands of `kind` have been sent.
+    #[cfg(feature = "stats")]
+    pub fn commands(&self, kind: CommandKind) -> u32 {
+        return self.commands[kind as usize];
+    }
+
+    /// How many commands have been sent, of any kind. Commands defined outside this crate,
+    /// sent with `R502::send`, are not counted.
+    #[cfg(feature = "stats")]
+    pub fn commands_total(&self) -> u32 {
+        return self.commands.iter().fold(0, |total, count| total.wrapping_add(*count));
+    }
+
+    pub(crate) fn count_command(&mut self, kind: CommandKind) {
+        #[cfg(feature = "stats")]
+        {
+            let count = &mut self.commands[kind as usize];
+            *count = count.wrapping_add(1);
+        }
+    }
+
+    /// Counts the errors which have a counter of their own.
+    pub(crate) fn count_error<TXE, RXE>(&mut self, error: &Error<TXE, RXE>) {
+        #[cfg(feature = "stats")]
+        match error {
+            Error::RecvBadChecksum => self.checksum_errors = self.checksum_errors.wrapping_add(1),
+            Error::Timeout => self.timeouts = self.timeouts.wrapping_add(1),
+            _ => {}
+        }
+    }
+
+    pub(crate) fn count_resync(&mut self) {
+        #[cfg(feature = "stats")]
+        {
+            self.resyncs = self.resyncs.wrapping_add(1);
+        }
+    }
+
+    #[cfg(feature = "cmd-enroll")]
+    pub(crate) fn count_retry(&mut self) {
+        #[cfg(feature = "stats")]
+        {
+            self.retries = self.retries.wrapping_add(1);
+        }
+    }
+
+    pub(crate) fn count_sent(&mut self, bytes: usize) {
+        #[cfg(feature = "stats")]
+        {
+            self.bytes_sent = self.bytes_sent.wrapping_add(bytes as u64);
+        }
+    }
+
+    pub(crate) fn count_received(&mut self, bytes: usize) {
+        #[cfg(feature = "stats")]
+        {
+            self.bytes_received = self.bytes_received.wrapping_add(bytes as u64);
+        }
+    }
+}
+
+#[cfg(feature = "stats")]
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// What the driver has done since it was created, or since
+    /// [`reset_stats`](#method.reset_stats).
+    pub fn stats(&self) -> &DriverStats {
+        return &self.stats;
+    }
+
+    /// Sets every counter back to zero, for example after sending them off.
+    pub fn reset_stats(&mut self) {
+        self.stats = DriverStats::new();
+    }
+}
+
+#[cfg(all(test, feature = "stats"))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::commands::Command;
+    #[cfg(feature = "cmd-enroll")]
+    use crate::emulator::char_file;
+    use crate::emulator::{Emulator, EmulatorRx, EmulatorTx, NoDelay};
+    use crate::faults::Fault;
+
+    fn r502(emulator: &Emulator) -> R502<(EmulatorTx, EmulatorRx)> {
+        let (tx, rx) = emulator.serial();
+        return R502::new(tx, rx, 0xffffffff);
+    }
+
+    #[test]
+    fn test_stats_commands_and_bytes() {
+        // given: a fresh driver
+        let emulator = Emulator::new();
+        let mut r502 = r502(&emulator);
+
+        // when: sending two `TemplateNum` and a `GenImg`
+        r502.send_command(Command::TemplateNum).unwrap();
+        r502.send_command(Command::TemplateNum).unwrap();
+        r502.send_command(Command::GenImg).unwrap();
+
+        // then: each is counted, with the 12 bytes of every command and the 14- and 12-byte
+        // replies
+        let stats = r502.stats();
+        assert_eq!(stats.commands(CommandKind::TemplateNum), 2);
+        assert_eq!(stats.commands(CommandKind::GenImg), 1);
+        assert_eq!(stats.commands(CommandKind::HandShake), 0);
+        assert_eq!(stats.commands_total(), 3);
+        assert_eq!(stats.bytes_sent, 36);
+        assert_eq!(stats.bytes_received, 14 + 14 + 12);
+    }
+
+    #[test]
+    fn test_stats_count_sent_commands() {
+        // given: a fresh driver
+        let emulator = Emulator::new();
+        let mut r502 = r502(&emulator);
+
+        // when: sending a `TemplateNum` through `send`
+        r502.send(&Command::TemplateNum).unwrap();
+
+        // then: it is counted as with `send_command`
+        assert_eq!(r502.stats().commands(CommandKind::TemplateNum), 1);
+        assert_eq!(r502.stats().commands_total(), 1);
+    }
+
+    #[test]
+    fn test_stats_checksum_errors() {
+        // given: a serial port which corrupts a byte of the next reply
+        let emulator = Emulator::new();
+        let mut r502 = r502(&emulator).with_faults();
+        r502.transport_mut().inject(Fault::CorruptRead(10));
+
+        // when: sending a command
+        let result = r502.send_command(Command::TemplateNum);
+
+        // then: the bad checksum is counted
+        assert_eq!(matches!(result, Err(Error::RecvBadChecksum)), true);
+        assert_eq!(r502.stats().checksum_errors, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-enroll")]
+    fn test_stats_retries() {
+        // given: a module whose next store is corrupted, with a template in buffer 1
+        let emulator = Emulator::new();
+        emulator.state().buffers[0] = Some(char_file(7));
+        emulator.state().corrupt_stores = 1;
+        let mut r502 = r502(&emulator);
+
+        // when: storing it, with a retry
+        r502.store_and_verify(1, 3, true).unwrap();
+
+        // then: the retry is counted
+        assert_eq!(r502.stats().retries, 1);
+        assert_eq!(r502.stats().commands(CommandKind::Store), 2);
+    }
+
+    #[test]
+    fn test_stats_timeouts_and_resyncs() {
+        // given: a module which does not answer, with junk waiting on the line
+        let emulator = Emulator::new();
+        emulator.state().would_block = true;
+        emulator.state().silent = true;
+        emulator.send_raw(&[0x00, 0x13]);
+        let mut r502 = r502(&emulator);
+
+        // when: scanning for it
+        let found = r502.scan_addresses(core::iter::empty(), &mut NoDelay, 5).unwrap();
+
+        // then: the junk and both timeouts are counted
+        assert_eq!(found.is_empty(), true);
+        assert_eq!(r502.stats().resyncs, 1);
+        assert_eq!(r502.stats().timeouts, 2);
+        assert_eq!(r502.stats().commands(CommandKind::HandShake), 2);
+    }
+
+    #[test]
+    fn test_reset_stats() {
+        // given: a driver which has sent a command
+        let emulator = Emulator::new();
+        let mut r502 = r502(&emulator);
+        r502.send_command(Command::TemplateNum).unwrap();
+
+        // when: resetting the counters
+        r502.reset_stats();
+
+        // then: they are all back at zero
+        assert_eq!(r502.stats(), &DriverStats::default());
+    }
+}
@@ -0,0 +1,429 @@
+use std::vec::Vec;
+
+use crate::audit::AuditError;
+use crate::commands::Command;
+use crate::driver::R502;
+use crate::library::LibraryError;
+use crate::maintenance::{DeleteError, MAX_DELETE_RANGES};
+use crate::manifest::{LibraryManifest, ManifestSlot};
+use crate::responses::*;
+use crate::template::{ImportError, Template, TransferError};
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// What `sync_to_manifest` did with a slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncAction {
+    /// The slot already held the template in the manifest.
+    Unchanged,
+
+    /// The slot was empty, and the template was imported.
+    Imported,
+
+    /// The slot held a different template, which was overwritten.
+    Replaced,
+
+    /// The slot is not in the manifest, and its template was deleted.
+    Deleted,
+
+    /// No template was supplied for the slot, so it was left as it was.
+    MissingTemplate,
+
+    /// The supplied template does not match the digest in the manifest, so it was not used and
+    /// the slot was left as it was.
+    DigestMismatch,
+
+    /// The slot is past the end of the library.
+    OutOfRange,
+
+    /// The module refused to store the template. The slot may be empty now.
+    ImportFailed,
+
+    /// The module refused to delete the template.
+    DeleteFailed,
+}
+
+impl SyncAction {
+    /// True if the slot does not match the manifest after the sync.
+    pub fn is_failure(self) -> bool {
+        return !matches!(
+            self,
+            Self::Unchanged | Self::Imported | Self::Replaced | Self::Deleted
+        );
+    }
+}
+
+/// A slot touched by `sync_to_manifest`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotChange {
+    pub index: u16,
+    pub action: SyncAction,
+}
+
+/// Result of `sync_to_manifest`: one entry per slot in the manifest or on the module, lowest
+/// index first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    pub changes: Vec<SlotChange>,
+}
+
+impl SyncReport {
+    /// True if the library now matches the manifest.
+    pub fn is_complete(&self) -> bool {
+        return !self.changes.iter().any(|change| change.action.is_failure());
+    }
+
+    /// Number of slots which ended up with `action`.
+    pub fn count(&self, action: SyncAction) -> usize {
+        return self.changes.iter().filter(|change| change.action == action).count();
+    }
+}
+
+/// Error type for `sync_to_manifest`. Changes made before the error stay made.
+#[derive(Debug)]
+pub enum SyncError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// `ReadSysPara` failed with the given confirmation code.
+    ReadSysPara(u8),
+
+    /// The _index table_ could not be read.
+    Library(LibraryError<TXE, RXE>),
+
+    /// A template was imported, or stale slots were deleted, but the record of it could not be
+    /// added to the `AuditLog`. Slots after them may not have been synced.
+    Audit(AuditError<TXE, RXE>),
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for SyncError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Makes the library match `manifest`, for example to keep the modules at a site in step
+    /// with one another. `templates` supplies the template for a slot of the manifest, or
+    /// `None` if it is not at hand.
+    ///
+    /// Slots whose template already has the digest in the manifest are left alone. The others
+    /// are imported, but only once their template is supplied and has the right digest, so a
+    /// slot is never overwritten with something that does not belong there. Slots which are not
+    /// in the manifest are deleted last. A slot which cannot be synced is reported in the
+    /// `SyncReport`, and the rest are synced regardless; only communication problems stop the
+    /// sync early.
+    ///
+    /// **Note:** This overwrites the contents of both _character buffers_.
+    pub fn sync_to_manifest<F>(
+        &mut self,
+        manifest: &LibraryManifest,
+        mut templates: F,
+    ) -> Result<SyncReport, SyncError<T::WriteError, T::ReadError>>
+    where
+        F: FnMut(&ManifestSlot) -> Option<Template>,
+    {
+        let result = expect_reply!(self.send_command(Command::ReadSysPara), Reply::ReadSysPara)?;
+        if result.confirmation_code != 0x00 {
+            return Err(SyncError::ReadSysPara(result.confirmation_code));
+        }
+        let table = self
+            .read_index_table_pages(result.system_parameters.finger_library_size)
+            .map_err(SyncError::Library)?;
+
+        let mut report = SyncReport::default();
+        for slot in manifest.slots.iter() {
+            let index = slot.index;
+            let occupied = table.is_occupied(index);
+            let action = if index >= table.capacity() {
+                SyncAction::OutOfRange
+            } else if occupied && self.slot_digest(index)? == Some(slot.digest) {
+                SyncAction::Unchanged
+            } else {
+                match templates(slot) {
+                    None => SyncAction::MissingTemplate,
+                    Some(template) if template.digest() != slot.digest => {
+                        SyncAction::DigestMismatch
+                    }
+                    Some(template) => match self.import_template(index, &template, true) {
+                        Ok(()) if occupied => SyncAction::Replaced,
+                        Ok(()) => SyncAction::Imported,
+                        Err(ImportError::Comms(error))
+                        | Err(ImportError::Transfer(TransferError::Comms(error))) => {
+                            return Err(SyncError::Comms(error))
+                        }
+                        Err(ImportError::Audit(error)) => return Err(SyncError::Audit(error)),
+                        Err(_) => SyncAction::ImportFailed,
+                    },
+                }
+            };
+            report.changes.push(SlotChange { index, action });
+        }
+
+        let stale: Vec<u16> = table
+            .occupied()
+            .filter(|index| !manifest.slots.iter().any(|slot| slot.index == *index))
+            .collect();
+        // Each batch holds at most `MAX_DELETE_RANGES` runs, so that its `DeleteReport` has the
+        // outcome of every one of them.
+        let mut batch_start = 0;
+        while batch_start < stale.len() {
+            let mut runs = 1;
+            let mut batch_end = batch_start + 1;
+            while batch_end < stale.len() {
+                if stale[batch_end] != stale[batch_end - 1] + 1 {
+                    if runs == MAX_DELETE_RANGES {
+                        break;
+                    }
+                    runs += 1;
+                }
+                batch_end += 1;
+            }
+
+            let batch = &stale[batch_start..batch_end];
+            let deleted = match self.delete_indices(batch) {
+                Ok(deleted) => deleted,
+                Err(DeleteError::Comms(error)) => return Err(SyncError::Comms(error)),
+                Err(DeleteError::Library(error)) => return Err(SyncError::Library(error)),
+                Err(DeleteError::Audit { error, .. }) => return Err(SyncError::Audit(error)),
+            };
+            for index in batch.iter().copied() {
+                let succeeded = deleted.ranges.iter().any(|range| {
+                    range.status == DeletCharStatus::Success
+                        && (range.start..range.start + range.count).contains(&index)
+                });
+                let action =
+                    if succeeded { SyncAction::Deleted } else { SyncAction::DeleteFailed };
+                report.changes.push(SlotChange { index, action });
+            }
+            batch_start = batch_end;
+        }
+
+        report.changes.sort_by_key(|change| change.index);
+        return Ok(report);
+    }
+
+    /// The digest of the template at `index`, or `None` if it cannot be exported.
+    fn slot_digest(
+        &mut self,
+        index: u16,
+    ) -> Result<Option<u32>, Error<T::WriteError, T::ReadError>> {
+        use crate::template::ExportError;
+
+        return match self.export_template(index) {
+            Ok(template) => Ok(Some(template.digest())),
+            Err(ExportError::Comms(error))
+            | Err(ExportError::Transfer(TransferError::Comms(error))) => Err(error),
+            Err(_) => Ok(None),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{char_file, Emulator};
+    use crate::manifest::MANIFEST_SCHEMA_VERSION;
+    use std::string::String;
+    use std::vec;
+
+    fn template(finger: u8) -> Template {
+        return Template::from_bytes(&char_file(finger)).unwrap();
+    }
+
+    /// A manifest of `(index, finger)` pairs.
+    fn manifest(slots: &[(u16, u8)]) -> LibraryManifest {
+        return LibraryManifest {
+            schema_version: MANIFEST_SCHEMA_VERSION,
+            chip_serial: None,
+            firmware_version: None,
+            algorithm_version: None,
+            system_identifier_code: 9,
+            capacity: 200,
+            slots: slots
+                .iter()
+                .map(|(index, finger)| ManifestSlot {
+                    index: *index,
+                    label: None,
+                    size: 1536,
+                    digest: template(*finger).digest(),
+                })
+                .collect(),
+        };
+    }
+
+    /// Supplies the template of the finger each digest belongs to, for fingers 0 to 19.
+    fn blobs(slot: &ManifestSlot) -> Option<Template> {
+        return (0..20).map(template).find(|template| template.digest() == slot.digest);
+    }
+
+    fn actions(report: &SyncReport) -> Vec<(u16, SyncAction)> {
+        return report.changes.iter().map(|change| (change.index, change.action)).collect();
+    }
+
+    #[test]
+    fn test_sync_add_only() {
+        // given: a module with one of the three templates in the manifest
+        let emulator = Emulator::new();
+        emulator.enroll(1, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: syncing it
+        let report = r502.sync_to_manifest(&manifest(&[(1, 7), (2, 8), (3, 9)]), blobs).unwrap();
+
+        // then: the missing two were imported
+        assert_eq!(report.is_complete(), true);
+        assert_eq!(
+            actions(&report),
+            [(1, SyncAction::Unchanged), (2, SyncAction::Imported), (3, SyncAction::Imported)]
+        );
+        assert_eq!(emulator.slot(2).unwrap()[0], 8);
+        assert_eq!(emulator.slot(3).unwrap()[0], 9);
+    }
+
+    #[test]
+    fn test_sync_high_capacity() {
+        // given: a 1500-slot module with stray templates above slot 255
+        let emulator = Emulator::with_geometry(1500, 2);
+        emulator.enroll(300, 7);
+        emulator.enroll(1024, 8);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: syncing it to a manifest which uses the far end of the library
+        let target = manifest(&[(300, 7), (1499, 9), (1500, 10)]);
+        let report = r502.sync_to_manifest(&target, blobs).unwrap();
+
+        // then: every slot up to the real capacity was handled
+        assert_eq!(
+            actions(&report),
+            [
+                (300, SyncAction::Unchanged),
+                (1024, SyncAction::Deleted),
+                (1499, SyncAction::Imported),
+                (1500, SyncAction::OutOfRange),
+            ]
+        );
+        assert_eq!(emulator.slot(1499).unwrap()[0], 9);
+        assert_eq!(emulator.slot(1024), None);
+    }
+
+    #[test]
+    fn test_sync_delete_only() {
+        // given: a module with two templates the manifest does not know about
+        let emulator = Emulator::new();
+        emulator.enroll(1, 7);
+        emulator.enroll(5, 8);
+        emulator.enroll(6, 9);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: syncing it
+        let report = r502.sync_to_manifest(&manifest(&[(1, 7)]), blobs).unwrap();
+
+        // then: they were deleted
+        assert_eq!(
+            actions(&report),
+            [(1, SyncAction::Unchanged), (5, SyncAction::Deleted), (6, SyncAction::Deleted)]
+        );
+        assert_eq!(emulator.slot(1).is_some(), true);
+        assert_eq!(emulator.slot(5), None);
+        assert_eq!(emulator.slot(6), None);
+    }
+
+    #[test]
+    fn test_sync_delete_many_runs() {
+        // given: a module with templates in every other slot up to 78, none of them in the
+        // manifest, which is more runs than a `DeleteReport` holds
+        let emulator = Emulator::new();
+        for index in (0..80).step_by(2) {
+            emulator.enroll(index, 3);
+        }
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: syncing it to an empty manifest
+        let report = r502.sync_to_manifest(&manifest(&[]), blobs).unwrap();
+
+        // then: every slot is reported as deleted
+        assert_eq!(report.count(SyncAction::Deleted), 40);
+        assert_eq!(report.is_complete(), true);
+        assert_eq!((0..80).all(|index| emulator.slot(index).is_none()), true);
+    }
+
+    #[test]
+    fn test_sync_mixed() {
+        // given: a module with a stale slot, a swapped slot, and a slot the host has no copy of
+        let emulator = Emulator::new();
+        emulator.enroll(1, 7);
+        emulator.enroll(2, 3);
+        emulator.enroll(4, 5);
+        emulator.enroll(9, 6);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        let mut target = manifest(&[(1, 7), (2, 8), (3, 9), (4, 10), (250, 11)]);
+        target.slots.push(ManifestSlot {
+            index: 6,
+            label: Some(String::from("tampered")),
+            size: 1536,
+            digest: 0x12345678,
+        });
+
+        // when: syncing it, without a copy of finger 10
+        let report = r502
+            .sync_to_manifest(&target, |slot| {
+                if slot.index == 4 {
+                    return None;
+                }
+                if slot.index == 6 {
+                    return Some(template(12));
+                }
+                return blobs(slot);
+            })
+            .unwrap();
+
+        // then: every slot was dealt with, and the failures are reported
+        assert_eq!(report.is_complete(), false);
+        assert_eq!(
+            actions(&report),
+            vec![
+                (1, SyncAction::Unchanged),
+                (2, SyncAction::Replaced),
+                (3, SyncAction::Imported),
+                (4, SyncAction::MissingTemplate),
+                (6, SyncAction::DigestMismatch),
+                (9, SyncAction::Deleted),
+                (250, SyncAction::OutOfRange),
+            ]
+        );
+
+        // and: the slot without a copy still holds its old template
+        assert_eq!(emulator.slot(2).unwrap()[0], 8);
+        assert_eq!(emulator.slot(4).unwrap()[0], 5);
+        assert_eq!(emulator.slot(6), None);
+        assert_eq!(emulator.slot(9), None);
+    }
+
+    #[test]
+    fn test_sync_store_failure() {
+        // given: a module whose flash fails on the first write
+        let emulator = Emulator::new();
+        emulator.fail_next(0x06, 0x18);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: syncing two new slots
+        let report = r502.sync_to_manifest(&manifest(&[(1, 7), (2, 8)]), blobs).unwrap();
+
+        // then: the second slot was still imported
+        assert_eq!(
+            actions(&report),
+            [(1, SyncAction::ImportFailed), (2, SyncAction::Imported)]
+        );
+        assert_eq!(report.count(SyncAction::Imported), 1);
+    }
+}
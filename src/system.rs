@@ -0,0 +1,539 @@
+use embedded_hal::blocking::delay::DelayMs;
+
+use crate::commands::Command;
+use crate::driver::R502;
+use crate::responses::*;
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// Error type for `authenticate`.
+#[derive(Debug)]
+pub enum AuthError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// The R502 rejected the password.
+    WrongPassword,
+
+    /// The R502 could not process the password verification request.
+    PacketError,
+
+    /// The R502 accepted the password, but its status register does not say so.
+    NotAuthenticated(SystemParameters),
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for AuthError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+/// Error type for `change_password`. Each variant says which password is live afterwards.
+#[derive(Debug)]
+pub enum ChangePasswordError<TXE, RXE> {
+    /// The old password could not be verified. Nothing was changed; the old password is live.
+    OldPassword(AuthError<TXE, RXE>),
+
+    /// The R502 refused the new password. The old password is still live.
+    Rejected(SetPwdStatus),
+
+    /// `SetPwd` was sent, but its reply did not arrive. Either password may be live; try
+    /// verifying with the new one first.
+    Indeterminate(Error<TXE, RXE>),
+
+    /// The R502 accepted the new password, but it could not be verified afterwards. The new
+    /// password should be live, but this could not be confirmed.
+    Unconfirmed(AuthError<TXE, RXE>),
+}
+
+/// Outcome of one of the probes run by `health_check`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    /// The probe ran and the module reported no problems.
+    Passed,
+
+    /// The probe ran and the module reported a problem.
+    Failed,
+
+    /// The firmware does not support the probe, so it was skipped.
+    Unsupported,
+}
+
+/// Result of `health_check`.
+#[derive(Debug, Clone, Copy)]
+pub struct HealthReport {
+    /// Result of the `HandShake` probe.
+    pub handshake: Probe,
+
+    /// Result of the `CheckSensor` probe.
+    pub sensor: Probe,
+
+    /// The status register, as read by `ReadSysPara`. See `SystemParameters` for its bits.
+    pub status_register: u16,
+}
+
+impl HealthReport {
+    /// True if no probe failed and the module is not busy.
+    pub fn is_healthy(&self) -> bool {
+        return self.handshake != Probe::Failed
+            && self.sensor != Probe::Failed
+            && self.status_register & 1 == 0;
+    }
+}
+
+/// Error type for `health_check`.
+#[derive(Debug)]
+pub enum HealthError<TXE, RXE> {
+    /// Communication with the R502 failed, for example because it did not reply at all.
+    Comms(Error<TXE, RXE>),
+
+    /// The R502 replied, but at least one probe failed or it reported itself busy.
+    Unhealthy(HealthReport),
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for HealthError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+/// Error type for `wait_until_idle`.
+#[derive(Debug)]
+pub enum IdleError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// `ReadSysPara` failed with the given confirmation code.
+    ReadSysPara(u8),
+
+    /// The R502 was still busy when the timeout ran out.
+    Timeout,
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for IdleError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Verifies `password` with `VfyPwd`, then reads the system parameters with `ReadSysPara`
+    /// to confirm that the R502 considers the session authenticated.
+    ///
+    /// Returns the system parameters, which are handy for later decisions such as the library
+    /// capacity or the data packet size.
+    pub fn authenticate(
+        &mut self,
+        password: u32,
+    ) -> Result<SystemParameters, AuthError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(
+            self.send_command(Command::VfyPwd { password }),
+            Reply::VfyPwd
+        )?;
+        match result.confirmation_code {
+            PasswordVerificationState::Correct => {}
+            PasswordVerificationState::Incorrect => return Err(AuthError::WrongPassword),
+            PasswordVerificationState::Error => return Err(AuthError::PacketError),
+        }
+
+        let result = expect_reply!(self.send_command(Command::ReadSysPara), Reply::ReadSysPara)?;
+        let parameters = result.system_parameters;
+        if !parameters.password_ok() {
+            return Err(AuthError::NotAuthenticated(parameters));
+        }
+        return Ok(parameters);
+    }
+
+    /// Polls `ReadSysPara` every `poll_interval_ms` until the busy bit of the status register
+    /// is clear, for after operations which leave the R502 busy for a moment and make it
+    /// reject the next instruction. Gives up with `IdleError::Timeout` after roughly
+    /// `timeout_ms`. A `poll_interval_ms` of 0 polls back to back, but still counts each poll
+    /// as 1ms towards the timeout so that the wait always ends.
+    pub fn wait_until_idle<D>(
+        &mut self,
+        delay: &mut D,
+        poll_interval_ms: u16,
+        timeout_ms: u32,
+    ) -> Result<(), IdleError<T::WriteError, T::ReadError>>
+    where
+        D: DelayMs<u16>,
+    {
+        let mut waited = 0u32;
+        loop {
+            let result =
+                expect_reply!(self.send_command(Command::ReadSysPara), Reply::ReadSysPara)?;
+            if result.confirmation_code != 0x00 {
+                return Err(IdleError::ReadSysPara(result.confirmation_code));
+            }
+            if !result.system_parameters.busy() {
+                return Ok(());
+            }
+            if waited >= timeout_ms {
+                return Err(IdleError::Timeout);
+            }
+            delay.delay_ms(poll_interval_ms);
+            waited += poll_interval_ms.max(1) as u32;
+        }
+    }
+
+    /// Changes the module password from `old` to `new`: verifies `old`, sets `new` with
+    /// `SetPwd`, and then verifies `new` to confirm that it took.
+    ///
+    /// The driver does not keep the password itself, so callers that store it should only
+    /// switch over to `new` once this returns `Ok`, or as the error tells them to.
+    pub fn change_password(
+        &mut self,
+        old: u32,
+        new: u32,
+    ) -> Result<(), ChangePasswordError<T::WriteError, T::ReadError>> {
+        self.authenticate(old)
+            .map_err(ChangePasswordError::OldPassword)?;
+
+        let result = expect_reply!(
+            self.send_command(Command::SetPwd { password: new }),
+            Reply::SetPwd
+        )
+        .map_err(ChangePasswordError::Indeterminate)?;
+        match result.confirmation_code {
+            SetPwdStatus::Success => {}
+            status => return Err(ChangePasswordError::Rejected(status)),
+        }
+
+        self.authenticate(new)
+            .map_err(ChangePasswordError::Unconfirmed)?;
+        return Ok(());
+    }
+
+    /// Cheaply checks that the module is alive, for use from a watchdog or supervisor task.
+    ///
+    /// Runs `HandShake` and `CheckSensor`, then `ReadSysPara` to check that the module is not
+    /// busy. Firmware which does not know `HandShake` or `CheckSensor` answers them with a
+    /// `PacketError`; those probes are then reported as `Probe::Unsupported` rather than
+    /// failing the check.
+    pub fn health_check(
+        &mut self,
+    ) -> Result<HealthReport, HealthError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(self.send_command(Command::HandShake), Reply::HandShake)?;
+        let handshake = match result.confirmation_code {
+            HandShakeStatus::Success => Probe::Passed,
+            HandShakeStatus::PacketError => Probe::Unsupported,
+        };
+
+        let result = expect_reply!(self.send_command(Command::CheckSensor), Reply::CheckSensor)?;
+        let sensor = match result.confirmation_code {
+            CheckSensorStatus::Success => Probe::Passed,
+            CheckSensorStatus::PacketError => Probe::Unsupported,
+            CheckSensorStatus::SensorAbnormal => Probe::Failed,
+        };
+
+        let result = expect_reply!(self.send_command(Command::ReadSysPara), Reply::ReadSysPara)?;
+        let report = HealthReport {
+            handshake,
+            sensor,
+            status_register: result.system_parameters.status_register,
+        };
+
+        if !report.is_healthy() {
+            return Err(HealthError::Unhealthy(report));
+        }
+        return Ok(report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::emulator::{Emulator, EmulatorError, NoDelay};
+    use std::vec;
+
+    #[test]
+    fn test_authenticate() {
+        // given: a module with the default password
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: authenticating with the right password
+        let result = r502.authenticate(0x00000000);
+
+        // then: the system parameters are returned
+        let parameters = result.unwrap();
+        assert_eq!(parameters.password_ok(), true);
+        assert_eq!(parameters.finger_library_size, 200);
+    }
+
+    #[test]
+    fn test_authenticate_wrong_password() {
+        // given: a module with a non-default password
+        let emulator = Emulator::new();
+        emulator.state().password = 0x12345678;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: authenticating with the default password
+        let result = r502.authenticate(0x00000000);
+
+        // then: the password is reported as wrong
+        match result {
+            Err(AuthError::WrongPassword) => {}
+            other => panic!("Expected AuthError::WrongPassword, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn test_change_password() {
+        // given: a module with the default password
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: changing the password
+        let result = r502.change_password(0x00000000, 0x12345678);
+
+        // then: the new password is live and was verified
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(emulator.state().password, 0x12345678);
+        assert_eq!(emulator.instructions(), vec![0x13, 0x0f, 0x12, 0x13, 0x0f]);
+    }
+
+    #[test]
+    fn test_change_password_wrong_old_password() {
+        // given: a module with a password set
+        let emulator = Emulator::new();
+        emulator.state().password = 0xcafebabe;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: changing the password, giving the wrong old one
+        let result = r502.change_password(0x00000000, 0x12345678);
+
+        // then: nothing is changed
+        match result {
+            Err(ChangePasswordError::OldPassword(AuthError::WrongPassword)) => {}
+            other => panic!("Expected ChangePasswordError::OldPassword, got {:?}", other),
+        };
+        assert_eq!(emulator.state().password, 0xcafebabe);
+        assert_eq!(emulator.instructions().contains(&0x12), false);
+    }
+
+    #[test]
+    fn test_change_password_rejected() {
+        // given: a module which refuses the new password
+        let emulator = Emulator::new();
+        emulator.fail_next(0x12, 0x01);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: changing the password
+        let result = r502.change_password(0x00000000, 0x12345678);
+
+        // then: the refusal is reported and the old password is still live
+        match result {
+            Err(ChangePasswordError::Rejected(SetPwdStatus::PacketError)) => {}
+            other => panic!("Expected ChangePasswordError::Rejected, got {:?}", other),
+        };
+        assert_eq!(emulator.state().password, 0x00000000);
+    }
+
+    #[test]
+    fn test_change_password_reply_lost() {
+        // given: a module whose reply to SetPwd gets lost
+        let emulator = Emulator::new();
+        emulator.lose_next_reply(0x12);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: changing the password
+        let result = r502.change_password(0x00000000, 0x12345678);
+
+        // then: the outcome is reported as unknown
+        match result {
+            Err(ChangePasswordError::Indeterminate(Error::RecvReadError(_))) => {}
+            other => panic!("Expected ChangePasswordError::Indeterminate, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn test_change_password_unconfirmed() {
+        // given: a module which accepts the new password, but then fails to verify it
+        let emulator = Emulator::new();
+        emulator.fail_after(0x13, 1, 0x13);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: changing the password
+        let result = r502.change_password(0x00000000, 0x12345678);
+
+        // then: the new password is reported as set but unconfirmed
+        match result {
+            Err(ChangePasswordError::Unconfirmed(AuthError::WrongPassword)) => {}
+            other => panic!("Expected ChangePasswordError::Unconfirmed, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn test_health_check() {
+        // given: a healthy module
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: checking its health
+        let report = r502.health_check().unwrap();
+
+        // then: every probe passed
+        assert_eq!(report.handshake, Probe::Passed);
+        assert_eq!(report.sensor, Probe::Passed);
+        assert_eq!(report.is_healthy(), true);
+    }
+
+    #[test]
+    fn test_health_check_old_firmware() {
+        // given: a healthy module with firmware that predates HandShake and CheckSensor
+        let emulator = Emulator::new();
+        emulator.state().unsupported.extend_from_slice(&[0x40, 0x36]);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: checking its health
+        let report = r502.health_check().unwrap();
+
+        // then: the unsupported probes are skipped
+        assert_eq!(report.handshake, Probe::Unsupported);
+        assert_eq!(report.sensor, Probe::Unsupported);
+    }
+
+    #[test]
+    fn test_health_check_dead_sensor() {
+        // given: a module with a dead sensor
+        let emulator = Emulator::new();
+        emulator.state().sensor_ok = false;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: checking its health
+        let result = r502.health_check();
+
+        // then: the sensor probe failed
+        match result {
+            Err(HealthError::Unhealthy(report)) => {
+                assert_eq!(report.handshake, Probe::Passed);
+                assert_eq!(report.sensor, Probe::Failed);
+            }
+            other => panic!("Expected HealthError::Unhealthy, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn test_health_check_busy() {
+        // given: a module which reports itself busy
+        let emulator = Emulator::new();
+        emulator.state().busy = true;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: checking its health
+        let result = r502.health_check();
+
+        // then: the status register is reported
+        match result {
+            Err(HealthError::Unhealthy(report)) => assert_eq!(report.status_register & 1, 1),
+            other => panic!("Expected HealthError::Unhealthy, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn test_health_check_no_reply() {
+        // given: a module which does not reply
+        let emulator = Emulator::new();
+        emulator.state().silent = true;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: checking its health
+        let result = r502.health_check();
+
+        // then: the timeout is reported
+        match result {
+            Err(HealthError::Comms(Error::RecvReadError(EmulatorError::Timeout))) => {}
+            other => panic!("Expected HealthError::Comms, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn test_authenticate_transport_error() {
+        // given: a module which does not reply
+        let emulator = Emulator::new();
+        emulator.state().silent = true;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: authenticating
+        let result = r502.authenticate(0x00000000);
+
+        // then: the transport error is passed through
+        match result {
+            Err(AuthError::Comms(Error::RecvReadError(EmulatorError::Timeout))) => {}
+            other => panic!("Expected AuthError::Comms, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn test_wait_until_idle() {
+        // given: a module which reports itself busy twice
+        let emulator = Emulator::new();
+        emulator.state().busy_reads = 2;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: waiting for it
+        let result = r502.wait_until_idle(&mut NoDelay, 10, 100);
+
+        // then: it returns once the busy bit clears
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(emulator.instructions(), vec![0x0f, 0x0f, 0x0f]);
+    }
+
+    #[test]
+    fn test_wait_until_idle_timeout() {
+        // given: a module which stays busy
+        let emulator = Emulator::new();
+        emulator.state().busy = true;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: waiting for 30ms, polling every 10ms
+        let result = r502.wait_until_idle(&mut NoDelay, 10, 30);
+
+        // then: it gives up after the last poll
+        match result {
+            Err(IdleError::Timeout) => {}
+            other => panic!("Expected IdleError::Timeout, got {:?}", other),
+        };
+        assert_eq!(emulator.instructions(), vec![0x0f, 0x0f, 0x0f, 0x0f]);
+    }
+
+    #[test]
+    fn test_wait_until_idle_zero_interval() {
+        // given: a module which stays busy
+        let emulator = Emulator::new();
+        emulator.state().busy = true;
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: waiting for 3ms without a poll interval
+        let result = r502.wait_until_idle(&mut NoDelay, 0, 3);
+
+        // then: each poll counts as 1ms, so it still gives up
+        match result {
+            Err(IdleError::Timeout) => {}
+            other => panic!("Expected IdleError::Timeout, got {:?}", other),
+        };
+        assert_eq!(emulator.instructions(), vec![0x0f, 0x0f, 0x0f, 0x0f]);
+    }
+}
@@ -0,0 +1,987 @@
+use arrayvec::ArrayVec;
+use byteorder::{BigEndian, ByteOrder};
+use core::fmt;
+
+use crate::audit::AuditError;
+#[cfg(all(feature = "cmd-enroll", feature = "cmd-transfer"))]
+use crate::audit::AuditOperation;
+#[cfg(feature = "cmd-transfer")]
+use crate::commands::Command;
+#[cfg(feature = "cmd-transfer")]
+use crate::driver::R502;
+use crate::library::LibraryError;
+use crate::notepad::crc16;
+use crate::responses::*;
+#[cfg(feature = "cmd-transfer")]
+use crate::transport::Transport;
+use crate::utils::Error;
+
+/// The largest _character file_ or template the driver can hold. The R502 and R503 use
+/// 1536-byte templates, older modules use 512 bytes.
+pub const TEMPLATE_CAPACITY: usize = 2048;
+
+/// First bytes of a template in the format of `Template::to_wire`.
+pub const TEMPLATE_WIRE_MAGIC: [u8; 4] = *b"R5TP";
+
+/// Version of the format written by `Template::to_wire`.
+pub const TEMPLATE_WIRE_VERSION: u8 = 1;
+
+/// Bytes `Template::to_wire` adds around the template: magic, version, length and CRC.
+pub const TEMPLATE_WIRE_OVERHEAD: usize = 4 + 1 + 2 + 2;
+
+/// Longest text `Template::to_base64` writes, for a template of `TEMPLATE_CAPACITY` bytes.
+/// The R502's 1536-byte templates take 2060 bytes; `Template::base64_len` gives the exact
+/// length for a given template.
+pub const TEMPLATE_BASE64_CAPACITY: usize = base64_len(TEMPLATE_CAPACITY + TEMPLATE_WIRE_OVERHEAD);
+
+/// The base64 alphabet of RFC 4648, without the padding character.
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Why a template could not be written in, or read from, the format of `Template::to_wire`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemplateWireError {
+    /// The output buffer is too small; `needed` bytes are needed.
+    BufferTooSmall { needed: usize },
+
+    /// The input ends before the template does.
+    Truncated,
+
+    /// The input does not start with `TEMPLATE_WIRE_MAGIC`, so it is not a template.
+    BadMagic,
+
+    /// The input was written in a version of the format this driver does not know.
+    UnsupportedVersion(u8),
+
+    /// The template is longer than `TEMPLATE_CAPACITY`.
+    TooLarge,
+
+    /// The CRC does not match the contents, so the copy is damaged.
+    BadCrc,
+
+    /// The text is not strict base64, as `Template::to_base64` writes it, or holds more than
+    /// one template.
+    BadBase64,
+}
+
+/// A fingerprint _character file_ or template, as transferred with `UpChar` and `DownChar`.
+///
+/// The contents are opaque; the driver only moves them between the module and the host.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Template {
+    data: ArrayVec<u8, TEMPLATE_CAPACITY>,
+}
+
+impl Template {
+    /// Creates an empty template.
+    pub fn new() -> Self {
+        return Self {
+            data: ArrayVec::new(),
+        };
+    }
+
+    /// Creates a template from raw bytes, or returns `None` if there are more than
+    /// `TEMPLATE_CAPACITY` of them.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut template = Self::new();
+        return match template.data.try_extend_from_slice(bytes) {
+            Ok(()) => Some(template),
+            Err(_) => None,
+        };
+    }
+
+    /// Appends `data`, as received in a data packet. False if it does not fit, in which case
+    /// nothing is appended.
+    #[cfg(feature = "cmd-transfer")]
+    pub(crate) fn append(&mut self, data: &[u8]) -> bool {
+        return self.data.try_extend_from_slice(data).is_ok();
+    }
+
+    /// The raw template data.
+    pub fn as_bytes(&self) -> &[u8] {
+        return &self.data[..];
+    }
+
+    /// Length of the template in bytes.
+    pub fn len(&self) -> usize {
+        return self.data.len();
+    }
+
+    /// True if the template holds no data.
+    pub fn is_empty(&self) -> bool {
+        return self.data.is_empty();
+    }
+
+    /// A 32-bit FNV-1a hash of the template data, to tell templates apart or spot damaged
+    /// copies. Not a cryptographic hash.
+    ///
+    /// Manifests and `verify_slot` use this hash too, so digests taken by different tools can
+    /// be compared.
+    pub fn digest(&self) -> u32 {
+        let mut hash = 0x811c9dc5u32;
+        for byte in self.as_bytes() {
+            hash ^= *byte as u32;
+            hash = hash.wrapping_mul(0x01000193);
+        }
+        return hash;
+    }
+
+    /// Length of the template in the format of [`to_wire`](#method.to_wire).
+    pub fn wire_len(&self) -> usize {
+        return self.len() + TEMPLATE_WIRE_OVERHEAD;
+    }
+
+    /// Writes the template to `out` in a format for keeping it in flash or on a card, returning
+    /// the number of bytes written. Unlike the bare bytes, the format tells a template from
+    /// anything else, and catches copies which were cut short or damaged:
+    ///
+    /// ```text
+    /// magic   | "R5TP" [4]
+    /// version | TEMPLATE_WIRE_VERSION [1]
+    /// length  | length of the template, big-endian [2]
+    /// data    | the template [length]
+    /// crc     | CRC-16/CCITT-FALSE of everything before it, big-endian [2]
+    /// ```
+    pub fn to_wire(&self, out: &mut [u8]) -> Result<usize, TemplateWireError> {
+        let length = self.wire_len();
+        if out.len() < length {
+            return Err(TemplateWireError::BufferTooSmall { needed: length });
+        }
+        out[0..4].copy_from_slice(&TEMPLATE_WIRE_MAGIC);
+        out[4] = TEMPLATE_WIRE_VERSION;
+        BigEndian::write_u16(&mut out[5..7], self.len() as u16);
+        out[7..length - 2].copy_from_slice(self.as_bytes());
+        let crc = crc16(&out[..length - 2]);
+        BigEndian::write_u16(&mut out[length - 2..length], crc);
+        return Ok(length);
+    }
+
+    /// Reads a template written by [`to_wire`](#method.to_wire). Anything after it in `bytes`
+    /// is ignored; [`wire_len`](#method.wire_len) tells where it ended.
+    pub fn from_wire(bytes: &[u8]) -> Result<Self, TemplateWireError> {
+        if bytes.len() < 7 {
+            return Err(TemplateWireError::Truncated);
+        }
+        if bytes[0..4] != TEMPLATE_WIRE_MAGIC {
+            return Err(TemplateWireError::BadMagic);
+        }
+        if bytes[4] != TEMPLATE_WIRE_VERSION {
+            return Err(TemplateWireError::UnsupportedVersion(bytes[4]));
+        }
+        let data_length = BigEndian::read_u16(&bytes[5..7]) as usize;
+        if data_length > TEMPLATE_CAPACITY {
+            return Err(TemplateWireError::TooLarge);
+        }
+        let length = data_length + TEMPLATE_WIRE_OVERHEAD;
+        if bytes.len() < length {
+            return Err(TemplateWireError::Truncated);
+        }
+        if BigEndian::read_u16(&bytes[length - 2..length]) != crc16(&bytes[..length - 2]) {
+            return Err(TemplateWireError::BadCrc);
+        }
+        return Ok(Self::from_bytes(&bytes[7..length - 2]).unwrap());
+    }
+
+    /// Length of the template in the format of [`to_base64`](#method.to_base64).
+    pub fn base64_len(&self) -> usize {
+        return base64_len(self.wire_len());
+    }
+
+    /// Writes the template to `out` as text, for transports which only carry text, such as
+    /// MQTT topics or a serial console, returning the number of bytes written. The text is the
+    /// format of [`to_wire`](#method.to_wire) in base64 (RFC 4648, with `+`, `/` and `=`
+    /// padding, and no line breaks), so it is checked the same way when read back.
+    pub fn to_base64(&self, out: &mut [u8]) -> Result<usize, TemplateWireError> {
+        let length = self.base64_len();
+        if out.len() < length {
+            return Err(TemplateWireError::BufferTooSmall { needed: length });
+        }
+        let mut wire = [0u8; TEMPLATE_CAPACITY + TEMPLATE_WIRE_OVERHEAD];
+        let wire_length = self.to_wire(&mut wire)?;
+        for (chunk, text) in wire[..wire_length].chunks(3).zip(out.chunks_mut(4)) {
+            let mut group = [0u8; 3];
+            group[..chunk.len()].copy_from_slice(chunk);
+            let bits = (group[0] as u32) << 16 | (group[1] as u32) << 8 | group[2] as u32;
+            for (i, character) in text.iter_mut().enumerate() {
+                *character = if i <= chunk.len() {
+                    BASE64_ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize]
+                } else {
+                    b'='
+                };
+            }
+        }
+        return Ok(length);
+    }
+
+    /// Reads a template written by [`to_base64`](#method.to_base64). The text must be exactly
+    /// one template: surrounding whitespace, line breaks, missing padding, characters outside
+    /// the alphabet and stray bits in the last character are all refused as
+    /// `TemplateWireError::BadBase64`, before the wire format is checked.
+    pub fn from_base64(text: &[u8]) -> Result<Self, TemplateWireError> {
+        if text.len() > TEMPLATE_BASE64_CAPACITY {
+            return Err(TemplateWireError::TooLarge);
+        }
+        if !text.len().is_multiple_of(4) {
+            return Err(TemplateWireError::BadBase64);
+        }
+        let mut wire = [0u8; TEMPLATE_BASE64_CAPACITY / 4 * 3];
+        let mut length = 0;
+        for (n, group) in text.chunks(4).enumerate() {
+            let last = (n + 1) * 4 == text.len();
+            let padding = group.iter().rev().take_while(|c| **c == b'=').count();
+            if padding > 2 || (padding > 0 && !last) {
+                return Err(TemplateWireError::BadBase64);
+            }
+            let mut bits = 0u32;
+            for character in &group[..4 - padding] {
+                let value = BASE64_ALPHABET.iter().position(|c| c == character);
+                bits = bits << 6 | value.ok_or(TemplateWireError::BadBase64)? as u32;
+            }
+            bits <<= 6 * padding;
+            if bits & (0xffff >> (8 * (2 - padding))) != 0 {
+                return Err(TemplateWireError::BadBase64);
+            }
+            let bytes = [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8];
+            wire[length..length + 3 - padding].copy_from_slice(&bytes[..3 - padding]);
+            length += 3 - padding;
+        }
+
+        let template = Self::from_wire(&wire[..length])?;
+        if template.wire_len() != length {
+            return Err(TemplateWireError::BadBase64);
+        }
+        return Ok(template);
+    }
+}
+
+/// Length of `length` bytes in base64, with padding.
+const fn base64_len(length: usize) -> usize {
+    return length.div_ceil(3) * 4;
+}
+
+impl Default for Template {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl fmt::Debug for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return f.debug_struct("Template").field("len", &self.data.len()).finish();
+    }
+}
+
+/// Error type for template transfers between the host and the R502.
+#[derive(Debug)]
+pub enum TransferError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// The R502 refused to upload the _character buffer_.
+    UploadRejected(UpCharStatus),
+
+    /// The R502 refused to accept a download into the _character buffer_.
+    DownloadRejected(DownCharStatus),
+
+    /// The uploaded data did not fit into a `Template`.
+    TooLarge,
+
+    /// The uploaded data did not fit into the slice given for it; `needed` bytes are needed.
+    BufferTooSmall { needed: usize },
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for TransferError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+/// Error type for `export_template`.
+#[derive(Debug)]
+pub enum ExportError<TXE, RXE> {
+    /// Communication with the R502 failed while loading the template.
+    Comms(Error<TXE, RXE>),
+
+    /// There is no template stored at the given index.
+    SlotEmpty,
+
+    /// The index is past the end of the library.
+    IndexOutOfRange,
+
+    /// The template could not be loaded for some other reason.
+    LoadFailed(LoadCharStatus),
+
+    /// The template was loaded, but uploading it to the host failed.
+    Transfer(TransferError<TXE, RXE>),
+
+    /// The template was exported, but could not be written in the format of
+    /// `Template::to_wire`.
+    Wire(TemplateWireError),
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for ExportError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+/// Error type for `import_template`.
+#[derive(Debug)]
+pub enum ImportError<TXE, RXE> {
+    /// Communication with the R502 failed.
+    Comms(Error<TXE, RXE>),
+
+    /// The _index table_ could not be read to check whether the slot is free.
+    Library(LibraryError<TXE, RXE>),
+
+    /// The slot already holds a template, and overwriting was not allowed.
+    Occupied,
+
+    /// Transferring the template into the _character buffer_ failed.
+    Transfer(TransferError<TXE, RXE>),
+
+    /// The template was transferred, but storing it in the library failed.
+    Store(StoreStatus),
+
+    /// The template could not be read from the format of `Template::to_wire`, so nothing was
+    /// sent to the module.
+    Wire(TemplateWireError),
+
+    /// The template was stored, but the record of it could not be added to the `AuditLog`.
+    Audit(AuditError<TXE, RXE>),
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for ImportError<TXE, RXE> {
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return Self::Comms(error);
+    }
+}
+
+#[cfg(feature = "cmd-transfer")]
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Uploads the contents of _character buffer_ `buffer` to the host using `UpChar`.
+    pub fn upload_template(
+        &mut self,
+        buffer: u8,
+    ) -> Result<Template, TransferError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(self.send_command(Command::UpChar { buffer }), Reply::UpChar)?;
+        match result.confirmation_code {
+            UpCharStatus::Success => {}
+            status => return Err(TransferError::UploadRejected(status)),
+        }
+
+        let mut template = Template::new();
+        let mut overflow = false;
+        self.receive_data(|data| {
+            if !template.append(data) {
+                overflow = true;
+            }
+        })?;
+
+        if overflow {
+            return Err(TransferError::TooLarge);
+        }
+        return Ok(template);
+    }
+
+    /// Uploads the contents of _character buffer_ `buffer` into `out`, rather than into a
+    /// `Template`, returning the number of bytes written. This lets a scratch buffer shared
+    /// with other code hold the transfer. Bytes of `out` past the template are left alone.
+    ///
+    /// If the template does not fit, the rest of it is still read from the module, so the
+    /// next command finds the line quiet, and `TransferError::BufferTooSmall` says how long
+    /// it was.
+    pub fn up_char_into(
+        &mut self,
+        buffer: u8,
+        out: &mut [u8],
+    ) -> Result<usize, TransferError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(self.send_command(Command::UpChar { buffer }), Reply::UpChar)?;
+        match result.confirmation_code {
+            UpCharStatus::Success => {}
+            status => return Err(TransferError::UploadRejected(status)),
+        }
+
+        let mut length = 0;
+        self.receive_data(|data| {
+            if let Some(space) = out.get_mut(length..length + data.len()) {
+                space.copy_from_slice(data);
+            }
+            length += data.len();
+        })?;
+
+        if length > out.len() {
+            return Err(TransferError::BufferTooSmall { needed: length });
+        }
+        return Ok(length);
+    }
+
+    /// Reads the template stored at `index` in the library, for example to keep a copy of it
+    /// on the host. The template is loaded into _character buffer_ 2 with `LoadChar` and then
+    /// uploaded with `UpChar`.
+    ///
+    /// **Note:** This overwrites the contents of _character buffer_ 2.
+    pub fn export_template(
+        &mut self,
+        index: u16,
+    ) -> Result<Template, ExportError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(
+            self.send_command(Command::LoadChar { buffer: 2, index }),
+            Reply::LoadChar
+        )?;
+        match result.confirmation_code {
+            LoadCharStatus::Success => {}
+            LoadCharStatus::LibraryReadError => return Err(ExportError::SlotEmpty),
+            #[cfg(feature = "r503")]
+            LoadCharStatus::TemplateEmpty => return Err(ExportError::SlotEmpty),
+            LoadCharStatus::IndexOutOfRange => return Err(ExportError::IndexOutOfRange),
+            status => return Err(ExportError::LoadFailed(status)),
+        }
+
+        return self.upload_template(2).map_err(ExportError::Transfer);
+    }
+
+    /// Like [`export_template`](#method.export_template), but writes the template to `out` in
+    /// the format of `Template::to_wire`, returning the number of bytes written.
+    ///
+    /// **Note:** This overwrites the contents of _character buffer_ 2.
+    pub fn export_template_wire(
+        &mut self,
+        index: u16,
+        out: &mut [u8],
+    ) -> Result<usize, ExportError<T::WriteError, T::ReadError>> {
+        return self.export_template(index)?.to_wire(out).map_err(ExportError::Wire);
+    }
+
+    /// Exports the template at `index` and checks that its `Template::digest` is `expected`,
+    /// to spot templates which were damaged or swapped since the digest was taken.
+    ///
+    /// **Note:** This overwrites the contents of _character buffer_ 2.
+    pub fn verify_slot(
+        &mut self,
+        index: u16,
+        expected: u32,
+    ) -> Result<bool, ExportError<T::WriteError, T::ReadError>> {
+        return Ok(self.export_template(index)?.digest() == expected);
+    }
+
+    /// Writes `template` into the library at `index`, for example to restore a copy taken with
+    /// [`export_template`](#method.export_template). The template is downloaded into
+    /// _character buffer_ 1 with `DownChar` and then stored with `Store`.
+    ///
+    /// Unless `overwrite` is set, the _index table_ is checked first and an occupied slot is
+    /// left alone. If an `AuditLog` is being kept, the template stored is recorded in it.
+    ///
+    /// **Note:** This overwrites the contents of _character buffer_ 1.
+    #[cfg(feature = "cmd-enroll")]
+    pub fn import_template(
+        &mut self,
+        index: u16,
+        template: &Template,
+        overwrite: bool,
+    ) -> Result<(), ImportError<T::WriteError, T::ReadError>> {
+        if !overwrite && self.is_slot_occupied(index).map_err(ImportError::Library)? {
+            return Err(ImportError::Occupied);
+        }
+
+        self.download_template(1, template)
+            .map_err(ImportError::Transfer)?;
+
+        let result = expect_reply!(
+            self.send_command(Command::Store { buffer: 1, index }),
+            Reply::Store
+        )?;
+        match result.confirmation_code {
+            StoreStatus::Success => {}
+            status => return Err(ImportError::Store(status)),
+        }
+        return self.record_audit(AuditOperation::Stored, index, 1).map_err(ImportError::Audit);
+    }
+
+    /// Like [`import_template`](#method.import_template), but reads the template from `bytes`
+    /// in the format of `Template::to_wire`, as written by
+    /// [`export_template_wire`](#method.export_template_wire). A damaged copy is refused
+    /// before anything is sent to the module.
+    ///
+    /// **Note:** This overwrites the contents of _character buffer_ 1.
+    #[cfg(feature = "cmd-enroll")]
+    pub fn import_template_wire(
+        &mut self,
+        index: u16,
+        bytes: &[u8],
+        overwrite: bool,
+    ) -> Result<(), ImportError<T::WriteError, T::ReadError>> {
+        let template = Template::from_wire(bytes).map_err(ImportError::Wire)?;
+        return self.import_template(index, &template, overwrite);
+    }
+
+    /// Downloads `template` into _character buffer_ `buffer` using `DownChar`.
+    pub fn download_template(
+        &mut self,
+        buffer: u8,
+        template: &Template,
+    ) -> Result<(), TransferError<T::WriteError, T::ReadError>> {
+        return self.down_char_from(buffer, template.as_bytes());
+    }
+
+    /// Downloads `data` into _character buffer_ `buffer` using `DownChar`, straight from the
+    /// caller's slice, as uploaded with [`up_char_into`](#method.up_char_into).
+    pub fn down_char_from(
+        &mut self,
+        buffer: u8,
+        data: &[u8],
+    ) -> Result<(), TransferError<T::WriteError, T::ReadError>> {
+        let result = expect_reply!(
+            self.send_command(Command::DownChar { buffer }),
+            Reply::DownChar
+        )?;
+        match result.confirmation_code {
+            DownCharStatus::Success => {}
+            status => return Err(TransferError::DownloadRejected(status)),
+        }
+
+        self.send_data(data)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::char_file;
+    #[cfg(feature = "cmd-transfer")]
+    use crate::emulator::Emulator;
+
+    #[test]
+    fn test_digest_known_answers() {
+        // given: inputs with published FNV-1a 32-bit hashes
+        let digest = |bytes: &[u8]| Template::from_bytes(bytes).unwrap().digest();
+
+        // then: the digests match them
+        assert_eq!(digest(b""), 0x811c9dc5);
+        assert_eq!(digest(b"a"), 0xe40c292c);
+        assert_eq!(digest(b"foobar"), 0xbf9cf968);
+    }
+
+    #[test]
+    fn test_digest_tells_templates_apart() {
+        // given: two templates, and a copy of the first with one bit flipped
+        let first = Template::from_bytes(&char_file(7)).unwrap();
+        let second = Template::from_bytes(&char_file(8)).unwrap();
+        let mut damaged = char_file(7);
+        damaged[700] ^= 0x01;
+        let damaged = Template::from_bytes(&damaged).unwrap();
+
+        // then: each has its own digest
+        assert_eq!(first.digest(), first.clone().digest());
+        assert_ne!(first.digest(), second.digest());
+        assert_ne!(first.digest(), damaged.digest());
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_verify_slot() {
+        // given: a module with finger 7 enrolled at index 3, and its digest
+        let emulator = Emulator::new();
+        emulator.enroll(3, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        let expected = Template::from_bytes(&char_file(7)).unwrap().digest();
+
+        // then: the slot verifies
+        assert_eq!(r502.verify_slot(3, expected).unwrap(), true);
+
+        // when: the template in the slot is swapped for another
+        emulator.enroll(3, 8);
+
+        // then: it no longer verifies
+        assert_eq!(r502.verify_slot(3, expected).unwrap(), false);
+
+        // and: an empty slot is an error rather than a mismatch
+        match r502.verify_slot(4, expected) {
+            Err(ExportError::SlotEmpty) => {}
+            other => panic!("Expected ExportError::SlotEmpty, got {:?}", other),
+        };
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_upload_template() {
+        // given: a module with a character file in buffer 1
+        let emulator = Emulator::new();
+        emulator.state().buffers[0] = Some(char_file(7));
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: uploading buffer 1
+        let result = r502.upload_template(1);
+
+        // then: the whole character file arrives
+        let template = result.unwrap();
+        assert_eq!(template.as_bytes(), &char_file(7)[..]);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_up_char_into() {
+        // given: a module with a character file in buffer 1
+        let emulator = Emulator::new();
+        emulator.state().buffers[0] = Some(char_file(7));
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: uploading it into a slice of exactly its size
+        let mut exact = [0u8; 1536];
+        let length = r502.up_char_into(1, &mut exact).unwrap();
+
+        // then: the whole character file arrives
+        assert_eq!(length, 1536);
+        assert_eq!(&exact[..], &char_file(7)[..]);
+
+        // when: uploading it into a larger slice
+        let mut larger = [0x55u8; 2000];
+        let length = r502.up_char_into(1, &mut larger).unwrap();
+
+        // then: it fills the start, and the rest is left alone
+        assert_eq!(length, 1536);
+        assert_eq!(&larger[..1536], &char_file(7)[..]);
+        assert_eq!(larger[1536..].iter().all(|byte| *byte == 0x55), true);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_up_char_into_too_small() {
+        // given: a module with a character file in buffer 1
+        let emulator = Emulator::new();
+        emulator.state().buffers[0] = Some(char_file(7));
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: uploading it into a slice one byte short
+        let mut short = [0u8; 1535];
+        let result = r502.up_char_into(1, &mut short);
+
+        // then: the size needed is reported
+        match result {
+            Err(TransferError::BufferTooSmall { needed: 1536 }) => {}
+            other => panic!("Expected TransferError::BufferTooSmall, got {:?}", other),
+        };
+
+        // and: the next command goes through
+        let result = r502.send_command(Command::TemplateNum);
+        assert_eq!(matches!(result, Ok(Reply::TemplateNum(_))), true);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_down_char_from() {
+        // given: a character file in a caller's scratch buffer, with room to spare
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        let mut scratch = [0u8; 2000];
+        scratch[..1536].copy_from_slice(&char_file(8));
+
+        // when: downloading it into buffer 2
+        r502.down_char_from(2, &scratch[..1536]).unwrap();
+
+        // then: the module holds it
+        assert_eq!(emulator.state().buffers[1].as_deref(), Some(&char_file(8)[..]));
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_export_template() {
+        // given: a module with finger 7 enrolled at index 3 and nothing at index 4
+        let emulator = Emulator::new();
+        emulator.enroll(3, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: exporting index 3
+        let result = r502.export_template(3);
+
+        // then: the stored template is returned
+        assert_eq!(result.unwrap().as_bytes(), &char_file(7)[..]);
+
+        // when: exporting index 4
+        let result = r502.export_template(4);
+
+        // then: the slot is reported as empty
+        match result {
+            Err(ExportError::SlotEmpty) => {}
+            other => panic!("Expected ExportError::SlotEmpty, got {:?}", other),
+        };
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_export_template_upload_failure() {
+        // given: a module with finger 7 enrolled at index 3
+        let emulator = Emulator::new();
+        emulator.enroll(3, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // and: which fails to upload the character buffer
+        emulator.fail_next(0x08, 0x0d);
+
+        // when: exporting index 3
+        let result = r502.export_template(3);
+
+        // then: the transfer failure is reported
+        match result {
+            Err(ExportError::Transfer(TransferError::UploadRejected(UpCharStatus::UploadFailed))) => {}
+            other => panic!("Expected ExportError::Transfer, got {:?}", other),
+        };
+    }
+
+    #[test]
+    fn test_wire_round_trip() {
+        // given: a template
+        let template = Template::from_bytes(&char_file(7)).unwrap();
+
+        // when: writing it in the wire format and reading it back
+        let mut buffer = [0u8; 1600];
+        let length = template.to_wire(&mut buffer).unwrap();
+        let parsed = Template::from_wire(&buffer).unwrap();
+
+        // then: it is framed as documented, and comes back the same
+        assert_eq!(length, 1536 + TEMPLATE_WIRE_OVERHEAD);
+        assert_eq!(length, template.wire_len());
+        assert_eq!(&buffer[..7], &[b'R', b'5', b'T', b'P', 1, 0x06, 0x00]);
+        assert_eq!(parsed, template);
+
+        // and: so does an empty template
+        let length = Template::new().to_wire(&mut buffer).unwrap();
+        assert_eq!(Template::from_wire(&buffer[..length]), Ok(Template::new()));
+    }
+
+    #[test]
+    fn test_wire_errors() {
+        // given: a template in the wire format
+        let template = Template::from_bytes(&[0x42; 40]).unwrap();
+        let mut buffer = [0u8; 49];
+        template.to_wire(&mut buffer).unwrap();
+
+        // then: a damaged copy fails its CRC check
+        let mut damaged = buffer;
+        damaged[20] ^= 0x10;
+        assert_eq!(Template::from_wire(&damaged), Err(TemplateWireError::BadCrc));
+
+        // and: an unknown version is refused
+        let mut newer = buffer;
+        newer[4] = 2;
+        assert_eq!(Template::from_wire(&newer), Err(TemplateWireError::UnsupportedVersion(2)));
+
+        // and: a copy which was cut short is spotted, wherever it was cut
+        assert_eq!(Template::from_wire(&buffer[..48]), Err(TemplateWireError::Truncated));
+        assert_eq!(Template::from_wire(&buffer[..5]), Err(TemplateWireError::Truncated));
+
+        // and: something which is not a template at all is refused
+        assert_eq!(Template::from_wire(&[0u8; 49]), Err(TemplateWireError::BadMagic));
+
+        // and: writing needs room for the whole frame
+        assert_eq!(
+            template.to_wire(&mut [0u8; 48]),
+            Err(TemplateWireError::BufferTooSmall { needed: 49 })
+        );
+    }
+
+    #[test]
+    fn test_base64_known_answers() {
+        // given: templates whose wire format ends at each point of a base64 group
+        let cases: [(&[u8], &[u8]); 4] = [
+            (&[], b"UjVUUAEAAMkI"),
+            (&[0x01, 0x02, 0x03], b"UjVUUAEAAwECA8si"),
+            (&[0x01, 0x02, 0x03, 0x04], b"UjVUUAEABAECAwRtdw=="),
+            (&[0xfb, 0xff], b"UjVUUAEAAvv/km8="),
+        ];
+
+        for (bytes, expected) in cases.iter() {
+            // when: writing each as base64
+            let template = Template::from_bytes(bytes).unwrap();
+            let mut buffer = [0u8; 32];
+            let length = template.to_base64(&mut buffer).unwrap();
+
+            // then: the text is what a reference encoder makes of the wire format
+            assert_eq!(&buffer[..length], *expected);
+            assert_eq!(length, template.base64_len());
+            assert_eq!(Template::from_base64(expected), Ok(template));
+        }
+    }
+
+    #[test]
+    fn test_base64_round_trip() {
+        // given: a full-size template, and the largest the driver can hold
+        let template = Template::from_bytes(&char_file(7)).unwrap();
+        let largest = Template::from_bytes(&[0xa5; TEMPLATE_CAPACITY]).unwrap();
+
+        // when: writing them as base64 and reading them back
+        let mut buffer = [0u8; TEMPLATE_BASE64_CAPACITY];
+        let length = template.to_base64(&mut buffer).unwrap();
+        let parsed = Template::from_base64(&buffer[..length]).unwrap();
+
+        // then: they come back the same, in the documented lengths
+        assert_eq!(length, 2060);
+        assert_eq!(parsed, template);
+        assert_eq!(buffer[..length].iter().all(|c| c.is_ascii_graphic()), true);
+        let length = largest.to_base64(&mut buffer).unwrap();
+        assert_eq!(length, TEMPLATE_BASE64_CAPACITY);
+        assert_eq!(Template::from_base64(&buffer[..length]), Ok(largest));
+    }
+
+    #[test]
+    fn test_base64_errors() {
+        // given: a template as base64
+        let template = Template::from_bytes(&[0x01, 0x02, 0x03, 0x04]).unwrap();
+        let text = b"UjVUUAEABAECAwRtdw==";
+
+        // then: text which is not strict base64 is refused
+        let bad: [&[u8]; 7] = [
+            b"UjVUUAEABAECAwRtdw",
+            b"UjVUUAEABAECAwRtdw==\n",
+            b"UjVUUAEABAECAwRtdx==",
+            b"UjVUUAEABAECAwRt=w==",
+            b"UjVUUAEABAECAwRtd===",
+            b"UjVUUAEA BAECAwRtdw==",
+            b"UjVU-AEABAECAwRtdw==",
+        ];
+        for text in bad.iter() {
+            assert_eq!(Template::from_base64(text), Err(TemplateWireError::BadBase64));
+        }
+
+        // and: so is text with anything after the template
+        assert_eq!(
+            Template::from_base64(b"UjVUUAEAAwECA8siAAAA"),
+            Err(TemplateWireError::BadBase64)
+        );
+
+        // and: damage inside the text is caught by the wire format
+        let mut damaged = *text;
+        damaged[10] = b'F';
+        assert_eq!(Template::from_base64(&damaged), Err(TemplateWireError::BadCrc));
+        assert_eq!(Template::from_base64(b""), Err(TemplateWireError::Truncated));
+        assert_eq!(
+            Template::from_base64(&[b'A'; TEMPLATE_BASE64_CAPACITY + 4]),
+            Err(TemplateWireError::TooLarge)
+        );
+
+        // and: writing needs room for the whole text
+        assert_eq!(
+            template.to_base64(&mut [0u8; 19]),
+            Err(TemplateWireError::BufferTooSmall { needed: 20 })
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "cmd-transfer", feature = "cmd-enroll"))]
+    fn test_export_import_wire() {
+        // given: a module with finger 7 at index 3
+        let emulator = Emulator::new();
+        emulator.enroll(3, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: backing up the template in the wire format and restoring it to index 8
+        let mut backup = [0u8; 2048];
+        let length = r502.export_template_wire(3, &mut backup).unwrap();
+        r502.import_template_wire(8, &backup[..length], false).unwrap();
+
+        // then: the copy is the same as the original
+        assert_eq!(emulator.slot(8), Some(char_file(7)));
+
+        // and: a damaged backup is refused without talking to the module
+        backup[100] ^= 0x01;
+        let sent = emulator.instructions().len();
+        let result = r502.import_template_wire(9, &backup[..length], false);
+        assert_eq!(matches!(result, Err(ImportError::Wire(TemplateWireError::BadCrc))), true);
+        assert_eq!(emulator.instructions().len(), sent);
+    }
+
+    #[test]
+    #[cfg(all(feature = "cmd-transfer", feature = "cmd-enroll"))]
+    fn test_import_template() {
+        // given: an empty module
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: importing a template into index 6
+        let template = Template::from_bytes(&char_file(9)).unwrap();
+        let result = r502.import_template(6, &template, false);
+
+        // then: the template ends up in the library
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(emulator.slot(6), Some(char_file(9)));
+    }
+
+    #[test]
+    #[cfg(all(feature = "cmd-transfer", feature = "cmd-enroll"))]
+    fn test_import_template_refuses_occupied_slot() {
+        // given: a module with finger 7 enrolled at index 6
+        let emulator = Emulator::new();
+        emulator.enroll(6, 7);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: importing another template into index 6 without overwriting
+        let template = Template::from_bytes(&char_file(9)).unwrap();
+        let result = r502.import_template(6, &template, false);
+
+        // then: the import is refused and the slot is untouched
+        match result {
+            Err(ImportError::Occupied) => {}
+            other => panic!("Expected ImportError::Occupied, got {:?}", other),
+        };
+        assert_eq!(emulator.slot(6), Some(char_file(7)));
+
+        // when: importing again, allowing overwrites
+        let result = r502.import_template(6, &template, true);
+
+        // then: the slot is replaced
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(emulator.slot(6), Some(char_file(9)));
+    }
+
+    #[test]
+    #[cfg(all(feature = "cmd-transfer", feature = "cmd-enroll"))]
+    fn test_import_template_flash_error() {
+        // given: a module which fails to write to flash
+        let emulator = Emulator::new();
+        emulator.fail_next(0x06, 0x18);
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: importing a template
+        let template = Template::from_bytes(&char_file(9)).unwrap();
+        let result = r502.import_template(6, &template, false);
+
+        // then: the failure is reported as a store failure, not a transfer failure
+        match result {
+            Err(ImportError::Store(StoreStatus::WriteError)) => {}
+            other => panic!("Expected ImportError::Store, got {:?}", other),
+        };
+        assert_eq!(emulator.slot(6), None);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_download_template() {
+        // given: an empty module
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+
+        // when: downloading a template into buffer 2
+        let template = Template::from_bytes(&char_file(9)).unwrap();
+        let result = r502.download_template(2, &template);
+
+        // then: the buffer holds the template
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(emulator.state().buffers[1], Some(char_file(9)));
+    }
+}
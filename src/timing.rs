@@ -0,0 +1,332 @@
+use crate::clock::Clock;
+use crate::consts::FRAME_HEADER_LENGTH;
+use crate::driver::R502;
+use crate::transport::Transport;
+
+/// How long the latest command took, as measured by `Timed`. All times are in the unit of its
+/// `Clock`, milliseconds for the usual tick counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CommandTiming {
+    /// From the first byte of the command being written until the transmitter was flushed.
+    /// A slow write points at the UART rather than the module.
+    pub write_ms: u32,
+
+    /// From the flush until the first byte of the reply arrived, or `None` if nothing has
+    /// arrived yet. This is the time the module took to act on the command.
+    pub wait_ms: Option<u32>,
+
+    /// From the first byte of the command being written until the last frame of the reply had
+    /// been read, or `None` if the reply is not complete yet. For `UpChar`, the last frame is
+    /// the final data packet.
+    pub total_ms: Option<u32>,
+}
+
+/// A `Transport` which times each command passing through it on a `Clock`, for finding out
+/// whether it is the module or the serial port which is slow. See
+/// [`R502::with_timing`](struct.R502.html#method.with_timing).
+///
+/// As with `CommandGap`, a command is everything written between two flushes, so the data
+/// packets sent by `download_template` are timed as a command of their own, one which gets no
+/// reply. The clock is read a handful of times per command, not per byte. Without this
+/// wrapper, nothing is timed and nothing is spent on it.
+#[derive(Debug)]
+pub struct Timed<T, C> {
+    transport: T,
+    clock: C,
+    start: u32,
+    writing: bool,
+    flushed_at: Option<u32>,
+    timing: Option<CommandTiming>,
+    rx_seen: usize,
+    rx_length: usize,
+}
+
+impl<T, C> Timed<T, C>
+where
+    T: Transport,
+    C: Clock,
+{
+    /// Wraps `transport`, timing commands on `clock`.
+    pub fn new(transport: T, clock: C) -> Self {
+        return Self {
+            transport,
+            clock,
+            start: 0,
+            writing: false,
+            flushed_at: None,
+            timing: None,
+            rx_seen: 0,
+            rx_length: FRAME_HEADER_LENGTH,
+        };
+    }
+
+    /// How long the latest command took, as far as it got, or `None` if nothing has been sent
+    /// yet. A command which stalled shows which part it stalled in.
+    pub fn last_timing(&self) -> Option<CommandTiming> {
+        return self.timing;
+    }
+
+    /// Gives back the transport and clock.
+    pub fn release(self) -> (T, C) {
+        return (self.transport, self.clock);
+    }
+
+    /// Starts timing a command on its first byte.
+    fn start_frame(&mut self) {
+        if !self.writing {
+            self.start = self.clock.now_ms();
+            self.writing = true;
+            self.flushed_at = None;
+            self.timing = Some(CommandTiming::default());
+            self.rx_seen = 0;
+            self.rx_length = FRAME_HEADER_LENGTH;
+        }
+    }
+
+    /// Follows the reply byte by byte, far enough to know where each frame ends. Returns true
+    /// on the last byte of a frame.
+    fn track_rx(&mut self, byte: u8) -> bool {
+        match (self.rx_seen, byte) {
+            (0, 0xEF) | (1, 0x01) => {}
+            (0, _) => return false,
+            (1, _) => {
+                self.rx_seen = 0;
+                return false;
+            }
+            (7, _) => self.rx_length = FRAME_HEADER_LENGTH + ((byte as usize) << 8),
+            (8, _) => self.rx_length += byte as usize,
+            _ => {}
+        }
+        self.rx_seen += 1;
+        if self.rx_seen < self.rx_length {
+            return false;
+        }
+        self.rx_seen = 0;
+        self.rx_length = FRAME_HEADER_LENGTH;
+        return true;
+    }
+}
+
+impl<T, C> Transport for Timed<T, C>
+where
+    T: Transport,
+    C: Clock,
+{
+    type WriteError = T::WriteError;
+    type ReadError = T::ReadError;
+
+    fn write_byte(&mut self, byte: u8) -> nb::Result<(), Self::WriteError> {
+        self.start_frame();
+        return self.transport.write_byte(byte);
+    }
+
+    fn write_slice(&mut self, bytes: &[u8]) -> nb::Result<usize, Self::WriteError> {
+        self.start_frame();
+        return self.transport.write_slice(bytes);
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::WriteError> {
+        self.transport.flush()?;
+        if self.writing {
+            let now = self.clock.now_ms();
+            self.writing = false;
+            self.flushed_at = Some(now);
+            if let Some(timing) = &mut self.timing {
+                timing.write_ms = now.wrapping_sub(self.start);
+            }
+        }
+        return Ok(());
+    }
+
+    fn read_byte(&mut self) -> nb::Result<u8, Self::ReadError> {
+        let byte = self.transport.read_byte()?;
+        let first = matches!(self.timing, Some(CommandTiming { wait_ms: None, .. }));
+        let last = self.track_rx(byte);
+        if first || last {
+            let now = self.clock.now_ms();
+            if let Some(timing) = &mut self.timing {
+                if first {
+                    let flushed_at = self.flushed_at.unwrap_or(now);
+                    timing.wait_ms = Some(now.wrapping_sub(flushed_at));
+                }
+                if last {
+                    timing.total_ms = Some(now.wrapping_sub(self.start));
+                }
+            }
+        }
+        return Ok(byte);
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Times every command from now on with `clock`; see
+    /// [`last_timing`](#method.last_timing). Call it before the first command, or between
+    /// commands.
+    pub fn with_timing<C>(self, clock: C) -> R502<Timed<T, C>>
+    where
+        C: Clock,
+    {
+        return self.map_transport(|transport| Timed::new(transport, clock));
+    }
+}
+
+impl<T, C> R502<Timed<T, C>>
+where
+    T: Transport,
+    C: Clock,
+{
+    /// How long the latest command took, as far as it got, or `None` if nothing has been sent
+    /// since [`with_timing`](#method.with_timing). After a timeout, this shows whether the
+    /// command was still being written, or the module had not answered at all.
+    pub fn last_timing(&self) -> Option<CommandTiming> {
+        return self.transport().last_timing();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::commands::Command;
+    #[cfg(feature = "cmd-transfer")]
+    use crate::emulator::char_file;
+    use crate::emulator::{Emulator, EmulatorRx, EmulatorTx};
+    #[cfg(feature = "cmd-transfer")]
+    use crate::template::Template;
+    use std::boxed::Box;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// A serial port on which every byte written takes 1 ms and every byte read takes 2 ms,
+    /// and the reply only starts after `latency_ms`, in 10 ms steps.
+    struct SlowSerial {
+        serial: (EmulatorTx, EmulatorRx),
+        time: Rc<Cell<u32>>,
+        latency_ms: u32,
+        waited_ms: u32,
+    }
+
+    impl SlowSerial {
+        fn advance(&self, ms: u32) {
+            self.time.set(self.time.get() + ms);
+        }
+    }
+
+    impl Transport for SlowSerial {
+        type WriteError = <(EmulatorTx, EmulatorRx) as Transport>::WriteError;
+        type ReadError = <(EmulatorTx, EmulatorRx) as Transport>::ReadError;
+
+        fn write_byte(&mut self, byte: u8) -> nb::Result<(), Self::WriteError> {
+            self.advance(1);
+            self.waited_ms = 0;
+            return self.serial.write_byte(byte);
+        }
+
+        fn flush(&mut self) -> nb::Result<(), Self::WriteError> {
+            return self.serial.flush();
+        }
+
+        fn read_byte(&mut self) -> nb::Result<u8, Self::ReadError> {
+            if self.waited_ms < self.latency_ms {
+                self.advance(10);
+                self.waited_ms += 10;
+                return Err(nb::Error::WouldBlock);
+            }
+            self.advance(2);
+            return self.serial.read_byte();
+        }
+    }
+
+    type TimedR502 = R502<Timed<SlowSerial, Box<dyn FnMut() -> u32>>>;
+
+    fn timed(emulator: &Emulator, latency_ms: u32) -> TimedR502 {
+        let time = Rc::new(Cell::new(1000));
+        let serial =
+            SlowSerial { serial: emulator.serial(), time: time.clone(), latency_ms, waited_ms: 0 };
+        let clock: Box<dyn FnMut() -> u32> = Box::new(move || time.get());
+        return R502::with_transport(serial, 0xffffffff).with_timing(clock);
+    }
+
+    #[test]
+    fn test_timing_command() {
+        // given: a timed R502 on a port where the reply starts 30 ms after the command
+        let emulator = Emulator::new();
+        let mut r502 = timed(&emulator, 30);
+
+        // when: sending `TemplateNum`
+        r502.send_command(Command::TemplateNum).unwrap();
+
+        // then: writing the 12 bytes took 12 ms, the first byte of the reply came 32 ms
+        // after that, and the rest of the 14-byte reply 26 ms later
+        let timing = CommandTiming { write_ms: 12, wait_ms: Some(32), total_ms: Some(70) };
+        assert_eq!(r502.last_timing(), Some(timing));
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_timing_only_latest_command() {
+        // given: a timed R502 with finger 7 in buffer 1
+        let emulator = Emulator::new();
+        emulator.state().buffers[0] = Some(char_file(7));
+        let mut r502 = timed(&emulator, 0);
+
+        // when: sending `TemplateNum`, then uploading the template
+        r502.send_command(Command::TemplateNum).unwrap();
+        let template = r502.upload_template(1).unwrap();
+
+        // then: the timing covers the upload alone, up to its last data packet
+        let packets = template.len().div_ceil(128);
+        let read = 12 + template.len() + packets * 11;
+        let timing = r502.last_timing().unwrap();
+        assert_eq!(timing.write_ms, 13);
+        assert_eq!(timing.wait_ms, Some(2));
+        assert_eq!(timing.total_ms, Some(13 + 2 * read as u32));
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_timing_data_packets() {
+        // given: a timed R502
+        let emulator = Emulator::new();
+        let mut r502 = timed(&emulator, 0);
+
+        // when: downloading a template
+        let template = Template::from_bytes(&[0x11; 40]).unwrap();
+        r502.download_template(1, &template).unwrap();
+
+        // then: the data packets were timed as a command which gets no reply
+        let timing = CommandTiming { write_ms: 40 + 11, wait_ms: None, total_ms: None };
+        assert_eq!(r502.last_timing(), Some(timing));
+    }
+
+    #[test]
+    fn test_timing_stalled_command() {
+        // given: a timed R502 on a port where the reply never starts in time
+        let emulator = Emulator::new();
+        let mut r502 = timed(&emulator, 1000);
+
+        // when: starting `TemplateNum` and polling a few times
+        r502.start_command(Command::TemplateNum).unwrap();
+        for _ in 0..5 {
+            assert_eq!(matches!(r502.poll(), Err(nb::Error::WouldBlock)), true);
+        }
+
+        // then: the command was written, and the module has not answered
+        let timing = CommandTiming { write_ms: 12, wait_ms: None, total_ms: None };
+        assert_eq!(r502.last_timing(), Some(timing));
+    }
+
+    #[test]
+    fn test_no_timing_before_first_command() {
+        // given: a timed R502
+        let emulator = Emulator::new();
+        let r502 = timed(&emulator, 0);
+
+        // then: there is nothing to report
+        assert_eq!(r502.last_timing(), None);
+    }
+}
@@ -0,0 +1,242 @@
+use core::fmt;
+use core::time::Duration;
+use embedded_io_async::{ErrorKind, ErrorType, Read, Write};
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio_serial::SerialStream;
+
+use crate::async_driver::R502Async;
+
+/// Error reading from or writing to a `TokioSerial`.
+#[derive(Debug)]
+pub enum TokioSerialError {
+    /// Nothing was read within the read timeout of the `TokioSerial`; the module most likely
+    /// did not reply. `R502Async` reports this as `Error::Timeout`.
+    Timeout,
+
+    /// Any other I/O error.
+    Io(io::Error),
+}
+
+impl From<io::Error> for TokioSerialError {
+    fn from(error: io::Error) -> Self {
+        return match error.kind() {
+            io::ErrorKind::TimedOut => Self::Timeout,
+            _ => Self::Io(error),
+        };
+    }
+}
+
+impl fmt::Display for TokioSerialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Self::Timeout => f.write_str("timed out"),
+            Self::Io(error) => write!(f, "{}", error),
+        };
+    }
+}
+
+impl std::error::Error for TokioSerialError {}
+
+impl embedded_io_async::Error for TokioSerialError {
+    fn kind(&self) -> ErrorKind {
+        return match self {
+            Self::Timeout => ErrorKind::TimedOut,
+            Self::Io(_) => ErrorKind::Other,
+        };
+    }
+}
+
+/// A tokio stream, by default a `tokio_serial::SerialStream`, as an `embedded-io-async` serial
+/// port, for running [`R502Async`](struct.R502Async.html) on a PC with a USB to serial
+/// converter.
+///
+/// Reads wait at most the read timeout, if one is set, for each chunk of a reply; once it runs
+/// out, `R502Async` returns `Error::Timeout` rather than waiting forever. The timeout needs a
+/// tokio runtime with the timer enabled.
+#[derive(Debug)]
+pub struct TokioSerial<S = SerialStream> {
+    stream: S,
+    read_timeout: Option<Duration>,
+}
+
+impl<S> TokioSerial<S> {
+    /// Wraps `stream`, which should already be set to the module's baud rate, with no read
+    /// timeout.
+    pub fn new(stream: S) -> Self {
+        return Self { stream, read_timeout: None };
+    }
+
+    /// Wraps `stream`, giving up on reads after `timeout`.
+    pub fn with_read_timeout(stream: S, timeout: Duration) -> Self {
+        return Self { stream, read_timeout: Some(timeout) };
+    }
+
+    /// How long a read waits for data, `None` for as long as it takes.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        return self.read_timeout;
+    }
+
+    /// Changes how long a read waits for data, `None` for as long as it takes.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    /// The stream, for changing its settings.
+    pub fn stream_mut(&mut self) -> &mut S {
+        return &mut self.stream;
+    }
+
+    /// Unwraps the stream.
+    pub fn into_inner(self) -> S {
+        return self.stream;
+    }
+}
+
+impl<S> ErrorType for TokioSerial<S> {
+    type Error = TokioSerialError;
+}
+
+impl<S> Read for TokioSerial<S>
+where
+    S: AsyncRead + Unpin,
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let read = self.stream.read(buf);
+        return match self.read_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, read).await {
+                Ok(result) => Ok(result?),
+                Err(_) => Err(TokioSerialError::Timeout),
+            },
+            None => Ok(read.await?),
+        };
+    }
+}
+
+impl<S> Write for TokioSerial<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        return Ok(self.stream.write(buf).await?);
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        return Ok(self.stream.flush().await?);
+    }
+}
+
+/// `R502Async` over the two halves of a tokio stream.
+pub type R502Tokio<S = SerialStream> =
+    R502Async<TokioSerial<WriteHalf<S>>, TokioSerial<ReadHalf<S>>>;
+
+impl<S> R502Tokio<S>
+where
+    S: AsyncRead + AsyncWrite,
+{
+    /// Creates an instance of the R502 talking over `stream`, such as a
+    /// `tokio_serial::SerialStream` opened at the module's baud rate, which is split into its
+    /// halves. Replies are given up on with `Error::Timeout` if nothing arrives for
+    /// `read_timeout`, or waited for as long as they take if it is `None`.
+    ///
+    /// ```no_run
+    /// # async fn run() -> Result<(), std::boxed::Box<dyn std::error::Error>> {
+    /// use core::time::Duration;
+    /// use hzgrow_r502::{Command, R502Async};
+    /// use tokio_serial::SerialPortBuilderExt;
+    ///
+    /// let stream = tokio_serial::new("/dev/ttyUSB0", 57600).open_native_async()?;
+    /// let timeout = Some(Duration::from_secs(1));
+    /// let mut r502 = R502Async::from_tokio(stream, 0xffffffff, timeout);
+    /// let reply = r502.send_command(Command::ReadSysPara).await;
+    /// # return Ok(());
+    /// # }
+    /// ```
+    pub fn from_tokio(stream: S, address: u32, read_timeout: Option<Duration>) -> Self {
+        let (rx, tx) = tokio::io::split(stream);
+        let rx = TokioSerial { stream: rx, read_timeout };
+        return Self::new(TokioSerial::new(tx), rx, address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Command;
+    use crate::responses::*;
+    use crate::utils::Error;
+    use tokio::io::DuplexStream;
+
+    /// `VfyPwd` with the default password, and the module's reply accepting it.
+    const VFY_PWD: [u8; 16] = [
+        0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x07, 0x13, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x1b,
+    ];
+    const VFY_PWD_REPLY: [u8; 12] =
+        [0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x00, 0x00, 0x0a];
+
+    /// `ReadSysPara`, and the reply of a module with a library of 200 slots.
+    const READ_SYS_PARA: [u8; 12] =
+        [0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x0f, 0x00, 0x13];
+    const READ_SYS_PARA_REPLY: [u8; 28] = [
+        0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x13, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xc8, 0x00, 0x03, 0xff, 0xff, 0xff, 0xff, 0x00, 0x02, 0x00, 0x06, 0x04, 0xe9,
+    ];
+
+    /// Answers each of `exchanges` in turn: reads the command, checks it, and sends the reply.
+    async fn module(mut stream: DuplexStream, exchanges: &[(&[u8], &[u8])]) {
+        for (command, reply) in exchanges.iter() {
+            let mut received = std::vec![0u8; command.len()];
+            stream.read_exact(&mut received).await.unwrap();
+            assert_eq!(&received[..], *command);
+            stream.write_all(reply).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_commands_over_tokio_stream() {
+        // given: a driver on one end of a tokio stream, and a module on the other
+        let (host, device) = tokio::io::duplex(256);
+        let mut r502 = R502Async::from_tokio(host, 0xffffffff, Some(Duration::from_secs(5)));
+        let exchanges: [(&[u8], &[u8]); 2] =
+            [(&VFY_PWD, &VFY_PWD_REPLY), (&READ_SYS_PARA, &READ_SYS_PARA_REPLY)];
+
+        // when: authenticating and reading the system parameters
+        let host = async {
+            let vfy_pwd = r502.send_command(Command::VfyPwd { password: 0 }).await;
+            let read_sys_para = r502.send_command(Command::ReadSysPara).await;
+            return (vfy_pwd, read_sys_para);
+        };
+        let ((vfy_pwd, read_sys_para), ()) = tokio::join!(host, module(device, &exchanges));
+
+        // then: both replies come through
+        match vfy_pwd {
+            Ok(Reply::VfyPwd(result)) => {
+                assert_eq!(
+                    matches!(result.confirmation_code, PasswordVerificationState::Correct),
+                    true
+                );
+            }
+            other => panic!("Expected Reply::VfyPwd, got {:?}", other),
+        }
+        match read_sys_para {
+            Ok(Reply::ReadSysPara(result)) => {
+                assert_eq!(result.system_parameters.finger_library_size, 200);
+            }
+            other => panic!("Expected Reply::ReadSysPara, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_timeout() {
+        // given: a driver with a short read timeout, and a module which never replies
+        let (host, _device) = tokio::io::duplex(256);
+        let mut r502 = R502Async::from_tokio(host, 0xffffffff, Some(Duration::from_millis(20)));
+
+        // when: sending a command
+        let result = r502.send_command(Command::ReadSysPara).await;
+
+        // then: the driver gives up with a timeout
+        assert_eq!(matches!(result, Err(Error::Timeout)), true);
+    }
+}
@@ -0,0 +1,128 @@
+use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::digital::v2::InputPin;
+
+use crate::driver::R502;
+use crate::transport::Transport;
+
+/// How many consecutive high readings, 1 ms apart, of the touch pin count as a finger.
+pub const TOUCH_DEBOUNCE_READS: u8 = 2;
+
+/// Error type for `wait_for_touch`.
+#[derive(Debug, PartialEq)]
+pub enum TouchError<PE> {
+    /// The touch pin could not be read.
+    Pin(PE),
+
+    /// Nobody touched the sensor in time.
+    Timeout,
+}
+
+/// Reads the touch pin `TOUCH_DEBOUNCE_READS` times, returning true if it was high every time.
+/// Gives up on the first low reading, so an untouched sensor costs a single read.
+pub(crate) fn read_touch<P, D>(pin: &mut P, delay: &mut D) -> Result<bool, P::Error>
+where
+    P: InputPin,
+    D: DelayMs<u16>,
+{
+    for read in 0..TOUCH_DEBOUNCE_READS {
+        if read > 0 {
+            delay.delay_ms(1);
+        }
+        if !pin.is_high()? {
+            return Ok(false);
+        }
+    }
+    return Ok(true);
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Waits for up to `timeout_ms` for a finger on the sensor, watching the module's touch
+    /// output (WAKEUP) on `pin` rather than polling with `GenImg`, so nothing is sent over the
+    /// UART. The pin is high while the sensor is touched, and has to read high
+    /// `TOUCH_DEBOUNCE_READS` times in a row.
+    ///
+    /// The touch output only says something is on the sensor; follow up with `GenImg` to
+    /// capture it.
+    pub fn wait_for_touch<P, D>(
+        &mut self,
+        pin: &mut P,
+        delay: &mut D,
+        timeout_ms: u32,
+    ) -> Result<(), TouchError<P::Error>>
+    where
+        P: InputPin,
+        D: DelayMs<u16>,
+    {
+        let mut waited_ms = 0;
+        loop {
+            if read_touch(pin, delay).map_err(TouchError::Pin)? {
+                return Ok(());
+            }
+            if waited_ms >= timeout_ms {
+                return Err(TouchError::Timeout);
+            }
+            delay.delay_ms(1);
+            waited_ms += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::emulator::{Emulator, NoDelay};
+
+    #[test]
+    fn test_wait_for_touch() {
+        // given: a touch pin which bounces high once, then settles high
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        let mut pin = emulator.touch_pin(&[false, true, false, true, true, false]);
+
+        // when: waiting for a touch
+        let result = r502.wait_for_touch(&mut pin, &mut NoDelay, 1000);
+
+        // then: it returns after two high readings in a row, having sent nothing
+        assert_eq!(result, Ok(()));
+        assert_eq!(emulator.state().touch_pin, [false]);
+        assert_eq!(emulator.instructions().is_empty(), true);
+    }
+
+    #[test]
+    fn test_wait_for_touch_timeout() {
+        // given: a touch pin which stays low
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        let mut pin = emulator.touch_pin(&[false; 100]);
+
+        // when: waiting for a touch for 10 ms
+        let result = r502.wait_for_touch(&mut pin, &mut NoDelay, 10);
+
+        // then: it gives up after reading the pin once a millisecond
+        assert_eq!(result, Err(TouchError::Timeout));
+        assert_eq!(emulator.state().touch_pin_reads.len(), 11);
+    }
+
+    #[test]
+    fn test_wait_for_touch_pin_error() {
+        // given: a touch pin which cannot be read
+        let emulator = Emulator::new();
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        let mut pin = emulator.touch_pin(&[]);
+        emulator.state().touch_pin_broken = true;
+
+        // when: waiting for a touch
+        let result = r502.wait_for_touch(&mut pin, &mut NoDelay, 10);
+
+        // then: the pin error comes back
+        assert_eq!(result, Err(TouchError::Pin(())));
+    }
+}
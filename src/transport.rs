@@ -0,0 +1,347 @@
+use embedded_hal::serial::{Read, Write};
+use nb::block;
+
+/// A byte stream to and from the R502, which `R502` talks to. Any pair of embedded-hal serial
+/// halves `(tx, rx)` is a `Transport`; implement it for anything else which carries the
+/// protocol, such as a USB CDC class or a TCP connection to a remote module.
+///
+/// The methods behave like their embedded-hal counterparts: they return `WouldBlock` rather
+/// than wait, and `R502` retries them.
+///
+/// Frames go out through [`write_slice`](#method.write_slice), a byte at a time unless the
+/// transport implements it to take more, for example by DMA or a single write to the host
+/// OS.
+pub trait Transport {
+    /// Error returned when writing fails.
+    type WriteError;
+
+    /// Error returned when reading fails.
+    type ReadError;
+
+    /// Writes a single byte.
+    fn write_byte(&mut self, byte: u8) -> nb::Result<(), Self::WriteError>;
+
+    /// Makes sure everything written so far has gone out.
+    fn flush(&mut self) -> nb::Result<(), Self::WriteError>;
+
+    /// Reads a single byte.
+    fn read_byte(&mut self) -> nb::Result<u8, Self::ReadError>;
+
+    /// Writes as much of `bytes` as can be taken without blocking, and returns how many bytes
+    /// that was, which is at least one unless `bytes` is empty. The driver hands it whole
+    /// frames. By default it writes the first byte with `write_byte`.
+    fn write_slice(&mut self, bytes: &[u8]) -> nb::Result<usize, Self::WriteError> {
+        return match bytes.first() {
+            Some(byte) => self.write_byte(*byte).map(|_| 1),
+            None => Ok(0),
+        };
+    }
+
+    /// Writes all of `bytes`, blocking until they have been taken.
+    fn write_all(&mut self, mut bytes: &[u8]) -> Result<(), Self::WriteError> {
+        while !bytes.is_empty() {
+            let taken = block!(self.write_slice(bytes))?;
+            bytes = &bytes[taken..];
+        }
+        return Ok(());
+    }
+
+    /// Fills `buffer`, blocking until enough bytes have arrived.
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), Self::ReadError> {
+        for byte in buffer.iter_mut() {
+            *byte = block!(self.read_byte())?;
+        }
+        return Ok(());
+    }
+}
+
+impl<TX, RX> Transport for (TX, RX)
+where
+    TX: Write<u8>,
+    RX: Read<u8>,
+{
+    type WriteError = TX::Error;
+    type ReadError = RX::Error;
+
+    fn write_byte(&mut self, byte: u8) -> nb::Result<(), Self::WriteError> {
+        return self.0.write(byte);
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::WriteError> {
+        return self.0.flush();
+    }
+
+    fn read_byte(&mut self) -> nb::Result<u8, Self::ReadError> {
+        return self.1.read();
+    }
+}
+
+/// Which way bytes went between the host and the module, in a recording or through a bridge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the host to the module.
+    Tx,
+
+    /// From the module to the host.
+    Rx,
+}
+
+/// A serial port which both reads and writes, for HALs which do not split their serial
+/// peripherals into halves. See [`R502::from_serial`](struct.R502.html#method.from_serial).
+#[derive(Debug)]
+pub struct CombinedSerial<S>(S);
+
+impl<S> CombinedSerial<S> {
+    /// Wraps `serial` so it can be used as a `Transport`.
+    pub const fn new(serial: S) -> Self {
+        return Self(serial);
+    }
+
+    /// The serial port, for borrowing it back.
+    pub fn inner_mut(&mut self) -> &mut S {
+        return &mut self.0;
+    }
+
+    /// Unwraps the serial port.
+    pub fn into_inner(self) -> S {
+        return self.0;
+    }
+}
+
+impl<S> Transport for CombinedSerial<S>
+where
+    S: Read<u8> + Write<u8>,
+{
+    type WriteError = <S as Write<u8>>::Error;
+    type ReadError = <S as Read<u8>>::Error;
+
+    fn write_byte(&mut self, byte: u8) -> nb::Result<(), Self::WriteError> {
+        return self.0.write(byte);
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::WriteError> {
+        return self.0.flush();
+    }
+
+    fn read_byte(&mut self) -> nb::Result<u8, Self::ReadError> {
+        return self.0.read();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::commands::Command;
+    #[cfg(feature = "cmd-transfer")]
+    use crate::consts::PacketSize;
+    use crate::driver::R502;
+    use crate::responses::Reply;
+    #[cfg(feature = "cmd-transfer")]
+    use crate::template::Template;
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    /// A transport which records what is written, and reads out whatever was queued up. With
+    /// `whole_slices`, it takes everything handed to `write_slice` at once, and records how much
+    /// that was in `slices`.
+    #[derive(Default)]
+    struct FakeTransport {
+        written: Vec<u8>,
+        incoming: VecDeque<u8>,
+        flushes: usize,
+        whole_slices: bool,
+        slices: Vec<usize>,
+    }
+
+    impl Transport for FakeTransport {
+        type WriteError = ();
+        type ReadError = ();
+
+        fn write_byte(&mut self, byte: u8) -> nb::Result<(), Self::WriteError> {
+            self.written.push(byte);
+            return Ok(());
+        }
+
+        fn write_slice(&mut self, bytes: &[u8]) -> nb::Result<usize, Self::WriteError> {
+            let taken = if self.whole_slices { bytes.len() } else { bytes.len().min(1) };
+            self.written.extend_from_slice(&bytes[..taken]);
+            self.slices.push(taken);
+            return Ok(taken);
+        }
+
+        fn flush(&mut self) -> nb::Result<(), Self::WriteError> {
+            self.flushes += 1;
+            return Ok(());
+        }
+
+        fn read_byte(&mut self) -> nb::Result<u8, Self::ReadError> {
+            return self.incoming.pop_front().ok_or(nb::Error::WouldBlock);
+        }
+    }
+
+    impl FakeTransport {
+        /// What was written, and how much each `write_slice` took.
+        #[cfg(feature = "cmd-transfer")]
+        fn take_written(&mut self) -> (Vec<u8>, Vec<usize>) {
+            return (core::mem::take(&mut self.written), core::mem::take(&mut self.slices));
+        }
+    }
+
+    #[test]
+    fn test_send_command_over_transport() {
+        // given: a transport with the reply to `TemplateNum` queued up
+        let mut transport = FakeTransport::default();
+        transport.incoming.extend(&[
+            0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x05, 0x00, 0x00, 0x2a, 0x00, 0x36,
+        ]);
+        let mut r502 = R502::with_transport(transport, 0xffffffff);
+
+        // when: sending `TemplateNum`
+        let reply = r502.send_command(Command::TemplateNum).unwrap();
+
+        // then: the reply is read from the transport
+        match reply {
+            Reply::TemplateNum(result) => assert_eq!(result.template_num, 42),
+            other => panic!("Expected Reply::TemplateNum, got {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_download_over_transport() {
+        // given: a transport with the acknowledgement of `DownChar` queued up
+        let mut transport = FakeTransport::default();
+        transport.incoming.extend(&[
+            0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x00, 0x00, 0x0a,
+        ]);
+        let mut r502 = R502::with_transport(transport, 0xffffffff);
+        r502.set_data_packet_size(PacketSize::Bytes32);
+
+        // when: downloading a 40-byte template
+        let template = Template::from_bytes(&[0x11; 40]).unwrap();
+        r502.download_template(1, &template).unwrap();
+
+        // then: the command went out, then a data packet and an end-of-data packet
+        let written = &r502.transport().written;
+        assert_eq!(written.len(), 13 + (11 + 32) + (11 + 8));
+        assert_eq!(&written[13..22], &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x02, 0x00, 0x22]);
+        assert_eq!(&written[56..65], &[0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x08, 0x00, 0x0a]);
+        assert_eq!(&written[73..], &[0x00, 0x9a]);
+        assert_eq!(r502.transport().flushes, 2);
+    }
+
+    #[test]
+    #[cfg(feature = "cmd-transfer")]
+    fn test_write_whole_slices() {
+        // given: two transports, one taking a byte at a time and one taking whole slices, with
+        // the acknowledgement of `DownChar` queued up
+        let download = |whole_slices| {
+            let mut transport = FakeTransport { whole_slices, ..FakeTransport::default() };
+            transport.incoming.extend(&[
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x00, 0x00, 0x0a,
+            ]);
+            let mut r502 = R502::with_transport(transport, 0xffffffff);
+            r502.set_data_packet_size(PacketSize::Bytes32);
+
+            // when: downloading a 40-byte template over each
+            let template = Template::from_bytes(&[0x11; 40]).unwrap();
+            r502.download_template(1, &template).unwrap();
+            return r502.transport_mut().take_written();
+        };
+        let (bytewise, bytewise_slices) = download(false);
+        let (slicewise, slicewise_slices) = download(true);
+
+        // then: the same bytes went out
+        assert_eq!(bytewise, slicewise);
+        assert_eq!(bytewise_slices.iter().all(|taken| *taken == 1), true);
+
+        // and: the command went out in one go, and each data packet as header, data, checksum
+        assert_eq!(slicewise_slices, [13, 9, 32, 2, 9, 8, 2]);
+    }
+
+    #[test]
+    fn test_from_serial() {
+        // given: a serial port which both reads and writes, with a reply to `GenImg` queued up
+        struct Port {
+            written: Vec<u8>,
+            incoming: VecDeque<u8>,
+        }
+
+        impl Write<u8> for Port {
+            type Error = ();
+            fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+                self.written.push(word);
+                return Ok(());
+            }
+            fn flush(&mut self) -> nb::Result<(), Self::Error> {
+                return Ok(());
+            }
+        }
+
+        impl Read<u8> for Port {
+            type Error = ();
+            fn read(&mut self) -> nb::Result<u8, Self::Error> {
+                return self.incoming.pop_front().ok_or(nb::Error::WouldBlock);
+            }
+        }
+
+        let port = Port {
+            written: Vec::new(),
+            incoming: VecDeque::from(std::vec![
+                0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x07, 0x00, 0x03, 0x02, 0x00, 0x0c,
+            ]),
+        };
+        let mut r502 = R502::from_serial(port, 0xffffffff);
+
+        // when: sending `GenImg`
+        let reply = r502.send_command(Command::GenImg).unwrap();
+
+        // then: the command went out and the reply came in through the same port
+        assert_eq!(matches!(reply, Reply::GenImg(_)), true);
+        let port = r502.transport_mut().inner_mut();
+        assert_eq!(
+            port.written,
+            [0xef, 0x01, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x03, 0x01, 0x00, 0x05]
+        );
+        assert_eq!(port.incoming.is_empty(), true);
+    }
+
+    #[test]
+    fn test_pair_is_a_transport() {
+        // given: a pair of serial halves, as used by `R502::new`
+        struct Tx(Vec<u8>);
+        struct Rx(VecDeque<u8>);
+
+        impl Write<u8> for Tx {
+            type Error = ();
+            fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+                self.0.push(word);
+                return Ok(());
+            }
+            fn flush(&mut self) -> nb::Result<(), Self::Error> {
+                return Ok(());
+            }
+        }
+
+        impl Read<u8> for Rx {
+            type Error = ();
+            fn read(&mut self) -> nb::Result<u8, Self::Error> {
+                return self.0.pop_front().ok_or(nb::Error::Other(()));
+            }
+        }
+
+        let mut pair = (Tx(Vec::new()), Rx(VecDeque::from(std::vec![1, 2, 3])));
+
+        // when: writing and reading through the `Transport` methods
+        pair.write_all(&[4, 5]).unwrap();
+        let mut buffer = [0u8; 2];
+        pair.read_exact(&mut buffer).unwrap();
+
+        // then: they go to the halves
+        assert_eq!(pair.0 .0, [4, 5]);
+        assert_eq!(buffer, [1, 2]);
+        assert_eq!(pair.read_exact(&mut [0u8; 2]), Err(()));
+    }
+}
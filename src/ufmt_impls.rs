@@ -0,0 +1,792 @@
+use ufmt::{uDebug, uDisplay, uWrite, Formatter};
+
+use crate::commands::{Command, CommandKind};
+use crate::responses::*;
+use crate::utils::Error;
+
+/// Implements `uDebug` for enums without fields, writing the variant's name as `Debug` does.
+macro_rules! name_udebug {
+    ($($name:ty { $($(#[$attr:meta])* $variant:ident),+ $(,)? })+) => {
+        $(
+            impl uDebug for $name {
+                fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+                where
+                    W: uWrite + ?Sized,
+                {
+                    return f.write_str(match self {
+                        $($(#[$attr])* Self::$variant => stringify!($variant),)+
+                    });
+                }
+            }
+        )+
+    };
+}
+
+/// Implements `uDebug` for structs, writing every field as `Debug` does. Byte arrays are
+/// given with `[..]`, as they are written as slices.
+macro_rules! struct_udebug {
+    ($($name:ident { $($field:ident $([$range:tt])?),+ $(,)? })+) => {
+        $(
+            impl uDebug for $name {
+                fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+                where
+                    W: uWrite + ?Sized,
+                {
+                    return f
+                        .debug_struct(stringify!($name))?
+                        $(.field(stringify!($field), &&self.$field$([$range])?)?)+
+                        .finish();
+                }
+            }
+        )+
+    };
+}
+
+/// Implements `uDebug` for `Reply`, writing each variant with its result as `Debug` does, and
+/// `uDisplay`, which hands over to the result as `Display` does.
+macro_rules! reply_ufmt {
+    ($($(#[$attr:meta])* $variant:ident),+ $(,)?) => {
+        impl uDebug for Reply {
+            fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+            where
+                W: uWrite + ?Sized,
+            {
+                return match self {
+                    $($(#[$attr])* Reply::$variant(result) => {
+                        f.debug_tuple(stringify!($variant))?.field(result)?.finish()
+                    })+
+                };
+            }
+        }
+
+        impl uDisplay for Reply {
+            fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+            where
+                W: uWrite + ?Sized,
+            {
+                return match self {
+                    $($(#[$attr])* Reply::$variant(result) => uDisplay::fmt(result, f),)+
+                };
+            }
+        }
+    };
+}
+
+name_udebug! {
+    CommandKind {
+        ReadSysPara, VfyPwd, GenImg, Img2Tz, Search, LoadChar, Match, TemplateNum,
+        ReadIndexTable,
+        #[cfg(feature = "cmd-enroll")]
+        RegModel,
+        #[cfg(feature = "cmd-enroll")]
+        Store,
+        #[cfg(feature = "cmd-transfer")]
+        UpChar,
+        #[cfg(feature = "cmd-transfer")]
+        DownChar,
+        #[cfg(feature = "cmd-transfer")]
+        DownImage,
+        SetSysPara, SetPwd, SetAdder, GetChipSN, GetRandomCode,
+        #[cfg(feature = "cmd-notepad")]
+        WriteNotepad,
+        #[cfg(feature = "cmd-notepad")]
+        ReadNotepad,
+        GetFwVer, GetAlgVer, HandShake, CheckSensor, SoftRst, Sleep, PortControl,
+        #[cfg(feature = "cmd-led")]
+        AuraLedConfig,
+        DeletChar, Empty,
+    }
+    PasswordVerificationState { Correct, Incorrect, Error }
+    GenImgStatus { Success, PacketError, FingerNotDetected, ImageNotCaptured }
+    Img2TzStatus {
+        Success, PacketError, FingerprintImageDistorted, ProcessingFailed, InvalidInput,
+    }
+    SearchStatus {
+        Success, PacketError, NoMatch,
+        #[cfg(feature = "r503")]
+        LibraryEmpty,
+    }
+    LoadCharStatus {
+        Success, PacketError, LibraryReadError, IndexOutOfRange,
+        #[cfg(feature = "r503")]
+        TemplateEmpty,
+    }
+    MatchStatus { Success, PacketError, NoMatch }
+    TemplateNumStatus { Success, PacketError }
+    ReadIndexTableStatus { Success, PacketError }
+    RegModelStatus { Success, PacketError, ProcessingError }
+    StoreStatus {
+        Success, PacketError, IndexOutOfRange, WriteError,
+        #[cfg(feature = "r503")]
+        LibraryFull,
+    }
+    UpCharStatus { Success, PacketError, UploadFailed }
+    DownCharStatus { Success, PacketError, CannotReceive }
+    DownImageStatus { Success, PacketError, CannotReceive }
+    SetPwdStatus { Success, PacketError }
+    SetSysParaStatus { Success, PacketError, WrongRegister }
+    SetAdderStatus { Success, PacketError }
+    GetChipSNStatus { Success, PacketError }
+    GetRandomCodeStatus { Success, PacketError }
+    HandShakeStatus { Success, PacketError }
+    SoftRstStatus { Success, PacketError }
+    GetFwVerStatus { Success, PacketError }
+    GetAlgVerStatus { Success, PacketError }
+    WriteNotepadStatus { Success, PacketError, WriteError }
+    ReadNotepadStatus { Success, PacketError }
+    SleepStatus { Success, PacketError }
+    PortControlStatus { Success, PacketError, PortOperationFailed }
+    AuraLedConfigStatus { Success, PacketError }
+    CheckSensorStatus { Success, PacketError, SensorAbnormal }
+    DeletCharStatus { Success, PacketError, DeleteFailed }
+    EmptyStatus { Success, PacketError, ClearFailed }
+}
+
+struct_udebug! {
+    SystemParameters {
+        status_register, system_identifier_code, finger_library_size, security_level,
+        device_address, packet_size, baud_setting,
+    }
+    ReadSysParaResult { address, confirmation_code, system_parameters, checksum }
+    VfyPwdResult { address, confirmation_code, checksum }
+    GenImgResult { address, confirmation_code, checksum }
+    Img2TzResult { address, confirmation_code, checksum }
+    SearchResult { address, confirmation_code, match_id, match_score, checksum }
+    LoadCharResult { address, confirmation_code, checksum }
+    MatchResult { address, confirmation_code, match_score, checksum }
+    TemplateNumResult { address, confirmation_code, template_num, checksum }
+    ReadIndexTableResult { address, confirmation_code, index_table[..], checksum }
+    RegModelResult { address, confirmation_code, checksum }
+    StoreResult { address, confirmation_code, checksum }
+    UpCharResult { address, confirmation_code, checksum }
+    DownCharResult { address, confirmation_code, checksum }
+    DownImageResult { address, confirmation_code, checksum }
+    SetPwdResult { address, confirmation_code, checksum }
+    SetSysParaResult { address, confirmation_code, checksum }
+    SetAdderResult { address, confirmation_code, checksum }
+    GetChipSNResult { address, confirmation_code, serial_number[..], checksum }
+    GetRandomCodeResult { address, confirmation_code, random_code, checksum }
+    HandShakeResult { address, confirmation_code, checksum }
+    SoftRstResult { address, confirmation_code, checksum }
+    GetFwVerResult { address, confirmation_code, version[..], checksum }
+    GetAlgVerResult { address, confirmation_code, version[..], checksum }
+    WriteNotepadResult { address, confirmation_code, checksum }
+    ReadNotepadResult { address, confirmation_code, data[..], checksum }
+    SleepResult { address, confirmation_code, checksum }
+    PortControlResult { address, confirmation_code, checksum }
+    CheckSensorResult { address, confirmation_code, checksum }
+    AuraLedConfigResult { address, confirmation_code, checksum }
+    DeletCharResult { address, confirmation_code, checksum }
+    EmptyResult { address, confirmation_code, checksum }
+}
+
+reply_ufmt!(
+    ReadSysPara, VfyPwd, GenImg, Img2Tz, Search, LoadChar, Match, TemplateNum, ReadIndexTable,
+    #[cfg(feature = "cmd-enroll")]
+    RegModel,
+    #[cfg(feature = "cmd-enroll")]
+    Store,
+    #[cfg(feature = "cmd-transfer")]
+    UpChar,
+    #[cfg(feature = "cmd-transfer")]
+    DownChar,
+    #[cfg(feature = "cmd-transfer")]
+    DownImage,
+    SetPwd, SetSysPara, SetAdder, GetChipSN, GetRandomCode, GetFwVer, GetAlgVer,
+    #[cfg(feature = "cmd-notepad")]
+    WriteNotepad,
+    #[cfg(feature = "cmd-notepad")]
+    ReadNotepad,
+    HandShake, CheckSensor, SoftRst, Sleep, PortControl,
+    #[cfg(feature = "cmd-led")]
+    AuraLedConfig,
+    DeletChar, Empty,
+);
+
+/// Written out as `Debug` would, except that the password of `VfyPwd` and `SetPwd` is left out,
+/// so that logs from the field do not give it away.
+impl uDebug for Command {
+    fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        return match self {
+            Self::VfyPwd { .. } => f.write_str("VfyPwd { password: <redacted> }"),
+            Self::SetPwd { .. } => f.write_str("SetPwd { password: <redacted> }"),
+            Self::Img2Tz { buffer } => f.debug_struct("Img2Tz")?.field("buffer", buffer)?.finish(),
+            Self::Search { buffer, start_index, end_index } => f
+                .debug_struct("Search")?
+                .field("buffer", buffer)?
+                .field("start_index", start_index)?
+                .field("end_index", end_index)?
+                .finish(),
+            Self::LoadChar { buffer, index } => f
+                .debug_struct("LoadChar")?
+                .field("buffer", buffer)?
+                .field("index", index)?
+                .finish(),
+            Self::ReadIndexTable { page } => {
+                f.debug_struct("ReadIndexTable")?.field("page", page)?.finish()
+            }
+            #[cfg(feature = "cmd-enroll")]
+            Self::Store { buffer, index } => f
+                .debug_struct("Store")?
+                .field("buffer", buffer)?
+                .field("index", index)?
+                .finish(),
+            #[cfg(feature = "cmd-transfer")]
+            Self::UpChar { buffer } => f.debug_struct("UpChar")?.field("buffer", buffer)?.finish(),
+            #[cfg(feature = "cmd-transfer")]
+            Self::DownChar { buffer } => {
+                f.debug_struct("DownChar")?.field("buffer", buffer)?.finish()
+            }
+            Self::SetSysPara { parameter, value } => f
+                .debug_struct("SetSysPara")?
+                .field("parameter", parameter)?
+                .field("value", value)?
+                .finish(),
+            Self::SetAdder { address } => {
+                f.debug_struct("SetAdder")?.field("address", address)?.finish()
+            }
+            #[cfg(feature = "cmd-notepad")]
+            Self::WriteNotepad { page, data } => f
+                .debug_struct("WriteNotepad")?
+                .field("page", page)?
+                .field("data", &&data[..])?
+                .finish(),
+            #[cfg(feature = "cmd-notepad")]
+            Self::ReadNotepad { page } => {
+                f.debug_struct("ReadNotepad")?.field("page", page)?.finish()
+            }
+            Self::PortControl { enable } => {
+                f.debug_struct("PortControl")?.field("enable", enable)?.finish()
+            }
+            #[cfg(feature = "cmd-led")]
+            Self::AuraLedConfig { control, speed, color, times } => f
+                .debug_struct("AuraLedConfig")?
+                .field("control", control)?
+                .field("speed", speed)?
+                .field("color", color)?
+                .field("times", times)?
+                .finish(),
+            Self::DeletChar { start_index, num_to_delete } => f
+                .debug_struct("DeletChar")?
+                .field("start_index", start_index)?
+                .field("num_to_delete", num_to_delete)?
+                .finish(),
+            #[cfg(feature = "cmd-enroll")]
+            Self::RegModel => f.write_str("RegModel"),
+            #[cfg(feature = "cmd-transfer")]
+            Self::DownImage => f.write_str("DownImage"),
+            Self::ReadSysPara
+            | Self::GenImg
+            | Self::Match
+            | Self::TemplateNum
+            | Self::GetChipSN
+            | Self::GetRandomCode
+            | Self::GetFwVer
+            | Self::GetAlgVer
+            | Self::HandShake
+            | Self::CheckSensor
+            | Self::SoftRst
+            | Self::Sleep
+            | Self::Empty => uDebug::fmt(&self.kind(), f),
+        };
+    }
+}
+
+/// The name of the command, without its fields.
+impl uDisplay for Command {
+    fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        return uDebug::fmt(&self.kind(), f);
+    }
+}
+
+/// Writes the low `digits` hex digits of `value` after `0x`, as `{:#0Nx}` does for `Display`.
+fn write_hex<W>(f: &mut Formatter<'_, W>, value: u32, digits: u32) -> Result<(), W::Error>
+where
+    W: uWrite + ?Sized,
+{
+    f.write_str("0x")?;
+    return write_hex_digits(f, value, digits);
+}
+
+fn write_hex_digits<W>(f: &mut Formatter<'_, W>, value: u32, digits: u32) -> Result<(), W::Error>
+where
+    W: uWrite + ?Sized,
+{
+    const DIGITS: &str = "0123456789abcdef";
+    for shift in (0..digits).rev() {
+        let digit = ((value >> (shift * 4)) & 0xf) as usize;
+        f.write_str(&DIGITS[digit..digit + 1])?;
+    }
+    return Ok(());
+}
+
+/// Writes `bytes` as hex, with no separators.
+fn write_hex_bytes<W>(f: &mut Formatter<'_, W>, bytes: &[u8]) -> Result<(), W::Error>
+where
+    W: uWrite + ?Sized,
+{
+    for byte in bytes {
+        write_hex_digits(f, *byte as u32, 2)?;
+    }
+    return Ok(());
+}
+
+/// Writes a version string padded with zeroes, with anything but printable ASCII as `?`.
+fn write_version<W>(f: &mut Formatter<'_, W>, version: &[u8]) -> Result<(), W::Error>
+where
+    W: uWrite + ?Sized,
+{
+    for byte in version.iter().take_while(|byte| **byte != 0) {
+        let printable = byte.is_ascii_graphic() || *byte == b' ';
+        f.write_str(match core::str::from_utf8(core::slice::from_ref(byte)) {
+            Ok(text) if printable => text,
+            _ => "?",
+        })?;
+    }
+    return Ok(());
+}
+
+/// The replies whose `Display` says more than their status; see display.rs. The ones which
+/// carry only a status get `uDisplay` there, along with the status codes.
+impl uDisplay for SystemParameters {
+    fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        f.write_str("library of ")?;
+        uDisplay::fmt(&self.finger_library_size, f)?;
+        f.write_str(", security level ")?;
+        uDisplay::fmt(&self.security_level, f)?;
+        f.write_str(", ")?;
+        uDisplay::fmt(&(self.baud_setting as u32 * 9600), f)?;
+        f.write_str(" baud, address ")?;
+        return write_hex(f, self.device_address, 8);
+    }
+}
+
+impl uDisplay for ReadSysParaResult {
+    fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        f.write_str("ReadSysPara: ")?;
+        return match self.confirmation_code {
+            0x00 => uDisplay::fmt(&self.system_parameters, f),
+            0x01 => f.write_str("packet error"),
+            code => {
+                f.write_str("error ")?;
+                write_hex(f, code as u32, 2)
+            }
+        };
+    }
+}
+
+impl uDisplay for SearchResult {
+    fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        f.write_str("Search: ")?;
+        return match self.confirmation_code {
+            SearchStatus::Success => {
+                f.write_str("match at slot ")?;
+                uDisplay::fmt(&self.match_id, f)?;
+                f.write_str(" (score ")?;
+                uDisplay::fmt(&self.match_score, f)?;
+                f.write_str(")")
+            }
+            ref status => uDisplay::fmt(status, f),
+        };
+    }
+}
+
+impl uDisplay for MatchResult {
+    fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        f.write_str("Match: ")?;
+        return match self.confirmation_code {
+            MatchStatus::Success => {
+                f.write_str("fingers match (score ")?;
+                uDisplay::fmt(&self.match_score, f)?;
+                f.write_str(")")
+            }
+            ref status => uDisplay::fmt(status, f),
+        };
+    }
+}
+
+impl uDisplay for TemplateNumResult {
+    fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        f.write_str("TemplateNum: ")?;
+        return match self.confirmation_code {
+            TemplateNumStatus::Success => {
+                uDisplay::fmt(&self.template_num, f)?;
+                f.write_str(" templates stored")
+            }
+            ref status => uDisplay::fmt(status, f),
+        };
+    }
+}
+
+impl uDisplay for ReadIndexTableResult {
+    fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        f.write_str("ReadIndexTable: ")?;
+        return match self.confirmation_code {
+            ReadIndexTableStatus::Success => {
+                let used: u32 = self.index_table.iter().map(|byte| byte.count_ones()).sum();
+                uDisplay::fmt(&used, f)?;
+                f.write_str(" slots in use on this page")
+            }
+            ref status => uDisplay::fmt(status, f),
+        };
+    }
+}
+
+impl uDisplay for GetChipSNResult {
+    fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        f.write_str("GetChipSN: ")?;
+        return match self.confirmation_code {
+            GetChipSNStatus::Success => {
+                f.write_str("serial number ")?;
+                write_hex_bytes(f, &self.serial_number)
+            }
+            ref status => uDisplay::fmt(status, f),
+        };
+    }
+}
+
+impl uDisplay for GetRandomCodeResult {
+    fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        f.write_str("GetRandomCode: ")?;
+        return match self.confirmation_code {
+            GetRandomCodeStatus::Success => write_hex(f, self.random_code, 8),
+            ref status => uDisplay::fmt(status, f),
+        };
+    }
+}
+
+impl uDisplay for GetFwVerResult {
+    fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        f.write_str("GetFwVer: ")?;
+        return match self.confirmation_code {
+            GetFwVerStatus::Success => {
+                f.write_str("firmware ")?;
+                write_version(f, &self.version)
+            }
+            ref status => uDisplay::fmt(status, f),
+        };
+    }
+}
+
+impl uDisplay for GetAlgVerResult {
+    fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        f.write_str("GetAlgVer: ")?;
+        return match self.confirmation_code {
+            GetAlgVerStatus::Success => {
+                f.write_str("algorithm ")?;
+                write_version(f, &self.version)
+            }
+            ref status => uDisplay::fmt(status, f),
+        };
+    }
+}
+
+impl uDisplay for ReadNotepadResult {
+    fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        f.write_str("ReadNotepad: ")?;
+        return match self.confirmation_code {
+            ReadNotepadStatus::Success => write_hex_bytes(f, &self.data),
+            ref status => uDisplay::fmt(status, f),
+        };
+    }
+}
+
+impl<TXE, RXE> uDebug for Error<TXE, RXE>
+where
+    TXE: uDebug,
+    RXE: uDebug,
+{
+    fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        return match self {
+            Error::WriteError(error) => f.debug_tuple("WriteError")?.field(error)?.finish(),
+            Error::RecvReadError(error) => f.debug_tuple("RecvReadError")?.field(error)?.finish(),
+            Error::RecvPacketTooShort => f.write_str("RecvPacketTooShort"),
+            Error::RecvUnsolicitedReply => f.write_str("RecvUnsolicitedReply"),
+            Error::RecvWrongReplyType => f.write_str("RecvWrongReplyType"),
+            Error::RecvUnknownCode(code) => f.debug_tuple("RecvUnknownCode")?.field(code)?.finish(),
+            Error::RecvBadStartCode => f.write_str("RecvBadStartCode"),
+            Error::RecvBadChecksum => f.write_str("RecvBadChecksum"),
+            Error::ModuleAsleep => f.write_str("ModuleAsleep"),
+            Error::CommandInProgress => f.write_str("CommandInProgress"),
+            Error::NoCommandInProgress => f.write_str("NoCommandInProgress"),
+            Error::InvalidBuffer(buffer) => f.debug_tuple("InvalidBuffer")?.field(buffer)?.finish(),
+            Error::Timeout => f.write_str("Timeout"),
+            Error::CommandTooLong { length } => {
+                f.debug_struct("CommandTooLong")?.field("length", length)?.finish()
+            }
+            Error::RecvPacketTooLong { length } => {
+                f.debug_struct("RecvPacketTooLong")?.field("length", length)?.finish()
+            }
+        };
+    }
+}
+
+/// A short description of what went wrong, with the transport's error for `WriteError` and
+/// `RecvReadError`.
+impl<TXE, RXE> uDisplay for Error<TXE, RXE>
+where
+    TXE: uDebug,
+    RXE: uDebug,
+{
+    fn fmt<W>(&self, f: &mut Formatter<'_, W>) -> Result<(), W::Error>
+    where
+        W: uWrite + ?Sized,
+    {
+        return match self {
+            Error::WriteError(error) => {
+                f.write_str("could not write to the module: ")?;
+                uDebug::fmt(error, f)
+            }
+            Error::RecvReadError(error) => {
+                f.write_str("could not read from the module: ")?;
+                uDebug::fmt(error, f)
+            }
+            Error::RecvPacketTooShort => f.write_str("reply too short"),
+            Error::RecvUnsolicitedReply => f.write_str("reply to no command"),
+            Error::RecvWrongReplyType => f.write_str("unexpected packet type"),
+            Error::RecvUnknownCode(code) => {
+                f.write_str("unknown confirmation code ")?;
+                write_hex(f, *code as u32, 2)
+            }
+            Error::RecvBadStartCode => f.write_str("bad start code"),
+            Error::RecvBadChecksum => f.write_str("bad checksum"),
+            Error::ModuleAsleep => f.write_str("module asleep"),
+            Error::CommandInProgress => f.write_str("another command in progress"),
+            Error::NoCommandInProgress => f.write_str("no command in progress"),
+            Error::InvalidBuffer(buffer) => {
+                f.write_str("no character buffer ")?;
+                uDisplay::fmt(buffer, f)
+            }
+            Error::Timeout => f.write_str("no reply in time"),
+            Error::CommandTooLong { length } => {
+                f.write_str("command too long: ")?;
+                uDisplay::fmt(length, f)?;
+                f.write_str(" bytes")
+            }
+            Error::RecvPacketTooLong { length } => {
+                f.write_str("reply too long: ")?;
+                uDisplay::fmt(length, f)?;
+                f.write_str(" bytes")
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use arrayvec::ArrayString;
+    use std::format;
+    use std::string::ToString;
+    use std::vec;
+    use ufmt::uwrite;
+
+    /// Collects formatted text in a fixed buffer, as firmware without an allocator would.
+    struct Buffer {
+        text: ArrayString<256>,
+    }
+
+    impl Buffer {
+        fn new() -> Buffer {
+            return Buffer { text: ArrayString::new() };
+        }
+    }
+
+    impl uWrite for Buffer {
+        type Error = ();
+
+        fn write_str(&mut self, s: &str) -> Result<(), ()> {
+            return self.text.try_push_str(s).map_err(|_| ());
+        }
+    }
+
+    #[test]
+    fn test_udebug_command() {
+        // given: a command with fields, and one with a password
+        let search = Command::Search { buffer: 1, start_index: 0, end_index: 0xff };
+        let password = Command::VfyPwd { password: 0x1234 };
+
+        // when: writing them with uDebug
+        let mut written = Buffer::new();
+        uwrite!(written, "{:?}", search).unwrap();
+        let mut redacted = Buffer::new();
+        uwrite!(redacted, "{:?}", password).unwrap();
+
+        // then: the fields come out as Debug writes them, but the password does not
+        assert_eq!(written.text.as_str(), format!("{:?}", search));
+        assert_eq!(redacted.text.as_str(), "VfyPwd { password: <redacted> }");
+    }
+
+    #[test]
+    fn test_udebug_reply() {
+        // given: a reply with a status and an index table
+        let mut index_table = [0u8; 32];
+        index_table[0] = 0b101;
+        let reply = Reply::ReadIndexTable(ReadIndexTableResult {
+            address: 0xffffffff,
+            confirmation_code: ReadIndexTableStatus::Success,
+            index_table,
+            checksum: 0x0123,
+        });
+
+        // when: writing it with uDebug
+        let mut written = Buffer::new();
+        uwrite!(written, "{:?}", reply).unwrap();
+
+        // then: it reads just as the Debug output does
+        assert_eq!(written.text.as_str(), format!("{:?}", reply));
+    }
+
+    #[test]
+    fn test_udisplay_error() {
+        // given: a transport error and a protocol error
+        let read: Error<u8, u8> = Error::RecvReadError(3);
+        let checksum: Error<u8, u8> = Error::RecvBadChecksum;
+
+        // when: writing them with uDisplay and uDebug
+        let mut displayed = Buffer::new();
+        uwrite!(displayed, "{}", read).unwrap();
+        let mut debugged = Buffer::new();
+        uwrite!(debugged, "{:?}", checksum).unwrap();
+
+        // then: both read as expected
+        assert_eq!(displayed.text.as_str(), "could not read from the module: 3");
+        assert_eq!(debugged.text.as_str(), "RecvBadChecksum");
+    }
+
+    #[test]
+    fn test_udisplay_reply() {
+        // given: replies with and without more to say than their status
+        let mut version = [0u8; 32];
+        version[..4].copy_from_slice(b"1.2\x01");
+        let replies = vec![
+            Reply::Search(SearchResult {
+                address: 0xffffffff,
+                confirmation_code: SearchStatus::Success,
+                match_id: 12,
+                match_score: 96,
+                checksum: 0,
+            }),
+            Reply::ReadSysPara(ReadSysParaResult {
+                address: 0xffffffff,
+                confirmation_code: 0x00,
+                system_parameters: SystemParameters {
+                    status_register: 0,
+                    system_identifier_code: 9,
+                    finger_library_size: 200,
+                    security_level: 3,
+                    device_address: 0xffffffff,
+                    packet_size: 2,
+                    baud_setting: 6,
+                },
+                checksum: 0,
+            }),
+            Reply::GetRandomCode(GetRandomCodeResult {
+                address: 0xffffffff,
+                confirmation_code: GetRandomCodeStatus::Success,
+                random_code: 0x00c0ffee,
+                checksum: 0,
+            }),
+            Reply::GetFwVer(GetFwVerResult {
+                address: 0xffffffff,
+                confirmation_code: GetFwVerStatus::Success,
+                version,
+                checksum: 0,
+            }),
+            Reply::VfyPwd(VfyPwdResult {
+                address: 0xffffffff,
+                confirmation_code: PasswordVerificationState::Incorrect,
+                checksum: 0,
+            }),
+        ];
+
+        for reply in replies {
+            // when: writing each with uDisplay
+            let mut written = Buffer::new();
+            uwrite!(written, "{}", reply).unwrap();
+
+            // then: it reads just as the Display output does
+            assert_eq!(written.text.as_str(), reply.to_string());
+        }
+    }
+
+    #[test]
+    fn test_udisplay_error_lengths() {
+        // given: errors carrying a code and a length
+        let code: Error<u8, u8> = Error::RecvUnknownCode(0x2a);
+        let length: Error<u8, u8> = Error::CommandTooLong { length: 600 };
+
+        // when: writing them with uDisplay and uDebug
+        let mut displayed = Buffer::new();
+        uwrite!(displayed, "{}", code).unwrap();
+        let mut debugged = Buffer::new();
+        uwrite!(debugged, "{:?}", length).unwrap();
+
+        // then: the code is in hex, and the length reads as Debug writes it
+        assert_eq!(displayed.text.as_str(), "unknown confirmation code 0x2a");
+        assert_eq!(debugged.text.as_str(), format!("{:?}", length));
+    }
+
+    #[test]
+    fn test_buffer_full() {
+        // given: a buffer too small for the reply
+        let reply = Reply::GetFwVer(GetFwVerResult {
+            address: 0xffffffff,
+            confirmation_code: GetFwVerStatus::Success,
+            version: [0x30; 32],
+            checksum: 0,
+        });
+        let mut written = Buffer::new();
+        uwrite!(written, "{:?}", reply).unwrap();
+
+        // when: writing it again into what is left of the buffer
+        let result = uwrite!(written, "{:?}", reply);
+
+        // then: the writer's error comes back rather than a panic
+        assert_eq!(result, Err(()));
+    }
+}
@@ -1,6 +1,13 @@
+use core::convert::Infallible;
+
+use crate::codec::DecodeError;
+
 /// Allows a type to define how to deserialise itself from some bytes
-pub trait FromPayload {
-    fn from_payload(payload: &[u8]) -> Self;
+///
+/// Returns `DecodeError::TooShort` if `payload` is too short, and `DecodeError::UnknownCode` if
+/// it carries a confirmation code the type does not know; malformed input never panics.
+pub trait FromPayload: Sized {
+    fn from_payload(payload: &[u8]) -> Result<Self, DecodeError>;
 }
 
 /// Something that lets you write commands (typically, a `R502`).
@@ -16,13 +23,33 @@ pub trait ToPayload {
     fn to_payload(&self, writer: &mut dyn CommandWriter);
 }
 
+/// A command the driver can send: how it is written, and what its reply decodes into.
+///
+/// The built-in [`Command`](enum.Command.html) is one, decoding into
+/// [`Reply`](enum.Reply.html). Crates for modules with extra instructions can define their
+/// own and send them with [`R502::send`](struct.R502.html#method.send), without forking
+/// `Command`. `to_payload` writes everything from the packet ID up to, but not including,
+/// the checksum.
+pub trait ProtocolCommand: ToPayload {
+    /// What the reply decodes into.
+    type Reply;
+
+    /// Decodes the reply `packet`, from the start code to the checksum. The checksum and the
+    /// packet ID have already been checked, but a packet shorter than expected, or with a
+    /// confirmation code the command does not know, should be reported as a `DecodeError`
+    /// rather than panic.
+    fn decode_reply(&self, packet: &[u8]) -> Result<Self::Reply, DecodeError>;
+}
+
 /// Error type for low-level R502 operations. Wraps transport-level
 /// errors as well.
 ///
 /// `RXE` and `TXE` will be the `Error` type(s) of your serial port
 /// implementation, as defined by `embedded_hal::serial::Read<u8>::Error`
-/// and `embedded_hal::serial::Write<u8>::Error` respectively.
+/// and `embedded_hal::serial::Write<u8>::Error` respectively, or
+/// `Transport::ReadError` and `Transport::WriteError` of another transport.
 #[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error<TXE, RXE> {
     /// Error writing data to the R502. The wrapped error should have more
     /// information as to what is causing this.
@@ -42,4 +69,193 @@ pub enum Error<TXE, RXE> {
 
     /// A packet of unexpected type was received instead of the reply.
     RecvWrongReplyType,
+
+    /// The reply carried a confirmation code which the command is not known to return.
+    RecvUnknownCode(u8),
+
+    /// What was received does not start like a packet, so the driver has most likely lost
+    /// track of where packets begin.
+    RecvBadStartCode,
+
+    /// The checksum of the packet received does not match its contents, so it was damaged on
+    /// the way.
+    RecvBadChecksum,
+
+    /// The module was put to sleep with `R502::standby` and has not been woken with
+    /// `R502::wake` yet, so the command was not sent.
+    ModuleAsleep,
+
+    /// `R502::start_command` was called while another command was still in progress.
+    CommandInProgress,
+
+    /// `R502::poll` was called with no command in progress.
+    NoCommandInProgress,
+
+    /// The command names a _character buffer_ the module does not have (see `CHAR_BUFFERS`),
+    /// so it was not sent.
+    InvalidBuffer(u8),
+
+    /// No reply arrived in time. Only the methods which are given a timer return this, such
+    /// as `R502::send_command_with_timeout` and `R502Async::send_command_timeout`, and
+    /// `R502Async` over a port whose reads time out, such as a `TokioSerial`.
+    Timeout,
+
+    /// The command packet is `length` bytes long, more than the driver's command buffer holds
+    /// (see `R502::with_buffer_sizes`), so it was not sent.
+    CommandTooLong { length: usize },
+
+    /// The packet being received is `length` bytes long, more than the driver's receive
+    /// buffer holds (see `R502::with_buffer_sizes`). The rest of it is left unread.
+    RecvPacketTooLong { length: usize },
+}
+
+/// `Error` without the transport errors, for serial ports which cannot fail, such as those
+/// whose error types are `core::convert::Infallible`. Get one with
+/// [`Error::into_protocol`](enum.Error.html#method.into_protocol), or with `?`, and there are
+/// no arms for errors which never happen to write:
+///
+/// ```
+/// # use core::convert::Infallible;
+/// use hzgrow_r502::{Command, ProtocolError, Reply, R502};
+/// # struct Port;
+/// # impl embedded_hal::serial::Write<u8> for Port {
+/// #     type Error = Infallible;
+/// #     fn write(&mut self, _: u8) -> nb::Result<(), Infallible> { Ok(()) }
+/// #     fn flush(&mut self) -> nb::Result<(), Infallible> { Ok(()) }
+/// # }
+/// # impl embedded_hal::serial::Read<u8> for Port {
+/// #     type Error = Infallible;
+/// #     fn read(&mut self) -> nb::Result<u8, Infallible> { Err(nb::Error::WouldBlock) }
+/// # }
+///
+/// fn template_count(r502: &mut R502<(Port, Port)>) -> Result<u16, ProtocolError> {
+///     return match r502.send_command(Command::TemplateNum)? {
+///         Reply::TemplateNum(result) => Ok(result.template_num),
+///         _ => Err(ProtocolError::RecvWrongReplyType),
+///     };
+/// }
+///
+/// fn describe(error: ProtocolError) -> &'static str {
+///     return match error {
+///         ProtocolError::Timeout => "no answer",
+///         ProtocolError::RecvBadChecksum => "line noise",
+///         _ => "something else",
+///     };
+/// }
+/// ```
+///
+/// The variants are those of `Error`, less `WriteError` and `RecvReadError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ProtocolError {
+    /// See `Error::RecvPacketTooShort`.
+    RecvPacketTooShort,
+
+    /// See `Error::RecvUnsolicitedReply`.
+    RecvUnsolicitedReply,
+
+    /// See `Error::RecvWrongReplyType`.
+    RecvWrongReplyType,
+
+    /// See `Error::RecvUnknownCode`.
+    RecvUnknownCode(u8),
+
+    /// See `Error::RecvBadStartCode`.
+    RecvBadStartCode,
+
+    /// See `Error::RecvBadChecksum`.
+    RecvBadChecksum,
+
+    /// See `Error::ModuleAsleep`.
+    ModuleAsleep,
+
+    /// See `Error::CommandInProgress`.
+    CommandInProgress,
+
+    /// See `Error::NoCommandInProgress`.
+    NoCommandInProgress,
+
+    /// See `Error::InvalidBuffer`.
+    InvalidBuffer(u8),
+
+    /// See `Error::Timeout`.
+    Timeout,
+
+    /// See `Error::CommandTooLong`.
+    CommandTooLong { length: usize },
+
+    /// See `Error::RecvPacketTooLong`.
+    RecvPacketTooLong { length: usize },
+}
+
+impl<TXE, RXE> Error<TXE, RXE>
+where
+    TXE: Into<Infallible>,
+    RXE: Into<Infallible>,
+{
+    /// Drops the transport errors, which cannot happen with these error types.
+    // Matching on `Infallible` is what makes the transport error arms unreachable.
+    #[allow(unreachable_code)]
+    pub fn into_protocol(self) -> ProtocolError {
+        return match self {
+            Error::WriteError(error) => match Into::<Infallible>::into(error) {},
+            Error::RecvReadError(error) => match Into::<Infallible>::into(error) {},
+            Error::RecvPacketTooShort => ProtocolError::RecvPacketTooShort,
+            Error::RecvUnsolicitedReply => ProtocolError::RecvUnsolicitedReply,
+            Error::RecvWrongReplyType => ProtocolError::RecvWrongReplyType,
+            Error::RecvUnknownCode(code) => ProtocolError::RecvUnknownCode(code),
+            Error::RecvBadStartCode => ProtocolError::RecvBadStartCode,
+            Error::RecvBadChecksum => ProtocolError::RecvBadChecksum,
+            Error::ModuleAsleep => ProtocolError::ModuleAsleep,
+            Error::CommandInProgress => ProtocolError::CommandInProgress,
+            Error::NoCommandInProgress => ProtocolError::NoCommandInProgress,
+            Error::InvalidBuffer(buffer) => ProtocolError::InvalidBuffer(buffer),
+            Error::Timeout => ProtocolError::Timeout,
+            Error::CommandTooLong { length } => ProtocolError::CommandTooLong { length },
+            Error::RecvPacketTooLong { length } => ProtocolError::RecvPacketTooLong { length },
+        };
+    }
+}
+
+impl<TXE, RXE> From<Error<TXE, RXE>> for ProtocolError
+where
+    TXE: Into<Infallible>,
+    RXE: Into<Infallible>,
+{
+    fn from(error: Error<TXE, RXE>) -> Self {
+        return error.into_protocol();
+    }
+}
+
+impl<TXE, RXE> From<ProtocolError> for Error<TXE, RXE> {
+    /// Goes back to an `Error`, for code which is generic over the transport.
+    fn from(error: ProtocolError) -> Self {
+        return match error {
+            ProtocolError::RecvPacketTooShort => Error::RecvPacketTooShort,
+            ProtocolError::RecvUnsolicitedReply => Error::RecvUnsolicitedReply,
+            ProtocolError::RecvWrongReplyType => Error::RecvWrongReplyType,
+            ProtocolError::RecvUnknownCode(code) => Error::RecvUnknownCode(code),
+            ProtocolError::RecvBadStartCode => Error::RecvBadStartCode,
+            ProtocolError::RecvBadChecksum => Error::RecvBadChecksum,
+            ProtocolError::ModuleAsleep => Error::ModuleAsleep,
+            ProtocolError::CommandInProgress => Error::CommandInProgress,
+            ProtocolError::NoCommandInProgress => Error::NoCommandInProgress,
+            ProtocolError::InvalidBuffer(buffer) => Error::InvalidBuffer(buffer),
+            ProtocolError::Timeout => Error::Timeout,
+            ProtocolError::CommandTooLong { length } => Error::CommandTooLong { length },
+            ProtocolError::RecvPacketTooLong { length } => Error::RecvPacketTooLong { length },
+        };
+    }
+}
+
+/// Unwraps the result of `send_command` into the expected result struct, turning a reply of
+/// any other kind into `Error::RecvWrongReplyType`.
+macro_rules! expect_reply {
+    ($reply:expr, $variant:path) => {
+        match $reply {
+            Ok($variant(result)) => Ok(result),
+            Ok(_) => Err($crate::utils::Error::RecvWrongReplyType),
+            Err(error) => Err(error),
+        }
+    };
 }
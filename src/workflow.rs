@@ -0,0 +1,231 @@
+use core::fmt;
+
+use crate::commands::CommandKind;
+use crate::driver::R502;
+use crate::transport::Transport;
+
+/// A stage of the work the driver is doing on the module, as reported to a
+/// `WorkflowObserver`. Each starts with the command which carries it out, whichever helper
+/// sends it, so every helper reports its progress in the same terms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WorkflowStep {
+    /// Looking at the sensor with `GenImg`, to capture a finger or to see that it has been
+    /// lifted. `attempt` counts the looks in a row, from 1.
+    Capturing { attempt: u16 },
+
+    /// Turning the image into a _character file_ with `Img2Tz`.
+    ProcessingImage,
+
+    /// Sending an image from the host with `DownImage`.
+    DownloadingImage,
+
+    /// Loading a template from the library with `LoadChar`.
+    LoadingTemplate,
+
+    /// Comparing the two _character buffers_ with `Match`.
+    Matching,
+
+    /// Searching the library with `Search`.
+    SearchingLibrary,
+
+    /// Combining captures into a template with `RegModel`.
+    CombiningCaptures,
+
+    /// Storing a template in the library with `Store`.
+    StoringTemplate,
+
+    /// Sending a template to the host with `UpChar`.
+    UploadingTemplate,
+
+    /// Sending a template from the host with `DownChar`.
+    DownloadingTemplate,
+
+    /// Deleting templates with `DeletChar` or `Empty`.
+    DeletingTemplates,
+}
+
+impl WorkflowStep {
+    /// A short name for the step, the same for every attempt, for logs.
+    pub fn name(self) -> &'static str {
+        return match self {
+            Self::Capturing { .. } => "capturing",
+            Self::ProcessingImage => "processing image",
+            Self::DownloadingImage => "downloading image",
+            Self::LoadingTemplate => "loading template",
+            Self::Matching => "matching",
+            Self::SearchingLibrary => "searching library",
+            Self::CombiningCaptures => "combining captures",
+            Self::StoringTemplate => "storing template",
+            Self::UploadingTemplate => "uploading template",
+            Self::DownloadingTemplate => "downloading template",
+            Self::DeletingTemplates => "deleting templates",
+        };
+    }
+}
+
+/// The name of the step, and the attempt for `Capturing`, such as `capturing (attempt 2)`.
+impl fmt::Display for WorkflowStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Self::Capturing { attempt } => write!(f, "capturing (attempt {})", attempt),
+            step => f.write_str(step.name()),
+        };
+    }
+}
+
+/// Told of each `WorkflowStep` as it starts, see
+/// [`R502::set_workflow_observer`](struct.R502.html#method.set_workflow_observer).
+pub type WorkflowObserver = fn(WorkflowStep);
+
+/// Works out the steps from the commands sent, and tells the observer, if there is one.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct WorkflowTracker {
+    observer: Option<WorkflowObserver>,
+    attempts: u16,
+}
+
+impl WorkflowTracker {
+    pub(crate) const fn new() -> Self {
+        return Self { observer: None, attempts: 0 };
+    }
+
+    /// A command of `kind` is being sent.
+    pub(crate) fn command(&mut self, kind: CommandKind) {
+        let observer = match self.observer {
+            Some(observer) => observer,
+            None => return,
+        };
+        let step = match kind {
+            CommandKind::GenImg => {
+                self.attempts = self.attempts.saturating_add(1);
+                observer(WorkflowStep::Capturing { attempt: self.attempts });
+                return;
+            }
+            CommandKind::Img2Tz => WorkflowStep::ProcessingImage,
+            CommandKind::LoadChar => WorkflowStep::LoadingTemplate,
+            CommandKind::Match => WorkflowStep::Matching,
+            CommandKind::Search => WorkflowStep::SearchingLibrary,
+            #[cfg(feature = "cmd-enroll")]
+            CommandKind::RegModel => WorkflowStep::CombiningCaptures,
+            #[cfg(feature = "cmd-enroll")]
+            CommandKind::Store => WorkflowStep::StoringTemplate,
+            #[cfg(feature = "cmd-transfer")]
+            CommandKind::UpChar => WorkflowStep::UploadingTemplate,
+            #[cfg(feature = "cmd-transfer")]
+            CommandKind::DownChar => WorkflowStep::DownloadingTemplate,
+            #[cfg(feature = "cmd-transfer")]
+            CommandKind::DownImage => WorkflowStep::DownloadingImage,
+            CommandKind::DeletChar | CommandKind::Empty => WorkflowStep::DeletingTemplates,
+            _ => return,
+        };
+        self.attempts = 0;
+        observer(step);
+    }
+}
+
+impl<T> R502<T>
+where
+    T: Transport,
+{
+    /// Calls `observer` with each `WorkflowStep` as it starts, such as capturing, processing
+    /// the image or storing the template, for a UI or log to show progress in words rather
+    /// than frames. Steps are worked out from the commands sent, so the helpers and commands
+    /// sent by hand report them alike. Commands which only read or change settings, such as
+    /// `ReadSysPara` or `AuraLedConfig`, are not steps. `None`, the default, stops the
+    /// reports; without an observer, nothing is worked out.
+    ///
+    /// The observer is a plain function, which the driver can keep without borrowing or
+    /// allocating anything. To get the steps elsewhere, have it put them in a `static`, such
+    /// as a queue or an atomic.
+    pub fn set_workflow_observer(&mut self, observer: Option<WorkflowObserver>) {
+        self.workflow.observer = observer;
+        self.workflow.attempts = 0;
+    }
+}
+
+#[cfg(all(test, feature = "cmd-enroll"))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::emulator::{Emulator, EmulatorRx, EmulatorTx, NoDelay};
+    use crate::enroll::EnrollConfig;
+    use crate::led::LedFeedback;
+    use std::cell::RefCell;
+    use std::format;
+    use std::vec::Vec;
+
+    std::thread_local! {
+        static STEPS: RefCell<Vec<WorkflowStep>> = const { RefCell::new(Vec::new()) };
+    }
+
+    fn record(step: WorkflowStep) {
+        STEPS.with(|steps| steps.borrow_mut().push(step));
+    }
+
+    fn steps() -> Vec<WorkflowStep> {
+        return STEPS.with(|steps| steps.borrow_mut().split_off(0));
+    }
+
+    fn r502(emulator: &Emulator) -> R502<(EmulatorTx, EmulatorRx)> {
+        let (tx, rx) = emulator.serial();
+        let mut r502 = R502::new(tx, rx, 0xffffffff);
+        r502.set_workflow_observer(Some(record));
+        return r502;
+    }
+
+    #[test]
+    fn test_enrollment_steps() {
+        // given: a user who is slow to place their finger the first time
+        let emulator = Emulator::new();
+        emulator.touch(&[None, Some(7), None, Some(7)]);
+        let mut r502 = r502(&emulator);
+
+        // when: enrolling, checking for duplicates and flashing the LED
+        let config = EnrollConfig {
+            reject_duplicates: true,
+            led: Some(LedFeedback::default()),
+            ..EnrollConfig::default()
+        };
+        r502.enroll(3, &config, &mut NoDelay, |_| {}).unwrap();
+
+        // then: every step is reported in order, with the LED commands left out
+        assert_eq!(
+            steps(),
+            [
+                WorkflowStep::Capturing { attempt: 1 },
+                WorkflowStep::Capturing { attempt: 2 },
+                WorkflowStep::ProcessingImage,
+                WorkflowStep::Capturing { attempt: 1 },
+                WorkflowStep::Capturing { attempt: 2 },
+                WorkflowStep::ProcessingImage,
+                WorkflowStep::CombiningCaptures,
+                WorkflowStep::SearchingLibrary,
+                WorkflowStep::StoringTemplate,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_workflow_observer() {
+        // given: a driver whose observer has been taken away
+        let emulator = Emulator::new();
+        emulator.script_captures(7, 2);
+        let mut r502 = r502(&emulator);
+        r502.set_workflow_observer(None);
+
+        // when: enrolling
+        r502.enroll(3, &EnrollConfig::default(), &mut NoDelay, |_| {}).unwrap();
+
+        // then: nothing is reported
+        assert_eq!(steps(), []);
+    }
+
+    #[test]
+    fn test_workflow_step_display() {
+        assert_eq!(format!("{}", WorkflowStep::Capturing { attempt: 2 }), "capturing (attempt 2)");
+        assert_eq!(format!("{}", WorkflowStep::StoringTemplate), "storing template");
+        assert_eq!(WorkflowStep::Capturing { attempt: 9 }.name(), "capturing");
+    }
+}